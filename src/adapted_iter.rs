@@ -1,5 +1,8 @@
 use crate::adapters::AdaptInfo;
 
+/// The files in an archive (or other container). They must be returned in an order that only depends on the input,
+/// usually the order they are stored in, so repeated searches give the same output.
+/// Formats extracted to a directory first (e.g. with 7z) return their files sorted by path.
 // TODO: using iterator trait possible?? should basically be Iterator<AdaptInfo>
 pub trait AdaptedFilesIter {
     // next takes a 'a-lived reference and returns an AdaptInfo that lives as long as the reference
@@ -22,3 +25,20 @@ impl AdaptedFilesIter for SingleAdaptedFileAsIter<'_> {
 }
 
 pub type AdaptedFilesIterBox<'a> = Box<dyn AdaptedFilesIter + 'a>;
+
+/// A fixed list of AdaptInfos, returned in order
+pub struct MultiAdaptedFilesIter<'a> {
+    ais: std::vec::IntoIter<AdaptInfo<'a>>,
+}
+impl MultiAdaptedFilesIter<'_> {
+    pub fn new<'a>(ais: Vec<AdaptInfo<'a>>) -> MultiAdaptedFilesIter<'a> {
+        MultiAdaptedFilesIter {
+            ais: ais.into_iter(),
+        }
+    }
+}
+impl AdaptedFilesIter for MultiAdaptedFilesIter<'_> {
+    fn next<'a>(&'a mut self) -> Option<AdaptInfo<'a>> {
+        self.ais.next()
+    }
+}
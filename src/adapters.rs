@@ -1,18 +1,66 @@
+pub mod android;
+pub mod browser;
+pub mod cab;
+pub mod cpio;
+pub mod csv;
 pub mod custom;
-// pub mod decompress;
+pub mod dbf;
+pub mod decompress;
+pub mod dicom;
+pub mod dmg;
+pub mod doc;
+pub mod docker;
+pub mod enex;
+pub mod evtx;
+pub mod executable;
+pub mod fallback;
 // pub mod ffmpeg;
+pub mod fits;
+pub mod geo;
+pub mod git;
+pub mod gron;
+pub mod iwork;
+pub mod javaclass;
+pub mod journal;
+pub mod lzw;
+pub mod mdb;
+pub mod msi;
+pub mod netcdf;
+pub mod onenote;
+pub mod pandoc_server;
+pub mod pcap;
+pub mod pdfforms;
+pub mod persistent;
+pub mod plist;
+pub mod plugin;
 pub mod postproc;
+pub mod psd;
 // pub mod pdfpages;
+pub mod registry;
+pub mod sandbox;
+pub mod sas;
+pub mod sevenzip;
 pub mod spawning;
-// pub mod sqlite;
+pub mod split;
+pub mod spreadsheet;
+pub mod spss;
+pub mod sqlite;
+pub mod squashfs;
+pub mod stata;
+pub mod strings;
+pub mod svg;
 // pub mod tar;
 // pub mod tesseract;
-// pub mod writing;
+pub mod torrent;
+pub mod vcard;
+pub mod warc;
+pub mod wasm;
+pub mod writing;
+pub mod zim;
 pub mod zip;
 use crate::{adapted_iter::AdaptedFilesIterBox, config::RgaConfig, matching::*};
 use anyhow::*;
-// use custom::builtin_spawning_adapters;
-//use custom::CustomAdapterConfig;
+use custom::builtin_spawning_adapters;
 use custom::CustomAdapterConfig;
 use log::*;
 
@@ -20,7 +68,7 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io::prelude::*;
 use std::iter::Iterator;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 pub type ReadBox<'a> = Box<dyn Read + 'a>;
@@ -85,6 +133,25 @@ pub trait FileAdapter: GetMetadata {
         a: AdaptInfo<'a>,
         detection_reason: &FileMatcher,
     ) -> Result<AdaptedFilesIterBox<'a>>;
+
+    /// additional data the output of a (real) file depends on, apart from the file itself and the active adapters.
+    /// Is included in the cache key
+    fn cache_key_extra(&self, _filepath: &Path, _config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// digest of the definition of the adapter, if its behaviour is not fixed by its name and version
+    /// (e.g. the command line of custom adapters). Is included in the cache key whenever the adapter is active
+    fn definition_digest(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// if the output is just the content of the input (e.g. decompressed), the file name of that content.
+    /// When no other adapter handles that file, the output is streamed without caching it,
+    /// since reading it from the cache would not be faster than producing it again
+    fn passthrough_name(&self, _filepath: &Path) -> Option<PathBuf> {
+        None
+    }
 }
 
 pub struct AdaptInfo<'a> {
@@ -110,24 +177,65 @@ pub fn get_all_adapters(custom_adapters: Option<Vec<CustomAdapterConfig>>) -> Ad
     let mut adapters: Vec<Rc<dyn FileAdapter>> = vec![];
     if let Some(custom_adapters) = custom_adapters {
         for adapter_config in custom_adapters {
-            adapters.push(Rc::new(adapter_config.to_adapter()));
+            adapters.push(adapter_config.to_file_adapter());
         }
     }
 
     let internal_adapters: Vec<Rc<dyn FileAdapter>> = vec![
         //Rc::new(ffmpeg::FFmpegAdapter::new()),
         Rc::new(zip::ZipAdapter::new()),
-        //Rc::new(decompress::DecompressAdapter::new()),
+        Rc::new(pdfforms::PdfFormsAdapter::new()),
+        Rc::new(decompress::DecompressAdapter::new()),
+        Rc::new(split::SplitAdapter::new()),
+        Rc::new(cpio::CpioAdapter::new()),
         // Rc::new(tar::TarAdapter::new()),
-        //Rc::new(sqlite::SqliteAdapter::new()),
+        Rc::new(browser::BrowserAdapter::new()),
+        Rc::new(sqlite::SqliteAdapter::new()),
+        Rc::new(mdb::MdbAdapter::new()),
+        Rc::new(dbf::DbfAdapter::new()),
+        Rc::new(spss::SpssAdapter::new()),
+        Rc::new(stata::StataAdapter::new()),
+        Rc::new(sas::SasAdapter::new()),
+        Rc::new(netcdf::NetcdfAdapter::new()),
+        Rc::new(fits::FitsAdapter::new()),
+        Rc::new(dicom::DicomAdapter::new()),
+        Rc::new(pcap::PcapAdapter::new()),
+        Rc::new(executable::ExecutableAdapter::new()),
+        Rc::new(javaclass::JavaClassAdapter::new()),
+        Rc::new(wasm::WasmAdapter::new()),
+        Rc::new(git::GitAdapter::new()),
+        Rc::new(registry::RegistryAdapter::new()),
+        Rc::new(evtx::EvtxAdapter::new()),
+        Rc::new(journal::JournalAdapter::new()),
+        Rc::new(plist::PlistAdapter::new()),
+        Rc::new(dmg::DmgAdapter::new()),
+        Rc::new(msi::MsiAdapter::new()),
+        Rc::new(cab::CabAdapter::new()),
+        Rc::new(squashfs::SquashfsAdapter::new()),
+        Rc::new(docker::DockerAdapter::new()),
+        Rc::new(warc::WarcAdapter::new()),
+        Rc::new(enex::EnexAdapter::new()),
+        Rc::new(onenote::OneNoteAdapter::new()),
+        Rc::new(geo::GeoAdapter::new()),
+        Rc::new(vcard::VcardAdapter::new()),
+        Rc::new(spreadsheet::SpreadsheetAdapter::new()),
+        Rc::new(doc::DocAdapter::new()),
+        Rc::new(android::AndroidAdapter::new()),
+        Rc::new(svg::SvgAdapter::new()),
+        Rc::new(iwork::IworkAdapter::new()),
+        Rc::new(psd::PsdAdapter::new()),
+        Rc::new(torrent::TorrentAdapter::new()),
+        Rc::new(zim::ZimAdapter::new()),
+        Rc::new(gron::GronAdapter::new()),
+        Rc::new(csv::CsvAdapter::new()),
         // Rc::new(pdfpages::PdfPagesAdapter::new()),
         // Rc::new(tesseract::TesseractAdapter::new()),
     ];
-    /*adapters.extend(
+    adapters.extend(
         builtin_spawning_adapters
             .iter()
             .map(|e| -> Rc<dyn FileAdapter> { Rc::new(e.clone().to_adapter()) }),
-    );*/
+    );
     adapters.extend(internal_adapters);
 
     adapters
@@ -135,6 +243,54 @@ pub fn get_all_adapters(custom_adapters: Option<Vec<CustomAdapterConfig>>) -> Ad
         .partition(|e| !e.metadata().disabled_by_default)
 }
 
+/// Optional parts of adapters that are not adapters of their own, but are enabled like the adapters that are disabled
+/// by default, e.g. --rga-adapters=+githistory. (name, description)
+pub const OPTIONAL_MODES: &[(&str, &str)] = &[(
+    "githistory",
+    "Also search every version of the files in git repositories, like --rga-git-history",
+)];
+
+/// How an entry of --rga-adapters changes the default adapters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdapterChange {
+    /// +name
+    Add,
+    /// -name
+    Remove,
+    /// part of a list of exactly the adapters to use
+    Only,
+}
+
+/// The entries of --rga-adapters. An entry without + or - after one with takes its prefix,
+/// so "-a,b" removes a and b, and "+a,-b" adds a and removes b
+pub fn parse_adapter_names<T: AsRef<str>>(adapter_names: &[T]) -> Vec<(&str, AdapterChange)> {
+    let mut change = AdapterChange::Only;
+    adapter_names
+        .iter()
+        .map(|name| {
+            let name = name.as_ref();
+            if let Some(name) = name.strip_prefix('+') {
+                change = AdapterChange::Add;
+                (name, change)
+            } else if let Some(name) = name.strip_prefix('-') {
+                change = AdapterChange::Remove;
+                (name, change)
+            } else {
+                (name, change)
+            }
+        })
+        .collect()
+}
+
+/// whether the adapter or optional mode is enabled with --rga-adapters (e.g. "strings" with --rga-adapters=+strings)
+pub fn adapter_listed<T: AsRef<str>>(adapter_names: &[T], name: &str) -> bool {
+    parse_adapter_names(adapter_names)
+        .iter()
+        .rev()
+        .find(|(n, _)| *n == name)
+        .map_or(false, |(_, change)| *change != AdapterChange::Remove)
+}
+
 /**
  * filter adapters by given names:
  *
@@ -142,54 +298,50 @@ pub fn get_all_adapters(custom_adapters: Option<Vec<CustomAdapterConfig>>) -> Ad
  *  - "a,b" means use adapters a,b
  *  - "-a,b" means use default list except for a and b
  *  - "+a,b" means use default list but also a and b (a,b will be prepended to the list so given higher priority)
+ *  - "+a,-b" means use default list with a but without b
+ *
+ * The names of OPTIONAL_MODES are allowed, but are not adapters
  */
 pub fn get_adapters_filtered<T: AsRef<str>>(
     custom_adapters: Option<Vec<CustomAdapterConfig>>,
     adapter_names: &Vec<T>,
 ) -> Result<Vec<Rc<dyn FileAdapter>>> {
     let (def_enabled_adapters, def_disabled_adapters) = get_all_adapters(custom_adapters);
-    let adapters = if !adapter_names.is_empty() {
-        let adapters_map: HashMap<_, _> = def_enabled_adapters
-            .iter()
-            .chain(def_disabled_adapters.iter())
-            .map(|e| (e.metadata().name.clone(), e.clone()))
-            .collect();
-        let mut adapters = vec![];
-        let mut subtractive = false;
-        let mut additive = false;
-        for (i, name) in adapter_names.iter().enumerate() {
-            let mut name = name.as_ref();
-            if i == 0 && (name.starts_with('-')) {
-                subtractive = true;
-                name = &name[1..];
-                adapters = def_enabled_adapters.clone();
-            } else if i == 0 && (name.starts_with('+')) {
-                name = &name[1..];
-                adapters = def_enabled_adapters.clone();
-                additive = true;
-            }
-            if subtractive {
-                let inx = adapters
-                    .iter()
-                    .position(|a| a.metadata().name == name)
-                    .ok_or_else(|| format_err!("Could not remove {}: Not in list", name))?;
+    let adapters_map: HashMap<_, _> = def_enabled_adapters
+        .iter()
+        .chain(def_disabled_adapters.iter())
+        .map(|e| (e.metadata().name.clone(), e.clone()))
+        .collect();
+    let entries = parse_adapter_names(adapter_names);
+    let mut adapters = match entries.first() {
+        Some((_, AdapterChange::Only)) => vec![],
+        _ => def_enabled_adapters,
+    };
+    for (name, change) in entries {
+        if OPTIONAL_MODES.iter().any(|(mode, _)| *mode == name) {
+            continue;
+        }
+        let position = adapters.iter().position(|a| a.metadata().name == name);
+        if change == AdapterChange::Remove {
+            let inx =
+                position.ok_or_else(|| format_err!("Could not remove {}: Not in list", name))?;
+            adapters.remove(inx);
+            continue;
+        }
+        let adapter = adapters_map
+            .get(name)
+            .ok_or_else(|| format_err!("Unknown adapter: \"{}\"", name))?
+            .clone();
+        if change == AdapterChange::Add {
+            // an adapter that is enabled anyway gets a higher priority
+            if let Some(inx) = position {
                 adapters.remove(inx);
-            } else {
-                let adapter = adapters_map
-                    .get(name)
-                    .ok_or_else(|| format_err!("Unknown adapter: \"{}\"", name))?
-                    .clone();
-                if additive {
-                    adapters.insert(0, adapter);
-                } else {
-                    adapters.push(adapter);
-                }
             }
+            adapters.insert(0, adapter);
+        } else {
+            adapters.push(adapter);
         }
-        adapters
-    } else {
-        def_enabled_adapters
-    };
+    }
     debug!(
         "Chosen available adapters: {}",
         adapters
@@ -200,3 +352,71 @@ pub fn get_adapters_filtered<T: AsRef<str>>(
     );
     Ok(adapters)
 }
+
+/// the adapters enabled with --rga-adapters, ordered by --rga-adapter-priority
+pub fn enabled_adapters(config: &RgaConfig) -> Result<Vec<Rc<dyn FileAdapter>>> {
+    let mut adapters = get_adapters_filtered(config.custom_adapters.clone(), &config.adapters)?;
+    // with --rga-strings-fallback, the fallback is one of the active adapters (which the cache keys of archives include)
+    if config.strings.fallback && !adapters.iter().any(|a| a.metadata().name == "strings") {
+        adapters.push(Rc::new(strings::StringsAdapter::new()));
+    }
+    if !config.adapter_priority.is_empty() {
+        let (enabled, disabled) = get_all_adapters(config.custom_adapters.clone());
+        for name in &config.adapter_priority {
+            if !enabled
+                .iter()
+                .chain(disabled.iter())
+                .any(|a| &a.metadata().name == name)
+            {
+                return Err(format_err!(
+                    "Unknown adapter in --rga-adapter-priority: \"{}\"",
+                    name
+                ));
+            }
+        }
+        prioritize_adapters(&mut adapters, &config.adapter_priority);
+    }
+    Ok(adapters)
+}
+
+/// move the given adapters to the front, in the given order. The order of the others is kept
+pub fn prioritize_adapters(adapters: &mut Vec<Rc<dyn FileAdapter>>, priority: &[String]) {
+    adapters.sort_by_key(|a| {
+        priority
+            .iter()
+            .position(|name| name == &a.metadata().name)
+            .unwrap_or(priority.len())
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn adapter_names() -> Result<()> {
+        let names = |list: &[&str]| -> Result<Vec<String>> {
+            Ok(get_adapters_filtered(None, &list.to_vec())?
+                .iter()
+                .map(|a| a.metadata().name.clone())
+                .collect())
+        };
+        let defaults = names(&[])?;
+        assert!(!defaults.contains(&"strings".to_string()));
+
+        let added = names(&["+gron", "+strings", "-zip", "githistory"])?;
+        assert_eq!(added[..2], ["strings", "gron"]);
+        assert!(!added.contains(&"zip".to_string()));
+        assert_eq!(added.len(), defaults.len() + 1);
+        // -a,b removes both
+        assert_eq!(names(&["-zip", "sqlite"])?.len(), defaults.len() - 2);
+        assert_eq!(names(&["zip", "sqlite"])?, ["zip", "sqlite"]);
+        assert!(names(&["+ocr"]).is_err());
+
+        assert!(adapter_listed(&["+gron", "+strings"], "strings"));
+        assert!(adapter_listed(&["zip", "githistory"], "githistory"));
+        assert!(!adapter_listed(&["-zip", "strings"], "strings"));
+        assert!(!adapter_listed(&["+gron"], "strings"));
+        Ok(())
+    }
+}
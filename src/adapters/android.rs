@@ -0,0 +1,569 @@
+//! Decoding of the binary formats inside of Android app packages (.apk, which are read by the zip adapter):
+//! compiled binary XML (AndroidManifest.xml, res/**/*.xml), the resource table (resources.arsc) and the strings of Dalvik bytecode (classes.dex).
+//! See frameworks/base/libs/androidfw/include/androidfw/ResourceTypes.h in AOSP for the resource formats.
+
+use super::*;
+use anyhow::*;
+use doc::{u16_at, u32_at};
+use lazy_static::lazy_static;
+use spawning::read_input;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["dex", "arsc"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "android".to_owned(),
+        version: 1,
+        description: "Decodes binary AndroidManifest.xml and resources.arsc files in Android packages to text, and outputs the strings of classes.dex bytecode. Text XML files are passed through".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .chain(
+                ["**/AndroidManifest.xml", "**/res/**/*.xml"]
+                    .iter()
+                    .map(|s| FastFileMatcher::PathGlob(s.to_string()))
+            )
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct AndroidAdapter;
+
+impl AndroidAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(AndroidAdapter))
+    }
+}
+impl GetMetadata for AndroidAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_TABLE_TYPE: u16 = 0x0002;
+const RES_XML_TYPE: u16 = 0x0003;
+const RES_XML_START_NAMESPACE_TYPE: u16 = 0x0100;
+const RES_XML_END_NAMESPACE_TYPE: u16 = 0x0101;
+const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+const RES_XML_END_ELEMENT_TYPE: u16 = 0x0103;
+const RES_XML_CDATA_TYPE: u16 = 0x0104;
+const RES_XML_RESOURCE_MAP_TYPE: u16 = 0x0180;
+const RES_TABLE_PACKAGE_TYPE: u16 = 0x0200;
+const RES_TABLE_TYPE_TYPE: u16 = 0x0201;
+
+const NO_INDEX: u32 = 0xFFFF_FFFF;
+
+fn u8_at(data: &[u8], offset: usize) -> Result<u8> {
+    data.get(offset)
+        .copied()
+        .ok_or_else(|| format_err!("unexpected end of file at {}", offset))
+}
+
+/// the strings of a ResStringPool chunk starting at `start`
+fn string_pool(data: &[u8], start: usize) -> Result<Vec<String>> {
+    let header_size = u16_at(data, start + 2)? as usize;
+    let count = u32_at(data, start + 8)? as usize;
+    let utf8 = u32_at(data, start + 16)? & (1 << 8) != 0;
+    let strings_start = start + u32_at(data, start + 20)? as usize;
+    let mut strings = Vec::with_capacity(count.min(data.len() / 4));
+    for i in 0..count {
+        let mut pos = strings_start + u32_at(data, start + header_size + i * 4)? as usize;
+        let string = if utf8 {
+            // the length in utf-16 units, then the length in bytes, each one or two bytes
+            pos += if u8_at(data, pos)? & 0x80 != 0 { 2 } else { 1 };
+            let mut len = u8_at(data, pos)? as usize;
+            if len & 0x80 != 0 {
+                len = ((len & 0x7F) << 8) | u8_at(data, pos + 1)? as usize;
+                pos += 1;
+            }
+            pos += 1;
+            let bytes = data
+                .get(pos..pos + len)
+                .ok_or_else(|| format_err!("string out of bounds"))?;
+            String::from_utf8_lossy(bytes).into_owned()
+        } else {
+            let mut len = u16_at(data, pos)? as usize;
+            if len & 0x8000 != 0 {
+                len = ((len & 0x7FFF) << 16) | u16_at(data, pos + 2)? as usize;
+                pos += 2;
+            }
+            pos += 2;
+            let bytes = data
+                .get(pos..pos + len * 2)
+                .ok_or_else(|| format_err!("string out of bounds"))?;
+            let units = bytes.chunks(2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+            std::char::decode_utf16(units)
+                .map(|c| c.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+                .collect()
+        };
+        strings.push(string);
+    }
+    Ok(strings)
+}
+
+fn string(strings: &[String], index: u32) -> &str {
+    strings
+        .get(index as usize)
+        .map(|s| s.as_str())
+        .unwrap_or("")
+}
+
+/// the float value of a complex (dimension or fraction) value
+fn complex_value(data: u32) -> f32 {
+    let radix_mult = [
+        1.0,
+        1.0 / (1 << 7) as f32,
+        1.0 / (1 << 15) as f32,
+        1.0 / (1 << 23) as f32,
+    ];
+    (data & 0xFFFF_FF00) as i32 as f32 / (1 << 8) as f32 * radix_mult[(data >> 4) as usize & 3]
+}
+
+/// a typed Res_value as text
+fn format_value(strings: &[String], data_type: u8, data: u32) -> String {
+    match data_type {
+        0x00 => String::new(),
+        0x01 => format!("@0x{:08x}", data),
+        0x02 => format!("?0x{:08x}", data),
+        0x03 => string(strings, data).to_string(),
+        0x04 => f32::from_bits(data).to_string(),
+        0x05 => {
+            let units = ["px", "dp", "sp", "pt", "in", "mm"];
+            format!(
+                "{}{}",
+                complex_value(data),
+                units.get(data as usize & 0xF).unwrap_or(&"")
+            )
+        }
+        0x06 => format!(
+            "{}{}",
+            complex_value(data) * 100.0,
+            if data & 0xF == 1 { "%p" } else { "%" }
+        ),
+        0x10 => (data as i32).to_string(),
+        0x11 => format!("0x{:x}", data),
+        0x12 => (data != 0).to_string(),
+        0x1C..=0x1F => format!("#{:08x}", data),
+        _ => format!("0x{:08x}", data),
+    }
+}
+
+/// an element or attribute name with the prefix of its namespace
+fn qualified(strings: &[String], namespaces: &[(String, String)], ns: u32, name: &str) -> String {
+    let uri = if ns == NO_INDEX {
+        ""
+    } else {
+        string(strings, ns)
+    };
+    match namespaces.iter().rev().find(|(u, _)| u == uri) {
+        Some((_, prefix)) => format!("{}:{}", prefix, name),
+        None => name.to_string(),
+    }
+}
+
+/// compiled binary xml back to (indented) xml text
+pub fn decode_xml(data: &[u8]) -> Result<String> {
+    let end = (u32_at(data, 4)? as usize).min(data.len());
+    let mut pos = u16_at(data, 2)? as usize;
+    let mut strings = vec![];
+    let mut resource_ids = vec![];
+    // (uri, prefix) of the namespaces in scope
+    let mut namespaces: Vec<(String, String)> = vec![];
+    let mut new_namespaces = vec![];
+    let mut depth = 0;
+    let mut out = String::new();
+    while pos + 8 <= end {
+        let chunk_type = u16_at(data, pos)?;
+        let header_size = u16_at(data, pos + 2)? as usize;
+        let size = u32_at(data, pos + 4)? as usize;
+        if size < 8 {
+            return Err(format_err!("invalid chunk size {} at {}", size, pos));
+        }
+        match chunk_type {
+            RES_STRING_POOL_TYPE => strings = string_pool(data, pos)?,
+            RES_XML_RESOURCE_MAP_TYPE => {
+                resource_ids = (0..(size.saturating_sub(header_size)) / 4)
+                    .map(|i| u32_at(data, pos + header_size + i * 4))
+                    .collect::<Result<Vec<_>>>()?;
+            }
+            RES_XML_START_NAMESPACE_TYPE => {
+                let prefix = string(&strings, u32_at(data, pos + 16)?).to_string();
+                let uri = string(&strings, u32_at(data, pos + 20)?).to_string();
+                new_namespaces.push((prefix.clone(), uri.clone()));
+                namespaces.push((uri, prefix));
+            }
+            RES_XML_END_NAMESPACE_TYPE => {
+                namespaces.pop();
+            }
+            RES_XML_START_ELEMENT_TYPE => {
+                let ns = u32_at(data, pos + 16)?;
+                let name = string(&strings, u32_at(data, pos + 20)?);
+                let attribute_start = u16_at(data, pos + 24)? as usize;
+                let attribute_size = u16_at(data, pos + 26)? as usize;
+                let attribute_count = u16_at(data, pos + 28)? as usize;
+                out.push_str(&"  ".repeat(depth));
+                out.push_str(&format!("<{}", qualified(&strings, &namespaces, ns, name)));
+                for (prefix, uri) in new_namespaces.drain(..) {
+                    out.push_str(&format!(" xmlns:{}=\"{}\"", prefix, uri));
+                }
+                for i in 0..attribute_count {
+                    let a = pos + header_size + attribute_start + i * attribute_size;
+                    let name_index = u32_at(data, a + 4)?;
+                    let raw_value = u32_at(data, a + 8)?;
+                    // obfuscated packages strip the attribute names, only the resource id remains
+                    let name = match string(&strings, name_index) {
+                        "" => resource_ids
+                            .get(name_index as usize)
+                            .map(|id| format!("0x{:08x}", id))
+                            .unwrap_or_default(),
+                        name => name.to_string(),
+                    };
+                    let value = if raw_value != NO_INDEX {
+                        string(&strings, raw_value).to_string()
+                    } else {
+                        format_value(&strings, u8_at(data, a + 15)?, u32_at(data, a + 16)?)
+                    };
+                    out.push_str(&format!(
+                        " {}=\"{}\"",
+                        qualified(&strings, &namespaces, u32_at(data, a)?, &name),
+                        value
+                    ));
+                }
+                out.push_str(">\n");
+                depth += 1;
+            }
+            RES_XML_END_ELEMENT_TYPE => {
+                depth = depth.saturating_sub(1);
+                let ns = u32_at(data, pos + 16)?;
+                let name = string(&strings, u32_at(data, pos + 20)?);
+                out.push_str(&"  ".repeat(depth));
+                out.push_str(&format!(
+                    "</{}>\n",
+                    qualified(&strings, &namespaces, ns, name)
+                ));
+            }
+            RES_XML_CDATA_TYPE => {
+                let text = string(&strings, u32_at(data, pos + 16)?).trim();
+                if !text.is_empty() {
+                    out.push_str(&"  ".repeat(depth));
+                    out.push_str(text);
+                    out.push('\n');
+                }
+            }
+            _ => {}
+        }
+        pos += size;
+    }
+    Ok(out)
+}
+
+/// the entries of a ResTable_type chunk as `type/name (locale): value` lines
+fn table_type(
+    data: &[u8],
+    start: usize,
+    strings: &[String],
+    type_strings: &[String],
+    key_strings: &[String],
+    out: &mut String,
+) -> Result<()> {
+    let header_size = u16_at(data, start + 2)? as usize;
+    let id = u8_at(data, start + 8)?;
+    let sparse = u8_at(data, start + 9)? & 0x01 != 0;
+    let entry_count = u32_at(data, start + 12)? as usize;
+    let entries_start = start + u32_at(data, start + 16)? as usize;
+    let type_name = type_strings
+        .get((id as usize).wrapping_sub(1))
+        .map(|s| s.as_str())
+        .unwrap_or("unknown");
+    // the locale of the ResTable_config, if any (packed three letter codes are ignored)
+    let config = start + 20;
+    let language = data.get(config + 8..config + 10).unwrap_or(&[0, 0]);
+    let country = data.get(config + 10..config + 12).unwrap_or(&[0, 0]);
+    let locale = if language[0] != 0 && language[0] & 0x80 == 0 {
+        let mut locale = format!(" ({}", String::from_utf8_lossy(language));
+        if country[0] != 0 && country[0] & 0x80 == 0 {
+            locale.push_str(&format!("-r{}", String::from_utf8_lossy(country)));
+        }
+        locale.push(')');
+        locale
+    } else {
+        String::new()
+    };
+    for i in 0..entry_count {
+        let offset = if sparse {
+            u16_at(data, start + header_size + i * 4 + 2)? as u32 * 4
+        } else {
+            u32_at(data, start + header_size + i * 4)?
+        };
+        if offset == NO_INDEX {
+            continue;
+        }
+        let entry = entries_start + offset as usize;
+        let entry_size = u16_at(data, entry)? as usize;
+        let flags = u16_at(data, entry + 2)?;
+        let (key, value) = if flags & 0x0008 != 0 {
+            // compact entry: key index in place of the size, type in the high byte of the flags
+            (
+                entry_size as u32,
+                format_value(strings, (flags >> 8) as u8, u32_at(data, entry + 4)?),
+            )
+        } else if flags & 0x0001 != 0 {
+            // complex entry (styles, arrays, plurals): a map of values
+            let count = u32_at(data, entry + 12)? as usize;
+            let values = (0..count)
+                .map(|j| {
+                    let map = entry + entry_size + j * 12;
+                    Ok(format_value(
+                        strings,
+                        u8_at(data, map + 7)?,
+                        u32_at(data, map + 8)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            (u32_at(data, entry + 4)?, values.join(", "))
+        } else {
+            let value = entry + entry_size;
+            (
+                u32_at(data, entry + 4)?,
+                format_value(strings, u8_at(data, value + 3)?, u32_at(data, value + 4)?),
+            )
+        };
+        out.push_str(&format!(
+            "{}/{}{}: {}\n",
+            type_name,
+            string(key_strings, key),
+            locale,
+            value.replace('\n', " ")
+        ));
+    }
+    Ok(())
+}
+
+/// the resource table as `type/name: value` lines per package
+pub fn decode_resource_table(data: &[u8]) -> Result<String> {
+    let end = (u32_at(data, 4)? as usize).min(data.len());
+    let mut pos = u16_at(data, 2)? as usize;
+    let mut strings = vec![];
+    let mut out = String::new();
+    while pos + 8 <= end {
+        let chunk_type = u16_at(data, pos)?;
+        let size = u32_at(data, pos + 4)? as usize;
+        if size < 8 {
+            return Err(format_err!("invalid chunk size {} at {}", size, pos));
+        }
+        match chunk_type {
+            RES_STRING_POOL_TYPE => strings = string_pool(data, pos)?,
+            RES_TABLE_PACKAGE_TYPE => {
+                let name: Vec<u16> = (0..128)
+                    .map(|i| u16_at(data, pos + 12 + i * 2))
+                    .collect::<Result<Vec<_>>>()?;
+                let name = String::from_utf16_lossy(&name);
+                out.push_str(&format!("package {}\n", name.trim_end_matches('\0')));
+                let type_strings = string_pool(data, pos + u32_at(data, pos + 268)? as usize)?;
+                let key_strings = string_pool(data, pos + u32_at(data, pos + 276)? as usize)?;
+                let package_end = (pos + size).min(end);
+                let mut chunk = pos + u16_at(data, pos + 2)? as usize;
+                while chunk + 8 <= package_end {
+                    let chunk_size = u32_at(data, chunk + 4)? as usize;
+                    if chunk_size < 8 {
+                        return Err(format_err!(
+                            "invalid chunk size {} at {}",
+                            chunk_size,
+                            chunk
+                        ));
+                    }
+                    if u16_at(data, chunk)? == RES_TABLE_TYPE_TYPE {
+                        table_type(data, chunk, &strings, &type_strings, &key_strings, &mut out)?;
+                    }
+                    chunk += chunk_size;
+                }
+            }
+            _ => {}
+        }
+        pos += size;
+    }
+    Ok(out)
+}
+
+fn uleb128(data: &[u8], pos: &mut usize) -> Result<u32> {
+    let mut result = 0u32;
+    for shift in (0..35).step_by(7) {
+        let b = u8_at(data, *pos)?;
+        *pos += 1;
+        result |= ((b & 0x7F) as u32) << shift;
+        if b & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+/// the string table of dalvik bytecode (class, method and field names and string constants)
+pub fn dex_strings(data: &[u8]) -> Result<Vec<String>> {
+    let count = u32_at(data, 0x38)? as usize;
+    let ids_offset = u32_at(data, 0x3C)? as usize;
+    let mut strings = Vec::with_capacity(count.min(data.len() / 4));
+    for i in 0..count {
+        let mut pos = u32_at(data, ids_offset + i * 4)? as usize;
+        // the length in utf-16 units, then modified utf-8 terminated by a zero byte
+        uleb128(data, &mut pos)?;
+        let bytes = data
+            .get(pos..)
+            .ok_or_else(|| format_err!("string out of bounds"))?;
+        let len = memchr::memchr(0, bytes).unwrap_or(bytes.len());
+        strings.push(String::from_utf8_lossy(&bytes[..len]).replace('\n', " "));
+    }
+    Ok(strings)
+}
+
+fn adapt_android(data: &[u8], oup: &mut dyn Write) -> Result<()> {
+    if data.starts_with(b"dex\n") {
+        for string in dex_strings(data)? {
+            writeln!(oup, "{}", string)?;
+        }
+        return Ok(());
+    }
+    match (u16_at(data, 0), u16_at(data, 2)) {
+        (Ok(RES_XML_TYPE), Ok(8)) => oup.write_all(decode_xml(data)?.as_bytes())?,
+        (Ok(RES_TABLE_TYPE), Ok(12)) => oup.write_all(decode_resource_table(data)?.as_bytes())?,
+        // a text xml file (e.g. in an android project)
+        _ => oup.write_all(data)?,
+    }
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for AndroidAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            filepath_hint,
+            is_real_file,
+            ..
+        } = ai;
+        let data = read_input(&filepath_hint, is_real_file, &mut inp)?;
+        adapt_android(&data, oup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn u16s(values: &[u16]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    fn utf16_pool(strings: &[&str]) -> Vec<u8> {
+        let mut offsets = vec![];
+        let mut string_data = vec![];
+        for s in strings {
+            offsets.push(string_data.len() as u32);
+            let units: Vec<u16> = s.encode_utf16().collect();
+            string_data.extend(u16s(&[units.len() as u16]));
+            string_data.extend(u16s(&units));
+            string_data.extend(u16s(&[0]));
+        }
+        while string_data.len() % 4 != 0 {
+            string_data.push(0);
+        }
+        let strings_start = 28 + 4 * strings.len() as u32;
+        let size = strings_start + string_data.len() as u32;
+        let mut pool = u16s(&[RES_STRING_POOL_TYPE, 28]);
+        pool.extend(u32s(&[size, strings.len() as u32, 0, 0, strings_start, 0]));
+        pool.extend(u32s(&offsets));
+        pool.extend(string_data);
+        pool
+    }
+
+    fn node(chunk_type: u16, body: &[u32]) -> Vec<u8> {
+        let mut node = u16s(&[chunk_type, 16]);
+        node.extend(u32s(&[16 + 4 * body.len() as u32, 1, NO_INDEX]));
+        node.extend(u32s(body));
+        node
+    }
+
+    fn start_element(name: u32, attributes: &[[u32; 5]]) -> Vec<u8> {
+        let mut body = vec![NO_INDEX, name, 20 | (20 << 16), attributes.len() as u32, 0];
+        for a in attributes {
+            body.extend(a);
+        }
+        node(RES_XML_START_ELEMENT_TYPE, &body)
+    }
+
+    #[test]
+    fn binary_xml() -> Result<()> {
+        let mut body = utf16_pool(&[
+            "android",
+            "http://schemas.android.com/apk/res/android",
+            "manifest",
+            "package",
+            "com.example.app",
+            "uses-permission",
+            "name",
+            "android.permission.INTERNET",
+            "versionCode",
+        ]);
+        body.extend(node(RES_XML_START_NAMESPACE_TYPE, &[0, 1]));
+        body.extend(start_element(
+            2,
+            &[
+                [NO_INDEX, 3, 4, 8 | (0x03 << 24), 4],
+                [1, 8, NO_INDEX, 8 | (0x10 << 24), 42],
+            ],
+        ));
+        body.extend(start_element(5, &[[1, 6, 7, 8 | (0x03 << 24), 7]]));
+        body.extend(node(RES_XML_END_ELEMENT_TYPE, &[NO_INDEX, 5]));
+        body.extend(node(RES_XML_END_ELEMENT_TYPE, &[NO_INDEX, 2]));
+        body.extend(node(RES_XML_END_NAMESPACE_TYPE, &[0, 1]));
+        let mut xml = u16s(&[RES_XML_TYPE, 8]);
+        xml.extend(u32s(&[8 + body.len() as u32]));
+        xml.extend(body);
+
+        let mut oup = Vec::new();
+        adapt_android(&xml, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "<manifest xmlns:android=\"http://schemas.android.com/apk/res/android\" package=\"com.example.app\" android:versionCode=\"42\">\n  \
+             <uses-permission android:name=\"android.permission.INTERNET\">\n  \
+             </uses-permission>\n\
+             </manifest>\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dex() -> Result<()> {
+        let mut dex = b"dex\n035\0".to_vec();
+        dex.resize(0x70, 0);
+        dex[0x38..0x40].copy_from_slice(&u32s(&[2, 0x70]));
+        dex.extend(u32s(&[0x78, 0x80]));
+        dex.extend(b"\x05Hello\0\0");
+        dex.extend(b"\x06<init>\0");
+        let mut oup = Vec::new();
+        adapt_android(&dex, &mut oup)?;
+        assert_eq!(String::from_utf8(oup)?, "Hello\n<init>\n");
+        Ok(())
+    }
+}
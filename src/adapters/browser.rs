@@ -0,0 +1,284 @@
+use super::*;
+use anyhow::*;
+use chrono::NaiveDateTime;
+use lazy_static::lazy_static;
+use rusqlite::{Connection, NO_PARAMS};
+use serde_json::Value;
+use spawning::input_as_file;
+use std::collections::HashMap;
+use std::fs::File;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+/// firefox history and bookmarks, chrome (and chromium based browsers) history and bookmarks
+static FILENAMES: &[&str] = &["places.sqlite", "History", "Bookmarks"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "browser".to_owned(),
+        version: 1,
+        description: "Outputs the history and bookmarks of Firefox (places.sqlite) and Chrome profiles (History, Bookmarks) as `time URL title` lines. Databases are copied first, so profiles of running browsers can be searched".to_owned(),
+        recurses: false,
+        fast_matchers: FILENAMES
+            .iter()
+            .map(|s| FastFileMatcher::PathGlob(format!("**/{}", s)))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct BrowserAdapter;
+
+impl BrowserAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(BrowserAdapter))
+    }
+}
+impl GetMetadata for BrowserAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// seconds since the unix epoch as `2020-10-16 12:00:00` (UTC)
+fn format_time(secs: i64) -> Option<String> {
+    NaiveDateTime::from_timestamp_opt(secs, 0).map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+/// firefox stores microseconds since the unix epoch
+fn firefox_time(micros: Option<i64>) -> Option<String> {
+    micros
+        .filter(|t| *t > 0)
+        .and_then(|t| format_time(t / 1_000_000))
+}
+
+/// chrome stores microseconds since 1601-01-01
+fn chrome_time(micros: Option<i64>) -> Option<String> {
+    micros
+        .filter(|t| *t > 0)
+        .and_then(|t| format_time(t / 1_000_000 - 11_644_473_600))
+}
+
+fn write_entry(
+    oup: &mut dyn Write,
+    time: Option<String>,
+    url: &str,
+    title: Option<String>,
+    folder: Option<&str>,
+) -> Result<()> {
+    write!(oup, "{} {}", time.as_deref().unwrap_or("-"), url)?;
+    if let Some(title) = title.filter(|t| !t.is_empty()) {
+        write!(oup, " {}", title.replace('\n', " "))?;
+    }
+    match folder {
+        Some("") => write!(oup, " (bookmark)")?,
+        Some(folder) => write!(oup, " (bookmark in {})", folder)?,
+        None => {}
+    }
+    writeln!(oup)?;
+    Ok(())
+}
+
+fn firefox(conn: &Connection, oup: &mut dyn Write) -> Result<()> {
+    let mut history = conn.prepare(
+        "select v.visit_date, p.url, p.title from moz_historyvisits v
+         join moz_places p on p.id = v.place_id order by v.visit_date",
+    )?;
+    let mut rows = history.query(NO_PARAMS)?;
+    while let Some(row) = rows.next()? {
+        let url: String = row.get(1)?;
+        write_entry(oup, firefox_time(row.get(0)?), &url, row.get(2)?, None)?;
+    }
+
+    // id -> (parent, title) of the bookmark folders
+    let mut folders: HashMap<i64, (i64, String)> = HashMap::new();
+    let mut query = conn.prepare("select id, parent, title from moz_bookmarks where type = 2")?;
+    let mut rows = query.query(NO_PARAMS)?;
+    while let Some(row) = rows.next()? {
+        let title: Option<String> = row.get(2)?;
+        folders.insert(row.get(0)?, (row.get(1)?, title.unwrap_or_default()));
+    }
+    let folder_path = |mut id: i64| {
+        let mut path = vec![];
+        // the root folder has itself as parent
+        while let Some((parent, title)) = folders.get(&id) {
+            if !title.is_empty() {
+                path.push(title.as_str());
+            }
+            if *parent == id || path.len() > 100 {
+                break;
+            }
+            id = *parent;
+        }
+        path.reverse();
+        path.join("/")
+    };
+    let mut bookmarks = conn.prepare(
+        "select b.dateAdded, p.url, b.title, b.parent from moz_bookmarks b
+         join moz_places p on p.id = b.fk where b.type = 1 order by b.dateAdded",
+    )?;
+    let mut rows = bookmarks.query(NO_PARAMS)?;
+    while let Some(row) = rows.next()? {
+        let url: String = row.get(1)?;
+        let folder = folder_path(row.get(3)?);
+        write_entry(
+            oup,
+            firefox_time(row.get(0)?),
+            &url,
+            row.get(2)?,
+            Some(&folder),
+        )?;
+    }
+    Ok(())
+}
+
+fn chrome_history(conn: &Connection, oup: &mut dyn Write) -> Result<()> {
+    let mut history = conn.prepare(
+        "select v.visit_time, u.url, u.title from visits v
+         join urls u on u.id = v.url order by v.visit_time",
+    )?;
+    let mut rows = history.query(NO_PARAMS)?;
+    while let Some(row) = rows.next()? {
+        let url: String = row.get(1)?;
+        write_entry(oup, chrome_time(row.get(0)?), &url, row.get(2)?, None)?;
+    }
+    Ok(())
+}
+
+fn chrome_bookmark_node(node: &Value, folder: &str, oup: &mut dyn Write) -> Result<()> {
+    let name = node["name"].as_str().unwrap_or_default();
+    let time = chrome_time(node["date_added"].as_str().and_then(|t| t.parse().ok()));
+    match node["type"].as_str() {
+        Some("url") => write_entry(
+            oup,
+            time,
+            node["url"].as_str().unwrap_or_default(),
+            Some(name.to_string()),
+            Some(folder),
+        )?,
+        _ => {
+            let folder = if folder.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}/{}", folder, name)
+            };
+            for child in node["children"].as_array().into_iter().flatten() {
+                chrome_bookmark_node(child, &folder, oup)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn chrome_bookmarks(json: &Value, oup: &mut dyn Write) -> Result<()> {
+    let roots = json["roots"]
+        .as_object()
+        .ok_or_else(|| format_err!("not a chrome bookmarks file"))?;
+    for root in roots.values().filter(|r| r.is_object()) {
+        chrome_bookmark_node(root, "", oup)?;
+    }
+    Ok(())
+}
+
+fn adapt_database(path: &Path, oup: &mut dyn Write) -> Result<()> {
+    // the browser keeps the database locked while it is running, so work on a snapshot
+    let (_dir, snapshot) = sqlite::snapshot(path)?;
+    let conn = Connection::open(&snapshot)?;
+    let tables: Vec<String> = conn
+        .prepare("select name from sqlite_master where type='table'")?
+        .query_map(NO_PARAMS, |r| r.get::<_, String>(0))?
+        .filter_map(|e| e.ok())
+        .collect();
+    let has = |t: &str| tables.iter().any(|n| n == t);
+    if has("moz_places") && has("moz_historyvisits") && has("moz_bookmarks") {
+        firefox(&conn, oup)
+    } else if has("urls") && has("visits") {
+        chrome_history(&conn, oup)
+    } else {
+        Err(format_err!("not a browser history database"))
+    }
+}
+
+impl WritingFileAdapterTrait for BrowserAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            mut inp,
+            ..
+        } = ai;
+        let (path, _tmp) = input_as_file(&filepath_hint, is_real_file, &mut inp)?;
+        let mut data = Vec::new();
+        File::open(&path)?.read_to_end(&mut data)?;
+        if data.starts_with(b"SQLite format 3\0") {
+            return adapt_database(&path, oup);
+        }
+        match serde_json::from_slice::<Value>(&data) {
+            Ok(json) if json["roots"].is_object() => chrome_bookmarks(&json, oup),
+            // some other file with the same name
+            _ => Ok(oup.write_all(&data)?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn firefox_places() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("places.sqlite");
+        {
+            let conn = Connection::open(&fname)?;
+            conn.execute_batch(
+                "create table moz_places (id integer primary key, url text, title text);
+                 create table moz_historyvisits (id integer primary key, place_id integer, visit_date integer);
+                 create table moz_bookmarks (id integer primary key, type integer, fk integer, parent integer, title text, dateAdded integer);
+                 insert into moz_places values (1, 'https://www.rust-lang.org/', 'Rust Programming Language');
+                 insert into moz_places values (2, 'https://docs.rs/', NULL);
+                 insert into moz_historyvisits values (1, 1, 1602849600000000);
+                 insert into moz_historyvisits values (2, 2, 1602853200000000);
+                 insert into moz_bookmarks values (1, 2, NULL, 1, '', 0);
+                 insert into moz_bookmarks values (2, 2, NULL, 1, 'toolbar', 0);
+                 insert into moz_bookmarks values (3, 2, NULL, 2, 'Dev', 0);
+                 insert into moz_bookmarks values (4, 1, 1, 3, 'Rust', 1602849600000000);",
+            )?;
+        }
+        let mut oup = Vec::new();
+        adapt_database(&fname, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "2020-10-16 12:00:00 https://www.rust-lang.org/ Rust Programming Language\n\
+             2020-10-16 13:00:00 https://docs.rs/\n\
+             2020-10-16 12:00:00 https://www.rust-lang.org/ Rust (bookmark in toolbar/Dev)\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn chrome_bookmarks_json() -> Result<()> {
+        let json: Value = serde_json::from_str(
+            r#"{"checksum": "x", "roots": {
+                "bookmark_bar": {"name": "Bookmarks bar", "type": "folder", "children": [
+                    {"name": "ripgrep", "type": "url", "url": "https://github.com/BurntSushi/ripgrep",
+                     "date_added": "13247323200000000"}]},
+                "other": {"name": "Other bookmarks", "type": "folder", "children": []}},
+              "version": 1}"#,
+        )?;
+        let mut oup = Vec::new();
+        chrome_bookmarks(&json, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "2020-10-16 12:00:00 https://github.com/BurntSushi/ripgrep ripgrep (bookmark in Bookmarks bar)\n"
+        );
+        Ok(())
+    }
+}
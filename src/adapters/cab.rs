@@ -0,0 +1,47 @@
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+
+static EXTENSIONS: &[&str] = &["cab"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "cab".to_owned(),
+        version: 2,
+        description: "Extracts Windows cabinet (.cab) archives using 7z and recurses into the contained files".to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/vnd.ms-cab-compressed".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct CabAdapter;
+
+impl CabAdapter {
+    pub fn new() -> CabAdapter {
+        CabAdapter
+    }
+}
+impl GetMetadata for CabAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+impl FileAdapter for CabAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        sevenzip::extract(ai, Some("cab"))
+    }
+}
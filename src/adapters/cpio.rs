@@ -0,0 +1,419 @@
+use super::*;
+use crate::entry_names::decode_entry_name;
+use crate::{adapted_iter::SingleAdaptedFileAsIter, print_bytes};
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use std::io::{BufReader, Cursor};
+
+static EXTENSIONS: &[&str] = &["cpio", "rpm"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "cpio".to_owned(),
+        version: 2,
+        description: "Reads cpio archives (newc and odc format, e.g. initramfs images) and the payload of RPM packages as a stream and recurses into their contents".to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/x-cpio".to_owned()),
+            FileMatcher::MimeType("application/x-rpm".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct CpioAdapter;
+
+impl CpioAdapter {
+    pub fn new() -> CpioAdapter {
+        CpioAdapter
+    }
+}
+impl GetMetadata for CpioAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+/// PATH_MAX on linux. The names of longer entries are not read, since their size is taken from the header
+const MAX_NAME_LEN: u64 = 4096;
+
+struct EntryHeader {
+    name: Vec<u8>,
+    mode: u32,
+    size: u64,
+    /// padding after the file data
+    data_padding: u64,
+}
+
+fn parse_num(field: &[u8], radix: u32) -> Result<u64> {
+    let s = std::str::from_utf8(field)?;
+    u64::from_str_radix(s, radix).with_context(|| format!("invalid cpio header field {:?}", s))
+}
+
+/// read the header of the next entry, None at the end of the input
+fn read_header(inp: &mut dyn BufRead) -> Result<Option<EntryHeader>> {
+    if inp.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let mut magic = [0u8; 6];
+    inp.read_exact(&mut magic)?;
+    let (mode, size, namesize, name_padding, data_padding_mod) = match &magic {
+        // newc and newc with checksums: 8 hex digit fields, padded to 4 bytes
+        b"070701" | b"070702" => {
+            let mut h = [0u8; 104];
+            inp.read_exact(&mut h)?;
+            let field = |i: usize| parse_num(&h[i * 8..i * 8 + 8], 16);
+            let namesize = field(11)?;
+            (
+                field(1)?,
+                field(6)?,
+                namesize,
+                (4 - (110 + namesize) % 4) % 4,
+                4,
+            )
+        }
+        // odc (portable ascii): octal fields, no padding
+        b"070707" => {
+            let mut h = [0u8; 70];
+            inp.read_exact(&mut h)?;
+            (
+                parse_num(&h[12..18], 8)?,
+                parse_num(&h[59..70], 8)?,
+                parse_num(&h[53..59], 8)?,
+                0,
+                1,
+            )
+        }
+        _ => {
+            return Err(format_err!(
+                "unsupported cpio format (magic {:?})",
+                String::from_utf8_lossy(&magic)
+            ))
+        }
+    };
+    if namesize > MAX_NAME_LEN {
+        return Err(format_err!(
+            "invalid cpio header: entry name of {} bytes",
+            namesize
+        ));
+    }
+    let mut name = vec![0u8; (namesize + name_padding) as usize];
+    inp.read_exact(&mut name)?;
+    name.truncate(namesize as usize);
+    // null terminated
+    if name.last() == Some(&0) {
+        name.pop();
+    }
+    Ok(Some(EntryHeader {
+        name,
+        mode: mode as u32,
+        size,
+        data_padding: (data_padding_mod - size % data_padding_mod) % data_padding_mod,
+    }))
+}
+
+/// the file extension of a compressed stream, so the decompress adapter can pick it up
+fn compressed_ext(magic: &[u8]) -> Option<&'static str> {
+    if magic.starts_with(b"0707") {
+        Some("cpio")
+    } else if magic.starts_with(&[0x1f, 0x8b]) {
+        Some("cpio.gz")
+    } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+        Some("cpio.xz")
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Some("cpio.zst")
+    } else if magic.starts_with(b"BZh") {
+        Some("cpio.bz2")
+    } else if magic.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+        Some("cpio.lz4")
+    } else {
+        None
+    }
+}
+
+/// skip the lead, signature and header of an RPM package, leaving the input at the (compressed) cpio payload
+fn skip_rpm_headers(inp: &mut dyn Read) -> Result<()> {
+    let mut lead = [0u8; 96];
+    inp.read_exact(&mut lead)?;
+    if lead[0..4] != [0xed, 0xab, 0xee, 0xdb] {
+        return Err(format_err!("not an rpm file"));
+    }
+    for is_signature in &[true, false] {
+        let mut intro = [0u8; 16];
+        inp.read_exact(&mut intro)?;
+        if intro[0..3] != [0x8e, 0xad, 0xe8] {
+            return Err(format_err!("invalid rpm header"));
+        }
+        let nindex = u32::from_be_bytes([intro[8], intro[9], intro[10], intro[11]]) as u64;
+        let hsize = u32::from_be_bytes([intro[12], intro[13], intro[14], intro[15]]) as u64;
+        let mut len = 16 * nindex + hsize;
+        if *is_signature {
+            // the signature is padded to 8 bytes
+            len += (8 - (16 + len) % 8) % 8;
+        }
+        std::io::copy(&mut (&mut *inp).take(len), &mut std::io::sink())?;
+    }
+    Ok(())
+}
+
+/// reads the data of one entry, keeping track of how much is left to skip afterwards
+struct EntryReader<'b> {
+    inp: &'b mut dyn Read,
+    remaining: &'b mut u64,
+}
+impl<'b> Read for EntryReader<'b> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let max = (buf.len() as u64).min(*self.remaining) as usize;
+        let n = self.inp.read(&mut buf[..max])?;
+        *self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+struct CpioIter<'a> {
+    inp: BufReader<ReadBox<'a>>,
+    filepath_hint: PathBuf,
+    line_prefix: String,
+    archive_recursion_depth: i32,
+    postprocess: bool,
+    config: RgaConfig,
+    /// unread bytes of the previous entry
+    remaining: u64,
+    padding: u64,
+    done: bool,
+}
+
+impl<'a> CpioIter<'a> {
+    /// after a trailer there may be (after zero padding) another archive, e.g. an initramfs
+    /// consisting of an uncompressed microcode archive followed by the compressed main archive.
+    /// Returns the extension of the following data, None at the end of the input.
+    fn skip_to_next_archive(&mut self) -> Result<Option<&'static str>> {
+        loop {
+            let buf = self.inp.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            let zeros = buf.iter().take_while(|b| **b == 0).count();
+            let all_zero = zeros == buf.len();
+            self.inp.consume(zeros);
+            if !all_zero {
+                break;
+            }
+        }
+        let ext = compressed_ext(self.inp.fill_buf()?);
+        if ext.is_none() {
+            warn!(
+                "{}: ignoring unknown data after cpio archive",
+                self.filepath_hint.display()
+            );
+        }
+        Ok(ext)
+    }
+
+    /// skip to the next regular file or to compressed data following the archive
+    fn advance(&mut self) -> Result<Option<Next>> {
+        loop {
+            std::io::copy(
+                &mut (&mut self.inp).take(self.remaining + self.padding),
+                &mut std::io::sink(),
+            )?;
+            self.remaining = 0;
+            self.padding = 0;
+            let header = match read_header(&mut self.inp)? {
+                Some(header) => header,
+                None => return Ok(None),
+            };
+            if header.name == b"TRAILER!!!" {
+                match self.skip_to_next_archive()? {
+                    None => return Ok(None),
+                    Some("cpio") => continue,
+                    Some(ext) => return Ok(Some(Next::Compressed(ext))),
+                }
+            }
+            self.remaining = header.size;
+            self.padding = header.data_padding;
+            if header.mode & S_IFMT == S_IFREG {
+                return Ok(Some(Next::File(header)));
+            }
+        }
+    }
+}
+
+enum Next {
+    File(EntryHeader),
+    /// the extension of the compressed rest of the input
+    Compressed(&'static str),
+}
+
+impl<'a> AdaptedFilesIter for CpioIter<'a> {
+    fn next<'b>(&'b mut self) -> Option<AdaptInfo<'b>> {
+        if self.done {
+            return None;
+        }
+        match self.advance() {
+            Ok(Some(Next::File(header))) => {
+                let name = decode_entry_name(&header.name, &self.config.entry_name_encoding, None);
+                debug!(
+                    "{}{}|{}: {}",
+                    self.line_prefix,
+                    self.filepath_hint.display(),
+                    name,
+                    print_bytes(header.size as f64)
+                );
+                Some(AdaptInfo {
+                    line_prefix: self.config.entry_prefix(&self.line_prefix, &name),
+                    filepath_hint: PathBuf::from(name),
+                    is_real_file: false,
+                    archive_recursion_depth: self.archive_recursion_depth + 1,
+                    inp: Box::new(EntryReader {
+                        inp: &mut self.inp,
+                        remaining: &mut self.remaining,
+                    }),
+                    postprocess: self.postprocess,
+                    config: self.config.clone(),
+                })
+            }
+            Ok(Some(Next::Compressed(ext))) => {
+                // hand the rest to the decompress adapter, which will give it back to us
+                self.done = true;
+                Some(AdaptInfo {
+                    filepath_hint: PathBuf::from(format!(
+                        "{}.{}",
+                        self.filepath_hint.display(),
+                        ext
+                    )),
+                    is_real_file: false,
+                    archive_recursion_depth: self.archive_recursion_depth + 1,
+                    inp: Box::new(&mut self.inp),
+                    line_prefix: self.line_prefix.clone(),
+                    postprocess: self.postprocess,
+                    config: self.config.clone(),
+                })
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                warn!("{}: {:#}", self.filepath_hint.display(), e);
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
+impl FileAdapter for CpioAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        let AdaptInfo {
+            filepath_hint,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let is_rpm = match detection_reason {
+            FileMatcher::MimeType(mime) => mime == "application/x-rpm",
+            _ => filepath_hint
+                .extension()
+                .map(|e| e.eq_ignore_ascii_case("rpm"))
+                .unwrap_or(false),
+        };
+        if is_rpm {
+            skip_rpm_headers(&mut inp)?;
+            let mut magic = [0u8; 6];
+            inp.read_exact(&mut magic)?;
+            let ext = compressed_ext(&magic)
+                .ok_or_else(|| format_err!("unknown rpm payload compression"))?;
+            return Ok(Box::new(SingleAdaptedFileAsIter::new(AdaptInfo {
+                filepath_hint: filepath_hint.with_extension(ext),
+                is_real_file: false,
+                archive_recursion_depth: archive_recursion_depth + 1,
+                inp: Box::new(Cursor::new(magic).chain(inp)),
+                line_prefix,
+                postprocess,
+                config,
+            })));
+        }
+        Ok(Box::new(CpioIter {
+            inp: BufReader::new(inp),
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            remaining: 0,
+            padding: 0,
+            done: false,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn newc_entry(out: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+        let namesize = name.len() + 1;
+        out.extend(
+            format!(
+                "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+                1, mode, 0, 0, 1, 0, data.len(), 0, 0, 0, 0, namesize, 0
+            )
+            .as_bytes(),
+        );
+        out.extend(name.as_bytes());
+        out.push(0);
+        out.resize(out.len() + (4 - (110 + namesize) % 4) % 4, 0);
+        out.extend(data);
+        out.resize(out.len() + (4 - data.len() % 4) % 4, 0);
+    }
+
+    #[test]
+    fn newc() -> Result<()> {
+        let mut archive = Vec::new();
+        newc_entry(&mut archive, "etc", 0o040755, b"");
+        newc_entry(&mut archive, "etc/hostname", 0o100644, b"box");
+        newc_entry(&mut archive, "bin/sh", 0o120777, b"busybox");
+        newc_entry(
+            &mut archive,
+            "init",
+            0o100755,
+            b"#!/bin/sh\nexec switch_root",
+        );
+        newc_entry(&mut archive, "TRAILER!!!", 0, b"");
+        archive.resize(archive.len() + 512, 0);
+
+        let adapter = CpioAdapter::new();
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("initramfs.cpio"),
+            Box::new(Cursor::new(archive)),
+        );
+        let o = adapted_to_vec(adapter.adapt(a, &d)?)?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            "PREFIX:etc/hostname: box\nPREFIX:init: #!/bin/sh\nPREFIX:init: exec switch_root\n"
+        );
+
+        // namesize is the 12th field
+        let header = format!("070701{:088x}{:08x}{:08x}", 0, 0xffff_ffffu32, 0);
+        assert!(read_header(&mut Cursor::new(header.as_bytes())).is_err());
+        Ok(())
+    }
+}
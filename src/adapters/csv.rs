@@ -0,0 +1,164 @@
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+use spreadsheet::column_name;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["csv", "tsv", "tab", "psv"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "csv".to_owned(),
+        version: 2,
+        description: "Outputs the rows of CSV / TSV files with the column names from the header row attached (delimiter is detected automatically). Disabled by default, enable it with --rga-adapters=+csv".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("text/csv".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct CsvAdapter;
+
+impl CsvAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(CsvAdapter))
+    }
+}
+impl GetMetadata for CsvAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+static DELIMITERS: &[u8] = b",\t;|";
+
+/// count the delimiter in a line, ignoring quoted parts
+fn count_outside_quotes(line: &[u8], delimiter: u8) -> usize {
+    let mut quoted = false;
+    let mut count = 0;
+    for &b in line {
+        if b == b'"' {
+            quoted = !quoted;
+        } else if b == delimiter && !quoted {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// the delimiter that occurs the same (non-zero) number of times in the first lines, preferring the most frequent one
+fn detect_delimiter(sample: &[u8]) -> u8 {
+    let mut lines: Vec<&[u8]> = sample.split(|&b| b == b'\n').take(10).collect();
+    // the last line of the sample is probably cut off
+    if lines.len() > 1 {
+        lines.pop();
+    }
+    DELIMITERS
+        .iter()
+        .filter_map(|&d| {
+            let counts: Vec<usize> = lines.iter().map(|l| count_outside_quotes(l, d)).collect();
+            let first = *counts.get(0)?;
+            if first > 0 && counts.iter().all(|&c| c == first) {
+                Some((first, d))
+            } else {
+                None
+            }
+        })
+        .max()
+        .map(|(_, d)| d)
+        .unwrap_or(b',')
+}
+
+fn adapt_csv(mut inp: impl BufRead, cells: bool, oup: &mut dyn Write) -> Result<()> {
+    let delimiter = detect_delimiter(inp.fill_buf()?);
+    let mut reader = ::csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(inp);
+    let headers = reader.headers()?.clone();
+    for (i, record) in reader.records().enumerate() {
+        let record = record?;
+        let row = i + 1;
+        let values = record.iter().enumerate().filter(|(_, v)| !v.is_empty());
+        let header = |col: usize| headers.get(col).filter(|h| !h.is_empty());
+        let column = |col: usize| {
+            header(col)
+                .map(|h| h.to_string())
+                .unwrap_or_else(|| (col + 1).to_string())
+        };
+        if cells {
+            for (col, value) in values {
+                // the reference of the cell when the file is opened as a spreadsheet, with the header in row 1
+                let cell = format!("{}{}", column_name(col as u32), row + 1);
+                match header(col) {
+                    Some(h) => writeln!(oup, "{} ({}): {}", cell, h, value)?,
+                    None => writeln!(oup, "{}: {}", cell, value)?,
+                }
+            }
+        } else {
+            let values: Vec<String> = values
+                .map(|(col, value)| format!("{}={}", column(col), value))
+                .collect();
+            writeln!(oup, "row {}: {}", row, values.join(", "))?;
+        }
+    }
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for CsvAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo { inp, config, .. } = ai;
+        adapt_csv(
+            std::io::BufReader::with_capacity(1 << 16, inp),
+            config.csv.cells,
+            oup,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn semicolon_rows_and_cells() -> Result<()> {
+        let data =
+            "name;email;note\nAlice;alice@example.com;\"likes ; and \"\"quotes\"\"\"\nBob;;\n";
+        let mut oup = Vec::new();
+        adapt_csv(data.as_bytes(), false, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "row 1: name=Alice, email=alice@example.com, note=likes ; and \"quotes\"\n\
+             row 2: name=Bob\n"
+        );
+        let mut oup = Vec::new();
+        adapt_csv(data.as_bytes(), true, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "A2 (name): Alice\n\
+             B2 (email): alice@example.com\n\
+             C2 (note): likes ; and \"quotes\"\n\
+             A3 (name): Bob\n"
+        );
+        let mut oup = Vec::new();
+        adapt_csv("a,b\n1,2,3\n".as_bytes(), true, &mut oup)?;
+        assert_eq!(String::from_utf8(oup)?, "A2 (a): 1\nB2 (b): 2\nC2: 3\n");
+        Ok(())
+    }
+
+    #[test]
+    fn tabs() {
+        assert_eq!(detect_delimiter(b"a\tb,c\td\n1\t2,3\t4\n5\t"), b'\t');
+    }
+}
@@ -1,14 +1,26 @@
 use super::{
-    spawning::{SpawningFileAdapter, SpawningFileAdapterTrait},
-    AdapterMeta, GetMetadata,
+    pandoc_server, persistent,
+    plugin::PluginAdapter,
+    sandbox::sandboxed,
+    spawning::{
+        limit_resources, program_command, CommandPaths, SpawningFileAdapter,
+        SpawningFileAdapterTrait,
+    },
+    AdapterMeta, FileAdapter, GetMetadata,
 };
+use crate::config::RgaConfig;
 use crate::matching::{FastFileMatcher, FileMatcher};
+use crate::secrets::{self, SecretKind};
 use anyhow::Result;
 use lazy_static::lazy_static;
 use regex::{Captures, Regex};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::Read;
 use std::path::Path;
+use std::rc::Rc;
 
 // mostly the same as AdapterMeta + SpawningFileAdapter
 #[derive(Debug, Deserialize, Serialize, JsonSchema, Default, PartialEq, Clone)]
@@ -19,7 +31,8 @@ pub struct CustomAdapterConfig {
     pub description: String,
     /// if true, the adapter will be disabled by default
     pub disabled_by_default: Option<bool>,
-    /// version identifier. used to key cache entries, change if the configuration or program changes
+    /// version identifier. used to key cache entries, change if the program changes.
+    /// Changes of binary and args are detected automatically
     pub version: i32,
     /// the file extensions this adapter supports. For example ["epub", "mobi"]
     pub extensions: Vec<String>,
@@ -27,12 +40,58 @@ pub struct CustomAdapterConfig {
     pub mimetypes: Option<Vec<String>>,
     /// if --rga-accurate, only match by mime types, ignore extensions completely
     pub match_only_by_mime: Option<bool>,
+    /// the bytes files start with, as hex. For example ["7f 45 4c 46"].
+    /// Bytes at an offset within the first 64KiB are given as "offset: hex", for example ["32769: 43 44 30 30 31"].
+    /// Matched also without --rga-accurate, so files without an extension are handled too
+    pub magic: Option<Vec<String>>,
+    /// a regex matched against the first 64KiB of files. For example "^%!PS-Adobe"
+    pub content_regex: Option<String>,
     /// the name or path of the binary to run
     pub binary: String,
     /// The arguments to run the program with. Placeholders:
-    /// {}: the file path (TODO)
+    /// {file_extension} or {ext}: the extension of the file, e.g. "docx"
+    /// {path}: the path of the file (within archives: the path within the archive)
+    /// {basename}: the file name, e.g. "report.docx"
+    /// {file}: the path of the file to read (only with needs_file)
+    /// {tmpdir}: a temporary directory for the program, deleted after it ran
     /// stdin of the program will be connected to the input file, and stdout is assumed to be the converted file
     pub args: Vec<String>,
+    /// environment variables to run the program (and the pipe_to programs) with. For example {"LC_ALL": "C.UTF-8"}.
+    /// The values can contain the same placeholders as args
+    pub env: Option<BTreeMap<String, String>>,
+    /// if true, the program can't read the input from stdin, but needs a (seekable) file.
+    /// It's given the path of the file as {file}, or as the last argument if the args don't contain {file}.
+    /// Files within archives are written to a temporary file first
+    pub needs_file: Option<bool>,
+    /// programs the output is piped through, in order. For example msgconvert piped to html2text.
+    /// Their args can contain the same placeholders as args
+    pub pipe_to: Option<Vec<CustomCommand>>,
+    /// the file extension of the output, e.g. "eml". The output is then matched against the adapters again,
+    /// like a file in an archive. By default, the output is plain text
+    pub output_extension: Option<String>,
+    /// if true, the program separates pages with form feeds, which are replaced by "Page N:" line prefixes
+    /// (unless --rga-no-page-numbers is given)
+    pub page_breaks: Option<bool>,
+    /// the path of a shared library implementing the adapter, instead of a binary to run.
+    /// See the plugin module for the functions it has to export
+    pub library: Option<String>,
+    /// if true, the binary is started once and converts all files, which it gets as requests on stdin
+    /// (see the persistent module for the protocol). Its args can't contain placeholders
+    pub persistent: Option<bool>,
+    /// the arguments to give the password of protected PDF files with (see --rga-pdf-password), before the args.
+    /// {password} is replaced by the password. For example ["-opw", "{password}", "-upw", "{password}"]
+    pub pdf_password_args: Option<Vec<String>>,
+    /// the argument with which the binary prints its version, for example "--version". Shown by --rga-doctor
+    pub version_arg: Option<String>,
+}
+
+/// a program the output of a custom adapter is piped through
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Default, PartialEq, Clone)]
+pub struct CustomCommand {
+    /// the name or path of the binary to run
+    pub binary: String,
+    /// the arguments to run the program with
+    pub args: Vec<String>,
 }
 
 fn strs(arr: &[&str]) -> Vec<String> {
@@ -92,11 +151,22 @@ lazy_static! {
                 "--atx-headers"
             ]),
             disabled_by_default: None,
-            match_only_by_mime: None
+            match_only_by_mime: None,
+            magic: None,
+            content_regex: None,
+            needs_file: None,
+            env: None,
+            pipe_to: None,
+            output_extension: None,
+            library: None,
+            persistent: None,
+            page_breaks: None,
+            pdf_password_args: None,
+            version_arg: Some("--version".to_string())
         },
         CustomAdapterConfig {
             name: "poppler".to_owned(),
-            version: 1,
+            version: 2,
             description: "Uses pdftotext (from poppler-utils) to extract plain text from PDF files"
                 .to_owned(),
 
@@ -106,8 +176,19 @@ lazy_static! {
             binary: "pdftotext".to_string(),
             args: strs(&["-", "-"]),
             disabled_by_default: None,
-            match_only_by_mime: None
-            // postprocessors: [{name: "add_page_numbers_by_pagebreaks"}]
+            match_only_by_mime: None,
+            magic: None,
+            content_regex: None,
+            needs_file: None,
+            env: None,
+            pipe_to: None,
+            output_extension: None,
+            library: None,
+            persistent: None,
+            page_breaks: Some(true),
+            // poppler accepts either kind of password with both
+            pdf_password_args: Some(strs(&["-opw", "{password}", "-upw", "{password}"])),
+            version_arg: Some("-v".to_string())
         }
     ];
 }
@@ -115,6 +196,13 @@ lazy_static! {
 pub struct CustomSpawningFileAdapter {
     binary: String,
     args: Vec<String>,
+    page_breaks: bool,
+    needs_file: bool,
+    env: BTreeMap<String, String>,
+    pipe_to: Vec<CustomCommand>,
+    output_extension: Option<String>,
+    persistent: bool,
+    pdf_password_args: Vec<String>,
     meta: AdapterMeta,
 }
 impl GetMetadata for CustomSpawningFileAdapter {
@@ -122,14 +210,14 @@ impl GetMetadata for CustomSpawningFileAdapter {
         &self.meta
     }
 }
-fn arg_replacer(arg: &str, filepath_hint: &Path) -> Result<String> {
+fn arg_replacer(arg: &str, paths: &CommandPaths) -> Result<String> {
     lazy_static::lazy_static! {
         static ref ARG_REP: Regex = Regex::new(r"\{([a-z_]+)\}").unwrap();
     }
     let mut err = None;
     let r = ARG_REP.replace_all(arg, |m: &Captures| -> String {
         let idx = m.get(0).unwrap().range();
-        if arg.chars().nth(idx.start - 1) == Some('{') {
+        if idx.start > 0 && arg.chars().nth(idx.start - 1) == Some('{') {
             // skip
             return m.get(0).unwrap().as_str().to_string();
         }
@@ -138,11 +226,26 @@ fn arg_replacer(arg: &str, filepath_hint: &Path) -> Result<String> {
             return m.get(0).unwrap().as_str().to_string();
         }
         let key = m.get(1).unwrap().as_str();
-        if key == "file_extension" {
-            return filepath_hint
-                .extension()
-                .map(|e| e.to_string_lossy().to_string())
-                .unwrap_or("".to_string());
+        let lossy = |p: Option<&std::ffi::OsStr>| p.map(|p| p.to_string_lossy().to_string());
+        let replacement = match key {
+            "file_extension" | "ext" => {
+                Some(lossy(paths.filepath_hint.extension()).unwrap_or_default())
+            }
+            "path" => Some(paths.filepath_hint.to_string_lossy().to_string()),
+            "basename" => Some(lossy(paths.filepath_hint.file_name()).unwrap_or_default()),
+            "file" => paths.input_path.map(|p| p.to_string_lossy().to_string()),
+            "tmpdir" => paths.tmpdir.map(|p| p.to_string_lossy().to_string()),
+            _ => None,
+        };
+        if let Some(replacement) = replacement {
+            return replacement;
+        }
+        if key == "file" {
+            err = Some(anyhow::anyhow!(
+                "{{file}} in '{}' is only replaced for adapters with needs_file",
+                arg
+            ));
+            return "".to_string();
         }
         err = Some(anyhow::anyhow!(
             "Unknown arg replacement key '{}' in '{}'",
@@ -158,44 +261,311 @@ fn arg_replacer(arg: &str, filepath_hint: &Path) -> Result<String> {
         Ok(r.to_string())
     }
 }
+impl CustomSpawningFileAdapter {
+    /// the builtin pandoc adapter, as opposed to custom adapters that run pandoc with their own arguments
+    fn is_builtin_pandoc(&self) -> bool {
+        self.meta.name == "pandoc" && self.binary == "pandoc"
+    }
+    /// the output format of the builtin pandoc adapter
+    fn pandoc_output_format(config: &RgaConfig) -> &'static str {
+        if config.markdown {
+            "gfm"
+        } else {
+            "plain"
+        }
+    }
+    fn args(&self, paths: &CommandPaths, config: &RgaConfig) -> Result<Vec<String>> {
+        let mut args = self
+            .args
+            .iter()
+            .map(|arg| {
+                if arg == "--to=plain" && self.is_builtin_pandoc() {
+                    return Ok(format!("--to={}", Self::pandoc_output_format(config)));
+                }
+                arg_replacer(arg, paths)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(input_path) = paths.input_path {
+            if !self.args.iter().any(|arg| arg.contains("{file}")) {
+                args.push(input_path.to_string_lossy().into_owned());
+            }
+        }
+        Ok(args)
+    }
+    /// the environment variables for the program and the programs of pipe_to
+    fn envs(&self, paths: &CommandPaths) -> Result<Vec<(&String, String)>> {
+        self.env
+            .iter()
+            .map(|(key, value)| Ok((key, arg_replacer(value, paths)?)))
+            .collect()
+    }
+    /// whether the placeholder is used in the args, the args of pipe_to or the env
+    fn uses_placeholder(&self, key: &str) -> bool {
+        let placeholder = format!("{{{}}}", key);
+        self.args
+            .iter()
+            .chain(self.pipe_to.iter().flat_map(|c| &c.args))
+            .chain(self.env.values())
+            .any(|arg| arg.contains(&placeholder))
+    }
+}
 impl SpawningFileAdapterTrait for CustomSpawningFileAdapter {
     fn get_exe(&self) -> &str {
         &self.binary
     }
+    fn page_breaks(&self) -> bool {
+        self.page_breaks
+    }
+    fn needs_file(&self) -> bool {
+        self.needs_file
+    }
+    fn needs_tmpdir(&self) -> bool {
+        self.uses_placeholder("tmpdir")
+    }
+    fn output_extension(&self) -> Option<&str> {
+        self.output_extension.as_deref()
+    }
+    fn persistent(&self) -> bool {
+        self.persistent
+    }
     fn command(
         &self,
-        filepath_hint: &std::path::Path,
+        paths: &CommandPaths,
+        config: &RgaConfig,
         mut command: std::process::Command,
     ) -> Result<std::process::Command> {
-        command.args(
-            self.args
-                .iter()
-                .map(|arg| arg_replacer(arg, &filepath_hint))
-                .collect::<Result<Vec<_>>>()?,
-        );
+        if !self.pdf_password_args.is_empty() {
+            if let Some(password) =
+                secrets::password_for(config, SecretKind::Pdf, paths.filepath_hint)?
+            {
+                command.args(
+                    self.pdf_password_args
+                        .iter()
+                        .map(|arg| arg.replace("{password}", &password)),
+                );
+            }
+        }
+        command.args(self.args(paths, config)?);
+        command.envs(self.envs(paths)?);
         log::debug!("running command {:?}", command);
         Ok(command)
     }
+
+    fn pipe_to(&self, paths: &CommandPaths) -> Result<Vec<(String, std::process::Command)>> {
+        self.pipe_to
+            .iter()
+            .map(|c| {
+                let mut command = program_command(&c.binary);
+                for arg in &c.args {
+                    command.arg(arg_replacer(arg, paths)?);
+                }
+                command.envs(self.envs(paths)?);
+                Ok((c.binary.clone(), command))
+            })
+            .collect()
+    }
+
+    /// the output of encrypted pdfs depends on the password
+    fn cache_key_extra(&self, filepath: &Path, config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        let mut extra = None;
+        if !self.pdf_password_args.is_empty() {
+            extra = secrets::cache_key(config, SecretKind::Pdf, filepath)?;
+        }
+        // identical files are only converted once, unless the program is given the file name
+        if self.uses_placeholder("path") || self.uses_placeholder("basename") {
+            extra
+                .get_or_insert_with(Vec::new)
+                .extend_from_slice(filepath.to_string_lossy().as_bytes());
+        }
+        Ok(extra)
+    }
+
+    fn convert_with_server(
+        &self,
+        filepath_hint: &Path,
+        config: &RgaConfig,
+        inp: &mut dyn Read,
+    ) -> Result<Option<String>> {
+        if self.persistent {
+            let mut command = program_command(&self.binary);
+            command
+                .args(config.adapter_extra_args(&self.meta.name))
+                .args(&self.args)
+                .envs(&self.env);
+            // the files are sent on stdin, the program doesn't need to read any
+            let paths = CommandPaths {
+                filepath_hint,
+                input_path: None,
+                tmpdir: None,
+            };
+            let mut command = sandboxed(command, &paths, config)?;
+            // the cpu time of the process adds up over all files, so only the memory and file size limits apply
+            let mut limits = config.clone();
+            limits.adapter_cpu_limit = None;
+            limit_resources(&mut command, &limits);
+            return persistent::convert(command, filepath_hint, inp).map(Some);
+        }
+        // custom adapters that run pandoc have their own arguments. The server is reached over the network,
+        // which the sandbox doesn't have, so with it pandoc is run (in the sandbox) for every file
+        if !config.pandoc_server || config.sandbox || !self.is_builtin_pandoc() {
+            return Ok(None);
+        }
+        let from = filepath_hint
+            .extension()
+            .map(|e| e.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        pandoc_server::convert(&from, Self::pandoc_output_format(config), inp)
+    }
+
+    /// editing the command of a custom adapter changes its output
+    fn definition_digest(&self) -> Option<Vec<u8>> {
+        let mut definition = bincode::serialize(&(&self.binary, &self.args, self.meta.version))
+            .expect("could not serialize adapter definition");
+        // only added when set, so the digests of other adapters stay the same
+        if !self.env.is_empty() || !self.pipe_to.is_empty() || self.output_extension.is_some() {
+            definition.extend(
+                bincode::serialize(&(&self.env, &self.pipe_to, &self.output_extension))
+                    .expect("could not serialize adapter definition"),
+            );
+        }
+        if self.persistent {
+            definition.extend(b"persistent");
+        }
+        if self.page_breaks {
+            definition.extend(b"page_breaks");
+        }
+        if self.needs_file {
+            definition.extend(b"needs_file");
+        }
+        if !self.pdf_password_args.is_empty() {
+            definition.extend(
+                bincode::serialize(&self.pdf_password_args)
+                    .expect("could not serialize adapter definition"),
+            );
+        }
+        Some(Sha256::digest(&definition).to_vec())
+    }
 }
 impl CustomAdapterConfig {
     pub fn to_adapter(&self) -> SpawningFileAdapter {
-        let ad = CustomSpawningFileAdapter {
+        SpawningFileAdapter::new(Box::new(self.to_custom_adapter()))
+    }
+    /// the adapter running the binary, or the plugin adapter if a library is given
+    pub fn to_file_adapter(&self) -> Rc<dyn FileAdapter> {
+        match &self.library {
+            Some(library) => Rc::new(PluginAdapter::new(self, library)),
+            None => Rc::new(self.to_adapter()),
+        }
+    }
+    pub fn adapter_meta(&self) -> AdapterMeta {
+        self.to_custom_adapter().meta
+    }
+    /// the mistakes in the definition that make the adapter fail or never match a file (see --rga-doctor)
+    pub fn problems(&self) -> Vec<String> {
+        lazy_static! {
+            static ref NAME: Regex = Regex::new("^[a-z0-9_]+$").unwrap();
+        }
+        let mut problems = vec![];
+        if !NAME.is_match(&self.name) {
+            problems.push(format!(
+                "the name {:?} must only include a-z, 0-9, _",
+                self.name
+            ));
+        }
+        let no_matchers = |v: &Option<Vec<String>>| v.as_ref().map_or(true, Vec::is_empty);
+        if self.extensions.is_empty()
+            && no_matchers(&self.mimetypes)
+            && no_matchers(&self.magic)
+            && self.content_regex.is_none()
+        {
+            problems.push(
+                "has no extensions, mimetypes, magic or content_regex, so it never matches a file"
+                    .to_string(),
+            );
+        }
+        if self.library.is_some() && !self.binary.is_empty() {
+            problems.push("has both a binary and a library".to_string());
+        }
+        if self.persistent == Some(true) {
+            lazy_static! {
+                static ref PLACEHOLDER: Regex = Regex::new(r"\{[a-z_]+\}").unwrap();
+            }
+            if self.needs_file == Some(true) || self.pipe_to.is_some() || self.library.is_some() {
+                problems.push(
+                    "is persistent, which can't be combined with needs_file, pipe_to or library"
+                        .to_string(),
+                );
+            }
+            if self.args.iter().any(|arg| PLACEHOLDER.is_match(arg)) {
+                problems.push(
+                    "is persistent, its args can't contain placeholders (the path is sent with every file)"
+                        .to_string(),
+                );
+            }
+        }
+        let adapter = self.to_custom_adapter();
+        let paths = CommandPaths {
+            filepath_hint: Path::new("file.ext"),
+            input_path: Some(Path::new("file.ext")).filter(|_| adapter.needs_file),
+            tmpdir: Some(Path::new("tmp")),
+        };
+        let commands = adapter
+            .args(&paths, &RgaConfig::default())
+            .and(adapter.envs(&paths).map(|_| ()))
+            .and(adapter.pipe_to(&paths).map(|_| ()));
+        if let Err(e) = commands {
+            problems.push(format!("{:#}", e));
+        }
+        let adapters = vec![self.to_file_adapter()];
+        let matchers = crate::matching::adapter_matcher(&adapters, false)
+            .and_then(|_| crate::matching::adapter_matcher(&adapters, true));
+        if let Err(e) = matchers {
+            problems.push(format!("{:#}", e));
+        }
+        problems
+    }
+    fn to_custom_adapter(&self) -> CustomSpawningFileAdapter {
+        CustomSpawningFileAdapter {
             binary: self.binary.clone(),
             args: self.args.clone(),
+            page_breaks: self.page_breaks.unwrap_or(false),
+            needs_file: self.needs_file.unwrap_or(false),
+            env: self.env.clone().unwrap_or_default(),
+            pipe_to: self.pipe_to.clone().unwrap_or_default(),
+            output_extension: self.output_extension.clone(),
+            persistent: self.persistent == Some(true),
+            pdf_password_args: self.pdf_password_args.clone().unwrap_or_default(),
             meta: AdapterMeta {
                 name: self.name.clone(),
                 version: self.version,
                 description: format!(
-                    "{}\nRuns: {} {}",
+                    "{}\nRuns: {} {}{}",
                     self.description,
                     self.binary,
-                    self.args.join(" ")
+                    self.args.join(" "),
+                    self.pipe_to
+                        .iter()
+                        .flatten()
+                        .map(|c| format!(" | {} {}", c.binary, c.args.join(" ")))
+                        .collect::<String>()
                 ),
-                recurses: false,
+                // the output is adapted again, so the cache key has to include the other adapters
+                recurses: self.output_extension.is_some(),
                 fast_matchers: self
                     .extensions
                     .iter()
                     .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+                    .chain(
+                        self.magic
+                            .iter()
+                            .flatten()
+                            .map(|s| FastFileMatcher::Magic(s.to_string())),
+                    )
+                    .chain(
+                        self.content_regex
+                            .iter()
+                            .map(|s| FastFileMatcher::ContentRegex(s.to_string())),
+                    )
                     .collect(),
                 slow_matchers: self.mimetypes.as_ref().map(|mimetypes| {
                     mimetypes
@@ -206,8 +576,7 @@ impl CustomAdapterConfig {
                 keep_fast_matchers_if_accurate: !self.match_only_by_mime.unwrap_or(false),
                 disabled_by_default: self.disabled_by_default.unwrap_or(false),
             },
-        };
-        SpawningFileAdapter::new(Box::new(ad))
+        }
     }
 }
 
@@ -245,4 +614,288 @@ PREFIX:\u{c}
         );
         Ok(())
     }
+
+    #[test]
+    fn pdf_password_file() -> Result<()> {
+        use std::io::Write;
+        let mut password_file = tempfile::NamedTempFile::new()?;
+        writeln!(
+            password_file,
+            "# passwords\n\ninvoices/*.pdf  secret one\nreport-*.pdf hunter2"
+        )?;
+        let mut config = RgaConfig::default();
+        config.pdf.password = Some("fallback".to_string());
+        config.pdf.password_file = Some(password_file.path().to_string_lossy().into_owned());
+
+        let password =
+            |path: &str| secrets::password_for(&config, SecretKind::Pdf, Path::new(path));
+        assert_eq!(
+            password("invoices/2020.pdf")?.as_deref(),
+            Some("secret one")
+        );
+        assert_eq!(
+            password("/home/me/report-q3.pdf")?.as_deref(),
+            Some("hunter2")
+        );
+        assert_eq!(password("other.pdf")?.as_deref(), Some("fallback"));
+        Ok(())
+    }
+
+    #[test]
+    fn definition_digest() {
+        let poppler = builtin_spawning_adapters
+            .iter()
+            .find(|e| e.name == "poppler")
+            .expect("no poppler adapter");
+        let mut edited = poppler.clone();
+        edited.args.push("-layout".to_string());
+        let digest = |c: &CustomAdapterConfig| c.to_adapter().definition_digest();
+        assert!(digest(poppler).is_some());
+        assert_eq!(digest(poppler), digest(&poppler.clone()));
+        assert_ne!(digest(poppler), digest(&edited));
+        let mut piped = poppler.clone();
+        piped.pipe_to = Some(vec![CustomCommand {
+            binary: "fmt".to_string(),
+            args: vec![],
+        }]);
+        assert_ne!(digest(poppler), digest(&piped));
+        let mut password = poppler.clone();
+        password.pdf_password_args = Some(strs(&["-upw", "{password}"]));
+        assert_ne!(digest(poppler), digest(&password));
+        let mut no_page_breaks = poppler.clone();
+        no_page_breaks.page_breaks = None;
+        assert_ne!(digest(poppler), digest(&no_page_breaks));
+        let mut needs_file = poppler.clone();
+        needs_file.needs_file = Some(true);
+        assert_ne!(digest(poppler), digest(&needs_file));
+    }
+
+    #[test]
+    fn pandoc_markdown() -> Result<()> {
+        let pandoc = builtin_spawning_adapters
+            .iter()
+            .find(|e| e.name == "pandoc")
+            .expect("no pandoc adapter");
+        let adapter = pandoc.to_custom_adapter();
+        let mut config = RgaConfig::default();
+        let paths = CommandPaths {
+            filepath_hint: Path::new("doc.docx"),
+            input_path: None,
+            tmpdir: None,
+        };
+        let args = adapter.args(&paths, &config)?;
+        assert!(args.contains(&"--from=docx".to_string()));
+        assert!(args.contains(&"--to=plain".to_string()));
+        config.markdown = true;
+        let args = adapter.args(&paths, &config)?;
+        assert!(args.contains(&"--to=gfm".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn needs_file() -> Result<()> {
+        let adapter = CustomAdapterConfig {
+            name: "cat".to_string(),
+            binary: "cat".to_string(),
+            args: strs(&["--", "{file}"]),
+            needs_file: Some(true),
+            ..Default::default()
+        }
+        .to_adapter();
+        // a file in an archive, which is written to a temporary file
+        let (mut a, d) = simple_adapt_info(
+            Path::new("archive.zip/inner.bin"),
+            Box::new(std::io::Cursor::new(b"hello from a file\n".to_vec())),
+        );
+        a.is_real_file = false;
+        let o = adapted_to_vec(adapter.adapt(a, &d)?)?;
+        assert_eq!(String::from_utf8(o)?, "PREFIX:hello from a file\n");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn pipe_to() -> Result<()> {
+        let adapter = CustomAdapterConfig {
+            name: "upper".to_string(),
+            binary: "cat".to_string(),
+            pipe_to: Some(vec![
+                CustomCommand {
+                    binary: "tr".to_string(),
+                    args: strs(&["a-z", "A-Z"]),
+                },
+                CustomCommand {
+                    binary: "sed".to_string(),
+                    args: strs(&["s/^/{file_extension}: /"]),
+                },
+            ]),
+            ..Default::default()
+        }
+        .to_adapter();
+        let (a, d) = simple_adapt_info(
+            Path::new("file.low"),
+            Box::new(std::io::Cursor::new(b"hello\n".to_vec())),
+        );
+        let o = adapted_to_vec(adapter.adapt(a, &d)?)?;
+        assert_eq!(String::from_utf8(o)?, "PREFIX:low: HELLO\n");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn placeholders_and_env() -> Result<()> {
+        let mut env = BTreeMap::new();
+        env.insert("GREETING".to_string(), "hello from {path}".to_string());
+        let adapter = CustomAdapterConfig {
+            name: "sh".to_string(),
+            binary: "sh".to_string(),
+            args: strs(&[
+                "-c",
+                "echo $GREETING {basename} {ext}; test -d {tmpdir} && echo tmpdir",
+            ]),
+            env: Some(env),
+            ..Default::default()
+        }
+        .to_adapter();
+        let (a, d) = simple_adapt_info(Path::new("dir/file.abc"), Box::new(std::io::empty()));
+        let o = adapted_to_vec(adapter.adapt(a, &d)?)?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            "PREFIX:hello from dir/file.abc file.abc abc\nPREFIX:tmpdir\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn priority() -> Result<()> {
+        use crate::adapters::enabled_adapters;
+        use crate::config::RgaConfig;
+        use crate::matching::{adapter_matches, FileMeta};
+        let mut config = RgaConfig {
+            custom_adapters: Some(vec![CustomAdapterConfig {
+                name: "mydocx".to_string(),
+                binary: "mydocx".to_string(),
+                extensions: strs(&["docx"]),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+        let matched = |config: &RgaConfig| -> Result<Vec<String>> {
+            let matches = adapter_matches(&enabled_adapters(config)?, false)?;
+            Ok(matches(FileMeta {
+                lossy_filename: "a.docx".to_string(),
+                lossy_path: "a.docx".to_string(),
+                mimetype: None,
+                content: None,
+            })
+            .iter()
+            .map(|(adapter, _)| adapter.metadata().name.clone())
+            .collect())
+        };
+        // custom adapters come first
+        assert_eq!(matched(&config)?, strs(&["mydocx", "pandoc"]));
+        config.adapter_priority = strs(&["pandoc"]);
+        assert_eq!(matched(&config)?, strs(&["pandoc", "mydocx"]));
+        config.adapter_priority = strs(&["nope"]);
+        assert!(matched(&config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn offset_magic() -> Result<()> {
+        use crate::matching::{sniff_len, Magic};
+        use std::rc::Rc;
+        let iso = Magic::parse("32769: 43 44 30 30 31")?;
+        assert_eq!(iso.offset, 32769);
+        assert_eq!(iso.end(), 32774);
+        let mut content = vec![0u8; 32769];
+        content.extend_from_slice(b"CD001\x01");
+        assert!(iso.matches(&content));
+        assert!(!iso.matches(&content[..32770]));
+        assert!(!iso.matches(b"CD001"));
+        assert!(Magic::parse("x: 43").is_err());
+        assert!(Magic::parse("70000: 43").is_err());
+
+        let adapter = CustomAdapterConfig {
+            name: "iso".to_string(),
+            binary: "isoinfo".to_string(),
+            magic: Some(strs(&["32769: 43 44 30 30 31", "7f 45 4c 46"])),
+            ..Default::default()
+        };
+        let adapters: Vec<Rc<dyn FileAdapter>> = vec![Rc::new(adapter.to_adapter())];
+        assert_eq!(sniff_len(&adapters, false)?, 32774);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn conditions() -> Result<()> {
+        use crate::config::AdapterConditions;
+        use crate::matching::FileConditions;
+        use std::os::unix::fs::PermissionsExt;
+        let conditions = FileConditions::new(&AdapterConditions {
+            max_size: Some(10),
+            executable: Some(true),
+            paths: strs(&["**/bin/*"]),
+            ..Default::default()
+        })?;
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("bin"))?;
+        let file = dir.path().join("bin/tool");
+        std::fs::write(&file, b"\x7fELF")?;
+        let path = file.to_string_lossy().to_string();
+        assert!(!conditions.allows(&path, Some(&file)));
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o755))?;
+        assert!(conditions.allows(&path, Some(&file)));
+        assert!(!conditions.allows("lib/tool", Some(&file)));
+        std::fs::write(&file, b"\x7fELF and more than 10 bytes")?;
+        assert!(!conditions.allows(&path, Some(&file)));
+        // files in archives are not executable
+        assert!(!conditions.allows("bin/tool", None));
+        Ok(())
+    }
+
+    #[test]
+    fn content_matchers() -> Result<()> {
+        use crate::matching::{adapter_matcher, FileMeta};
+        use std::rc::Rc;
+        let elf = CustomAdapterConfig {
+            name: "elf".to_string(),
+            binary: "readelf".to_string(),
+            magic: Some(strs(&["7f 45 4c 46"])),
+            content_regex: Some("^%!PS-Adobe".to_string()),
+            ..Default::default()
+        };
+        let adapters: Vec<Rc<dyn FileAdapter>> = vec![Rc::new(elf.to_adapter())];
+        let matcher = adapter_matcher(&adapters, false)?;
+        let matched = |content: Option<&[u8]>| {
+            matcher(FileMeta {
+                lossy_filename: "noext".to_string(),
+                lossy_path: "dir/noext".to_string(),
+                mimetype: None,
+                content,
+            })
+            .map(|(_, reason)| reason)
+        };
+        assert!(matches!(
+            matched(Some(&b"\x7fELF\x02\x01"[..])),
+            Some(FileMatcher::Fast(FastFileMatcher::Magic(_)))
+        ));
+        assert!(matches!(
+            matched(Some(&b"%!PS-Adobe-3.0\n"[..])),
+            Some(FileMatcher::Fast(FastFileMatcher::ContentRegex(_)))
+        ));
+        assert!(matched(Some(&b"\x7fEL"[..])).is_none());
+        assert!(matched(Some(&b"#!/bin/sh\n%!PS-Adobe"[..])).is_none());
+        assert!(matched(None).is_none());
+
+        let invalid = CustomAdapterConfig {
+            magic: Some(strs(&["7f 4"])),
+            ..elf
+        };
+        let adapters: Vec<Rc<dyn FileAdapter>> = vec![Rc::new(invalid.to_adapter())];
+        assert!(adapter_matcher(&adapters, false).is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,228 @@
+use super::*;
+use anyhow::*;
+use encoding_rs::WINDOWS_1252;
+use lazy_static::lazy_static;
+use log::*;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["dbf"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "dbf".to_owned(),
+        version: 1,
+        description:
+            "Reads dBase / FoxPro .dbf tables and outputs every record with its field names"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct DbfAdapter;
+
+impl DbfAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(DbfAdapter))
+    }
+}
+impl GetMetadata for DbfAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+struct DbfField {
+    name: String,
+    field_type: u8,
+    length: usize,
+}
+
+/// text in dbf files has no reliable encoding information. Use utf8 if valid, cp1252 otherwise
+fn decode_text(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.to_owned(),
+        Err(_) => WINDOWS_1252
+            .decode_without_bom_handling(bytes)
+            .0
+            .into_owned(),
+    }
+}
+
+fn format_value(field: &DbfField, raw: &[u8]) -> String {
+    let value = decode_text(raw);
+    let value = value.trim_matches(|c: char| c == ' ' || c == '\0');
+    match field.field_type {
+        // date YYYYMMDD
+        b'D' if value.len() == 8 && value.is_ascii() => {
+            format!("{}-{}-{}", &value[0..4], &value[4..6], &value[6..8])
+        }
+        b'L' => match value {
+            "T" | "t" | "Y" | "y" => "true".to_owned(),
+            "F" | "f" | "N" | "n" => "false".to_owned(),
+            _ => "".to_owned(),
+        },
+        // memo fields only contain a block number in the separate .dbt/.fpt file
+        b'M' | b'G' | b'B' => "[memo]".to_owned(),
+        _ => value.to_owned(),
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> usize {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]]) as usize
+}
+
+fn read_fields(inp: &mut dyn Read, header_len: usize) -> Result<Vec<DbfField>> {
+    // the field descriptors (32 bytes each) are followed by a 0x0D terminator. Some writers add a few bytes after that
+    let mut descriptors = vec![0u8; header_len.checked_sub(32).context("header too short")?];
+    inp.read_exact(&mut descriptors)?;
+    let mut fields = vec![];
+    for descriptor in descriptors.chunks(32) {
+        if descriptor[0] == 0x0d || descriptor.len() < 32 {
+            break;
+        }
+        let name_len = descriptor[0..11].iter().position(|&b| b == 0).unwrap_or(11);
+        fields.push(DbfField {
+            name: decode_text(&descriptor[0..name_len]),
+            field_type: descriptor[11],
+            length: descriptor[16] as usize,
+        });
+    }
+    Ok(fields)
+}
+
+impl WritingFileAdapterTrait for DbfAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo { mut inp, .. } = ai;
+        let mut header = [0u8; 32];
+        inp.read_exact(&mut header)
+            .context("dbf header too short")?;
+        let record_count = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let header_len = read_u16(&header, 8);
+        let record_len = read_u16(&header, 10);
+        let fields = read_fields(&mut inp, header_len)?;
+        // one deletion flag byte followed by the field values
+        let fields_len: usize = fields.iter().map(|f| f.length).sum();
+        if record_len < 1 + fields_len {
+            bail!(
+                "invalid dbf record length {} for fields of {} bytes",
+                record_len,
+                fields_len
+            );
+        }
+        debug!(
+            "dbf has {} records with {} fields",
+            record_count,
+            fields.len()
+        );
+
+        let mut record = vec![0u8; record_len];
+        for _ in 0..record_count {
+            if let Err(e) = inp.read_exact(&mut record) {
+                if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                    // truncated file, record count in the header is wrong
+                    break;
+                }
+                return Err(e.into());
+            }
+            // first byte is the deletion flag
+            if record[0] == b'*' {
+                continue;
+            }
+            let mut offset = 1;
+            let mut values = vec![];
+            for field in &fields {
+                let end = std::cmp::min(offset + field.length, record.len());
+                values.push(format!(
+                    "{}={}",
+                    field.name,
+                    format_value(field, &record[offset..end])
+                ));
+                offset = end;
+            }
+            writeln!(oup, "{}", values.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn create_dbf(fields: &[(&str, u8, u8)], records: &[&[&str]]) -> Vec<u8> {
+        let record_len: usize = 1 + fields.iter().map(|f| f.2 as usize).sum::<usize>();
+        let header_len = 32 + 32 * fields.len() + 1;
+        let mut out = vec![0x03, 120, 1, 1];
+        out.extend_from_slice(&(records.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(header_len as u16).to_le_bytes());
+        out.extend_from_slice(&(record_len as u16).to_le_bytes());
+        out.resize(32, 0);
+        for (name, field_type, len) in fields {
+            let mut descriptor = vec![0u8; 32];
+            descriptor[..name.len()].copy_from_slice(name.as_bytes());
+            descriptor[11] = *field_type;
+            descriptor[16] = *len;
+            out.extend(descriptor);
+        }
+        out.push(0x0d);
+        for record in records {
+            out.push(b' ');
+            for ((_, _, len), value) in fields.iter().zip(record.iter()) {
+                out.extend(format!("{:width$}", value, width = *len as usize).bytes());
+            }
+        }
+        out.push(0x1a);
+        out
+    }
+
+    #[test]
+    fn records() -> Result<()> {
+        let dbf = create_dbf(
+            &[
+                ("NAME", b'C', 10),
+                ("FOUNDED", b'D', 8),
+                ("ACTIVE", b'L', 1),
+            ],
+            &[&["Vilnius", "13230125", "T"], &["Kaunas", "13610101", "F"]],
+        );
+        let adapter: Box<dyn FileAdapter> = Box::new(DbfAdapter::new());
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("cities.dbf"),
+            Box::new(std::io::Cursor::new(dbf)),
+        );
+        let buf = adapted_to_vec(adapter.adapt(a, &d)?)?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:NAME=Vilnius, FOUNDED=1323-01-25, ACTIVE=true\nPREFIX:NAME=Kaunas, FOUNDED=1361-01-01, ACTIVE=false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn zero_record_length() -> Result<()> {
+        let mut dbf = create_dbf(&[("NAME", b'C', 10)], &[&["Vilnius"]]);
+        dbf[10] = 0;
+        dbf[11] = 0;
+        let adapter: Box<dyn FileAdapter> = Box::new(DbfAdapter::new());
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("cities.dbf"),
+            Box::new(std::io::Cursor::new(dbf)),
+        );
+        assert!(adapter.adapt(a, &d).is_err());
+        Ok(())
+    }
+}
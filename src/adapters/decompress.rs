@@ -1,21 +1,28 @@
 use super::*;
-use crate::preproc::rga_preproc;
+use crate::adapted_iter::SingleAdaptedFileAsIter;
 use anyhow::Result;
 use lazy_static::lazy_static;
+use lzw::LzwDecoder;
 
 use std::path::PathBuf;
 
-static EXTENSIONS: &[&str] = &["tgz", "tbz", "tbz2", "gz", "bz2", "xz", "zst"];
+static EXTENSIONS: &[&str] = &[
+    "tgz", "tbz", "tbz2", "gz", "bz2", "xz", "zst", "lz4", "br", "lz", "Z", "taz", "lzma",
+];
 static MIME_TYPES: &[&str] = &[
     "application/gzip",
     "application/x-bzip",
     "application/x-xz",
     "application/zstd",
+    "application/x-lz4",
+    "application/x-lzip",
+    "application/x-compress",
+    "application/x-lzma",
 ];
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "decompress".to_owned(),
-        version: 1,
+        version: 3,
         description:
             "Reads compressed file as a stream and runs a different extractor on the contents."
                 .to_owned(),
@@ -48,27 +55,78 @@ impl GetMetadata for DecompressAdapter {
     }
 }
 
-fn decompress_any(reason: &FileMatcher, inp: ReadBox) -> Result<ReadBox> {
+/// lzip files are a small header followed by a raw LZMA stream (with end marker) and a trailer.
+/// Only the first member of multi-member files is decompressed.
+fn lzip_decoder<'a>(mut inp: ReadBox<'a>) -> Result<ReadBox<'a>> {
+    let mut header = [0u8; 6];
+    inp.read_exact(&mut header)?;
+    if &header[0..4] != b"LZIP" || header[4] != 1 {
+        return Err(format_err!("not an lzip (version 1) file"));
+    }
+    let base = 1u32 << (header[5] & 0x1f);
+    let dict_size = base - (base / 16) * (header[5] >> 5) as u32;
+    let mut options = xz2::stream::LzmaOptions::new_preset(6)?;
+    options
+        .dict_size(dict_size)
+        .literal_context_bits(3)
+        .literal_position_bits(0)
+        .position_bits(2);
+    let mut filters = xz2::stream::Filters::new();
+    filters.lzma1(&options);
+    let stream = xz2::stream::Stream::new_raw_decoder(&filters)?;
+    Ok(Box::new(xz2::read::XzDecoder::new_stream(inp, stream)))
+}
+
+/// .lzma files are the "lzma_alone" format of LZMA Utils: a 13 byte header and a raw LZMA stream
+fn lzma_decoder<'a>(inp: ReadBox<'a>) -> Result<ReadBox<'a>> {
+    let stream = xz2::stream::Stream::new_lzma_decoder(u64::MAX)?;
+    Ok(Box::new(xz2::read::XzDecoder::new_stream(inp, stream)))
+}
+
+fn decompress_any<'a>(reason: &FileMatcher, inp: ReadBox<'a>) -> Result<ReadBox<'a>> {
     use FastFileMatcher::*;
     use FileMatcher::*;
-    let gz = |inp: ReadBox| Box::new(flate2::read::MultiGzDecoder::new(inp));
-    let bz2 = |inp: ReadBox| Box::new(bzip2::read::BzDecoder::new(inp));
-    let xz = |inp: ReadBox| Box::new(xz2::read::XzDecoder::new_multi_decoder(inp));
-    let zst = |inp: ReadBox| zstd::stream::read::Decoder::new(inp); // returns result
+    let gz = |inp: ReadBox<'a>| -> ReadBox<'a> { Box::new(flate2::read::MultiGzDecoder::new(inp)) };
+    let bz2 = |inp: ReadBox<'a>| -> ReadBox<'a> { Box::new(bzip2::read::BzDecoder::new(inp)) };
+    let xz = |inp: ReadBox<'a>| -> ReadBox<'a> {
+        Box::new(xz2::read::XzDecoder::new_multi_decoder(inp))
+    };
+    let zst = |inp: ReadBox<'a>| -> Result<ReadBox<'a>> {
+        Ok(Box::new(zstd::stream::read::Decoder::new(inp)?))
+    };
+    let lz4 = |inp: ReadBox<'a>| -> Result<ReadBox<'a>> { Ok(Box::new(lz4::Decoder::new(inp)?)) };
+    let br = |inp: ReadBox<'a>| -> ReadBox<'a> { Box::new(brotli::Decompressor::new(inp, 4096)) };
+    let z = |inp: ReadBox<'a>| -> Result<ReadBox<'a>> { Ok(Box::new(LzwDecoder::new(inp)?)) };
 
     Ok(match reason {
         Fast(FileExtension(ext)) => match ext.as_ref() {
             "tgz" | "gz" => gz(inp),
             "tbz" | "tbz2" | "bz2" => bz2(inp),
             "xz" => xz(inp),
-            "zst" => Box::new(zst(inp)?),
+            "zst" => zst(inp)?,
+            "lz4" => lz4(inp)?,
+            "br" => br(inp),
+            "lz" => lzip_decoder(inp)?,
+            "Z" | "taz" => z(inp)?,
+            "lzma" => lzma_decoder(inp)?,
             ext => Err(format_err!("don't know how to decompress {}", ext))?,
         },
+        // the builtin signatures (e.g. of files without an extension or stdin)
+        Fast(Magic(hex)) if hex == "1f 8b" => gz(inp),
+        Fast(Magic(hex)) if hex == "42 5a 68" => bz2(inp),
+        Fast(Magic(hex)) if hex == "fd 37 7a 58 5a 00" => xz(inp),
+        Fast(Magic(hex)) if hex == "28 b5 2f fd" => zst(inp)?,
+        Fast(PathGlob(glob)) => Err(format_err!("don't know how to decompress {}", glob))?,
+        Fast(matcher) => Err(format_err!("don't know how to decompress {:?}", matcher))?,
         MimeType(mime) => match mime.as_ref() {
             "application/gzip" => gz(inp),
             "application/x-bzip" => bz2(inp),
             "application/x-xz" => xz(inp),
-            "application/zstd" => Box::new(zst(inp)?),
+            "application/zstd" => zst(inp)?,
+            "application/x-lz4" => lz4(inp)?,
+            "application/x-lzip" => lzip_decoder(inp)?,
+            "application/x-compress" => z(inp)?,
+            "application/x-lzma" => lzma_decoder(inp)?,
             mime => Err(format_err!("don't know how to decompress mime {}", mime))?,
         },
     })
@@ -83,19 +141,24 @@ fn get_inner_filename(filename: &Path) -> PathBuf {
         .expect("no filename given?")
         .to_string_lossy();
     let new_extension = match extension.to_owned().as_ref() {
-        "tgz" | "tbz" | "tbz2" => ".tar",
+        "tgz" | "tbz" | "tbz2" | "taz" => ".tar",
         _other => "",
     };
     filename.with_file_name(format!("{}{}", stem, new_extension))
 }
 
 impl FileAdapter for DecompressAdapter {
-    fn adapt(&self, ai: AdaptInfo, detection_reason: &FileMatcher) -> Result<ReadBox> {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
         let AdaptInfo {
             filepath_hint,
             inp,
             line_prefix,
             archive_recursion_depth,
+            postprocess,
             config,
             ..
         } = ai;
@@ -106,9 +169,14 @@ impl FileAdapter for DecompressAdapter {
             archive_recursion_depth: archive_recursion_depth + 1,
             inp: decompress_any(detection_reason, inp)?,
             line_prefix,
-            config: config.clone(),
+            postprocess,
+            config,
         };
-        rga_preproc(ai2)
+        Ok(Box::new(SingleAdaptedFileAsIter::new(ai2)))
+    }
+
+    fn passthrough_name(&self, filepath: &Path) -> Option<PathBuf> {
+        Some(get_inner_filename(filepath))
     }
 }
 
@@ -127,6 +195,12 @@ mod tests {
             ("hi/test.tbz", "hi/test.tar"),
             ("hi/test.hi.bz2", "hi/test.hi"),
             ("hello.tar.gz", "hello.tar"),
+            ("hello.tar.lz4", "hello.tar"),
+            ("hello.tar.br", "hello.tar"),
+            ("hello.tar.lz", "hello.tar"),
+            ("hello.tar.Z", "hello.tar"),
+            ("hello.taz", "hello.tar"),
+            ("hello.tar.lzma", "hello.tar"),
         ] {
             assert_eq!(get_inner_filename(&PathBuf::from(a)), PathBuf::from(*b));
         }
@@ -139,10 +213,9 @@ mod tests {
         let filepath = test_data_dir().join("hello.gz");
 
         let (a, d) = simple_adapt_info(&filepath, Box::new(File::open(&filepath)?));
-        let mut r = adapter.adapt(a, &d)?;
-        let mut o = Vec::new();
-        r.read_to_end(&mut o)?;
-        assert_eq!(String::from_utf8(o)?, "hello\n");
+        let r = adapter.adapt(a, &d)?;
+        let o = adapted_to_vec(r)?;
+        assert_eq!(String::from_utf8(o)?, "PREFIX:hello\nPREFIX:\n");
         Ok(())
     }
 
@@ -153,17 +226,174 @@ mod tests {
         let filepath = test_data_dir().join("short.pdf.gz");
 
         let (a, d) = simple_adapt_info(&filepath, Box::new(File::open(&filepath)?));
-        let mut r = adapter.adapt(a, &d)?;
-        let mut o = Vec::new();
-        r.read_to_end(&mut o)?;
+        let r = adapter.adapt(a, &d)?;
+        let o = adapted_to_vec(r)?;
         assert_eq!(
             String::from_utf8(o)?,
-            "hello world
-this is just a test.
+            "PREFIX:hello world
+PREFIX:this is just a test.
+PREFIX:
+PREFIX:1
+PREFIX:
+PREFIX:\u{c}
+"
+        );
+        Ok(())
+    }
 
-1
+    #[test]
+    fn gz_by_signature() -> Result<()> {
+        use crate::matching::{adapter_matcher, FileMeta};
+        use anyhow::Context;
+        let data = std::fs::read(test_data_dir().join("hello.gz"))?;
+        let adapters = get_adapters_filtered(None, &Vec::<String>::new())?;
+        let matcher = adapter_matcher(&adapters, false)?;
+        let (adapter, reason) = matcher(FileMeta {
+            lossy_filename: "hello".to_string(),
+            lossy_path: "dir/hello".to_string(),
+            mimetype: None,
+            content: Some(&data[..]),
+        })
+        .context("no adapter for gzip without an extension")?;
+        assert_eq!(adapter.metadata().name, "decompress");
+        let (a, _) = simple_adapt_info(
+            &PathBuf::from("dir/hello"),
+            Box::new(std::io::Cursor::new(data)),
+        );
+        let o = adapted_to_vec(DecompressAdapter.adapt(a, &reason)?)?;
+        assert_eq!(String::from_utf8(o)?, "PREFIX:hello\nPREFIX:\n");
+        // only without a matching extension
+        let (adapter, _) = matcher(FileMeta {
+            lossy_filename: "hello.zip".to_string(),
+            lossy_path: "dir/hello.zip".to_string(),
+            mimetype: None,
+            content: Some(&b"\x1f\x8b"[..]),
+        })
+        .context("no adapter for zip")?;
+        assert_eq!(adapter.metadata().name, "zip");
+        Ok(())
+    }
+
+    #[test]
+    fn tar_bz2_from_stdin() -> Result<()> {
+        use crate::matching::{adapter_matcher, FileMeta};
+        use anyhow::Context;
+        let data = std::fs::read(test_data_dir().join("../tar/test.tar.bz2"))?;
+        let adapters = get_adapters_filtered(None, &Vec::<String>::new())?;
+        let matcher = adapter_matcher(&adapters, false)?;
+        fn stdin(content: &[u8]) -> FileMeta {
+            FileMeta {
+                lossy_filename: "stdin".to_string(),
+                lossy_path: "stdin".to_string(),
+                mimetype: None,
+                content: Some(content),
+            }
+        }
+        let (adapter, reason) = matcher(stdin(&data[..])).context("no adapter for tar.bz2")?;
+        assert_eq!(adapter.metadata().name, "decompress");
+        let mut tar = Vec::new();
+        decompress_any(&reason, Box::new(&data[..]))?.read_to_end(&mut tar)?;
+        let (adapter, _) = matcher(stdin(&tar[..])).context("no adapter for tar")?;
+        assert_eq!(adapter.metadata().name, "tar");
+        Ok(())
+    }
 
-\u{c}"
+    #[test]
+    fn chain() -> Result<()> {
+        use crate::matching::{adapter_chain, adapter_matcher};
+        let adapters = get_adapters_filtered(None, &vec!["+csv", "gron"])?;
+        let names = |path: &str| -> Result<Vec<String>> {
+            let chain = adapter_chain(adapter_matcher(&adapters, false)?, Path::new(path));
+            Ok(chain
+                .iter()
+                .map(|(path, adapter, _)| format!("{}:{}", adapter.metadata().name, path.display()))
+                .collect())
+        };
+        assert_eq!(
+            names("foo.tar.zst")?,
+            vec!["decompress:foo.tar.zst", "tar:foo.tar"]
+        );
+        assert_eq!(
+            names("foo.csv.gz")?,
+            vec!["decompress:foo.csv.gz", "csv:foo.csv"]
+        );
+        assert_eq!(
+            names("foo.json.br")?,
+            vec!["decompress:foo.json.br", "gron:foo.json"]
+        );
+        assert_eq!(
+            names("foo.txt.gz.xz")?,
+            vec!["decompress:foo.txt.gz.xz", "decompress:foo.txt.gz"]
+        );
+        assert_eq!(names("foo.tgz")?, vec!["decompress:foo.tgz", "tar:foo.tar"]);
+        assert_eq!(names("foo.txt")?, Vec::<String>::new());
+        Ok(())
+    }
+
+    fn decompress_str(filename: &str, data: Vec<u8>) -> Result<String> {
+        let adapter = DecompressAdapter;
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from(filename),
+            Box::new(std::io::Cursor::new(data)),
+        );
+        let o = adapted_to_vec(adapter.adapt(a, &d)?)?;
+        Ok(String::from_utf8(o)?)
+    }
+
+    #[test]
+    fn lz4_brotli() -> Result<()> {
+        use std::io::Write;
+        let mut lz4 = lz4::EncoderBuilder::new().build(Vec::new())?;
+        lz4.write_all(b"hello lz4")?;
+        let (lz4, res) = lz4.finish();
+        res?;
+        assert_eq!(decompress_str("hello.lz4", lz4)?, "PREFIX:hello lz4\n");
+
+        let mut br = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+        br.write_all(b"hello brotli")?;
+        assert_eq!(
+            decompress_str("hello.br", br.into_inner())?,
+            "PREFIX:hello brotli\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lzip() -> Result<()> {
+        use std::io::Write;
+        // header with a 64 KiB dictionary, raw lzma stream, dummy trailer
+        let mut lz = b"LZIP\x01\x10".to_vec();
+        let mut options = xz2::stream::LzmaOptions::new_preset(6)?;
+        options
+            .dict_size(1 << 16)
+            .literal_context_bits(3)
+            .literal_position_bits(0)
+            .position_bits(2);
+        let mut filters = xz2::stream::Filters::new();
+        filters.lzma1(&options);
+        let stream = xz2::stream::Stream::new_raw_encoder(&filters)?;
+        let mut enc = xz2::write::XzEncoder::new_stream(Vec::new(), stream);
+        enc.write_all(b"hello lzip")?;
+        lz.extend(enc.finish()?);
+        lz.extend(&[0u8; 20]);
+        assert_eq!(decompress_str("hello.lz", lz)?, "PREFIX:hello lzip\n");
+        Ok(())
+    }
+
+    #[test]
+    fn compress_lzma() -> Result<()> {
+        use std::io::Write;
+        // compress(1) output
+        let z = b"\x1f\x9d\x90\x68\xca\xb0\x61\xf3\x06";
+        assert_eq!(decompress_str("hello.Z", z.to_vec())?, "PREFIX:hello\n");
+
+        let options = xz2::stream::LzmaOptions::new_preset(6)?;
+        let stream = xz2::stream::Stream::new_lzma_encoder(&options)?;
+        let mut enc = xz2::write::XzEncoder::new_stream(Vec::new(), stream);
+        enc.write_all(b"hello lzma")?;
+        assert_eq!(
+            decompress_str("hello.lzma", enc.finish()?)?,
+            "PREFIX:hello lzma\n"
         );
         Ok(())
     }
@@ -0,0 +1,178 @@
+use super::sandbox::sandboxed;
+use super::spawning::{input_as_file, map_exe_error, CommandPaths};
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+use std::process::Command;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["dcm", "dicom"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "dicom".to_owned(),
+        version: 1,
+        description: "Uses dcmdump (dcmtk) to output the tags of DICOM medical images as TagName=value lines. See --rga-dicom-redact to hide patient information".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/dicom".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+/// tags outside of the patient group (0010) that identify people
+static IDENTIFYING_TAGS: &[&str] = &[
+    "0008,0050", // AccessionNumber
+    "0008,0080", // InstitutionName
+    "0008,0081", // InstitutionAddress
+    "0008,0090", // ReferringPhysicianName
+    "0008,1048", // PhysiciansOfRecord
+    "0008,1050", // PerformingPhysicianName
+    "0008,1060", // NameOfPhysiciansReadingStudy
+    "0008,1070", // OperatorsName
+    "0032,1032", // RequestingPhysician
+];
+
+#[derive(Default, Clone)]
+pub struct DicomAdapter;
+
+impl DicomAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(DicomAdapter))
+    }
+}
+impl GetMetadata for DicomAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// convert a dcmdump line like `(0010,0010) PN [Doe^Jane]   #   8, 1 PatientName` to `PatientName=Doe^Jane`.
+/// Returns None for sequence / item delimiters and elements without (loaded) value
+fn format_element(line: &str, redact: bool) -> Option<String> {
+    let line = line.trim_start();
+    if !line.starts_with('(') || line.get(10..11) != Some(")") {
+        return None;
+    }
+    let tag = &line[1..10];
+    // items and sequence delimiters
+    if tag.starts_with("fffe") {
+        return None;
+    }
+    let hash = line.rfind('#')?;
+    // `#   8, 1 PatientName`: byte length, value multiplicity, name
+    let name = line[hash + 1..].split_whitespace().nth(2)?;
+    let value = line[11..hash].trim();
+    // skip the VR
+    let value = value.splitn(2, ' ').nth(1).unwrap_or("").trim();
+    let value = if value.starts_with('[') {
+        value.trim_start_matches('[').trim_end_matches(']')
+    } else if value.starts_with('(') {
+        // "(no value available)", "(not loaded)", "(Sequence with ...)"
+        return None;
+    } else {
+        // known UIDs are shown with their name as `=CTImageStorage`
+        value.trim_start_matches('=')
+    };
+    if value.is_empty() {
+        return None;
+    }
+    if redact && (tag.starts_with("0010,") || IDENTIFYING_TAGS.contains(&tag)) {
+        return Some(format!("{}=[redacted]", name));
+    }
+    Some(format!("{}={}", name, value))
+}
+
+impl WritingFileAdapterTrait for DicomAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            mut inp,
+            config,
+            ..
+        } = ai;
+        let (inp_fname, _tmpfile) = input_as_file(&filepath_hint, is_real_file, &mut inp)?;
+        // -M: don't load long values (pixel data)
+        let mut cmd = Command::new("dcmdump");
+        cmd.arg("-M").arg(&inp_fname);
+        let paths = CommandPaths {
+            filepath_hint: &filepath_hint,
+            input_path: Some(&inp_fname),
+            tmpdir: None,
+        };
+        let output = sandboxed(cmd, &paths, &config)?
+            .output()
+            .map_err(|e| map_exe_error(e, "dcmdump", "Make sure you have dcmtk installed."))?;
+        if !output.status.success() {
+            return Err(format_err!(
+                "dcmdump failed: {:?}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if let Some(element) = format_element(line, config.dicom.redact) {
+                writeln!(oup, "{}", element)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn cache_key_extra(&self, _filepath: &Path, config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        // the patient information is hidden with --rga-dicom-redact
+        Ok(Some(b"redact".to_vec()).filter(|_| config.dicom.redact))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn elements() {
+        let dump = "# Dicom-File-Format\n\
+            (0008,0020) DA [20200131]                               #   8, 1 StudyDate\n\
+            (0008,0080) LO [General Hospital]                       #  16, 1 InstitutionName\n\
+            (0008,1110) SQ (Sequence with explicit length #=1)      #  22, 1 ReferencedStudySequence\n\
+            \x20 (fffe,e000) na (Item with explicit length #=1)      #  14, 1 Item\n\
+            \x20   (0008,1150) UI =CTImageStorage                    #   6, 1 ReferencedSOPClassUID\n\
+            (0010,0010) PN [Doe^Jane]                               #   8, 1 PatientName\n\
+            (0010,0040) CS (no value available)                     #   0, 0 PatientSex\n\
+            (7fe0,0010) OW (not loaded)                             # 524288, 1 PixelData\n";
+        let lines = |redact| -> Vec<String> {
+            dump.lines()
+                .filter_map(|l| format_element(l, redact))
+                .collect()
+        };
+        assert_eq!(
+            lines(false),
+            vec![
+                "StudyDate=20200131",
+                "InstitutionName=General Hospital",
+                "ReferencedSOPClassUID=CTImageStorage",
+                "PatientName=Doe^Jane",
+            ]
+        );
+        assert_eq!(
+            lines(true),
+            vec![
+                "StudyDate=20200131",
+                "InstitutionName=[redacted]",
+                "ReferencedSOPClassUID=CTImageStorage",
+                "PatientName=[redacted]",
+            ]
+        );
+    }
+}
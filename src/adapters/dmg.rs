@@ -0,0 +1,48 @@
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+
+// the partitions inside of a dmg are extracted as .hfs / .apfs files by some 7z versions, so handle those as well
+static EXTENSIONS: &[&str] = &["dmg", "hfs", "hfsx", "apfs"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "dmg".to_owned(),
+        version: 2,
+        description: "Extracts macOS disk images (.dmg, HFS+ and APFS) using 7z and recurses into the contained files".to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-apple-diskimage".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct DmgAdapter;
+
+impl DmgAdapter {
+    pub fn new() -> DmgAdapter {
+        DmgAdapter
+    }
+}
+impl GetMetadata for DmgAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+impl FileAdapter for DmgAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        sevenzip::extract(ai, None)
+    }
+}
@@ -0,0 +1,254 @@
+//! Text extraction from binary Word 97-2003 documents, see [MS-DOC] "Retrieving Text".
+//! The text is stored in the WordDocument stream as pieces described by the piece table in the table stream.
+
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+use spawning::input_as_file;
+use std::fs::File;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["doc", "dot"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "doc".to_owned(),
+        version: 1,
+        description: "Extracts the text of legacy binary Word documents (Word 97-2003 .doc)"
+            .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/msword".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct DocAdapter;
+
+impl DocAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(DocAdapter))
+    }
+}
+impl GetMetadata for DocAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+pub fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| format_err!("unexpected end of document at {}", offset))
+}
+
+pub fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| format_err!("unexpected end of document at {}", offset))
+}
+
+/// whether the piece table is in the 1Table stream (otherwise 0Table)
+fn uses_1table(word: &[u8]) -> Result<bool> {
+    if u16_at(word, 0)? != 0xA5EC {
+        return Err(format_err!("not a Word document"));
+    }
+    let flags = u16_at(word, 0x0A)?;
+    if flags & 0x0100 != 0 {
+        return Err(format_err!("document is encrypted"));
+    }
+    Ok(flags & 0x0200 != 0)
+}
+
+/// offset and length of the Clx (which contains the piece table) in the table stream
+fn clx_location(word: &[u8]) -> Result<(usize, usize)> {
+    let nfib = u16_at(word, 2)?;
+    if nfib < 0x00C1 {
+        return Err(format_err!(
+            "Word 6 / 95 documents (nFib {:#x}) are not supported",
+            nfib
+        ));
+    }
+    // FibBase, then variable length arrays of u16, u32 and (fc, lcb) u32 pairs
+    let csw = u16_at(word, 32)? as usize;
+    let cslw_offset = 34 + csw * 2;
+    let cslw = u16_at(word, cslw_offset)? as usize;
+    let fc_lcb_offset = cslw_offset + 2 + cslw * 4 + 2;
+    let fc_clx = u32_at(word, fc_lcb_offset + 66 * 4)? as usize;
+    let lcb_clx = u32_at(word, fc_lcb_offset + 67 * 4)? as usize;
+    Ok((fc_clx, lcb_clx))
+}
+
+/// map the special characters of the document text
+fn push_char(out: &mut String, field_depth: &mut Vec<bool>, c: char) {
+    match c {
+        // field begin: the field code until the separator is hidden
+        '\u{13}' => field_depth.push(true),
+        '\u{14}' => {
+            if let Some(in_code) = field_depth.last_mut() {
+                *in_code = false;
+            }
+        }
+        '\u{15}' => {
+            field_depth.pop();
+        }
+        _ if field_depth.last() == Some(&true) => {}
+        // paragraph end, line break, page / section break
+        '\r' | '\u{0b}' | '\u{0c}' => out.push('\n'),
+        // table cell / row end
+        '\u{07}' => out.push('\t'),
+        '\t' => out.push('\t'),
+        '\u{1e}' => out.push('-'),
+        c if (c as u32) < 0x20 => {}
+        c => out.push(c),
+    }
+}
+
+/// the text of a document, given the WordDocument and table streams
+pub fn doc_text(word: &[u8], table: &[u8]) -> Result<String> {
+    let (fc_clx, lcb_clx) = clx_location(word)?;
+    let clx = table
+        .get(fc_clx..fc_clx + lcb_clx)
+        .ok_or_else(|| format_err!("invalid piece table location"))?;
+    // skip the Prc (formatting) entries before the Pcdt
+    let mut pos = 0;
+    while clx.get(pos) == Some(&0x01) {
+        let cb_grpprl = u16_at(clx, pos + 1)? as usize;
+        pos += 3 + cb_grpprl;
+    }
+    if clx.get(pos) != Some(&0x02) {
+        return Err(format_err!("invalid piece table"));
+    }
+    let lcb = u32_at(clx, pos + 1)? as usize;
+    let plc = clx
+        .get(pos + 5..pos + 5 + lcb)
+        .ok_or_else(|| format_err!("invalid piece table size"))?;
+    // n + 1 character positions, then n piece descriptors of 8 bytes
+    let n = (lcb.saturating_sub(4)) / 12;
+    let mut out = String::new();
+    let mut field_depth = vec![];
+    for i in 0..n {
+        let cp_start = u32_at(plc, i * 4)? as usize;
+        let cp_end = u32_at(plc, (i + 1) * 4)? as usize;
+        let len = cp_end.saturating_sub(cp_start);
+        let fc = u32_at(plc, (n + 1) * 4 + i * 8 + 2)?;
+        if fc & 0x4000_0000 != 0 {
+            // "compressed": one byte per character, cp1252
+            let start = ((fc & 0x3FFF_FFFF) / 2) as usize;
+            let bytes = word
+                .get(start..start + len)
+                .ok_or_else(|| format_err!("piece out of bounds"))?;
+            let (text, _, _) = encoding_rs::WINDOWS_1252.decode(bytes);
+            for c in text.chars() {
+                push_char(&mut out, &mut field_depth, c);
+            }
+        } else {
+            let start = fc as usize;
+            let bytes = word
+                .get(start..start + len * 2)
+                .ok_or_else(|| format_err!("piece out of bounds"))?;
+            let units = bytes.chunks(2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+            for c in std::char::decode_utf16(units) {
+                push_char(
+                    &mut out,
+                    &mut field_depth,
+                    c.unwrap_or(std::char::REPLACEMENT_CHARACTER),
+                );
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn read_stream<F: Read + std::io::Seek>(
+    file: &mut cfb::CompoundFile<F>,
+    name: &str,
+) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    file.open_stream(name)
+        .with_context(|| format!("could not open stream {}", name))?
+        .read_to_end(&mut data)?;
+    Ok(data)
+}
+
+impl WritingFileAdapterTrait for DocAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            mut inp,
+            ..
+        } = ai;
+        let (path, _tmp) = input_as_file(&filepath_hint, is_real_file, &mut inp)?;
+        let mut file = cfb::CompoundFile::open(File::open(&path)?)?;
+        let word = read_stream(&mut file, "/WordDocument")?;
+        let table_name = if uses_1table(&word)? {
+            "/1Table"
+        } else {
+            "/0Table"
+        };
+        let table = read_stream(&mut file, table_name)?;
+        oup.write_all(doc_text(&word, &table)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pieces() -> Result<()> {
+        // minimal FIB: csw = 14, cslw = 22, 93 fc/lcb pairs
+        let mut word = vec![0u8; 4096];
+        word[0..2].copy_from_slice(&0xA5ECu16.to_le_bytes());
+        word[2..4].copy_from_slice(&0x00C1u16.to_le_bytes());
+        word[0x0A..0x0C].copy_from_slice(&0x0200u16.to_le_bytes());
+        word[32..34].copy_from_slice(&14u16.to_le_bytes());
+        word[62..64].copy_from_slice(&22u16.to_le_bytes());
+        word[152..154].copy_from_slice(&93u16.to_le_bytes());
+
+        // cp1252 piece with a hyperlink field, utf-16 piece
+        let compressed = b"Hello \x13 HYPERLINK \"x\" \x14world\x15\r";
+        word[1024..1024 + compressed.len()].copy_from_slice(compressed);
+        let unicode: Vec<u8> = "Ünïcode\u{7}cell\r"
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes().to_vec())
+            .collect();
+        word[2048..2048 + unicode.len()].copy_from_slice(&unicode);
+
+        let cps = [
+            0u32,
+            compressed.len() as u32,
+            (compressed.len() + 13) as u32,
+        ];
+        let mut plc = vec![];
+        for cp in &cps {
+            plc.extend(&cp.to_le_bytes());
+        }
+        for fc in &[(1024u32 * 2) | 0x4000_0000, 2048] {
+            plc.extend(&[0, 0]);
+            plc.extend(&fc.to_le_bytes());
+            plc.extend(&[0, 0]);
+        }
+        let mut table = vec![0x02];
+        table.extend(&(plc.len() as u32).to_le_bytes());
+        table.extend(&plc);
+        word[154 + 66 * 4..154 + 67 * 4].copy_from_slice(&0u32.to_le_bytes());
+        word[154 + 67 * 4..154 + 68 * 4].copy_from_slice(&(table.len() as u32).to_le_bytes());
+
+        assert!(uses_1table(&word)?);
+        assert_eq!(doc_text(&word, &table)?, "Hello world\nÜnïcode\tcell\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+use super::*;
+use crate::adapted_iter::AdaptedFilesIter;
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use serde_json::Value;
+use spawning::input_as_file;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Seek, SeekFrom};
+
+static EXTENSIONS: &[&str] = &["tar"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "docker".to_owned(),
+        version: 1,
+        description: "Reads container images saved with `docker save` (or in OCI layout), outputs the manifest and image config and recurses into the files of the image (after applying all layers). Disabled by default since it matches all .tar files, enable it with --rga-adapters=+docker".to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct DockerAdapter;
+
+impl DockerAdapter {
+    pub fn new() -> DockerAdapter {
+        DockerAdapter
+    }
+}
+impl GetMetadata for DockerAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn clean_path(path: &str) -> String {
+    path.trim_start_matches("./")
+        .trim_start_matches('/')
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// the members of the image tarball: path -> (offset, size)
+fn index_tar(file: &File) -> Result<HashMap<String, (u64, u64)>> {
+    let mut index = HashMap::new();
+    let mut archive = ::tar::Archive::new(file);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = clean_path(&entry.path()?.to_string_lossy());
+        index.insert(path, (entry.raw_file_position(), entry.size()));
+    }
+    Ok(index)
+}
+
+/// `size` bytes of the file starting at `offset`
+fn section(file: &File, offset: u64, size: u64) -> Result<std::io::Take<File>> {
+    let mut file = file.try_clone()?;
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(file.take(size))
+}
+
+/// a member of the image tarball, decompressed if it is gzipped (layers in OCI images usually are)
+fn open_member(file: &File, (offset, size): (u64, u64)) -> Result<Box<dyn Read>> {
+    let mut inp = BufReader::new(section(file, offset, size)?);
+    if inp.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(flate2::bufread::GzDecoder::new(inp)))
+    } else {
+        Ok(Box::new(inp))
+    }
+}
+
+struct Image {
+    /// (name in the tarball, content) of the manifest and config
+    documents: Vec<(String, Vec<u8>)>,
+    /// names of the layer tarballs, lowest first
+    layers: Vec<String>,
+}
+
+fn read_image(file: &File, index: &HashMap<String, (u64, u64)>) -> Result<Image> {
+    let read = |name: &str| -> Result<Vec<u8>> {
+        let member = *index
+            .get(name)
+            .with_context(|| format!("{} is missing in the image", name))?;
+        let mut data = Vec::new();
+        open_member(file, member)?.read_to_end(&mut data)?;
+        Ok(data)
+    };
+    let blob = |digest: &Value| -> Result<String> {
+        let digest = digest.as_str().context("invalid digest")?;
+        Ok(format!("blobs/{}", digest.replacen(':', "/", 1)))
+    };
+    let mut documents = vec![];
+    let mut layers = vec![];
+    if index.contains_key("manifest.json") {
+        // docker save
+        let manifest = read("manifest.json")?;
+        let json: Value = serde_json::from_slice(&manifest)?;
+        let images = json.as_array().context("invalid manifest.json")?;
+        if images.len() > 1 {
+            debug!(
+                "image tarball contains {} images, only reading the first",
+                images.len()
+            );
+        }
+        let image = images.get(0).context("manifest.json contains no images")?;
+        documents.push(("manifest.json".to_string(), manifest));
+        if let Some(config) = image["Config"].as_str() {
+            documents.push(("config.json".to_string(), read(config)?));
+        }
+        for layer in image["Layers"]
+            .as_array()
+            .context("invalid manifest.json")?
+        {
+            layers.push(clean_path(layer.as_str().context("invalid layer")?));
+        }
+    } else if index.contains_key("index.json") {
+        // OCI image layout
+        let oci_index: Value = serde_json::from_slice(&read("index.json")?)?;
+        let manifest_name = blob(&oci_index["manifests"][0]["digest"])?;
+        let manifest = read(&manifest_name)?;
+        let json: Value = serde_json::from_slice(&manifest)?;
+        documents.push(("manifest.json".to_string(), manifest));
+        documents.push((
+            "config.json".to_string(),
+            read(&blob(&json["config"]["digest"])?)?,
+        ));
+        for layer in json["layers"]
+            .as_array()
+            .context("invalid image manifest")?
+        {
+            layers.push(blob(&layer["digest"])?);
+        }
+    } else {
+        return Err(format_err!(
+            "not a container image (neither manifest.json nor index.json found)"
+        ));
+    }
+    Ok(Image { documents, layers })
+}
+
+/// deleted files in a layer are marked with a `.wh.<name>` file next to them,
+/// `.wh..wh..opq` marks a directory as replaced
+#[derive(Default)]
+struct Whiteouts {
+    paths: HashSet<String>,
+    dirs: Vec<String>,
+}
+
+impl Whiteouts {
+    fn add(&mut self, path: &str) -> bool {
+        let (dir, name) = match path.rfind('/') {
+            Some(i) => (&path[..i + 1], &path[i + 1..]),
+            None => ("", path),
+        };
+        if name == ".wh..wh..opq" {
+            self.dirs.push(dir.to_string());
+        } else if name.starts_with(".wh.") {
+            self.paths.insert(format!("{}{}", dir, &name[4..]));
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn extend(&mut self, other: Whiteouts) {
+        self.paths.extend(other.paths);
+        self.dirs.extend(other.dirs);
+    }
+
+    fn hides(&self, path: &str) -> bool {
+        if self.dirs.iter().any(|d| path.starts_with(d.as_str())) {
+            return true;
+        }
+        // a deleted directory hides everything below it
+        let mut prefix = path;
+        loop {
+            if self.paths.contains(prefix) {
+                return true;
+            }
+            match prefix.rfind('/') {
+                Some(i) => prefix = &prefix[..i],
+                None => return false,
+            }
+        }
+    }
+}
+
+/// the files that are visible in the final image, per layer
+fn visible_files(
+    file: &File,
+    index: &HashMap<String, (u64, u64)>,
+    layers: &[String],
+) -> Result<Vec<HashSet<String>>> {
+    let mut visible = vec![HashSet::new(); layers.len()];
+    let mut seen = HashSet::new();
+    let mut whiteouts = Whiteouts::default();
+    // upper layers take precedence
+    for (i, layer) in layers.iter().enumerate().rev() {
+        let member = *index
+            .get(layer)
+            .with_context(|| format!("layer {} is missing in the image", layer))?;
+        // whiteouts only apply to the layers below
+        let mut layer_whiteouts = Whiteouts::default();
+        let mut archive = ::tar::Archive::new(open_member(file, member)?);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = clean_path(&entry.path()?.to_string_lossy());
+            if layer_whiteouts.add(&path) || !entry.header().entry_type().is_file() {
+                continue;
+            }
+            if whiteouts.hides(&path) || !seen.insert(path.clone()) {
+                continue;
+            }
+            visible[i].insert(path);
+        }
+        whiteouts.extend(layer_whiteouts);
+    }
+    Ok(visible)
+}
+
+/// a layer tarball that can be read at any position, to read its files one at a time
+struct Layer {
+    /// the image tarball, or the decompressed layer if it is gzipped
+    file: File,
+    _tmp: Option<tempfile::NamedTempFile>,
+    /// the visible files: (path, offset in file, size)
+    files: std::vec::IntoIter<(String, u64, u64)>,
+}
+
+struct DockerIter {
+    file: File,
+    index: HashMap<String, (u64, u64)>,
+    documents: std::vec::IntoIter<(String, Vec<u8>)>,
+    layers: std::vec::IntoIter<(String, HashSet<String>)>,
+    current: Option<Layer>,
+    line_prefix: String,
+    archive_recursion_depth: i32,
+    postprocess: bool,
+    config: RgaConfig,
+    _tmp: Option<tempfile::NamedTempFile>,
+}
+
+impl DockerIter {
+    /// find the visible files in the layer. Uncompressed layers are read in place,
+    /// gzipped ones are decompressed into one temporary file (up to the decompression limit)
+    fn open_layer(&self, layer: &str, files: &HashSet<String>) -> Result<Layer> {
+        let (offset, size) = *self.index.get(layer).context("layer disappeared")?;
+        let mut member = BufReader::new(section(&self.file, offset, size)?);
+        let (file, offset, size, tmp) = if member.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+            let mut tmp = tempfile::Builder::new().prefix("rga-docker-").tempfile()?;
+            let mut decompressed = self
+                .config
+                .expansion
+                .limit(Box::new(flate2::bufread::GzDecoder::new(member)));
+            let size = std::io::copy(&mut decompressed, &mut tmp)?;
+            (tmp.reopen()?, 0, size, Some(tmp))
+        } else {
+            (self.file.try_clone()?, offset, size, None)
+        };
+        let mut visible = vec![];
+        let mut archive = ::tar::Archive::new(section(&file, offset, size)?);
+        let read = (|| -> Result<()> {
+            for entry in archive.entries()? {
+                let entry = entry?;
+                let path = clean_path(&entry.path()?.to_string_lossy());
+                if files.contains(&path) {
+                    visible.push((path, offset + entry.raw_file_position(), entry.size()));
+                }
+            }
+            Ok(())
+        })();
+        match read {
+            // the layer was cut off, the files before that are still read (the output ends with the truncation marker)
+            Err(e) if self.config.expansion.exceeded() => {
+                debug!(
+                    "layer {} after reaching the decompression limit: {}",
+                    layer, e
+                )
+            }
+            read => read?,
+        }
+        Ok(Layer {
+            file,
+            _tmp: tmp,
+            files: visible.into_iter(),
+        })
+    }
+}
+
+impl AdaptedFilesIter for DockerIter {
+    fn next<'a>(&'a mut self) -> Option<AdaptInfo<'a>> {
+        if let Some((name, content)) = self.documents.next() {
+            return Some(AdaptInfo {
+                line_prefix: self.config.entry_prefix(&self.line_prefix, &name),
+                filepath_hint: PathBuf::from(name),
+                is_real_file: false,
+                archive_recursion_depth: self.archive_recursion_depth + 1,
+                inp: Box::new(Cursor::new(content)),
+                postprocess: self.postprocess,
+                config: self.config.clone(),
+            });
+        }
+        loop {
+            if let Some(layer) = &mut self.current {
+                if let Some((path, offset, size)) = layer.files.next() {
+                    let inp = match section(&layer.file, offset, size) {
+                        Ok(inp) => inp,
+                        Err(e) => {
+                            warn!("could not read file {}: {}", path, e);
+                            continue;
+                        }
+                    };
+                    return Some(AdaptInfo {
+                        line_prefix: self.config.entry_prefix(&self.line_prefix, &path),
+                        filepath_hint: PathBuf::from(path),
+                        is_real_file: false,
+                        archive_recursion_depth: self.archive_recursion_depth + 1,
+                        inp: Box::new(inp),
+                        postprocess: self.postprocess,
+                        config: self.config.clone(),
+                    });
+                }
+            }
+            // the temporary file of the previous layer is deleted
+            self.current = None;
+            let (layer, files) = self.layers.next()?;
+            match self.open_layer(&layer, &files) {
+                Ok(layer) => self.current = Some(layer),
+                Err(e) => warn!("could not read layer {}: {}", layer, e),
+            }
+        }
+    }
+}
+
+impl FileAdapter for DockerAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+        } = ai;
+        // the manifest is at the end of the tarball, so we need random access
+        let (path, tmp) = input_as_file(&filepath_hint, is_real_file, &mut inp)?;
+        let file = File::open(&path)?;
+        let index = index_tar(&file)?;
+        let image = read_image(&file, &index)?;
+        let visible = visible_files(&file, &index, &image.layers)?;
+        let layers: Vec<_> = image
+            .layers
+            .into_iter()
+            .zip(visible)
+            .filter(|(_, files)| !files.is_empty())
+            .collect();
+        Ok(Box::new(DockerIter {
+            file,
+            index,
+            documents: image.documents.into_iter(),
+            layers: layers.into_iter(),
+            current: None,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            _tmp: tmp,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn whiteouts() {
+        let mut w = Whiteouts::default();
+        assert!(w.add("etc/.wh.shadow"));
+        assert!(w.add("var/cache/.wh..wh..opq"));
+        assert!(!w.add("etc/passwd"));
+        assert!(w.hides("etc/shadow"));
+        assert!(!w.hides("etc/passwd"));
+        assert!(w.hides("var/cache/apt/pkgcache.bin"));
+        assert!(w.add(".wh.opt"));
+        assert!(w.hides("opt/app/config.yml"));
+        assert!(!w.hides("optional"));
+    }
+}
@@ -0,0 +1,303 @@
+use super::*;
+use crate::adapted_iter::AdaptedFilesIter;
+use anyhow::*;
+use chrono::NaiveDateTime;
+use lazy_static::lazy_static;
+use log::*;
+use std::collections::VecDeque;
+use std::io::Cursor;
+use warc::html_to_text;
+
+static EXTENSIONS: &[&str] = &["enex"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "enex".to_owned(),
+        version: 1,
+        description: "Reads Evernote exports (.enex), converts the notes to text prefixed with their title and creation date and recurses into attachments".to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct EnexAdapter;
+
+impl EnexAdapter {
+    pub fn new() -> EnexAdapter {
+        EnexAdapter
+    }
+}
+impl GetMetadata for EnexAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the byte ranges of the attributes and the content of the first `<name>` element in xml.
+/// This is not a real xml parser, but good enough for the simple formats of exports
+pub fn find_element(
+    xml: &str,
+    name: &str,
+) -> Option<(std::ops::Range<usize>, std::ops::Range<usize>)> {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+    let mut pos = 0;
+    loop {
+        let start = pos + xml[pos..].find(&open)? + open.len();
+        let tag_end = start + xml[start..].find('>')?;
+        let attributes = &xml[start..tag_end];
+        pos = tag_end + 1;
+        // don't match elements that just start with the same name (e.g. <note-attributes>)
+        if !attributes
+            .chars()
+            .next()
+            .map_or(true, |c| c.is_whitespace() || c == '/')
+        {
+            continue;
+        }
+        if attributes.ends_with('/') {
+            // empty element
+            return Some((start..tag_end - 1, pos..pos));
+        }
+        let end = xml[pos..].find(&close).map_or(xml.len(), |end| pos + end);
+        return Some((start..tag_end, pos..end));
+    }
+}
+
+/// the raw attributes and contents of all `<name>` elements in xml
+pub fn elements_with_attributes<'x>(xml: &'x str, name: &str) -> Vec<(&'x str, &'x str)> {
+    let mut res = vec![];
+    let mut rest = xml;
+    while let Some((attributes, content)) = find_element(rest, name) {
+        res.push((&rest[attributes], &rest[content.clone()]));
+        rest = &rest[content.end..];
+    }
+    res
+}
+
+/// the raw contents of all `<name>` elements in xml
+pub fn elements<'x>(xml: &'x str, name: &str) -> Vec<&'x str> {
+    elements_with_attributes(xml, name)
+        .into_iter()
+        .map(|(_, content)| content)
+        .collect()
+}
+
+pub fn element<'x>(xml: &'x str, name: &str) -> Option<&'x str> {
+    find_element(xml, name).map(|(_, content)| &xml[content])
+}
+
+/// the value of an attribute in the raw attributes of an element
+pub fn attribute(attributes: &str, name: &str) -> Option<String> {
+    let mut rest = attributes;
+    loop {
+        let eq = rest.find('=')?;
+        let key = rest[..eq].trim();
+        let value = rest[eq + 1..].trim_start();
+        let quote = value.chars().next()?;
+        let end = value[1..].find(quote)? + 1;
+        if key == name {
+            return Some(unescape(&value[1..end]));
+        }
+        rest = &value[end + 1..];
+    }
+}
+
+/// the text of an element, either CDATA or escaped xml
+pub fn unescape(raw: &str) -> String {
+    let raw = raw.trim();
+    if raw.starts_with("<![CDATA[") {
+        raw["<![CDATA[".len()..]
+            .trim_end()
+            .trim_end_matches("]]>")
+            .to_string()
+    } else {
+        // there are no tags in the escaped text, so this only decodes entities
+        html_to_text(raw)
+    }
+}
+
+/// enex dates look like 20201016T153000Z
+fn format_date(date: &str) -> String {
+    NaiveDateTime::parse_from_str(date.trim(), "%Y%m%dT%H%M%SZ")
+        .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|_| date.trim().to_string())
+}
+
+struct Note {
+    title: String,
+    date: Option<String>,
+    text: String,
+    /// file name and content
+    attachments: Vec<(String, Vec<u8>)>,
+}
+
+fn parse_note(xml: &str) -> Note {
+    let title = element(xml, "title")
+        .map(unescape)
+        .unwrap_or_else(|| "Untitled".to_string());
+    let date = element(xml, "created").map(format_date);
+    let mut text = String::new();
+    let tags: Vec<String> = elements(xml, "tag").into_iter().map(unescape).collect();
+    if !tags.is_empty() {
+        text.push_str(&format!("tags: {}\n", tags.join(", ")));
+    }
+    if let Some(content) = element(xml, "content") {
+        // ENML is xhtml
+        text.push_str(&html_to_text(&unescape(content)));
+    }
+    let mut attachments = vec![];
+    for (i, resource) in elements(xml, "resource").into_iter().enumerate() {
+        let data = element(resource, "data").unwrap_or("");
+        let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+        let name = element(resource, "file-name")
+            .map(unescape)
+            .unwrap_or_else(|| format!("attachment{}", i + 1));
+        match base64::decode(&data) {
+            Ok(data) => attachments.push((name, data)),
+            Err(e) => warn!("could not decode attachment {} of {}: {}", name, title, e),
+        }
+    }
+    Note {
+        title,
+        date,
+        text,
+        attachments,
+    }
+}
+
+struct EnexIter {
+    xml: String,
+    pos: usize,
+    pending: VecDeque<AdaptInfo<'static>>,
+    line_prefix: String,
+    archive_recursion_depth: i32,
+    postprocess: bool,
+    config: RgaConfig,
+}
+
+impl EnexIter {
+    fn file(&self, note_prefix: &str, name: String, data: Vec<u8>) -> AdaptInfo<'static> {
+        AdaptInfo {
+            line_prefix: note_prefix.to_string(),
+            filepath_hint: PathBuf::from(name),
+            is_real_file: false,
+            archive_recursion_depth: self.archive_recursion_depth + 1,
+            inp: Box::new(Cursor::new(data)),
+            postprocess: self.postprocess,
+            config: self.config.clone(),
+        }
+    }
+
+    /// parse the next note into pending files, false if there are no more notes
+    fn next_note(&mut self) -> bool {
+        let range = match find_element(&self.xml[self.pos..], "note") {
+            Some((_, content)) => content,
+            None => return false,
+        };
+        let note = parse_note(&self.xml[self.pos + range.start..self.pos + range.end]);
+        self.pos += range.end;
+        let note_prefix = match &note.date {
+            Some(date) => self
+                .config
+                .entry_prefix(&self.line_prefix, &format!("{} ({})", note.title, date)),
+            None => self.config.entry_prefix(&self.line_prefix, &note.title),
+        };
+        // the title could contain anything, only the extension matters for the adapter detection
+        let text = self.file(&note_prefix, "note.txt".to_string(), note.text.into_bytes());
+        self.pending.push_back(text);
+        for (name, data) in note.attachments {
+            let prefix = self.config.entry_prefix(&note_prefix, &name);
+            let attachment = self.file(&prefix, name, data);
+            self.pending.push_back(attachment);
+        }
+        true
+    }
+}
+
+impl AdaptedFilesIter for EnexIter {
+    fn next<'b>(&'b mut self) -> Option<AdaptInfo<'b>> {
+        while self.pending.is_empty() {
+            if !self.next_note() {
+                return None;
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+impl FileAdapter for EnexAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data)?;
+        Ok(Box::new(EnexIter {
+            xml: String::from_utf8_lossy(&data).into_owned(),
+            pos: 0,
+            pending: VecDeque::new(),
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn notes() -> Result<()> {
+        let enex = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE en-export SYSTEM "http://xml.evernote.com/pub/evernote-export3.dtd">
+<en-export export-date="20201016T120000Z" application="Evernote" version="10">
+<note><title>Shopping &amp; errands</title><created>20201016T153000Z</created><tag>home</tag><tag>todo</tag>
+<content><![CDATA[<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<!DOCTYPE en-note SYSTEM "http://xml.evernote.com/pub/enml2.dtd">
+<en-note><div>milk</div><div>bread &amp; butter</div></en-note>]]></content>
+<note-attributes><author>me</author></note-attributes>
+<resource><data encoding="base64">aGVsbG8gZnJv
+bSB0aGUgYXR0YWNobWVudA==</data><mime>text/plain</mime>
+<resource-attributes><file-name>list.txt</file-name></resource-attributes></resource>
+</note>
+<note><title>Empty</title><content>&lt;en-note&gt;&lt;p&gt;escaped&lt;/p&gt;&lt;/en-note&gt;</content></note>
+</en-export>
+"#;
+        let adapter = EnexAdapter::new();
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("export.enex"),
+            Box::new(Cursor::new(enex.as_bytes().to_vec())),
+        );
+        let o = adapted_to_vec(adapter.adapt(a, &d)?)?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            "PREFIX:Shopping & errands (2020-10-16 15:30:00): tags: home, todo
+PREFIX:Shopping & errands (2020-10-16 15:30:00): milk
+PREFIX:Shopping & errands (2020-10-16 15:30:00): bread & butter
+PREFIX:Shopping & errands (2020-10-16 15:30:00): list.txt: hello from the attachment
+PREFIX:Empty: escaped
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use super::*;
+use ::evtx::EvtxParser;
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use serde_json::Value;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["evtx"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "evtx".to_owned(),
+        version: 1,
+        description: "Outputs one line per record of Windows event logs (.evtx) with timestamp, event id, provider and the event data (or the rendered message if the log contains it)".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct EvtxAdapter;
+
+impl EvtxAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(EvtxAdapter))
+    }
+}
+impl GetMetadata for EvtxAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the text of an element, which is either the value itself or its #text if it has attributes
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        Value::Object(o) => o.get("#text").and_then(text),
+        Value::Array(a) => Some(a.iter().filter_map(text).collect::<Vec<_>>().join(", ")),
+        v => Some(v.to_string()),
+    }
+}
+
+/// `name=value` pairs of the EventData / UserData element, nested elements are joined with "."
+fn flatten(value: &Value, prefix: &str, out: &mut Vec<String>) {
+    match value {
+        Value::Object(o) => {
+            for (k, v) in o {
+                if k == "#attributes" {
+                    continue;
+                }
+                if k == "#text" {
+                    if let Some(t) = text(v) {
+                        out.push(format!("{}={}", prefix, t));
+                    }
+                    continue;
+                }
+                let name = if prefix.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", prefix, k)
+                };
+                flatten(v, &name, out);
+            }
+        }
+        v => {
+            if let Some(t) = text(v) {
+                if !t.is_empty() {
+                    out.push(format!("{}={}", prefix, t));
+                }
+            }
+        }
+    }
+}
+
+fn format_event(timestamp: &str, event: &Value) -> String {
+    let event = &event["Event"];
+    let system = &event["System"];
+    let id = text(&system["EventID"]).unwrap_or_default();
+    let provider = text(&system["Provider"]["#attributes"]["Name"]).unwrap_or_default();
+    let channel = text(&system["Channel"]).unwrap_or_default();
+    let mut line = format!("{} event {} {} ({})", timestamp, id, provider, channel);
+    // only present in forwarded events, normal logs need the message templates of the provider
+    let message = match text(&event["RenderingInfo"]["Message"]) {
+        Some(message) => message.replace(|c: char| c == '\r' || c == '\n', " "),
+        None => {
+            let mut fields = vec![];
+            flatten(&event["EventData"], "", &mut fields);
+            flatten(&event["UserData"], "", &mut fields);
+            fields.join(", ")
+        }
+    };
+    if !message.is_empty() {
+        line.push_str(": ");
+        line.push_str(&message);
+    }
+    line
+}
+
+impl WritingFileAdapterTrait for EvtxAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo { mut inp, .. } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data)?;
+        let mut parser = EvtxParser::from_buffer(data)?;
+        for record in parser.records_json_value() {
+            match record {
+                Ok(record) => writeln!(
+                    oup,
+                    "{}",
+                    format_event(
+                        &record.timestamp.format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+                        &record.data
+                    )
+                )?,
+                Err(e) => warn!("skipping invalid event log record: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn event_data() -> Result<()> {
+        let event = serde_json::json!({
+            "Event": {
+                "#attributes": {"xmlns": "http://schemas.microsoft.com/win/2004/08/events/event"},
+                "System": {
+                    "Provider": {"#attributes": {"Name": "Microsoft-Windows-Security-Auditing"}},
+                    "EventID": 4624,
+                    "Channel": "Security",
+                },
+                "EventData": {
+                    "IpAddress": "-",
+                    "LogonType": 10,
+                    "TargetUserName": "alice",
+                }
+            }
+        });
+        assert_eq!(
+            format_event("2020-09-13 12:26:40.000", &event),
+            "2020-09-13 12:26:40.000 event 4624 Microsoft-Windows-Security-Auditing (Security): \
+             IpAddress=-, LogonType=10, TargetUserName=alice"
+        );
+        Ok(())
+    }
+}
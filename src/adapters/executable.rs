@@ -0,0 +1,269 @@
+use super::*;
+use anyhow::*;
+use goblin::{elf::Elf, mach::Mach, mach::MachO, pe::PE, Object};
+use lazy_static::lazy_static;
+use log::*;
+use spawning::read_input;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["exe", "dll", "sys", "ocx", "so", "ko", "dylib", "o"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "executable".to_owned(),
+        version: 1,
+        description: "Outputs imported and exported symbols, linked libraries, section names and embedded version strings of ELF, PE (Windows) and Mach-O executables and libraries".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            [
+                "application/x-executable",
+                "application/x-sharedlib",
+                "application/x-object",
+                "application/x-pie-executable",
+                "application/x-dosexec",
+                "application/x-mach-binary"
+            ]
+            .iter()
+            .map(|s| FileMatcher::MimeType(s.to_string()))
+            .collect()
+        ),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ExecutableAdapter;
+
+impl ExecutableAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(ExecutableAdapter))
+    }
+}
+impl GetMetadata for ExecutableAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// keys of the StringFileInfo block of PE version resources
+static PE_VERSION_KEYS: &[&str] = &[
+    "CompanyName",
+    "FileDescription",
+    "FileVersion",
+    "InternalName",
+    "LegalCopyright",
+    "OriginalFilename",
+    "ProductName",
+    "ProductVersion",
+    "Comments",
+];
+
+fn utf16le(s: &str) -> Vec<u8> {
+    s.encode_utf16()
+        .flat_map(|c| c.to_le_bytes().to_vec())
+        .collect()
+}
+
+fn read_utf16z(data: &[u8]) -> String {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// find the `Key: value` pairs of version resources in the (raw) resource section of a PE file
+fn pe_version_strings(rsrc: &[u8]) -> Vec<String> {
+    let mut out = vec![];
+    for key in PE_VERSION_KEYS {
+        let mut needle = utf16le(key);
+        needle.extend_from_slice(&[0, 0]);
+        let mut start = 0;
+        while let Some(i) = rsrc[start..]
+            .windows(needle.len())
+            .position(|w| w == &needle[..])
+        {
+            let mut value_start = start + i + needle.len();
+            // the value is aligned to 32 bit, skip the padding
+            while rsrc.get(value_start..value_start + 2) == Some(&[0u8, 0][..])
+                && value_start % 4 != 0
+            {
+                value_start += 2;
+            }
+            let value = read_utf16z(&rsrc[value_start..]);
+            if !value.is_empty() {
+                out.push(format!("{}: {}", key, value));
+            }
+            start = value_start;
+        }
+    }
+    out
+}
+
+fn adapt_elf(elf: &Elf, data: &[u8], oup: &mut dyn Write) -> Result<()> {
+    for lib in &elf.libraries {
+        writeln!(oup, "library: {}", lib)?;
+    }
+    for sym in elf.dynsyms.iter() {
+        let name = match elf.dynstrtab.get(sym.st_name).and_then(|n| n.ok()) {
+            Some(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+        if sym.is_import() {
+            writeln!(oup, "import: {}", name)?;
+        } else if sym.st_bind() != goblin::elf::sym::STB_LOCAL {
+            writeln!(oup, "export: {}", name)?;
+        }
+    }
+    // full symbol table, if the binary is not stripped
+    for sym in elf.syms.iter() {
+        if sym.is_import() || !(sym.is_function() || sym.st_type() == goblin::elf::sym::STT_OBJECT)
+        {
+            continue;
+        }
+        if let Some(name) = elf.strtab.get(sym.st_name).and_then(|n| n.ok()) {
+            if !name.is_empty() {
+                writeln!(oup, "symbol: {}", name)?;
+            }
+        }
+    }
+    for section in &elf.section_headers {
+        let name = match elf.shdr_strtab.get(section.sh_name).and_then(|n| n.ok()) {
+            Some(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+        writeln!(oup, "section: {}", name)?;
+        // compiler / linker versions
+        if name == ".comment" {
+            let start = section.sh_offset as usize;
+            let end = start.saturating_add(section.sh_size as usize);
+            if let Some(comment) = data.get(start..end) {
+                for version in comment.split(|&b| b == 0).filter(|s| !s.is_empty()) {
+                    writeln!(oup, "version: {}", String::from_utf8_lossy(version))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn adapt_pe(pe: &PE, data: &[u8], oup: &mut dyn Write) -> Result<()> {
+    for lib in &pe.libraries {
+        writeln!(oup, "library: {}", lib)?;
+    }
+    for import in &pe.imports {
+        writeln!(oup, "import: {} ({})", import.name, import.dll)?;
+    }
+    for export in &pe.exports {
+        if let Some(name) = export.name {
+            writeln!(oup, "export: {}", name)?;
+        }
+    }
+    for section in &pe.sections {
+        let name = match section.name() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        writeln!(oup, "section: {}", name)?;
+        if name == ".rsrc" {
+            let start = section.pointer_to_raw_data as usize;
+            let end = start.saturating_add(section.size_of_raw_data as usize);
+            if let Some(rsrc) = data.get(start..std::cmp::min(end, data.len())) {
+                for version in pe_version_strings(rsrc) {
+                    writeln!(oup, "version: {}", version)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn adapt_macho(macho: &MachO, oup: &mut dyn Write) -> Result<()> {
+    for lib in macho.libs.iter().filter(|l| **l != "self") {
+        writeln!(oup, "library: {}", lib)?;
+    }
+    for import in macho.imports()? {
+        writeln!(oup, "import: {} ({})", import.name, import.dylib)?;
+    }
+    for export in macho.exports()? {
+        writeln!(oup, "export: {}", export.name)?;
+    }
+    for segment in macho.segments.iter() {
+        for (section, _) in segment.sections()? {
+            writeln!(oup, "section: {},{}", section.segname()?, section.name()?)?;
+        }
+    }
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for ExecutableAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            filepath_hint,
+            is_real_file,
+            ..
+        } = ai;
+        let data = read_input(&filepath_hint, is_real_file, &mut inp)?;
+        match Object::parse(&data)? {
+            Object::Elf(elf) => adapt_elf(&elf, &data, oup),
+            Object::PE(pe) => adapt_pe(&pe, &data, oup),
+            Object::Mach(Mach::Binary(macho)) => adapt_macho(&macho, oup),
+            Object::Mach(Mach::Fat(fat)) => {
+                // universal binaries contain the same code for multiple architectures, the first one is enough
+                let macho = fat.get(0)?;
+                adapt_macho(&macho, oup)
+            }
+            _ => {
+                debug!(
+                    "{} is not a known executable format",
+                    filepath_hint.to_string_lossy()
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn version_resource() {
+        let mut rsrc = vec![0u8; 6];
+        rsrc.extend(utf16le("ProductName"));
+        // terminator and padding
+        rsrc.extend(&[0, 0, 0, 0]);
+        rsrc.extend(utf16le("rga test"));
+        rsrc.extend(&[0, 0, 0, 0]);
+        assert_eq!(pe_version_strings(&rsrc), vec!["ProductName: rga test"]);
+    }
+
+    #[test]
+    fn own_executable() -> Result<()> {
+        // the test binary itself is an ELF, PE or Mach-O file depending on the platform
+        let exe = std::fs::read(std::env::current_exe()?)?;
+        let mut oup = Vec::new();
+        match Object::parse(&exe)? {
+            Object::Elf(elf) => adapt_elf(&elf, &exe, &mut oup)?,
+            Object::PE(pe) => adapt_pe(&pe, &exe, &mut oup)?,
+            Object::Mach(Mach::Binary(macho)) => adapt_macho(&macho, &mut oup)?,
+            _ => return Ok(()),
+        }
+        let out = String::from_utf8_lossy(&oup);
+        assert!(out.lines().any(|l| l.starts_with("section: ")));
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+//! Reduced replacements for builtin adapters whose program is not installed, so rga still finds something on minimal
+//! systems instead of failing for every file: the text of docx/odt/epub/fb2/ipynb without pandoc (no tables or
+//! formatting), and the text of PDF files with lopdf without pdftotext (often with worse spacing and no layout).
+//!
+//! The adapter keeps its name, so --rga-adapters works the same. rga reports once per run which adapters are degraded,
+//! and the cache keys include the fallback, so the output is adapted again once the program is installed.
+
+use super::warc::html_to_text;
+use super::FileAdapter;
+use anyhow::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Mutex;
+
+pub struct Fallback {
+    /// the builtin adapter
+    pub adapter: &'static str,
+    /// the program the adapter runs
+    pub program: &'static str,
+    /// changes the cache key when the conversion changes
+    pub version: i32,
+    /// what is extracted without the program
+    pub description: &'static str,
+    convert: fn(&Path, &mut dyn Read) -> Result<String>,
+}
+
+impl Fallback {
+    /// the text of the file, like the output of the program
+    pub fn convert(&self, filepath_hint: &Path, inp: &mut dyn Read) -> Result<String> {
+        (self.convert)(filepath_hint, inp)
+    }
+}
+
+static FALLBACKS: &[Fallback] = &[
+    Fallback {
+        adapter: "pandoc",
+        program: "pandoc",
+        version: 1,
+        description: "only the plain text of docx, odt, epub, fb2 and ipynb files is extracted (no tables or formatting)",
+        convert: document_text,
+    },
+    Fallback {
+        adapter: "poppler",
+        program: "pdftotext",
+        version: 1,
+        description: "the text of PDF files is extracted with lopdf (worse spacing, no layout)",
+        convert: pdf_text,
+    },
+];
+
+lazy_static! {
+    static ref AVAILABLE: Mutex<HashMap<String, bool>> = Mutex::new(HashMap::new());
+    /// paragraphs and line breaks of docx (w:) and odt (text:), html_to_text drops the other tags
+    static ref OFFICE_BREAK: Regex =
+        Regex::new(r"</w:p>|</text:p>|</text:h>|<w:br\s*/>|<w:cr\s*/>|<text:line-break\s*/>").unwrap();
+    static ref OFFICE_TAB: Regex = Regex::new(r"<w:tab\s*/>|<text:tab\s*/>").unwrap();
+}
+
+/// whether the program is in PATH, looked up once per process
+pub fn program_available(program: &str) -> bool {
+    *AVAILABLE
+        .lock()
+        .unwrap()
+        .entry(program.to_string())
+        .or_insert_with(|| crate::doctor::find_program(program).is_some())
+}
+
+/// the fallback to use instead of running the program, if the adapter is a builtin one that has a fallback and the program is missing
+pub fn active_fallback(adapter: &str, program: &str) -> Option<&'static Fallback> {
+    FALLBACKS
+        .iter()
+        .find(|f| f.adapter == adapter && f.program == program)
+        .filter(|f| !program_available(f.program))
+}
+
+/// the fallbacks that are used for the given (enabled) adapters
+pub fn degraded(adapters: &[Rc<dyn FileAdapter>]) -> Vec<&'static Fallback> {
+    FALLBACKS
+        .iter()
+        .filter(|f| adapters.iter().any(|a| a.metadata().name == f.adapter))
+        .filter(|f| !program_available(f.program))
+        .collect()
+}
+
+fn office_xml_text(xml: &str) -> String {
+    let xml = OFFICE_BREAK.replace_all(xml, "<br>");
+    html_to_text(&OFFICE_TAB.replace_all(&xml, " "))
+}
+
+fn zip_entry(archive: &mut zip::ZipArchive<Cursor<Vec<u8>>>, name: &str) -> Result<String> {
+    let mut text = String::new();
+    archive
+        .by_name(name)
+        .with_context(|| format!("no {} in the file", name))?
+        .read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// the source of the cells of a jupyter notebook
+fn notebook_text(json: &str) -> Result<String> {
+    let notebook: serde_json::Value = serde_json::from_str(json)?;
+    let mut out = String::new();
+    for cell in notebook["cells"].as_array().into_iter().flatten() {
+        match &cell["source"] {
+            serde_json::Value::String(s) => out.push_str(s),
+            serde_json::Value::Array(lines) => {
+                for line in lines.iter().filter_map(|l| l.as_str()) {
+                    out.push_str(line);
+                }
+            }
+            _ => continue,
+        }
+        out.push_str("\n\n");
+    }
+    Ok(out)
+}
+
+fn document_text(filepath_hint: &Path, inp: &mut dyn Read) -> Result<String> {
+    let extension = filepath_hint
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let mut data = Vec::new();
+    inp.read_to_end(&mut data)?;
+    match extension.as_str() {
+        "fb2" => Ok(html_to_text(&String::from_utf8_lossy(&data))),
+        "ipynb" => notebook_text(&String::from_utf8_lossy(&data)),
+        "docx" | "odt" | "epub" => {
+            let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+            match extension.as_str() {
+                "docx" => Ok(office_xml_text(&zip_entry(
+                    &mut archive,
+                    "word/document.xml",
+                )?)),
+                "odt" => Ok(office_xml_text(&zip_entry(&mut archive, "content.xml")?)),
+                _ => {
+                    let mut out = String::new();
+                    for i in 0..archive.len() {
+                        let mut entry = archive.by_index(i)?;
+                        let name = entry.name().to_ascii_lowercase();
+                        if !(name.ends_with(".html")
+                            || name.ends_with(".xhtml")
+                            || name.ends_with(".htm"))
+                        {
+                            continue;
+                        }
+                        let mut html = String::new();
+                        entry.read_to_string(&mut html)?;
+                        out.push_str(&html_to_text(&html));
+                        out.push('\n');
+                    }
+                    Ok(out)
+                }
+            }
+        }
+        _ => bail!(
+            "pandoc is not installed, and .{} files can't be read without it",
+            extension
+        ),
+    }
+}
+
+/// the pages are separated by form feeds like in the output of pdftotext, so they get the page prefixes
+fn pdf_text(_filepath_hint: &Path, inp: &mut dyn Read) -> Result<String> {
+    let mut data = Vec::new();
+    inp.read_to_end(&mut data)?;
+    let doc = lopdf::Document::load_mem(&data).context("could not parse the PDF file")?;
+    let mut pages = Vec::new();
+    for num in doc.get_pages().keys() {
+        // pages that can't be read (e.g. with an unsupported font encoding) are left empty
+        pages.push(doc.extract_text(&[*num]).unwrap_or_default());
+    }
+    Ok(pages.join("\x0c"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn office_and_notebook_text() -> Result<()> {
+        assert_eq!(
+            office_xml_text(
+                r#"<w:body><w:p><w:r><w:t>Hello</w:t><w:tab/><w:t>world &amp; all</w:t></w:r></w:p><w:p><w:r><w:t>Bye</w:t></w:r></w:p></w:body>"#
+            )
+            .trim(),
+            "Hello world & all\nBye"
+        );
+        assert_eq!(
+            notebook_text(
+                r##"{"cells": [{"cell_type": "markdown", "source": ["# Title\n", "text"]}, {"cell_type": "code", "source": "print(1)"}]}"##
+            )?,
+            "# Title\ntext\n\nprint(1)\n\n"
+        );
+        assert!(active_fallback("pandoc", "rga-no-such-program").is_none());
+        assert!(active_fallback("mydocx", "pandoc").is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["fits", "fit", "fts", "fz"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "fits".to_owned(),
+        version: 1,
+        description:
+            "Outputs the header cards of FITS astronomy files, including image and table extensions"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("image/fits".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct FitsAdapter;
+
+impl FitsAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(FitsAdapter))
+    }
+}
+impl GetMetadata for FitsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const BLOCK_LEN: u64 = 2880;
+const CARD_LEN: usize = 80;
+
+/// the header cards of one header data unit
+struct Hdu {
+    cards: Vec<String>,
+}
+
+impl Hdu {
+    fn keyword(card: &str) -> &str {
+        card.get(0..8).unwrap_or(card).trim_end()
+    }
+
+    fn value(&self, keyword: &str) -> Option<&str> {
+        self.cards
+            .iter()
+            .find(|c| Hdu::keyword(c) == keyword && c.get(8..10) == Some("= "))
+            .map(|c| {
+                let v = &c[10..];
+                // strings are quoted and may contain slashes, comments start after the value
+                if v.trim_start().starts_with('\'') {
+                    v.trim().splitn(3, '\'').nth(1).unwrap_or("").trim_end()
+                } else {
+                    v.split('/').next().unwrap_or("").trim()
+                }
+            })
+    }
+
+    fn int_value(&self, keyword: &str) -> Option<u64> {
+        self.value(keyword)
+            .and_then(|v| v.parse::<i64>().ok())
+            .and_then(i64::checked_abs)
+            .map(|v| v as u64)
+    }
+
+    /// size of the data following the header, in bytes, padded to full blocks. Sizes that don't fit in a u64
+    /// are only found in invalid files
+    fn data_len(&self) -> Result<u64> {
+        let naxis = self.int_value("NAXIS").unwrap_or(0);
+        if naxis == 0 {
+            return Ok(0);
+        }
+        if naxis > 999 {
+            return Err(format_err!("invalid FITS header: NAXIS = {}", naxis));
+        }
+        let overflow = || format_err!("invalid FITS header: the data size overflows");
+        let bytes_per_value = self.int_value("BITPIX").unwrap_or(8) / 8;
+        let mut values: u64 = 1;
+        for i in 1..=naxis {
+            let len = self.int_value(&format!("NAXIS{}", i)).unwrap_or(0);
+            values = values.checked_mul(len).ok_or_else(overflow)?;
+        }
+        let pcount = self.int_value("PCOUNT").unwrap_or(0);
+        let gcount = self.int_value("GCOUNT").unwrap_or(1);
+        pcount
+            .checked_add(values)
+            .and_then(|n| n.checked_mul(gcount))
+            .and_then(|n| n.checked_mul(bytes_per_value))
+            .and_then(|n| n.checked_add(BLOCK_LEN - 1))
+            .map(|n| n / BLOCK_LEN * BLOCK_LEN)
+            .ok_or_else(overflow)
+    }
+}
+
+/// read the header cards up to END. Returns None at the end of the file
+fn read_hdu(inp: &mut dyn Read) -> Result<Option<Hdu>> {
+    let mut cards = vec![];
+    let mut block = vec![0u8; BLOCK_LEN as usize];
+    loop {
+        if let Err(e) = inp.read_exact(&mut block) {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                // either the end of the file or some trailing garbage
+                return Ok(None);
+            }
+            return Err(e.into());
+        }
+        for card in block.chunks(CARD_LEN) {
+            // headers are restricted to printable ascii
+            let card: String = card.iter().map(|&b| b as char).collect();
+            if Hdu::keyword(&card) == "END" {
+                return Ok(Some(Hdu { cards }));
+            }
+            if !card.trim().is_empty() {
+                cards.push(card.trim_end().to_string());
+            }
+        }
+    }
+}
+
+impl WritingFileAdapterTrait for FitsAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo { mut inp, .. } = ai;
+        let mut hdu_num = 0;
+        while let Some(hdu) = read_hdu(&mut inp)? {
+            if hdu_num == 0 && hdu.cards.first().map(|c| Hdu::keyword(c)) != Some("SIMPLE") {
+                return Err(format_err!("not a FITS file (missing SIMPLE card)"));
+            }
+            // the primary header is output as is, extension cards are marked with their number and type
+            let marker = match hdu.value("XTENSION") {
+                Some(xtension) => format!("[{} {}] ", hdu_num, xtension.trim()),
+                None if hdu_num == 0 => "".to_string(),
+                None => format!("[{}] ", hdu_num),
+            };
+            for card in &hdu.cards {
+                writeln!(oup, "{}{}", marker, card)?;
+            }
+            // skip the data
+            let data_len = hdu.data_len()?;
+            debug!("skipping {} bytes of data in HDU {}", data_len, hdu_num);
+            let skipped = std::io::copy(&mut (&mut inp).take(data_len), &mut std::io::sink())?;
+            if skipped < data_len {
+                break;
+            }
+            hdu_num += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn header(cards: &[&str]) -> Vec<u8> {
+        let mut out: Vec<u8> = cards
+            .iter()
+            .chain(std::iter::once(&"END"))
+            .flat_map(|c| format!("{:80}", c).into_bytes())
+            .collect();
+        let padded = (out.len() + 2879) / 2880 * 2880;
+        out.resize(padded, b' ');
+        out
+    }
+
+    #[test]
+    fn primary_and_table_headers() -> Result<()> {
+        let mut fits = header(&[
+            "SIMPLE  =                    T / conforms to FITS standard",
+            "BITPIX  =                  -32",
+            "NAXIS   =                    2",
+            "NAXIS1  =                   10",
+            "NAXIS2  =                   10",
+            "OBJECT  = 'M31     '           / object name",
+        ]);
+        // 10 * 10 float32 image, padded to one block
+        fits.extend(vec![0u8; 2880]);
+        fits.extend(header(&[
+            "XTENSION= 'BINTABLE'           / binary table extension",
+            "BITPIX  =                    8",
+            "NAXIS   =                    2",
+            "NAXIS1  =                    8",
+            "NAXIS2  =                    0",
+            "PCOUNT  =                    0",
+            "GCOUNT  =                    1",
+            "TTYPE1  = 'TIME    '",
+        ]));
+
+        let adapter: Box<dyn FileAdapter> = Box::new(FitsAdapter::new());
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("m31.fits"),
+            Box::new(std::io::Cursor::new(fits)),
+        );
+        let out = String::from_utf8(adapted_to_vec(adapter.adapt(a, &d)?)?)?;
+        assert!(out.contains("\nPREFIX:OBJECT  = 'M31     '           / object name\n"));
+        assert!(out.ends_with("\nPREFIX:[1 BINTABLE] TTYPE1  = 'TIME    '\n"));
+
+        let huge = header(&[
+            "SIMPLE  =                    T",
+            "BITPIX  =                   64",
+            "NAXIS   =                    2",
+            "NAXIS1  =  4294967296",
+            "NAXIS2  =  4294967296",
+        ]);
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("huge.fits"),
+            Box::new(std::io::Cursor::new(huge)),
+        );
+        assert!(adapter.adapt(a, &d).is_err());
+        Ok(())
+    }
+}
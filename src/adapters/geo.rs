@@ -0,0 +1,301 @@
+use super::*;
+use anyhow::*;
+use enex::{attribute, element, elements, elements_with_attributes, unescape};
+use lazy_static::lazy_static;
+use serde_json::Value;
+use warc::html_to_text;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["gpx", "kml", "geojson"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "geo".to_owned(),
+        version: 1,
+        description: "Outputs the names, descriptions and coordinates of waypoints, tracks, routes and placemarks in GPX, KML and GeoJSON files, one per line".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/gpx+xml".to_owned()),
+            FileMatcher::MimeType("application/vnd.google-earth.kml+xml".to_owned()),
+            FileMatcher::MimeType("application/geo+json".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct GeoAdapter;
+
+impl GeoAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(GeoAdapter))
+    }
+}
+impl GetMetadata for GeoAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// `kind name (lat, lon): description`, all on one line
+fn write_place(
+    oup: &mut dyn Write,
+    kind: &str,
+    name: Option<String>,
+    coordinates: Option<(String, String)>,
+    description: Option<String>,
+) -> Result<()> {
+    write!(oup, "{}", kind)?;
+    if let Some(name) = name.filter(|n| !n.is_empty()) {
+        write!(oup, " {}", name)?;
+    }
+    if let Some((lat, lon)) = coordinates {
+        write!(oup, " ({}, {})", lat, lon)?;
+    }
+    // descriptions often contain html
+    let description = description
+        .map(|d| html_to_text(&d).lines().collect::<Vec<_>>().join(" "))
+        .filter(|d| !d.is_empty());
+    if let Some(description) = description {
+        write!(oup, ": {}", description)?;
+    }
+    writeln!(oup)?;
+    Ok(())
+}
+
+fn text(xml: &str, name: &str) -> Option<String> {
+    element(xml, name).map(unescape)
+}
+
+fn gpx_point(attributes: &str) -> Option<(String, String)> {
+    Some((attribute(attributes, "lat")?, attribute(attributes, "lon")?))
+}
+
+fn gpx(xml: &str, oup: &mut dyn Write) -> Result<()> {
+    let description = |content: &str| text(content, "desc").or_else(|| text(content, "cmt"));
+    for (attributes, content) in elements_with_attributes(xml, "wpt") {
+        write_place(
+            oup,
+            "waypoint",
+            text(content, "name"),
+            gpx_point(attributes),
+            description(content),
+        )?;
+    }
+    for (kind, container, point) in &[("route", "rte", "rtept"), ("track", "trk", "trkpt")] {
+        for route in elements(xml, container) {
+            let points = elements_with_attributes(route, point);
+            // the name of the route itself comes before the first point
+            let header = &route[..route.find(&format!("<{}", point)).unwrap_or(route.len())];
+            write_place(
+                oup,
+                kind,
+                text(header, "name"),
+                points.first().and_then(|(a, _)| gpx_point(a)),
+                description(header),
+            )?;
+            for (attributes, content) in points {
+                // only named points are interesting
+                if let Some(name) = text(content, "name") {
+                    write_place(
+                        oup,
+                        &format!("{} point", kind),
+                        Some(name),
+                        gpx_point(attributes),
+                        description(content),
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn kml(xml: &str, oup: &mut dyn Write) -> Result<()> {
+    for placemark in elements(xml, "Placemark") {
+        // lon,lat[,alt] tuples separated by whitespace, use the first one
+        let coordinates = text(placemark, "coordinates").and_then(|c| {
+            let mut parts = c.split_whitespace().next()?.split(',');
+            let lon = parts.next()?.to_string();
+            let lat = parts.next()?.to_string();
+            Some((lat, lon))
+        });
+        write_place(
+            oup,
+            "placemark",
+            text(placemark, "name"),
+            coordinates,
+            text(placemark, "description"),
+        )?;
+    }
+    Ok(())
+}
+
+/// the first position in a geometry, formatted as (lat, lon)
+fn first_position(coordinates: &Value) -> Option<(String, String)> {
+    match coordinates {
+        Value::Array(a) => match (a.get(0), a.get(1)) {
+            (Some(Value::Number(lon)), Some(Value::Number(lat))) => {
+                Some((lat.to_string(), lon.to_string()))
+            }
+            _ => a.get(0).and_then(first_position),
+        },
+        _ => None,
+    }
+}
+
+fn geojson_feature(feature: &Value, oup: &mut dyn Write) -> Result<()> {
+    let properties = feature["properties"].as_object();
+    let property = |keys: &[&str]| {
+        keys.iter()
+            .filter_map(|k| properties?.get(*k)?.as_str())
+            .next()
+            .map(ToString::to_string)
+    };
+    let name = property(&["name", "title"]);
+    let mut description = property(&["description", "desc"]);
+    // the other simple properties are often interesting as well (e.g. address, type)
+    let others: Vec<String> = properties
+        .into_iter()
+        .flatten()
+        .filter(|(k, _)| !["name", "title", "description", "desc"].contains(&k.as_str()))
+        .filter_map(|(k, v)| match v {
+            Value::String(s) => Some(format!("{}={}", k, s)),
+            Value::Number(n) => Some(format!("{}={}", k, n)),
+            Value::Bool(b) => Some(format!("{}={}", k, b)),
+            _ => None,
+        })
+        .collect();
+    if !others.is_empty() {
+        let others = others.join(", ");
+        description = Some(match description {
+            Some(d) => format!("{} {}", d, others),
+            None => others,
+        });
+    }
+    write_place(
+        oup,
+        "feature",
+        name,
+        first_position(&feature["geometry"]["coordinates"]),
+        description,
+    )
+}
+
+fn geojson(json: &Value, oup: &mut dyn Write) -> Result<()> {
+    match json["type"].as_str() {
+        Some("FeatureCollection") => {
+            for feature in json["features"].as_array().into_iter().flatten() {
+                geojson_feature(feature, oup)?;
+            }
+        }
+        Some("Feature") => geojson_feature(json, oup)?,
+        // a bare geometry
+        _ => write_place(
+            oup,
+            json["type"].as_str().unwrap_or("geometry"),
+            None,
+            first_position(&json["coordinates"]),
+            None,
+        )?,
+    }
+    Ok(())
+}
+
+fn adapt_geo(filepath_hint: &Path, data: &str, oup: &mut dyn Write) -> Result<()> {
+    let ext = filepath_hint
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "geojson" => geojson(&serde_json::from_str(data)?, oup),
+        "kml" => kml(data, oup),
+        "gpx" => gpx(data, oup),
+        // detected by mime type
+        _ if data.contains("<gpx") => gpx(data, oup),
+        _ if data.contains("<kml") => kml(data, oup),
+        _ => geojson(&serde_json::from_str(data)?, oup),
+    }
+}
+
+impl WritingFileAdapterTrait for GeoAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            filepath_hint,
+            mut inp,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data)?;
+        adapt_geo(&filepath_hint, &String::from_utf8_lossy(&data), oup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn geo(name: &str, data: &str) -> Result<String> {
+        let mut oup = Vec::new();
+        adapt_geo(Path::new(name), data, &mut oup)?;
+        Ok(String::from_utf8(oup)?)
+    }
+
+    #[test]
+    fn gpx_waypoints_and_tracks() -> Result<()> {
+        assert_eq!(
+            geo(
+                "hike.gpx",
+                r#"<?xml version="1.0"?><gpx version="1.1" creator="test">
+<wpt lat="47.4210" lon="10.9863"><name>Zugspitze</name><desc>Summit &amp; cross</desc></wpt>
+<trk><name>Day 1</name><trkseg>
+<trkpt lat="47.40" lon="10.98"><ele>2000</ele></trkpt>
+<trkpt lat="47.41" lon="10.99"><name>Hut</name></trkpt>
+</trkseg></trk></gpx>"#
+            )?,
+            "waypoint Zugspitze (47.4210, 10.9863): Summit & cross\n\
+             track Day 1 (47.40, 10.98)\n\
+             track point Hut (47.41, 10.99)\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn kml_placemarks() -> Result<()> {
+        assert_eq!(
+            geo(
+                "places.kml",
+                r#"<kml xmlns="http://www.opengis.net/kml/2.2"><Document><Placemark>
+<name>Brandenburg Gate</name><description><![CDATA[<b>Landmark</b><br>Berlin]]></description>
+<Point><coordinates>13.3777,52.5163,0</coordinates></Point></Placemark></Document></kml>"#
+            )?,
+            "placemark Brandenburg Gate (52.5163, 13.3777): Landmark Berlin\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn geojson_features() -> Result<()> {
+        assert_eq!(
+            geo(
+                "cities.geojson",
+                r#"{"type":"FeatureCollection","features":[
+{"type":"Feature","properties":{"name":"Paris","population":2148000},"geometry":{"type":"Point","coordinates":[2.35,48.85]}},
+{"type":"Feature","properties":{"title":"Seine"},"geometry":{"type":"LineString","coordinates":[[3.1,47.5],[0.1,49.4]]}}]}"#
+            )?,
+            "feature Paris (48.85, 2.35): population=2148000\n\
+             feature Seine (47.5, 3.1)\n"
+        );
+        Ok(())
+    }
+}
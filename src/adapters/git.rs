@@ -0,0 +1,534 @@
+use super::*;
+use crate::adapted_iter::{AdaptedFilesIter, MultiAdaptedFilesIter, SingleAdaptedFileAsIter};
+use anyhow::*;
+use git2::{ObjectType, Oid, Repository, TreeWalkMode, TreeWalkResult};
+use lazy_static::lazy_static;
+use log::*;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::io::Cursor;
+use std::path::Path;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "git".to_owned(),
+        version: 1,
+        description: "Reads git objects (loose objects in .git/objects and packfiles) and outputs commit messages, tree entries and blob contents, prefixed with the object id. With --rga-git-history, .git/HEAD is expanded to every file version reachable from any ref".to_owned(),
+        recurses: true,
+        fast_matchers: vec![
+            FastFileMatcher::FileExtension("pack".to_owned()),
+            FastFileMatcher::PathGlob("**/objects/[0-9a-f][0-9a-f]/[0-9a-f]*".to_owned()),
+            FastFileMatcher::PathGlob("**/.git/HEAD".to_owned())
+        ],
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct GitAdapter;
+
+impl GitAdapter {
+    pub fn new() -> GitAdapter {
+        GitAdapter
+    }
+}
+impl GetMetadata for GitAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// the parts of the AdaptInfo of the git file that are passed on to the objects
+#[derive(Clone)]
+struct ObjectOutput {
+    line_prefix: String,
+    archive_recursion_depth: i32,
+    postprocess: bool,
+    config: RgaConfig,
+}
+
+/// `100644 blob <id>\tname` lines, like git ls-tree
+fn format_tree(data: &[u8]) -> Result<String> {
+    let mut lines = vec![];
+    let mut rest = data;
+    while !rest.is_empty() {
+        let nul = rest
+            .iter()
+            .position(|&b| b == 0)
+            .context("invalid tree entry")?;
+        let entry = String::from_utf8_lossy(&rest[..nul]);
+        let id = rest.get(nul + 1..nul + 21).context("invalid tree entry")?;
+        let (mode, name) = entry.split_at(entry.find(' ').context("invalid tree entry")?);
+        let kind = match mode {
+            "40000" => "tree",
+            "160000" => "commit",
+            _ => "blob",
+        };
+        lines.push(format!(
+            "{:0>6} {} {}\t{}",
+            mode,
+            kind,
+            to_hex(id),
+            &name[1..]
+        ));
+        rest = &rest[nul + 21..];
+    }
+    Ok(lines.join("\n"))
+}
+
+impl ObjectOutput {
+    /// the AdaptInfo for a single object. blobs are recursed into, using their file name from a tree if known
+    fn object<'a>(
+        &self,
+        id: &str,
+        kind: &str,
+        mut data: Vec<u8>,
+        name: Option<&str>,
+    ) -> Result<Option<AdaptInfo<'a>>> {
+        let hint = match kind {
+            "blob" => name.unwrap_or(id).to_string(),
+            "tree" => {
+                data = format_tree(&data)?.into_bytes();
+                format!("{}.tree.txt", id)
+            }
+            kind => {
+                // the line prefixer always ends the output with a newline
+                if data.ends_with(b"\n") {
+                    data.pop();
+                }
+                format!("{}.{}.txt", id, kind)
+            }
+        };
+        if data.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(AdaptInfo {
+            filepath_hint: PathBuf::from(hint),
+            is_real_file: false,
+            archive_recursion_depth: self.archive_recursion_depth + 1,
+            inp: Box::new(Cursor::new(data)),
+            line_prefix: self.config.entry_prefix(&self.line_prefix, &id),
+            postprocess: self.postprocess,
+            config: self.config.clone(),
+        }))
+    }
+}
+
+/// loose objects are zlib compressed, with a `<type> <size>\0` header
+fn read_loose_object(inp: &mut dyn Read) -> Result<(String, Vec<u8>)> {
+    let mut data = Vec::new();
+    flate2::read::ZlibDecoder::new(inp).read_to_end(&mut data)?;
+    let nul = data
+        .iter()
+        .position(|&b| b == 0)
+        .context("not a git object")?;
+    let header = String::from_utf8_lossy(&data[..nul]).into_owned();
+    let kind = header.split(' ').next().unwrap_or("").to_string();
+    match kind.as_str() {
+        "commit" | "tree" | "blob" | "tag" => {}
+        _ => return Err(format_err!("not a git object")),
+    }
+    data.drain(..=nul);
+    Ok((kind, data))
+}
+
+/// the object ids in a pack index (.idx) file, in version 1 or 2 format
+fn read_pack_index(idx: &[u8]) -> Result<Vec<Oid>> {
+    let (fanout_start, entry_len, entry_offset) = if idx.starts_with(b"\xfftOc") {
+        (8, 20, 0)
+    } else {
+        (0, 24, 4)
+    };
+    let count_pos = fanout_start + 255 * 4;
+    let count = idx
+        .get(count_pos..count_pos + 4)
+        .context("invalid pack index")?;
+    let count = u32::from_be_bytes([count[0], count[1], count[2], count[3]]) as usize;
+    let entries_start = fanout_start + 256 * 4;
+    (0..count)
+        .map(|i| {
+            let start = entries_start + i * entry_len + entry_offset;
+            let id = idx.get(start..start + 20).context("invalid pack index")?;
+            Ok(Oid::from_bytes(id)?)
+        })
+        .collect()
+}
+
+struct GitPackIter {
+    repo: Repository,
+    ids: std::vec::IntoIter<Oid>,
+    names: HashMap<Oid, String>,
+    output: ObjectOutput,
+}
+
+impl AdaptedFilesIter for GitPackIter {
+    fn next<'a>(&'a mut self) -> Option<AdaptInfo<'a>> {
+        loop {
+            let id = self.ids.next()?;
+            let odb = match self.repo.odb() {
+                Ok(odb) => odb,
+                Err(e) => {
+                    warn!("could not open git object database: {}", e);
+                    return None;
+                }
+            };
+            let obj = match odb.read(id) {
+                Ok(obj) => obj,
+                Err(e) => {
+                    warn!("could not read git object {}: {}", id, e);
+                    continue;
+                }
+            };
+            let kind = obj.kind().str();
+            match self.output.object(
+                &id.to_string(),
+                kind,
+                obj.data().to_vec(),
+                self.names.get(&id).map(|n| n.as_str()),
+            ) {
+                Ok(Some(ai)) => return Some(ai),
+                Ok(None) => {}
+                Err(e) => warn!("could not read git object {}: {}", id, e),
+            }
+        }
+    }
+}
+
+fn adapt_pack<'a>(pack: &Path, output: ObjectOutput) -> Result<AdaptedFilesIterBox<'a>> {
+    // packs live in <git dir>/objects/pack/
+    let git_dir = pack
+        .ancestors()
+        .nth(3)
+        .context("pack file is not in a git repository")?;
+    let repo = Repository::open(git_dir)
+        .with_context(|| format!("opening git repository {}", git_dir.to_string_lossy()))?;
+    let ids = read_pack_index(&std::fs::read(pack.with_extension("idx"))?)?;
+    debug!("pack contains {} objects", ids.len());
+    // blobs don't know their file name, so collect the names from the trees in this pack
+    let mut names = HashMap::new();
+    for id in &ids {
+        if let Ok(tree) = repo.find_tree(*id) {
+            for entry in tree.iter() {
+                if entry.kind() == Some(ObjectType::Blob) {
+                    if let Some(name) = entry.name() {
+                        names.insert(entry.id(), name.to_string());
+                    }
+                }
+            }
+        }
+    }
+    Ok(Box::new(GitPackIter {
+        repo,
+        ids: ids.into_iter(),
+        names,
+        output,
+    }))
+}
+
+/// every blob reachable from any ref, each one only once (with the path and newest commit it was found in)
+struct GitHistoryIter {
+    repo: Repository,
+    commits: std::vec::IntoIter<Oid>,
+    /// short id of the commit the pending blobs were found in
+    commit: String,
+    /// blobs of the current commit that were not in a commit walked before: (id, path)
+    pending: std::vec::IntoIter<(Oid, String)>,
+    /// trees and blobs that were already walked
+    seen: HashSet<Oid>,
+    output: ObjectOutput,
+}
+
+impl GitHistoryIter {
+    fn new(repo: Repository, output: ObjectOutput) -> Result<GitHistoryIter> {
+        let mut walk = repo.revwalk()?;
+        // newest first. Commits with the same time are ordered by their parents, so the order does not change between runs
+        walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+        // all branches, tags, remotes, ...
+        walk.push_glob("*")?;
+        if repo.head().is_ok() {
+            walk.push_head()?;
+        }
+        let commits = walk.collect::<std::result::Result<Vec<_>, _>>()?;
+        debug!("walking {} commits", commits.len());
+        Ok(GitHistoryIter {
+            repo,
+            commits: commits.into_iter(),
+            commit: String::new(),
+            pending: vec![].into_iter(),
+            seen: HashSet::new(),
+            output,
+        })
+    }
+
+    /// collect the blobs of the commit that were not seen yet
+    fn walk_commit(&mut self, id: Oid) -> Result<()> {
+        let tree = self.repo.find_commit(id)?.tree()?;
+        let seen = &mut self.seen;
+        let mut blobs = vec![];
+        tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+            match entry.kind() {
+                // everything below a known tree is known as well
+                Some(ObjectType::Tree) if !seen.insert(entry.id()) => TreeWalkResult::Skip,
+                Some(ObjectType::Blob) => {
+                    if seen.insert(entry.id()) {
+                        let name = String::from_utf8_lossy(entry.name_bytes());
+                        blobs.push((entry.id(), format!("{}{}", dir, name)));
+                    }
+                    TreeWalkResult::Ok
+                }
+                _ => TreeWalkResult::Ok,
+            }
+        })?;
+        self.commit = id.to_string()[..7].to_string();
+        self.pending = blobs.into_iter();
+        Ok(())
+    }
+}
+
+impl AdaptedFilesIter for GitHistoryIter {
+    fn next<'a>(&'a mut self) -> Option<AdaptInfo<'a>> {
+        loop {
+            while let Some((id, path)) = self.pending.next() {
+                let data = match self.repo.find_blob(id) {
+                    Ok(blob) => blob.content().to_vec(),
+                    Err(e) => {
+                        warn!("could not read git blob {} ({}): {}", id, path, e);
+                        continue;
+                    }
+                };
+                if data.is_empty() {
+                    continue;
+                }
+                return Some(AdaptInfo {
+                    line_prefix: self.output.config.entry_prefix(
+                        &self.output.line_prefix,
+                        &format!("{}:{}", self.commit, path),
+                    ),
+                    filepath_hint: PathBuf::from(path),
+                    is_real_file: false,
+                    archive_recursion_depth: self.output.archive_recursion_depth + 1,
+                    inp: Box::new(Cursor::new(data)),
+                    postprocess: self.output.postprocess,
+                    config: self.output.config.clone(),
+                });
+            }
+            let commit = self.commits.next()?;
+            if let Err(e) = self.walk_commit(commit) {
+                warn!("could not read git commit {}: {}", commit, e);
+            }
+        }
+    }
+}
+
+fn is_head(filepath: &Path) -> bool {
+    filepath.file_name() == Some(OsStr::new("HEAD"))
+}
+
+impl FileAdapter for GitAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+        } = ai;
+        let output = ObjectOutput {
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+        };
+        if is_head(&filepath_hint) {
+            if !output.config.git.history || !is_real_file {
+                return Ok(Box::new(SingleAdaptedFileAsIter::new(AdaptInfo {
+                    filepath_hint: PathBuf::from("HEAD.txt"),
+                    is_real_file: false,
+                    archive_recursion_depth,
+                    inp,
+                    line_prefix: output.line_prefix,
+                    postprocess,
+                    config: output.config,
+                })));
+            }
+            let git_dir = filepath_hint
+                .parent()
+                .context("HEAD is not in a git directory")?;
+            let repo = Repository::open(git_dir)
+                .with_context(|| format!("opening git repository {}", git_dir.to_string_lossy()))?;
+            return Ok(Box::new(GitHistoryIter::new(repo, output)?));
+        }
+        if filepath_hint.extension() == Some(OsStr::new("pack")) {
+            if !is_real_file {
+                // packs need the index file and the rest of the repository (for deltas)
+                return Ok(Box::new(SingleAdaptedFileAsIter::new(AdaptInfo {
+                    filepath_hint: PathBuf::from("skipped.txt"),
+                    is_real_file: false,
+                    archive_recursion_depth,
+                    inp: Box::new(Cursor::new(b"[rga: skipping git pack in archive]".to_vec())),
+                    line_prefix: output.line_prefix,
+                    postprocess,
+                    config: output.config,
+                })));
+            }
+            return adapt_pack(&filepath_hint, output);
+        }
+        // loose object: the id is the directory name + the file name
+        let id = format!(
+            "{}{}",
+            filepath_hint
+                .parent()
+                .and_then(|p| p.file_name())
+                .unwrap_or_default()
+                .to_string_lossy(),
+            filepath_hint
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+        );
+        let (kind, data) = read_loose_object(&mut inp)?;
+        Ok(match output.object(&id, &kind, data, None)? {
+            Some(ai) => Box::new(SingleAdaptedFileAsIter::new(ai)),
+            None => Box::new(MultiAdaptedFilesIter::new(vec![])),
+        })
+    }
+
+    /// the history changes without HEAD being modified, so key it on the commits all refs point to
+    fn cache_key_extra(&self, filepath: &Path, config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        if !config.git.history || !is_head(filepath) {
+            return Ok(None);
+        }
+        let repo = Repository::open(
+            filepath
+                .parent()
+                .context("HEAD is not in a git directory")?,
+        )?;
+        let mut key = vec![];
+        if let Some(head) = repo.head().ok().and_then(|h| h.target()) {
+            key.extend_from_slice(head.as_bytes());
+        }
+        for reference in repo.references()? {
+            let reference = reference?;
+            if let Some(target) = reference.target() {
+                key.extend_from_slice(reference.name_bytes());
+                key.push(0);
+                key.extend_from_slice(target.as_bytes());
+            }
+        }
+        Ok(Some(key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+    use flate2::{write::ZlibEncoder, Compression};
+
+    fn loose_object(kind: &str, content: &[u8]) -> Result<Vec<u8>> {
+        let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+        write!(enc, "{} {}\0", kind, content.len())?;
+        enc.write_all(content)?;
+        Ok(enc.finish()?)
+    }
+
+    #[test]
+    fn loose_blob() -> Result<()> {
+        let adapter: Box<dyn FileAdapter> = Box::new(GitAdapter::new());
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("repo/.git/objects/95/d09f2b10159347eece71399a7e2e907ea3df4f"),
+            Box::new(Cursor::new(loose_object("blob", b"hello world")?)),
+        );
+        let buf = adapted_to_vec(adapter.adapt(a, &d)?)?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:95d09f2b10159347eece71399a7e2e907ea3df4f: hello world\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn history() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let repo = Repository::init(dir.path())?;
+        let sig = git2::Signature::new("rga", "rga@example.com", &git2::Time::new(1600000000, 0))?;
+        let mut parents = vec![];
+        for files in &[
+            vec![("notes.txt", "the password is hunter2")],
+            vec![("notes.txt", "nothing to see here"), ("empty.txt", "")],
+        ] {
+            let mut tree = repo.treebuilder(None)?;
+            for (name, content) in files {
+                tree.insert(name, repo.blob(content.as_bytes())?, 0o100644)?;
+            }
+            let tree = repo.find_tree(tree.write()?)?;
+            let parent_commits = parents
+                .iter()
+                .map(|id| repo.find_commit(*id))
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            let id = repo.commit(
+                Some("HEAD"),
+                &sig,
+                &sig,
+                "commit",
+                &tree,
+                &parent_commits.iter().collect::<Vec<_>>(),
+            )?;
+            parents = vec![id];
+        }
+        let mut config = RgaConfig::default();
+        config.git.history = true;
+        let mut iter = GitHistoryIter::new(
+            Repository::open(dir.path())?,
+            ObjectOutput {
+                line_prefix: "PREFIX:".to_string(),
+                archive_recursion_depth: 0,
+                postprocess: true,
+                config,
+            },
+        )?;
+        let mut found = vec![];
+        while let Some(mut ai) = iter.next() {
+            let mut content = String::new();
+            ai.inp.read_to_string(&mut content)?;
+            found.push(format!("{}{}", ai.line_prefix, content));
+        }
+        let newest = &parents[0].to_string()[..7];
+        assert_eq!(found.len(), 2);
+        assert_eq!(
+            found[0],
+            format!("PREFIX:{}:notes.txt: nothing to see here", newest)
+        );
+        assert!(found[1].ends_with(":notes.txt: the password is hunter2"));
+        Ok(())
+    }
+
+    #[test]
+    fn tree_entries() -> Result<()> {
+        let mut tree = b"100644 README.md\0".to_vec();
+        tree.extend(&[0xab; 20]);
+        tree.extend(b"40000 src\0");
+        tree.extend(&[0x01; 20]);
+        assert_eq!(
+            format_tree(&tree)?,
+            format!(
+                "100644 blob {}\tREADME.md\n040000 tree {}\tsrc",
+                "ab".repeat(20),
+                "01".repeat(20)
+            )
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use serde::Deserialize;
+use serde_json::Value;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["json", "jsonl", "ndjson", "geojson", "yaml", "yml"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "gron".to_owned(),
+        version: 1,
+        description: "Flattens JSON and YAML documents into `path.to.key = value` lines (like gron), so every match shows its full key path. Disabled by default, enable it with --rga-adapters=+gron".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/json".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct GronAdapter;
+
+impl GronAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(GronAdapter))
+    }
+}
+impl GetMetadata for GronAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .map(|c| c.is_alphabetic() || c == '_' || c == '$')
+        .unwrap_or(false)
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+pub fn flatten(value: &Value, path: &mut String, oup: &mut dyn Write) -> Result<()> {
+    match value {
+        Value::Object(o) if !o.is_empty() => {
+            for (k, v) in o {
+                let len = path.len();
+                if is_identifier(k) {
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(k);
+                } else {
+                    path.push_str(&format!("[{}]", Value::String(k.clone())));
+                }
+                flatten(v, path, oup)?;
+                path.truncate(len);
+            }
+        }
+        Value::Array(a) if !a.is_empty() => {
+            for (i, v) in a.iter().enumerate() {
+                let len = path.len();
+                path.push_str(&format!("[{}]", i));
+                flatten(v, path, oup)?;
+                path.truncate(len);
+            }
+        }
+        // scalars and empty containers
+        v => writeln!(
+            oup,
+            "{} = {}",
+            if path.is_empty() { "." } else { path.as_str() },
+            v
+        )?,
+    }
+    Ok(())
+}
+
+fn parse_documents(filepath_hint: &Path, data: &str) -> Result<Vec<Value>> {
+    let ext = filepath_hint
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    Ok(match ext.as_str() {
+        "yaml" | "yml" => serde_yaml::Deserializer::from_str(data)
+            .map(Value::deserialize)
+            .collect::<std::result::Result<_, _>>()?,
+        "jsonl" | "ndjson" => data
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<_, _>>()?,
+        _ => vec![serde_json::from_str(data)?],
+    })
+}
+
+fn adapt_gron(filepath_hint: &Path, data: &str, oup: &mut dyn Write) -> Result<()> {
+    let documents = match parse_documents(filepath_hint, data) {
+        Ok(documents) => documents,
+        Err(e) => {
+            debug!(
+                "could not parse {}, passing through: {}",
+                filepath_hint.display(),
+                e
+            );
+            oup.write_all(data.as_bytes())?;
+            return Ok(());
+        }
+    };
+    let multiple = documents.len() > 1;
+    for (i, document) in documents.iter().enumerate() {
+        // multiple documents (yaml streams, json lines) are numbered
+        let mut path = if multiple {
+            format!("[{}]", i)
+        } else {
+            String::new()
+        };
+        flatten(document, &mut path, oup)?;
+    }
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for GronAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            filepath_hint,
+            mut inp,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data)?;
+        adapt_gron(&filepath_hint, &String::from_utf8_lossy(&data), oup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gron(name: &str, data: &str) -> Result<String> {
+        let mut oup = Vec::new();
+        adapt_gron(Path::new(name), data, &mut oup)?;
+        Ok(String::from_utf8(oup)?)
+    }
+
+    #[test]
+    fn json() -> Result<()> {
+        assert_eq!(
+            gron(
+                "package.json",
+                r#"{"name":"rga","scripts":{"build:release":"cargo build"},"keywords":["grep",1,null],"dependencies":{}}"#
+            )?,
+            "name = \"rga\"\n\
+             scripts[\"build:release\"] = \"cargo build\"\n\
+             keywords[0] = \"grep\"\n\
+             keywords[1] = 1\n\
+             keywords[2] = null\n\
+             dependencies = {}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn yaml_documents() -> Result<()> {
+        assert_eq!(
+            gron(
+                "k8s.yaml",
+                "kind: Service\n---\nkind: Deployment\nspec:\n  replicas: 2\n"
+            )?,
+            "[0].kind = \"Service\"\n\
+             [1].kind = \"Deployment\"\n\
+             [1].spec.replicas = 2\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,312 @@
+//! Apple iWork documents (Pages, Numbers, Keynote) are zip files (or bundle directories with an Index.zip)
+//! of .iwa files: snappy compressed streams of protobuf messages, see https://github.com/obriensp/iWorkFileFormat.
+//! Since the protobuf schemas are not public, all text fields of all messages are extracted.
+
+use super::*;
+use crate::adapted_iter::SingleAdaptedFileAsIter;
+use anyhow::*;
+use lazy_static::lazy_static;
+use std::io::{BufRead, BufReader, Cursor};
+
+static EXTENSIONS: &[&str] = &["pages", "numbers", "key", "iwa"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "iwork".to_owned(),
+        version: 1,
+        description: "Extracts the text, table cells and slide text of Apple iWork documents (Pages, Numbers, Keynote) by decoding their .iwa streams".to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/vnd.apple.pages".to_owned()),
+            FileMatcher::MimeType("application/vnd.apple.numbers".to_owned()),
+            FileMatcher::MimeType("application/vnd.apple.keynote".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct IworkAdapter;
+
+impl IworkAdapter {
+    pub fn new() -> IworkAdapter {
+        IworkAdapter
+    }
+}
+impl GetMetadata for IworkAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the concatenated chunks of an .iwa file: each chunk has a zero byte, a 24 bit length
+/// and raw snappy data (without the crc of the snappy framing format)
+fn iwa_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    let mut pos = 0;
+    let mut decoder = snap::raw::Decoder::new();
+    while pos < data.len() {
+        let header = data
+            .get(pos..pos + 4)
+            .ok_or_else(|| format_err!("truncated iwa chunk header"))?;
+        if header[0] != 0 {
+            return Err(format_err!("unknown iwa chunk type {}", header[0]));
+        }
+        let len = u32::from_le_bytes([header[1], header[2], header[3], 0]) as usize;
+        let chunk = data
+            .get(pos + 4..pos + 4 + len)
+            .ok_or_else(|| format_err!("truncated iwa chunk"))?;
+        out.extend(decoder.decompress_vec(chunk)?);
+        pos += 4 + len;
+    }
+    Ok(out)
+}
+
+fn varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut result = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = *data.get(*pos)?;
+        *pos += 1;
+        result |= ((b & 0x7F) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Some(result);
+        }
+    }
+    None
+}
+
+/// the fields of a protobuf message as (field number, length delimited value or None)
+fn fields(data: &[u8]) -> Option<Vec<(u64, Option<&[u8]>)>> {
+    let mut res = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        let key = varint(data, &mut pos)?;
+        let value = match key & 7 {
+            0 => {
+                varint(data, &mut pos)?;
+                None
+            }
+            1 => {
+                pos += 8;
+                None
+            }
+            2 => {
+                let len = varint(data, &mut pos)? as usize;
+                let value = data.get(pos..pos.checked_add(len)?)?;
+                pos += len;
+                Some(value)
+            }
+            5 => {
+                pos += 4;
+                None
+            }
+            _ => return None,
+        };
+        if pos > data.len() || key >> 3 == 0 {
+            return None;
+        }
+        res.push((key >> 3, value));
+    }
+    Some(res)
+}
+
+fn is_text(bytes: &[u8]) -> Option<&str> {
+    let s = std::str::from_utf8(bytes).ok()?;
+    if s.chars().any(|c| c.is_control() && c != '\n' && c != '\t')
+        || !s.chars().any(char::is_alphanumeric)
+    {
+        return None;
+    }
+    Some(s)
+}
+
+/// all text in a message and its sub messages, in field order.
+/// Returns None if the data is not a valid protobuf message
+fn message_text(data: &[u8], depth: usize, out: &mut Vec<String>) -> Option<()> {
+    let fields = fields(data)?;
+    for (_, value) in fields {
+        let value = match value {
+            Some(value) => value,
+            None => continue,
+        };
+        if let Some(text) = is_text(value) {
+            // paragraph and line separators, attachment placeholders
+            let text = text
+                .replace(|c: char| c == '\u{2028}' || c == '\u{2029}', "\n")
+                .replace('\u{fffc}', "");
+            out.extend(
+                text.lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty())
+                    .map(String::from),
+            );
+        } else if depth < 32 {
+            let mut nested = vec![];
+            // packed numbers and other binary data don't parse as messages
+            if message_text(value, depth + 1, &mut nested).is_some() {
+                out.extend(nested);
+            }
+        }
+    }
+    Some(())
+}
+
+/// the text of all messages in a decompressed .iwa stream.
+/// The stream is a sequence of (varint length, ArchiveInfo, message payloads),
+/// the ArchiveInfo lists the lengths of the messages in its MessageInfos (field 2, length in field 3)
+pub fn iwa_text(data: &[u8]) -> Result<Vec<String>> {
+    let mut out = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        let info_len =
+            varint(data, &mut pos).ok_or_else(|| format_err!("invalid archive info"))? as usize;
+        let info = pos
+            .checked_add(info_len)
+            .and_then(|end| data.get(pos..end))
+            .ok_or_else(|| format_err!("truncated archive info"))?;
+        pos += info_len;
+        let message_infos =
+            fields(info).ok_or_else(|| format_err!("invalid archive info at {}", pos))?;
+        for (_, message_info) in message_infos.into_iter().filter(|(f, _)| *f == 2) {
+            let message_info = message_info.unwrap_or_default();
+            // the length is a varint, so parse the message info manually
+            let mut len = 0;
+            let mut p = 0;
+            while p < message_info.len() {
+                let key = varint(message_info, &mut p).unwrap_or(0);
+                match key & 7 {
+                    0 if key >> 3 == 3 => len = varint(message_info, &mut p).unwrap_or(0) as usize,
+                    0 => {
+                        varint(message_info, &mut p);
+                    }
+                    2 => {
+                        let l = varint(message_info, &mut p).unwrap_or(0) as usize;
+                        p = match p.checked_add(l) {
+                            Some(p) => p,
+                            None => break,
+                        };
+                    }
+                    _ => break,
+                }
+            }
+            let message = pos
+                .checked_add(len)
+                .and_then(|end| data.get(pos..end))
+                .ok_or_else(|| format_err!("truncated message"))?;
+            pos += len;
+            message_text(message, 0, &mut out);
+        }
+    }
+    Ok(out)
+}
+
+impl FileAdapter for IworkAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+        } = ai;
+        let mut inp = BufReader::new(inp);
+        let is_zip = inp.fill_buf()?.starts_with(b"PK\x03\x04");
+        let is_iwa = filepath_hint
+            .extension()
+            .map(|e| e.eq_ignore_ascii_case("iwa"))
+            .unwrap_or(false);
+        if is_zip {
+            // the .iwa files in it are adapted by recursion
+            return super::zip::ZipAdapter::new().adapt(
+                AdaptInfo {
+                    filepath_hint,
+                    is_real_file,
+                    inp: Box::new(inp),
+                    line_prefix,
+                    archive_recursion_depth,
+                    postprocess,
+                    config,
+                },
+                detection_reason,
+            );
+        }
+        let output: ReadBox = if is_iwa {
+            let mut data = Vec::new();
+            inp.read_to_end(&mut data)?;
+            let mut text = iwa_text(&iwa_decompress(&data)?)?.join("\n");
+            text.push('\n');
+            Box::new(Cursor::new(text.into_bytes()))
+        } else {
+            // some other file with the same extension, e.g. a .key file with a PEM key
+            Box::new(inp)
+        };
+        Ok(Box::new(SingleAdaptedFileAsIter::new(AdaptInfo {
+            filepath_hint: PathBuf::from(format!("{}.txt", filepath_hint.to_string_lossy())),
+            is_real_file: false,
+            inp: output,
+            line_prefix,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+        })))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn field(number: u64, value: &[u8]) -> Vec<u8> {
+        let mut out = vec![(number << 3 | 2) as u8, value.len() as u8];
+        out.extend(value);
+        out
+    }
+
+    #[test]
+    fn iwa() -> Result<()> {
+        // a StorageArchive-like message: some ints, the text, a nested message with a string
+        let mut storage = vec![0x08, 0x00];
+        storage.extend(field(
+            3,
+            "Hello world\u{2029}Second \u{fffc}paragraph".as_bytes(),
+        ));
+        storage.extend(field(5, &field(3, b"cell value")));
+        storage.extend(field(6, &[0x01, 0x02, 0xff, 0x80]));
+        let mut message_info = vec![0x08, 0xd1, 0x0f]; // type 2001
+        message_info.extend(&[0x18, storage.len() as u8]);
+        let info = field(2, &message_info);
+        let mut stream = vec![info.len() as u8];
+        stream.extend(&info);
+        stream.extend(&storage);
+
+        let compressed = snap::raw::Encoder::new().compress_vec(&stream)?;
+        let mut iwa = vec![0];
+        iwa.extend(&(compressed.len() as u32).to_le_bytes()[..3]);
+        iwa.extend(compressed);
+
+        assert_eq!(
+            iwa_text(&iwa_decompress(&iwa)?)?,
+            vec!["Hello world", "Second paragraph", "cell value"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn overflowing_lengths() {
+        // an archive info length of u64::MAX
+        let mut stream = vec![0xff; 9];
+        stream.push(0x01);
+        assert!(iwa_text(&stream).is_err());
+    }
+}
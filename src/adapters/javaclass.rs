@@ -0,0 +1,364 @@
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["class"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "javaclass".to_owned(),
+        version: 1,
+        description: "Outputs the class name, fields, method signatures, referenced classes and string constants of Java .class files (and .jar files through the zip adapter)".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-java-applet".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct JavaClassAdapter;
+
+impl JavaClassAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(JavaClassAdapter))
+    }
+}
+impl GetMetadata for JavaClassAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+enum Constant {
+    Utf8(String),
+    Class(u16),
+    String(u16),
+    Other,
+}
+
+struct ClassReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ClassReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos + len;
+        let b = self
+            .data
+            .get(self.pos..end)
+            .context("unexpected end of class file")?;
+        self.pos = end;
+        Ok(b)
+    }
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+struct ClassFile {
+    constants: Vec<Constant>,
+}
+
+impl ClassFile {
+    fn utf8(&self, index: u16) -> Result<&str> {
+        match self.constants.get(index as usize) {
+            Some(Constant::Utf8(s)) => Ok(s),
+            _ => Err(format_err!("invalid constant pool index {}", index)),
+        }
+    }
+    fn class_name(&self, index: u16) -> Result<String> {
+        match self.constants.get(index as usize) {
+            Some(Constant::Class(name)) => Ok(binary_name(self.utf8(*name)?)),
+            _ => Err(format_err!("invalid class index {}", index)),
+        }
+    }
+}
+
+/// `java/lang/String` -> `java.lang.String`. Array classes are stored as descriptors
+fn binary_name(internal: &str) -> String {
+    if internal.starts_with('[') {
+        parse_descriptor(&mut internal.chars().peekable())
+    } else {
+        internal.replace('/', ".")
+    }
+}
+
+/// convert one field type descriptor (e.g. `[Ljava/lang/String;`) to its java syntax
+fn parse_descriptor(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    // the dimensions of arrays are counted instead of recursing, so a long run of [ in an invalid file can't overflow
+    // the stack
+    let mut dimensions = 0;
+    while chars.peek() == Some(&'[') {
+        chars.next();
+        dimensions += 1;
+    }
+    let element = match chars.next() {
+        Some('B') => "byte".to_string(),
+        Some('C') => "char".to_string(),
+        Some('D') => "double".to_string(),
+        Some('F') => "float".to_string(),
+        Some('I') => "int".to_string(),
+        Some('J') => "long".to_string(),
+        Some('S') => "short".to_string(),
+        Some('Z') => "boolean".to_string(),
+        Some('V') => "void".to_string(),
+        Some('L') => chars
+            .take_while(|&c| c != ';')
+            .collect::<String>()
+            .replace('/', "."),
+        Some(c) => c.to_string(),
+        None => "".to_string(),
+    };
+    element + &"[]".repeat(dimensions)
+}
+
+/// `([Ljava/lang/String;)V` with name main -> `void main(java.lang.String[])`
+fn method_signature(name: &str, descriptor: &str) -> String {
+    let mut chars = descriptor.chars().peekable();
+    let mut params = vec![];
+    if chars.peek() == Some(&'(') {
+        chars.next();
+        while chars.peek().map(|&c| c != ')').unwrap_or(false) {
+            params.push(parse_descriptor(&mut chars));
+        }
+        chars.next();
+    }
+    let ret = parse_descriptor(&mut chars);
+    format!("{} {}({})", ret, name, params.join(", "))
+}
+
+fn modifiers(flags: u16, is_method: bool) -> String {
+    let mut out = vec![];
+    let names: &[(u16, &str)] = &[
+        (0x0001, "public"),
+        (0x0002, "private"),
+        (0x0004, "protected"),
+        (0x0008, "static"),
+        (0x0010, "final"),
+        (0x0400, "abstract"),
+    ];
+    for (flag, name) in names {
+        if flags & flag != 0 {
+            out.push(*name);
+        }
+    }
+    let specific: &[(u16, &str)] = if is_method {
+        &[(0x0020, "synchronized"), (0x0100, "native")]
+    } else {
+        &[(0x0040, "volatile"), (0x0080, "transient")]
+    };
+    for (flag, name) in specific {
+        if flags & flag != 0 {
+            out.push(*name);
+        }
+    }
+    out.iter().map(|m| format!("{} ", m)).collect()
+}
+
+fn read_constants(r: &mut ClassReader) -> Result<Vec<Constant>> {
+    let count = r.u16()? as usize;
+    // index 0 is unused
+    let mut constants = vec![Constant::Other];
+    while constants.len() < count {
+        let tag = r.bytes(1)?[0];
+        let constant = match tag {
+            1 => {
+                let len = r.u16()? as usize;
+                // "modified utf-8", which is the same as utf-8 for everything but NUL and supplementary characters
+                Constant::Utf8(String::from_utf8_lossy(r.bytes(len)?).into_owned())
+            }
+            7 => Constant::Class(r.u16()?),
+            8 => Constant::String(r.u16()?),
+            3 | 4 | 9 | 10 | 11 | 12 | 17 | 18 => {
+                r.bytes(4)?;
+                Constant::Other
+            }
+            5 | 6 => {
+                r.bytes(8)?;
+                // longs and doubles take up two entries
+                constants.push(Constant::Other);
+                Constant::Other
+            }
+            15 => {
+                r.bytes(3)?;
+                Constant::Other
+            }
+            16 | 19 | 20 => {
+                r.bytes(2)?;
+                Constant::Other
+            }
+            t => return Err(format_err!("unknown constant pool tag {}", t)),
+        };
+        constants.push(constant);
+    }
+    Ok(constants)
+}
+
+/// read the fields or methods of the class
+fn read_members(
+    r: &mut ClassReader,
+    class: &ClassFile,
+    is_method: bool,
+    oup: &mut dyn Write,
+) -> Result<()> {
+    let count = r.u16()?;
+    for _ in 0..count {
+        let flags = r.u16()?;
+        let name = class.utf8(r.u16()?)?;
+        let descriptor = class.utf8(r.u16()?)?;
+        let attribute_count = r.u16()?;
+        for _ in 0..attribute_count {
+            r.u16()?;
+            let len = r.u32()? as usize;
+            r.bytes(len)?;
+        }
+        // skip compiler generated members
+        if flags & 0x1000 != 0 {
+            continue;
+        }
+        if is_method {
+            writeln!(
+                oup,
+                "method: {}{}",
+                modifiers(flags, true),
+                method_signature(name, descriptor)
+            )?;
+        } else {
+            writeln!(
+                oup,
+                "field: {}{} {}",
+                modifiers(flags, false),
+                parse_descriptor(&mut descriptor.chars().peekable()),
+                name
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn adapt_class(data: &[u8], oup: &mut dyn Write) -> Result<()> {
+    let mut r = ClassReader { data, pos: 0 };
+    if r.u32()? != 0xcafe_babe {
+        return Err(format_err!("not a java class file"));
+    }
+    // version
+    r.u32()?;
+    let class = ClassFile {
+        constants: read_constants(&mut r)?,
+    };
+    let flags = r.u16()?;
+    let this_class = r.u16()?;
+    let super_class = r.u16()?;
+    let kind = if flags & 0x2000 != 0 {
+        "@interface"
+    } else if flags & 0x0200 != 0 {
+        "interface"
+    } else if flags & 0x4000 != 0 {
+        "enum"
+    } else {
+        "class"
+    };
+    write!(oup, "{} {}", kind, class.class_name(this_class)?)?;
+    if super_class != 0 {
+        write!(oup, " extends {}", class.class_name(super_class)?)?;
+    }
+    let interface_count = r.u16()?;
+    let mut interfaces = vec![];
+    for _ in 0..interface_count {
+        interfaces.push(class.class_name(r.u16()?)?);
+    }
+    if !interfaces.is_empty() {
+        write!(oup, " implements {}", interfaces.join(", "))?;
+    }
+    writeln!(oup)?;
+    read_members(&mut r, &class, false, oup)?;
+    read_members(&mut r, &class, true, oup)?;
+
+    for (i, constant) in class.constants.iter().enumerate() {
+        match constant {
+            Constant::Class(_) if i as u16 != this_class && i as u16 != super_class => {
+                writeln!(oup, "uses: {}", class.class_name(i as u16)?)?;
+            }
+            Constant::String(s) => writeln!(oup, "string: {}", class.utf8(*s)?)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for JavaClassAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo { mut inp, .. } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data)?;
+        adapt_class(&data, oup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn utf8(s: &str) -> Vec<u8> {
+        let mut out = vec![1];
+        out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+        out.extend_from_slice(s.as_bytes());
+        out
+    }
+
+    #[test]
+    fn hello_class() -> Result<()> {
+        let mut class = vec![0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 52];
+        class.extend_from_slice(&11u16.to_be_bytes());
+        class.extend(utf8("Hello")); // 1
+        class.extend(&[7, 0, 1]); // 2
+        class.extend(utf8("java/lang/Object")); // 3
+        class.extend(&[7, 0, 3]); // 4
+        class.extend(utf8("main")); // 5
+        class.extend(utf8("([Ljava/lang/String;)V")); // 6
+        class.extend(utf8("Hello, world")); // 7
+        class.extend(&[8, 0, 7]); // 8
+        class.extend(utf8("java/io/PrintStream")); // 9
+        class.extend(&[7, 0, 9]); // 10
+                                  // public class Hello extends Object, no interfaces or fields
+        class.extend(&[0, 0x21, 0, 2, 0, 4, 0, 0, 0, 0]);
+        // public static void main(String[]) without attributes
+        class.extend(&[0, 1, 0, 0x09, 0, 5, 0, 6, 0, 0]);
+        // no class attributes
+        class.extend(&[0, 0]);
+
+        let mut oup = Vec::new();
+        adapt_class(&class, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "class Hello extends java.lang.Object\n\
+             method: public static void main(java.lang.String[])\n\
+             string: Hello, world\n\
+             uses: java.io.PrintStream\n"
+        );
+
+        assert_eq!(binary_name("[[I"), "int[][]");
+        let nested = format!("{}I", "[".repeat(60000));
+        assert!(binary_name(&nested).ends_with("int[][]"));
+        Ok(())
+    }
+}
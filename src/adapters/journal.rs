@@ -0,0 +1,276 @@
+use super::*;
+use anyhow::*;
+use chrono::NaiveDateTime;
+use lazy_static::lazy_static;
+use log::*;
+use spawning::read_input;
+use std::collections::HashMap;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+// journal files that were not closed cleanly are renamed to .journal~
+static EXTENSIONS: &[&str] = &["journal", "journal~"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "journal".to_owned(),
+        version: 1,
+        description: "Outputs the messages of systemd journal files with timestamp and unit name"
+            .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct JournalAdapter;
+
+impl JournalAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(JournalAdapter))
+    }
+}
+impl GetMetadata for JournalAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const OBJECT_DATA: u8 = 1;
+const OBJECT_ENTRY: u8 = 3;
+const OBJECT_HEADER_LEN: usize = 16;
+
+const COMPRESSED_XZ: u8 = 1;
+const COMPRESSED_LZ4: u8 = 2;
+const COMPRESSED_ZSTD: u8 = 4;
+
+/// incompatible flag for the smaller object format of systemd 252+
+const HEADER_INCOMPATIBLE_COMPACT: u32 = 16;
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32> {
+    let b = data
+        .get(pos..pos + 4)
+        .context("unexpected end of journal file")?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn u64_at(data: &[u8], pos: usize) -> Result<u64> {
+    let b = data
+        .get(pos..pos + 8)
+        .context("unexpected end of journal file")?;
+    let mut a = [0u8; 8];
+    a.copy_from_slice(b);
+    Ok(u64::from_le_bytes(a))
+}
+
+struct Journal<'a> {
+    data: &'a [u8],
+    compact: bool,
+}
+
+impl<'a> Journal<'a> {
+    /// (type, flags, object including its header)
+    fn object(&self, offset: usize) -> Result<(u8, u8, &'a [u8])> {
+        let header = self
+            .data
+            .get(offset..offset.saturating_add(OBJECT_HEADER_LEN))
+            .context("journal object out of bounds")?;
+        let size = u64_at(header, 8)? as usize;
+        if size < OBJECT_HEADER_LEN {
+            return Err(format_err!("invalid journal object at {}", offset));
+        }
+        let object = self
+            .data
+            .get(offset..offset.saturating_add(size))
+            .context("journal object out of bounds")?;
+        Ok((header[0], header[1], object))
+    }
+
+    /// the `FIELD=value` payload of a data object
+    fn field(&self, offset: usize) -> Result<Vec<u8>> {
+        let (kind, flags, object) = self.object(offset)?;
+        if kind != OBJECT_DATA {
+            return Err(format_err!("expected data object at {}", offset));
+        }
+        let payload = &object[if self.compact { 72 } else { 64 }.min(object.len())..];
+        let mut out = Vec::new();
+        if flags & COMPRESSED_XZ != 0 {
+            xz2::read::XzDecoder::new(payload).read_to_end(&mut out)?;
+        } else if flags & COMPRESSED_ZSTD != 0 {
+            zstd::stream::read::Decoder::new(payload)?.read_to_end(&mut out)?;
+        } else if flags & COMPRESSED_LZ4 != 0 {
+            return Err(format_err!(
+                "lz4 compressed journal fields are not supported"
+            ));
+        } else {
+            out.extend_from_slice(payload);
+        }
+        Ok(out)
+    }
+
+    fn entry(&self, object: &[u8], oup: &mut dyn Write) -> Result<()> {
+        let realtime = u64_at(object, 24)?;
+        let item_len = if self.compact { 4 } else { 16 };
+        let mut fields = HashMap::new();
+        for item in object[64.min(object.len())..].chunks_exact(item_len) {
+            let offset = if self.compact {
+                u32_at(item, 0)? as usize
+            } else {
+                u64_at(item, 0)? as usize
+            };
+            let field = match self.field(offset) {
+                Ok(field) => field,
+                Err(e) => {
+                    debug!("skipping journal field: {}", e);
+                    continue;
+                }
+            };
+            if let Some(eq) = field.iter().position(|&b| b == b'=') {
+                let name = String::from_utf8_lossy(&field[..eq]).into_owned();
+                fields.insert(name, String::from_utf8_lossy(&field[eq + 1..]).into_owned());
+            }
+        }
+        let message = match fields.get("MESSAGE") {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+        let source = ["_SYSTEMD_UNIT", "SYSLOG_IDENTIFIER", "_COMM"]
+            .iter()
+            .find_map(|f| fields.get(*f))
+            .map(|s| s.as_str())
+            .unwrap_or("-");
+        let time = NaiveDateTime::from_timestamp_opt(
+            (realtime / 1_000_000) as i64,
+            (realtime % 1_000_000) as u32 * 1000,
+        )
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S%.6f").to_string())
+        .unwrap_or_else(|| realtime.to_string());
+        for line in message.lines() {
+            writeln!(oup, "{} {}: {}", time, source, line)?;
+        }
+        Ok(())
+    }
+}
+
+fn adapt_journal(data: &[u8], oup: &mut dyn Write) -> Result<()> {
+    if !data.starts_with(b"LPKSHHRH") {
+        return Err(format_err!("not a systemd journal file"));
+    }
+    let journal = Journal {
+        data,
+        compact: u32_at(data, 12)? & HEADER_INCOMPATIBLE_COMPACT != 0,
+    };
+    let header_size = u64_at(data, 88)? as usize;
+    let arena_size = u64_at(data, 96)? as usize;
+    let end = header_size.saturating_add(arena_size).min(data.len());
+    // entries are appended in chronological order, so walking all objects is enough
+    let mut offset = header_size;
+    while offset.saturating_add(OBJECT_HEADER_LEN) <= end {
+        let (kind, _, object) = match journal.object(offset) {
+            Ok(o) => o,
+            Err(e) => {
+                // the tail of files that are still being written to may be incomplete
+                debug!("stopping at invalid journal object: {}", e);
+                break;
+            }
+        };
+        if kind == OBJECT_ENTRY {
+            journal.entry(object, oup)?;
+        }
+        // objects are 8 byte aligned
+        offset += (object.len() + 7) / 8 * 8;
+    }
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for JournalAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            filepath_hint,
+            is_real_file,
+            ..
+        } = ai;
+        let data = read_input(&filepath_hint, is_real_file, &mut inp)?;
+        adapt_journal(&data, oup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn object(journal: &mut Vec<u8>, kind: u8, content: &[u8]) -> u64 {
+        let offset = journal.len() as u64;
+        journal.push(kind);
+        journal.extend_from_slice(&[0; 7]);
+        journal.extend_from_slice(&((content.len() + OBJECT_HEADER_LEN) as u64).to_le_bytes());
+        journal.extend_from_slice(content);
+        while journal.len() % 8 != 0 {
+            journal.push(0);
+        }
+        offset
+    }
+
+    fn data(journal: &mut Vec<u8>, payload: &str) -> u64 {
+        let mut content = vec![0u8; 48];
+        content.extend_from_slice(payload.as_bytes());
+        object(journal, OBJECT_DATA, &content)
+    }
+
+    #[test]
+    fn messages() -> Result<()> {
+        let mut journal = b"LPKSHHRH".to_vec();
+        journal.resize(256, 0);
+        let message = data(&mut journal, "MESSAGE=Started Daily apt upgrade.");
+        let unit = data(&mut journal, "_SYSTEMD_UNIT=init.scope");
+        let mut entry = vec![0u8; 48];
+        entry[8..16].copy_from_slice(&1_600_000_000_250_000u64.to_le_bytes());
+        for item in &[message, unit] {
+            entry.extend_from_slice(&item.to_le_bytes());
+            entry.extend_from_slice(&[0; 8]);
+        }
+        object(&mut journal, OBJECT_ENTRY, &entry);
+        let arena = journal.len() as u64 - 256;
+        journal[88..96].copy_from_slice(&256u64.to_le_bytes());
+        journal[96..104].copy_from_slice(&arena.to_le_bytes());
+
+        let mut oup = Vec::new();
+        adapt_journal(&journal, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "2020-09-13 12:26:40.250000 init.scope: Started Daily apt upgrade.\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn huge_offsets() -> Result<()> {
+        let mut journal = b"LPKSHHRH".to_vec();
+        journal.resize(256, 0);
+        let message = data(&mut journal, "MESSAGE=Started Daily apt upgrade.");
+        let mut entry = vec![0u8; 48];
+        for item in &[u64::MAX, message] {
+            entry.extend_from_slice(&item.to_le_bytes());
+            entry.extend_from_slice(&[0; 8]);
+        }
+        object(&mut journal, OBJECT_ENTRY, &entry);
+        journal[88..96].copy_from_slice(&256u64.to_le_bytes());
+        journal[96..104].copy_from_slice(&u64::MAX.to_le_bytes());
+        adapt_journal(&journal, &mut Vec::new())?;
+
+        journal[88..96].copy_from_slice(&(u64::MAX - 8).to_le_bytes());
+        adapt_journal(&journal, &mut Vec::new())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+//! Decompression of the LZW streams of compress(1) (.Z files)
+
+use std::io::{BufRead, BufReader, Read};
+
+/// LZW decoder ported from the unlzw function of pigz.
+/// The code size grows from 9 bits to the maximum given in the header. Codes are written in groups of
+/// 8 (`bits` bytes), when the code size changes the rest of the current group is skipped
+pub struct LzwDecoder<R: Read> {
+    inp: BufReader<R>,
+    max_bits: u32,
+    block_mode: bool,
+    bits: u32,
+    mask: u32,
+    /// the last code in the table
+    end: u32,
+    prefix: Vec<u16>,
+    suffix: Vec<u8>,
+    prev: u32,
+    last: u8,
+    /// unused bits of the last byte
+    rem: u32,
+    left: u32,
+    /// bytes left in the current group
+    chunk: u32,
+    started: bool,
+    done: bool,
+    out: Vec<u8>,
+    out_pos: usize,
+}
+
+impl<R: Read> LzwDecoder<R> {
+    pub fn new(inp: R) -> std::io::Result<LzwDecoder<R>> {
+        let mut inp = BufReader::new(inp);
+        let mut header = [0u8; 3];
+        inp.read_exact(&mut header)?;
+        if header[0..2] != [0x1f, 0x9d] {
+            return Err(invalid("not a .Z file"));
+        }
+        let max_bits = (header[2] & 0x1f) as u32;
+        if !(9..=16).contains(&max_bits) {
+            return Err(invalid("invalid maximum code size"));
+        }
+        let block_mode = header[2] & 0x80 != 0;
+        Ok(LzwDecoder {
+            inp,
+            max_bits,
+            block_mode,
+            bits: 9,
+            mask: 0x1ff,
+            end: if block_mode { 256 } else { 255 },
+            prefix: vec![0; 1 << 16],
+            suffix: vec![0; 1 << 16],
+            prev: 0,
+            last: 0,
+            rem: 0,
+            left: 0,
+            chunk: 0,
+            started: false,
+            done: false,
+            out: vec![],
+            out_pos: 0,
+        })
+    }
+
+    fn next_byte(&mut self) -> std::io::Result<Option<u8>> {
+        let b = self.inp.fill_buf()?.first().copied();
+        if b.is_some() {
+            self.inp.consume(1);
+        }
+        Ok(b)
+    }
+
+    /// skip the rest of the current group of codes
+    fn skip_chunk(&mut self) -> std::io::Result<()> {
+        while self.chunk > 0 && self.next_byte()?.is_some() {
+            self.chunk -= 1;
+        }
+        self.chunk = 0;
+        self.rem = 0;
+        self.left = 0;
+        Ok(())
+    }
+
+    /// decode the next code into `out`, returns false at the end of the data
+    fn decode(&mut self) -> std::io::Result<bool> {
+        if !self.started {
+            self.started = true;
+            // the first code is a literal, the table entry is added with the next code
+            let first = match self.next_byte()? {
+                Some(b) => b,
+                None => return Ok(false),
+            };
+            let second = self
+                .next_byte()?
+                .ok_or_else(|| invalid("truncated .Z file"))?;
+            if second & 1 != 0 {
+                return Err(invalid("invalid first code"));
+            }
+            self.prev = first as u32;
+            self.last = first;
+            self.rem = (second >> 1) as u32;
+            self.left = 7;
+            self.chunk = self.bits - 2;
+            self.out.push(first);
+            return Ok(true);
+        }
+        if self.end >= self.mask && self.bits < self.max_bits {
+            self.skip_chunk()?;
+            self.bits += 1;
+            self.mask = self.mask << 1 | 1;
+        }
+        if self.chunk == 0 {
+            self.chunk = self.bits;
+        }
+        let mut code = self.rem;
+        let mut byte = match self.next_byte()? {
+            Some(b) => b,
+            None => return Ok(false),
+        };
+        code += (byte as u32) << self.left;
+        self.left += 8;
+        self.chunk -= 1;
+        if self.bits > self.left {
+            byte = match self.next_byte()? {
+                Some(b) => b,
+                None => return Ok(false),
+            };
+            code += (byte as u32) << self.left;
+            self.left += 8;
+            self.chunk -= 1;
+        }
+        code &= self.mask;
+        self.left -= self.bits;
+        self.rem = byte as u32 >> (8 - self.left);
+
+        if code == 256 && self.block_mode {
+            self.skip_chunk()?;
+            self.bits = 9;
+            self.mask = 0x1ff;
+            self.end = 255;
+            return Ok(true);
+        }
+
+        let start = self.out.len();
+        let current = code;
+        if code > self.end {
+            // the code that is being defined: the previous string plus its first byte
+            if code != self.end + 1 || self.prev > self.end {
+                return Err(invalid("invalid code"));
+            }
+            self.out.push(self.last);
+            code = self.prev;
+        }
+        while code >= 256 {
+            self.out.push(self.suffix[code as usize]);
+            code = self.prefix[code as usize] as u32;
+        }
+        self.out.push(code as u8);
+        self.last = code as u8;
+        self.out[start..].reverse();
+        if self.end < self.mask {
+            self.end += 1;
+            self.prefix[self.end as usize] = self.prev as u16;
+            self.suffix[self.end as usize] = self.last;
+        }
+        self.prev = current;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for LzwDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.out_pos == self.out.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.out.clear();
+            self.out_pos = 0;
+            if !self.decode()? {
+                self.done = true;
+            }
+        }
+        let n = buf.len().min(self.out.len() - self.out_pos);
+        buf[..n].copy_from_slice(&self.out[self.out_pos..self.out_pos + n]);
+        self.out_pos += n;
+        Ok(n)
+    }
+}
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode() -> std::io::Result<()> {
+        // `compress -c`, the repeated phrase is encoded with codes from the table
+        let z = b"\x1f\x9d\x90\x74\xde\x80\x10\x53\x06\xc4\x1b\x39\x20\xdc\xbc\xa1\x03\x22\xe0\xc0\x32\x2c\x1a\xa2\x09\xc3\x30\xcd\x1c\x89\x05\xe3\xd4\x29\x33\x87\x4e\x9a\x37\x6e\x5c\x34\x14\x48\xd0\x20\x42\x85\x0c\x1d\x12\x54\x00";
+        let mut out = String::new();
+        LzwDecoder::new(&z[..])?.read_to_string(&mut out)?;
+        assert_eq!(
+            out,
+            "to be or not to be, that is the question. to be or not to be\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use super::sandbox::sandboxed;
+use super::spawning::{input_as_file, map_exe_error, stderr_if_debug, CommandPaths};
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use std::process::{Command, Stdio};
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["mdb", "accdb"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "mdb".to_owned(),
+        version: 1,
+        description: "Uses mdbtools to dump the tables of Microsoft Access databases, similar to the sqlite adapter".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-msaccess".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct MdbAdapter;
+
+impl MdbAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(MdbAdapter))
+    }
+}
+impl GetMetadata for MdbAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// convert the csv output of mdb-export to `table.column=value` lines
+fn write_csv_rows(table: &str, csv: impl Read, oup: &mut dyn Write) -> Result<()> {
+    let mut reader = ::csv::Reader::from_reader(csv);
+    let headers = reader.headers()?.clone();
+    for record in reader.records() {
+        let record = record?;
+        writeln!(
+            oup,
+            "{}",
+            headers
+                .iter()
+                .zip(record.iter())
+                .map(|(col, val)| format!("{}.{}={}", table, col, val))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )?;
+    }
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for MdbAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            mut inp,
+            config,
+            ..
+        } = ai;
+        // mdbtools can only read real files
+        let (inp_fname, _tmpfile) = input_as_file(&filepath_hint, is_real_file, &mut inp)?;
+        let paths = CommandPaths {
+            filepath_hint: &filepath_hint,
+            input_path: Some(&inp_fname),
+            tmpdir: None,
+        };
+        let spawn_fail =
+            |e| map_exe_error(e, "mdb-tables", "Make sure you have mdbtools installed.");
+        let mut cmd = Command::new("mdb-tables");
+        cmd.arg("-1").arg(&inp_fname);
+        let tables = sandboxed(cmd, &paths, &config)?
+            .output()
+            .map_err(spawn_fail)?;
+        if !tables.status.success() {
+            return Err(format_err!("mdb-tables failed: {:?}", tables.status));
+        }
+        let tables = String::from_utf8_lossy(&tables.stdout);
+        for table in tables.lines().filter(|t| !t.is_empty()) {
+            debug!("exporting table {}", table);
+            let mut cmd = Command::new("mdb-export");
+            cmd.arg(&inp_fname).arg(table);
+            let mut export = sandboxed(cmd, &paths, &config)?
+                .stdout(Stdio::piped())
+                .stderr(stderr_if_debug())
+                .spawn()
+                .map_err(|e| {
+                    map_exe_error(e, "mdb-export", "Make sure you have mdbtools installed.")
+                })?;
+            let rows = write_csv_rows(table, export.stdout.take().expect("is piped"), oup);
+            if rows.is_err() {
+                // it may still be writing
+                export.kill().ok();
+            }
+            // also waited for on errors, so it doesn't stay a zombie
+            let status = export.wait()?;
+            rows?;
+            if !status.success() {
+                return Err(format_err!(
+                    "mdb-export failed for table {}: {:?}",
+                    table,
+                    status
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn csv_rows() -> Result<()> {
+        let mut oup = Vec::new();
+        write_csv_rows(
+            "Employees",
+            "ID,Name\n1,\"Doe, Jane\"\n2,Smith\n".as_bytes(),
+            &mut oup,
+        )?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "Employees.ID=1, Employees.Name=Doe, Jane\nEmployees.ID=2, Employees.Name=Smith\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use super::*;
+use crate::adapted_iter::AdaptedFilesIter;
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use spawning::input_as_file;
+use std::fs::File;
+use std::io::Cursor;
+
+static EXTENSIONS: &[&str] = &["msi", "msm"];
+
+/// the tables that are interesting to search in, most of the others only contain ids
+static TABLES: &[&str] = &[
+    "Property",
+    "File",
+    "Registry",
+    "Shortcut",
+    "Component",
+    "Feature",
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "msi".to_owned(),
+        version: 1,
+        description: "Outputs the property, file, registry and shortcut tables of Windows Installer packages and recurses into the embedded cabinet files".to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/x-msi".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct MsiAdapter;
+
+impl MsiAdapter {
+    pub fn new() -> MsiAdapter {
+        MsiAdapter
+    }
+}
+impl GetMetadata for MsiAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// one `Table: column=value, ...` line per row of the interesting tables
+fn format_tables<F: Read + std::io::Seek>(package: &mut ::msi::Package<F>) -> Result<String> {
+    let mut lines = vec![];
+    for table in TABLES {
+        if !package.has_table(table) {
+            continue;
+        }
+        let rows = package.select_rows(::msi::Select::table(*table))?;
+        let columns: Vec<String> = rows
+            .columns()
+            .iter()
+            .map(|c| c.name().to_string())
+            .collect();
+        for row in rows {
+            let values: Vec<String> = columns
+                .iter()
+                .filter(|c| !row[c.as_str()].is_null())
+                .map(|c| format!("{}={}", c, row[c.as_str()]))
+                .collect();
+            lines.push(format!("{}: {}", table, values.join(", ")));
+        }
+    }
+    Ok(lines.join("\n"))
+}
+
+struct MsiIter {
+    package: ::msi::Package<File>,
+    /// the table dump, yielded first
+    tables: Option<String>,
+    cabinets: std::vec::IntoIter<String>,
+    line_prefix: String,
+    archive_recursion_depth: i32,
+    postprocess: bool,
+    config: RgaConfig,
+    _tmp: Option<tempfile::NamedTempFile>,
+}
+
+impl AdaptedFilesIter for MsiIter {
+    fn next<'a>(&'a mut self) -> Option<AdaptInfo<'a>> {
+        if let Some(tables) = self.tables.take() {
+            return Some(AdaptInfo {
+                filepath_hint: PathBuf::from("tables.txt"),
+                is_real_file: false,
+                archive_recursion_depth: self.archive_recursion_depth + 1,
+                inp: Box::new(Cursor::new(tables.into_bytes())),
+                line_prefix: self.line_prefix.clone(),
+                postprocess: self.postprocess,
+                config: self.config.clone(),
+            });
+        }
+        loop {
+            let name = self.cabinets.next()?;
+            let mut data = Vec::new();
+            if let Err(e) = self
+                .package
+                .read_stream(&name)
+                .and_then(|mut s| s.read_to_end(&mut data))
+            {
+                warn!("could not read msi stream {}: {}", name, e);
+                continue;
+            }
+            return Some(AdaptInfo {
+                line_prefix: self.config.entry_prefix(&self.line_prefix, &name),
+                filepath_hint: PathBuf::from(name),
+                is_real_file: false,
+                archive_recursion_depth: self.archive_recursion_depth + 1,
+                inp: Box::new(Cursor::new(data)),
+                postprocess: self.postprocess,
+                config: self.config.clone(),
+            });
+        }
+    }
+}
+
+impl FileAdapter for MsiAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+        } = ai;
+        let (path, tmp) = input_as_file(&filepath_hint, is_real_file, &mut inp)?;
+        let mut package = ::msi::Package::open(File::open(&path)?)?;
+        let tables = format_tables(&mut package)?;
+        // the installed files are stored in cabinets, embedded as streams (external cabinets next to the msi are searched on their own)
+        let cabinets: Vec<String> = package
+            .streams()
+            .filter(|s| s.to_ascii_lowercase().ends_with(".cab"))
+            .collect();
+        Ok(Box::new(MsiIter {
+            package,
+            tables: if tables.is_empty() {
+                None
+            } else {
+                Some(tables)
+            },
+            cabinets: cabinets.into_iter(),
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            _tmp: tmp,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn property_table() -> Result<()> {
+        let mut package =
+            ::msi::Package::create(::msi::PackageType::Installer, Cursor::new(Vec::new()))?;
+        package.create_table(
+            "Property",
+            vec![
+                ::msi::Column::build("Property").primary_key().id_string(72),
+                ::msi::Column::build("Value").nullable().formatted_string(0),
+            ],
+        )?;
+        package.insert_rows(
+            ::msi::Insert::into("Property")
+                .row(vec![
+                    ::msi::Value::from("ProductName"),
+                    ::msi::Value::from("ripgrep-all"),
+                ])
+                .row(vec![::msi::Value::from("ARPNOREPAIR"), ::msi::Value::Null]),
+        )?;
+        assert_eq!(
+            format_tables(&mut package)?,
+            "Property: Property=ARPNOREPAIR\nProperty: Property=ProductName, Value=ripgrep-all"
+        );
+        Ok(())
+    }
+}
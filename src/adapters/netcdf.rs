@@ -0,0 +1,206 @@
+use super::sandbox::sandboxed;
+use super::spawning::{input_as_file, map_exe_error, CommandPaths};
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use std::ffi::OsStr;
+use std::process::Command;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["nc", "nc4", "cdf", "netcdf"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "netcdf".to_owned(),
+        version: 1,
+        description: "Uses ncdump to output the header (dimensions, variables, attributes) of netCDF files. See --rga-netcdf-max-values to also output small variables".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-netcdf".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct NetcdfAdapter;
+
+impl NetcdfAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(NetcdfAdapter))
+    }
+}
+impl GetMetadata for NetcdfAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn ncdump(args: &[&OsStr], paths: &CommandPaths, config: &RgaConfig) -> Result<Vec<u8>> {
+    let mut cmd = Command::new("ncdump");
+    cmd.args(args);
+    let output = sandboxed(cmd, paths, config)?.output().map_err(|e| {
+        map_exe_error(
+            e,
+            "ncdump",
+            "Make sure you have the netCDF tools installed.",
+        )
+    })?;
+    if !output.status.success() {
+        return Err(format_err!(
+            "ncdump failed: {:?}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// find the (top level) variables in an `ncdump -h` header that have at most max_values elements
+fn small_variables(header: &str, max_values: usize) -> Vec<String> {
+    let mut dims: HashMap<&str, usize> = HashMap::new();
+    let mut out = vec![];
+    let mut section = "";
+    for line in header.lines() {
+        if !line.starts_with('\t') {
+            section = line.trim();
+            continue;
+        }
+        // deeper indentation is attributes or groups
+        if line.starts_with("\t\t") {
+            continue;
+        }
+        let decl = line
+            .trim()
+            .trim_end_matches(|c: char| c != ';')
+            .trim_end_matches(';');
+        match section {
+            "dimensions:" => {
+                // `lat = 180 ;` or `time = UNLIMITED ; // (12 currently)`
+                let mut parts = decl.splitn(2, '=');
+                let name = parts.next().unwrap_or("").trim();
+                let size = parts.next().unwrap_or("").trim();
+                let size = if size == "UNLIMITED" {
+                    line.rsplit('(')
+                        .next()
+                        .and_then(|s| s.split_whitespace().next())
+                        .and_then(|s| s.parse().ok())
+                } else {
+                    size.parse().ok()
+                };
+                if let Some(size) = size {
+                    dims.insert(name, size);
+                }
+            }
+            "variables:" => {
+                // `float temp(time, lat) ;` or `int scalar ;`
+                let decl = decl.trim();
+                let name_and_dims = match decl.splitn(2, ' ').nth(1) {
+                    Some(n) => n.trim(),
+                    None => continue,
+                };
+                let (name, count) = match name_and_dims.find('(') {
+                    Some(i) => {
+                        let count = name_and_dims[i + 1..]
+                            .trim_end_matches(')')
+                            .split(',')
+                            .map(|d| dims.get(d.trim()).copied())
+                            .product::<Option<usize>>();
+                        (&name_and_dims[..i], count)
+                    }
+                    None => (name_and_dims, Some(1)),
+                };
+                match count {
+                    Some(count) if count <= max_values => out.push(name.trim().to_string()),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+impl WritingFileAdapterTrait for NetcdfAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            mut inp,
+            config,
+            ..
+        } = ai;
+        // ncdump can only read real files
+        let (inp_fname, _tmpfile) = input_as_file(&filepath_hint, is_real_file, &mut inp)?;
+        let paths = CommandPaths {
+            filepath_hint: &filepath_hint,
+            input_path: Some(&inp_fname),
+            tmpdir: None,
+        };
+        let header = ncdump(&[OsStr::new("-h"), inp_fname.as_os_str()], &paths, &config)?;
+        let max_values = config.netcdf.max_values;
+        let small = if max_values > 0 {
+            small_variables(&String::from_utf8_lossy(&header), max_values)
+        } else {
+            vec![]
+        };
+        if small.is_empty() {
+            oup.write_all(&header)?;
+            return Ok(());
+        }
+        debug!("dumping values of {} small variables", small.len());
+        // with -v, ncdump outputs the header followed by the data of the given variables
+        let vars = small.join(",");
+        oup.write_all(&ncdump(
+            &[OsStr::new("-v"), OsStr::new(&vars), inp_fname.as_os_str()],
+            &paths,
+            &config,
+        )?)?;
+        Ok(())
+    }
+
+    fn cache_key_extra(&self, _filepath: &Path, config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        // the values of small variables are only dumped with --rga-netcdf-max-values
+        let max_values = config.netcdf.max_values;
+        Ok(Some(max_values.to_string().into_bytes()).filter(|_| max_values > 0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_small_variables() {
+        let header = "netcdf sst {\n\
+            dimensions:\n\
+            \tlat = 180 ;\n\
+            \tlon = 360 ;\n\
+            \ttime = UNLIMITED ; // (12 currently)\n\
+            \tname_len = 20 ;\n\
+            variables:\n\
+            \tfloat lat(lat) ;\n\
+            \t\tlat:units = \"degrees_north\" ;\n\
+            \tdouble time(time) ;\n\
+            \tchar station(name_len) ;\n\
+            \tfloat sst(time, lat, lon) ;\n\
+            \tint crs ;\n\
+            \n\
+            // global attributes:\n\
+            \t\t:title = \"Sea surface temperature\" ;\n\
+            }\n";
+        assert_eq!(small_variables(header, 100), vec!["time", "station", "crs"]);
+        assert_eq!(small_variables(header, 1000).len(), 4);
+    }
+}
@@ -0,0 +1,116 @@
+use super::*;
+use crate::adapted_iter::MultiAdaptedFilesIter;
+use anyhow::*;
+use lazy_static::lazy_static;
+use onenote_parser::contents::{Content, OutlineElement, OutlineItem};
+use spawning::input_as_file;
+use std::io::Cursor;
+
+static EXTENSIONS: &[&str] = &["one"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "onenote".to_owned(),
+        version: 1,
+        description: "Extracts the text of the pages in OneNote section files (.one), prefixed with the page title".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/onenote".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct OneNoteAdapter;
+
+impl OneNoteAdapter {
+    pub fn new() -> OneNoteAdapter {
+        OneNoteAdapter
+    }
+}
+impl GetMetadata for OneNoteAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn element_text(element: &OutlineElement, out: &mut String) {
+    for content in element.contents() {
+        match content {
+            Content::RichText(text) => {
+                out.push_str(text.text());
+                out.push('\n');
+            }
+            Content::Table(table) => {
+                for row in table.contents() {
+                    for cell in row.contents() {
+                        for element in cell.contents() {
+                            element_text(element, out);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    items_text(element.children(), out);
+}
+
+/// the text of an outline, in document order
+fn items_text(items: &[OutlineItem], out: &mut String) {
+    for item in items {
+        match item {
+            OutlineItem::Element(element) => element_text(element, out),
+            OutlineItem::Group(group) => items_text(group.outlines(), out),
+        }
+    }
+}
+
+impl FileAdapter for OneNoteAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+        } = ai;
+        let (path, _tmp) = input_as_file(&filepath_hint, is_real_file, &mut inp)?;
+        let section = onenote_parser::Parser::new()
+            .parse_section(&path)
+            .map_err(|e| format_err!("could not parse OneNote section: {}", e))?;
+        let mut pages = vec![];
+        for page in section.page_series().iter().flat_map(|s| s.pages()) {
+            let title = page
+                .title_text()
+                .unwrap_or("Untitled Page")
+                .replace(|c: char| c.is_control(), " ");
+            let mut text = String::new();
+            for content in page.contents() {
+                if let Some(outline) = content.outline() {
+                    items_text(outline.items(), &mut text);
+                }
+            }
+            pages.push(AdaptInfo {
+                line_prefix: config.entry_prefix(&line_prefix, &title),
+                filepath_hint: PathBuf::from("page.txt"),
+                is_real_file: false,
+                archive_recursion_depth: archive_recursion_depth + 1,
+                inp: Box::new(Cursor::new(text.into_bytes())),
+                postprocess,
+                config: config.clone(),
+            });
+        }
+        Ok(Box::new(MultiAdaptedFilesIter::new(pages)))
+    }
+}
@@ -0,0 +1,145 @@
+use super::spawning::{stderr_if_debug, subprocess_exited, subprocess_started};
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use serde::Deserialize;
+use std::io::Read;
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// how long to wait for the server to accept connections after starting it
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/**
+ * A `pandoc server` (pandoc 3.0 or newer) that converts all documents of this process (with --rga-pandoc-server),
+ * instead of starting pandoc for every file. Starting pandoc takes longer than converting a small docx file.
+ */
+struct PandocServer {
+    proce: Child,
+    url: String,
+}
+
+enum State {
+    NotStarted,
+    Running(PandocServer),
+    /// the server could not be started (e.g. pandoc is too old), pandoc is run for every file instead
+    Failed,
+}
+
+lazy_static! {
+    static ref SERVER: Mutex<State> = Mutex::new(State::NotStarted);
+}
+
+impl PandocServer {
+    fn start() -> Result<PandocServer> {
+        // there is no way to let pandoc choose a port and tell us, so find a free one
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?
+            .local_addr()?
+            .port();
+        let mut proce = Command::new("pandoc")
+            .arg("server")
+            .arg(format!("--port={}", port))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(stderr_if_debug())
+            .spawn()
+            .context("could not start pandoc server")?;
+        subprocess_started(proce.id());
+        let start = Instant::now();
+        while TcpStream::connect((Ipv4Addr::LOCALHOST, port)).is_err() {
+            if let Some(status) = proce.try_wait()? {
+                subprocess_exited(proce.id());
+                bail!("pandoc server exited with {}", status);
+            }
+            if start.elapsed() > STARTUP_TIMEOUT {
+                proce.kill().ok();
+                proce.wait().ok();
+                subprocess_exited(proce.id());
+                bail!("pandoc server did not start listening");
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        debug!("started pandoc server on port {}", port);
+        Ok(PandocServer {
+            proce,
+            url: format!("http://127.0.0.1:{}/", port),
+        })
+    }
+
+    fn stop(mut self) {
+        debug!("stopping pandoc server");
+        self.proce.kill().ok();
+        self.proce.wait().ok();
+        subprocess_exited(self.proce.id());
+    }
+}
+
+#[derive(Deserialize)]
+struct ConvertResponse {
+    output: String,
+}
+
+/// the url of the server, starting it if necessary. None if it can't be started
+fn server_url() -> Option<String> {
+    let mut state = SERVER.lock().unwrap();
+    if let State::NotStarted = *state {
+        *state = match PandocServer::start() {
+            Ok(server) => State::Running(server),
+            Err(e) => {
+                warn!("{:#}, running pandoc for every file instead", e);
+                State::Failed
+            }
+        };
+    }
+    match &*state {
+        State::Running(server) => Some(server.url.clone()),
+        _ => None,
+    }
+}
+
+/// Convert the document with the pandoc server, like `pandoc --from=<from> --to=<to> --wrap=none`.
+/// Returns None without reading the input if the server is not available
+pub fn convert(from: &str, to: &str, inp: &mut dyn Read) -> Result<Option<String>> {
+    let url = match server_url() {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+    let mut data = Vec::new();
+    inp.read_to_end(&mut data)?;
+    // binary input formats (docx, odt, epub) are passed as base64, the server detects that
+    let request = serde_json::json!({
+        "text": base64::encode(&data),
+        "from": from,
+        "to": to,
+        "wrap": "none",
+    });
+    let resp = ureq::post(&url)
+        .set("Accept", "application/json")
+        .send_string(&request.to_string());
+    if let Some(e) = resp.synthetic_error() {
+        return Err(format_err!("pandoc server: {}", e));
+    }
+    let ok = resp.ok();
+    let status = resp.status_line().to_string();
+    let body = resp.into_string()?;
+    if !ok {
+        return Err(format_err!(
+            "pandoc server returned {}: {}",
+            status,
+            body.trim()
+        ));
+    }
+    let resp: ConvertResponse =
+        serde_json::from_str(&body).context("could not parse pandoc server response")?;
+    Ok(Some(resp.output))
+}
+
+/// stop the server if it was started. Called before exiting, since it would keep running otherwise
+pub fn stop_server() {
+    let mut state = SERVER.lock().unwrap();
+    if let State::Running(server) = std::mem::replace(&mut *state, State::NotStarted) {
+        server.stop();
+    }
+}
@@ -0,0 +1,460 @@
+use super::*;
+use anyhow::*;
+use chrono::NaiveDateTime;
+use lazy_static::lazy_static;
+use log::*;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["pcap", "pcapng", "cap"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "pcap".to_owned(),
+        version: 1,
+        description: "Outputs a one-line summary (time, addresses, protocol) per packet of pcap and pcapng network captures. See --rga-pcap-payload to also output the packet contents".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/vnd.tcpdump.pcap".to_owned()),
+            FileMatcher::MimeType("application/x-pcapng".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct PcapAdapter;
+
+impl PcapAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(PcapAdapter))
+    }
+}
+impl GetMetadata for PcapAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+// larger packets than this are certainly a corrupt file
+const MAX_PACKET_LEN: usize = 256 * 1024 * 1024;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+
+#[derive(Clone, Copy)]
+struct Endian {
+    big: bool,
+}
+
+impl Endian {
+    fn u16(self, b: &[u8]) -> u16 {
+        let b = [b[0], b[1]];
+        if self.big {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        }
+    }
+    fn u32(self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.big {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+}
+
+fn be16(b: &[u8]) -> u16 {
+    u16::from_be_bytes([b[0], b[1]])
+}
+
+fn format_time(secs: u64, nanos: u32) -> String {
+    NaiveDateTime::from_timestamp_opt(secs as i64, nanos)
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S%.6f").to_string())
+        .unwrap_or_else(|| format!("{}.{:09}", secs, nanos))
+}
+
+/// replace everything that is not printable ascii by "."
+fn printable(payload: &[u8]) -> String {
+    payload
+        .iter()
+        .map(|&b| {
+            if (0x20..0x7f).contains(&b) {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+/// summary of a transport layer packet: ports, protocol and the payload
+fn summarize_transport(protocol: u8, data: &[u8]) -> (Option<u16>, Option<u16>, String, &[u8]) {
+    match protocol {
+        6 if data.len() >= 20 => {
+            let flag_names = ["FIN", "SYN", "RST", "PSH", "ACK", "URG"];
+            let flags: Vec<&str> = flag_names
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| data[13] & (1 << i) != 0)
+                .map(|(_, name)| *name)
+                .collect();
+            let header_len = std::cmp::min(((data[12] >> 4) as usize) * 4, data.len());
+            (
+                Some(be16(&data[0..2])),
+                Some(be16(&data[2..4])),
+                format!("TCP [{}]", flags.join(",")),
+                &data[header_len..],
+            )
+        }
+        17 if data.len() >= 8 => (
+            Some(be16(&data[0..2])),
+            Some(be16(&data[2..4])),
+            "UDP".to_string(),
+            &data[8..],
+        ),
+        1 if data.len() >= 2 => (None, None, format!("ICMP type={}", data[0]), &[]),
+        58 if data.len() >= 2 => (None, None, format!("ICMPv6 type={}", data[0]), &[]),
+        p => (None, None, format!("IP proto={}", p), &[]),
+    }
+}
+
+fn with_port(addr: String, port: Option<u16>) -> String {
+    match port {
+        Some(port) => format!("{}:{}", addr, port),
+        None => addr,
+    }
+}
+
+/// summary of a network layer packet given its ethertype. Returns the summary and the payload
+fn summarize_network(ethertype: u16, data: &[u8]) -> (String, &[u8]) {
+    let (src, dst, protocol, transport) = match ethertype {
+        0x0800 if data.len() >= 20 => {
+            let header_len = std::cmp::min(((data[0] & 0x0f) as usize) * 4, data.len());
+            let total_len = std::cmp::max(
+                std::cmp::min(be16(&data[2..4]) as usize, data.len()),
+                header_len,
+            );
+            (
+                Ipv4Addr::new(data[12], data[13], data[14], data[15]).to_string(),
+                Ipv4Addr::new(data[16], data[17], data[18], data[19]).to_string(),
+                data[9],
+                // ethernet frames can be padded
+                &data[header_len..total_len],
+            )
+        }
+        0x86dd if data.len() >= 40 => {
+            let addr = |b: &[u8]| {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(b);
+                format!("[{}]", Ipv6Addr::from(octets))
+            };
+            (
+                addr(&data[8..24]),
+                addr(&data[24..40]),
+                data[6],
+                &data[40..],
+            )
+        }
+        0x0806 => return ("ARP".to_string(), &[]),
+        t => return (format!("ethertype 0x{:04x}", t), &[]),
+    };
+    let (sport, dport, summary, payload) = summarize_transport(protocol, transport);
+    (
+        format!(
+            "{} -> {} {}",
+            with_port(src, sport),
+            with_port(dst, dport),
+            summary
+        ),
+        payload,
+    )
+}
+
+/// summary of a captured frame given the link type of the capture
+fn summarize(linktype: u32, data: &[u8]) -> (String, &[u8]) {
+    match linktype {
+        // ethernet
+        1 if data.len() >= 14 => {
+            let mut ethertype = be16(&data[12..14]);
+            let mut offset = 14;
+            // vlan tags
+            while (ethertype == 0x8100 || ethertype == 0x88a8) && data.len() >= offset + 4 {
+                ethertype = be16(&data[offset + 2..offset + 4]);
+                offset += 4;
+            }
+            summarize_network(ethertype, &data[offset..])
+        }
+        // BSD loopback
+        0 if data.len() >= 4 => {
+            let family = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            let ethertype = if family == 2 { 0x0800 } else { 0x86dd };
+            summarize_network(ethertype, &data[4..])
+        }
+        // raw ip
+        101 | 228 | 229 if !data.is_empty() => {
+            let ethertype = if data[0] >> 4 == 6 { 0x86dd } else { 0x0800 };
+            summarize_network(ethertype, data)
+        }
+        // linux cooked capture
+        113 if data.len() >= 16 => summarize_network(be16(&data[14..16]), &data[16..]),
+        276 if data.len() >= 20 => summarize_network(be16(&data[0..2]), &data[20..]),
+        t => (format!("linktype {}", t), &[]),
+    }
+}
+
+struct PacketWriter<'a, 'b> {
+    oup: &'b mut (dyn Write + 'a),
+    payload: bool,
+    count: u64,
+}
+
+impl<'a, 'b> PacketWriter<'a, 'b> {
+    fn write(&mut self, time: String, linktype: u32, data: &[u8], orig_len: u32) -> Result<()> {
+        self.count += 1;
+        let (summary, payload) = summarize(linktype, data);
+        write!(
+            self.oup,
+            "{} {} {} len={}",
+            self.count, time, summary, orig_len
+        )?;
+        if self.payload && !payload.is_empty() {
+            write!(self.oup, " payload: {}", printable(payload))?;
+        }
+        writeln!(self.oup)?;
+        Ok(())
+    }
+}
+
+fn read_packet(inp: &mut dyn Read, len: usize) -> Result<Vec<u8>> {
+    if len > MAX_PACKET_LEN {
+        return Err(format_err!("invalid packet length {}", len));
+    }
+    let mut data = vec![0u8; len];
+    inp.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// classic libpcap format: global header followed by (header, data) records
+fn adapt_pcap(magic: [u8; 4], inp: &mut dyn Read, oup: &mut PacketWriter) -> Result<()> {
+    let (endian, nanos) = match magic {
+        [0xd4, 0xc3, 0xb2, 0xa1] => (Endian { big: false }, false),
+        [0xa1, 0xb2, 0xc3, 0xd4] => (Endian { big: true }, false),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (Endian { big: false }, true),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (Endian { big: true }, true),
+        _ => return Err(format_err!("not a pcap file")),
+    };
+    let mut header = [0u8; 20];
+    inp.read_exact(&mut header)?;
+    let linktype = endian.u32(&header[16..20]);
+    let mut record = [0u8; 16];
+    loop {
+        if let Err(e) = inp.read_exact(&mut record) {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                return Ok(());
+            }
+            return Err(e.into());
+        }
+        let secs = endian.u32(&record[0..4]) as u64;
+        let frac = endian.u32(&record[4..8]);
+        let data = read_packet(inp, endian.u32(&record[8..12]) as usize)?;
+        let nanos = if nanos {
+            frac
+        } else {
+            frac.saturating_mul(1000)
+        };
+        oup.write(
+            format_time(secs, nanos),
+            linktype,
+            &data,
+            endian.u32(&record[12..16]),
+        )?;
+    }
+}
+
+struct PcapngInterface {
+    linktype: u32,
+    /// timestamp units per second
+    resolution: u64,
+}
+
+/// parse the if_tsresol option of an interface description block
+fn interface_resolution(endian: Endian, mut options: &[u8]) -> u64 {
+    while options.len() >= 4 {
+        let code = endian.u16(&options[0..2]);
+        let len = endian.u16(&options[2..4]) as usize;
+        if code == 0 || options.len() < 4 + len {
+            break;
+        }
+        if code == 9 && len >= 1 {
+            let v = options[4];
+            let exp = (v & 0x7f) as u32;
+            return if v & 0x80 == 0 {
+                10u64.checked_pow(exp).unwrap_or(1_000_000)
+            } else {
+                2u64.checked_pow(exp).unwrap_or(1_000_000)
+            };
+        }
+        // options are padded to 32 bit
+        let next = 4 + (len + 3) / 4 * 4;
+        options = &options[std::cmp::min(next, options.len())..];
+    }
+    1_000_000
+}
+
+/// pcapng format: a sequence of blocks, packets are in (enhanced / simple) packet blocks
+fn adapt_pcapng(first_type: [u8; 4], inp: &mut dyn Read, oup: &mut PacketWriter) -> Result<()> {
+    let mut endian = Endian { big: false };
+    let mut interfaces: Vec<PcapngInterface> = vec![];
+    let mut block_type = first_type;
+    loop {
+        let mut len = [0u8; 4];
+        inp.read_exact(&mut len)?;
+        let mut body_start = vec![];
+        if u32::from_le_bytes(block_type) == PCAPNG_SECTION_HEADER {
+            // the byte order magic decides the endianness of everything in this section
+            let mut magic = [0u8; 4];
+            inp.read_exact(&mut magic)?;
+            endian.big = magic == [0x1a, 0x2b, 0x3c, 0x4d];
+            interfaces.clear();
+            body_start.extend_from_slice(&magic);
+        }
+        let total_len = endian.u32(&len) as usize;
+        // the body is followed by the total length again
+        let rest_len = total_len
+            .checked_sub(12 + body_start.len())
+            .context("invalid pcapng block length")?;
+        let mut body = body_start;
+        body.extend(read_packet(inp, rest_len)?);
+        let mut trailer = [0u8; 4];
+        inp.read_exact(&mut trailer)?;
+        match endian.u32(&block_type) {
+            // interface description block
+            1 if body.len() >= 8 => interfaces.push(PcapngInterface {
+                linktype: endian.u16(&body[0..2]) as u32,
+                resolution: interface_resolution(endian, &body[8..]),
+            }),
+            // enhanced packet block
+            6 if body.len() >= 20 => {
+                let interface = interfaces.get(endian.u32(&body[0..4]) as usize);
+                let captured = endian.u32(&body[12..16]) as usize;
+                let orig_len = endian.u32(&body[16..20]);
+                let data = &body[20..std::cmp::min(20 + captured, body.len())];
+                let (linktype, resolution) = interface
+                    .map(|i| (i.linktype, i.resolution))
+                    .unwrap_or((1, 1_000_000));
+                let ts = ((endian.u32(&body[4..8]) as u64) << 32) | endian.u32(&body[8..12]) as u64;
+                let nanos = (ts % resolution) as u128 * 1_000_000_000 / resolution as u128;
+                oup.write(
+                    format_time(ts / resolution, nanos as u32),
+                    linktype,
+                    data,
+                    orig_len,
+                )?;
+            }
+            // simple packet block (no timestamp)
+            3 if body.len() >= 4 => {
+                let linktype = interfaces.first().map(|i| i.linktype).unwrap_or(1);
+                oup.write(
+                    "-".to_string(),
+                    linktype,
+                    &body[4..],
+                    endian.u32(&body[0..4]),
+                )?;
+            }
+            _ => {}
+        }
+        if let Err(e) = inp.read_exact(&mut block_type) {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                return Ok(());
+            }
+            return Err(e.into());
+        }
+    }
+}
+
+impl WritingFileAdapterTrait for PcapAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp, config, ..
+        } = ai;
+        let mut writer = PacketWriter {
+            oup,
+            payload: config.pcap.payload,
+            count: 0,
+        };
+        let mut magic = [0u8; 4];
+        inp.read_exact(&mut magic)?;
+        if u32::from_le_bytes(magic) == PCAPNG_SECTION_HEADER {
+            adapt_pcapng(magic, &mut inp, &mut writer)?;
+        } else {
+            adapt_pcap(magic, &mut inp, &mut writer)?;
+        }
+        debug!("read {} packets", writer.count);
+        Ok(())
+    }
+
+    fn cache_key_extra(&self, _filepath: &Path, config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        // the packet contents are only output with --rga-pcap-payload
+        Ok(Some(b"payload".to_vec()).filter(|_| config.pcap.payload))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    /// an ethernet frame with an ipv4 udp packet
+    fn udp_frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        let total_len = (20 + 8 + payload.len()) as u16;
+        frame.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0]);
+        frame[16..18].copy_from_slice(&total_len.to_be_bytes());
+        frame.extend_from_slice(&[192, 168, 1, 2, 8, 8, 8, 8]);
+        frame.extend_from_slice(&5353u16.to_be_bytes());
+        frame.extend_from_slice(&53u16.to_be_bytes());
+        frame.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn pcap_udp() -> Result<()> {
+        let frame = udp_frame(b"\x01example\x03com");
+        let mut pcap = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        pcap.extend_from_slice(&[0; 8]);
+        pcap.extend_from_slice(&65535u32.to_le_bytes());
+        pcap.extend_from_slice(&1u32.to_le_bytes());
+        pcap.extend_from_slice(&1_600_000_000u32.to_le_bytes());
+        pcap.extend_from_slice(&250_000u32.to_le_bytes());
+        pcap.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        pcap.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        pcap.extend(&frame);
+
+        let adapter: Box<dyn FileAdapter> = Box::new(PcapAdapter::new());
+        let (mut a, d) = simple_adapt_info(
+            &PathBuf::from("dns.pcap"),
+            Box::new(std::io::Cursor::new(pcap)),
+        );
+        a.config.pcap.payload = true;
+        let buf = adapted_to_vec(adapter.adapt(a, &d)?)?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:1 2020-09-13 12:26:40.250000 192.168.1.2:5353 -> 8.8.8.8:53 UDP len=53 payload: .example.com\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,366 @@
+use super::*;
+use crate::adapted_iter::MultiAdaptedFilesIter;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use log::*;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use regex::Regex;
+use std::io::Cursor;
+
+static EXTENSIONS: &[&str] = &["pdf"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "pdfforms".to_owned(),
+        version: 1,
+        description: "Extracts the names and filled-in values of AcroForm and XFA form fields from PDF files, then passes the PDF on to the next matching adapter (e.g. poppler)".to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/pdf".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true
+    };
+}
+#[derive(Default)]
+pub struct PdfFormsAdapter;
+
+impl PdfFormsAdapter {
+    pub fn new() -> PdfFormsAdapter {
+        PdfFormsAdapter
+    }
+}
+
+impl GetMetadata for PdfFormsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// PDF text strings are either UTF-16BE with a BOM or PDFDocEncoding (which is latin1 for everything printable)
+fn decode_pdf_string(bytes: &[u8]) -> String {
+    if bytes.starts_with(&[0xfe, 0xff]) {
+        let units: Vec<u16> = bytes[2..]
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else if bytes.starts_with(&[0xef, 0xbb, 0xbf]) {
+        String::from_utf8_lossy(&bytes[3..]).into_owned()
+    } else {
+        bytes.iter().map(|&b| b as char).collect()
+    }
+}
+
+fn resolve<'a>(doc: &'a Document, obj: &'a Object) -> Option<&'a Object> {
+    match obj {
+        Object::Reference(id) => doc.get_object(*id).ok(),
+        obj => Some(obj),
+    }
+}
+
+fn get_resolved<'a>(doc: &'a Document, dict: &'a Dictionary, key: &[u8]) -> Option<&'a Object> {
+    dict.get(key).ok().and_then(|o| resolve(doc, o))
+}
+
+fn format_value(doc: &Document, value: &Object) -> Option<String> {
+    match resolve(doc, value)? {
+        Object::String(s, _) => Some(decode_pdf_string(s)),
+        Object::Name(n) => Some(String::from_utf8_lossy(n).into_owned()),
+        Object::Integer(i) => Some(i.to_string()),
+        Object::Real(r) => Some(r.to_string()),
+        Object::Boolean(b) => Some(b.to_string()),
+        Object::Array(values) => Some(
+            values
+                .iter()
+                .filter_map(|v| format_value(doc, v))
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        _ => None,
+    }
+}
+
+struct PageLookup {
+    /// page object id -> page number
+    pages: HashMap<ObjectId, u32>,
+    /// annotation (widget) object id -> page number
+    annots: HashMap<ObjectId, u32>,
+}
+
+impl PageLookup {
+    fn new(doc: &Document) -> PageLookup {
+        let mut pages = HashMap::new();
+        let mut annots = HashMap::new();
+        for (num, page_id) in doc.get_pages() {
+            pages.insert(page_id, num);
+            let page = match doc.get_object(page_id).and_then(Object::as_dict) {
+                Ok(page) => page,
+                Err(_) => continue,
+            };
+            if let Some(Object::Array(page_annots)) = get_resolved(doc, page, b"Annots") {
+                for annot in page_annots {
+                    if let Object::Reference(id) = annot {
+                        annots.insert(*id, num);
+                    }
+                }
+            }
+        }
+        PageLookup { pages, annots }
+    }
+
+    /// find the page a field (or one of its widgets) is placed on
+    fn page_of(&self, doc: &Document, id: Option<ObjectId>, field: &Dictionary) -> Option<u32> {
+        if let Some(page) = id.and_then(|id| self.annots.get(&id)) {
+            return Some(*page);
+        }
+        if let Ok(Object::Reference(page_id)) = field.get(b"P") {
+            if let Some(page) = self.pages.get(page_id) {
+                return Some(*page);
+            }
+        }
+        if let Some(Object::Array(kids)) = get_resolved(doc, field, b"Kids") {
+            for kid in kids {
+                let kid_id = match kid {
+                    Object::Reference(id) => Some(*id),
+                    _ => None,
+                };
+                if let Some(Object::Dictionary(kid_dict)) = resolve(doc, kid) {
+                    if kid_dict.has(b"T") {
+                        // a sub field, not a widget
+                        continue;
+                    }
+                    if let Some(page) = self.page_of(doc, kid_id, kid_dict) {
+                        return Some(page);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+fn walk_fields(
+    doc: &Document,
+    pages: &PageLookup,
+    fields: &[Object],
+    parent_name: &str,
+    depth: usize,
+    out: &mut Vec<String>,
+) {
+    // field trees can in theory be cyclic
+    if depth > 32 {
+        return;
+    }
+    for field in fields {
+        let id = match field {
+            Object::Reference(id) => Some(*id),
+            _ => None,
+        };
+        let dict = match resolve(doc, field) {
+            Some(Object::Dictionary(dict)) => dict,
+            _ => continue,
+        };
+        let name = match get_resolved(doc, dict, b"T") {
+            Some(Object::String(t, _)) if parent_name.is_empty() => decode_pdf_string(t),
+            Some(Object::String(t, _)) => format!("{}.{}", parent_name, decode_pdf_string(t)),
+            _ => parent_name.to_string(),
+        };
+        if let Some(value) = dict.get(b"V").ok().and_then(|v| format_value(doc, v)) {
+            if !value.is_empty() {
+                match pages.page_of(doc, id, dict) {
+                    Some(page) => out.push(format!("Page {} [{}]: {}", page, name, value)),
+                    None => out.push(format!("[{}]: {}", name, value)),
+                }
+            }
+        }
+        if let Some(Object::Array(kids)) = get_resolved(doc, dict, b"Kids") {
+            walk_fields(doc, pages, kids, &name, depth + 1, out);
+        }
+    }
+}
+
+fn unescape_xml(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// XFA forms store their values in the "datasets" XML packet. Output every leaf element with text content
+fn xfa_dataset_lines(doc: &Document, acroform: &Dictionary) -> Vec<String> {
+    lazy_static! {
+        static ref LEAF_ELEMENT: Regex =
+            Regex::new(r"<([A-Za-z_][\w.:-]*)(?:\s[^>]*)?>([^<]+)</([A-Za-z_][\w.:-]*)>").unwrap();
+    }
+    let datasets = match get_resolved(doc, acroform, b"XFA") {
+        Some(Object::Stream(stream)) => Some(stream),
+        // array of alternating packet names and streams
+        Some(Object::Array(packets)) => packets
+            .chunks(2)
+            .filter(|p| p.len() == 2)
+            .find(|p| format_value(doc, &p[0]).as_deref() == Some("datasets"))
+            .and_then(|p| match resolve(doc, &p[1]) {
+                Some(Object::Stream(stream)) => Some(stream),
+                _ => None,
+            }),
+        _ => None,
+    };
+    let datasets = match datasets {
+        Some(stream) => stream
+            .decompressed_content()
+            .unwrap_or_else(|_| stream.content.clone()),
+        None => return vec![],
+    };
+    let xml = String::from_utf8_lossy(&datasets);
+    LEAF_ELEMENT
+        .captures_iter(&xml)
+        .filter(|c| c[1] == c[3])
+        .filter_map(|c| {
+            let value = unescape_xml(c[2].trim());
+            if value.is_empty() {
+                None
+            } else {
+                Some(format!("[{}]: {}", &c[1], value))
+            }
+        })
+        .collect()
+}
+
+/// one line per filled form field, as `Page 3 [FieldName]: value`
+fn form_field_lines(doc: &Document) -> Vec<String> {
+    let catalog = match doc
+        .trailer
+        .get(b"Root")
+        .ok()
+        .and_then(|root| resolve(doc, root))
+    {
+        Some(Object::Dictionary(catalog)) => catalog,
+        _ => return vec![],
+    };
+    let acroform = match get_resolved(doc, catalog, b"AcroForm") {
+        Some(Object::Dictionary(acroform)) => acroform,
+        _ => return vec![],
+    };
+    let mut out = vec![];
+    if let Some(Object::Array(fields)) = get_resolved(doc, acroform, b"Fields") {
+        let pages = PageLookup::new(doc);
+        walk_fields(doc, &pages, fields, "", 0, &mut out);
+    }
+    out.extend(xfa_dataset_lines(doc, acroform));
+    out
+}
+
+impl FileAdapter for PdfFormsAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        let AdaptInfo {
+            filepath_hint,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            mut config,
+            ..
+        } = ai;
+        let mut pdf = Vec::new();
+        inp.read_to_end(&mut pdf)?;
+        let lines = match Document::load_mem(&pdf) {
+            Ok(doc) => form_field_lines(&doc),
+            Err(e) => {
+                debug!(
+                    "could not parse {} for form fields: {}",
+                    filepath_hint.to_string_lossy(),
+                    e
+                );
+                vec![]
+            }
+        };
+        debug!("found {} filled form fields", lines.len());
+
+        // hand the pdf itself to the next adapter in line (usually poppler)
+        config.adapters = enabled_adapters(&config)?
+            .iter()
+            .map(|a| a.metadata().name.clone())
+            .filter(|name| name != &METADATA.name)
+            .collect();
+        let mut outputs = vec![];
+        if !lines.is_empty() {
+            let mut fields_hint = filepath_hint.clone().into_os_string();
+            fields_hint.push(".formfields.txt");
+            outputs.push(AdaptInfo {
+                filepath_hint: PathBuf::from(fields_hint),
+                is_real_file: false,
+                inp: Box::new(Cursor::new(lines.join("\n").into_bytes())),
+                line_prefix: line_prefix.clone(),
+                archive_recursion_depth,
+                postprocess,
+                config: config.clone(),
+            });
+        }
+        outputs.push(AdaptInfo {
+            filepath_hint,
+            is_real_file: false,
+            inp: Box::new(Cursor::new(pdf)),
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+        });
+        Ok(Box::new(MultiAdaptedFilesIter::new(outputs)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lopdf::dictionary;
+
+    #[test]
+    fn acroform_fields() {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let field_id = doc.new_object_id();
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Annots" => vec![field_id.into()],
+        });
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        doc.objects.insert(
+            field_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Annot",
+                "Subtype" => "Widget",
+                "T" => Object::string_literal("Applicant"),
+                "V" => Object::string_literal("Jane Doe"),
+            }),
+        );
+        let acroform_id = doc.add_object(dictionary! {
+            "Fields" => vec![field_id.into()],
+        });
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+            "AcroForm" => acroform_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+
+        assert_eq!(form_field_lines(&doc), vec!["Page 1 [Applicant]: Jane Doe"]);
+    }
+}
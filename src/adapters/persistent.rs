@@ -0,0 +1,259 @@
+//! custom adapters with `"persistent": true`: the program is started once and converts all files of rga-preproc
+//! (all files of an archive, or all files with --rga-batch), so e.g. Python programs don't start the interpreter for every file.
+//!
+//! The program is run with its args (without placeholders) and gets the files as JSON-RPC 2.0 requests on stdin,
+//! framed like the language server protocol:
+//!
+//! ```text
+//! Content-Length: 105\r\n
+//! \r\n
+//! {"jsonrpc":"2.0","id":1,"method":"adapt","params":{"path":"docs/report.docx","size":6,"data":"aGVsbG8K"}}
+//! ```
+//!
+//! `path` is the path of the file (within archives: the path within the archive), `data` the content as base64.
+//! The program writes the response to stdout, with the same framing:
+//! `{"jsonrpc":"2.0","id":1,"result":{"text":"..."}}`, or
+//! `{"jsonrpc":"2.0","id":1,"error":{"code":1,"message":"..."}}` if the file could not be converted.
+//! Requests are sent one after another. stdin is closed when rga is done, then the program should exit.
+//! If the program exits or answers something else, it is started again for the next file.
+
+use super::spawning::{stderr_if_debug, subprocess_exited, subprocess_started};
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// how long to wait for the programs to exit after closing their stdin, before they are killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+struct Request<'a> {
+    jsonrpc: &'static str,
+    id: u64,
+    method: &'static str,
+    params: AdaptParams<'a>,
+}
+
+#[derive(Serialize)]
+struct AdaptParams<'a> {
+    path: &'a str,
+    size: usize,
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    id: Option<u64>,
+    result: Option<AdaptResult>,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct AdaptResult {
+    text: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+pub fn write_message(out: &mut impl Write, body: &[u8]) -> std::io::Result<()> {
+    write!(out, "Content-Length: {}\r\n\r\n", body.len())?;
+    out.write_all(body)?;
+    out.flush()
+}
+
+/// reads the body of the next message. Other headers are ignored
+pub fn read_message(inp: &mut impl BufRead) -> Result<Vec<u8>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if inp.read_line(&mut line)? == 0 {
+            bail!("the program exited");
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = Some(
+                    value
+                        .trim()
+                        .parse::<usize>()
+                        .context("invalid Content-Length")?,
+                );
+            }
+        }
+    }
+    let length = length.ok_or_else(|| format_err!("message without Content-Length"))?;
+    let mut body = vec![0; length];
+    inp.read_exact(&mut body)?;
+    Ok(body)
+}
+
+struct PersistentProcess {
+    proce: Child,
+    stdout: BufReader<ChildStdout>,
+    next_id: u64,
+}
+
+impl PersistentProcess {
+    fn start(mut command: Command) -> Result<PersistentProcess> {
+        debug!("starting persistent adapter {:?}", command);
+        let mut proce = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(stderr_if_debug())
+            .spawn()
+            .with_context(|| format!("could not start {:?}", command.get_program()))?;
+        subprocess_started(proce.id());
+        let stdout = BufReader::new(proce.stdout.take().expect("is piped"));
+        Ok(PersistentProcess {
+            proce,
+            stdout,
+            next_id: 1,
+        })
+    }
+
+    /// the outer error means the process can't be used anymore, the inner one that it could not convert the file
+    fn adapt(&mut self, path: &str, data: &[u8]) -> Result<Result<String, RpcError>> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = serde_json::to_vec(&Request {
+            jsonrpc: "2.0",
+            id,
+            method: "adapt",
+            params: AdaptParams {
+                path,
+                size: data.len(),
+                data: base64::encode(data),
+            },
+        })?;
+        let stdin = self.proce.stdin.as_mut().expect("is piped");
+        write_message(stdin, &request).context("could not send the file")?;
+        let response: Response = serde_json::from_slice(&read_message(&mut self.stdout)?)
+            .context("could not parse the response")?;
+        if response.id != Some(id) {
+            bail!("response to request {:?}, expected {}", response.id, id);
+        }
+        match (response.result, response.error) {
+            (_, Some(error)) => Ok(Err(error)),
+            (Some(result), None) => Ok(Ok(result.text)),
+            (None, None) => bail!("response without result or error"),
+        }
+    }
+
+    fn stop(mut self) {
+        // closing stdin tells the program to exit
+        drop(self.proce.stdin.take());
+        let start = Instant::now();
+        while let Ok(None) = self.proce.try_wait() {
+            if start.elapsed() > STOP_TIMEOUT {
+                debug!("persistent adapter did not exit, killing it");
+                self.proce.kill().ok();
+                self.proce.wait().ok();
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        subprocess_exited(self.proce.id());
+    }
+}
+
+type Handle = Arc<Mutex<PersistentProcess>>;
+
+lazy_static! {
+    /// the running programs, by their command
+    static ref PROCESSES: Mutex<HashMap<String, Handle>> = Mutex::new(HashMap::new());
+}
+
+/// Convert the file with the program of the command, starting it if it is not running yet
+pub fn convert(command: Command, filepath_hint: &Path, inp: &mut dyn Read) -> Result<String> {
+    let key = format!("{:?} {:?}", command.get_envs().collect::<Vec<_>>(), command);
+    let exe = command.get_program().to_string_lossy().into_owned();
+    let process = {
+        let mut processes = PROCESSES.lock().unwrap();
+        match processes.get(&key) {
+            Some(process) => process.clone(),
+            None => {
+                let process = Arc::new(Mutex::new(PersistentProcess::start(command)?));
+                processes.insert(key.clone(), process.clone());
+                process
+            }
+        }
+    };
+    let mut data = Vec::new();
+    inp.read_to_end(&mut data)?;
+    let path = filepath_hint.to_string_lossy();
+    let res = process.lock().unwrap().adapt(&path, &data);
+    match res {
+        Ok(Ok(text)) => Ok(text),
+        Ok(Err(e)) => Err(format_err!("{} failed ({}): {}", exe, e.code, e.message)),
+        Err(e) => {
+            // start it again for the next file
+            let mut processes = PROCESSES.lock().unwrap();
+            if processes
+                .get(&key)
+                .map_or(false, |p| Arc::ptr_eq(p, &process))
+            {
+                processes.remove(&key);
+            }
+            drop(processes);
+            if let Ok(process) = Arc::try_unwrap(process) {
+                process.into_inner().unwrap().stop();
+            }
+            Err(e.context(format!("persistent adapter {}", exe)))
+        }
+    }
+}
+
+/// stop all programs. Called before exiting, so they can finish cleanly
+pub fn stop_all() {
+    let processes: Vec<Handle> = PROCESSES.lock().unwrap().drain().map(|(_, p)| p).collect();
+    for process in processes {
+        match Arc::try_unwrap(process) {
+            Ok(process) => process.into_inner().unwrap().stop(),
+            // still converting a file on another thread, it gets EOF when rga exits
+            Err(_) => debug!("persistent adapter still in use, not stopping it"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn framing() -> Result<()> {
+        let mut buf = Vec::new();
+        write_message(&mut buf, br#"{"id":1}"#)?;
+        write_message(&mut buf, b"{}")?;
+        assert_eq!(&buf[..23], b"Content-Length: 8\r\n\r\n{\"");
+        let mut inp = &buf[..];
+        assert_eq!(read_message(&mut inp)?, br#"{"id":1}"#);
+        assert_eq!(read_message(&mut inp)?, b"{}");
+        assert!(read_message(&mut inp).is_err());
+
+        let mut other_headers =
+            &b"Content-Type: application/json\r\ncontent-length: 2\r\n\r\n{}"[..];
+        assert_eq!(read_message(&mut other_headers)?, b"{}");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn not_a_persistent_program() {
+        // cat echoes the request, which is no response
+        let err = convert(Command::new("cat"), Path::new("a.txt"), &mut &b"hello"[..]).unwrap_err();
+        assert!(format!("{:#}", err).contains("persistent adapter cat"));
+    }
+}
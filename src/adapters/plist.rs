@@ -0,0 +1,90 @@
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+use std::io::Cursor;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["plist", "bplist"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "plist".to_owned(),
+        version: 1,
+        description:
+            "Converts binary Apple property lists to XML. XML property lists are passed through"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-bplist".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct PlistAdapter;
+
+impl PlistAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(PlistAdapter))
+    }
+}
+impl GetMetadata for PlistAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn adapt_plist(data: &[u8], oup: &mut dyn Write) -> Result<()> {
+    if !data.starts_with(b"bplist00") {
+        // already text
+        oup.write_all(data)?;
+        return Ok(());
+    }
+    let value = ::plist::Value::from_reader(Cursor::new(data))?;
+    value.to_writer_xml(oup)?;
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for PlistAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo { mut inp, .. } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data)?;
+        adapt_plist(&data, oup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn binary_to_xml() -> Result<()> {
+        let mut dict = ::plist::Dictionary::new();
+        dict.insert(
+            "NSRecentDocuments".to_string(),
+            "/Users/me/notes.txt".into(),
+        );
+        let mut binary = Vec::new();
+        ::plist::Value::Dictionary(dict).to_writer_binary(&mut binary)?;
+        assert!(binary.starts_with(b"bplist00"));
+
+        let mut oup = Vec::new();
+        adapt_plist(&binary, &mut oup)?;
+        let xml = String::from_utf8(oup)?;
+        assert!(xml.contains("<key>NSRecentDocuments</key>"));
+        assert!(xml.contains("<string>/Users/me/notes.txt</string>"));
+        Ok(())
+    }
+}
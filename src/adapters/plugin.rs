@@ -0,0 +1,196 @@
+//! custom adapters implemented in a shared library ("library" instead of "binary"), loaded when they are first used.
+//!
+//! The library has to export these functions (C ABI):
+//!
+//! ```c
+//! // the version of this interface, must return 1
+//! uint32_t rga_abi_version(void);
+//! // extract the text of a file. Returns 0 on success, then *out is the text (UTF-8),
+//! // otherwise *out is the error message. path is the (lossy UTF-8) path of the file, as a hint
+//! int32_t rga_adapt(const uint8_t *input, size_t input_len, const char *path, uint8_t **out, size_t *out_len);
+//! // free an output of rga_adapt
+//! void rga_free(uint8_t *out, size_t out_len);
+//! ```
+//!
+//! The library is called from multiple threads at the same time and must be thread safe.
+
+use super::custom::CustomAdapterConfig;
+use super::*;
+use crate::adapted_iter::{MultiAdaptedFilesIter, SingleAdaptedFileAsIter};
+use anyhow::*;
+use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::sync::{Arc, Mutex};
+
+pub const ABI_VERSION: u32 = 1;
+
+type VersionFn = unsafe extern "C" fn() -> u32;
+type AdaptFn =
+    unsafe extern "C" fn(*const u8, usize, *const c_char, *mut *mut u8, *mut usize) -> i32;
+type FreeFn = unsafe extern "C" fn(*mut u8, usize);
+
+pub struct Plugin {
+    adapt: AdaptFn,
+    free: FreeFn,
+    // the functions point into the library, so it's never unloaded
+    _library: libloading::Library,
+}
+
+lazy_static! {
+    static ref PLUGINS: Mutex<HashMap<PathBuf, Arc<Plugin>>> = Mutex::new(HashMap::new());
+}
+
+impl Plugin {
+    pub fn load(path: &Path) -> Result<Plugin> {
+        // safety: the library is trusted like the binaries of custom adapters, the functions are checked by name only
+        unsafe {
+            let library = libloading::Library::new(path)?;
+            let version: VersionFn = *library
+                .get::<VersionFn>(b"rga_abi_version\0")
+                .context("not an rga plugin")?;
+            let version = version();
+            if version != ABI_VERSION {
+                return Err(format_err!(
+                    "plugin has interface version {}, rga supports {}",
+                    version,
+                    ABI_VERSION
+                ));
+            }
+            Ok(Plugin {
+                adapt: *library.get::<AdaptFn>(b"rga_adapt\0")?,
+                free: *library.get::<FreeFn>(b"rga_free\0")?,
+                _library: library,
+            })
+        }
+    }
+
+    /// the loaded plugin, so libraries are loaded once per process
+    pub fn get(path: &Path) -> Result<Arc<Plugin>> {
+        let mut plugins = PLUGINS.lock().expect("plugin lock poisoned");
+        if let Some(plugin) = plugins.get(path) {
+            return Ok(plugin.clone());
+        }
+        let plugin = Arc::new(
+            Plugin::load(path).with_context(|| format!("loading plugin {}", path.display()))?,
+        );
+        plugins.insert(path.to_owned(), plugin.clone());
+        Ok(plugin)
+    }
+
+    pub fn adapt(&self, input: &[u8], path: &Path) -> Result<Vec<u8>> {
+        let path = CString::new(path.to_string_lossy().replace('\0', ""))?;
+        let mut out: *mut u8 = std::ptr::null_mut();
+        let mut out_len: usize = 0;
+        let status = unsafe {
+            (self.adapt)(
+                input.as_ptr(),
+                input.len(),
+                path.as_ptr(),
+                &mut out,
+                &mut out_len,
+            )
+        };
+        let output = if out.is_null() {
+            vec![]
+        } else {
+            let output = unsafe { std::slice::from_raw_parts(out, out_len) }.to_vec();
+            unsafe { (self.free)(out, out_len) };
+            output
+        };
+        if status != 0 {
+            return Err(format_err!(
+                "plugin failed ({}): {}",
+                status,
+                String::from_utf8_lossy(&output)
+            ));
+        }
+        Ok(output)
+    }
+}
+
+pub struct PluginAdapter {
+    library: PathBuf,
+    meta: AdapterMeta,
+}
+
+impl PluginAdapter {
+    pub fn new(config: &CustomAdapterConfig, library: &str) -> PluginAdapter {
+        let mut meta = config.adapter_meta();
+        meta.description = format!("{}\nLoads: {}", config.description, library);
+        PluginAdapter {
+            library: PathBuf::from(library),
+            meta,
+        }
+    }
+}
+
+impl GetMetadata for PluginAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &self.meta
+    }
+}
+
+impl FileAdapter for PluginAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        let AdaptInfo {
+            filepath_hint,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let plugin = Plugin::get(&self.library)?;
+        let mut input = Vec::new();
+        inp.read_to_end(&mut input)?;
+        let output = plugin.adapt(&input, &filepath_hint)?;
+        if output.is_empty() {
+            return Ok(Box::new(MultiAdaptedFilesIter::new(vec![])));
+        }
+        // the output is plain text, so make sure it is not matched by this adapter again
+        let mut output_hint = filepath_hint.into_os_string();
+        output_hint.push(".txt");
+        Ok(Box::new(SingleAdaptedFileAsIter::new(AdaptInfo {
+            filepath_hint: PathBuf::from(output_hint),
+            is_real_file: false,
+            archive_recursion_depth,
+            inp: Box::new(std::io::Cursor::new(output)),
+            line_prefix,
+            postprocess,
+            config,
+        })))
+    }
+
+    /// the library can be replaced by another version, so its modification time is part of the cache key
+    fn definition_digest(&self) -> Option<Vec<u8>> {
+        let modified = std::fs::metadata(&self.library)
+            .and_then(|m| m.modified())
+            .ok();
+        let definition = bincode::serialize(&(&self.library, modified, self.meta.version))
+            .expect("could not serialize adapter definition");
+        Some(Sha256::digest(&definition).to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn not_a_plugin() {
+        assert!(Plugin::get(Path::new("/no/such/library.so")).is_err());
+        let err = Plugin::load(Path::new("libc.so.6"))
+            .err()
+            .expect("libc is no plugin");
+        assert!(format!("{:#}", err).contains("not an rga plugin"));
+    }
+}
@@ -2,13 +2,22 @@
 
 //impl<T> FileAdapter for T where T: RunFnAdapter {}
 
-use anyhow::Result;
+use anyhow::*;
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use serde::{Deserialize, Serialize};
 
-use std::{cmp::min, io::Read};
+use std::{
+    cmp::min,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
 
 use crate::adapted_iter::{AdaptedFilesIterBox, SingleAdaptedFileAsIter};
+use crate::config::{ControlChars, RgaConfig};
+use crate::detection::Detection;
 
-use super::{AdaptInfo, AdapterMeta, FileAdapter, GetMetadata};
+use super::{AdaptInfo, AdapterMeta, FileAdapter, GetMetadata, ReadBox};
 
 pub struct EnsureEndsWithNewline<R: Read> {
     inner: R,
@@ -111,7 +120,7 @@ impl GetMetadata for PostprocPrefix {
         lazy_static::lazy_static! {
             static ref METADATA: AdapterMeta = AdapterMeta {
                 name: "postprocprefix".to_owned(),
-                version: 1,
+                version: 2,
                 description: "Adds the line prefix to each line".to_owned(),
                 recurses: true,
                 fast_matchers: vec![],
@@ -129,15 +138,475 @@ impl FileAdapter for PostprocPrefix {
         a: super::AdaptInfo<'a>,
         _detection_reason: &crate::matching::FileMatcher,
     ) -> Result<AdaptedFilesIterBox<'a>> {
-        let read = EnsureEndsWithNewline::new(postproc_prefix(&a.line_prefix, a.inp)?);
+        let read: ReadBox = if a.config.json_records {
+            // every record ends with a newline
+            Box::new(JsonRecords::new(&a.line_prefix, a.inp))
+        } else if a.line_prefix.is_empty() {
+            // without a prefix, the input is passed through as is instead of being split at every newline
+            Box::new(EnsureEndsWithNewline::new(a.inp))
+        } else {
+            Box::new(EnsureEndsWithNewline::new(postproc_prefix(
+                &a.line_prefix,
+                a.inp,
+            )?))
+        };
         // keep adapt info (filename etc) except replace inp
         let ai = AdaptInfo {
-            inp: Box::new(read),
+            inp: read,
             postprocess: false,
             ..a
         };
         Ok(Box::new(SingleAdaptedFileAsIter::new(ai)))
     }
+
+    fn cache_key_extra(&self, _filepath: &Path, config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        // the input was transcoded depending on this
+        Ok(config.encoding.label.clone().map(String::into_bytes))
+    }
+
+    fn passthrough_name(&self, filepath: &Path) -> Option<PathBuf> {
+        Some(filepath.to_owned())
+    }
+}
+
+/// one line of output with --format json of rga-preproc
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TextRecord {
+    /// the file given to rga-preproc
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_path: Option<String>,
+    /// the line prefix of the file within the source (e.g. "dir/file.docx" in a zip file), None for the source itself
+    pub subfile_path: Option<String>,
+    /// from the "Page N:" prefix of paginated documents
+    pub page: Option<u32>,
+    /// 1-based line number within the subfile, 0 for the detection
+    pub line: u64,
+    pub text: String,
+    /// with --rga-verbose, the first record of every file says how it is handled (the text is empty)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detection: Option<Detection>,
+}
+
+impl TextRecord {
+    /// parse a line of json records. Lines that are not records (e.g. messages of archive adapters)
+    /// are turned into records of the source file
+    pub fn from_line(line: &[u8], line_number: u64) -> TextRecord {
+        serde_json::from_slice(line).unwrap_or_else(|_| TextRecord {
+            source_path: None,
+            subfile_path: None,
+            page: None,
+            line: line_number,
+            text: String::from_utf8_lossy(line).into_owned(),
+            detection: None,
+        })
+    }
+}
+
+/// splits the "Page N:" prefix of postproc_pagebreaks off a line
+pub fn split_page(line: &str) -> Option<(u32, &str)> {
+    let rest = line.strip_prefix("Page ")?;
+    let colon = rest.find(':')?;
+    let page = rest[..colon].parse().ok()?;
+    Some((page, &rest[colon + 1..]))
+}
+
+/// the subfile path of the records of a file with the line prefix, see RgaConfig::entry_prefix
+pub fn subfile_path(line_prefix: &str) -> Option<String> {
+    let subfile_path = line_prefix.strip_suffix(": ").unwrap_or(line_prefix);
+    Some(subfile_path.to_string()).filter(|p| !p.is_empty())
+}
+
+/// turns the lines of the input into TextRecords
+struct JsonRecords<R: Read> {
+    inner: BufReader<R>,
+    subfile_path: Option<String>,
+    line: u64,
+    line_buf: Vec<u8>,
+    /// the serialized records not read yet
+    pending: Vec<u8>,
+    pending_pos: usize,
+}
+
+impl<R: Read> JsonRecords<R> {
+    fn new(line_prefix: &str, inner: R) -> JsonRecords<R> {
+        JsonRecords {
+            inner: BufReader::new(inner),
+            subfile_path: subfile_path(line_prefix),
+            line: 0,
+            line_buf: Vec::new(),
+            pending: Vec::new(),
+            pending_pos: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for JsonRecords<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending_pos == self.pending.len() {
+            self.line_buf.clear();
+            if self.inner.read_until(b'\n', &mut self.line_buf)? == 0 {
+                return Ok(0);
+            }
+            if self.line_buf.last() == Some(&b'\n') {
+                self.line_buf.pop();
+            }
+            self.line += 1;
+            let text = String::from_utf8_lossy(&self.line_buf);
+            let (page, text) = match split_page(&text) {
+                Some((page, text)) => (Some(page), text),
+                None => (None, &text[..]),
+            };
+            let record = TextRecord {
+                source_path: None,
+                subfile_path: self.subfile_path.clone(),
+                page,
+                line: self.line,
+                text: text.to_string(),
+                detection: None,
+            };
+            self.pending.clear();
+            self.pending_pos = 0;
+            serde_json::to_writer(&mut self.pending, &record)?;
+            self.pending.push(b'\n');
+        }
+        let n = min(buf.len(), self.pending.len() - self.pending_pos);
+        buf[..n].copy_from_slice(&self.pending[self.pending_pos..self.pending_pos + n]);
+        self.pending_pos += n;
+        Ok(n)
+    }
+}
+
+/// UTF-16 text without a byte order mark: every other byte of ASCII characters is zero
+fn utf16_without_bom(buf: &[u8]) -> Option<&'static Encoding> {
+    let pairs = buf.len() / 2;
+    if pairs < 2 {
+        return None;
+    }
+    let even_zeros = buf.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd_zeros = buf.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+    if odd_zeros > pairs / 2 && even_zeros < pairs / 20 {
+        Some(encoding_rs::UTF_16LE)
+    } else if even_zeros > pairs / 2 && odd_zeros < pairs / 20 {
+        Some(encoding_rs::UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// the encoding of a text file given its first block, if it needs to be transcoded to UTF-8.
+///
+/// Returns None for UTF-8 (and ASCII) text and binary files.
+pub fn detect_encoding(label: Option<&str>, buf: &[u8]) -> Result<Option<&'static Encoding>> {
+    match label {
+        Some("none") => return Ok(None),
+        Some(label) => {
+            return Encoding::for_label(label.as_bytes())
+                .map(Some)
+                .ok_or_else(|| format_err!("unknown encoding {}", label))
+        }
+        None => {}
+    }
+    if let Some((encoding, _)) = Encoding::for_bom(buf) {
+        // also for utf-8, to strip the bom
+        return Ok(Some(encoding));
+    }
+    if let Some(encoding) = utf16_without_bom(buf) {
+        return Ok(Some(encoding));
+    }
+    if buf.contains(&0) {
+        return Ok(None);
+    }
+    match std::str::from_utf8(buf) {
+        Ok(_) => return Ok(None),
+        // the block may end in the middle of a character
+        Err(e) if e.error_len().is_none() => return Ok(None),
+        Err(_) => {}
+    }
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(buf, false);
+    let encoding = detector.guess(None, true);
+    Ok(if encoding == encoding_rs::UTF_8 {
+        None
+    } else {
+        Some(encoding)
+    })
+}
+
+/// transcode the input from the given encoding to UTF-8
+pub fn postproc_encoding(encoding: &'static Encoding, inp: impl Read) -> impl Read {
+    DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding))
+        .bom_override(true)
+        .strip_bom(true)
+        .build(inp)
+}
+
+/// the file:// URI of an absolute path
+pub fn file_uri(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let path = crate::paths::strip_verbatim(&path)
+        .unwrap_or_else(|| path.into_owned())
+        .replace('\\', "/");
+    // the server of a UNC path (\\server\share\...) is the host
+    let mut uri = String::from(if path.starts_with("//") {
+        "file:"
+    } else {
+        "file://"
+    });
+    if !path.starts_with('/') {
+        // windows drive letter
+        uri.push('/');
+    }
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' | b':' => {
+                uri.push(b as char)
+            }
+            b => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+/// a terminal hyperlink (OSC 8) to the uri with the given text
+pub fn hyperlink(uri: &str, text: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", uri, text)
+}
+
+/// escape sequences longer than this are not ended, e.g. an OSC without terminator.
+/// Enough for the long URIs of hyperlinks
+const MAX_SEQUENCE_LEN: usize = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ControlState {
+    Text,
+    /// after 0xC2, the first byte of the UTF-8 encoding of the C1 controls U+0080 - U+009F
+    C1Lead,
+    /// after ESC
+    Escape,
+    /// control sequence (ESC [), ended by a byte in @ - ~
+    Csi,
+    /// OSC, DCS, SOS, PM and APC strings, ended by BEL or ESC \
+    Str,
+    /// ESC in a string
+    StrEscape,
+}
+
+/// C0 controls and DEL, except tab, newline, carriage return and form feed
+fn is_control(b: u8) -> bool {
+    (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | b'\x0c')) || b == 0x7f
+}
+
+/// Strips or escapes (see --rga-control-chars) terminal control sequences and control characters.
+/// Works on bytes, so the output of adapters does not need to be valid UTF-8
+pub struct ControlCharsFilter<R: Read> {
+    inner: R,
+    escape: bool,
+    keep_hyperlinks: bool,
+    state: ControlState,
+    /// the escape sequence read so far
+    sequence: Vec<u8>,
+    out: Vec<u8>,
+    out_pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> ControlCharsFilter<R> {
+    fn new(inner: R, escape: bool, keep_hyperlinks: bool) -> ControlCharsFilter<R> {
+        ControlCharsFilter {
+            inner,
+            escape,
+            keep_hyperlinks,
+            state: ControlState::Text,
+            sequence: Vec::new(),
+            out: Vec::new(),
+            out_pos: 0,
+            eof: false,
+        }
+    }
+
+    /// output a control character or a whole sequence
+    fn control(&mut self, bytes: &[u8]) {
+        if !self.escape {
+            return;
+        }
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                0xc2 if matches!(bytes.get(i + 1), Some(0x80..=0x9f)) => {
+                    self.out
+                        .extend_from_slice(format!("\\u{{{:x}}}", bytes[i + 1]).as_bytes());
+                    i += 1;
+                }
+                b if is_control(b) => self
+                    .out
+                    .extend_from_slice(format!("\\x{:02x}", b).as_bytes()),
+                b => self.out.push(b),
+            }
+            i += 1;
+        }
+    }
+
+    fn complete(&mut self) {
+        let sequence = std::mem::take(&mut self.sequence);
+        self.state = ControlState::Text;
+        if self.keep_hyperlinks && sequence.starts_with(b"\x1b]8;") {
+            self.out.extend_from_slice(&sequence);
+        } else {
+            self.control(&sequence);
+        }
+    }
+
+    /// not a valid sequence: only the start is a control, the rest is read again as text
+    fn abort(&mut self) {
+        let sequence = std::mem::take(&mut self.sequence);
+        self.state = ControlState::Text;
+        let intro = if sequence[0] == 0x1b { 1 } else { 2 };
+        self.control(&sequence[..intro]);
+        for &b in &sequence[intro..] {
+            self.push(b);
+        }
+    }
+
+    fn push(&mut self, b: u8) {
+        use ControlState::*;
+        match self.state {
+            Text => match b {
+                0xc2 => self.state = C1Lead,
+                0x1b => {
+                    self.sequence.push(b);
+                    self.state = Escape;
+                }
+                b if is_control(b) => self.control(&[b]),
+                b => self.out.push(b),
+            },
+            C1Lead => {
+                self.state = Text;
+                match b {
+                    0x9b => {
+                        self.sequence.extend_from_slice(&[0xc2, b]);
+                        self.state = Csi;
+                    }
+                    0x90 | 0x98 | 0x9d | 0x9e | 0x9f => {
+                        self.sequence.extend_from_slice(&[0xc2, b]);
+                        self.state = Str;
+                    }
+                    0x80..=0x9f => self.control(&[0xc2, b]),
+                    b => {
+                        self.out.push(0xc2);
+                        self.push(b);
+                    }
+                }
+            }
+            Escape => match b {
+                b'[' | b']' | b'P' | b'X' | b'^' | b'_' | 0x20..=0x7e => {
+                    self.sequence.push(b);
+                    match b {
+                        b'[' => self.state = Csi,
+                        b']' | b'P' | b'X' | b'^' | b'_' => self.state = Str,
+                        // intermediate bytes, e.g. ESC ( B
+                        0x20..=0x2f => {}
+                        _ => self.complete(),
+                    }
+                }
+                b => {
+                    self.abort();
+                    self.push(b);
+                }
+            },
+            Csi => match b {
+                0x20..=0x3f => self.sequence.push(b),
+                0x40..=0x7e => {
+                    self.sequence.push(b);
+                    self.complete();
+                }
+                b => {
+                    self.abort();
+                    self.push(b);
+                }
+            },
+            Str => match b {
+                0x07 => {
+                    self.sequence.push(b);
+                    self.complete();
+                }
+                0x1b => {
+                    self.sequence.push(b);
+                    self.state = StrEscape;
+                }
+                // a match is printed with its line, so a sequence does not continue on the next line
+                b'\n' => {
+                    self.abort();
+                    self.push(b);
+                }
+                b => self.sequence.push(b),
+            },
+            StrEscape => {
+                if b == b'\\' {
+                    self.sequence.push(b);
+                    self.complete();
+                } else {
+                    // the string ends with the start of the next sequence
+                    self.sequence.pop();
+                    self.complete();
+                    self.push(0x1b);
+                    self.push(b);
+                }
+            }
+        }
+        if self.sequence.len() > MAX_SEQUENCE_LEN {
+            self.abort();
+        }
+    }
+
+    fn finish(&mut self) {
+        while !self.sequence.is_empty() {
+            self.abort();
+        }
+        if self.state == ControlState::C1Lead {
+            self.out.push(0xc2);
+        }
+        self.state = ControlState::Text;
+    }
+}
+
+impl<R: Read> Read for ControlCharsFilter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if self.out_pos < self.out.len() {
+                let n = min(buf.len(), self.out.len() - self.out_pos);
+                buf[..n].copy_from_slice(&self.out[self.out_pos..self.out_pos + n]);
+                self.out_pos += n;
+                return Ok(n);
+            }
+            if self.eof || buf.is_empty() {
+                return Ok(0);
+            }
+            self.out.clear();
+            self.out_pos = 0;
+            let mut chunk = [0u8; 8192];
+            let n = self.inner.read(&mut chunk)?;
+            if n == 0 {
+                self.eof = true;
+                self.finish();
+            }
+            for &b in &chunk[..n] {
+                self.push(b);
+            }
+        }
+    }
+}
+
+/// remove or escape the terminal control sequences and control characters of the output, see --rga-control-chars
+pub fn postproc_control_chars<'a>(config: &RgaConfig, inp: ReadBox<'a>) -> ReadBox<'a> {
+    match config.control_chars {
+        ControlChars::Keep => inp,
+        mode => Box::new(ControlCharsFilter::new(
+            inp,
+            mode == ControlChars::Escape,
+            config.hyperlinks,
+        )),
+    }
 }
 
 pub fn postproc_prefix(line_prefix: &str, inp: impl Read) -> Result<impl Read> {
@@ -150,19 +619,24 @@ pub fn postproc_prefix(line_prefix: &str, inp: impl Read) -> Result<impl Read> {
     })
 }
 
-pub fn postproc_pagebreaks(line_prefix: &str, inp: impl Read) -> Result<impl Read> {
+/// prefix the lines with the page number (pages are separated by form feeds), formatted by page_prefix
+pub fn postproc_pagebreaks(
+    line_prefix: &str,
+    page_prefix: impl Fn(usize) -> String + 'static,
+    inp: impl Read,
+) -> Result<impl Read> {
     let line_prefix = line_prefix.to_string(); // clone since
     let mut page_count = 1;
 
     Ok(ByteReplacer {
         inner: inp,
-        next_read: format!("{}Page {}:", line_prefix, page_count).into_bytes(),
+        next_read: format!("{}{}", line_prefix, page_prefix(page_count)).into_bytes(),
         haystacker: Box::new(|buf| memchr::memchr2(b'\n', b'\x0c', buf)),
         replacer: Box::new(move |b| match b {
-            b'\n' => format!("\n{}Page {}:", line_prefix, page_count).into_bytes(),
+            b'\n' => format!("\n{}{}", line_prefix, page_prefix(page_count)).into_bytes(),
             b'\x0c' => {
                 page_count += 1;
-                format!("\n{}Page {}:", line_prefix, page_count).into_bytes()
+                format!("\n{}{}", line_prefix, page_prefix(page_count)).into_bytes()
             }
             _ => b"[[imposs]]".to_vec(),
         }),
@@ -171,13 +645,18 @@ pub fn postproc_pagebreaks(line_prefix: &str, inp: impl Read) -> Result<impl Rea
 
 #[cfg(test)]
 mod tests {
-    use super::postproc_pagebreaks;
+    use super::{
+        detect_encoding, file_uri, hyperlink, postproc_encoding, postproc_pagebreaks,
+        ControlCharsFilter, JsonRecords, TextRecord,
+    };
+    use crate::config::PageFormat;
     use anyhow::Result;
     use std::io::Read;
 
     fn test_from_strs(a: &str, b: &str) -> Result<()> {
         let mut oup = Vec::new();
-        postproc_pagebreaks("", a.as_bytes())?.read_to_end(&mut oup)?;
+        postproc_pagebreaks("", |page| PageFormat::default().prefix(page), a.as_bytes())?
+            .read_to_end(&mut oup)?;
         let c = String::from_utf8_lossy(&oup);
         if b != c {
             anyhow::bail!("{}\nshould be\n{}\nbut is\n{}", a, b, c);
@@ -202,4 +681,155 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn hyperlinks() -> Result<()> {
+        let uri = file_uri(std::path::Path::new("/home/me/my report.pdf"));
+        assert_eq!(uri, "file:///home/me/my%20report.pdf");
+        assert_eq!(
+            file_uri(std::path::Path::new(r"\\?\C:\docs\a.pdf")),
+            "file:///C:/docs/a.pdf"
+        );
+        assert_eq!(
+            file_uri(std::path::Path::new(r"\\?\UNC\server\share\a b.pdf")),
+            "file://server/share/a%20b.pdf"
+        );
+        let mut oup = String::new();
+        postproc_pagebreaks(
+            "",
+            move |page| {
+                hyperlink(
+                    &format!("{}#page={}", uri, page),
+                    &format!("Page {}:", page),
+                )
+            },
+            "a\x0cb".as_bytes(),
+        )?
+        .read_to_string(&mut oup)?;
+        assert_eq!(
+            oup,
+            "\x1b]8;;file:///home/me/my%20report.pdf#page=1\x1b\\Page 1:\x1b]8;;\x1b\\a\n\
+             \x1b]8;;file:///home/me/my%20report.pdf#page=2\x1b\\Page 2:\x1b]8;;\x1b\\b"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn json_records() -> Result<()> {
+        let mut oup = String::new();
+        JsonRecords::new(
+            "dir/report.pdf: ",
+            "Page 1:first\nPage 2:\"second\"".as_bytes(),
+        )
+        .read_to_string(&mut oup)?;
+        let records = oup
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<serde_json::Result<Vec<TextRecord>>>()?;
+        assert_eq!(
+            records,
+            vec![
+                TextRecord {
+                    source_path: None,
+                    subfile_path: Some("dir/report.pdf".to_string()),
+                    page: Some(1),
+                    line: 1,
+                    text: "first".to_string(),
+                    detection: None,
+                },
+                TextRecord {
+                    source_path: None,
+                    subfile_path: Some("dir/report.pdf".to_string()),
+                    page: Some(2),
+                    line: 2,
+                    text: "\"second\"".to_string(),
+                    detection: None,
+                }
+            ]
+        );
+        Ok(())
+    }
+
+    fn transcode(mut data: &[u8]) -> Result<String> {
+        let mut oup = String::new();
+        match detect_encoding(None, data)? {
+            Some(encoding) => postproc_encoding(encoding, data).read_to_string(&mut oup)?,
+            None => data.read_to_string(&mut oup)?,
+        };
+        Ok(oup)
+    }
+
+    #[test]
+    fn encodings() -> Result<()> {
+        let utf16le: Vec<u8> = "2020-10-16 ERROR: Überlauf\n"
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes().to_vec())
+            .collect();
+        let mut with_bom = vec![0xFF, 0xFE];
+        with_bom.extend(&utf16le);
+        assert_eq!(transcode(&with_bom)?, "2020-10-16 ERROR: Überlauf\n");
+        assert_eq!(transcode(&utf16le)?, "2020-10-16 ERROR: Überlauf\n");
+
+        let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode("ログファイルのエラーメッセージです\n");
+        assert_eq!(transcode(&sjis)?, "ログファイルのエラーメッセージです\n");
+
+        assert_eq!(detect_encoding(None, "plain ütf-8".as_bytes())?, None);
+        assert_eq!(detect_encoding(None, b"\x7fELF\x02\x01\x01\x00\x00")?, None);
+        assert_eq!(
+            detect_encoding(Some("latin1"), b"caf\xe9")?,
+            Some(encoding_rs::WINDOWS_1252)
+        );
+        assert_eq!(detect_encoding(Some("none"), &with_bom)?, None);
+        Ok(())
+    }
+
+    fn filter(data: &[u8], escape: bool, keep_hyperlinks: bool) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        // one byte at a time, so the sequences are split between reads
+        let inp = data
+            .chunks(1)
+            .map(std::io::Cursor::new)
+            .fold(Box::new(std::io::empty()) as Box<dyn Read + '_>, |a, b| {
+                Box::new(a.chain(b))
+            });
+        ControlCharsFilter::new(inp, escape, keep_hyperlinks).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn control_chars() -> Result<()> {
+        let inp = "a \x1b[1;31mred\x1b[0m\tb\x00c\x1b]0;title\x07d\u{9b}2Je\x08\r\nf\x1b(Bg\x1b]unterminated\nh\x1b";
+        assert_eq!(
+            String::from_utf8(filter(inp.as_bytes(), false, false)?)?,
+            "a red\tbcde\r\nfg]unterminated\nh"
+        );
+        assert_eq!(
+            String::from_utf8(filter(inp.as_bytes(), true, false)?)?,
+            "a \\x1b[1;31mred\\x1b[0m\tb\\x00c\\x1b]0;title\\x07d\\u{9b}2Je\\x08\r\nf\\x1b(Bg\\x1b]unterminated\nh\\x1b"
+        );
+        // not controls
+        let text = "tab\there, ümlauts and ¢ents\x0c\n";
+        assert_eq!(
+            String::from_utf8(filter(text.as_bytes(), false, false)?)?,
+            text
+        );
+        // invalid UTF-8 is passed through
+        assert_eq!(filter(b"\xc2\xff\x80", false, false)?, b"\xc2\xff\x80");
+        Ok(())
+    }
+
+    #[test]
+    fn control_chars_keep_hyperlinks() -> Result<()> {
+        let link = hyperlink("file:///a.pdf#page=1", "Page 1:");
+        let inp = format!("{}text\x1b]0;title\x1b\\", link);
+        assert_eq!(
+            String::from_utf8(filter(inp.as_bytes(), false, true)?)?,
+            format!("{}text", link)
+        );
+        assert_eq!(
+            String::from_utf8(filter(inp.as_bytes(), false, false)?)?,
+            "Page 1:text"
+        );
+        Ok(())
+    }
 }
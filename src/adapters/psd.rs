@@ -0,0 +1,299 @@
+//! Layer names and the text of text layers in Photoshop files,
+//! see https://www.adobe.com/devnet-apps/photoshop/fileformatashtml/
+
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+use spawning::read_input;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["psd", "psb"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "psd".to_owned(),
+        version: 1,
+        description: "Outputs the layer names and the contents of text layers of Photoshop documents (.psd, .psb)".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("image/vnd.adobe.photoshop".to_owned()),
+            FileMatcher::MimeType("image/x-photoshop".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct PsdAdapter;
+
+impl PsdAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(PsdAdapter))
+    }
+}
+impl GetMetadata for PsdAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// big endian reader over the file, `large` for the 64 bit lengths of .psb files
+struct PsdReader<'d> {
+    data: &'d [u8],
+    pos: usize,
+    large: bool,
+}
+
+impl<'d> PsdReader<'d> {
+    fn bytes(&mut self, len: usize) -> Result<&'d [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or_else(|| format_err!("unexpected end of file at {}", self.pos))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+    fn u64(&mut self) -> Result<u64> {
+        let b = self.bytes(8)?;
+        let mut a = [0; 8];
+        a.copy_from_slice(b);
+        Ok(u64::from_be_bytes(a))
+    }
+    /// a length that is 64 bit in .psb files
+    fn length(&mut self) -> Result<usize> {
+        Ok(if self.large {
+            self.u64()? as usize
+        } else {
+            self.u32()? as usize
+        })
+    }
+    /// a block with a 32 bit length
+    fn block(&mut self) -> Result<&'d [u8]> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+}
+
+fn utf16be(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+/// the text of a type tool object (TySh). Instead of parsing the whole descriptor,
+/// look for the `Txt ` key with its TEXT value (a utf-16 string with a 32 bit length)
+fn type_tool_text(block: &[u8]) -> Option<String> {
+    let start = block.windows(8).position(|w| w == b"Txt TEXT")? + 8;
+    let len = u32::from_be_bytes([
+        *block.get(start)?,
+        *block.get(start + 1)?,
+        *block.get(start + 2)?,
+        *block.get(start + 3)?,
+    ]) as usize;
+    let text = block.get(start + 4..start + 4 + len * 2)?;
+    // paragraphs are separated by carriage returns
+    Some(utf16be(text).replace('\r', "\n"))
+}
+
+/// keys of additional layer information that have a 64 bit length in .psb files
+static LARGE_KEYS: &[&[u8]] = &[
+    b"LMsk", b"Lr16", b"Lr32", b"Layr", b"Mt16", b"Mt32", b"Mtrn", b"Alph", b"FMsk", b"lnk2",
+    b"FEid", b"FXid", b"PxSD",
+];
+
+/// (name, text) of each layer
+fn psd_layers(data: &[u8]) -> Result<Vec<(String, Option<String>)>> {
+    let mut r = PsdReader {
+        data,
+        pos: 0,
+        large: false,
+    };
+    if r.bytes(4)? != b"8BPS" {
+        return Err(format_err!("not a photoshop file"));
+    }
+    r.large = r.u16()? == 2;
+    // rest of the header, color mode data, image resources
+    r.bytes(20)?;
+    r.block()?;
+    r.block()?;
+    let layer_and_mask_len = r.length()?;
+    if layer_and_mask_len == 0 {
+        return Ok(vec![]);
+    }
+    let layer_info_len = r.length()?;
+    if layer_info_len == 0 {
+        return Ok(vec![]);
+    }
+    // negative if the first alpha channel contains the transparency
+    let count = (r.u16()? as i16).wrapping_abs() as u16;
+    let mut layers = vec![];
+    for _ in 0..count {
+        // bounds
+        r.bytes(16)?;
+        let channels = r.u16()? as usize;
+        for _ in 0..channels {
+            r.u16()?;
+            r.length()?;
+        }
+        // blend mode signature and key, opacity, clipping, flags, filler
+        r.bytes(12)?;
+        let extra = r.block()?;
+        let mut e = PsdReader {
+            data: extra,
+            pos: 0,
+            large: r.large,
+        };
+        // layer mask, blending ranges
+        e.block()?;
+        e.block()?;
+        // pascal string padded to a multiple of 4 bytes
+        let name_len = e.u8()? as usize;
+        let mut name = String::from_utf8_lossy(e.bytes(name_len)?).into_owned();
+        e.bytes((4 - (name_len + 1) % 4) % 4)?;
+        let mut text = None;
+        while e.pos + 12 <= extra.len() {
+            let signature = e.bytes(4)?;
+            if signature != b"8BIM" && signature != b"8B64" {
+                break;
+            }
+            let key = e.bytes(4)?;
+            let len = if e.large && LARGE_KEYS.contains(&key) {
+                e.u64()? as usize
+            } else {
+                e.u32()? as usize
+            };
+            let block = e.bytes(len)?;
+            match key {
+                b"luni" if block.len() >= 4 => {
+                    let chars = u32::from_be_bytes([block[0], block[1], block[2], block[3]]);
+                    let end = (4 + chars as usize * 2).min(block.len());
+                    name = utf16be(&block[4..end]);
+                }
+                b"TySh" => text = type_tool_text(block),
+                _ => {}
+            }
+        }
+        layers.push((name, text));
+    }
+    Ok(layers)
+}
+
+fn adapt_psd(data: &[u8], oup: &mut dyn Write) -> Result<()> {
+    for (name, text) in psd_layers(data)? {
+        // the end markers of layer groups
+        if name == "</Layer group>" {
+            continue;
+        }
+        match text {
+            Some(text) => {
+                for line in text.lines().filter(|l| !l.trim().is_empty()) {
+                    writeln!(oup, "{}: {}", name, line)?;
+                }
+            }
+            None => writeln!(oup, "layer {}", name)?,
+        }
+    }
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for PsdAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            filepath_hint,
+            is_real_file,
+            ..
+        } = ai;
+        let data = read_input(&filepath_hint, is_real_file, &mut inp)?;
+        adapt_psd(&data, oup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16()
+            .flat_map(|u| u.to_be_bytes().to_vec())
+            .collect()
+    }
+
+    fn layer(name: &str, info: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut extra = vec![0; 8];
+        extra.push(name.len() as u8);
+        extra.extend(name.as_bytes());
+        while extra.len() % 4 != 0 {
+            extra.push(0);
+        }
+        for (key, block) in info {
+            extra.extend(b"8BIM");
+            extra.extend(*key);
+            extra.extend(&(block.len() as u32).to_be_bytes());
+            extra.extend(block);
+        }
+        let mut record = vec![0; 16];
+        record.extend(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        record.extend(b"8BIMnorm");
+        record.extend(&[255, 0, 0, 0]);
+        record.extend(&(extra.len() as u32).to_be_bytes());
+        record.extend(extra);
+        record
+    }
+
+    #[test]
+    fn layers() -> Result<()> {
+        let mut type_tool = vec![0, 1];
+        type_tool.extend(vec![0; 48]);
+        type_tool.extend(&[0, 50, 0, 0, 0, 16]);
+        type_tool.extend(b"\0\0\0\0\0\0\0\x01\0\0\0\0TxLr\0\0\0\x01\0\0\0\0Txt TEXT");
+        let text = utf16("Summer Sale\r50% off\0");
+        type_tool.extend(&((text.len() / 2) as u32).to_be_bytes());
+        type_tool.extend(text);
+        let mut unicode_name = 9u32.to_be_bytes().to_vec();
+        unicode_name.extend(utf16("Hintergrü"));
+
+        let mut layers = (-2i16).to_be_bytes().to_vec();
+        layers.extend(layer("Background", &[(b"luni", unicode_name)]));
+        layers.extend(layer("Headline", &[(b"TySh", type_tool)]));
+        let mut psd = b"8BPS\0\x01".to_vec();
+        psd.extend(vec![0; 20]);
+        psd.extend(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        psd.extend(&((layers.len() + 4) as u32).to_be_bytes());
+        psd.extend(&(layers.len() as u32).to_be_bytes());
+        psd.extend(layers);
+
+        let mut oup = Vec::new();
+        adapt_psd(&psd, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "layer Hintergrü\nHeadline: Summer Sale\nHeadline: 50% off\n"
+        );
+        Ok(())
+    }
+}
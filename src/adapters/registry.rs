@@ -0,0 +1,438 @@
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use spawning::read_input;
+use std::collections::HashSet;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["hve", "hiv"];
+
+/// hives don't have an extension, so match the well known file names
+static FILE_NAMES: &[&str] = &[
+    "[Nn][Tt][Uu][Ss][Ee][Rr].[Dd][Aa][Tt]",
+    "[Uu][Ss][Rr][Cc][Ll][Aa][Ss][Ss].[Dd][Aa][Tt]",
+    "SYSTEM",
+    "SOFTWARE",
+    "SAM",
+    "SECURITY",
+    "DEFAULT",
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "registry".to_owned(),
+        version: 1,
+        description: "Outputs all values of Windows registry hive files (NTUSER.DAT, SYSTEM, SOFTWARE, ...) as `path\\to\\key\\value = data` lines".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .chain(
+                FILE_NAMES
+                    .iter()
+                    .map(|s| FastFileMatcher::PathGlob(format!("**/{}", s)))
+            )
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct RegistryAdapter;
+
+impl RegistryAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(RegistryAdapter))
+    }
+}
+impl GetMetadata for RegistryAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// cell offsets are relative to the first hive bin, which follows the 4096 byte base block
+const HBIN_START: usize = 4096;
+/// values larger than this are split into segments ("db" cells)
+const MAX_CELL_DATA: usize = 16344;
+const MAX_BINARY_LEN: usize = 64;
+
+fn u16_at(data: &[u8], pos: usize) -> Result<u16> {
+    let b = data
+        .get(pos..pos + 2)
+        .context("unexpected end of registry cell")?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32> {
+    let b = data
+        .get(pos..pos + 4)
+        .context("unexpected end of registry cell")?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn utf16(data: &[u8]) -> String {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// names are either "compressed" (latin1) or utf-16
+fn name(data: &[u8], compressed: bool) -> String {
+    if compressed {
+        data.iter().map(|&b| b as char).collect()
+    } else {
+        utf16(data)
+    }
+}
+
+struct Hive<'a> {
+    data: &'a [u8],
+    /// keys that were already output, so corrupt hives can't make us loop
+    visited: HashSet<u32>,
+}
+
+impl<'a> Hive<'a> {
+    fn cell(&self, offset: u32) -> Result<&'a [u8]> {
+        let pos = HBIN_START + offset as usize;
+        let size = u32_at(self.data, pos)? as i32;
+        // allocated cells have a negative size, which includes the size field itself
+        let len = (size as i64).abs() as usize;
+        if len < 4 {
+            return Err(format_err!("invalid registry cell at 0x{:x}", offset));
+        }
+        self.data
+            .get(pos + 4..pos + len)
+            .with_context(|| format!("registry cell at 0x{:x} is out of bounds", offset))
+    }
+
+    /// offsets of the subkeys from an index cell (lf, lh, li or ri).
+    /// `lists` are the index cells already read, so corrupt hives can't make us loop
+    fn subkeys(&self, offset: u32, lists: &mut HashSet<u32>, out: &mut Vec<u32>) -> Result<()> {
+        if !lists.insert(offset) {
+            return Err(format_err!("subkey list loop at 0x{:x}", offset));
+        }
+        let list = self.cell(offset)?;
+        let count = u16_at(list, 2)? as usize;
+        match list.get(0..2) {
+            Some(b"lf") | Some(b"lh") => {
+                for i in 0..count {
+                    out.push(u32_at(list, 4 + i * 8)?);
+                }
+            }
+            Some(b"li") => {
+                for i in 0..count {
+                    out.push(u32_at(list, 4 + i * 4)?);
+                }
+            }
+            // an ri list only references the other lists, so it is always the first list read
+            Some(b"ri") if lists.len() == 1 => {
+                for i in 0..count {
+                    self.subkeys(u32_at(list, 4 + i * 4)?, lists, out)?;
+                }
+            }
+            _ => return Err(format_err!("invalid subkey list at 0x{:x}", offset)),
+        }
+        Ok(())
+    }
+
+    fn value_data(&self, vk: &[u8]) -> Result<Vec<u8>> {
+        let size = u32_at(vk, 4)?;
+        let offset = u32_at(vk, 8)?;
+        if size & 0x8000_0000 != 0 {
+            // small values are stored in the offset field
+            let len = (size & 0x7fff_ffff).min(4) as usize;
+            return Ok(vk[8..8 + len].to_vec());
+        }
+        let len = size as usize;
+        let cell = self.cell(offset)?;
+        if len > MAX_CELL_DATA && cell.starts_with(b"db") {
+            let segment_count = u16_at(cell, 2)? as usize;
+            let segments = self.cell(u32_at(cell, 4)?)?;
+            let mut data = Vec::with_capacity(len.min(self.data.len()));
+            for i in 0..segment_count {
+                let segment = self.cell(u32_at(segments, i * 4)?)?;
+                let remaining = len - data.len();
+                data.extend_from_slice(&segment[..segment.len().min(remaining).min(MAX_CELL_DATA)]);
+            }
+            return Ok(data);
+        }
+        Ok(cell
+            .get(..len)
+            .context("registry value data is out of bounds")?
+            .to_vec())
+    }
+
+    fn value(&self, offset: u32, key_path: &str, oup: &mut dyn Write) -> Result<()> {
+        let vk = self.cell(offset)?;
+        if !vk.starts_with(b"vk") {
+            return Err(format_err!("invalid value at 0x{:x}", offset));
+        }
+        let name_len = u16_at(vk, 2)? as usize;
+        let kind = u32_at(vk, 12)?;
+        let flags = u16_at(vk, 16)?;
+        let value_name = name(
+            vk.get(20..20 + name_len).context("invalid value name")?,
+            flags & 1 != 0,
+        );
+        let data = self.value_data(vk)?;
+        writeln!(
+            oup,
+            "{}\\{} = {}",
+            key_path,
+            if value_name.is_empty() {
+                "(default)"
+            } else {
+                value_name.as_str()
+            },
+            format_value(kind, &data)
+        )?;
+        Ok(())
+    }
+
+    fn key(&mut self, offset: u32, path: &str, depth: usize, oup: &mut dyn Write) -> Result<()> {
+        if depth > 512 || !self.visited.insert(offset) {
+            return Err(format_err!("registry key loop at 0x{:x}", offset));
+        }
+        let nk = self.cell(offset)?;
+        if !nk.starts_with(b"nk") {
+            return Err(format_err!("invalid key at 0x{:x}", offset));
+        }
+        let flags = u16_at(nk, 2)?;
+        let subkey_count = u32_at(nk, 20)?;
+        let subkey_list = u32_at(nk, 28)?;
+        let value_count = u32_at(nk, 36)? as usize;
+        let value_list = u32_at(nk, 40)?;
+        let name_len = u16_at(nk, 72)? as usize;
+        let key_name = name(
+            nk.get(76..76 + name_len).context("invalid key name")?,
+            flags & 0x20 != 0,
+        );
+        // the name of the root key is meaningless (e.g. "ROOT" or "CsiTool-CreateHive-{...}")
+        let path = if depth == 0 {
+            String::new()
+        } else if path.is_empty() {
+            key_name
+        } else {
+            format!("{}\\{}", path, key_name)
+        };
+
+        if value_count > 0 {
+            let values = self.cell(value_list)?;
+            for i in 0..value_count {
+                let value = u32_at(values, i * 4)?;
+                if let Err(e) = self.value(value, &path, oup) {
+                    debug!("skipping registry value in {}: {}", path, e);
+                }
+            }
+        } else if depth > 0 {
+            writeln!(oup, "{}", path)?;
+        }
+
+        if subkey_count > 0 {
+            let mut subkeys = vec![];
+            self.subkeys(subkey_list, &mut HashSet::new(), &mut subkeys)?;
+            for subkey in subkeys {
+                if let Err(e) = self.key(subkey, &path, depth + 1, oup) {
+                    debug!("skipping registry key in {}: {}", path, e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn format_value(kind: u32, data: &[u8]) -> String {
+    match kind {
+        // REG_SZ, REG_EXPAND_SZ, REG_LINK
+        1 | 2 | 6 => utf16(data).trim_end_matches('\0').to_string(),
+        // REG_MULTI_SZ
+        7 => utf16(data)
+            .split('\0')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(", "),
+        // REG_DWORD
+        4 if data.len() >= 4 => {
+            let v = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            format!("0x{:08x} ({})", v, v)
+        }
+        // REG_DWORD_BIG_ENDIAN
+        5 if data.len() >= 4 => {
+            let v = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+            format!("0x{:08x} ({})", v, v)
+        }
+        // REG_QWORD
+        11 if data.len() >= 8 => {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&data[..8]);
+            let v = u64::from_le_bytes(b);
+            format!("0x{:016x} ({})", v, v)
+        }
+        _ => {
+            let hex: Vec<String> = data
+                .iter()
+                .take(MAX_BINARY_LEN)
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            if data.len() > MAX_BINARY_LEN {
+                format!("{} ... ({} bytes)", hex.join(" "), data.len())
+            } else {
+                hex.join(" ")
+            }
+        }
+    }
+}
+
+fn adapt_hive(data: &[u8], oup: &mut dyn Write) -> Result<()> {
+    if !data.starts_with(b"regf") {
+        // matched by file name only, so this might be anything
+        debug!("not a registry hive, passing through");
+        oup.write_all(data)?;
+        return Ok(());
+    }
+    let root = u32_at(data, 0x24)?;
+    let mut hive = Hive {
+        data,
+        visited: HashSet::new(),
+    };
+    hive.key(root, "", 0, oup)
+}
+
+impl WritingFileAdapterTrait for RegistryAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            filepath_hint,
+            is_real_file,
+            ..
+        } = ai;
+        let data = read_input(&filepath_hint, is_real_file, &mut inp)?;
+        adapt_hive(&data, oup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// appends an allocated cell to the hive and returns its offset
+    fn cell(hive: &mut Vec<u8>, content: &[u8]) -> u32 {
+        let offset = (hive.len() - HBIN_START) as u32;
+        let len = (content.len() + 4 + 7) / 8 * 8;
+        hive.extend_from_slice(&(-(len as i32)).to_le_bytes());
+        hive.extend_from_slice(content);
+        hive.resize(HBIN_START + offset as usize + len, 0);
+        offset
+    }
+
+    fn nk(
+        name: &str,
+        subkeys: &[u32],
+        subkey_list: u32,
+        values: usize,
+        value_list: u32,
+    ) -> Vec<u8> {
+        let mut nk = vec![0u8; 76];
+        nk[0..2].copy_from_slice(b"nk");
+        nk[2..4].copy_from_slice(&0x20u16.to_le_bytes());
+        nk[20..24].copy_from_slice(&(subkeys.len() as u32).to_le_bytes());
+        nk[28..32].copy_from_slice(&subkey_list.to_le_bytes());
+        nk[36..40].copy_from_slice(&(values as u32).to_le_bytes());
+        nk[40..44].copy_from_slice(&value_list.to_le_bytes());
+        nk[72..74].copy_from_slice(&(name.len() as u16).to_le_bytes());
+        nk.extend_from_slice(name.as_bytes());
+        nk
+    }
+
+    fn vk(name: &str, kind: u32, size: u32, data_offset: u32) -> Vec<u8> {
+        let mut vk = vec![0u8; 20];
+        vk[0..2].copy_from_slice(b"vk");
+        vk[2..4].copy_from_slice(&(name.len() as u16).to_le_bytes());
+        vk[4..8].copy_from_slice(&size.to_le_bytes());
+        vk[8..12].copy_from_slice(&data_offset.to_le_bytes());
+        vk[12..16].copy_from_slice(&kind.to_le_bytes());
+        vk[16..18].copy_from_slice(&1u16.to_le_bytes());
+        vk.extend_from_slice(name.as_bytes());
+        vk
+    }
+
+    #[test]
+    fn values() -> Result<()> {
+        let mut hive = b"regf".to_vec();
+        hive.resize(HBIN_START, 0);
+        hive.extend_from_slice(b"hbin");
+        hive.resize(HBIN_START + 32, 0);
+
+        let path: Vec<u8> = "C:\\Program Files\\rga\0"
+            .encode_utf16()
+            .flat_map(|c| c.to_le_bytes().to_vec())
+            .collect();
+        let path_data = cell(&mut hive, &path);
+        let install_dir = cell(
+            &mut hive,
+            &vk("InstallDir", 1, path.len() as u32, path_data),
+        );
+        let version = cell(&mut hive, &vk("Version", 4, 0x8000_0004, 42));
+        let values = cell(
+            &mut hive,
+            &[install_dir.to_le_bytes(), version.to_le_bytes()].concat(),
+        );
+        let rga = cell(&mut hive, &nk("rga", &[], 0, 2, values));
+        let empty = cell(&mut hive, &nk("Empty", &[], 0, 0, 0));
+        let mut list = b"lf".to_vec();
+        list.extend_from_slice(&2u16.to_le_bytes());
+        for key in &[rga, empty] {
+            list.extend_from_slice(&key.to_le_bytes());
+            list.extend_from_slice(&[0; 4]);
+        }
+        let list = cell(&mut hive, &list);
+        let software = cell(&mut hive, &nk("Software", &[rga, empty], list, 0, 0));
+        let mut root_list = b"li".to_vec();
+        root_list.extend_from_slice(&1u16.to_le_bytes());
+        root_list.extend_from_slice(&software.to_le_bytes());
+        let root_list = cell(&mut hive, &root_list);
+        let root = cell(&mut hive, &nk("ROOT", &[software], root_list, 0, 0));
+        hive[0x24..0x28].copy_from_slice(&root.to_le_bytes());
+
+        let mut oup = Vec::new();
+        adapt_hive(&hive, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "Software\n\
+             Software\\rga\\InstallDir = C:\\Program Files\\rga\n\
+             Software\\rga\\Version = 0x0000002a (42)\n\
+             Software\\Empty\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn subkey_list_loop() -> Result<()> {
+        let mut hive = b"regf".to_vec();
+        hive.resize(HBIN_START, 0);
+        hive.extend_from_slice(b"hbin");
+        hive.resize(HBIN_START + 32, 0);
+
+        // an ri list that references itself
+        let mut list = b"ri".to_vec();
+        list.extend_from_slice(&1u16.to_le_bytes());
+        list.extend_from_slice(&((hive.len() - HBIN_START) as u32).to_le_bytes());
+        let list = cell(&mut hive, &list);
+        let root = cell(&mut hive, &nk("ROOT", &[list], list, 0, 0));
+        hive[0x24..0x28].copy_from_slice(&root.to_le_bytes());
+
+        assert!(adapt_hive(&hive, &mut Vec::new()).is_err());
+        Ok(())
+    }
+}
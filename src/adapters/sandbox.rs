@@ -0,0 +1,148 @@
+//! --rga-sandbox: runs the programs of adapters in a bubblewrap (bwrap) sandbox, so parsing untrusted documents
+//! with large C code bases (poppler, ffmpeg, ...) can't read the home directory of the user or use the network.
+//! The sandbox only contains the system directories (read-only), the input file and the temporary directory of the program.
+
+use super::spawning::CommandPaths;
+use crate::config::RgaConfig;
+use anyhow::*;
+use std::path::Path;
+use std::process::Command;
+
+/// system directories that are mounted read-only in the sandbox if they exist, for the programs and their libraries
+#[cfg(target_os = "linux")]
+const SYSTEM_DIRS: &[&str] = &[
+    "/usr",
+    "/bin",
+    "/sbin",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/etc",
+    "/opt",
+    "/nix/store",
+];
+
+/// Wrap the command of an external program in the sandbox if --rga-sandbox is given,
+/// otherwise return it unchanged. The program can only read the paths of the command (and --rga-sandbox-path)
+#[cfg(target_os = "linux")]
+pub fn sandboxed(cmd: Command, paths: &CommandPaths, config: &RgaConfig) -> Result<Command> {
+    if !config.sandbox {
+        return Ok(cmd);
+    }
+    let bwrap = crate::doctor::find_program("bwrap")
+        .context("--rga-sandbox needs bubblewrap (bwrap), install it or run without the sandbox")?;
+    let mut sandbox = Command::new(bwrap);
+    sandbox.args(&[
+        "--unshare-all",
+        "--die-with-parent",
+        // so the program can't inject input into the terminal (TIOCSTI)
+        "--new-session",
+        "--proc",
+        "/proc",
+        "--dev",
+        "/dev",
+        "--tmpfs",
+        "/tmp",
+    ]);
+    for dir in SYSTEM_DIRS {
+        sandbox.arg("--ro-bind-try").arg(dir).arg(dir);
+    }
+    // programs given by path (e.g. custom adapters in the home directory) need to be readable
+    let program = Path::new(cmd.get_program());
+    if program.is_absolute() {
+        bind(&mut sandbox, "--ro-bind", program)?;
+    }
+    for path in &config.sandbox_path {
+        bind(&mut sandbox, "--ro-bind", Path::new(path))?;
+    }
+    if let Some(input_path) = paths.input_path {
+        bind(&mut sandbox, "--ro-bind", input_path)?;
+    }
+    if let Some(tmpdir) = paths.tmpdir {
+        bind(&mut sandbox, "--bind", tmpdir)?;
+    }
+    if let Some(dir) = cmd.get_current_dir() {
+        sandbox.arg("--chdir").arg(dir);
+    }
+    for (key, value) in cmd.get_envs() {
+        match value {
+            Some(value) => sandbox.env(key, value),
+            None => sandbox.env_remove(key),
+        };
+    }
+    sandbox
+        .arg("--")
+        .arg(cmd.get_program())
+        .args(cmd.get_args());
+    Ok(sandbox)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sandboxed(cmd: Command, _paths: &CommandPaths, config: &RgaConfig) -> Result<Command> {
+    if config.sandbox {
+        // don't run the program unprotected when the user asked for isolation
+        bail!("--rga-sandbox is only supported on Linux");
+    }
+    Ok(cmd)
+}
+
+/// mount the path at the same location in the sandbox
+#[cfg(target_os = "linux")]
+fn bind(sandbox: &mut Command, how: &str, path: &Path) -> Result<()> {
+    // bwrap resolves the paths relative to its own working directory, which is kept
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("could not add {} to the sandbox", path.display()))?;
+    sandbox.arg(how).arg(&path).arg(&path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::OsStr;
+
+    fn args(cmd: &Command) -> Vec<&OsStr> {
+        cmd.get_args().collect()
+    }
+
+    #[test]
+    fn unchanged_without_sandbox() -> Result<()> {
+        let mut cmd = Command::new("pdftotext");
+        cmd.arg("-");
+        let paths = CommandPaths {
+            filepath_hint: Path::new("a.pdf"),
+            input_path: None,
+            tmpdir: None,
+        };
+        let cmd = sandboxed(cmd, &paths, &RgaConfig::default())?;
+        assert_eq!(cmd.get_program(), "pdftotext");
+        assert_eq!(args(&cmd), vec!["-"]);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn wraps_command() -> Result<()> {
+        if crate::doctor::find_program("bwrap").is_none() {
+            return Ok(());
+        }
+        let input = tempfile::NamedTempFile::new()?;
+        let mut cmd = Command::new("pdftotext");
+        cmd.arg("-").env("LANG", "C");
+        let paths = CommandPaths {
+            filepath_hint: Path::new("a.pdf"),
+            input_path: Some(input.path()),
+            tmpdir: None,
+        };
+        let mut config = RgaConfig::default();
+        config.sandbox = true;
+        let cmd = sandboxed(cmd, &paths, &config)?;
+        let args = args(&cmd);
+        assert!(args.contains(&OsStr::new("--unshare-all")));
+        assert!(args.contains(&input.path().canonicalize()?.as_os_str()));
+        assert_eq!(&args[args.len() - 3..], &["--", "pdftotext", "-"]);
+        assert!(cmd.get_envs().any(|(k, _)| k == "LANG"));
+        Ok(())
+    }
+}
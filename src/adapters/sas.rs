@@ -0,0 +1,743 @@
+//! SAS data sets (.sas7bdat). The format is not documented, this follows the reverse engineered
+//! description of the sas7bdat R package (https://github.com/BioStatMatt/sas7bdat) and the reader of pandas.
+//! Value labels (formats) are stored in separate catalog files, so only the variable labels are output
+
+use super::*;
+use anyhow::*;
+use chrono::{Duration, NaiveDate};
+use encoding_rs::WINDOWS_1252;
+use lazy_static::lazy_static;
+use spawning::read_input;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["sas7bdat"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "sas".to_owned(),
+        version: 1,
+        description: "Outputs the variable labels and observations of SAS data sets (.sas7bdat) as `name=value` pairs".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-sas-data".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct SasAdapter;
+
+impl SasAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(SasAdapter))
+    }
+}
+impl GetMetadata for SasAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+static MAGIC: &[u8] = b"\0\0\0\0\0\0\0\0\0\0\0\0\xc2\xea\x81\x60\xb3\x14\x11\xcf\xbd\x92\x08\x00\x09\xc7\x31\x8c\x18\x1f\x10\x11";
+
+// subheader signatures
+const ROW_SIZE: u32 = 0xF7F7_F7F7;
+const COLUMN_SIZE: u32 = 0xF6F6_F6F6;
+const COLUMN_TEXT: u32 = 0xFFFF_FFFD;
+const COLUMN_NAME: u32 = 0xFFFF_FFFF;
+const COLUMN_ATTRIBUTES: u32 = 0xFFFF_FFFC;
+const FORMAT_AND_LABEL: u32 = 0xFFFF_FBFE;
+
+// page types
+const PAGE_META: u16 = 0;
+const PAGE_META2: u16 = 0x4000;
+const PAGE_DATA: u16 = 0x100;
+const PAGE_MIX: u16 = 0x200;
+const PAGE_AMD: u16 = 0x400;
+
+#[derive(PartialEq)]
+enum Compression {
+    None,
+    /// SASYZCRL
+    Rle,
+    /// SASYZCR2
+    Rdc,
+}
+
+#[derive(Default)]
+struct Column {
+    name: String,
+    label: String,
+    format: String,
+    offset: usize,
+    len: usize,
+    numeric: bool,
+}
+
+struct Sas7bdat<'d> {
+    data: &'d [u8],
+    little: bool,
+    /// 64 bit files have 8 byte integers (and pointers)
+    u64: bool,
+    compression: Compression,
+    page_length: usize,
+    row_length: usize,
+    row_count: usize,
+    mix_page_row_count: usize,
+    column_count: usize,
+    text_blocks: Vec<&'d [u8]>,
+    columns: Vec<Column>,
+    /// the number of columns described by the column name, attribute and format subheaders so far,
+    /// large data sets split them into multiple subheaders
+    names_seen: usize,
+    attributes_seen: usize,
+    formats_seen: usize,
+    /// the rows stored in subheaders of compressed data sets
+    subheader_rows: Vec<Vec<u8>>,
+}
+
+impl<'d> Sas7bdat<'d> {
+    fn int_len(&self) -> usize {
+        if self.u64 {
+            8
+        } else {
+            4
+        }
+    }
+    fn uint(&self, offset: usize, len: usize) -> Result<usize> {
+        let bytes = self
+            .data
+            .get(offset..offset.saturating_add(len))
+            .ok_or_else(|| format_err!("unexpected end of file at {}", offset))?;
+        let shift = |value: u64, b: &u8| value << 8 | *b as u64;
+        let value = if self.little {
+            bytes.iter().rev().fold(0, shift)
+        } else {
+            bytes.iter().fold(0, shift)
+        };
+        Ok(value as usize)
+    }
+    fn int(&self, offset: usize) -> Result<usize> {
+        self.uint(offset, self.int_len())
+    }
+    fn u16(&self, offset: usize) -> Result<usize> {
+        self.uint(offset, 2)
+    }
+    /// a slice of one of the column text blocks
+    fn text(&self, block: usize, offset: usize, len: usize) -> String {
+        let bytes = self
+            .text_blocks
+            .get(block)
+            .and_then(|b| b.get(offset..offset + len))
+            .unwrap_or_default();
+        decode(bytes)
+    }
+
+    /// returns false if the subheader is not one of the metadata subheaders
+    fn subheader(&mut self, offset: usize, len: usize) -> Result<bool> {
+        let data = self.data;
+        let int_len = self.int_len();
+        let signature_offset = if self.little {
+            offset
+        } else {
+            offset + int_len - 4
+        };
+        match self.uint(signature_offset, 4)? as u32 {
+            ROW_SIZE => {
+                self.row_length = self.int(offset + 5 * int_len)?;
+                // a row always fits into a page
+                if self.row_length > self.page_length {
+                    return Err(format_err!("invalid row length {}", self.row_length));
+                }
+                self.row_count = self.int(offset + 6 * int_len)?;
+                self.mix_page_row_count = self.int(offset + 15 * int_len)?;
+            }
+            COLUMN_SIZE => self.column_count = self.int(offset + int_len)?,
+            COLUMN_TEXT => {
+                let size = self.u16(offset + int_len)?;
+                let block = data
+                    .get(offset + int_len..offset + int_len + size)
+                    .ok_or_else(|| format_err!("column text out of bounds"))?;
+                if self.text_blocks.is_empty() {
+                    let contains = |literal: &[u8]| block.windows(8).any(|w| w == literal);
+                    if contains(b"SASYZCRL") {
+                        self.compression = Compression::Rle;
+                    } else if contains(b"SASYZCR2") {
+                        self.compression = Compression::Rdc;
+                    }
+                }
+                self.text_blocks.push(block);
+            }
+            COLUMN_NAME => {
+                let count = len.saturating_sub(2 * int_len + 12) / 8;
+                for i in 0..count {
+                    let base = offset + int_len + 8 * (i + 1);
+                    let name = self.text(self.u16(base)?, self.u16(base + 2)?, self.u16(base + 4)?);
+                    self.column(self.names_seen, |c| c.name = name);
+                    self.names_seen += 1;
+                }
+            }
+            COLUMN_ATTRIBUTES => {
+                let count = len.saturating_sub(2 * int_len + 12) / (int_len + 8);
+                for i in 0..count {
+                    let base = offset + i * (int_len + 8);
+                    let data_offset = self.int(base + int_len + 8)?;
+                    let data_len = self.uint(base + 2 * int_len + 8, 4)?;
+                    let numeric = self.uint(base + 2 * int_len + 14, 1)? == 1;
+                    self.column(self.attributes_seen, |c| {
+                        c.offset = data_offset;
+                        c.len = data_len;
+                        c.numeric = numeric;
+                    });
+                    self.attributes_seen += 1;
+                }
+            }
+            FORMAT_AND_LABEL => {
+                let base = offset + 3 * int_len;
+                let format = self.text(
+                    self.u16(base + 22)?,
+                    self.u16(base + 24)?,
+                    self.u16(base + 26)?,
+                );
+                let label = self.text(
+                    self.u16(base + 28)?,
+                    self.u16(base + 30)?,
+                    self.u16(base + 32)?,
+                );
+                // one subheader per column, in column order
+                self.column(self.formats_seen, |c| {
+                    c.format = format;
+                    c.label = label;
+                });
+                self.formats_seen += 1;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn column(&mut self, i: usize, f: impl FnOnce(&mut Column)) {
+        if self.columns.len() <= i {
+            self.columns.resize_with(i + 1, Column::default);
+        }
+        f(&mut self.columns[i]);
+    }
+
+    /// the subheader pointers of a meta or mix page
+    fn subheaders(&mut self, page: usize, count: usize, bit_offset: usize) -> Result<()> {
+        let data = self.data;
+        let int_len = self.int_len();
+        let pointer_len = if self.u64 { 24 } else { 12 };
+        for i in 0..count {
+            let pointer = page + bit_offset + 8 + i * pointer_len;
+            let offset = self.int(pointer)?;
+            let len = self.int(pointer + int_len)?;
+            let compression = self.uint(pointer + 2 * int_len, 1)?;
+            let subheader_type = self.uint(pointer + 2 * int_len + 1, 1)?;
+            // empty or truncated
+            if len == 0 || compression == 1 {
+                continue;
+            }
+            let start = page
+                .checked_add(offset)
+                .filter(|start| *start < data.len())
+                .ok_or_else(|| format_err!("subheader out of bounds"))?;
+            if self.subheader(start, len)? {
+                continue;
+            }
+            if subheader_type == 1 && (compression == 4 || compression == 0) {
+                // a row, compressed if shorter than a row
+                let raw = start
+                    .checked_add(len)
+                    .and_then(|end| data.get(start..end))
+                    .ok_or_else(|| format_err!("row out of bounds"))?;
+                self.subheader_rows.push(if len < self.row_length {
+                    match self.compression {
+                        Compression::Rle => rle_decompress(raw, self.row_length)?,
+                        Compression::Rdc => rdc_decompress(raw, self.row_length)?,
+                        Compression::None => raw.to_vec(),
+                    }
+                } else {
+                    raw.to_vec()
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn decode(bytes: &[u8]) -> String {
+    let s = match std::str::from_utf8(bytes) {
+        Ok(s) => s.into(),
+        Err(_) => WINDOWS_1252.decode_without_bom_handling(bytes).0,
+    };
+    s.trim_end_matches(|c| c == ' ' || c == '\0').to_string()
+}
+
+fn rle_decompress(inp: &[u8], len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let mut pos = 0;
+    let byte = |pos: usize| {
+        inp.get(pos)
+            .copied()
+            .ok_or_else(|| format_err!("truncated rle data"))
+    };
+    let literal = |out: &mut Vec<u8>, pos: usize, n: usize| -> Result<()> {
+        out.extend(
+            inp.get(pos..pos + n)
+                .ok_or_else(|| format_err!("truncated rle data"))?,
+        );
+        Ok(())
+    };
+    while pos < inp.len() {
+        let control = inp[pos] & 0xF0;
+        let low = (inp[pos] & 0x0F) as usize;
+        pos += 1;
+        match control {
+            0x00 => {
+                let n = byte(pos)? as usize + 64 + low * 256;
+                literal(&mut out, pos + 1, n)?;
+                pos += 1 + n;
+            }
+            0x40 => {
+                let n = byte(pos)? as usize + 18 + low * 256;
+                out.extend(std::iter::repeat(byte(pos + 1)?).take(n));
+                pos += 2;
+            }
+            0x60 => {
+                let n = byte(pos)? as usize + 17 + low * 256;
+                out.extend(std::iter::repeat(b' ').take(n));
+                pos += 1;
+            }
+            0x70 => {
+                let n = byte(pos)? as usize + 17 + low * 256;
+                out.extend(std::iter::repeat(0).take(n));
+                pos += 1;
+            }
+            0x80 | 0x90 | 0xA0 | 0xB0 => {
+                let n = low + 1 + (control as usize - 0x80) / 16 * 16;
+                literal(&mut out, pos, n)?;
+                pos += n;
+            }
+            0xC0 => {
+                out.extend(std::iter::repeat(byte(pos)?).take(low + 3));
+                pos += 1;
+            }
+            0xD0 => out.extend(std::iter::repeat(b'@').take(low + 2)),
+            0xE0 => out.extend(std::iter::repeat(b' ').take(low + 2)),
+            0xF0 => out.extend(std::iter::repeat(0).take(low + 2)),
+            _ => return Err(format_err!("unknown rle control byte {}", control)),
+        }
+    }
+    out.resize(len, 0);
+    Ok(out)
+}
+
+fn rdc_decompress(inp: &[u8], len: usize) -> Result<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::with_capacity(len);
+    let mut pos = 0;
+    let mut control_bits = 0u16;
+    let mut control_mask = 0u16;
+    let byte = |pos: usize| {
+        inp.get(pos)
+            .map(|b| *b as usize)
+            .ok_or_else(|| format_err!("truncated rdc data"))
+    };
+    let copy = |out: &mut Vec<u8>, offset: usize, n: usize| -> Result<()> {
+        let start = out
+            .len()
+            .checked_sub(offset)
+            .ok_or_else(|| format_err!("invalid rdc back reference"))?;
+        for i in 0..n {
+            out.push(out[start + i]);
+        }
+        Ok(())
+    };
+    while pos < inp.len() {
+        control_mask >>= 1;
+        if control_mask == 0 {
+            control_bits = (byte(pos)? << 8 | byte(pos + 1)?) as u16;
+            pos += 2;
+            control_mask = 0x8000;
+        }
+        if control_bits & control_mask == 0 {
+            out.push(byte(pos)? as u8);
+            pos += 1;
+            continue;
+        }
+        let command = byte(pos)? >> 4;
+        let count = byte(pos)? & 0x0F;
+        pos += 1;
+        match command {
+            // short run
+            0 => {
+                out.extend(std::iter::repeat(byte(pos)? as u8).take(count + 3));
+                pos += 1;
+            }
+            // long run
+            1 => {
+                let count = count + (byte(pos)? << 4) + 19;
+                out.extend(std::iter::repeat(byte(pos + 1)? as u8).take(count));
+                pos += 2;
+            }
+            // long pattern
+            2 => {
+                let offset = count + 3 + (byte(pos)? << 4);
+                let count = byte(pos + 1)? + 16;
+                copy(&mut out, offset, count)?;
+                pos += 2;
+            }
+            // short pattern, the command is the length
+            _ => {
+                let offset = count + 3 + (byte(pos)? << 4);
+                copy(&mut out, offset, command)?;
+                pos += 1;
+            }
+        }
+    }
+    out.resize(len, 0);
+    Ok(out)
+}
+
+/// dates are days, datetimes seconds since 1960-01-01
+fn format_number(value: f64, format: &str) -> String {
+    let epoch = NaiveDate::from_ymd(1960, 1, 1).and_hms(0, 0, 0);
+    let format = format.to_ascii_uppercase();
+    let date = if value.abs() >= 1e13 {
+        None
+    } else if format.contains("DATETIME") || format.starts_with("E8601DT") {
+        epoch
+            .checked_add_signed(Duration::seconds(value as i64))
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+    } else if [
+        "DATE", "MMDDYY", "DDMMYY", "YYMMDD", "E8601DA", "WEEKDATE", "WORDDATE",
+    ]
+    .iter()
+    .any(|f| format.starts_with(f))
+    {
+        epoch
+            .checked_add_signed(Duration::days(value as i64))
+            .map(|t| t.format("%Y-%m-%d").to_string())
+    } else {
+        None
+    };
+    date.unwrap_or_else(|| value.to_string())
+}
+
+fn write_row(dataset: &Sas7bdat, row: &[u8], oup: &mut dyn Write) -> Result<()> {
+    let mut first = true;
+    for c in &dataset.columns {
+        let raw = c
+            .offset
+            .checked_add(c.len)
+            .and_then(|end| row.get(c.offset..end))
+            .ok_or_else(|| format_err!("column {} out of bounds", c.name))?;
+        let value = if c.numeric {
+            // truncated numbers only store the most significant bytes
+            let mut bytes = [0u8; 8];
+            let len = raw.len().min(8);
+            let value = if dataset.little {
+                bytes[8 - len..].copy_from_slice(&raw[..len]);
+                f64::from_le_bytes(bytes)
+            } else {
+                bytes[..len].copy_from_slice(&raw[..len]);
+                f64::from_be_bytes(bytes)
+            };
+            // all missing values (., .A - .Z, ._) are NaNs
+            if value.is_nan() {
+                "".to_string()
+            } else {
+                format_number(value, &c.format)
+            }
+        } else {
+            decode(raw)
+        };
+        if !first {
+            write!(oup, ", ")?;
+        }
+        first = false;
+        write!(oup, "{}={}", c.name, value)?;
+    }
+    writeln!(oup)?;
+    Ok(())
+}
+
+/// a later row size subheader may lower the row count below the rows already written
+fn remaining_rows(dataset: &Sas7bdat, rows_written: usize) -> Result<usize> {
+    dataset.row_count.checked_sub(rows_written).ok_or_else(|| {
+        format_err!(
+            "row count {} is lower than the rows read",
+            dataset.row_count
+        )
+    })
+}
+
+fn adapt_sas7bdat(data: &[u8], oup: &mut dyn Write) -> Result<()> {
+    if !data.starts_with(MAGIC) || data.len() < 288 {
+        return Err(format_err!("not a sas7bdat file"));
+    }
+    let mut dataset = Sas7bdat {
+        data,
+        little: data[37] == 1,
+        u64: data[32] == b'3',
+        compression: Compression::None,
+        page_length: 0,
+        row_length: 0,
+        row_count: 0,
+        mix_page_row_count: 0,
+        column_count: 0,
+        text_blocks: vec![],
+        columns: vec![],
+        names_seen: 0,
+        attributes_seen: 0,
+        formats_seen: 0,
+        subheader_rows: vec![],
+    };
+    let align = if data[35] == b'3' { 4 } else { 0 };
+    let name = decode(&data[92..156]);
+    let header_length = dataset.uint(196 + align, 4)?;
+    let page_length = dataset.uint(200 + align, 4)?;
+    let page_count = dataset.int(204 + align)?;
+    let bit_offset = if dataset.u64 { 32 } else { 16 };
+    if page_length == 0 {
+        return Err(format_err!("invalid page length"));
+    }
+    dataset.page_length = page_length;
+
+    if !name.is_empty() {
+        writeln!(oup, "dataset: {}", name)?;
+    }
+    let mut rows_written = 0;
+    let mut variables_written = false;
+    for page in 0..page_count {
+        let page = page
+            .checked_mul(page_length)
+            .and_then(|p| p.checked_add(header_length));
+        let page = match page {
+            Some(page) if page < data.len() && data.len() - page >= page_length => page,
+            _ => break,
+        };
+        let page_type = dataset.u16(page + bit_offset)? as u16 & 0xFF00;
+        let block_count = dataset.u16(page + bit_offset + 2)?;
+        let subheader_count = dataset.u16(page + bit_offset + 4)?;
+        if [PAGE_META, PAGE_META2, PAGE_MIX, PAGE_AMD].contains(&page_type) {
+            dataset.subheaders(page, subheader_count, bit_offset)?;
+        }
+        // the metadata is complete before the first rows
+        if !variables_written
+            && dataset.row_length > 0
+            && dataset.columns.len() >= dataset.column_count
+        {
+            variables_written = true;
+            for c in &dataset.columns {
+                if c.label.is_empty() {
+                    writeln!(oup, "variable {}", c.name)?;
+                } else {
+                    writeln!(oup, "variable {}: {}", c.name, c.label)?;
+                }
+            }
+        }
+        let remaining = remaining_rows(&dataset, rows_written)?;
+        let (start, count) = match page_type {
+            PAGE_DATA => (page + bit_offset + 8, block_count),
+            PAGE_MIX => {
+                let start = bit_offset + 8 + subheader_count * if dataset.u64 { 24 } else { 12 };
+                (
+                    page + start + start % 8,
+                    dataset.row_count.min(dataset.mix_page_row_count),
+                )
+            }
+            _ => (0, 0),
+        };
+        let subheader_rows = std::mem::take(&mut dataset.subheader_rows);
+        for row in subheader_rows.iter().take(remaining) {
+            write_row(&dataset, row, oup)?;
+            rows_written += 1;
+        }
+        let remaining = remaining_rows(&dataset, rows_written)?;
+        for i in 0..count.min(remaining) {
+            let row = i
+                .checked_mul(dataset.row_length)
+                .and_then(|offset| start.checked_add(offset))
+                .and_then(|row_start| {
+                    data.get(row_start..row_start.checked_add(dataset.row_length)?)
+                })
+                .ok_or_else(|| format_err!("row out of bounds"))?;
+            write_row(&dataset, row, oup)?;
+            rows_written += 1;
+        }
+    }
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for SasAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            filepath_hint,
+            is_real_file,
+            ..
+        } = ai;
+        let data = read_input(&filepath_hint, is_real_file, &mut inp)?;
+        adapt_sas7bdat(&data, oup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn put(buf: &mut [u8], offset: usize, bytes: &[u8]) {
+        buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn subheader(page: &mut Vec<u8>, index: usize, offset: usize, contents: &[u8]) {
+        put(page, offset, contents);
+        let pointer = 24 + index * 12;
+        put(page, pointer, &(offset as u32).to_le_bytes());
+        put(page, pointer + 4, &(contents.len() as u32).to_le_bytes());
+    }
+
+    #[test]
+    fn uncompressed() -> Result<()> {
+        let mut header = vec![0u8; 1024];
+        put(&mut header, 0, MAGIC);
+        put(&mut header, 32, b"2");
+        put(&mut header, 35, b"2");
+        put(&mut header, 37, &[1]);
+        put(&mut header, 92, b"SURVEY");
+        put(&mut header, 196, &1024u32.to_le_bytes());
+        put(&mut header, 200, &1024u32.to_le_bytes());
+        put(&mut header, 204, &1u32.to_le_bytes());
+
+        let mut page = vec![0u8; 1024];
+        put(&mut page, 16, &0x200u16.to_le_bytes());
+        put(&mut page, 20, &9u16.to_le_bytes());
+        // id (8 bytes), sex (3 bytes), city (8 bytes), visit (8 bytes)
+        let mut row_size = vec![0u8; 64];
+        put(&mut row_size, 0, &ROW_SIZE.to_le_bytes());
+        put(&mut row_size, 20, &27u32.to_le_bytes());
+        put(&mut row_size, 24, &2u32.to_le_bytes());
+        put(&mut row_size, 60, &2u32.to_le_bytes());
+        subheader(&mut page, 0, 300, &row_size);
+        let mut column_size = vec![0u8; 12];
+        put(&mut column_size, 0, &COLUMN_SIZE.to_le_bytes());
+        put(&mut column_size, 4, &4u32.to_le_bytes());
+        subheader(&mut page, 1, 364, &column_size);
+        let text = b"\0\0\0\0idsexcityvisitRespondent sexCity of residenceDATE";
+        let mut column_text = COLUMN_TEXT.to_le_bytes().to_vec();
+        column_text.extend(text);
+        put(&mut column_text, 4, &(text.len() as u16).to_le_bytes());
+        subheader(&mut page, 2, 376, &column_text);
+        let mut column_name = vec![0u8; 8 + 12 + 4 * 8];
+        put(&mut column_name, 0, &COLUMN_NAME.to_le_bytes());
+        for (i, (offset, len)) in [(4, 2), (6, 3), (9, 4), (13, 5)].iter().enumerate() {
+            put(
+                &mut column_name,
+                12 + 8 * i + 2,
+                &(*offset as u16).to_le_bytes(),
+            );
+            put(
+                &mut column_name,
+                12 + 8 * i + 4,
+                &(*len as u16).to_le_bytes(),
+            );
+        }
+        subheader(&mut page, 3, 440, &column_name);
+        let mut attributes = vec![0u8; 8 + 12 + 4 * 12];
+        put(&mut attributes, 0, &COLUMN_ATTRIBUTES.to_le_bytes());
+        for (i, (offset, len, numeric)) in [(0, 8, 1), (8, 3, 1), (11, 8, 2), (19, 8, 1)]
+            .iter()
+            .enumerate()
+        {
+            put(
+                &mut attributes,
+                12 + 12 * i,
+                &(*offset as u32).to_le_bytes(),
+            );
+            put(&mut attributes, 16 + 12 * i, &(*len as u32).to_le_bytes());
+            put(&mut attributes, 22 + 12 * i, &[*numeric]);
+        }
+        subheader(&mut page, 4, 500, &attributes);
+        // format and label of each column
+        for (i, (format, label)) in [
+            ((0, 0), (0, 0)),
+            ((0, 0), (18, 14)),
+            ((0, 0), (32, 17)),
+            ((49, 4), (0, 0)),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let mut format_and_label = vec![0u8; 52];
+            put(&mut format_and_label, 0, &FORMAT_AND_LABEL.to_le_bytes());
+            put(&mut format_and_label, 36, &(format.0 as u16).to_le_bytes());
+            put(&mut format_and_label, 38, &(format.1 as u16).to_le_bytes());
+            put(&mut format_and_label, 42, &(label.0 as u16).to_le_bytes());
+            put(&mut format_and_label, 44, &(label.1 as u16).to_le_bytes());
+            subheader(&mut page, 5 + i, 580 + 52 * i, &format_and_label);
+        }
+        // the rows follow the 9 subheader pointers, aligned to 8 bytes
+        let mut rows = vec![];
+        rows.extend(&1f64.to_le_bytes());
+        rows.extend(&2f64.to_le_bytes()[5..]);
+        rows.extend(b"Vilnius ");
+        rows.extend(&22204f64.to_le_bytes());
+        rows.extend(&2f64.to_le_bytes());
+        rows.extend(&[0xFE, 0xFF, 0xFF]);
+        rows.extend(b"Kaunas  ");
+        rows.extend(&[0, 0, 0, 0, 0, 0xFE, 0xFF, 0xFF]);
+        put(&mut page, 136, &rows);
+
+        let mut sas = header;
+        sas.extend(page);
+        let mut oup = Vec::new();
+        adapt_sas7bdat(&sas, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "dataset: SURVEY\n\
+             variable id\n\
+             variable sex: Respondent sex\n\
+             variable city: City of residence\n\
+             variable visit\n\
+             id=1, sex=2, city=Vilnius, visit=2020-10-16\n\
+             id=2, sex=, city=Kaunas, visit=\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn row_length_larger_than_page() {
+        let mut header = vec![0u8; 1024];
+        put(&mut header, 0, MAGIC);
+        put(&mut header, 32, b"2");
+        put(&mut header, 35, b"2");
+        put(&mut header, 37, &[1]);
+        put(&mut header, 196, &1024u32.to_le_bytes());
+        put(&mut header, 200, &1024u32.to_le_bytes());
+        put(&mut header, 204, &1u32.to_le_bytes());
+
+        let mut page = vec![0u8; 1024];
+        put(&mut page, 16, &0x200u16.to_le_bytes());
+        put(&mut page, 20, &1u16.to_le_bytes());
+        let mut row_size = vec![0u8; 64];
+        put(&mut row_size, 0, &ROW_SIZE.to_le_bytes());
+        put(&mut row_size, 20, &u32::MAX.to_le_bytes());
+        subheader(&mut page, 0, 300, &row_size);
+
+        let mut sas = header;
+        sas.extend(page);
+        assert!(adapt_sas7bdat(&sas, &mut Vec::new()).is_err());
+    }
+}
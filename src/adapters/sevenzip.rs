@@ -0,0 +1,125 @@
+//! Extraction of archive formats we don't have a streaming reader for, using 7-Zip.
+//! The archive is extracted into a temporary directory, which is deleted once all files were read.
+
+use super::sandbox::sandboxed;
+use super::spawning::{input_as_file, map_exe_error, stderr_if_debug, CommandPaths};
+use super::AdaptInfo;
+use crate::adapted_iter::{AdaptedFilesIter, AdaptedFilesIterBox};
+use crate::config::RgaConfig;
+use crate::entry_names::decode_path;
+use anyhow::*;
+use log::*;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+fn list_files(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            list_files(&entry.path(), out)?;
+        } else if file_type.is_file() {
+            out.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+pub struct ExtractedFilesIter {
+    dir: tempfile::TempDir,
+    files: std::vec::IntoIter<PathBuf>,
+    line_prefix: String,
+    archive_recursion_depth: i32,
+    postprocess: bool,
+    config: RgaConfig,
+}
+
+impl AdaptedFilesIter for ExtractedFilesIter {
+    fn next<'a>(&'a mut self) -> Option<AdaptInfo<'a>> {
+        loop {
+            let path = self.files.next()?;
+            let inp = match File::open(&path) {
+                Ok(f) => f,
+                Err(e) => {
+                    warn!("could not open extracted file {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let relative = path.strip_prefix(self.dir.path()).unwrap_or(&path);
+            return Some(AdaptInfo {
+                line_prefix: self.config.entry_prefix(
+                    &self.line_prefix,
+                    &decode_path(relative, &self.config.entry_name_encoding),
+                ),
+                filepath_hint: relative.to_owned(),
+                is_real_file: false,
+                archive_recursion_depth: self.archive_recursion_depth + 1,
+                inp: Box::new(inp),
+                postprocess: self.postprocess,
+                config: self.config.clone(),
+            });
+        }
+    }
+}
+
+/// extract the whole archive with `7z x` and iterate over the contained files.
+/// `format` is passed to 7z as `-t<format>` if the format can't be detected from the file
+pub fn extract<'a>(ai: AdaptInfo<'a>, format: Option<&str>) -> Result<AdaptedFilesIterBox<'a>> {
+    let AdaptInfo {
+        filepath_hint,
+        is_real_file,
+        mut inp,
+        line_prefix,
+        archive_recursion_depth,
+        postprocess,
+        config,
+    } = ai;
+    let (path, _tmp) = input_as_file(&filepath_hint, is_real_file, &mut inp)?;
+    let dir = tempfile::Builder::new().prefix("rga-7z-").tempdir()?;
+    let mut cmd = Command::new("7z");
+    cmd.arg("x").arg("-y").arg("-bd").arg("-p");
+    if let Some(format) = format {
+        cmd.arg(format!("-t{}", format));
+    }
+    cmd.arg(format!("-o{}", dir.path().display()))
+        .arg(&path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(stderr_if_debug());
+    let paths = CommandPaths {
+        filepath_hint: &filepath_hint,
+        input_path: Some(&path),
+        tmpdir: Some(dir.path()),
+    };
+    let mut cmd = sandboxed(cmd, &paths, &config)?;
+    debug!("extracting: {:?}", cmd);
+    let status = cmd.status().map_err(|e| {
+        map_exe_error(
+            e,
+            "7z",
+            "Please make sure you have 7-Zip (p7zip) installed.",
+        )
+    })?;
+    let mut files = vec![];
+    list_files(dir.path(), &mut files)?;
+    if !status.success() {
+        if files.is_empty() {
+            return Err(format_err!("7z failed with {}", status));
+        }
+        // e.g. some files in the archive are encrypted or damaged
+        warn!(
+            "7z failed with {}, only searching the files that could be extracted",
+            status
+        );
+    }
+    Ok(Box::new(ExtractedFilesIter {
+        dir,
+        files: files.into_iter(),
+        line_prefix,
+        archive_recursion_depth,
+        postprocess,
+        config,
+    }))
+}
@@ -1,13 +1,21 @@
+use super::fallback::active_fallback;
+use super::postproc::{file_uri, hyperlink, postproc_pagebreaks};
+use super::sandbox::sandboxed;
 use crate::adapted_iter::SingleAdaptedFileAsIter;
+use crate::print_dur;
+use crate::truncation::Truncation;
 
 use super::*;
 use anyhow::*;
+use crossbeam_channel::RecvTimeoutError;
 use encoding_rs_io::DecodeReaderBytesBuilder;
+use fs2::FileExt;
 use log::*;
 
 use std::io::BufReader;
 use std::process::Command;
 use std::process::{Child, Stdio};
+use std::time::{Duration, Instant};
 use std::{io::prelude::*, path::Path};
 
 /**
@@ -57,10 +65,76 @@ pub fn postproc_line_prefix(
     Ok(())
 }
 
+/// the paths the arguments of a spawned program can refer to
+pub struct CommandPaths<'a> {
+    /// the path of the file (within archives: the path within the archive)
+    pub filepath_hint: &'a Path,
+    /// the file to read the input from if needs_file, otherwise it is passed on stdin
+    pub input_path: Option<&'a Path>,
+    /// a temporary directory for the program if needs_tmpdir, deleted after it ran
+    pub tmpdir: Option<&'a Path>,
+}
+
 // TODO: don't separate the trait and the struct
 pub trait SpawningFileAdapterTrait: GetMetadata {
     fn get_exe(&self) -> &str;
-    fn command(&self, filepath_hint: &Path, command: Command) -> Result<Command>;
+    fn command(
+        &self,
+        paths: &CommandPaths,
+        config: &RgaConfig,
+        command: Command,
+    ) -> Result<Command>;
+
+    /// whether the program can't read the input from stdin and needs the path of a (seekable) file
+    fn needs_file(&self) -> bool {
+        false
+    }
+
+    /// whether the program needs a temporary directory (e.g. to write files to)
+    fn needs_tmpdir(&self) -> bool {
+        false
+    }
+
+    /// programs the output is piped through, in order, with their names for error messages
+    fn pipe_to(&self, _paths: &CommandPaths) -> Result<Vec<(String, Command)>> {
+        Ok(vec![])
+    }
+
+    /// the file extension of the output, if it should be matched against the adapters again instead of being plain text
+    fn output_extension(&self) -> Option<&str> {
+        None
+    }
+
+    /// whether the program is started once and converts all files with convert_with_server, also with extra arguments
+    fn persistent(&self) -> bool {
+        false
+    }
+
+    /// whether the output has form feeds between pages, which are turned into "Page N:" prefixes
+    fn page_breaks(&self) -> bool {
+        false
+    }
+
+    /// see FileAdapter::cache_key_extra
+    fn cache_key_extra(&self, _filepath: &Path, _config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// see FileAdapter::definition_digest
+    fn definition_digest(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// convert the file with a long-running server process instead of running the program (see --rga-pandoc-server).
+    /// Must return None without reading the input if there is no server for this adapter
+    fn convert_with_server(
+        &self,
+        _filepath_hint: &Path,
+        _config: &RgaConfig,
+        _inp: &mut dyn Read,
+    ) -> Result<Option<String>> {
+        Ok(None)
+    }
 
     /*fn postproc(&self, line_prefix: &str, inp: &mut dyn Read, oup: &mut dyn Write) -> Result<()> {
         postproc_line_prefix(line_prefix, inp, oup)
@@ -89,56 +163,594 @@ impl GetMetadata for SpawningFileAdapter {
     }
 }*/
 
+/// stderr for subprocesses whose errors are not captured: only shown with --debug
+pub fn stderr_if_debug() -> Stdio {
+    if log_enabled!(Level::Debug) {
+        Stdio::inherit()
+    } else {
+        Stdio::null()
+    }
+}
+
+/// The command for the program of an adapter. On Windows, Command::new only finds .exe files in PATH,
+/// so scripts (e.g. custom adapters written as .cmd files) are run by their path. std then runs them
+/// with cmd.exe and quotes the arguments for it, which differs from the quoting of programs
+pub fn program_command(exe: &str) -> Command {
+    if cfg!(windows) {
+        if let Some(path) = crate::doctor::find_program(exe) {
+            let is_script = path.extension().map_or(false, |ext| {
+                ext.eq_ignore_ascii_case("bat") || ext.eq_ignore_ascii_case("cmd")
+            });
+            if is_script {
+                return Command::new(path);
+            }
+        }
+    }
+    Command::new(exe)
+}
+
 /// replace a Command.spawn() error "File not found" with a more readable error
 /// to indicate some program is not installed
 pub fn map_exe_error(err: std::io::Error, exe_name: &str, help: &str) -> Error {
     use std::io::ErrorKind::*;
     match err.kind() {
-        NotFound => format_err!("Could not find executable \"{}\". {}", exe_name, help),
+        // keep the io error in the chain, so callers can tell that the failure was caused by the environment
+        NotFound => Error::new(err).context(format!(
+            "Could not find executable \"{}\". {}",
+            exe_name, help
+        )),
         _ => Error::from(err),
     }
 }
 
-struct ProcWaitReader {
+/// Some programs can't read from stdin and need a real (seekable) file.
+/// Returns the path of the real file if there is one, or otherwise writes the input stream to a temporary file.
+/// The temporary file is deleted when the returned handle is dropped.
+pub fn input_as_file(
+    filepath_hint: &Path,
+    is_real_file: bool,
+    inp: &mut dyn Read,
+) -> Result<(PathBuf, Option<tempfile::NamedTempFile>)> {
+    if is_real_file {
+        return Ok((filepath_hint.to_owned(), None));
+    }
+    // keep the extension, some programs use it to detect the file type
+    let suffix = filepath_hint
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let mut tmp = tempfile::Builder::new()
+        .prefix("rga-")
+        .suffix(&suffix)
+        .tempfile()?;
+    std::io::copy(inp, &mut tmp)?;
+    tmp.flush()?;
+    debug!("copied input to temporary file {}", tmp.path().display());
+    Ok((tmp.path().to_owned(), Some(tmp)))
+}
+
+/// The complete input of an adapter, see read_input
+pub enum InputData {
+    Mapped(memmap2::Mmap),
+    Read(Vec<u8>),
+}
+
+impl std::ops::Deref for InputData {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            InputData::Mapped(map) => map,
+            InputData::Read(data) => data,
+        }
+    }
+}
+
+/// For adapters that need random access to the whole input.
+/// Real files are memory-mapped instead of copied into memory (falling back to reading them if that fails),
+/// other input streams are read to the end.
+pub fn read_input(
+    filepath_hint: &Path,
+    is_real_file: bool,
+    inp: &mut dyn Read,
+) -> Result<InputData> {
+    if is_real_file {
+        match map_file(filepath_hint) {
+            Ok(map) => return Ok(InputData::Mapped(map)),
+            Err(e) => debug!(
+                "could not mmap {}, reading it instead: {:#}",
+                filepath_hint.display(),
+                e
+            ),
+        }
+    }
+    let mut data = Vec::new();
+    inp.read_to_end(&mut data)?;
+    Ok(InputData::Read(data))
+}
+
+fn map_file(path: &Path) -> Result<memmap2::Mmap> {
+    let file = std::fs::File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        // empty files can't be mapped
+        bail!("file is empty");
+    }
+    // the mapping is only read. If the file is changed while it is mapped, the output can be garbage
+    // (like when it's changed while being read), same as with the mmap option of rg
+    Ok(unsafe { memmap2::Mmap::map(&file)? })
+}
+
+/// A slot of --rga-jobs, held while an external program runs. Released when dropped or when the process exits
+pub struct JobSlot {
+    _file: std::fs::File,
+}
+
+/// Wait until fewer than `jobs` external programs run in all rga processes.
+/// The slots are lock files in the cache directory, so they are also released when a process crashes
+pub fn acquire_job_slot(jobs: usize) -> Result<JobSlot> {
+    let dir = crate::project_dirs()?.cache_dir().join("jobs");
+    std::fs::create_dir_all(&dir)?;
+    let mut slots = (0..jobs.max(1))
+        .map(|i| {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .open(dir.join(format!("slot-{}", i)))
+        })
+        .collect::<std::io::Result<Vec<_>>>()
+        .with_context(|| format!("could not open job slots in {}", dir.display()))?;
+    let start = Instant::now();
+    let mut logged = false;
+    loop {
+        if let Some(i) = slots.iter().position(|f| f.try_lock_exclusive().is_ok()) {
+            if logged {
+                debug!("waited {} for a job slot", print_dur(start));
+            }
+            return Ok(JobSlot {
+                _file: slots.swap_remove(i),
+            });
+        }
+        if !logged {
+            debug!("all {} job slots are in use, waiting", jobs);
+            logged = true;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Apply the resource limits of the config (--rga-adapter-cpu-limit etc.) to the command of an external program,
+/// so a pathological input can't use up the cpu, memory or disk of the machine
+#[cfg(unix)]
+pub fn limit_resources(cmd: &mut Command, config: &RgaConfig) {
+    use std::os::unix::process::CommandExt;
+    let limits = [
+        (libc::RLIMIT_CPU, config.adapter_cpu_limit.map(|l| l.0)),
+        (
+            libc::RLIMIT_AS,
+            config.adapter_memory_limit.map(|l| l.0 as u64),
+        ),
+        (
+            libc::RLIMIT_FSIZE,
+            config.adapter_file_size_limit.map(|l| l.0 as u64),
+        ),
+    ];
+    if limits.iter().all(|(_, limit)| limit.is_none()) {
+        return;
+    }
+    // runs in the forked child, so it must only call async-signal-safe functions
+    let set_limits = move || {
+        for (resource, limit) in &limits {
+            let limit = match limit {
+                Some(limit) => *limit as libc::rlim_t,
+                None => continue,
+            };
+            let mut rlim = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            if unsafe { libc::getrlimit(*resource, &mut rlim) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            // the hard cpu limit is a second later, so the program is stopped with SIGXCPU instead of SIGKILL
+            let hard = if *resource == libc::RLIMIT_CPU {
+                limit.saturating_add(1)
+            } else {
+                limit
+            };
+            // the limits can only be lowered
+            if rlim.rlim_max != libc::RLIM_INFINITY {
+                rlim.rlim_cur = limit.min(rlim.rlim_max);
+                rlim.rlim_max = hard.min(rlim.rlim_max);
+            } else {
+                rlim.rlim_cur = limit;
+                rlim.rlim_max = hard;
+            }
+            if unsafe { libc::setrlimit(*resource, &rlim) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    };
+    unsafe {
+        cmd.pre_exec(set_limits);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn limit_resources(_cmd: &mut Command, config: &RgaConfig) {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    if config.adapter_cpu_limit.is_some()
+        || config.adapter_memory_limit.is_some()
+        || config.adapter_file_size_limit.is_some()
+    {
+        WARNED.call_once(|| warn!("resource limits of adapters are only supported on Unix"));
+    }
+}
+
+/// the resource limit the subprocess was stopped by (its name and option), see limit_resources
+#[cfg(unix)]
+fn exceeded_limit(status: &std::process::ExitStatus) -> Option<(&'static str, &'static str)> {
+    use std::os::unix::process::ExitStatusExt;
+    match status.signal() {
+        Some(libc::SIGXCPU) => Some(("cpu time", "--rga-adapter-cpu-limit")),
+        Some(libc::SIGXFSZ) => Some(("file size", "--rga-adapter-file-size-limit")),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn exceeded_limit(_status: &std::process::ExitStatus) -> Option<(&'static str, &'static str)> {
+    None
+}
+
+lazy_static::lazy_static! {
+    /// pids of the running subprocesses, see kill_subprocesses
+    static ref RUNNING: std::sync::Mutex<std::collections::HashSet<u32>> = Default::default();
+}
+
+/// register a long-running subprocess that is not run through pipe_output, see kill_subprocesses
+pub fn subprocess_started(pid: u32) {
+    RUNNING.lock().unwrap().insert(pid);
+}
+
+pub fn subprocess_exited(pid: u32) {
+    RUNNING.lock().unwrap().remove(&pid);
+}
+
+/// whether subprocesses are running that were not waited for yet
+pub fn subprocesses_running() -> bool {
+    !RUNNING.lock().unwrap().is_empty()
+}
+
+/// Kill all running subprocesses of adapters, before exiting without waiting for them
+#[cfg(unix)]
+pub fn kill_subprocesses() {
+    signal_subprocesses(libc::SIGKILL);
+}
+
+/// send the signal to all running subprocesses of adapters, e.g. to forward a SIGTERM (see signals)
+#[cfg(unix)]
+pub fn signal_subprocesses(signal: libc::c_int) {
+    for pid in RUNNING.lock().unwrap().iter() {
+        unsafe {
+            libc::kill(*pid as libc::pid_t, signal);
+        }
+    }
+}
+
+/// size of the chunks passed between the subprocess threads
+const PIPE_CHUNK_SIZE: usize = 1 << 16;
+/// how much of the stderr of a failed subprocess is included in the error
+const STDERR_TAIL_LEN: usize = 4096;
+
+/**
+ * The output of a running subprocess.
+ *
+ * stdin, stdout and stderr of the subprocess are each serviced by a thread, and this reader feeds the input to
+ * the stdin thread whenever it can take more while waiting for output. So a subprocess that writes output
+ * before it has read all of its input (or fills its stderr) can't block on a full pipe.
+ */
+struct ProcessReader<'a> {
+    inp: ReadBox<'a>,
+    /// chunk of the input that the stdin thread has not taken yet
+    pending_input: Option<Vec<u8>>,
+    /// None once the input is exhausted or the subprocess closed its stdin
+    stdin: Option<crossbeam_channel::Sender<Vec<u8>>>,
+    stdout: crossbeam_channel::Receiver<std::io::Result<Vec<u8>>>,
+    stderr: Option<std::thread::JoinHandle<Vec<u8>>>,
+    current: std::io::Cursor<Vec<u8>>,
+    /// whether the output so far ends with a newline, so the timeout message starts on its own line
+    at_line_start: bool,
     proce: Child,
+    /// released as soon as the subprocess exits
+    slot: Option<JobSlot>,
+    exe_name: String,
+    /// --rga-adapter-timeout, the subprocess is killed at the deadline
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    done: bool,
+}
+
+enum Output {
+    Chunk(Vec<u8>),
+    End,
+    TimedOut,
 }
-impl Read for ProcWaitReader {
-    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+
+impl<'a> ProcessReader<'a> {
+    fn new(
+        mut proce: Child,
+        inp: ReadBox<'a>,
+        slot: Option<JobSlot>,
+        exe_name: &str,
+        timeout: Option<Duration>,
+    ) -> ProcessReader<'a> {
+        let (stdin, stdin_receiver) = crossbeam_channel::bounded::<Vec<u8>>(4);
+        let mut child_stdin = proce.stdin.take().expect("is piped");
+        std::thread::spawn(move || {
+            for chunk in stdin_receiver.iter() {
+                if let Err(e) = child_stdin.write_all(&chunk) {
+                    // the subprocess does not read (all of) its input, e.g. if it reads the file from disk instead
+                    debug!("subprocess closed stdin: {}", e);
+                    break;
+                }
+            }
+            // dropping stdin signals EOF
+        });
+
+        let (stdout_sender, stdout) = crossbeam_channel::bounded(4);
+        let mut child_stdout = proce.stdout.take().expect("is piped");
+        std::thread::spawn(move || loop {
+            let mut chunk = vec![0u8; PIPE_CHUNK_SIZE];
+            let res = match child_stdout.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    chunk.truncate(n);
+                    Ok(chunk)
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = res.is_err();
+            // the receiver is gone if the output is not needed anymore
+            if stdout_sender.send(res).is_err() || failed {
+                break;
+            }
+        });
+
+        let mut child_stderr = proce.stderr.take().expect("is piped");
+        let stderr_name = exe_name.to_string();
+        let stderr = std::thread::spawn(move || {
+            let mut tail = Vec::new();
+            let mut chunk = vec![0u8; PIPE_CHUNK_SIZE];
+            while let Ok(n) = child_stderr.read(&mut chunk) {
+                if n == 0 {
+                    break;
+                }
+                // adapters often print warnings for every file, only show them with --debug.
+                // Errors include the end of the output
+                debug!(
+                    "{} stderr: {}",
+                    stderr_name,
+                    String::from_utf8_lossy(&chunk[..n]).trim_end()
+                );
+                tail.extend_from_slice(&chunk[..n]);
+                if tail.len() > STDERR_TAIL_LEN {
+                    tail.drain(..tail.len() - STDERR_TAIL_LEN);
+                }
+            }
+            tail
+        });
+
+        ProcessReader {
+            inp,
+            pending_input: None,
+            stdin: Some(stdin),
+            stdout,
+            stderr: Some(stderr),
+            current: std::io::Cursor::new(Vec::new()),
+            at_line_start: true,
+            proce,
+            slot,
+            exe_name: exe_name.to_string(),
+            timeout,
+            deadline: timeout.map(|t| Instant::now() + t),
+            done: false,
+        }
+    }
+
+    /// wait for the next chunk of output, feeding input in the meantime
+    fn next_output(&mut self) -> std::io::Result<Output> {
+        let received = |r: Option<std::io::Result<Vec<u8>>>| match r {
+            Some(chunk) => Ok(Output::Chunk(chunk?)),
+            None => Ok(Output::End),
+        };
+        loop {
+            if self.pending_input.is_none() && self.stdin.is_some() {
+                let mut chunk = vec![0u8; PIPE_CHUNK_SIZE];
+                let n = self.inp.read(&mut chunk)?;
+                if n == 0 {
+                    // close stdin
+                    self.stdin = None;
+                } else {
+                    chunk.truncate(n);
+                    self.pending_input = Some(chunk);
+                }
+            }
+            let stdin = match &self.stdin {
+                Some(stdin) => stdin,
+                None => {
+                    return match self.deadline {
+                        Some(deadline) => match self.stdout.recv_timeout(remaining(deadline)) {
+                            Ok(r) => received(Some(r)),
+                            Err(RecvTimeoutError::Timeout) => Ok(Output::TimedOut),
+                            Err(RecvTimeoutError::Disconnected) => received(None),
+                        },
+                        None => received(self.stdout.recv().ok()),
+                    }
+                }
+            };
+            let mut sel = crossbeam_channel::Select::new();
+            let send_index = sel.send(stdin);
+            let recv_index = sel.recv(&self.stdout);
+            let op = match self.deadline {
+                Some(deadline) => match sel.select_timeout(remaining(deadline)) {
+                    Ok(op) => op,
+                    Err(_) => return Ok(Output::TimedOut),
+                },
+                None => sel.select(),
+            };
+            if op.index() == send_index {
+                let chunk = self.pending_input.take().expect("read above");
+                if op.send(stdin, chunk).is_err() {
+                    self.stdin = None;
+                }
+            } else {
+                assert_eq!(op.index(), recv_index);
+                return received(op.recv(&self.stdout).ok());
+            }
+        }
+    }
+
+    fn exited(&mut self) {
+        self.slot = None;
+        subprocess_exited(self.proce.id());
+    }
+
+    /// kill the subprocess and end the output with a note instead of failing, so the search continues
+    fn time_out(&mut self) -> std::io::Result<()> {
+        warn!(
+            "{} did not finish within the adapter timeout, killing it",
+            self.exe_name
+        );
+        self.done = true;
+        self.stdin = None;
+        self.inp = Box::new(std::io::empty());
+        // it may have exited in the meantime
+        self.proce.kill().ok();
+        self.proce.wait()?;
+        self.exited();
+        let timeout = self.timeout.expect("only times out with a timeout");
+        self.end_with_note(
+            &Truncation::new(format!("{:?}", timeout), "--rga-adapter-timeout").to_string(),
+        );
+        Ok(())
+    }
+
+    /// end the output with a note on its own line
+    fn end_with_note(&mut self, note: &str) {
+        let mut line = String::new();
+        if !self.at_line_start {
+            line.push('\n');
+        }
+        line.push_str(note);
+        line.push('\n');
+        self.current = std::io::Cursor::new(line.into_bytes());
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.done = true;
+        self.stdin = None;
         let status = self.proce.wait()?;
+        self.exited();
+        let stderr = self
+            .stderr
+            .take()
+            .and_then(|t| t.join().ok())
+            .unwrap_or_default();
         if status.success() {
-            Ok(0)
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format_err!("subprocess failed: {:?}", status),
-            ))
+            return Ok(());
+        }
+        // like timeouts, the search continues with the output so far
+        if let Some((limit, flag)) = exceeded_limit(&status) {
+            warn!(
+                "{} exceeded the {} limit, its output is incomplete",
+                self.exe_name, limit
+            );
+            let truncation = Truncation::new(format!("the {} limit", limit), flag);
+            self.end_with_note(&truncation.to_string());
+            return Ok(());
+        }
+        let stderr = String::from_utf8_lossy(&stderr);
+        let stderr = stderr.trim();
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            if stderr.is_empty() {
+                format_err!("subprocess failed: {:?}", status)
+            } else {
+                format_err!("subprocess failed: {:?}: {}", status, stderr)
+            },
+        ))
+    }
+}
+
+fn remaining(deadline: Instant) -> Duration {
+    deadline.saturating_duration_since(Instant::now())
+}
+
+impl Drop for ProcessReader<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            // the rest of the output is not needed, e.g. because rg exited after the first match
+            debug!("output of {} not needed anymore, killing it", self.exe_name);
+            self.proce.kill().ok();
+            self.proce.wait().ok();
+            self.exited();
+        }
+    }
+}
+
+impl Read for ProcessReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let n = self.current.read(buf)?;
+            if n > 0 || self.done {
+                return Ok(n);
+            }
+            match self.next_output()? {
+                Output::Chunk(chunk) => {
+                    self.at_line_start = chunk.ends_with(b"\n");
+                    self.current = std::io::Cursor::new(chunk);
+                }
+                Output::End => self.finish()?,
+                Output::TimedOut => self.time_out()?,
+            }
         }
     }
 }
+
 pub fn pipe_output<'a>(
     _line_prefix: &str,
     mut cmd: Command,
-    inp: &mut (dyn Read + 'a),
+    inp: ReadBox<'a>,
     exe_name: &str,
     help: &str,
+    timeout: Option<Duration>,
+    jobs: Option<usize>,
 ) -> Result<ReadBox<'a>> {
-    let mut cmd = cmd
+    let slot = jobs.map(acquire_job_slot).transpose()?;
+    let proce = cmd
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
         .spawn()
         .map_err(|e| map_exe_error(e, exe_name, help))?;
-    let mut stdi = cmd.stdin.take().expect("is piped");
-    let stdo = cmd.stdout.take().expect("is piped");
-
-    // TODO: how to handle this copying better?
-    // do we really need threads for this?
-    crossbeam::scope(|_s| -> Result<()> {
-        std::io::copy(inp, &mut stdi)?;
-        drop(stdi); // NEEDED! otherwise deadlock
-        Ok(())
-    })
-    .unwrap()?;
-    Ok(Box::new(stdo.chain(ProcWaitReader { proce: cmd })))
+    subprocess_started(proce.id());
+    Ok(Box::new(ProcessReader::new(
+        proce, inp, slot, exe_name, timeout,
+    )))
+}
+
+/// the output of a program that uses a temporary file or directory, which is deleted once the output is read
+struct KeepTempFiles<'a> {
+    inner: ReadBox<'a>,
+    _file: Option<tempfile::NamedTempFile>,
+    _dir: Option<tempfile::TempDir>,
+}
+
+impl Read for KeepTempFiles<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
 }
 
 impl FileAdapter for SpawningFileAdapter {
@@ -157,15 +769,116 @@ impl FileAdapter for SpawningFileAdapter {
             is_real_file,
         } = ai;
 
-        let cmd = Command::new(self.inner.get_exe());
-        let cmd = self
-            .inner
-            .command(&filepath_hint, cmd)
-            .with_context(|| format!("Could not set cmd arguments for {}", self.inner.get_exe()))?;
-        debug!("executing {:?}", cmd);
-        let output = pipe_output(&line_prefix, cmd, &mut inp, self.inner.get_exe(), "")?;
+        let extra_args = config.adapter_extra_args(&self.metadata().name);
+        let fallback = active_fallback(&self.metadata().name, self.inner.get_exe());
+        // the server can't be given the extra arguments, unless the adapter starts it itself
+        let converted = if let Some(fallback) = fallback {
+            debug!(
+                "{} is not installed, using the fallback",
+                self.inner.get_exe()
+            );
+            Some(fallback.convert(&filepath_hint, &mut inp)?)
+        } else if extra_args.is_empty() || self.inner.persistent() {
+            self.inner
+                .convert_with_server(&filepath_hint, &config, &mut inp)?
+        } else {
+            None
+        };
+        let output: ReadBox = match converted {
+            Some(text) => Box::new(std::io::Cursor::new(text.into_bytes())),
+            None => {
+                let (input_path, tmp) = if self.inner.needs_file() {
+                    let (path, tmp) = input_as_file(&filepath_hint, is_real_file, &mut inp)?;
+                    // on Windows, programs can't open files deep in folder trees by their usual path
+                    (Some(crate::paths::long_path(&path)), tmp)
+                } else {
+                    (None, None)
+                };
+                let tmpdir = if self.inner.needs_tmpdir() {
+                    Some(tempfile::Builder::new().prefix("rga-").tempdir()?)
+                } else {
+                    None
+                };
+                let paths = CommandPaths {
+                    filepath_hint: &filepath_hint,
+                    input_path: input_path.as_deref(),
+                    tmpdir: tmpdir.as_ref().map(|d| d.path()),
+                };
+                let mut cmd = program_command(self.inner.get_exe());
+                cmd.args(extra_args);
+                let cmd = self.inner.command(&paths, &config, cmd).with_context(|| {
+                    format!("Could not set cmd arguments for {}", self.inner.get_exe())
+                })?;
+                // the limits apply to the sandbox and the program it runs
+                let mut cmd = sandboxed(cmd, &paths, &config)?;
+                limit_resources(&mut cmd, &config);
+                debug!("executing {:?}", cmd);
+                let inp: ReadBox = if input_path.is_some() {
+                    Box::new(std::io::empty())
+                } else {
+                    inp
+                };
+                let timeout = config.adapter_timeout.map(|t| Duration::from_secs(t.0));
+                let mut output = pipe_output(
+                    &line_prefix,
+                    cmd,
+                    inp,
+                    self.inner.get_exe(),
+                    "",
+                    timeout,
+                    config.jobs.map(|j| j.0),
+                )?;
+                for (exe, cmd) in self.inner.pipe_to(&paths)? {
+                    let mut cmd = sandboxed(cmd, &paths, &config)?;
+                    limit_resources(&mut cmd, &config);
+                    debug!("piping to {:?}", cmd);
+                    // the pipeline is one job, waiting for another slot while holding one could deadlock
+                    output = pipe_output(&line_prefix, cmd, output, &exe, "", timeout, None)?;
+                }
+                if tmp.is_some() || tmpdir.is_some() {
+                    Box::new(KeepTempFiles {
+                        inner: output,
+                        _file: tmp,
+                        _dir: tmpdir,
+                    })
+                } else {
+                    output
+                }
+            }
+        };
+        let output: ReadBox = if self.inner.page_breaks() && !config.no_page_numbers {
+            let page_format = config.effective_page_format();
+            // files in archives can't be opened by a link
+            if config.hyperlinks && is_real_file && !config.json_records {
+                let uri = file_uri(&filepath_hint);
+                Box::new(postproc_pagebreaks(
+                    "",
+                    move |page| {
+                        hyperlink(&format!("{}#page={}", uri, page), &page_format.prefix(page))
+                    },
+                    output,
+                )?)
+            } else {
+                Box::new(postproc_pagebreaks(
+                    "",
+                    move |page| page_format.prefix(page),
+                    output,
+                )?)
+            }
+        } else {
+            output
+        };
+        // the output is plain text, so make sure it is not matched by this adapter again.
+        // Other outputs are adapted like a file in an archive, which also limits how often that can repeat
+        let mut output_hint = filepath_hint.into_os_string();
+        output_hint.push(".");
+        output_hint.push(self.inner.output_extension().unwrap_or("txt"));
+        let archive_recursion_depth = match self.inner.output_extension() {
+            Some(_) => archive_recursion_depth + 1,
+            None => archive_recursion_depth,
+        };
         Ok(Box::new(SingleAdaptedFileAsIter::new(AdaptInfo {
-            filepath_hint,
+            filepath_hint: PathBuf::from(output_hint),
             inp: output,
             line_prefix,
             is_real_file: false,
@@ -174,4 +887,132 @@ impl FileAdapter for SpawningFileAdapter {
             config,
         })))
     }
+
+    fn cache_key_extra(&self, filepath: &Path, config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        let mut extra = self.inner.cache_key_extra(filepath, config)?;
+        if self.inner.page_breaks() && config.no_page_numbers {
+            extra
+                .get_or_insert_with(Vec::new)
+                .extend_from_slice(b"no-page-numbers");
+        }
+        if let Some(fallback) = active_fallback(&self.metadata().name, self.inner.get_exe()) {
+            extra
+                .get_or_insert_with(Vec::new)
+                .extend_from_slice(format!("fallback={}", fallback.version).as_bytes());
+        }
+        Ok(extra)
+    }
+
+    fn definition_digest(&self) -> Option<Vec<u8>> {
+        self.inner.definition_digest()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn large_input_and_output() -> Result<()> {
+        // cat writes output before it has read all input, which blocks if the input is written first
+        let input = "all work and no play makes jack a dull boy\n".repeat(100_000);
+        let mut output = String::new();
+        pipe_output(
+            "",
+            Command::new("cat"),
+            Box::new(std::io::Cursor::new(input.clone().into_bytes())),
+            "cat",
+            "",
+            None,
+            None,
+        )?
+        .read_to_string(&mut output)?;
+        assert_eq!(output, input);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn failure_includes_stderr() -> Result<()> {
+        let mut cmd = Command::new("sh");
+        cmd.args(&["-c", "echo broken file >&2; exit 3"]);
+        let err = pipe_output("", cmd, Box::new(std::io::empty()), "sh", "", None, None)?
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert!(err.to_string().contains("broken file"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn read_input_maps_real_files() -> Result<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(b"file content")?;
+        let data = read_input(file.path(), true, &mut std::io::empty())?;
+        assert!(matches!(data, InputData::Mapped(_)));
+        assert_eq!(&data[..], b"file content");
+        let data = read_input(file.path(), false, &mut &b"stream content"[..])?;
+        assert!(matches!(data, InputData::Read(_)));
+        assert_eq!(&data[..], b"stream content");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn killed_when_dropped() -> Result<()> {
+        let mut cmd = Command::new("sh");
+        cmd.args(&["-c", "echo started; sleep 10"]);
+        let start = Instant::now();
+        let mut output = pipe_output("", cmd, Box::new(std::io::empty()), "sh", "", None, None)?;
+        output.read_exact(&mut [0u8; 8])?;
+        drop(output);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn file_size_limit() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg("printf partial; exec head -c 100000 /dev/zero > \"$0\"")
+            .arg(dir.path().join("out"));
+        let mut config = RgaConfig::default();
+        config.adapter_file_size_limit = Some(crate::config::ProcessSizeLimit(1000));
+        limit_resources(&mut cmd, &config);
+        let mut output = String::new();
+        pipe_output("", cmd, Box::new(std::io::empty()), "sh", "", None, None)?
+            .read_to_string(&mut output)?;
+        assert_eq!(
+            output,
+            "partial\n[rga: truncated at the file size limit (--rga-adapter-file-size-limit), rerun with --rga-no-limits]\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn timeout() -> Result<()> {
+        let mut cmd = Command::new("sh");
+        cmd.args(&["-c", "printf partial; sleep 10"]);
+        let start = Instant::now();
+        let mut output = String::new();
+        pipe_output(
+            "",
+            cmd,
+            Box::new(std::io::empty()),
+            "sh",
+            "",
+            Some(Duration::from_millis(200)),
+            None,
+        )?
+        .read_to_string(&mut output)?;
+        assert_eq!(
+            output,
+            "partial\n[rga: truncated at 200ms (--rga-adapter-timeout), rerun with --rga-no-limits]\n"
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
 }
@@ -0,0 +1,197 @@
+use super::*;
+use crate::adapted_iter::SingleAdaptedFileAsIter;
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use std::fs::File;
+
+// only the first volume is matched, the others are read from next to it
+static RAR_FIRST_VOLUMES: &[&str] = &["*.part1.rar", "*.part01.rar", "*.part001.rar"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "split".to_owned(),
+        version: 2,
+        description: "Reassembles split archives (foo.zip.001, foo.zip.002, ...) and recurses into the concatenated volumes. Split 7z and multi-volume rar archives (foo.7z.001, foo.part1.rar) are extracted with 7z".to_owned(),
+        recurses: true,
+        fast_matchers: std::iter::once(FastFileMatcher::FileExtension("001".to_string()))
+            .chain(
+                RAR_FIRST_VOLUMES
+                    .iter()
+                    .map(|s| FastFileMatcher::PathGlob(format!("**/{}", s)))
+            )
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct SplitAdapter;
+
+impl SplitAdapter {
+    pub fn new() -> SplitAdapter {
+        SplitAdapter
+    }
+}
+impl GetMetadata for SplitAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the paths of the volumes following the first one (foo.zip.001 -> foo.zip.002, foo.zip.003, ...)
+fn next_volumes(first: &Path) -> Vec<PathBuf> {
+    let width = first.extension().map(|e| e.len()).unwrap_or(3);
+    (2..)
+        .map(|i| first.with_extension(format!("{:0width$}", i, width = width)))
+        .take_while(|p| p.exists())
+        .collect()
+}
+
+/// the paths of the volumes following the first one of a multi-volume rar archive (foo.part01.rar -> foo.part02.rar, ...)
+fn next_rar_volumes(first: &Path) -> Vec<PathBuf> {
+    let name = match first.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return vec![],
+    };
+    let number = match name.to_ascii_lowercase().rfind(".part") {
+        Some(i) => i + ".part".len()..name.len() - ".rar".len(),
+        None => return vec![],
+    };
+    (2..)
+        .map(|i| {
+            first.with_file_name(format!(
+                "{}{:0width$}{}",
+                &name[..number.start],
+                i,
+                &name[number.end..],
+                width = number.len()
+            ))
+        })
+        .take_while(|p| p.exists())
+        .collect()
+}
+
+/// whether the volumes can't simply be concatenated and have to be given to 7z
+fn needs_7z(filepath_hint: &Path) -> bool {
+    let name = filepath_hint.to_string_lossy().to_ascii_lowercase();
+    name.ends_with(".7z.001") || name.ends_with(".rar")
+}
+
+impl FileAdapter for SplitAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        if needs_7z(&ai.filepath_hint) {
+            if !ai.is_real_file {
+                return Err(format_err!(
+                    "{}: split 7z and rar archives can only be read from real files",
+                    ai.filepath_hint.display()
+                ));
+            }
+            // 7z finds the other volumes itself
+            return sevenzip::extract(ai, None);
+        }
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+        } = ai;
+        let mut concatenated = inp;
+        if is_real_file {
+            for volume in next_volumes(&filepath_hint) {
+                debug!("appending volume {}", volume.display());
+                concatenated = Box::new(concatenated.chain(File::open(volume)?));
+            }
+        } else {
+            // no way to find the siblings of a file in an archive
+            warn!(
+                "{}: only reading the first volume of split file",
+                filepath_hint.display()
+            );
+        }
+        Ok(Box::new(SingleAdaptedFileAsIter::new(AdaptInfo {
+            filepath_hint: filepath_hint.with_extension(""),
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            inp: concatenated,
+            line_prefix,
+            postprocess,
+            config,
+        })))
+    }
+
+    /// the other volumes are read from next to the file, so the output changes with them
+    fn cache_key_extra(&self, filepath: &Path, _config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        let volumes = if filepath
+            .to_string_lossy()
+            .to_ascii_lowercase()
+            .ends_with(".rar")
+        {
+            next_rar_volumes(filepath)
+        } else {
+            next_volumes(filepath)
+        };
+        let mut key = Vec::new();
+        for volume in volumes {
+            let meta = std::fs::metadata(&volume)?;
+            key.push((meta.len(), meta.modified()?));
+        }
+        Ok(Some(bincode::serialize(&key)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn concatenates_volumes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("greeting.txt.001"), "hello ")?;
+        std::fs::write(dir.path().join("greeting.txt.002"), "split ")?;
+        std::fs::write(dir.path().join("greeting.txt.003"), "world")?;
+        let filepath = dir.path().join("greeting.txt.001");
+
+        let adapter = SplitAdapter::new();
+        let (a, d) = simple_adapt_info(&filepath, Box::new(File::open(&filepath)?));
+        let o = adapted_to_vec(adapter.adapt(a, &d)?)?;
+        assert_eq!(String::from_utf8(o)?, "PREFIX:hello split world\n");
+
+        let key = adapter.cache_key_extra(&filepath, &RgaConfig::default())?;
+        std::fs::write(dir.path().join("greeting.txt.003"), "world!")?;
+        assert_ne!(
+            adapter.cache_key_extra(&filepath, &RgaConfig::default())?,
+            key
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn volume_names() {
+        assert!(needs_7z(Path::new("a/b.7z.001")));
+        assert!(needs_7z(Path::new("b.part01.rar")));
+        assert!(!needs_7z(Path::new("b.zip.001")));
+
+        let dir = tempfile::tempdir().unwrap();
+        for name in &["b.part01.rar", "b.part02.rar", "b.part03.rar"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        assert_eq!(
+            next_rar_volumes(&dir.path().join("b.part01.rar")),
+            [
+                dir.path().join("b.part02.rar"),
+                dir.path().join("b.part03.rar")
+            ]
+        );
+    }
+}
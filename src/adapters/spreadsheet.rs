@@ -0,0 +1,131 @@
+use super::*;
+use anyhow::*;
+use calamine::{DataType, Reader};
+use lazy_static::lazy_static;
+use spawning::input_as_file;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["xls", "xlsx", "xlsm", "xlsb", "ods"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "spreadsheet".to_owned(),
+        version: 1,
+        description: "Outputs the cells of Excel (including the old binary .xls format) and OpenDocument spreadsheets, prefixed with sheet name and cell reference (Sheet1!C14)".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/vnd.ms-excel".to_owned()),
+            FileMatcher::MimeType(
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet".to_owned()
+            ),
+            FileMatcher::MimeType("application/vnd.oasis.opendocument.spreadsheet".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct SpreadsheetAdapter;
+
+impl SpreadsheetAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(SpreadsheetAdapter))
+    }
+}
+impl GetMetadata for SpreadsheetAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the column letters of a zero based column index (0 -> A, 26 -> AA)
+pub fn column_name(mut col: u32) -> String {
+    let mut name = vec![];
+    loop {
+        name.push(b'A' + (col % 26) as u8);
+        if col < 26 {
+            break;
+        }
+        col = col / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).expect("ascii")
+}
+
+fn format_cell(cell: &DataType) -> Option<String> {
+    Some(match cell {
+        DataType::Empty => return None,
+        DataType::String(s) if s.trim().is_empty() => return None,
+        // multi-line cells are output on one line so the cell reference stays in front
+        DataType::String(s) => s.lines().collect::<Vec<_>>().join(" "),
+        DataType::DateTime(_) => match cell.as_datetime() {
+            Some(d) => d.to_string(),
+            None => cell.to_string(),
+        },
+        cell => cell.to_string(),
+    })
+}
+
+impl WritingFileAdapterTrait for SpreadsheetAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            mut inp,
+            ..
+        } = ai;
+        // the formats are zip / ole containers that need seeking
+        let (path, _tmp) = input_as_file(&filepath_hint, is_real_file, &mut inp)?;
+        let mut workbook = calamine::open_workbook_auto(&path)
+            .with_context(|| format!("could not open spreadsheet {}", filepath_hint.display()))?;
+        for sheet in workbook.sheet_names().to_owned() {
+            let range = match workbook.worksheet_range(&sheet) {
+                Some(Ok(range)) => range,
+                Some(Err(e)) => {
+                    log::warn!("could not read sheet {}: {}", sheet, e);
+                    continue;
+                }
+                None => continue,
+            };
+            let (first_row, first_col) = range.start().unwrap_or((0, 0));
+            for (row, col, cell) in range.used_cells() {
+                if let Some(value) = format_cell(cell) {
+                    writeln!(
+                        oup,
+                        "{}!{}{}: {}",
+                        sheet,
+                        column_name(first_col + col as u32),
+                        first_row + row as u32 + 1,
+                        value
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn column_names() {
+        assert_eq!(column_name(0), "A");
+        assert_eq!(column_name(25), "Z");
+        assert_eq!(column_name(26), "AA");
+        assert_eq!(column_name(27), "AB");
+        assert_eq!(column_name(701), "ZZ");
+        assert_eq!(column_name(702), "AAA");
+    }
+}
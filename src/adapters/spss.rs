@@ -0,0 +1,694 @@
+//! SPSS system files (.sav, and the zlib compressed .zsav),
+//! see https://www.gnu.org/software/pspp/pspp-dev/html_node/System-File-Format.html
+
+use super::*;
+use anyhow::*;
+use chrono::{Duration, NaiveDate};
+use encoding_rs::{Encoding, WINDOWS_1252};
+use lazy_static::lazy_static;
+use spawning::read_input;
+use std::collections::HashMap;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["sav", "zsav"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "spss".to_owned(),
+        version: 1,
+        description: "Outputs the variable labels, value labels and cases of SPSS data files (.sav, .zsav) as `name=value` pairs, with value labels applied".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-spss-sav".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct SpssAdapter;
+
+impl SpssAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(SpssAdapter))
+    }
+}
+impl GetMetadata for SpssAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// reader for the records of the dictionary, in the byte order of the file
+struct SavReader<'d> {
+    data: &'d [u8],
+    pos: usize,
+    big: bool,
+}
+
+impl<'d> SavReader<'d> {
+    fn bytes(&mut self, len: usize) -> Result<&'d [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or_else(|| format_err!("unexpected end of file at {}", self.pos))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+    fn i32(&mut self) -> Result<i32> {
+        let b = self.bytes(4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Ok(if self.big {
+            i32::from_be_bytes(b)
+        } else {
+            i32::from_le_bytes(b)
+        })
+    }
+    fn len(&mut self) -> Result<usize> {
+        let len = self.i32()?;
+        if len < 0 {
+            return Err(format_err!("negative length at {}", self.pos));
+        }
+        Ok(len as usize)
+    }
+    fn i64(&mut self) -> Result<i64> {
+        let b = self.bytes(8)?;
+        let mut a = [0; 8];
+        a.copy_from_slice(b);
+        Ok(if self.big {
+            i64::from_be_bytes(a)
+        } else {
+            i64::from_le_bytes(a)
+        })
+    }
+    fn f64(&mut self) -> Result<f64> {
+        Ok(f64::from_bits(self.i64()? as u64))
+    }
+}
+
+/// the system missing value
+const SYSMIS: f64 = -f64::MAX;
+
+struct Variable {
+    name: String,
+    label: Option<String>,
+    /// 0 for numeric variables
+    width: usize,
+    /// the print format: type << 16 | width << 8 | decimals
+    format: u32,
+    /// number of 8 byte slots in a case
+    slots: usize,
+    /// (raw 8 byte value, label)
+    value_labels: Vec<(Vec<u8>, String)>,
+    /// number of following variables that are segments of this (very long) string
+    segments: usize,
+}
+
+enum Value {
+    Number(f64),
+    Text(Vec<u8>),
+}
+
+struct Dictionary {
+    big: bool,
+    encoding: Option<&'static Encoding>,
+    file_label: String,
+    documents: Vec<String>,
+    variables: Vec<Variable>,
+}
+
+impl Dictionary {
+    fn decode(&self, bytes: &[u8]) -> String {
+        let s = match (self.encoding, std::str::from_utf8(bytes)) {
+            (Some(encoding), _) => encoding.decode_without_bom_handling(bytes).0,
+            (None, Ok(s)) => s.into(),
+            (None, Err(_)) => WINDOWS_1252.decode_without_bom_handling(bytes).0,
+        };
+        s.trim_end_matches(|c| c == ' ' || c == '\0').to_string()
+    }
+    fn number(&self, raw: &[u8]) -> f64 {
+        let mut a = [0; 8];
+        a.copy_from_slice(&raw[..8]);
+        f64::from_bits(if self.big {
+            u64::from_be_bytes(a)
+        } else {
+            u64::from_le_bytes(a)
+        })
+    }
+}
+
+/// seconds since the start of the gregorian calendar (1582-10-14)
+fn format_date(secs: f64, with_time: bool) -> Option<String> {
+    if secs.is_nan() || secs.abs() >= 1e13 {
+        return None;
+    }
+    let time = NaiveDate::from_ymd(1582, 10, 14)
+        .and_hms(0, 0, 0)
+        .checked_add_signed(Duration::seconds(secs as i64))?;
+    Some(if with_time {
+        time.format("%Y-%m-%d %H:%M:%S").to_string()
+    } else {
+        time.format("%Y-%m-%d").to_string()
+    })
+}
+
+fn format_number(value: f64, format: u32) -> String {
+    if value == SYSMIS || value.is_nan() {
+        return "".to_string();
+    }
+    let decimals = (format & 0xff) as usize;
+    let formatted = match (format >> 16) & 0xff {
+        // DATE, ADATE, JDATE, QYR, MOYR, WKYR, EDATE, SDATE
+        20 | 23 | 24 | 28 | 29 | 30 | 38 | 39 => format_date(value, false),
+        // DATETIME, YMDHMS
+        22 | 41 => format_date(value, true),
+        _ => None,
+    };
+    formatted.unwrap_or_else(|| format!("{:.*}", decimals, value))
+}
+
+type RawValueLabels<'d> = (Vec<Vec<u8>>, Vec<&'d [u8]>, Vec<usize>);
+
+fn read_dictionary(r: &mut SavReader, file_label: &[u8]) -> Result<Dictionary> {
+    let mut dict = Dictionary {
+        big: r.big,
+        encoding: None,
+        file_label: String::new(),
+        documents: vec![],
+        variables: vec![],
+    };
+    // raw strings, decoded when the encoding is known
+    let mut documents: Vec<&[u8]> = vec![];
+    let mut names: Vec<&[u8]> = vec![];
+    let mut labels: Vec<Option<&[u8]>> = vec![];
+    // (values, labels, variables) of each value label record
+    let mut raw_value_labels: Vec<RawValueLabels> = vec![];
+    let mut long_names: &[u8] = &[];
+    let mut very_long_strings: &[u8] = &[];
+    let mut long_value_labels: Vec<&[u8]> = vec![];
+    // the variable of each 8 byte slot, None for the continuation slots of strings
+    let mut slot_variables: Vec<Option<usize>> = vec![];
+    loop {
+        match r.i32()? {
+            2 => {
+                let width = r.i32()?;
+                let has_label = r.i32()?;
+                let missing_values = r.i32()?;
+                let format = r.i32()? as u32;
+                let _write_format = r.i32()?;
+                let name = r.bytes(8)?;
+                let label = if has_label == 1 {
+                    let len = r.len()?;
+                    let label = r.bytes(len)?;
+                    r.bytes((4 - len % 4) % 4)?;
+                    Some(label)
+                } else {
+                    None
+                };
+                r.bytes(missing_values.abs() as usize * 8)?;
+                if width < 0 {
+                    slot_variables.push(None);
+                    if let Some(v) = dict.variables.last_mut() {
+                        v.slots += 1;
+                    }
+                    continue;
+                }
+                slot_variables.push(Some(dict.variables.len()));
+                names.push(name);
+                labels.push(label);
+                dict.variables.push(Variable {
+                    name: String::new(),
+                    label: None,
+                    width: width as usize,
+                    format,
+                    slots: 1,
+                    value_labels: vec![],
+                    segments: 1,
+                });
+            }
+            3 => {
+                let count = r.len()?;
+                let mut values = vec![];
+                let mut value_labels = vec![];
+                for _ in 0..count {
+                    values.push(r.bytes(8)?.to_vec());
+                    let len = r.bytes(1)?[0] as usize;
+                    value_labels.push(r.bytes(len)?);
+                    r.bytes((8 - (len + 1) % 8) % 8)?;
+                }
+                // always followed by the variables the labels apply to
+                if r.i32()? != 4 {
+                    return Err(format_err!("value labels without variables"));
+                }
+                let count = r.len()?;
+                let mut variables = vec![];
+                for _ in 0..count {
+                    let slot = r.len()?;
+                    if let Some(Some(v)) = slot.checked_sub(1).and_then(|s| slot_variables.get(s)) {
+                        variables.push(*v);
+                    }
+                }
+                raw_value_labels.push((values, value_labels, variables));
+            }
+            6 => {
+                let lines = r.len()?;
+                for _ in 0..lines {
+                    documents.push(r.bytes(80)?);
+                }
+            }
+            7 => {
+                let subtype = r.i32()?;
+                let size = r.len()?;
+                let count = r.len()?;
+                let data = r.bytes(size.saturating_mul(count))?;
+                match subtype {
+                    13 => long_names = data,
+                    14 => very_long_strings = data,
+                    20 => dict.encoding = Encoding::for_label(data),
+                    21 => long_value_labels.push(data),
+                    _ => {}
+                }
+            }
+            999 => {
+                r.i32()?;
+                break;
+            }
+            other => return Err(format_err!("unknown record type {}", other)),
+        }
+    }
+
+    dict.file_label = dict.decode(file_label).trim().to_string();
+    dict.documents = documents
+        .into_iter()
+        .map(|d| dict.decode(d))
+        .filter(|d| !d.trim().is_empty())
+        .collect();
+    let names: Vec<String> = names.into_iter().map(|n| dict.decode(n)).collect();
+    let labels: Vec<Option<String>> = labels
+        .into_iter()
+        .map(|l| l.map(|l| dict.decode(l)))
+        .collect();
+    for ((v, name), label) in dict.variables.iter_mut().zip(names).zip(labels) {
+        v.name = name;
+        v.label = label;
+    }
+    for (values, value_labels, variables) in raw_value_labels {
+        let value_labels: Vec<(Vec<u8>, String)> = values
+            .into_iter()
+            .zip(value_labels.into_iter().map(|l| dict.decode(l)))
+            .collect();
+        for v in variables {
+            dict.variables[v].value_labels.extend(value_labels.clone());
+        }
+    }
+    // SHORT=Long name pairs, separated by tabs
+    let long_names = dict.decode(long_names);
+    let long_names: HashMap<&str, &str> = long_names
+        .split('\t')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            Some((parts.next()?, parts.next()?))
+        })
+        .collect();
+    // SHORT=width pairs, terminated by \0\t. The string is split into variables of 252 bytes
+    let very_long_strings = dict.decode(very_long_strings);
+    for pair in very_long_strings.split('\t') {
+        let mut parts = pair.trim_end_matches('\0').splitn(2, '=');
+        if let (Some(name), Some(Ok(width))) = (parts.next(), parts.next().map(str::parse::<usize>))
+        {
+            if let Some(v) = dict.variables.iter_mut().find(|v| v.name == name) {
+                v.width = width;
+                v.segments = (width + 251) / 252;
+            }
+        }
+    }
+    for v in dict.variables.iter_mut() {
+        if let Some(long_name) = long_names.get(v.name.as_str()) {
+            v.name = long_name.to_string();
+        }
+    }
+    // value labels of long string variables
+    for data in long_value_labels {
+        let mut l = SavReader {
+            data,
+            pos: 0,
+            big: dict.big,
+        };
+        while l.pos < data.len() {
+            let name_len = l.len()?;
+            let name = dict.decode(l.bytes(name_len)?);
+            let _width = l.i32()?;
+            let count = l.len()?;
+            let mut value_labels = vec![];
+            for _ in 0..count {
+                let value_len = l.len()?;
+                let value = l.bytes(value_len)?.to_vec();
+                let label_len = l.len()?;
+                value_labels.push((value, dict.decode(l.bytes(label_len)?)));
+            }
+            if let Some(v) = dict.variables.iter_mut().find(|v| v.name == name) {
+                v.value_labels.extend(value_labels);
+            }
+        }
+    }
+    Ok(dict)
+}
+
+/// the 8 byte slots of the cases, either stored directly or bytecode compressed
+struct Slots<'d> {
+    data: &'d [u8],
+    pos: usize,
+    compressed: bool,
+    big: bool,
+    bias: f64,
+    commands: [u8; 8],
+    command: usize,
+}
+
+impl<'d> Slots<'d> {
+    fn raw(&mut self) -> Option<Vec<u8>> {
+        let slot = self.data.get(self.pos..self.pos + 8)?;
+        self.pos += 8;
+        Some(slot.to_vec())
+    }
+    fn next(&mut self) -> Option<Vec<u8>> {
+        if !self.compressed {
+            return self.raw();
+        }
+        loop {
+            if self.command == 8 {
+                let commands = self.data.get(self.pos..self.pos + 8)?;
+                self.commands.copy_from_slice(commands);
+                self.pos += 8;
+                self.command = 0;
+            }
+            let code = self.commands[self.command];
+            self.command += 1;
+            let big = self.big;
+            let number = |n: f64| {
+                if big {
+                    n.to_bits().to_be_bytes().to_vec()
+                } else {
+                    n.to_bits().to_le_bytes().to_vec()
+                }
+            };
+            return match code {
+                0 => continue,
+                1..=251 => Some(number(code as f64 - self.bias)),
+                252 => None,
+                253 => self.raw(),
+                254 => Some(b"        ".to_vec()),
+                _ => Some(number(SYSMIS)),
+            };
+        }
+    }
+}
+
+/// the case data of a .zsav file: a sequence of zlib streams of bytecode compressed data
+fn zsav_data(r: &mut SavReader) -> Result<Vec<u8>> {
+    let _zheader_offset = r.i64()?;
+    let ztrailer_offset = r.i64()? as usize;
+    let _ztrailer_len = r.i64()?;
+    let end = ztrailer_offset.min(r.data.len()).max(r.pos);
+    let mut out = vec![];
+    let mut pos = r.pos;
+    while pos < end {
+        let mut decoder = flate2::bufread::ZlibDecoder::new(&r.data[pos..end]);
+        decoder.read_to_end(&mut out)?;
+        if decoder.total_in() == 0 {
+            break;
+        }
+        pos += decoder.total_in() as usize;
+    }
+    Ok(out)
+}
+
+fn write_value(dict: &Dictionary, v: &Variable, value: &Value, oup: &mut dyn Write) -> Result<()> {
+    let (formatted, label) = match value {
+        Value::Number(n) => (
+            format_number(*n, v.format),
+            v.value_labels
+                .iter()
+                .find(|(raw, _)| dict.number(raw) == *n)
+                .map(|(_, l)| l),
+        ),
+        Value::Text(t) => {
+            let text = dict.decode(t);
+            let label = v
+                .value_labels
+                .iter()
+                .find(|(raw, _)| dict.decode(raw) == text)
+                .map(|(_, l)| l);
+            (text, label)
+        }
+    };
+    write!(oup, "{}={}", v.name, formatted)?;
+    if let Some(label) = label {
+        write!(oup, " ({})", label)?;
+    }
+    Ok(())
+}
+
+fn adapt_sav(data: &[u8], oup: &mut dyn Write) -> Result<()> {
+    let magic = data.get(0..4).unwrap_or_default();
+    if magic != b"$FL2" && magic != b"$FL3" {
+        return Err(format_err!("not an spss system file"));
+    }
+    // the layout code is 2 (or 3) in the byte order of the file
+    let layout = data
+        .get(64..68)
+        .ok_or_else(|| format_err!("spss header too short"))?;
+    let mut r = SavReader {
+        data,
+        pos: 68,
+        big: layout[0] == 0 && layout[3] != 0,
+    };
+    let _case_size = r.i32()?;
+    let compression = r.i32()?;
+    let _weight_index = r.i32()?;
+    let case_count = r.i32()?;
+    let bias = r.f64()?;
+    // creation date and time
+    r.bytes(9 + 8)?;
+    let file_label = r.bytes(64)?;
+    r.bytes(3)?;
+    let dict = read_dictionary(&mut r, file_label)?;
+
+    if !dict.file_label.is_empty() {
+        writeln!(oup, "label: {}", dict.file_label)?;
+    }
+    for line in &dict.documents {
+        writeln!(oup, "document: {}", line.trim())?;
+    }
+    let variables: Vec<&Variable> = {
+        let mut variables = vec![];
+        let mut skip = 0;
+        for v in &dict.variables {
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            skip = v.segments - 1;
+            variables.push(v);
+        }
+        variables
+    };
+    for v in &variables {
+        match &v.label {
+            Some(label) => writeln!(oup, "variable {}: {}", v.name, label)?,
+            None => writeln!(oup, "variable {}", v.name)?,
+        }
+        for (raw, label) in &v.value_labels {
+            let value = if v.width == 0 {
+                format_number(dict.number(raw), v.format)
+            } else {
+                dict.decode(raw)
+            };
+            writeln!(oup, "value {}={}: {}", v.name, value, label)?;
+        }
+    }
+
+    let zdata;
+    let mut slots = match compression {
+        0 | 1 => Slots {
+            data,
+            pos: r.pos,
+            compressed: compression == 1,
+            big: dict.big,
+            bias,
+            commands: [0; 8],
+            command: 8,
+        },
+        2 => {
+            zdata = zsav_data(&mut r)?;
+            Slots {
+                data: &zdata,
+                pos: 0,
+                compressed: true,
+                big: dict.big,
+                bias,
+                commands: [0; 8],
+                command: 8,
+            }
+        }
+        other => return Err(format_err!("unknown compression {}", other)),
+    };
+    let mut case = 0;
+    'cases: while case_count < 0 || case < case_count {
+        let mut values = vec![];
+        for v in &dict.variables {
+            let mut raw = vec![];
+            for _ in 0..v.slots {
+                match slots.next() {
+                    Some(slot) => raw.extend(slot),
+                    None => break 'cases,
+                }
+            }
+            values.push(if v.width == 0 {
+                Value::Number(dict.number(&raw))
+            } else {
+                raw.truncate(v.width);
+                Value::Text(raw)
+            });
+        }
+        // join the segments of very long strings
+        let mut values = values.into_iter();
+        let mut first = true;
+        for v in &variables {
+            let mut value = values.next().unwrap();
+            if let Value::Text(text) = &mut value {
+                for _ in 1..v.segments {
+                    text.truncate(252);
+                    if let Some(Value::Text(segment)) = values.next() {
+                        text.extend(segment);
+                    }
+                }
+            }
+            if !first {
+                write!(oup, ", ")?;
+            }
+            first = false;
+            write_value(&dict, v, &value, oup)?;
+        }
+        writeln!(oup)?;
+        case += 1;
+    }
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for SpssAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            filepath_hint,
+            is_real_file,
+            ..
+        } = ai;
+        let data = read_input(&filepath_hint, is_real_file, &mut inp)?;
+        adapt_sav(&data, oup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn variable(name: &str, width: i32, label: Option<&str>, format: u32) -> Vec<u8> {
+        let mut out = vec![];
+        for i in &[
+            2,
+            width,
+            label.is_some() as i32,
+            0,
+            format as i32,
+            format as i32,
+        ] {
+            out.extend(&i.to_le_bytes());
+        }
+        out.extend(format!("{:8}", name).bytes());
+        if let Some(label) = label {
+            out.extend(&(label.len() as i32).to_le_bytes());
+            out.extend(label.bytes());
+            while out.len() % 4 != 0 {
+                out.push(b' ');
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn compressed_cases() -> Result<()> {
+        let mut sav = b"$FL2".to_vec();
+        sav.extend(format!("{:60}", "@(#) SPSS DATA FILE pspp 1.4.1").bytes());
+        for i in &[2i32, 4, 1, 0, 2] {
+            sav.extend(&i.to_le_bytes());
+        }
+        sav.extend(&100f64.to_le_bytes());
+        sav.extend(b"16 Oct 2012:00:00");
+        sav.extend(format!("{:64}", "Household survey").bytes());
+        sav.extend(&[0, 0, 0]);
+
+        sav.extend(variable("ID", 0, None, 5 << 16 | 8 << 8));
+        sav.extend(variable("SEX", 0, Some("Respondent sex"), 5 << 16 | 8 << 8));
+        sav.extend(variable(
+            "CITY",
+            9,
+            Some("City of residence"),
+            1 << 16 | 9 << 8,
+        ));
+        sav.extend(variable("", -1, None, 0));
+        // value labels for SEX
+        sav.extend(&3i32.to_le_bytes());
+        sav.extend(&2i32.to_le_bytes());
+        for (value, label) in &[(1f64, "Male   "), (2f64, "Female ")] {
+            sav.extend(&value.to_le_bytes());
+            sav.push(label.trim().len() as u8);
+            sav.extend(label.bytes());
+        }
+        for i in &[4i32, 1, 2] {
+            sav.extend(&i.to_le_bytes());
+        }
+        // long variable names
+        let long_names = b"ID=id\tSEX=sex\tCITY=city";
+        for i in &[7, 13, 1, long_names.len() as i32] {
+            sav.extend(&i.to_le_bytes());
+        }
+        sav.extend(long_names);
+        for i in &[7i32, 20, 1, 5] {
+            sav.extend(&i.to_le_bytes());
+        }
+        sav.extend(b"UTF-8");
+        sav.extend(&999i32.to_le_bytes());
+        sav.extend(&0i32.to_le_bytes());
+        // two cases: 1, 2, "Vilnius", 2, sysmis, "Kaunas"
+        sav.extend(&[101, 102, 253, 254, 102, 255, 253, 254]);
+        sav.extend(b"Vilnius ");
+        sav.extend(b"Kaunas  ");
+        sav.extend(&[252, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut oup = Vec::new();
+        adapt_sav(&sav, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "label: Household survey\n\
+             variable id\n\
+             variable sex: Respondent sex\n\
+             value sex=1: Male\n\
+             value sex=2: Female\n\
+             variable city: City of residence\n\
+             id=1, sex=2 (Female), city=Vilnius\n\
+             id=2, sex=, city=Kaunas\n"
+        );
+        Ok(())
+    }
+}
@@ -1,22 +1,27 @@
 use super::*;
-use anyhow::Result;
+use crate::adapted_iter::{AdaptedFilesIter, SingleAdaptedFileAsIter};
+use crate::config::SqliteConfig;
+use crate::truncation::Truncation;
+use anyhow::{Context, Result};
 use lazy_static::lazy_static;
 use log::*;
 use rusqlite::types::ValueRef;
 use rusqlite::*;
+use std::collections::VecDeque;
 use std::convert::TryInto;
-use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
 
 static EXTENSIONS: &[&str] = &["db", "db3", "sqlite", "sqlite3"];
 
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "sqlite".to_owned(),
-        version: 1,
+        version: 3,
         description:
             "Uses sqlite bindings to convert sqlite databases into a simple plain text format"
                 .to_owned(),
-        recurses: false, // set to true if we decide to make sqlite blobs searchable (gz blob in db is kinda common I think)
+        recurses: true, // blobs can be run through the other adapters (--rga-sqlite-recurse-blobs)
         fast_matchers: EXTENSIONS
             .iter()
             .map(|s| FastFileMatcher::FileExtension(s.to_string()))
@@ -33,8 +38,8 @@ lazy_static! {
 pub struct SqliteAdapter;
 
 impl SqliteAdapter {
-    pub fn new() -> WritingFileAdapter {
-        WritingFileAdapter::new(Box::new(SqliteAdapter {}))
+    pub fn new() -> SqliteAdapter {
+        SqliteAdapter
     }
 }
 impl GetMetadata for SqliteAdapter {
@@ -60,66 +65,321 @@ fn format_blob(b: ValueRef) -> String {
     }
 }
 
-impl WritingFileAdapterTrait for SqliteAdapter {
-    fn adapt_write(
+/// guess a file extension for a blob from its magic bytes, so it can be matched by the fast (extension) matchers
+fn blob_extension(blob: &[u8]) -> &'static str {
+    let magics: &[(&[u8], &str)] = &[
+        (b"\x1f\x8b", ".gz"),
+        (b"BZh", ".bz2"),
+        (b"\xfd7zXZ\x00", ".xz"),
+        (b"\x28\xb5\x2f\xfd", ".zst"),
+        (b"PK\x03\x04", ".zip"),
+        (b"%PDF", ".pdf"),
+        (b"SQLite format 3\x00", ".sqlite3"),
+        (b"\x89PNG", ".png"),
+        (b"\xff\xd8\xff", ".jpg"),
+    ];
+    magics
+        .iter()
+        .find(|(magic, _)| blob.starts_with(magic))
+        .map(|(_, ext)| *ext)
+        .unwrap_or("")
+}
+
+fn filter_tables(tables: Vec<String>, config: &SqliteConfig) -> Result<Vec<String>> {
+    let to_patterns = |names: &Vec<String>| -> Result<Vec<glob::Pattern>> {
+        names
+            .iter()
+            .map(|n| glob::Pattern::new(n).with_context(|| format!("invalid table pattern {}", n)))
+            .collect()
+    };
+    let include = to_patterns(&config.include_tables)?;
+    let exclude = to_patterns(&config.exclude_tables)?;
+    Ok(tables
+        .into_iter()
+        .filter(|t| include.is_empty() || include.iter().any(|p| p.matches(t)))
+        .filter(|t| !exclude.iter().any(|p| p.matches(t)))
+        .collect())
+}
+
+/// the path of a file sqlite keeps next to the database, e.g. "-wal" for the write-ahead log
+fn companion(path: &Path, suffix: &str) -> PathBuf {
+    let mut companion = path.as_os_str().to_owned();
+    companion.push(suffix);
+    PathBuf::from(companion)
+}
+
+fn has_wal(path: &Path) -> bool {
+    std::fs::metadata(companion(path, "-wal")).map_or(false, |m| m.len() > 0)
+}
+
+/// Copy the database with its write-ahead log or rollback journal (which have the recent changes) to a temporary directory.
+/// For databases that are locked by a running application (browsers, messengers).
+/// Returns the path of the copy, which is deleted with the directory
+pub fn snapshot(path: &Path) -> Result<(tempfile::TempDir, PathBuf)> {
+    let dir = tempfile::tempdir()?;
+    let snapshot = dir.path().join("snapshot.sqlite");
+    std::fs::copy(path, &snapshot).with_context(|| format!("could not copy {}", path.display()))?;
+    for suffix in &["-wal", "-journal"] {
+        let source = companion(path, suffix);
+        if source.exists() {
+            std::fs::copy(&source, companion(&snapshot, suffix))
+                .with_context(|| format!("could not copy {}", source.display()))?;
+        }
+    }
+    Ok((dir, snapshot))
+}
+
+fn table_names(conn: &Connection) -> Result<Vec<String>> {
+    Ok(conn
+        .prepare("select name from sqlite_master where type='table'")?
+        .query_map(NO_PARAMS, |r| r.get::<_, String>(0))?
+        .filter_map(|e| e.ok())
+        .collect())
+}
+
+/// An open database, without changing the file or anything next to it
+struct Database {
+    conn: Connection,
+    tables: Vec<String>,
+    /// the copy that is read if the database is locked
+    snapshot: Option<tempfile::TempDir>,
+}
+
+fn open_database(path: &Path) -> Result<Database> {
+    if !has_wal(path) {
+        // immutable: sqlite neither takes locks nor creates a -shm or journal file, so it works on read-only media
+        // and while another program writes (the output may then be inconsistent, like when copying the file)
+        let mut uri = url::Url::from_file_path(path.canonicalize()?)
+            .map_err(|_| anyhow::format_err!("invalid path {}", path.display()))?;
+        uri.set_query(Some("immutable=1"));
+        let conn = Connection::open_with_flags(
+            uri.as_str(),
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI,
+        )?;
+        let tables = table_names(&conn)?;
+        return Ok(Database {
+            conn,
+            tables,
+            snapshot: None,
+        });
+    }
+    // the changes in the write-ahead log are only seen when reading it through the -shm index, which needs locks
+    let opened = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(anyhow::Error::from)
+        .and_then(|conn| {
+            let tables = table_names(&conn)?;
+            Ok((conn, tables))
+        });
+    match opened {
+        Ok((conn, tables)) => Ok(Database {
+            conn,
+            tables,
+            snapshot: None,
+        }),
+        Err(e) => {
+            // e.g. "database is locked" while a browser is running, or a read-only directory
+            debug!(
+                "could not open {} ({:#}), reading a snapshot",
+                path.display(),
+                e
+            );
+            let (dir, snapshot) = snapshot(path)?;
+            // opened for writing, so the write-ahead log is recovered
+            let conn = Connection::open(&snapshot)?;
+            let tables = table_names(&conn)?;
+            Ok(Database {
+                conn,
+                tables,
+                snapshot: Some(dir),
+            })
+        }
+    }
+}
+
+struct SqliteAdaptIter<'a> {
+    conn: Connection,
+    /// dropped after the connection
+    _snapshot: Option<tempfile::TempDir>,
+    tables: std::vec::IntoIter<String>,
+    outputs: VecDeque<AdaptInfo<'a>>,
+    line_prefix: String,
+    archive_recursion_depth: i32,
+    postprocess: bool,
+    config: RgaConfig,
+}
+
+impl<'a> SqliteAdaptIter<'a> {
+    fn text_output(&self, table: &str, text: String) -> AdaptInfo<'a> {
+        AdaptInfo {
+            filepath_hint: PathBuf::from(format!("{}.txt", table)),
+            is_real_file: false,
+            archive_recursion_depth: self.archive_recursion_depth + 1,
+            inp: Box::new(Cursor::new(text.into_bytes())),
+            line_prefix: self.line_prefix.clone(),
+            postprocess: self.postprocess,
+            config: self.config.clone(),
+        }
+    }
+
+    /// queue the outputs for one table: the rows as text, interleaved with the blobs to recurse into
+    fn adapt_table(&mut self, table: &str) -> Result<()> {
+        let recurse_blobs = self.config.sqlite.recurse_blobs;
+        let max_blob_len = self.config.sqlite.max_blob_len.0;
+        let mut outputs = vec![];
+        let mut text = String::new();
+        {
+            // can't use query param at that position
+            let mut sel = self.conn.prepare(&format!(
+                "select * from {}",
+                rusqlite::vtab::escape_double_quote(table)
+            ))?;
+            let col_names: Vec<String> = sel
+                .column_names()
+                .into_iter()
+                .map(|e| e.to_owned())
+                .collect();
+            let mut z = sel.query(NO_PARAMS)?;
+            let mut row_num = 0;
+            while let Some(row) = z.next()? {
+                row_num += 1;
+                if !text.is_empty() {
+                    text.push('\n');
+                }
+                text.push_str(
+                    &col_names
+                        .iter()
+                        .enumerate()
+                        .map(|(i, e)| format!("{}.{}={}", table, e, format_blob(row.get_raw(i))))
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                );
+                if !recurse_blobs {
+                    continue;
+                }
+                for (i, col_name) in col_names.iter().enumerate() {
+                    if let ValueRef::Blob(blob) = row.get_raw(i) {
+                        if blob.is_empty() {
+                            continue;
+                        }
+                        if blob.len() > max_blob_len {
+                            let truncation = Truncation::new(
+                                crate::print_bytes(max_blob_len as f64),
+                                "--rga-sqlite-max-blob-len",
+                            );
+                            text.push_str(&format!("\n{}.{}: {}", table, col_name, truncation));
+                            continue;
+                        }
+                        // flush the rows so far to keep the output in order
+                        if !text.is_empty() {
+                            outputs.push(self.text_output(table, std::mem::take(&mut text)));
+                        }
+                        outputs.push(AdaptInfo {
+                            filepath_hint: PathBuf::from(format!(
+                                "{}.{}{}",
+                                table,
+                                col_name,
+                                blob_extension(blob)
+                            )),
+                            is_real_file: false,
+                            archive_recursion_depth: self.archive_recursion_depth + 1,
+                            inp: Box::new(Cursor::new(blob.to_vec())),
+                            line_prefix: self.config.entry_prefix(
+                                &self.line_prefix,
+                                &format!("{}.{} (row {})", table, col_name, row_num),
+                            ),
+                            postprocess: self.postprocess,
+                            config: self.config.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        if !text.is_empty() {
+            outputs.push(self.text_output(table, text));
+        }
+        self.outputs.extend(outputs);
+        Ok(())
+    }
+}
+
+impl<'a> AdaptedFilesIter for SqliteAdaptIter<'a> {
+    fn next<'b>(&'b mut self) -> Option<AdaptInfo<'b>> {
+        while self.outputs.is_empty() {
+            let table = self.tables.next()?;
+            if let Err(e) = self.adapt_table(&table) {
+                warn!("could not read sqlite table {}: {:?}", table, e);
+                let text = format!("{}: [rga: could not read table: {}]", table, e);
+                let output = self.text_output(&table, text);
+                self.outputs.push_back(output);
+            }
+        }
+        self.outputs.pop_front()
+    }
+}
+
+impl FileAdapter for SqliteAdapter {
+    fn adapt<'a>(
         &self,
-        ai: AdaptInfo,
+        ai: AdaptInfo<'a>,
         _detection_reason: &FileMatcher,
-        oup: &mut dyn Write,
-    ) -> Result<()> {
+    ) -> Result<AdaptedFilesIterBox<'a>> {
         let AdaptInfo {
             is_real_file,
             filepath_hint,
             line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
             ..
         } = ai;
         if !is_real_file {
             // db is in an archive
             // todo: read to memory and then use that blob if size < max
-            writeln!(oup, "{}[rga: skipping sqlite in archive]", line_prefix,)?;
-            return Ok(());
+            return Ok(Box::new(SingleAdaptedFileAsIter::new(AdaptInfo {
+                filepath_hint: PathBuf::from("skipped.txt"),
+                is_real_file: false,
+                archive_recursion_depth,
+                inp: Box::new(Cursor::new(b"[rga: skipping sqlite in archive]".to_vec())),
+                line_prefix,
+                postprocess,
+                config,
+            })));
         }
         let inp_fname = filepath_hint;
 
-        let conn = Connection::open_with_flags(inp_fname, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
-        let tables: Vec<String> = conn
-            .prepare("select name from sqlite_master where type='table'")?
-            .query_map(NO_PARAMS, |r| r.get::<_, String>(0))?
-            .filter_map(|e| e.ok())
-            .collect();
+        let Database {
+            conn,
+            tables,
+            snapshot,
+        } = open_database(&inp_fname)?;
         debug!("db has {} tables", tables.len());
-        for table in tables {
-            // can't use query param at that position
-            let mut sel = conn.prepare(&format!(
-                "select * from {}",
-                rusqlite::vtab::escape_double_quote(&table)
-            ))?;
-            let mut z = sel.query(NO_PARAMS)?;
-            let col_names: Vec<String> = z
-                .column_names()
-                .ok_or_else(|| format_err!("no column names"))?
-                .into_iter()
-                .map(|e| e.to_owned())
-                .collect();
-            // writeln!(oup, "{}: {}", table, cols.join(", "))?;
+        let tables = filter_tables(tables, &config.sqlite)?;
+        Ok(Box::new(SqliteAdaptIter {
+            conn,
+            _snapshot: snapshot,
+            tables: tables.into_iter(),
+            outputs: VecDeque::new(),
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+        }))
+    }
 
-            // kind of shitty (lossy) output. maybe output real csv or something?
-            while let Some(row) = z.next()? {
-                writeln!(
-                    oup,
-                    "{}{}: {}",
-                    line_prefix,
-                    table,
-                    col_names
-                        .iter()
-                        .enumerate()
-                        .map(|(i, e)| format!("{}={}", e, format_blob(row.get_raw(i))))
-                        .collect::<Vec<String>>()
-                        .join(", ")
-                )?;
-            }
+    /// the blobs are only recursed into with --rga-sqlite-recurse-blobs, and tables can be skipped. The recent changes
+    /// are in the write-ahead log, which is changed without changing the modification time of the database
+    fn cache_key_extra(&self, filepath: &Path, config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        let blobs = Some(config.sqlite.max_blob_len.0).filter(|_| config.sqlite.recurse_blobs);
+        let tables = Some((&config.sqlite.include_tables, &config.sqlite.exclude_tables))
+            .filter(|(include, exclude)| !include.is_empty() || !exclude.is_empty());
+        let wal = match std::fs::metadata(companion(filepath, "-wal")) {
+            Ok(wal) if wal.len() > 0 => Some((wal.len(), wal.modified()?)),
+            _ => None,
+        };
+        if blobs.is_none() && tables.is_none() && wal.is_none() {
+            return Ok(None);
         }
-        Ok(())
+        Ok(Some(bincode::serialize(&(blobs, tables, wal))?))
     }
 }
 
@@ -135,16 +395,99 @@ mod test {
         let fname = test_data_dir().join("hello.sqlite3");
         let rd = File::open(&fname)?;
         let (a, d) = simple_adapt_info(&fname, Box::new(rd));
-        let mut res = adapter.adapt(a, &d)?;
+        let res = adapter.adapt(a, &d)?;
 
-        let mut buf = Vec::new();
-        res.read_to_end(&mut buf)?;
+        let buf = adapted_to_vec(res)?;
 
         assert_eq!(
             String::from_utf8(buf)?,
-            "PREFIX:tbl: greeting='hello', from='sqlite database!'\nPREFIX:tbl2: x=123, y=456.789\n",
+            "PREFIX:tbl.greeting='hello', tbl.from='sqlite database!'\nPREFIX:tbl2.x=123, tbl2.y=456.789\n",
         );
 
         Ok(())
     }
+
+    #[test]
+    fn exclude_tables() -> Result<()> {
+        let adapter: Box<dyn FileAdapter> = Box::new(SqliteAdapter::new());
+        let fname = test_data_dir().join("hello.sqlite3");
+        let (mut a, d) = simple_adapt_info(&fname, Box::new(File::open(&fname)?));
+        a.config.sqlite.exclude_tables = vec!["tbl".to_string()];
+        let buf = adapted_to_vec(adapter.adapt(a, &d)?)?;
+
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:tbl2.x=123, tbl2.y=456.789\n",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn recurse_blobs() -> Result<()> {
+        use flate2::{write::GzEncoder, Compression};
+
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("blobs.sqlite3");
+        {
+            let conn = Connection::open(&fname)?;
+            let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+            gz.write_all(b"compressed greeting")?;
+            conn.execute("create table payloads (id integer, data blob)", NO_PARAMS)?;
+            conn.execute(
+                "insert into payloads values (1, ?)",
+                rusqlite::params![gz.finish()?],
+            )?;
+        }
+        let adapter: Box<dyn FileAdapter> = Box::new(SqliteAdapter::new());
+        let (mut a, d) = simple_adapt_info(&fname, Box::new(File::open(&fname)?));
+        a.config.sqlite.recurse_blobs = true;
+        let buf = adapted_to_vec(adapter.adapt(a, &d)?)?;
+
+        let out = String::from_utf8(buf)?;
+        assert!(out.starts_with("PREFIX:payloads.id=1, payloads.data=[blob "));
+        assert!(out.ends_with("\nPREFIX:payloads.data (row 1): compressed greeting\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn locked_wal_database() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("app.sqlite3");
+        // like a running application: the changes are only in the write-ahead log and the database is locked
+        let writer = Connection::open(&fname)?;
+        writer.execute_batch(
+            "pragma journal_mode=wal;
+             pragma wal_autocheckpoint=0;
+             pragma locking_mode=exclusive;
+             create table messages (text text);
+             insert into messages values ('not checkpointed yet');",
+        )?;
+        assert!(has_wal(&fname));
+        let adapter: Box<dyn FileAdapter> = Box::new(SqliteAdapter::new());
+        let (a, d) = simple_adapt_info(&fname, Box::new(File::open(&fname)?));
+        let buf = adapted_to_vec(adapter.adapt(a, &d)?)?;
+
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:messages.text='not checkpointed yet'\n"
+        );
+        drop(writer);
+        Ok(())
+    }
+
+    #[test]
+    fn immutable_without_wal() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("plain.sqlite3");
+        std::fs::copy(test_data_dir().join("hello.sqlite3"), &fname)?;
+        let adapter: Box<dyn FileAdapter> = Box::new(SqliteAdapter::new());
+        let (a, d) = simple_adapt_info(&fname, Box::new(File::open(&fname)?));
+        adapted_to_vec(adapter.adapt(a, &d)?)?;
+
+        // nothing is created next to the database
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+        Ok(())
+    }
 }
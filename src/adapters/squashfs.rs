@@ -0,0 +1,126 @@
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+
+// not .snap, which is also used for other formats (e.g. jest snapshots). Snaps are matched by their signature
+static EXTENSIONS: &[&str] = &["squashfs", "sqsh", "sfs", "appimage"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "squashfs".to_owned(),
+        version: 2,
+        description: "Extracts SquashFS images (firmware, snaps, AppImages) using 7z and recurses into the contained files".to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-squashfs".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct SquashfsAdapter;
+
+impl SquashfsAdapter {
+    pub fn new() -> SquashfsAdapter {
+        SquashfsAdapter
+    }
+}
+impl GetMetadata for SquashfsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// AppImages are an ELF runtime with the squashfs image appended, which starts after the section headers
+fn elf_end(header: &[u8]) -> Result<u64> {
+    let u16_at = |pos: usize| u16::from_le_bytes([header[pos], header[pos + 1]]) as u64;
+    let (shoff, shentsize, shnum) = match header[4] {
+        // 32 bit
+        1 => (
+            u32::from_le_bytes([header[0x20], header[0x21], header[0x22], header[0x23]]) as u64,
+            u16_at(0x2e),
+            u16_at(0x30),
+        ),
+        2 => {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&header[0x28..0x30]);
+            (u64::from_le_bytes(b), u16_at(0x3a), u16_at(0x3c))
+        }
+        c => return Err(format_err!("unknown ELF class {}", c)),
+    };
+    Ok(shoff + shentsize * shnum)
+}
+
+impl FileAdapter for SquashfsAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+        } = ai;
+        let mut header = [0u8; 64];
+        inp.read_exact(&mut header)?;
+        if !header.starts_with(b"\x7fELF") {
+            // a real file is passed to 7z by its path, so only the stream needs the header back
+            let ai = AdaptInfo {
+                filepath_hint,
+                is_real_file,
+                inp: Box::new(std::io::Cursor::new(header).chain(inp)),
+                line_prefix,
+                archive_recursion_depth,
+                postprocess,
+                config,
+            };
+            return sevenzip::extract(ai, Some("squashfs"));
+        }
+        let offset = elf_end(&header)?;
+        debug!("squashfs image starts at {}", offset);
+        std::io::copy(
+            &mut (&mut inp).take(offset.saturating_sub(header.len() as u64)),
+            &mut std::io::sink(),
+        )?;
+        sevenzip::extract(
+            AdaptInfo {
+                filepath_hint: filepath_hint.with_extension("squashfs"),
+                is_real_file: false,
+                inp,
+                line_prefix,
+                archive_recursion_depth,
+                postprocess,
+                config,
+            },
+            Some("squashfs"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn appimage_offset() -> Result<()> {
+        let mut header = [0u8; 64];
+        header[..5].copy_from_slice(b"\x7fELF\x02");
+        header[0x28..0x30].copy_from_slice(&180000u64.to_le_bytes());
+        header[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+        header[0x3c..0x3e].copy_from_slice(&29u16.to_le_bytes());
+        assert_eq!(elf_end(&header)?, 180000 + 64 * 29);
+        Ok(())
+    }
+}
@@ -0,0 +1,578 @@
+//! Stata data files (.dta): the binary formats 113 to 115 and the tagged formats 117 to 119,
+//! see https://www.stata.com/help.cgi?dta
+
+use super::*;
+use anyhow::*;
+use chrono::{Duration, NaiveDate};
+use encoding_rs::WINDOWS_1252;
+use lazy_static::lazy_static;
+use spawning::read_input;
+use std::collections::HashMap;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["dta"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "stata".to_owned(),
+        version: 1,
+        description: "Outputs the variable labels, value labels and observations of Stata data files (.dta) as `name=value` pairs, with value labels applied".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-stata-dta".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct StataAdapter;
+
+impl StataAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(StataAdapter))
+    }
+}
+impl GetMetadata for StataAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+struct DtaReader<'d> {
+    data: &'d [u8],
+    pos: usize,
+    big: bool,
+    /// format version, strings are utf-8 from 118 on
+    release: u32,
+}
+
+impl<'d> DtaReader<'d> {
+    fn bytes(&mut self, len: usize) -> Result<&'d [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or_else(|| format_err!("unexpected end of file at {}", self.pos))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+    /// an unsigned integer of `len` bytes
+    fn uint(&mut self, len: usize) -> Result<u64> {
+        let bytes = self.bytes(len)?;
+        let shift = |value: u64, b: &u8| value << 8 | *b as u64;
+        Ok(if self.big {
+            bytes.iter().fold(0, shift)
+        } else {
+            bytes.iter().rev().fold(0, shift)
+        })
+    }
+    fn tag(&mut self, tag: &str) -> Result<()> {
+        if self.bytes(tag.len())? != tag.as_bytes() {
+            return Err(format_err!("expected {} at {}", tag, self.pos - tag.len()));
+        }
+        Ok(())
+    }
+    fn text(&self, bytes: &[u8]) -> String {
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        let bytes = &bytes[..end];
+        match std::str::from_utf8(bytes) {
+            Ok(s) => s.to_string(),
+            // latin1 before 118, but files written on windows are often cp1252
+            Err(_) => WINDOWS_1252
+                .decode_without_bom_handling(bytes)
+                .0
+                .into_owned(),
+        }
+    }
+    /// a fixed length, zero terminated string
+    fn string(&mut self, len: usize) -> Result<String> {
+        let bytes = self.bytes(len)?;
+        Ok(self.text(bytes))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum VarType {
+    Str(usize),
+    StrL,
+    Double,
+    Float,
+    Long,
+    Int,
+    Byte,
+}
+
+impl VarType {
+    /// the type codes of the binary formats
+    fn binary(code: u8) -> Result<VarType> {
+        Ok(match code {
+            1..=244 => VarType::Str(code as usize),
+            251 => VarType::Byte,
+            252 => VarType::Int,
+            253 => VarType::Long,
+            254 => VarType::Float,
+            255 => VarType::Double,
+            _ => return Err(format_err!("unknown variable type {}", code)),
+        })
+    }
+    /// the type codes of the tagged formats
+    fn tagged(code: u64) -> Result<VarType> {
+        Ok(match code {
+            1..=2045 => VarType::Str(code as usize),
+            32768 => VarType::StrL,
+            65526 => VarType::Double,
+            65527 => VarType::Float,
+            65528 => VarType::Long,
+            65529 => VarType::Int,
+            65530 => VarType::Byte,
+            _ => return Err(format_err!("unknown variable type {}", code)),
+        })
+    }
+    fn size(self) -> usize {
+        match self {
+            VarType::Str(len) => len,
+            VarType::StrL | VarType::Double => 8,
+            VarType::Float | VarType::Long => 4,
+            VarType::Int => 2,
+            VarType::Byte => 1,
+        }
+    }
+}
+
+struct Variable {
+    name: String,
+    var_type: VarType,
+    format: String,
+    value_label_name: String,
+    label: String,
+}
+
+struct Dataset {
+    label: String,
+    variables: Vec<Variable>,
+    observations: usize,
+    /// offset of the data
+    data: usize,
+    /// (v, o) -> contents of the long strings (strL)
+    strls: HashMap<(u64, u64), String>,
+    /// label name -> (value, label)
+    value_labels: HashMap<String, Vec<(i64, String)>>,
+}
+
+enum Value {
+    Number(f64),
+    Integer(i64),
+    Text(String),
+    /// reference (v, o) to a long string
+    StrL(u64, u64),
+    Missing,
+}
+
+fn read_binary(r: &mut DtaReader) -> Result<Dataset> {
+    let _filetype = r.bytes(2)?;
+    let count = r.uint(2)? as usize;
+    let observations = r.uint(4)? as usize;
+    let label = r.string(81)?;
+    let _timestamp = r.bytes(18)?;
+    let mut types = vec![];
+    for &code in r.bytes(count)? {
+        types.push(VarType::binary(code)?);
+    }
+    let mut names = vec![];
+    for _ in 0..count {
+        names.push(r.string(33)?);
+    }
+    let _sortlist = r.bytes(2 * (count + 1))?;
+    let format_len = if r.release >= 114 { 49 } else { 12 };
+    let mut formats = vec![];
+    for _ in 0..count {
+        formats.push(r.string(format_len)?);
+    }
+    let mut label_names = vec![];
+    for _ in 0..count {
+        label_names.push(r.string(33)?);
+    }
+    let mut variables = vec![];
+    for (((name, var_type), format), value_label_name) in
+        names.into_iter().zip(types).zip(formats).zip(label_names)
+    {
+        variables.push(Variable {
+            name,
+            var_type,
+            format,
+            value_label_name,
+            label: r.string(81)?,
+        });
+    }
+    // expansion fields
+    loop {
+        let data_type = r.bytes(1)?[0];
+        let len = r.uint(4)? as usize;
+        if data_type == 0 && len == 0 {
+            break;
+        }
+        r.bytes(len)?;
+    }
+    let data = r.pos;
+    let row_len: usize = variables.iter().map(|v| v.var_type.size()).sum();
+    r.pos = data.saturating_add(row_len.saturating_mul(observations));
+    let mut value_labels = HashMap::new();
+    while r.pos.saturating_add(4) <= r.data.len() {
+        let len = r.uint(4)? as usize;
+        let name = r.string(33)?;
+        r.bytes(3)?;
+        let table = r.bytes(len)?;
+        value_labels.insert(name, value_label_table(r, table)?);
+    }
+    Ok(Dataset {
+        label,
+        variables,
+        observations,
+        data,
+        strls: HashMap::new(),
+        value_labels,
+    })
+}
+
+/// a value label table: the number of entries, the length of the text,
+/// then the offsets of the labels in the text, the values and the text
+fn value_label_table(r: &DtaReader, table: &[u8]) -> Result<Vec<(i64, String)>> {
+    let mut t = DtaReader {
+        data: table,
+        pos: 0,
+        big: r.big,
+        release: r.release,
+    };
+    let count = t.uint(4)? as usize;
+    let text_len = t.uint(4)? as usize;
+    let mut offsets = vec![];
+    for _ in 0..count {
+        offsets.push(t.uint(4)? as usize);
+    }
+    let mut values = vec![];
+    for _ in 0..count {
+        values.push(t.uint(4)? as u32 as i32 as i64);
+    }
+    let text = t.bytes(text_len)?;
+    Ok(values
+        .into_iter()
+        .zip(offsets)
+        .map(|(value, offset)| (value, t.text(text.get(offset..).unwrap_or_default())))
+        .collect())
+}
+
+fn read_tagged(r: &mut DtaReader) -> Result<Dataset> {
+    r.tag("<byteorder>")?;
+    r.big = r.bytes(3)? == b"MSF";
+    r.tag("</byteorder><K>")?;
+    let count = r.uint(if r.release >= 119 { 4 } else { 2 })? as usize;
+    r.tag("</K><N>")?;
+    let observations = r.uint(if r.release >= 118 { 8 } else { 4 })? as usize;
+    r.tag("</N><label>")?;
+    let label_len = r.uint(if r.release >= 118 { 2 } else { 1 })? as usize;
+    let label = r.string(label_len)?;
+    r.tag("</label><timestamp>")?;
+    let timestamp_len = r.uint(1)? as usize;
+    r.bytes(timestamp_len)?;
+    r.tag("</timestamp></header><map>")?;
+    // offsets of the sections
+    let mut map = vec![];
+    for _ in 0..14 {
+        map.push(r.uint(8)? as usize);
+    }
+    r.tag("</map><variable_types>")?;
+    let mut types = vec![];
+    for _ in 0..count {
+        types.push(VarType::tagged(r.uint(2)?)?);
+    }
+    // names are 32 characters, utf-8 from 118 on
+    let name_len = if r.release >= 118 { 129 } else { 33 };
+    r.tag("</variable_types><varnames>")?;
+    let mut names = vec![];
+    for _ in 0..count {
+        names.push(r.string(name_len)?);
+    }
+    r.tag("</varnames><sortlist>")?;
+    r.bytes((count + 1) * if r.release >= 119 { 4 } else { 2 })?;
+    r.tag("</sortlist><formats>")?;
+    let mut formats = vec![];
+    for _ in 0..count {
+        formats.push(r.string(if r.release >= 118 { 57 } else { 49 })?);
+    }
+    r.tag("</formats><value_label_names>")?;
+    let mut label_names = vec![];
+    for _ in 0..count {
+        label_names.push(r.string(name_len)?);
+    }
+    r.tag("</value_label_names><variable_labels>")?;
+    let mut variables = vec![];
+    for (((name, var_type), format), value_label_name) in
+        names.into_iter().zip(types).zip(formats).zip(label_names)
+    {
+        variables.push(Variable {
+            name,
+            var_type,
+            format,
+            value_label_name,
+            label: r.string(if r.release >= 118 { 321 } else { 81 })?,
+        });
+    }
+
+    let mut strls = HashMap::new();
+    r.pos = map[10];
+    r.tag("<strls>")?;
+    while r.data.get(r.pos..r.pos.saturating_add(3)) == Some(b"GSO") {
+        r.pos += 3;
+        let v = r.uint(4)?;
+        let o = r.uint(if r.release >= 118 { 8 } else { 4 })?;
+        let _binary = r.bytes(1)?[0] == 129;
+        let len = r.uint(4)? as usize;
+        let contents = r.bytes(len)?;
+        strls.insert((v, o), r.text(contents));
+    }
+    let mut value_labels = HashMap::new();
+    r.pos = map[11];
+    r.tag("<value_labels>")?;
+    while r.data.get(r.pos..r.pos.saturating_add(5)) == Some(b"<lbl>") {
+        r.pos += 5;
+        let len = r.uint(4)? as usize;
+        let name = r.string(name_len)?;
+        r.bytes(3)?;
+        let table = r.bytes(len)?;
+        value_labels.insert(name, value_label_table(r, table)?);
+        r.tag("</lbl>")?;
+    }
+    Ok(Dataset {
+        label,
+        variables,
+        observations,
+        data: map[9].saturating_add("<data>".len()),
+        strls,
+        value_labels,
+    })
+}
+
+fn read_value(r: &mut DtaReader, var_type: VarType) -> Result<Value> {
+    // values above these are the missing values (., .a, ... .z)
+    Ok(match var_type {
+        VarType::Str(len) => Value::Text(r.string(len)?),
+        VarType::StrL => {
+            if r.release >= 118 {
+                // 2 (3 in 119) bytes v and 6 (5) bytes o, in the byte order of the file
+                let v_bits = if r.release >= 119 { 24 } else { 16 };
+                let raw = r.uint(8)?;
+                if r.big {
+                    Value::StrL(raw >> (64 - v_bits), raw & (u64::MAX >> v_bits))
+                } else {
+                    Value::StrL(raw & ((1 << v_bits) - 1), raw >> v_bits)
+                }
+            } else {
+                Value::StrL(r.uint(4)?, r.uint(4)?)
+            }
+        }
+        VarType::Byte => match r.uint(1)? as u8 as i8 {
+            v if v > 100 => Value::Missing,
+            v => Value::Integer(v as i64),
+        },
+        VarType::Int => match r.uint(2)? as u16 as i16 {
+            v if v > 32740 => Value::Missing,
+            v => Value::Integer(v as i64),
+        },
+        VarType::Long => match r.uint(4)? as u32 as i32 {
+            v if v > 2_147_483_620 => Value::Missing,
+            v => Value::Integer(v as i64),
+        },
+        VarType::Float => match f32::from_bits(r.uint(4)? as u32) {
+            v if v.is_nan() || v > 1.701e38 => Value::Missing,
+            v => Value::Number(v as f64),
+        },
+        VarType::Double => match f64::from_bits(r.uint(8)?) {
+            v if v.is_nan() || v > 8.988e307 => Value::Missing,
+            v => Value::Number(v),
+        },
+    })
+}
+
+/// dates are days (%td) or milliseconds (%tc) since 1960-01-01
+fn format_date(value: f64, format: &str) -> Option<String> {
+    let epoch = NaiveDate::from_ymd(1960, 1, 1).and_hms(0, 0, 0);
+    if value.is_nan() || value.abs() >= 1e15 {
+        return None;
+    }
+    if format.starts_with("%td") || format.starts_with("%d") {
+        let date = epoch.checked_add_signed(Duration::days(value as i64))?;
+        Some(date.format("%Y-%m-%d").to_string())
+    } else if format.starts_with("%tc") || format.starts_with("%tC") {
+        let time = epoch.checked_add_signed(Duration::milliseconds(value as i64))?;
+        Some(time.format("%Y-%m-%d %H:%M:%S").to_string())
+    } else {
+        None
+    }
+}
+
+fn adapt_dta(data: &[u8], oup: &mut dyn Write) -> Result<()> {
+    let mut r = DtaReader {
+        data,
+        pos: 0,
+        big: false,
+        release: 0,
+    };
+    let dataset = if data.starts_with(b"<stata_dta><header><release>") {
+        r.pos = 28;
+        let release = r.string(3)?;
+        r.release = release.parse()?;
+        r.tag("</release>")?;
+        read_tagged(&mut r)?
+    } else {
+        let header = data
+            .get(0..2)
+            .ok_or_else(|| format_err!("stata header too short"))?;
+        if !(113..=115).contains(&header[0]) {
+            return Err(format_err!("unsupported stata file format {}", header[0]));
+        }
+        r.release = header[0] as u32;
+        r.big = header[1] == 1;
+        r.pos = 2;
+        read_binary(&mut r)?
+    };
+
+    if !dataset.label.is_empty() {
+        writeln!(oup, "label: {}", dataset.label)?;
+    }
+    let no_labels = vec![];
+    for v in &dataset.variables {
+        if v.label.is_empty() {
+            writeln!(oup, "variable {}", v.name)?;
+        } else {
+            writeln!(oup, "variable {}: {}", v.name, v.label)?;
+        }
+        let value_labels = dataset
+            .value_labels
+            .get(&v.value_label_name)
+            .unwrap_or(&no_labels);
+        for (value, label) in value_labels {
+            writeln!(oup, "value {}={}: {}", v.name, value, label)?;
+        }
+    }
+    r.pos = dataset.data;
+    for _ in 0..dataset.observations {
+        let mut first = true;
+        for v in &dataset.variables {
+            let value = read_value(&mut r, v.var_type)?;
+            let mut label = None;
+            let formatted = match value {
+                Value::Missing => "".to_string(),
+                Value::StrL(v, o) => dataset.strls.get(&(v, o)).cloned().unwrap_or_default(),
+                Value::Text(text) => text,
+                Value::Integer(i) => {
+                    label = dataset
+                        .value_labels
+                        .get(&v.value_label_name)
+                        .and_then(|labels| labels.iter().find(|(value, _)| *value == i))
+                        .map(|(_, label)| label);
+                    format_date(i as f64, &v.format).unwrap_or_else(|| i.to_string())
+                }
+                Value::Number(n) => format_date(n, &v.format).unwrap_or_else(|| n.to_string()),
+            };
+            if !first {
+                write!(oup, ", ")?;
+            }
+            first = false;
+            write!(oup, "{}={}", v.name, formatted)?;
+            if let Some(label) = label {
+                write!(oup, " ({})", label)?;
+            }
+        }
+        writeln!(oup)?;
+    }
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for StataAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            filepath_hint,
+            is_real_file,
+            ..
+        } = ai;
+        let data = read_input(&filepath_hint, is_real_file, &mut inp)?;
+        adapt_dta(&data, oup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixed(s: &str, len: usize) -> Vec<u8> {
+        let mut out = s.as_bytes().to_vec();
+        out.resize(len, 0);
+        out
+    }
+
+    #[test]
+    fn format_114() -> Result<()> {
+        let mut dta = vec![114, 2, 1, 0];
+        dta.extend(&3u16.to_le_bytes());
+        dta.extend(&2u32.to_le_bytes());
+        dta.extend(fixed("Household survey", 81));
+        dta.extend(fixed("16 Oct 2020 12:00", 18));
+        dta.extend(&[253, 251, 7]);
+        for name in &["id", "sex", "city"] {
+            dta.extend(fixed(name, 33));
+        }
+        dta.extend(vec![0; 8]);
+        for format in &["%12.0g", "%8.0g", "%9s"] {
+            dta.extend(fixed(format, 49));
+        }
+        for label_name in &["", "sexlbl", ""] {
+            dta.extend(fixed(label_name, 33));
+        }
+        for label in &["", "Respondent sex", "City of residence"] {
+            dta.extend(fixed(label, 81));
+        }
+        dta.extend(&[0, 0, 0, 0, 0]);
+        dta.extend(&1i32.to_le_bytes());
+        dta.push(2);
+        dta.extend(fixed("Vilnius", 7));
+        dta.extend(&2i32.to_le_bytes());
+        dta.push(101);
+        dta.extend(fixed("Kaunas", 7));
+
+        let mut table: Vec<u8> = vec![];
+        for i in &[2i32, 12, 0, 5, 1, 2] {
+            table.extend(&i.to_le_bytes());
+        }
+        table.extend(b"Male\0Female\0");
+        dta.extend(&(table.len() as i32).to_le_bytes());
+        dta.extend(fixed("sexlbl", 33));
+        dta.extend(&[0, 0, 0]);
+        dta.extend(table);
+
+        let mut oup = Vec::new();
+        adapt_dta(&dta, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "label: Household survey\n\
+             variable id\n\
+             variable sex: Respondent sex\n\
+             value sex=1: Male\n\
+             value sex=2: Female\n\
+             variable city: City of residence\n\
+             id=1, sex=2 (Female), city=Vilnius\n\
+             id=2, sex=, city=Kaunas\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "strings".to_owned(),
+        version: 1,
+        description: "Extracts printable ASCII / UTF-8 and UTF-16 strings from binary files. Only used as a fallback for files without a matching adapter, see --rga-strings-fallback".to_owned(),
+        recurses: false,
+        // never matched directly
+        fast_matchers: vec![],
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: false,
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct StringsAdapter;
+
+impl StringsAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(StringsAdapter))
+    }
+}
+impl GetMetadata for StringsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn is_printable(b: u8) -> bool {
+    (0x20..0x7f).contains(&b) || b == b'\t'
+}
+
+/// streaming version of `strings`, finds runs of at least min_len printable characters
+pub struct StringsExtractor {
+    min_len: usize,
+    /// current run of printable ascii and (possibly) utf8 bytes
+    text: Vec<u8>,
+    /// current runs of utf-16le encoded ascii, for both alignments
+    utf16: [Vec<u8>; 2],
+    prev: Option<u8>,
+    pos: usize,
+}
+
+impl StringsExtractor {
+    pub fn new(min_len: usize) -> StringsExtractor {
+        StringsExtractor {
+            min_len,
+            text: vec![],
+            utf16: [vec![], vec![]],
+            prev: None,
+            pos: 0,
+        }
+    }
+
+    fn flush_text(&mut self, oup: &mut dyn Write) -> Result<()> {
+        if self.text.len() >= self.min_len {
+            // bytes >= 0x80 that are not valid utf8 split the run
+            for s in String::from_utf8_lossy(&self.text).split('\u{FFFD}') {
+                if s.chars().count() >= self.min_len {
+                    writeln!(oup, "{}", s)?;
+                }
+            }
+        }
+        self.text.clear();
+        Ok(())
+    }
+
+    fn flush_utf16(&mut self, alignment: usize, oup: &mut dyn Write) -> Result<()> {
+        let run = &mut self.utf16[alignment];
+        if run.len() >= self.min_len {
+            oup.write_all(run)?;
+            writeln!(oup)?;
+        }
+        run.clear();
+        Ok(())
+    }
+
+    pub fn push(&mut self, b: u8, oup: &mut dyn Write) -> Result<()> {
+        if is_printable(b) || b >= 0x80 {
+            self.text.push(b);
+        } else {
+            self.flush_text(oup)?;
+        }
+        if let Some(prev) = self.prev {
+            // the pair (prev, b) is one utf-16 code unit for the alignment of prev
+            let alignment = (self.pos - 1) % 2;
+            if is_printable(prev) && b == 0 {
+                self.utf16[alignment].push(prev);
+            } else {
+                self.flush_utf16(alignment, oup)?;
+            }
+        }
+        self.prev = Some(b);
+        self.pos += 1;
+        Ok(())
+    }
+
+    pub fn finish(&mut self, oup: &mut dyn Write) -> Result<()> {
+        self.flush_text(oup)?;
+        self.flush_utf16(0, oup)?;
+        self.flush_utf16(1, oup)?;
+        Ok(())
+    }
+}
+
+impl WritingFileAdapterTrait for StringsAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp, config, ..
+        } = ai;
+        let mut extractor = StringsExtractor::new(config.strings.min_len.0);
+        let mut buf = vec![0u8; 1 << 16];
+        loop {
+            let n = inp.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            for &b in &buf[..n] {
+                extractor.push(b, oup)?;
+            }
+        }
+        extractor.finish(oup)
+    }
+
+    fn cache_key_extra(&self, _filepath: &Path, config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        // shorter strings are skipped
+        Ok(Some(config.strings.min_len.0.to_string().into_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ascii_and_utf16() -> Result<()> {
+        let mut data = b"\x00\x01firmware v1.2\x00ab\x00\xff".to_vec();
+        data.extend("Grüße aus".as_bytes());
+        data.extend(b"\x00\x00");
+        data.extend(
+            "wide\u{1}"
+                .encode_utf16()
+                .flat_map(|c| c.to_le_bytes().to_vec()),
+        );
+        let mut oup = Vec::new();
+        let mut extractor = StringsExtractor::new(4);
+        for &b in &data {
+            extractor.push(b, &mut oup)?;
+        }
+        extractor.finish(&mut oup)?;
+        assert_eq!(String::from_utf8(oup)?, "firmware v1.2\nGrüße aus\nwide\n");
+        Ok(())
+    }
+}
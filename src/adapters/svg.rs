@@ -0,0 +1,162 @@
+use super::*;
+use anyhow::*;
+use enex::unescape;
+use lazy_static::lazy_static;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["svg", "svgz"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "svg".to_owned(),
+        version: 1,
+        description: "Outputs the text labels (<text>, <tspan>) and titles / descriptions of SVG images (also gzipped .svgz) instead of the path data, one per line".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("image/svg+xml".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct SvgAdapter;
+
+impl SvgAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(SvgAdapter))
+    }
+}
+impl GetMetadata for SvgAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the element name of a tag without namespace prefix (`svg:text` -> `text`)
+fn local_name(tag: &str) -> &str {
+    let name = tag
+        .split(|c: char| c.is_whitespace() || c == '/')
+        .next()
+        .unwrap_or("");
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// the text of all text, title and desc elements, in document order.
+/// Titles and descriptions are prefixed with `title: ` and `desc: `
+fn svg_text(xml: &str) -> Vec<String> {
+    let mut lines = vec![];
+    // the element we are collecting the text of, its nesting depth and the text so far
+    let mut current: Option<(&str, usize, String)> = None;
+    let mut rest = xml;
+    while let Some(lt) = rest.find('<') {
+        if let Some((_, _, text)) = &mut current {
+            text.push_str(&unescape(&rest[..lt]));
+            text.push(' ');
+        }
+        rest = &rest[lt..];
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map(|e| &rest[e + 3..]).unwrap_or("");
+            continue;
+        }
+        if rest.starts_with("<![CDATA[") {
+            let end = rest.find("]]>").map(|e| e + 3).unwrap_or(rest.len());
+            if let Some((_, _, text)) = &mut current {
+                text.push_str(&unescape(&rest[..end]));
+            }
+            rest = &rest[end..];
+            continue;
+        }
+        let gt = match rest.find('>') {
+            Some(gt) => gt,
+            None => break,
+        };
+        let tag = &rest[1..gt];
+        rest = &rest[gt + 1..];
+        if tag.starts_with('/') {
+            let name = local_name(&tag[1..]);
+            match &mut current {
+                Some((kind, depth, _)) if *kind == name && *depth > 0 => *depth -= 1,
+                Some((kind, _, text)) if *kind == name => {
+                    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                    if !text.is_empty() {
+                        lines.push(match *kind {
+                            "text" => text,
+                            kind => format!("{}: {}", kind, text),
+                        });
+                    }
+                    current = None;
+                }
+                _ => {}
+            }
+        } else if !tag.ends_with('/') && !tag.starts_with('?') && !tag.starts_with('!') {
+            let name = local_name(tag);
+            match &mut current {
+                Some((kind, depth, _)) if *kind == name => *depth += 1,
+                Some(_) => {}
+                None if ["text", "title", "desc"].contains(&name) => {
+                    current = Some((name, 0, String::new()))
+                }
+                None => {}
+            }
+        }
+    }
+    lines
+}
+
+impl WritingFileAdapterTrait for SvgAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo { mut inp, .. } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data)?;
+        if data.starts_with(&[0x1f, 0x8b]) {
+            // .svgz
+            let mut decompressed = Vec::new();
+            flate2::read::GzDecoder::new(&data[..]).read_to_end(&mut decompressed)?;
+            data = decompressed;
+        }
+        for line in svg_text(&String::from_utf8_lossy(&data)) {
+            writeln!(oup, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn labels() {
+        let svg = r#"<?xml version="1.0"?>
+<svg xmlns="http://www.w3.org/2000/svg" xmlns:svg="http://www.w3.org/2000/svg">
+  <title>Architecture</title>
+  <desc>Services &amp; queues</desc>
+  <!-- <text>commented out</text> -->
+  <g><path d="M 10 10 L 20 20"/>
+    <text x="10" y="20">Load <tspan font-weight="bold">Balancer</tspan></text>
+    <svg:text><tspan x="0">multi</tspan><tspan x="0" dy="1em">line</tspan></svg:text>
+    <text><![CDATA[a < b]]></text>
+    <text/>
+  </g>
+</svg>"#;
+        assert_eq!(
+            svg_text(svg),
+            vec![
+                "title: Architecture",
+                "desc: Services & queues",
+                "Load Balancer",
+                "multi line",
+                "a < b",
+            ]
+        );
+    }
+}
@@ -1,7 +1,10 @@
+use super::zip::entry_metadata_line;
 use super::*;
+use crate::entry_names::decode_entry_name;
 use crate::{preproc::rga_preproc, print_bytes};
 use ::tar::EntryType::Regular;
 use anyhow::*;
+use chrono::NaiveDateTime;
 use lazy_static::lazy_static;
 use log::*;
 use std::path::PathBuf;
@@ -12,7 +15,7 @@ static EXTENSIONS: &[&str] = &["tar"];
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "tar".to_owned(),
-        version: 1,
+        version: 2,
         description: "Reads a tar file as a stream and recurses down into its contents".to_owned(),
         recurses: true,
         fast_matchers: EXTENSIONS
@@ -57,7 +60,12 @@ impl WritingFileAdapterTrait for TarAdapter {
         for entry in archive.entries()? {
             let mut file = entry?;
             if Regular == file.header().entry_type() {
-                let path = PathBuf::from(file.path()?.to_owned());
+                // not file.path(), which fails for names that are not UTF-8 on windows
+                let path = PathBuf::from(decode_entry_name(
+                    &file.path_bytes(),
+                    &config.entry_name_encoding,
+                    None,
+                ));
                 debug!(
                     "{}|{}: {}",
                     filepath_hint.display(),
@@ -65,6 +73,32 @@ impl WritingFileAdapterTrait for TarAdapter {
                     print_bytes(file.header().size()? as f64),
                 );
                 let line_prefix = &format!("{}{}: ", line_prefix, path.display());
+                if config.archive_metadata {
+                    let comment = match file.pax_extensions()? {
+                        Some(extensions) => extensions
+                            .filter_map(|e| e.ok())
+                            .find(|e| e.key() == Ok("comment"))
+                            .and_then(|e| e.value().ok().map(|v| v.to_string())),
+                        None => None,
+                    };
+                    let header = file.header();
+                    let modified = header
+                        .mtime()
+                        .ok()
+                        .and_then(|t| NaiveDateTime::from_timestamp_opt(t as i64, 0))
+                        .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string());
+                    write!(
+                        oup,
+                        "{}{}",
+                        line_prefix,
+                        entry_metadata_line(
+                            header.size()?,
+                            modified,
+                            header.mode().ok(),
+                            comment.as_deref().unwrap_or("")
+                        )
+                    )?;
+                }
                 let ai2: AdaptInfo = AdaptInfo {
                     filepath_hint: path,
                     is_real_file: false,
@@ -0,0 +1,291 @@
+use super::*;
+use anyhow::*;
+use chrono::NaiveDateTime;
+use lazy_static::lazy_static;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["torrent"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "torrent".to_owned(),
+        version: 1,
+        description:
+            "Outputs the name, trackers, comment and file list of BitTorrent metainfo files"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-bittorrent".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct TorrentAdapter;
+
+impl TorrentAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(TorrentAdapter))
+    }
+}
+impl GetMetadata for TorrentAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[derive(Debug)]
+pub enum Bencode {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Bencode>),
+    /// keys in file order
+    Dict(Vec<(Vec<u8>, Bencode)>),
+}
+
+impl Bencode {
+    pub fn get(&self, key: &str) -> Option<&Bencode> {
+        match self {
+            Bencode::Dict(entries) => entries
+                .iter()
+                .find(|(k, _)| k == key.as_bytes())
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+    pub fn str(&self) -> Option<String> {
+        match self {
+            Bencode::Bytes(b) => Some(String::from_utf8_lossy(b).into_owned()),
+            _ => None,
+        }
+    }
+    pub fn int(&self) -> Option<i64> {
+        match self {
+            Bencode::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+    pub fn list(&self) -> &[Bencode] {
+        match self {
+            Bencode::List(l) => l,
+            _ => &[],
+        }
+    }
+}
+
+fn parse_value(data: &[u8], pos: &mut usize, depth: usize) -> Result<Bencode> {
+    if depth > 64 {
+        return Err(format_err!("bencode nested too deeply"));
+    }
+    let find = |pos: usize, b: u8| {
+        data[pos..]
+            .iter()
+            .position(|c| *c == b)
+            .map(|i| pos + i)
+            .ok_or_else(|| format_err!("unterminated bencode value at {}", pos))
+    };
+    match data.get(*pos) {
+        Some(b'i') => {
+            let end = find(*pos, b'e')?;
+            let i = std::str::from_utf8(&data[*pos + 1..end])?.parse()?;
+            *pos = end + 1;
+            Ok(Bencode::Int(i))
+        }
+        Some(b'l') => {
+            *pos += 1;
+            let mut list = vec![];
+            while data.get(*pos) != Some(&b'e') {
+                list.push(parse_value(data, pos, depth + 1)?);
+            }
+            *pos += 1;
+            Ok(Bencode::List(list))
+        }
+        Some(b'd') => {
+            *pos += 1;
+            let mut dict = vec![];
+            while data.get(*pos) != Some(&b'e') {
+                let key = match parse_value(data, pos, depth + 1)? {
+                    Bencode::Bytes(key) => key,
+                    _ => return Err(format_err!("dictionary key is not a string")),
+                };
+                dict.push((key, parse_value(data, pos, depth + 1)?));
+            }
+            *pos += 1;
+            Ok(Bencode::Dict(dict))
+        }
+        Some(b'0'..=b'9') => {
+            let colon = find(*pos, b':')?;
+            let len: usize = std::str::from_utf8(&data[*pos..colon])?.parse()?;
+            let end = (colon + 1)
+                .checked_add(len)
+                .ok_or_else(|| format_err!("bencode string out of bounds"))?;
+            let bytes = data
+                .get(colon + 1..end)
+                .ok_or_else(|| format_err!("bencode string out of bounds"))?;
+            *pos = end;
+            Ok(Bencode::Bytes(bytes.to_vec()))
+        }
+        Some(c) => Err(format_err!(
+            "invalid bencode value {:?} at {}",
+            *c as char,
+            pos
+        )),
+        None => Err(format_err!("unexpected end of bencode data")),
+    }
+}
+
+pub fn parse_bencode(data: &[u8]) -> Result<Bencode> {
+    parse_value(data, &mut 0, 0)
+}
+
+fn format_size(len: i64) -> String {
+    format!("{} bytes", len)
+}
+
+/// the files of a v2 file tree: nested dicts of path components, files have an empty key
+fn file_tree(tree: &Bencode, path: &str, oup: &mut dyn Write) -> Result<()> {
+    if let Bencode::Dict(entries) = tree {
+        for (name, value) in entries {
+            if name.is_empty() {
+                let len = value.get("length").and_then(Bencode::int).unwrap_or(0);
+                writeln!(oup, "file: {} ({})", path, format_size(len))?;
+            } else {
+                let name = String::from_utf8_lossy(name);
+                let path = if path.is_empty() {
+                    name.into_owned()
+                } else {
+                    format!("{}/{}", path, name)
+                };
+                file_tree(value, &path, oup)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn adapt_torrent(torrent: &Bencode, oup: &mut dyn Write) -> Result<()> {
+    let info = torrent
+        .get("info")
+        .ok_or_else(|| format_err!("not a torrent file (no info dictionary)"))?;
+    // the utf-8 variants are set by some clients when the encoding of the plain ones is different
+    let text = |d: &Bencode, key: &str| {
+        d.get(&format!("{}.utf-8", key))
+            .or_else(|| d.get(key))
+            .and_then(Bencode::str)
+    };
+    let name = text(info, "name").unwrap_or_default();
+    writeln!(oup, "name: {}", name)?;
+    let mut trackers = vec![];
+    trackers.extend(torrent.get("announce").and_then(Bencode::str));
+    for tier in torrent
+        .get("announce-list")
+        .map(Bencode::list)
+        .unwrap_or(&[])
+    {
+        trackers.extend(tier.list().iter().filter_map(Bencode::str));
+    }
+    let mut seen = std::collections::HashSet::new();
+    for tracker in trackers.into_iter().filter(|t| seen.insert(t.clone())) {
+        writeln!(oup, "announce: {}", tracker)?;
+    }
+    match torrent.get("url-list") {
+        Some(Bencode::List(seeds)) => {
+            for seed in seeds.iter().filter_map(Bencode::str) {
+                writeln!(oup, "web seed: {}", seed)?;
+            }
+        }
+        Some(seed) => {
+            if let Some(seed) = seed.str() {
+                writeln!(oup, "web seed: {}", seed)?;
+            }
+        }
+        None => {}
+    }
+    if let Some(comment) = text(torrent, "comment") {
+        writeln!(oup, "comment: {}", comment.replace('\n', " "))?;
+    }
+    if let Some(created_by) = text(torrent, "created by") {
+        writeln!(oup, "created by: {}", created_by)?;
+    }
+    let creation_date = torrent
+        .get("creation date")
+        .and_then(Bencode::int)
+        .and_then(|t| NaiveDateTime::from_timestamp_opt(t, 0));
+    if let Some(date) = creation_date {
+        writeln!(oup, "creation date: {}", date.format("%Y-%m-%d %H:%M:%S"))?;
+    }
+    if let Some(files) = info.get("files") {
+        for file in files.list() {
+            let path = file
+                .get("path.utf-8")
+                .or_else(|| file.get("path"))
+                .map(Bencode::list)
+                .unwrap_or(&[])
+                .iter()
+                .filter_map(Bencode::str)
+                .collect::<Vec<_>>()
+                .join("/");
+            let len = file.get("length").and_then(Bencode::int).unwrap_or(0);
+            writeln!(oup, "file: {}/{} ({})", name, path, format_size(len))?;
+        }
+    } else if let Some(tree) = info.get("file tree") {
+        file_tree(tree, "", oup)?;
+    } else if let Some(len) = info.get("length").and_then(Bencode::int) {
+        writeln!(oup, "file: {} ({})", name, format_size(len))?;
+    }
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for TorrentAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo { mut inp, .. } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data)?;
+        adapt_torrent(&parse_bencode(&data)?, oup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn multi_file() -> Result<()> {
+        let torrent = parse_bencode(
+            b"d8:announce30:udp://tracker.example.org:133713:announce-listll30:udp://tracker.example.org:1337el\
+              26:https://backup.example.orgee7:comment11:Debian ISOs10:created by13:mktorrent 1.113:creation datei1602849600e\
+              4:infod5:filesld6:lengthi3000000e4:pathl3:iso5:a.isoeed6:lengthi42e4:pathl9:SHA256SUMeee\
+              4:name6:debian12:piece lengthi262144e6:pieces0:ee",
+        )?;
+        let mut oup = Vec::new();
+        adapt_torrent(&torrent, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "name: debian\n\
+             announce: udp://tracker.example.org:1337\n\
+             announce: https://backup.example.org\n\
+             comment: Debian ISOs\n\
+             created by: mktorrent 1.1\n\
+             creation date: 2020-10-16 12:00:00\n\
+             file: debian/iso/a.iso (3000000 bytes)\n\
+             file: debian/SHA256SUM (42 bytes)\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn string_length_overflow() {
+        assert!(parse_bencode(b"18446744073709551615:abc").is_err());
+    }
+}
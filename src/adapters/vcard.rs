@@ -0,0 +1,255 @@
+use super::*;
+use anyhow::*;
+use chrono::{NaiveDate, NaiveDateTime};
+use lazy_static::lazy_static;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["vcf", "vcard", "ics", "ical", "ifb"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "vcard".to_owned(),
+        version: 1,
+        description: "Outputs the properties of vCard contacts and iCalendar events one per line, with folded lines joined and quoted-printable values decoded".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("text/vcard".to_owned()),
+            FileMatcher::MimeType("text/x-vcard".to_owned()),
+            FileMatcher::MimeType("text/calendar".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct VcardAdapter;
+
+impl VcardAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(VcardAdapter))
+    }
+}
+impl GetMetadata for VcardAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn is_quoted_printable(line: &str) -> bool {
+    line.split(':')
+        .next()
+        .map(|p| p.to_ascii_uppercase().contains("QUOTED-PRINTABLE"))
+        .unwrap_or(false)
+}
+
+/// join folded lines (continuation lines start with a space or tab, or
+/// the previous quoted-printable line ends with a soft line break)
+fn unfold(data: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in data.lines() {
+        let line = line.trim_end_matches('\r');
+        match lines.last_mut() {
+            Some(prev) if line.starts_with(' ') || line.starts_with('\t') => {
+                prev.push_str(&line[1..])
+            }
+            Some(prev) if prev.ends_with('=') && is_quoted_printable(prev) => {
+                prev.pop();
+                prev.push_str(line);
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+fn decode_quoted_printable(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'=' && i + 3 <= bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    out
+}
+
+/// split at separators that are not escaped with a backslash, and unescape the parts
+fn split_unescape(value: &str, separator: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') | Some('N') => parts.last_mut().unwrap().push(' '),
+                Some(c) => parts.last_mut().unwrap().push(c),
+                None => {}
+            },
+            c if c == separator => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts
+}
+
+/// 20201016T120000Z -> 2020-10-16 12:00:00
+fn format_date(value: &str) -> Option<String> {
+    let v = value.trim_end_matches('Z');
+    if let Ok(d) = NaiveDateTime::parse_from_str(v, "%Y%m%dT%H%M%S") {
+        return Some(d.format("%Y-%m-%d %H:%M:%S").to_string());
+    }
+    NaiveDate::parse_from_str(v, "%Y%m%d")
+        .ok()
+        .map(|d| d.format("%Y-%m-%d").to_string())
+}
+
+/// a content line `GROUP.NAME;PARAM=x;TYPE=work:value` as `NAME (work): value`
+fn format_property(line: &str) -> Option<String> {
+    // the value starts at the first colon outside of quoted parameter values
+    let mut in_quotes = false;
+    let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            in_quotes = !in_quotes;
+            None
+        }
+        ':' if !in_quotes => Some(i),
+        _ => None,
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let mut params = head.split(';');
+    let name = params.next()?;
+    let name = name.rsplit('.').next().unwrap_or(name).to_ascii_uppercase();
+    if ["BEGIN", "END", "VERSION", "PRODID", "CALSCALE"].contains(&name.as_str()) {
+        return None;
+    }
+    let mut types = vec![];
+    let mut charset = None;
+    let mut quoted_printable = false;
+    for param in params {
+        let mut kv = param.splitn(2, '=');
+        let key = kv.next().unwrap_or("").to_ascii_uppercase();
+        let val = kv.next().unwrap_or("").trim_matches('"');
+        match key.as_str() {
+            "ENCODING" if val.eq_ignore_ascii_case("QUOTED-PRINTABLE") => quoted_printable = true,
+            // inline binary data (photos, attachments)
+            "ENCODING" if val.eq_ignore_ascii_case("B") || val.eq_ignore_ascii_case("BASE64") => {
+                return None
+            }
+            "CHARSET" => charset = Some(val.to_string()),
+            "TYPE" => types.extend(val.split(',').map(|t| t.to_ascii_lowercase())),
+            // vCard 2.1 style types without a key (TEL;WORK;VOICE:...)
+            "QUOTED-PRINTABLE" => quoted_printable = true,
+            t if val.is_empty() && !t.is_empty() => types.push(t.to_ascii_lowercase()),
+            _ => {}
+        }
+    }
+    let value = if quoted_printable {
+        let bytes = decode_quoted_printable(value);
+        let encoding = charset
+            .and_then(|c| encoding_rs::Encoding::for_label(c.as_bytes()))
+            .unwrap_or(encoding_rs::UTF_8);
+        encoding.decode(&bytes).0.into_owned()
+    } else {
+        value.to_string()
+    };
+    // structured values (N, ADR) are separated by semicolons
+    let value = split_unescape(&value, ';')
+        .into_iter()
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<_>>()
+        .join("; ");
+    if value.is_empty() {
+        return None;
+    }
+    let value = match name.as_str() {
+        "DTSTART" | "DTEND" | "DUE" | "DTSTAMP" | "CREATED" | "LAST-MODIFIED" | "BDAY" | "REV"
+        | "ANNIVERSARY" => format_date(&value).unwrap_or(value),
+        _ => value,
+    };
+    Some(if types.is_empty() {
+        format!("{}: {}", name, value)
+    } else {
+        format!("{} ({}): {}", name, types.join(", "), value)
+    })
+}
+
+fn adapt_vcard(data: &str, oup: &mut dyn Write) -> Result<()> {
+    for line in unfold(data) {
+        if let Some(property) = format_property(&line) {
+            writeln!(oup, "{}", property)?;
+        }
+    }
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for VcardAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo { mut inp, .. } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data)?;
+        adapt_vcard(&String::from_utf8_lossy(&data), oup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn vcard(data: &str) -> Result<String> {
+        let mut oup = Vec::new();
+        adapt_vcard(data, &mut oup)?;
+        Ok(String::from_utf8(oup)?)
+    }
+
+    #[test]
+    fn contacts() -> Result<()> {
+        assert_eq!(
+            vcard(
+                "BEGIN:VCARD\r\nVERSION:2.1\r\nN:Müller;Jürgen;;;\r\n\
+                 FN;CHARSET=UTF-8;ENCODING=QUOTED-PRINTABLE:J=C3=BCrgen M=\r\n=C3=BCller\r\n\
+                 TEL;WORK;VOICE:+49 30 1234\r\n\
+                 item1.EMAIL;TYPE=INTERNET,pref:juergen@example.com\r\n\
+                 NOTE:met at the confer\r\n ence\\, room 3\r\n\
+                 PHOTO;ENCODING=BASE64;TYPE=JPEG:/9j/4AAQ\r\nEND:VCARD\r\n"
+            )?,
+            "N: Müller; Jürgen\n\
+             FN: Jürgen Müller\n\
+             TEL (work, voice): +49 30 1234\n\
+             EMAIL (internet, pref): juergen@example.com\n\
+             NOTE: met at the conference, room 3\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn events() -> Result<()> {
+        assert_eq!(
+            vcard(
+                "BEGIN:VCALENDAR\nBEGIN:VEVENT\nDTSTART;TZID=Europe/Berlin:20201016T150000\n\
+                 SUMMARY:Release planning\nDESCRIPTION:Agenda:\\n- changelog\nEND:VEVENT\nEND:VCALENDAR\n"
+            )?,
+            "DTSTART: 2020-10-16 15:00:00\n\
+             SUMMARY: Release planning\n\
+             DESCRIPTION: Agenda: - changelog\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,476 @@
+use super::*;
+use crate::adapted_iter::AdaptedFilesIter;
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use std::io::{BufRead, BufReader, Cursor};
+
+// .warc.gz is handled by the decompress adapter first
+static EXTENSIONS: &[&str] = &["warc", "arc"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "warc".to_owned(),
+        version: 1,
+        description: "Reads web archives (WARC and ARC) and recurses into the HTTP response bodies, prefixed with URL and capture date. HTML is converted to text".to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/warc".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct WarcAdapter;
+
+impl WarcAdapter {
+    pub fn new() -> WarcAdapter {
+        WarcAdapter
+    }
+}
+impl GetMetadata for WarcAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// header names are case insensitive, so they are stored lowercase
+fn parse_headers(lines: &str) -> HashMap<String, String> {
+    lines
+        .lines()
+        .filter_map(|line| {
+            let colon = line.find(':')?;
+            Some((
+                line[..colon].trim().to_ascii_lowercase(),
+                line[colon + 1..].trim().to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// split a HTTP message into its headers and body
+fn split_http(data: &[u8]) -> Option<(HashMap<String, String>, &[u8])> {
+    let end = data.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = String::from_utf8_lossy(&data[..end]);
+    // skip the status line
+    let headers = head.splitn(2, "\r\n").nth(1).unwrap_or("");
+    Some((parse_headers(headers), &data[end + 4..]))
+}
+
+fn dechunk(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .context("invalid chunked encoding")?;
+        let size = String::from_utf8_lossy(&data[..line_end]);
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or("").trim(), 16)?;
+        if size == 0 {
+            return Ok(out);
+        }
+        let end = (line_end + 2)
+            .checked_add(size)
+            .context("invalid chunk size")?;
+        let chunk = data.get(line_end + 2..end).context("truncated chunk")?;
+        out.extend_from_slice(chunk);
+        // the chunk ends with \r\n
+        data = data.get(end + 2..).unwrap_or(&[]);
+    }
+}
+
+/// decode the body of a HTTP response according to its transfer and content encoding
+fn decode_body(headers: &HashMap<String, String>, body: &[u8]) -> Result<Vec<u8>> {
+    let mut body = if headers
+        .get("transfer-encoding")
+        .map(|e| e.to_ascii_lowercase().contains("chunked"))
+        .unwrap_or(false)
+    {
+        dechunk(body)?
+    } else {
+        body.to_vec()
+    };
+    match headers
+        .get("content-encoding")
+        .map(|e| e.to_ascii_lowercase())
+        .as_deref()
+    {
+        Some("gzip") | Some("x-gzip") => {
+            let mut out = Vec::new();
+            flate2::read::MultiGzDecoder::new(&body[..]).read_to_end(&mut out)?;
+            body = out;
+        }
+        Some("deflate") => {
+            let mut out = Vec::new();
+            flate2::read::ZlibDecoder::new(&body[..]).read_to_end(&mut out)?;
+            body = out;
+        }
+        _ => {}
+    }
+    Ok(body)
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    Some(match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        e if e.starts_with("#x") || e.starts_with("#X") => {
+            std::char::from_u32(u32::from_str_radix(&e[2..], 16).ok()?)?
+        }
+        e if e.starts_with('#') => std::char::from_u32(e[1..].parse().ok()?)?,
+        _ => return None,
+    })
+}
+
+/// very simple html to text conversion: drops tags, scripts and styles, decodes entities and
+/// puts block elements on their own lines
+pub fn html_to_text(html: &str) -> String {
+    let mut out = String::new();
+    let mut rest = html;
+    while let Some(lt) = rest.find(|c: char| c == '<' || c == '&') {
+        out.push_str(&rest[..lt]);
+        rest = &rest[lt..];
+        if rest.starts_with('&') {
+            let entity = rest[1..]
+                .find(';')
+                .filter(|&end| end <= 10)
+                .and_then(|end| decode_entity(&rest[1..1 + end]).map(|c| (c, end)));
+            match entity {
+                Some((c, end)) => {
+                    out.push(c);
+                    rest = &rest[end + 2..];
+                }
+                None => {
+                    out.push('&');
+                    rest = &rest[1..];
+                }
+            }
+            continue;
+        }
+        let tag_end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = rest[1..tag_end].to_ascii_lowercase();
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect();
+        rest = &rest[tag_end + 1..];
+        if !tag.starts_with('/') && (name == "script" || name == "style") {
+            // skip the content
+            let close = format!("</{}", name);
+            let end = rest.to_ascii_lowercase().find(&close).unwrap_or(rest.len());
+            rest = &rest[end..];
+            continue;
+        }
+        match name.as_str() {
+            "p" | "div" | "br" | "li" | "tr" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6"
+            | "title" | "section" | "article" | "table" | "ul" | "ol" | "pre" | "blockquote" => {
+                out.push('\n')
+            }
+            "td" | "th" => out.push(' '),
+            _ => {}
+        }
+    }
+    out.push_str(rest);
+    out.lines()
+        .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// file name for the body, so the right adapter is chosen for it
+fn body_name(url: &str, content_type: &str) -> String {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    let ext = match mime.as_str() {
+        "text/html" | "application/xhtml+xml" => Some("html"),
+        "text/plain" => Some("txt"),
+        "application/pdf" => Some("pdf"),
+        "application/json" => Some("json"),
+        "application/zip" => Some("zip"),
+        _ => None,
+    };
+    let path = url
+        .split(|c: char| c == '?' || c == '#')
+        .next()
+        .unwrap_or("");
+    let after_scheme = path.splitn(2, "://").nth(1).unwrap_or(path);
+    let file_name = match after_scheme.find('/') {
+        Some(slash) => after_scheme[slash + 1..].rsplit('/').next().unwrap_or(""),
+        None => "",
+    };
+    let file_name = if file_name.is_empty() {
+        "index"
+    } else {
+        file_name
+    };
+    match ext {
+        Some(ext) if !file_name.ends_with(&format!(".{}", ext)) => {
+            format!("{}.{}", file_name, ext)
+        }
+        _ => file_name.to_string(),
+    }
+}
+
+/// one archived HTTP response or resource
+struct Record {
+    url: String,
+    date: String,
+    name: String,
+    body: Vec<u8>,
+}
+
+fn record_from_http(url: String, date: String, data: &[u8]) -> Result<Option<Record>> {
+    let (headers, body) = match split_http(data) {
+        Some(http) => http,
+        None => return Ok(None),
+    };
+    let content_type = headers.get("content-type").cloned().unwrap_or_default();
+    let body = decode_body(&headers, body)?;
+    Ok(Some(Record {
+        name: body_name(&url, &content_type),
+        url,
+        date,
+        body,
+    }))
+}
+
+struct WarcIter<'a> {
+    inp: BufReader<ReadBox<'a>>,
+    line_prefix: String,
+    archive_recursion_depth: i32,
+    postprocess: bool,
+    config: RgaConfig,
+}
+
+impl<'a> WarcIter<'a> {
+    fn read_line(&mut self) -> Result<Option<String>> {
+        let mut line = Vec::new();
+        if self.inp.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(
+            String::from_utf8_lossy(&line)
+                .trim_end_matches(|c: char| c == '\r' || c == '\n')
+                .to_string(),
+        ))
+    }
+
+    fn read_exact_vec(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        (&mut self.inp).take(len as u64).read_to_end(&mut data)?;
+        if data.len() < len {
+            return Err(format_err!("truncated record"));
+        }
+        Ok(data)
+    }
+
+    /// the next record we are interested in, or None at the end of the archive
+    fn next_record(&mut self) -> Result<Option<Record>> {
+        loop {
+            // records are separated by empty lines
+            let first = loop {
+                match self.read_line()? {
+                    None => return Ok(None),
+                    Some(l) if l.trim().is_empty() => continue,
+                    Some(l) => break l,
+                }
+            };
+            if first.starts_with("WARC/") {
+                let mut head = String::new();
+                loop {
+                    match self.read_line()? {
+                        Some(l) if !l.is_empty() => {
+                            head.push_str(&l);
+                            head.push('\n');
+                        }
+                        _ => break,
+                    }
+                }
+                let headers = parse_headers(&head);
+                let len: usize = headers
+                    .get("content-length")
+                    .context("WARC record without Content-Length")?
+                    .parse()?;
+                let data = self.read_exact_vec(len)?;
+                let url = headers.get("warc-target-uri").cloned().unwrap_or_default();
+                let date = headers.get("warc-date").cloned().unwrap_or_default();
+                let content_type = headers.get("content-type").cloned().unwrap_or_default();
+                match headers.get("warc-type").map(|t| t.as_str()) {
+                    Some("response") if content_type.starts_with("application/http") => {
+                        if let Some(record) = record_from_http(url, date, &data)? {
+                            return Ok(Some(record));
+                        }
+                    }
+                    Some("resource") | Some("conversion") => {
+                        return Ok(Some(Record {
+                            name: body_name(&url, &content_type),
+                            url,
+                            date,
+                            body: data,
+                        }))
+                    }
+                    _ => {}
+                }
+            } else {
+                // ARC: "URL IP-address Archive-date Content-type Archive-length"
+                let fields: Vec<&str> = first.split(' ').collect();
+                if fields.len() < 5 {
+                    return Err(format_err!("invalid ARC record header"));
+                }
+                let len: usize = fields[fields.len() - 1].parse()?;
+                let data = self.read_exact_vec(len)?;
+                let url = fields[0].to_string();
+                if url.starts_with("filedesc:") {
+                    continue;
+                }
+                let date = fields[2].to_string();
+                if data.starts_with(b"HTTP/") {
+                    if let Some(record) = record_from_http(url, date, &data)? {
+                        return Ok(Some(record));
+                    }
+                } else {
+                    return Ok(Some(Record {
+                        name: body_name(&url, fields[3]),
+                        url,
+                        date,
+                        body: data,
+                    }));
+                }
+            }
+        }
+    }
+}
+
+impl<'a> AdaptedFilesIter for WarcIter<'a> {
+    fn next<'b>(&'b mut self) -> Option<AdaptInfo<'b>> {
+        loop {
+            let record = match self.next_record() {
+                Ok(Some(record)) => record,
+                Ok(None) => return None,
+                Err(e) => {
+                    // we can't find the next record without a valid length
+                    warn!("stopping at invalid web archive record: {}", e);
+                    return None;
+                }
+            };
+            if record.body.is_empty() {
+                continue;
+            }
+            let (name, body) = if record.name.ends_with(".html") {
+                let text = html_to_text(&String::from_utf8_lossy(&record.body));
+                (format!("{}.txt", record.name), text.into_bytes())
+            } else {
+                (record.name, record.body)
+            };
+            return Some(AdaptInfo {
+                line_prefix: self.config.entry_prefix(
+                    &self.line_prefix,
+                    &format!("{} {}", record.url, record.date),
+                ),
+                filepath_hint: PathBuf::from(name),
+                is_real_file: false,
+                archive_recursion_depth: self.archive_recursion_depth + 1,
+                inp: Box::new(Cursor::new(body)),
+                postprocess: self.postprocess,
+                config: self.config.clone(),
+            });
+        }
+    }
+}
+
+impl FileAdapter for WarcAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        let AdaptInfo {
+            inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        Ok(Box::new(WarcIter {
+            inp: BufReader::new(inp),
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn chunked_html_response() -> Result<()> {
+        let http = "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    1c\r\n<html><title>Hi</title><p>Gr\r\n\
+                    23\r\n&uuml;&#223;e &amp; more</p></html>\r\n0\r\n\r\n";
+        let warc = format!(
+            "WARC/1.0\r\nWARC-Type: response\r\nWARC-Target-URI: https://example.com/\r\n\
+             WARC-Date: 2020-09-13T12:26:40Z\r\nContent-Type: application/http; msgtype=response\r\n\
+             Content-Length: {}\r\n\r\n{}\r\n\r\n",
+            http.len(),
+            http
+        );
+        let adapter: Box<dyn FileAdapter> = Box::new(WarcAdapter::new());
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("crawl.warc"),
+            Box::new(Cursor::new(warc.into_bytes())),
+        );
+        let mut iter = adapter.adapt(a, &d)?;
+        let mut ai = iter.next().context("no record")?;
+        let mut text = String::new();
+        ai.inp.read_to_string(&mut text)?;
+        assert_eq!(ai.filepath_hint, PathBuf::from("index.html.txt"));
+        assert_eq!(
+            ai.line_prefix,
+            "PREFIX:https://example.com/ 2020-09-13T12:26:40Z: "
+        );
+        assert_eq!(text, "Hi\nGr&uuml;ße & more");
+        assert!(dechunk(b"ffffffffffffffff\r\nx").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn body_names() {
+        assert_eq!(body_name("https://example.com", "text/html"), "index.html");
+        assert_eq!(
+            body_name(
+                "https://example.com/docs/a.pdf?download=1",
+                "application/pdf"
+            ),
+            "a.pdf"
+        );
+        assert_eq!(
+            body_name(
+                "https://example.com/files/data.tar.gz",
+                "application/octet-stream"
+            ),
+            "data.tar.gz"
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use super::*;
+use anyhow::*;
+use lazy_static::lazy_static;
+use spawning::read_input;
+use strings::StringsExtractor;
+use writing::{WritingFileAdapter, WritingFileAdapterTrait};
+
+static EXTENSIONS: &[&str] = &["wasm"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "wasm".to_owned(),
+        version: 1,
+        description: "Outputs the function names (name section), imports, exports and strings in data segments of WebAssembly modules".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/wasm".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct WasmAdapter;
+
+impl WasmAdapter {
+    pub fn new() -> WritingFileAdapter {
+        WritingFileAdapter::new(Box::new(WasmAdapter))
+    }
+}
+impl GetMetadata for WasmAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+struct WasmReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> WasmReader<'a> {
+    fn new(data: &'a [u8]) -> WasmReader<'a> {
+        WasmReader { data, pos: 0 }
+    }
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+    fn byte(&mut self) -> Result<u8> {
+        let b = *self
+            .data
+            .get(self.pos)
+            .context("unexpected end of wasm module")?;
+        self.pos += 1;
+        Ok(b)
+    }
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).context("invalid length")?;
+        let b = self
+            .data
+            .get(self.pos..end)
+            .context("unexpected end of wasm module")?;
+        self.pos = end;
+        Ok(b)
+    }
+    /// LEB128, signed values are only skipped so their sign does not matter
+    fn leb(&mut self) -> Result<u64> {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            if shift < 64 {
+                result |= ((b & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if b & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+    }
+    fn len(&mut self) -> Result<usize> {
+        Ok(self.leb()? as usize)
+    }
+    fn name(&mut self) -> Result<String> {
+        let len = self.len()?;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+    fn limits(&mut self) -> Result<()> {
+        let flags = self.byte()?;
+        self.leb()?;
+        if flags & 1 != 0 {
+            self.leb()?;
+        }
+        Ok(())
+    }
+    /// skip a constant expression (offset of data segments)
+    fn const_expr(&mut self) -> Result<()> {
+        loop {
+            match self.byte()? {
+                0x0b => return Ok(()),
+                // i32.const, i64.const, global.get
+                0x41 | 0x42 | 0x23 => {
+                    self.leb()?;
+                }
+                op => {
+                    return Err(format_err!(
+                        "unsupported opcode 0x{:02x} in constant expression",
+                        op
+                    ))
+                }
+            }
+        }
+    }
+}
+
+fn kind_name(kind: u8) -> &'static str {
+    match kind {
+        0 => "function",
+        1 => "table",
+        2 => "memory",
+        3 => "global",
+        4 => "tag",
+        _ => "unknown",
+    }
+}
+
+fn imports(r: &mut WasmReader, oup: &mut dyn Write) -> Result<()> {
+    for _ in 0..r.len()? {
+        let module = r.name()?;
+        let field = r.name()?;
+        let kind = r.byte()?;
+        match kind {
+            0 => {
+                r.leb()?;
+            }
+            1 => {
+                r.byte()?;
+                r.limits()?;
+            }
+            2 => r.limits()?,
+            3 => {
+                r.bytes(2)?;
+            }
+            4 => {
+                r.byte()?;
+                r.leb()?;
+            }
+            k => return Err(format_err!("unknown import kind {}", k)),
+        }
+        writeln!(oup, "import: {}.{} ({})", module, field, kind_name(kind))?;
+    }
+    Ok(())
+}
+
+fn exports(r: &mut WasmReader, oup: &mut dyn Write) -> Result<()> {
+    for _ in 0..r.len()? {
+        let name = r.name()?;
+        let kind = r.byte()?;
+        r.leb()?;
+        writeln!(oup, "export: {} ({})", name, kind_name(kind))?;
+    }
+    Ok(())
+}
+
+fn data_strings(r: &mut WasmReader, oup: &mut dyn Write) -> Result<()> {
+    let mut strings = Vec::new();
+    for _ in 0..r.len()? {
+        match r.leb()? {
+            0 => r.const_expr()?,
+            // passive
+            1 => {}
+            2 => {
+                r.leb()?;
+                r.const_expr()?;
+            }
+            f => return Err(format_err!("unknown data segment flags {}", f)),
+        }
+        let len = r.len()?;
+        let mut extractor = StringsExtractor::new(4);
+        for &b in r.bytes(len)? {
+            extractor.push(b, &mut strings)?;
+        }
+        extractor.finish(&mut strings)?;
+    }
+    for line in String::from_utf8_lossy(&strings).lines() {
+        writeln!(oup, "data: {}", line)?;
+    }
+    Ok(())
+}
+
+/// the "name" custom section, containing the module name and function names
+fn names(r: &mut WasmReader, oup: &mut dyn Write) -> Result<()> {
+    while !r.at_end() {
+        let id = r.byte()?;
+        let len = r.len()?;
+        let mut sub = WasmReader::new(r.bytes(len)?);
+        match id {
+            0 => writeln!(oup, "module: {}", sub.name()?)?,
+            1 => {
+                for _ in 0..sub.len()? {
+                    sub.leb()?;
+                    writeln!(oup, "function: {}", sub.name()?)?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn adapt_wasm(data: &[u8], oup: &mut dyn Write) -> Result<()> {
+    let mut r = WasmReader::new(data);
+    if r.bytes(8)? != b"\0asm\x01\0\0\0" {
+        return Err(format_err!("not a wasm module (or unsupported version)"));
+    }
+    while !r.at_end() {
+        let id = r.byte()?;
+        let len = r.len()?;
+        let mut section = WasmReader::new(r.bytes(len)?);
+        match id {
+            0 => {
+                let name = section.name()?;
+                if name == "name" {
+                    names(&mut section, oup)?;
+                } else {
+                    writeln!(oup, "custom section: {}", name)?;
+                }
+            }
+            2 => imports(&mut section, oup)?,
+            7 => exports(&mut section, oup)?,
+            11 => data_strings(&mut section, oup)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+impl WritingFileAdapterTrait for WasmAdapter {
+    fn adapt_write<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+        oup: &mut (dyn Write + 'a),
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            filepath_hint,
+            is_real_file,
+            ..
+        } = ai;
+        let data = read_input(&filepath_hint, is_real_file, &mut inp)?;
+        adapt_wasm(&data, oup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn section(id: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![id, content.len() as u8];
+        out.extend_from_slice(content);
+        out
+    }
+
+    fn name(s: &str) -> Vec<u8> {
+        let mut out = vec![s.len() as u8];
+        out.extend_from_slice(s.as_bytes());
+        out
+    }
+
+    #[test]
+    fn module() -> Result<()> {
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        // import env.log as function with type 0
+        let mut import = vec![1];
+        import.extend(name("env"));
+        import.extend(name("log"));
+        import.extend(&[0, 0]);
+        wasm.extend(section(2, &import));
+        // export function 1 as greet
+        let mut export = vec![1];
+        export.extend(name("greet"));
+        export.extend(&[0, 1]);
+        wasm.extend(section(7, &export));
+        // active data segment at offset 1024
+        let mut data = vec![1, 0, 0x41, 0x80, 0x08, 0x0b];
+        data.extend(name("\x01Hello from wasm\0"));
+        wasm.extend(section(11, &data));
+        // function names
+        let mut names = name("name");
+        let mut functions = vec![1, 1];
+        functions.extend(name("greet_impl"));
+        names.push(1);
+        names.extend(name(std::str::from_utf8(&functions)?));
+        wasm.extend(section(0, &names));
+
+        let mut oup = Vec::new();
+        adapt_wasm(&wasm, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "import: env.log (function)\n\
+             export: greet (function)\n\
+             data: Hello from wasm\n\
+             function: greet_impl\n"
+        );
+        Ok(())
+    }
+}
@@ -1,8 +1,10 @@
-use super::{FileAdapter, GetMetadata, ReadBox};
+use super::{AdaptInfo, FileAdapter, GetMetadata};
+use crate::adapted_iter::{AdaptedFilesIterBox, MultiAdaptedFilesIter, SingleAdaptedFileAsIter};
+use crate::config::RgaConfig;
+use crate::spill::SpillBuffer;
 use anyhow::Result;
-use std::io::Read;
-use std::io::Write;
-use std::thread::Thread;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
 // this trait / struct split is ugly but necessary because of "conflicting trait implementation" otherwise with SpawningFileAdapter
 #[dyn_clonable::clonable]
@@ -13,6 +15,11 @@ pub trait WritingFileAdapterTrait: GetMetadata + Send + Clone {
         detection_reason: &crate::matching::FileMatcher,
         oup: &mut (dyn Write + 'a),
     ) -> Result<()>;
+
+    /// see FileAdapter::cache_key_extra
+    fn cache_key_extra(&self, _filepath: &Path, _config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
 }
 
 pub struct WritingFileAdapter {
@@ -30,36 +37,45 @@ impl GetMetadata for WritingFileAdapter {
     }
 }
 
-struct PipedReadWriter<'a> {
-    inner: ReadBox<'a>,
-    pipe_thread: Thread,
-}
-
-impl<'a> Read for PipedReadWriter<'a> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        todo!()
-    }
-}
-
 impl FileAdapter for WritingFileAdapter {
     fn adapt<'a>(
         &self,
-        ai_outer: super::AdaptInfo<'a>,
+        ai: super::AdaptInfo<'a>,
         detection_reason: &crate::matching::FileMatcher,
-    ) -> anyhow::Result<ReadBox<'a>> {
-        let (r, w) = crate::pipe::pipe();
-        let cc = self.inner.clone();
-        let detc = detection_reason.clone();
-        std::thread::spawn(move || {
-            let mut oup = w;
-            let ai = ai_outer;
-            let res = cc.adapt_write(ai, &detc, &mut oup);
-            if let Err(e) = res {
-                oup.write_err(std::io::Error::new(std::io::ErrorKind::Other, e))
-                    .expect("could not write err");
-            }
-        });
+    ) -> anyhow::Result<AdaptedFilesIterBox<'a>> {
+        // the output is plain text, so make sure it is not matched by this adapter again
+        let mut output_hint = ai.filepath_hint.clone().into_os_string();
+        output_hint.push(".txt");
+        let line_prefix = ai.line_prefix.clone();
+        let archive_recursion_depth = ai.archive_recursion_depth;
+        let postprocess = ai.postprocess;
+        let config = ai.config.clone();
+
+        // AdaptInfo is not Send (and may borrow from the parent archive), so the adapter can't run in its own thread.
+        // run it to completion instead. Large outputs are moved to a temporary file
+        let mut oup = SpillBuffer::new(config.memory_limit.0);
+        self.inner.adapt_write(ai, detection_reason, &mut oup)?;
+        if oup.is_empty() {
+            return Ok(Box::new(MultiAdaptedFilesIter::new(vec![])));
+        }
+        // the line prefixer always ends the output with a newline
+        let len = if oup.last_byte() == Some(b'\n') {
+            oup.len() - 1
+        } else {
+            oup.len()
+        };
+        Ok(Box::new(SingleAdaptedFileAsIter::new(AdaptInfo {
+            filepath_hint: PathBuf::from(output_hint),
+            is_real_file: false,
+            archive_recursion_depth,
+            inp: Box::new(oup.into_reader()?.take(len)),
+            line_prefix,
+            postprocess,
+            config,
+        })))
+    }
 
-        Ok(Box::new(r))
+    fn cache_key_extra(&self, filepath: &Path, config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        self.inner.cache_key_extra(filepath, config)
     }
 }
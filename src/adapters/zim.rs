@@ -0,0 +1,380 @@
+//! ZIM archives (offline Wikipedia etc. from Kiwix), see https://wiki.openzim.org/wiki/ZIM_file_format.
+//! The articles are read in cluster order, so every (compressed) cluster is only decompressed once.
+
+use super::*;
+use crate::adapted_iter::AdaptedFilesIter;
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use spawning::input_as_file;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Seek, SeekFrom};
+use tempfile::NamedTempFile;
+use warc::html_to_text;
+
+static EXTENSIONS: &[&str] = &["zim"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "zim".to_owned(),
+        version: 1,
+        description: "Outputs the text of the articles in ZIM archives (Kiwix offline Wikipedia dumps etc.), prefixed with the article title".to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/x-zim".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ZimAdapter;
+
+impl ZimAdapter {
+    pub fn new() -> ZimAdapter {
+        ZimAdapter
+    }
+}
+impl GetMetadata for ZimAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const ZIM_MAGIC: u32 = 0x044D_495A;
+const REDIRECT: u16 = 0xFFFF;
+
+fn read_u16(r: &mut impl Read) -> Result<u16> {
+    let mut b = [0; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_le_bytes(b))
+}
+
+fn read_u32(r: &mut impl Read) -> Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_u64(r: &mut impl Read) -> Result<u64> {
+    let mut b = [0; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+fn read_string(r: &mut impl BufRead) -> Result<String> {
+    let mut s = Vec::new();
+    r.read_until(0, &mut s)?;
+    if s.last() == Some(&0) {
+        s.pop();
+    }
+    Ok(String::from_utf8_lossy(&s).into_owned())
+}
+
+struct Article {
+    cluster: u32,
+    blob: u32,
+    title: String,
+    url: String,
+    html: bool,
+}
+
+struct ZimIter {
+    file: BufReader<File>,
+    _tmp: Option<NamedTempFile>,
+    cluster_pointers: Vec<u64>,
+    /// where the last cluster ends
+    clusters_end: u64,
+    articles: std::vec::IntoIter<Article>,
+    /// number and decompressed data of the current cluster
+    cluster: Option<(u32, Vec<u8>)>,
+    line_prefix: String,
+    archive_recursion_depth: i32,
+    postprocess: bool,
+    config: RgaConfig,
+}
+
+impl ZimIter {
+    fn decompress_cluster(&mut self, cluster: u32) -> Result<Vec<u8>> {
+        let start = *self
+            .cluster_pointers
+            .get(cluster as usize)
+            .ok_or_else(|| format_err!("invalid cluster number {}", cluster))?;
+        let end = self
+            .cluster_pointers
+            .get(cluster as usize + 1)
+            .copied()
+            .unwrap_or(self.clusters_end);
+        self.file.seek(SeekFrom::Start(start))?;
+        let mut compressed = vec![0; end.saturating_sub(start) as usize];
+        self.file.read_exact(&mut compressed)?;
+        let (info, body) = compressed
+            .split_first()
+            .ok_or_else(|| format_err!("empty cluster {}", cluster))?;
+        let mut data = Vec::new();
+        match info & 0x0F {
+            0 | 1 => data.extend_from_slice(body),
+            4 => {
+                xz2::read::XzDecoder::new(body).read_to_end(&mut data)?;
+            }
+            5 => {
+                zstd::stream::read::Decoder::new(body)?.read_to_end(&mut data)?;
+            }
+            c => return Err(format_err!("unsupported cluster compression {}", c)),
+        }
+        // remember if the offsets are 64 bit in the first byte of the data
+        data.insert(0, info & 0x10);
+        Ok(data)
+    }
+
+    fn blob(&mut self, cluster: u32, blob: u32) -> Result<Vec<u8>> {
+        if self.cluster.as_ref().map(|(n, _)| *n) != Some(cluster) {
+            let data = self.decompress_cluster(cluster)?;
+            self.cluster = Some((cluster, data));
+        }
+        let data = &self.cluster.as_ref().expect("just set").1;
+        let (extended, data) = (data[0] != 0, &data[1..]);
+        let size = if extended { 8 } else { 4 };
+        let offset = |i: usize| -> Result<usize> {
+            let b = data
+                .get(i * size..(i + 1) * size)
+                .ok_or_else(|| format_err!("invalid blob number {}", blob))?;
+            let mut bytes = [0; 8];
+            bytes[..size].copy_from_slice(b);
+            Ok(u64::from_le_bytes(bytes) as usize)
+        };
+        let (start, end) = (offset(blob as usize)?, offset(blob as usize + 1)?);
+        data.get(start..end)
+            .map(|b| b.to_vec())
+            .ok_or_else(|| format_err!("blob {} out of bounds", blob))
+    }
+}
+
+impl AdaptedFilesIter for ZimIter {
+    fn next<'b>(&'b mut self) -> Option<AdaptInfo<'b>> {
+        loop {
+            let article = self.articles.next()?;
+            let data = match self.blob(article.cluster, article.blob) {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("could not read zim article {}: {:?}", article.url, e);
+                    continue;
+                }
+            };
+            let text = if article.html {
+                html_to_text(&String::from_utf8_lossy(&data))
+            } else {
+                String::from_utf8_lossy(&data).into_owned()
+            };
+            let title = if article.title.is_empty() {
+                &article.url
+            } else {
+                &article.title
+            };
+            return Some(AdaptInfo {
+                filepath_hint: PathBuf::from(format!("{}.txt", article.url)),
+                is_real_file: false,
+                archive_recursion_depth: self.archive_recursion_depth + 1,
+                inp: Box::new(Cursor::new(text.into_bytes())),
+                line_prefix: self.config.entry_prefix(&self.line_prefix, &title),
+                postprocess: self.postprocess,
+                config: self.config.clone(),
+            });
+        }
+    }
+}
+
+impl FileAdapter for ZimAdapter {
+    fn adapt<'a>(
+        &self,
+        ai: AdaptInfo<'a>,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox<'a>> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+        } = ai;
+        // zim files are read by random access
+        let (path, tmp) = input_as_file(&filepath_hint, is_real_file, &mut inp)?;
+        let mut file = BufReader::new(File::open(&path)?);
+        if read_u32(&mut file)? != ZIM_MAGIC {
+            return Err(format_err!("not a zim file"));
+        }
+        let _major = read_u16(&mut file)?;
+        let minor = read_u16(&mut file)?;
+        file.seek(SeekFrom::Start(24))?;
+        let article_count = read_u32(&mut file)?;
+        let cluster_count = read_u32(&mut file)?;
+        let url_ptr_pos = read_u64(&mut file)?;
+        let _title_ptr_pos = read_u64(&mut file)?;
+        let cluster_ptr_pos = read_u64(&mut file)?;
+        let mime_list_pos = read_u64(&mut file)?;
+        file.seek(SeekFrom::Start(72))?;
+        let checksum_pos = read_u64(&mut file)?;
+
+        file.seek(SeekFrom::Start(mime_list_pos))?;
+        let mut mimetypes = vec![];
+        loop {
+            let mime = read_string(&mut file)?;
+            if mime.is_empty() {
+                break;
+            }
+            mimetypes.push(mime);
+        }
+
+        file.seek(SeekFrom::Start(cluster_ptr_pos))?;
+        let cluster_pointers = (0..cluster_count)
+            .map(|_| read_u64(&mut file))
+            .collect::<Result<Vec<_>>>()?;
+        file.seek(SeekFrom::Start(url_ptr_pos))?;
+        let url_pointers = (0..article_count)
+            .map(|_| read_u64(&mut file))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut articles = vec![];
+        for pointer in url_pointers {
+            file.seek(SeekFrom::Start(pointer))?;
+            let mime_index = read_u16(&mut file)?;
+            let mut parameter_len_and_namespace = [0; 2];
+            file.read_exact(&mut parameter_len_and_namespace)?;
+            let namespace = parameter_len_and_namespace[1];
+            let _revision = read_u32(&mut file)?;
+            if mime_index == REDIRECT {
+                continue;
+            }
+            let cluster = read_u32(&mut file)?;
+            let blob = read_u32(&mut file)?;
+            let url = read_string(&mut file)?;
+            let title = read_string(&mut file)?;
+            // old archives have the articles in namespace A, newer ones all content in C
+            let content_namespace = if minor >= 1 { b'C' } else { b'A' };
+            let mime = mimetypes
+                .get(mime_index as usize)
+                .map(|m| m.as_str())
+                .unwrap_or("");
+            if namespace != content_namespace || !mime.starts_with("text/") {
+                continue;
+            }
+            articles.push(Article {
+                cluster,
+                blob,
+                title,
+                url,
+                html: mime.starts_with("text/html"),
+            });
+        }
+        debug!("zim file has {} text articles", articles.len());
+        articles.sort_by_key(|a| (a.cluster, a.blob));
+        Ok(Box::new(ZimIter {
+            file,
+            _tmp: tmp,
+            cluster_pointers,
+            clusters_end: checksum_pos,
+            articles: articles.into_iter(),
+            cluster: None,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+    use std::io::Write;
+
+    fn entry(mime: u16, cluster: u32, blob: u32, url: &str, title: &str) -> Vec<u8> {
+        let mut e = mime.to_le_bytes().to_vec();
+        e.extend(&[0, b'A', 0, 0, 0, 0]);
+        e.extend(&cluster.to_le_bytes());
+        if mime != REDIRECT {
+            e.extend(&blob.to_le_bytes());
+        }
+        e.extend(url.as_bytes());
+        e.push(0);
+        e.extend(title.as_bytes());
+        e.push(0);
+        e
+    }
+
+    #[test]
+    fn articles() -> Result<()> {
+        let blobs: &[&[u8]] = &[
+            b"<html><body><h1>Rust</h1><p>A systems programming language.</p></body></html>",
+            b"\x89PNG",
+            b"<p>Ferris is the mascot.</p>",
+        ];
+        let mut cluster = vec![1];
+        let mut offset = 4 * (blobs.len() as u32 + 1);
+        for blob in blobs {
+            cluster.extend(&offset.to_le_bytes());
+            offset += blob.len() as u32;
+        }
+        cluster.extend(&offset.to_le_bytes());
+        for blob in blobs {
+            cluster.extend(*blob);
+        }
+
+        let mimes = b"text/html\0image/png\0\0";
+        let entries = vec![
+            entry(0, 0, 2, "Ferris", "Ferris the crab"),
+            entry(1, 0, 1, "logo.png", ""),
+            entry(REDIRECT, 0, 0, "Rust_language", "Rust language"),
+            entry(0, 0, 0, "Rust", ""),
+        ];
+        let mimes_pos = 80u64;
+        let mut entries_pos = mimes_pos + mimes.len() as u64;
+        let mut url_pointers = vec![];
+        for e in &entries {
+            url_pointers.push(entries_pos);
+            entries_pos += e.len() as u64;
+        }
+        let url_ptr_pos = entries_pos;
+        let cluster_ptr_pos = url_ptr_pos + 8 * entries.len() as u64;
+        let cluster_pos = cluster_ptr_pos + 8;
+        let checksum_pos = cluster_pos + cluster.len() as u64;
+
+        let mut zim = ZIM_MAGIC.to_le_bytes().to_vec();
+        zim.extend(&[5, 0, 0, 0]);
+        zim.extend(&[0; 16]);
+        zim.extend(&(entries.len() as u32).to_le_bytes());
+        zim.extend(&1u32.to_le_bytes());
+        for pos in &[url_ptr_pos, url_ptr_pos, cluster_ptr_pos, mimes_pos] {
+            zim.extend(&pos.to_le_bytes());
+        }
+        zim.extend(&[0xFF; 8]);
+        zim.extend(&checksum_pos.to_le_bytes());
+        zim.extend(mimes);
+        for e in &entries {
+            zim.extend(e);
+        }
+        for p in url_pointers {
+            zim.extend(&p.to_le_bytes());
+        }
+        zim.extend(&cluster_pos.to_le_bytes());
+        zim.extend(cluster);
+
+        let mut file = tempfile::Builder::new().suffix(".zim").tempfile()?;
+        file.write_all(&zim)?;
+        let adapter: Box<dyn FileAdapter> = Box::new(ZimAdapter::new());
+        let (a, d) = simple_adapt_info(file.path(), Box::new(File::open(file.path())?));
+        let buf = adapted_to_vec(adapter.adapt(a, &d)?)?;
+        let out = String::from_utf8(buf)?;
+        assert!(out.contains("PREFIX:Rust: A systems programming language.\n"));
+        assert!(out.contains("PREFIX:Ferris the crab: Ferris is the mascot.\n"));
+        assert!(!out.contains("PNG"));
+        assert!(out.find("Rust:").unwrap() < out.find("Ferris the crab:").unwrap());
+        Ok(())
+    }
+}
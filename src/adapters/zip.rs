@@ -1,22 +1,29 @@
 use super::*;
+use crate::entry_names::{decode_entry_name, NameEncoding};
+use crate::secrets::{self, SecretKind};
 use crate::{adapted_iter::AdaptedFilesIter, print_bytes};
 use anyhow::*;
 use lazy_static::lazy_static;
 use log::*;
+use spawning::input_as_file;
+use std::fs::File;
 
-static EXTENSIONS: &[&str] = &["zip"];
+static EXTENSIONS: &[&str] = &["zip", "jar", "apk"];
 
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "zip".to_owned(),
-        version: 1,
-        description: "Reads a zip file as a stream and recurses down into its contents".to_owned(),
+        version: 4,
+        description: "Reads a zip file as a stream and recurses down into its contents. Encrypted entries are decrypted with the password for the file (--rga-password-file, --rga-zip-password, ...), entry metadata is output with --rga-archive-metadata".to_owned(),
         recurses: true,
         fast_matchers: EXTENSIONS
             .iter()
             .map(|s| FastFileMatcher::FileExtension(s.to_string()))
             .collect(),
-        slow_matchers: Some(vec![FileMatcher::MimeType("application/zip".to_owned())]),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/zip".to_owned()),
+            FileMatcher::MimeType("application/vnd.android.package-archive".to_owned())
+        ]),
         keep_fast_matchers_if_accurate: false,
         disabled_by_default: false
     };
@@ -41,44 +48,310 @@ impl FileAdapter for ZipAdapter {
         inp: AdaptInfo<'a>,
         _detection_reason: &FileMatcher,
     ) -> Result<Box<dyn AdaptedFilesIter + 'a>> {
-        Ok(Box::new(ZipAdaptIter { inp }))
+        let password = secrets::password_for(&inp.config, SecretKind::Zip, &inp.filepath_hint)?;
+        if password.is_some() || inp.config.archive_metadata {
+            // decryption, unix modes and comments need the central directory, so we can't stream
+            let AdaptInfo {
+                filepath_hint,
+                is_real_file,
+                mut inp,
+                line_prefix,
+                postprocess,
+                config,
+                ..
+            } = inp;
+            let (path, tmp) = input_as_file(&filepath_hint, is_real_file, &mut inp)?;
+            let archive = ::zip::ZipArchive::new(File::open(&path)?)
+                .with_context(|| format!("could not open zip file {}", filepath_hint.display()))?;
+            return Ok(Box::new(ZipArchiveIter {
+                archive,
+                _tmp: tmp,
+                index: 0,
+                password,
+                comment_done: false,
+                metadata_done: false,
+                line_prefix,
+                postprocess,
+                config,
+            }));
+        }
+        Ok(Box::new(ZipAdaptIter { inp, done: false }))
+    }
+
+    /// the output of encrypted entries depends on the password, the output of all entries on --rga-archive-metadata
+    fn cache_key_extra(&self, filepath: &Path, config: &RgaConfig) -> Result<Option<Vec<u8>>> {
+        let password = secrets::cache_key(config, SecretKind::Zip, filepath)?;
+        if password.is_none() && !config.archive_metadata {
+            return Ok(None);
+        }
+        let mut extra = vec![config.archive_metadata as u8];
+        if let Some(p) = password {
+            extra.extend_from_slice(&p);
+        }
+        Ok(Some(extra))
+    }
+}
+
+/// a line describing an archive entry, output before its contents with --rga-archive-metadata
+pub fn entry_metadata_line(
+    size: u64,
+    modified: Option<String>,
+    mode: Option<u32>,
+    comment: &str,
+) -> String {
+    let mut parts = vec![format!("size {}", size)];
+    if let Some(modified) = modified {
+        parts.push(format!("modified {}", modified));
+    }
+    if let Some(mode) = mode {
+        parts.push(format!("mode {:04o}", mode & 0o7777));
     }
+    if !comment.is_empty() {
+        parts.push(format!("comment {}", comment));
+    }
+    format!("[{}]\n", parts.join(", "))
+}
+
+/// a single text "file" output with the given line prefix
+fn text_note<'a>(
+    line_prefix: String,
+    postprocess: bool,
+    config: &RgaConfig,
+    text: String,
+) -> AdaptInfo<'a> {
+    AdaptInfo {
+        filepath_hint: PathBuf::from("rga-note.txt"),
+        is_real_file: false,
+        inp: Box::new(std::io::Cursor::new(text.into_bytes())),
+        line_prefix,
+        archive_recursion_depth: 2,
+        postprocess,
+        config: config.clone(),
+    }
+}
+
+/// a single text "file" explaining why (part of) the archive could not be read,
+/// so the failure shows up in the search results instead of silently producing nothing
+fn error_note<'a>(
+    line_prefix: &str,
+    postprocess: bool,
+    config: &RgaConfig,
+    msg: String,
+) -> AdaptInfo<'a> {
+    text_note(
+        line_prefix.to_string(),
+        postprocess,
+        config,
+        format!("[rga: {}]\n", msg),
+    )
 }
 
 struct ZipAdaptIter<'a> {
     inp: AdaptInfo<'a>,
+    done: bool,
 }
 impl<'a> AdaptedFilesIter for ZipAdaptIter<'a> {
     fn next<'b>(&'b mut self) -> Option<AdaptInfo<'b>> {
+        if self.done {
+            return None;
+        }
         let line_prefix = &self.inp.line_prefix;
         let filepath_hint = &self.inp.filepath_hint;
         let archive_recursion_depth = 1;
         let postprocess = self.inp.postprocess;
-        ::zip::read::read_zipfile_from_stream(&mut self.inp.inp)
-            .unwrap()
-            .and_then(|file| {
-                if file.is_dir() {
-                    return None;
-                }
-                debug!(
-                    "{}{}|{}: {} ({} packed)",
-                    line_prefix,
-                    filepath_hint.to_string_lossy(),
-                    file.name(),
-                    print_bytes(file.size() as f64),
-                    print_bytes(file.compressed_size() as f64)
-                );
-                let line_prefix = format!("{}{}: ", line_prefix, file.name());
-                Some(AdaptInfo {
-                    filepath_hint: PathBuf::from(file.name()),
-                    is_real_file: false,
-                    inp: Box::new(file),
-                    line_prefix,
-                    archive_recursion_depth: archive_recursion_depth + 1,
-                    postprocess,
-                    config: RgaConfig::default(), //config.clone(),
-                })
+        let config = &self.inp.config;
+        let entry = match ::zip::read::read_zipfile_from_stream(&mut self.inp.inp) {
+            Ok(entry) => entry,
+            Err(e) => {
+                // the stream can't continue after an entry we can't read
+                self.done = true;
+                let msg = if e.to_string().contains("ncrypted") {
+                    format!(
+                        "{} contains encrypted files. {} to search them",
+                        filepath_hint.display(),
+                        secrets::password_hint(SecretKind::Zip)
+                    )
+                } else {
+                    format!("could not read zip file {}: {}", filepath_hint.display(), e)
+                };
+                warn!("{}", msg);
+                return Some(error_note(line_prefix, postprocess, config, msg));
+            }
+        };
+        entry.and_then(|file| {
+            if file.is_dir() {
+                return None;
+            }
+            let name = entry_name(&file, config);
+            debug!(
+                "{}{}|{}: {} ({} packed)",
+                line_prefix,
+                filepath_hint.to_string_lossy(),
+                name,
+                print_bytes(file.size() as f64),
+                print_bytes(file.compressed_size() as f64)
+            );
+            let line_prefix = config.entry_prefix(&line_prefix, &name);
+            Some(AdaptInfo {
+                filepath_hint: PathBuf::from(name),
+                is_real_file: false,
+                inp: Box::new(file),
+                line_prefix,
+                archive_recursion_depth: archive_recursion_depth + 1,
+                postprocess,
+                config: config.clone(),
             })
+        })
+    }
+}
+
+/// reads entries by index from the central directory, for encrypted entries and entry metadata
+struct ZipArchiveIter {
+    archive: ::zip::ZipArchive<File>,
+    _tmp: Option<tempfile::NamedTempFile>,
+    index: usize,
+    password: Option<String>,
+    comment_done: bool,
+    /// whether the metadata line of the current entry was already output
+    metadata_done: bool,
+    line_prefix: String,
+    postprocess: bool,
+    config: RgaConfig,
+}
+
+fn open_entry<'z>(
+    archive: &'z mut ::zip::ZipArchive<File>,
+    i: usize,
+    password: &Option<String>,
+) -> ::zip::result::ZipResult<
+    std::result::Result<::zip::read::ZipFile<'z>, ::zip::result::InvalidPassword>,
+> {
+    match password {
+        Some(password) => archive.by_index_decrypt(i, password.as_bytes()),
+        None => archive.by_index(i).map(Ok),
+    }
+}
+
+/// names without the UTF-8 flag are CP437, but often the code page of the system that created the zip file
+fn entry_name(file: &::zip::read::ZipFile, config: &RgaConfig) -> String {
+    decode_entry_name(
+        file.name_raw(),
+        &config.entry_name_encoding,
+        Some(NameEncoding::Cp437),
+    )
+}
+
+fn zip_entry_metadata(file: &::zip::read::ZipFile) -> String {
+    let t = file.last_modified();
+    entry_metadata_line(
+        file.size(),
+        Some(format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            t.year(),
+            t.month(),
+            t.day(),
+            t.hour(),
+            t.minute(),
+            t.second()
+        )),
+        file.unix_mode(),
+        file.comment(),
+    )
+}
+
+enum Step {
+    Skip,
+    /// the metadata line and name of the entry
+    Metadata(String, String),
+    Content,
+    Error(String),
+}
+
+impl AdaptedFilesIter for ZipArchiveIter {
+    fn next<'b>(&'b mut self) -> Option<AdaptInfo<'b>> {
+        if !self.comment_done {
+            self.comment_done = true;
+            let comment = String::from_utf8_lossy(self.archive.comment())
+                .trim()
+                .to_string();
+            if self.config.archive_metadata && !comment.is_empty() {
+                return Some(text_note(
+                    self.line_prefix.clone(),
+                    self.postprocess,
+                    &self.config,
+                    format!("[archive comment {}]\n", comment),
+                ));
+            }
+        }
+        loop {
+            let i = self.index;
+            if i >= self.archive.len() {
+                return None;
+            }
+            // check the entry first, returning the borrowed entry from inside the loop
+            // conditionally is not possible
+            let step = match open_entry(&mut self.archive, i, &self.password) {
+                Ok(Ok(file)) if file.is_dir() => Step::Skip,
+                Ok(Ok(file)) if self.config.archive_metadata && !self.metadata_done => {
+                    Step::Metadata(zip_entry_metadata(&file), entry_name(&file, &self.config))
+                }
+                Ok(Ok(_)) => Step::Content,
+                Ok(Err(::zip::result::InvalidPassword)) => {
+                    Step::Error(format!("wrong zip password for entry {}", i))
+                }
+                Err(e) => Step::Error(format!("could not read zip entry {}: {}", i, e)),
+            };
+            match step {
+                Step::Skip => {
+                    self.index += 1;
+                    continue;
+                }
+                Step::Metadata(line, name) => {
+                    self.metadata_done = true;
+                    return Some(text_note(
+                        self.config.entry_prefix(&self.line_prefix, &name),
+                        self.postprocess,
+                        &self.config,
+                        line,
+                    ));
+                }
+                Step::Error(msg) => {
+                    self.index += 1;
+                    warn!("{}", msg);
+                    return Some(error_note(
+                        &self.line_prefix,
+                        self.postprocess,
+                        &self.config,
+                        msg,
+                    ));
+                }
+                Step::Content => {
+                    self.index += 1;
+                    self.metadata_done = false;
+                }
+            }
+            let file = match open_entry(&mut self.archive, i, &self.password) {
+                Ok(Ok(file)) => file,
+                _ => unreachable!("zip entry was readable before"),
+            };
+            let name = entry_name(&file, &self.config);
+            debug!(
+                "{}{}: {} ({} packed)",
+                self.line_prefix,
+                name,
+                print_bytes(file.size() as f64),
+                print_bytes(file.compressed_size() as f64)
+            );
+            return Some(AdaptInfo {
+                line_prefix: self.config.entry_prefix(&self.line_prefix, &name),
+                filepath_hint: PathBuf::from(name),
+                is_real_file: false,
+                inp: Box::new(file),
+                archive_recursion_depth: 2,
+                postprocess: self.postprocess,
+                config: self.config.clone(),
+            });
+        }
     }
 }
 
@@ -124,4 +397,57 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn encrypted_without_password() -> Result<()> {
+        let mut zipfile = create_zip("secret.txt", "secret text", false)?;
+        // set the "encrypted" flag of the local file header
+        zipfile[6] |= 1;
+        let adapter: Box<dyn FileAdapter> = Box::new(ZipAdapter::new());
+
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("secret.zip"),
+            Box::new(std::io::Cursor::new(zipfile)),
+        );
+        let buf = String::from_utf8(adapted_to_vec(adapter.adapt(a, &d)?)?)?;
+
+        assert!(buf.starts_with("PREFIX:[rga: secret.zip contains encrypted files"));
+        assert!(buf.contains("--rga-zip-password"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn archive_metadata() -> Result<()> {
+        use ::zip::write::FileOptions;
+        use std::io::Write;
+
+        let mut zip = ::zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.set_comment("nightly build");
+        let options = FileOptions::default()
+            .compression_method(::zip::CompressionMethod::Stored)
+            .last_modified_time(
+                ::zip::DateTime::from_date_and_time(2020, 5, 17, 12, 30, 0).expect("valid date"),
+            )
+            .unix_permissions(0o755);
+        zip.start_file("run.sh", options)?;
+        zip.write_all(b"echo hi\n")?;
+        let zipfile = zip.finish()?.into_inner();
+        let adapter: Box<dyn FileAdapter> = Box::new(ZipAdapter::new());
+
+        let (mut a, d) = simple_adapt_info(
+            &PathBuf::from("build.zip"),
+            Box::new(std::io::Cursor::new(zipfile)),
+        );
+        a.is_real_file = false;
+        a.config.archive_metadata = true;
+        let buf = adapted_to_vec(adapter.adapt(a, &d)?)?;
+
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:[archive comment nightly build]\nPREFIX:run.sh: [size 8, modified 2020-05-17 12:30:00, mode 0755]\nPREFIX:run.sh: echo hi\n",
+        );
+
+        Ok(())
+    }
 }
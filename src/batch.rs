@@ -0,0 +1,256 @@
+use crate::adapters::postproc::postproc_prefix;
+use crate::adapters::*;
+use crate::config::RgaConfig;
+use crate::failures::Failure;
+use crate::matching::{adapter_matcher, has_content_matchers, FastFileMatcher, FileMeta};
+use crate::preproc::rga_preproc;
+use crate::spill::SpillBuffer;
+use anyhow::*;
+use log::*;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// rg options that change which files are walked
+#[derive(Default, Debug)]
+pub struct WalkOptions {
+    pub hidden: bool,
+    pub no_ignore: bool,
+}
+
+/// whether a file is handled by an adapter (going by its name, like the pre-glob rga passes to rg)
+pub fn adapted_file_filter(config: &RgaConfig) -> Result<impl Fn(&Path) -> bool> {
+    let adapters = enabled_adapters(config)?;
+    let matcher = adapter_matcher(&adapters, false)?;
+    // with these, every file may need an adapter
+    let all_files = config.accurate
+        || config.strings.fallback
+        || !config.mime.is_empty()
+        || has_content_matchers(&adapters);
+    Ok(move |path: &Path| {
+        all_files
+            || path.file_name().map_or(false, |name| {
+                matcher(FileMeta {
+                    mimetype: None,
+                    lossy_filename: name.to_string_lossy().to_string(),
+                    lossy_path: path.to_string_lossy().to_string(),
+                    content: None,
+                })
+                .is_some()
+            })
+    })
+}
+
+/// the arguments rga always passes to rg
+pub const RG_ARGS: &[&str] = &[
+    "--no-line-number",
+    // smart case by default because within weird files
+    // we probably can't really trust casing anyways
+    "--smart-case",
+];
+
+/// the globs of the files rg runs rga-preproc on (--pre-glob)
+pub fn pre_globs(config: &RgaConfig) -> Result<Vec<String>> {
+    let adapters = enabled_adapters(config)?;
+    // the strings fallback, adapters matching the content and --rga-mime need to see every file
+    if config.accurate
+        || config.strings.fallback
+        || !config.mime.is_empty()
+        || has_content_matchers(&adapters)
+    {
+        return Ok(vec!["*".to_owned()]);
+    }
+    let extensions = adapters
+        .iter()
+        .flat_map(|a| &a.metadata().fast_matchers)
+        .flat_map(|m| match m {
+            FastFileMatcher::FileExtension(ext) => vec![ext.clone(), ext.to_ascii_uppercase()],
+            _ => vec![],
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    let globs = adapters
+        .iter()
+        .flat_map(|a| &a.metadata().fast_matchers)
+        .filter_map(|m| match m {
+            FastFileMatcher::PathGlob(glob) => Some(glob.clone()),
+            _ => None,
+        });
+    Ok(std::iter::once(format!("*.{{{}}}", extensions))
+        .chain(globs)
+        .collect())
+}
+
+/// the files below the roots that are handled by an adapter, sorted by path within each root (in the given order).
+/// Respects .gitignore, .ignore and hidden files like rg
+pub fn batch_files(
+    roots: &[PathBuf],
+    config: &RgaConfig,
+    walk: &WalkOptions,
+) -> Result<Vec<PathBuf>> {
+    let (first, rest) = roots
+        .split_first()
+        .ok_or_else(|| format_err!("no paths to search"))?;
+    let handled = adapted_file_filter(config)?;
+
+    let mut builder = ignore::WalkBuilder::new(first);
+    for root in rest {
+        builder.add(root);
+    }
+    builder
+        .standard_filters(!walk.no_ignore)
+        .hidden(!walk.hidden)
+        // the order of directory entries depends on the file system
+        .sort_by_file_name(|a, b| a.cmp(b));
+    let mut files = Vec::new();
+    for entry in builder.build() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("{}", e);
+                continue;
+            }
+        };
+        if !entry.file_type().map_or(false, |t| t.is_file()) {
+            continue;
+        }
+        let path = entry.into_path();
+        if handled(&path) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// the output of one file, with every line prefixed by the path like the files in archives
+fn preprocess_file(path: &Path, config: &RgaConfig) -> Result<Box<dyn Read + Send>> {
+    let oup = rga_preproc(AdaptInfo {
+        inp: Box::new(File::open(path)?),
+        // same as rga-preproc, so the cache entries are shared
+        filepath_hint: std::env::current_dir()?.join(path),
+        is_real_file: true,
+        line_prefix: "".to_string(),
+        archive_recursion_depth: 0,
+        postprocess: true,
+        config: config.clone(),
+    })?;
+    // the prefix is added afterwards, so the cached output is the same as when running rga-preproc
+    let mut prefixed = postproc_prefix(&config.entry_prefix("", &path.display().to_string()), oup)?;
+    let mut buf = SpillBuffer::new(config.memory_limit.0);
+    std::io::copy(&mut prefixed, &mut buf)?;
+    Ok(buf.into_reader()?)
+}
+
+/**
+ * Run the adapters on the files on a thread pool (one thread per cpu) and write their outputs to oup,
+ * one file after another in the order of the files, so repeated searches give the same output.
+ * The files are processed roughly in that order, outputs of files that finish early are kept until it's their turn.
+ *
+ * Files that can't be processed are reported and skipped. Stops early when oup is closed (e.g. rg exited after the first match with -q).
+ */
+pub fn preprocess_batch(
+    files: Vec<PathBuf>,
+    config: &RgaConfig,
+    oup: &mut dyn Write,
+) -> Result<()> {
+    let (sender, receiver) = crossbeam_channel::bounded(rayon::current_num_threads());
+    let stop = std::sync::Arc::new(AtomicBool::new(false));
+    let worker = {
+        let config = config.clone();
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            // par_bridge takes the files in order, unlike splitting the list between the threads
+            files.into_iter().enumerate().par_bridge().for_each_with(
+                sender,
+                |sender, (index, path)| {
+                    if stop.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    let res = preprocess_file(&path, &config);
+                    sender.send((index, path, res)).ok();
+                },
+            )
+        })
+    };
+    let mut res = Ok(());
+    let mut finished = BTreeMap::new();
+    let mut next_output = 0;
+    'receive: for (index, path, output) in receiver.iter() {
+        finished.insert(index, (path, output));
+        while let Some((path, output)) = finished.remove(&next_output) {
+            next_output += 1;
+            match output {
+                Ok(mut output) => {
+                    if let Err(e) = std::io::copy(&mut output, oup) {
+                        if e.kind() != std::io::ErrorKind::BrokenPipe {
+                            res = Err(e.into());
+                        }
+                        debug!("output closed, stopping");
+                        break 'receive;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("rga: {}: {:#}", path.display(), e);
+                    Failure::new(&path.to_string_lossy(), &e).record();
+                }
+            }
+        }
+    }
+    stop.store(true, Ordering::Relaxed);
+    drop(receiver);
+    worker
+        .join()
+        .map_err(|_| format_err!("batch worker thread panicked"))?;
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn batch() -> Result<()> {
+        let mut config = RgaConfig::default();
+        config.cache.disabled = true;
+        let dir = test_data_dir();
+        let files = batch_files(&[dir.clone()], &config, &WalkOptions::default())?;
+        assert!(files.contains(&dir.join("hello.gz")));
+
+        let mut oup = Vec::new();
+        preprocess_batch(vec![dir.join("hello.gz")], &config, &mut oup)?;
+        let oup = String::from_utf8(oup)?;
+        assert!(
+            oup.starts_with(&format!("{}: hello\n", dir.join("hello.gz").display())),
+            "{}",
+            oup
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn output_in_order_of_files() -> Result<()> {
+        let mut config = RgaConfig::default();
+        config.cache.disabled = true;
+        let dir = tempfile::tempdir()?;
+        let files = (0..50)
+            .map(|i| {
+                let path = dir.path().join(format!("file{:02}.txt", i));
+                std::fs::write(&path, format!("line {}\n", i))?;
+                Ok(path)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut oup = Vec::new();
+        preprocess_batch(files.clone(), &config, &mut oup)?;
+        let expected: String = files
+            .iter()
+            .enumerate()
+            .map(|(i, path)| format!("{}: line {}\n", path.display(), i))
+            .collect();
+        assert_eq!(String::from_utf8(oup)?, expected);
+        Ok(())
+    }
+}
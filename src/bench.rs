@@ -0,0 +1,236 @@
+use crate::adapters::*;
+use crate::batch::{batch_files, WalkOptions};
+use crate::cache_stats::{CacheStats, STATS_FILE_ENV};
+use crate::config::{CachePath, RgaConfig};
+use crate::matching::{adapter_matcher, FileMeta};
+use crate::preproc::rga_preproc;
+use crate::{print_bytes, print_duration};
+use anyhow::*;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// how many of the slowest files are listed
+const SLOWEST_FILES: usize = 10;
+
+/// result of processing one file with an empty and with a filled cache
+#[derive(Debug)]
+pub struct FileTiming {
+    pub path: PathBuf,
+    pub adapter: String,
+    pub size: u64,
+    pub cold: Duration,
+    pub warm: Duration,
+    /// whether the second run read the output from the cache
+    pub cache_hit: bool,
+    pub error: Option<String>,
+}
+
+fn run(path: &Path, config: &RgaConfig) -> Result<Duration> {
+    let start = Instant::now();
+    let mut oup = rga_preproc(AdaptInfo {
+        inp: Box::new(File::open(path)?),
+        filepath_hint: std::env::current_dir()?.join(path),
+        is_real_file: true,
+        line_prefix: "".to_string(),
+        archive_recursion_depth: 0,
+        postprocess: true,
+        config: config.clone(),
+    })?;
+    std::io::copy(&mut oup, &mut std::io::sink())?;
+    Ok(start.elapsed())
+}
+
+/**
+ * Run the adapters on every file below the roots twice, first with an empty (temporary) cache, then with the filled one.
+ * The user's cache settings are used, except for the location of the cache.
+ * Files are processed one after another, so the times are not distorted by other adapters running at the same time.
+ */
+pub fn bench(roots: &[PathBuf], config: &RgaConfig) -> Result<Vec<FileTiming>> {
+    let cache_dir = tempfile::tempdir()?;
+    let stats_file = tempfile::NamedTempFile::new()?;
+    let mut config = config.clone();
+    config.cache.disabled = false;
+    config.cache.readonly = false;
+    config.cache.remote = None;
+    config.cache.path = CachePath(
+        cache_dir
+            .path()
+            .to_str()
+            .ok_or_else(|| format_err!("temporary directory not utf8"))?
+            .to_owned(),
+    );
+    // cache hits are counted with the stats of rga-preproc, see --rga-cache-stats
+    std::env::set_var(STATS_FILE_ENV, stats_file.path());
+
+    let adapters = enabled_adapters(config)?;
+    let matcher = adapter_matcher(&adapters, false)?;
+    let files = batch_files(roots, &config, &WalkOptions::default())?;
+    let mut timings = Vec::new();
+    for (i, path) in files.into_iter().enumerate() {
+        eprint!("\r{} files", i + 1);
+        let adapter = path
+            .file_name()
+            .and_then(|name| {
+                matcher(FileMeta {
+                    mimetype: None,
+                    lossy_filename: name.to_string_lossy().to_string(),
+                    lossy_path: path.to_string_lossy().to_string(),
+                    content: None,
+                })
+            })
+            .map(|(a, _)| a.metadata().name.clone())
+            .unwrap_or_else(|| "(other)".to_string());
+        let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        let mut timing = FileTiming {
+            path,
+            adapter,
+            size,
+            cold: Duration::default(),
+            warm: Duration::default(),
+            cache_hit: false,
+            error: None,
+        };
+        match run(&timing.path, &config) {
+            Ok(cold) => {
+                timing.cold = cold;
+                let hits_before = CacheStats::read(stats_file.path())?.hits;
+                match run(&timing.path, &config) {
+                    Ok(warm) => timing.warm = warm,
+                    Err(e) => timing.error = Some(format!("{:#}", e)),
+                }
+                timing.cache_hit = CacheStats::read(stats_file.path())?.hits > hits_before;
+            }
+            Err(e) => timing.error = Some(format!("{:#}", e)),
+        }
+        timings.push(timing);
+    }
+    eprintln!();
+    std::env::remove_var(STATS_FILE_ENV);
+    Ok(timings)
+}
+
+#[derive(Default)]
+struct AdapterTotals {
+    files: usize,
+    errors: usize,
+    size: u64,
+    cold: Duration,
+    warm: Duration,
+    cache_hits: usize,
+}
+
+/// per-adapter totals, and the files that took longest
+pub fn print_report(timings: &[FileTiming], oup: &mut dyn Write) -> Result<()> {
+    let mut per_adapter: BTreeMap<&str, AdapterTotals> = BTreeMap::new();
+    for t in timings {
+        let totals = per_adapter.entry(&t.adapter).or_default();
+        totals.files += 1;
+        totals.size += t.size;
+        totals.cold += t.cold;
+        totals.warm += t.warm;
+        if t.cache_hit {
+            totals.cache_hits += 1;
+        }
+        if t.error.is_some() {
+            totals.errors += 1;
+        }
+    }
+    writeln!(
+        oup,
+        "{:<16} {:>6} {:>10} {:>10} {:>10} {:>12} {:>11}",
+        "adapter", "files", "size", "cold", "warm", "cold speed", "cache hits"
+    )?;
+    for (adapter, t) in &per_adapter {
+        let speed = if t.cold.as_secs_f64() > 0.0 {
+            format!("{}/s", print_bytes(t.size as f64 / t.cold.as_secs_f64()))
+        } else {
+            "-".to_string()
+        };
+        writeln!(
+            oup,
+            "{:<16} {:>6} {:>10} {:>10} {:>10} {:>12} {:>11}",
+            adapter,
+            t.files,
+            print_bytes(t.size as f64),
+            print_duration(t.cold),
+            print_duration(t.warm),
+            speed,
+            format!("{}/{}", t.cache_hits, t.files - t.errors)
+        )?;
+    }
+
+    let mut slowest: Vec<&FileTiming> = timings.iter().filter(|t| t.error.is_none()).collect();
+    slowest.sort_by_key(|t| std::cmp::Reverse(t.cold));
+    if !slowest.is_empty() {
+        writeln!(oup, "\nslowest files:")?;
+    }
+    for t in slowest.iter().take(SLOWEST_FILES) {
+        writeln!(
+            oup,
+            "{:>10}  {} ({})",
+            print_duration(t.cold),
+            t.path.display(),
+            t.adapter
+        )?;
+    }
+    let failed: Vec<&FileTiming> = timings.iter().filter(|t| t.error.is_some()).collect();
+    if !failed.is_empty() {
+        writeln!(oup, "\nfailed files:")?;
+    }
+    for t in failed {
+        writeln!(
+            oup,
+            "{} ({}): {}",
+            t.path.display(),
+            t.adapter,
+            t.error.as_deref().unwrap_or_default()
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn report() -> Result<()> {
+        let timing = |path: &str, adapter: &str, cold_ms, error: Option<&str>| FileTiming {
+            path: PathBuf::from(path),
+            adapter: adapter.to_string(),
+            size: 1000,
+            cold: Duration::from_millis(cold_ms),
+            warm: Duration::from_millis(1),
+            cache_hit: error.is_none(),
+            error: error.map(String::from),
+        };
+        let mut oup = Vec::new();
+        print_report(
+            &[
+                timing("a.docx", "pandoc", 500, None),
+                timing("b.docx", "pandoc", 1500, None),
+                timing("c.pdf", "poppler", 200, Some("broken")),
+            ],
+            &mut oup,
+        )?;
+        let oup = String::from_utf8(oup)?;
+        let lines: Vec<&str> = oup.lines().collect();
+        assert!(lines[1].starts_with("pandoc"), "{}", oup);
+        assert!(lines[1].ends_with("2/2"), "{}", oup);
+        assert!(lines[2].ends_with("0/0"), "{}", oup);
+        assert!(
+            oup.contains("\nslowest files:\n     1.50s  b.docx (pandoc)\n"),
+            "{}",
+            oup
+        );
+        assert!(
+            oup.ends_with("failed files:\nc.pdf (poppler): broken\n"),
+            "{}",
+            oup
+        );
+        Ok(())
+    }
+}
@@ -1,7 +1,33 @@
 use anyhow::Context;
+use rga::fzf::{adapted_text, first_match, query_regex, MatchLocation};
+use rga::preproc::choose_adapter;
+use ripgrep_all as rga;
 
+use std::path::Path;
 use std::process::Command;
 
+/// start the program, Ok(false) if it's not installed
+fn try_spawn(cmd: &mut Command) -> std::io::Result<bool> {
+    use std::io::ErrorKind::*;
+    cmd.spawn().map_or_else(
+        |err| match err.kind() {
+            NotFound => Ok(false),
+            _ => Err(err),
+        },
+        |_| Ok(true),
+    )
+}
+
+/// where the first match of the query is in the file, to open it there
+fn locate(query: &str, fname: &str) -> anyhow::Result<(bool, Option<MatchLocation>)> {
+    // same as rga-fzf, so the adapted text is read from the cache
+    let config = rga::config::parse_args(&["rga-fzf", "--rga-cache-max-blob-len=10M"], false)?;
+    let path = Path::new(fname);
+    let is_text = choose_adapter(&config, path)?.1.is_none();
+    let text = adapted_text(path, &config)?;
+    Ok((is_text, first_match(&text, &query_regex(query))))
+}
+
 // TODO: add --rg-params=..., --rg-preview-params=... and --fzf-params=... params
 // TODO: remove passthrough_args
 fn main() -> anyhow::Result<()> {
@@ -11,21 +37,32 @@ fn main() -> anyhow::Result<()> {
     let fname = args.next().context("no filename")?;
     // let instance_id = std::env::var("RGA_FZF_INSTANCE").unwrap_or("unk".to_string());
 
+    let (is_text, location) = locate(&query, &fname).unwrap_or_else(|e| {
+        log::debug!("could not find the match in {}: {:#}", fname, e);
+        (false, None)
+    });
+
     if fname.ends_with(".pdf") {
-        use std::io::ErrorKind::*;
-        let worked = Command::new("evince")
-            .arg("--find")
-            .arg(&query)
-            .arg(&fname)
-            .spawn()
-            .map_or_else(
-                |err| match err.kind() {
-                    NotFound => Ok(false),
-                    _ => Err(err),
-                },
-                |_| Ok(true),
-            )?;
-        if worked {
+        let page = location.as_ref().and_then(|l| l.page);
+        let mut evince = Command::new("evince");
+        let mut zathura = Command::new("zathura");
+        if let Some(page) = page {
+            evince.arg(format!("--page-index={}", page));
+            zathura.arg(format!("--page={}", page));
+        }
+        if try_spawn(evince.arg("--find").arg(&query).arg(&fname))?
+            || try_spawn(zathura.arg(&fname))?
+        {
+            return Ok(());
+        }
+    }
+    if let (true, Some(location)) = (is_text, &location) {
+        // text files are searched as they are, so the line is the line in the file
+        if let Ok(editor) = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR")) {
+            Command::new(editor)
+                .arg(format!("+{}", location.line + 1))
+                .arg(&fname)
+                .status()?;
             return Ok(());
         }
     }
@@ -1,14 +1,45 @@
 use anyhow::Context;
 use rga::adapters::spawning::map_exe_error;
+use rga::fzf::{adapted_text, query_regex, write_preview};
 use ripgrep_all as rga;
 
+use std::path::Path;
 use std::process::{Command, Stdio};
 
+/// same as the searches, so the adapted text is read from the cache
+const CACHE_ARGS: &[&str] = &["rga-fzf", "--rga-cache-max-blob-len=10M"];
+
+/// the preview of the selected file, from the first match on
+fn preview(query: &str, file: &str) -> anyhow::Result<()> {
+    if file.is_empty() {
+        println!("[no file found]");
+        return Ok(());
+    }
+    let config = rga::config::parse_args(CACHE_ARGS, false)?;
+    let text = adapted_text(Path::new(file), &config)?;
+    // set by fzf
+    let height = std::env::var("FZF_PREVIEW_LINES")
+        .ok()
+        .and_then(|h| h.parse().ok())
+        .unwrap_or(40);
+    write_preview(
+        &text,
+        &query_regex(query),
+        height,
+        &mut std::io::stdout().lock(),
+    )
+}
+
 // TODO: add --rg-params=..., --rg-preview-params=... and --fzf-params=... params
 // TODO: remove passthrough_args
 fn main() -> anyhow::Result<()> {
     env_logger::init();
     let mut passthrough_args: Vec<String> = std::env::args().skip(1).collect();
+    if let [flag, query, file] = &passthrough_args[..] {
+        if flag == "--rga-fzf-preview" {
+            return preview(query, file);
+        }
+    }
     let inx = passthrough_args.iter().position(|e| !e.starts_with("-"));
     let initial_query = if let Some(inx) = inx {
         passthrough_args.remove(inx)
@@ -27,15 +58,16 @@ fn main() -> anyhow::Result<()> {
         .context("rga-fzf-open executable is in non-unicode path")?;
 
     let rg_prefix = format!(
-        "{} --files-with-matches --rga-cache-max-blob-len=10M",
-        preproc_exe
+        "{} --files-with-matches {}",
+        preproc_exe,
+        CACHE_ARGS[1..].join(" ")
     );
+    let exe = exe
+        .to_str()
+        .context("rga-fzf executable is in non-unicode path")?;
 
     let child = Command::new("fzf")
-        .arg(format!(
-            "--preview={} --pretty --context 5 {{q}} --rga-fzf-path=_{{}}",
-            preproc_exe
-        ))
+        .arg(format!("--preview={} --rga-fzf-preview {{q}} {{}}", exe))
         .arg("--preview-window=70%:wrap")
         .arg("--phony")
         .arg("--query")
@@ -1,39 +1,222 @@
+use rga::adapters::postproc::TextRecord;
 use rga::adapters::*;
+use rga::failures::Failure;
+use rga::list::{open_subfile, split_subfile_path};
 use rga::preproc::*;
+use rga::pretty::render_pretty;
 use rga::print_dur;
+use rga::remote::{is_url, open_url, url_file_name};
 use ripgrep_all as rga;
 
 use anyhow::Context;
 use log::debug;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
 use std::{fs::File, time::Instant};
 
+/// like std::io::copy, but with a larger buffer. Stdout is line buffered, large writes go to it directly
+fn copy(inp: &mut dyn Read, oup: &mut dyn Write) -> std::io::Result<u64> {
+    let mut buf = vec![0u8; 1 << 17];
+    let mut total = 0;
+    loop {
+        let n = match inp.read(&mut buf) {
+            Ok(0) => return Ok(total),
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        oup.write_all(&buf[..n])?;
+        total += n as u64;
+    }
+}
+
+/// removes `--format text|json` from the arguments, returns whether json was requested
+fn take_format_arg(args: &mut Vec<std::ffi::OsString>) -> anyhow::Result<bool> {
+    let mut json = false;
+    while let Some(i) = args.iter().position(|a| {
+        a.to_str()
+            .map_or(false, |a| a == "--format" || a.starts_with("--format="))
+    }) {
+        let arg = args.remove(i).into_string().expect("checked above");
+        let value = match arg.strip_prefix("--format=") {
+            Some(value) => value.to_string(),
+            None if i < args.len() => args.remove(i).to_string_lossy().into_owned(),
+            None => anyhow::bail!("--format needs a value (text or json)"),
+        };
+        json = match value.as_str() {
+            "text" => false,
+            "json" => true,
+            other => anyhow::bail!("unknown format {:?}, expected text or json", other),
+        };
+    }
+    Ok(json)
+}
+
+/// removes all occurrences of the flag from the arguments, returns whether it was given
+fn take_flag(args: &mut Vec<std::ffi::OsString>, flag: &str) -> bool {
+    let len = args.len();
+    args.retain(|a| a != flag);
+    args.len() != len
+}
+
+#[cfg(unix)]
+fn stdout_is_terminal() -> bool {
+    unsafe { libc::isatty(1) == 1 }
+}
+
+#[cfg(not(unix))]
+fn stdout_is_terminal() -> bool {
+    false
+}
+
+/// adds the source path to the records of the adapters
+fn copy_records(source_path: &str, inp: &mut dyn Read, oup: &mut dyn Write) -> anyhow::Result<()> {
+    for (i, line) in BufReader::with_capacity(1 << 17, inp)
+        .split(b'\n')
+        .enumerate()
+    {
+        let mut record = TextRecord::from_line(&line?, i as u64 + 1);
+        record.source_path = Some(source_path.to_string());
+        let mut out = serde_json::to_vec(&record)?;
+        out.push(b'\n');
+        oup.write_all(&out)?;
+    }
+    Ok(())
+}
+
+/// rg closes the pipe when it does not need more output (e.g. with -l or -m, or when it was quit).
+/// Writing only notices that when the adapter produces output, but e.g. pandoc writes nothing until
+/// it has converted the whole document. So watch the pipe and stop everything as soon as it's closed
+#[cfg(unix)]
+fn exit_when_stdout_closed() {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(1, &mut stat) } != 0 || stat.st_mode & libc::S_IFMT != libc::S_IFIFO {
+        return;
+    }
+    std::thread::spawn(|| {
+        let mut fd = libc::pollfd {
+            fd: 1,
+            events: 0,
+            revents: 0,
+        };
+        loop {
+            // POLLERR and POLLHUP are always reported
+            if unsafe { libc::poll(&mut fd, 1, -1) } < 0 {
+                if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return;
+            }
+            if fd.revents & (libc::POLLERR | libc::POLLHUP) != 0 {
+                debug!("stdout closed, stopping");
+                rga::signals::kill_and_exit(0);
+            }
+        }
+    });
+}
+
+/// stops the pandoc server (--rga-pandoc-server) and the persistent adapters when main returns
+struct ServerGuard;
+
+impl Drop for ServerGuard {
+    fn drop(&mut self) {
+        rga::adapters::pandoc_server::stop_server();
+        rga::adapters::persistent::stop_all();
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     env_logger::init();
+    // removes the temporary files when main returns, also after the adapters failed
+    let _cleanup = rga::signals::install()?;
+    #[cfg(unix)]
+    exit_when_stdout_closed();
     let mut arg_arr: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    let json = take_format_arg(&mut arg_arr)?;
+    // --pretty renders the json records, --color forces colors when not writing to a terminal
+    let pretty = take_flag(&mut arg_arr, "--pretty");
+    let color = take_flag(&mut arg_arr, "--color") || stdout_is_terminal();
     let last = arg_arr.pop().expect("No filename specified");
-    let config = rga::config::parse_args(arg_arr, true)?;
+    let mut config = rga::config::parse_args(arg_arr, true)?;
+    config.json_records = json || pretty;
     //clap::App::new("rga-preproc").arg(Arg::from_usage())
-    let path = {
-        let filepath = last;
-        std::env::current_dir()?.join(&filepath)
+    let url = last.to_str().filter(|a| is_url(a)).map(String::from);
+    let (i, path, is_real_file): (ReadBox, _, _) = match &url {
+        // not cached, since it can't be checked whether the file changed
+        Some(url) => (Box::new(open_url(url)?), url_file_name(url), false),
+        None if last == "-" => (Box::new(std::io::stdin()), PathBuf::from(STDIN_HINT), false),
+        // a file in an archive, e.g. backup.zip/docs/report.pdf. Only that file is extracted
+        None => match last.to_str().and_then(split_subfile_path) {
+            Some((archive, inner)) => {
+                let i = open_subfile(&archive, &inner, &config)?;
+                (i, PathBuf::from(&last), false)
+            }
+            None => {
+                // e.g. \\?\C:\... is searched like C:\..., with the same cache entries
+                let path = rga::paths::simplify(&std::env::current_dir()?.join(&last));
+                let i = File::open(&path).context("Specified input file not found")?;
+                (Box::new(i), path, true)
+            }
+        },
     };
-
-    let i = File::open(&path).context("Specified input file not found")?;
+    let source_path = url.unwrap_or_else(|| path.to_string_lossy().into_owned());
+    let ignore_errors = config.ignore_adapter_errors;
     let mut o = std::io::stdout();
     let ai = AdaptInfo {
-        inp: Box::new(i),
+        inp: i,
         filepath_hint: path,
-        is_real_file: true,
+        is_real_file,
         line_prefix: "".to_string(),
         archive_recursion_depth: 0,
         postprocess: true,
         config,
     };
 
+    // the pandoc server is kept running until the end, for the other documents in archives
+    let _server = ServerGuard;
+    // printed or passed to rga at the end, see --rga-verbose
+    let _detections = rga::detection::FlushGuard;
     let start = Instant::now();
-    let mut oup = rga_preproc(ai).context("during preprocessing")?;
+    // recorded for the summary rga prints after the search. With --rga-ignore-adapter-errors,
+    // rg does not see the error (it would count it), an error line is output instead
+    let failed = |e: anyhow::Error| -> anyhow::Result<()> {
+        let failure = Failure::new(&source_path, &e);
+        failure.record();
+        if !ignore_errors {
+            return Err(e);
+        }
+        eprintln!("rga: {}: {:#}", source_path, e);
+        if !json {
+            std::io::stdout().write_all(failure.error_line("").as_bytes())?;
+        }
+        Ok(())
+    };
+    let mut oup = match rga_preproc(ai).context("during preprocessing") {
+        Ok(oup) => oup,
+        Err(e) => return failed(e),
+    };
     debug!("finding and starting adapter took {}", print_dur(start));
-    std::io::copy(&mut oup, &mut o).context("copying adapter output to stdout")?;
+    let res = if pretty {
+        render_pretty(&source_path, &mut oup, &mut o.lock(), color)
+    } else if json {
+        copy_records(&source_path, &mut oup, &mut o.lock())
+    } else {
+        copy(&mut oup, &mut o.lock())
+            .map(|_| ())
+            .map_err(anyhow::Error::from)
+    };
+    match res {
+        // rg does not need more output, e.g. with -l. Dropping the output stops the adapters
+        Err(e)
+            if e.downcast_ref::<std::io::Error>()
+                .map_or(false, |e| e.kind() == std::io::ErrorKind::BrokenPipe) =>
+        {
+            debug!("stdout closed");
+            return Ok(());
+        }
+        Err(e) => return failed(e.context("copying adapter output to stdout")),
+        Ok(()) => {}
+    };
     debug!("running adapter took {} total", print_dur(start));
     Ok(())
 }
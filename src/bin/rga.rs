@@ -1,14 +1,44 @@
 use anyhow::Result;
-use rga::adapters::spawning::map_exe_error;
+use rga::adapters::spawning::{map_exe_error, subprocess_exited, subprocess_started};
 use rga::adapters::*;
-use rga::config::{split_args, RgaConfig};
+use rga::adapters::{pandoc_server, persistent};
+use rga::batch::{batch_files, pre_globs, preprocess_batch, WalkOptions, RG_ARGS};
+use rga::bench::{bench, print_report};
+use rga::cache_stats::{CacheStats, STATS_FILE_ENV};
+use rga::completions::completions_app;
+use rga::config::{
+    find_project_cache, search_root, set_cache_path, split_args, CachePath, RgaConfig,
+};
+use rga::daemon::{search_with_daemon, serve};
+use rga::detection::{read_detections, Detection, DETECTIONS_FILE_ENV};
+use rga::doctor::doctor;
+use rga::exit_code::{is_quiet, SearchStatus};
+use rga::failures::{print_summary, read_failures, Failure, FAILURES_FILE_ENV};
+use rga::index::{build_index, index_path, search_index};
+use rga::interactive::interactive;
+use rga::list::{list_subfiles, preprocess_subfile, split_subfile_path};
 use rga::matching::*;
-use rga::print_dur;
+use rga::preproc::{
+    cache_key_path, cached_output, choose_adapter, matching_adapters, preprocess_stdin,
+};
+use rga::preproc_cache::{export_cache, import_cache, open_cache};
+use rga::remote::{is_url, preprocess_url};
+use rga::report::{self, report};
+use rga::rg_args::RgArgs;
+use rga::rg_json::{set_json_prefixes, JsonOutput};
+use rga::ssh::{search_remote, shell_quote};
+use rga::watch::watch;
+use rga::{print_bytes, print_dur};
 use ripgrep_all as rga;
 use structopt::StructOpt;
 
 use schemars::schema_for;
-use std::process::Command;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 fn list_adapters(args: RgaConfig) -> Result<()> {
@@ -22,6 +52,9 @@ fn list_adapters(args: RgaConfig) -> Result<()> {
             .iter()
             .map(|m| match m {
                 FastFileMatcher::FileExtension(ext) => format!(".{}", ext),
+                FastFileMatcher::PathGlob(glob) => glob.clone(),
+                FastFileMatcher::Magic(hex) => format!("magic {}", hex),
+                FastFileMatcher::ContentRegex(re) => format!("content /{}/", re),
             })
             .collect::<Vec<_>>()
             .join(", ");
@@ -53,13 +86,411 @@ fn list_adapters(args: RgaConfig) -> Result<()> {
     for adapter in enabled_adapters {
         print(adapter)
     }
-    println!("The following adapters are disabled by default, and can be enabled using '--rga-adapters=+pdfpages,tesseract':\n");
+    println!("The following adapters are disabled by default, and can be enabled using '--rga-adapters=+strings,+gron':\n");
     for adapter in disabled_adapters {
         print(adapter)
     }
+    for (name, description) in OPTIONAL_MODES {
+        println!(" - **{}**\n     {}  \n", name, description);
+    }
     return Ok(());
 }
-fn main() -> anyhow::Result<()> {
+/// rga cache
+#[derive(StructOpt, Debug)]
+#[structopt(name = "rga cache")]
+enum CacheCommand {
+    /// Show the size of the cache per adapter
+    Stats,
+    /// List the cached files
+    Ls {
+        /// Only list the outputs of this adapter
+        #[structopt(long)]
+        adapter: Option<String>,
+    },
+    /// Delete the cache, or the outputs of one adapter
+    Clear {
+        /// Only delete the outputs of this adapter
+        #[structopt(long)]
+        adapter: Option<String>,
+    },
+    /// Write all cache entries to a file, e.g. to seed the cache of a CI pipeline.
+    ///
+    /// Entries are keyed by the path and modification time of the files unless --rga-cache-content-hash is used,
+    /// so an import is only useful on machines with the same files at the same paths (or with content hashing).
+    Export {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Add the entries of a file written by `rga cache export` to the cache
+    Import {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Print the cached output of the adapter for a file, without running the adapter
+    Show {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
+/// rga index
+#[derive(StructOpt, Debug)]
+#[structopt(name = "rga index")]
+enum IndexCommand {
+    /// Add the text of the files in the paths (default: the current directory) to the index, or update it
+    Build {
+        #[structopt(parse(from_os_str))]
+        paths: Vec<PathBuf>,
+    },
+    /// Search the index, best matches first
+    Search {
+        query: String,
+        /// How many files to show
+        #[structopt(long, default_value = "10")]
+        limit: usize,
+    },
+}
+
+/// `rga index build` or `rga index search`. Searching for "index" in a file named e.g. "build" still works if the file exists
+fn is_index_command(args: &[OsString]) -> bool {
+    match args {
+        [first, second, ..] => {
+            first == "index"
+                && ["build", "search"].iter().any(|c| second == c)
+                && !Path::new(second).exists()
+        }
+        _ => false,
+    }
+}
+
+fn index_command(config: &RgaConfig, args: &[OsString]) -> Result<()> {
+    let command = IndexCommand::from_iter(
+        std::iter::once(OsString::from("rga index")).chain(args.iter().cloned()),
+    );
+    let index_dir = index_path(config);
+    match command {
+        IndexCommand::Build { paths } => {
+            let paths = if paths.is_empty() {
+                vec![PathBuf::from(".")]
+            } else {
+                paths
+            };
+            add_exe_to_path()?;
+            let stats = build_index(&index_dir, &paths, config);
+            pandoc_server::stop_server();
+            persistent::stop_all();
+            let stats = stats?;
+            println!(
+                "indexed {} files, {} unchanged, {} removed, {} failed",
+                stats.added, stats.unchanged, stats.removed, stats.failed
+            );
+        }
+        IndexCommand::Search { query, limit } => {
+            for hit in search_index(&index_dir, &query, limit)? {
+                println!("{}: {}", hit.path, hit.snippet);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// rga serve
+#[derive(StructOpt, Debug)]
+#[structopt(name = "rga serve")]
+struct ServeCommand {
+    /// The directory with the files to search and extract
+    #[structopt(parse(from_os_str))]
+    dir: PathBuf,
+    /// The address to listen on
+    #[structopt(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+}
+
+/// `rga serve <dir> [--listen=<address>]`. Searching for "serve" in a directory still works with other rg options
+/// (or with `rga -e serve <dir>`)
+fn is_serve_command(args: &[OsString]) -> bool {
+    match args {
+        [first, dir, rest @ ..] => {
+            first == "serve"
+                && Path::new(dir).is_dir()
+                && rest
+                    .iter()
+                    .all(|a| a.to_string_lossy().starts_with("--listen"))
+        }
+        _ => false,
+    }
+}
+
+/// `rga cache stats`, `rga cache ls`, `rga cache clear`, `rga cache export`, `rga cache import` or `rga cache show`.
+/// Searching for "cache" in a file named e.g. "stats" still works if the file exists
+fn is_cache_command(args: &[OsString]) -> bool {
+    match args {
+        [first, second, ..] => {
+            first == "cache"
+                && ["stats", "ls", "clear", "export", "import", "show"]
+                    .iter()
+                    .any(|c| second == c)
+                && !Path::new(second).exists()
+        }
+        _ => false,
+    }
+}
+
+/// the adapter name of a cache store ("{adapter}.v{version}")
+fn db_adapter_name(db_name: &str) -> &str {
+    db_name.rsplitn(2, ".v").last().unwrap_or(db_name)
+}
+
+fn cache_command(config: RgaConfig, args: &[OsString]) -> Result<()> {
+    let command = CacheCommand::from_iter(
+        std::iter::once(OsString::from("rga cache")).chain(args.iter().cloned()),
+    );
+    let path = Path::new(&config.cache.path.0);
+    if let CacheCommand::Clear { adapter: None } = command {
+        if config.cache.readonly {
+            anyhow::bail!("the cache is read-only (--rga-cache-readonly)");
+        }
+        if path.exists() {
+            std::fs::remove_dir_all(path)?;
+        }
+        println!("deleted cache at {}", path.display());
+        return Ok(());
+    }
+    if let CacheCommand::Show { file } = &command {
+        // same path as rga-preproc gets
+        let path = std::env::current_dir()?.join(file);
+        let (adapter, output) = cached_output(&config, &path)?;
+        let mut output = output.ok_or_else(|| {
+            anyhow::format_err!(
+                "the output of adapter {} for {} is not cached",
+                adapter,
+                file.display()
+            )
+        })?;
+        std::io::copy(&mut output, &mut std::io::stdout().lock())?;
+        return Ok(());
+    }
+    if let CacheCommand::Import { .. } = command {
+        if config.cache.readonly {
+            anyhow::bail!("the cache is read-only (--rga-cache-readonly)");
+        }
+    }
+    let mut cache = open_cache(&config.cache)?
+        .ok_or_else(|| anyhow::format_err!("the cache is disabled or does not exist"))?;
+    let (enabled_adapters, disabled_adapters) = get_all_adapters(config.custom_adapters.clone());
+    let all_adapters: Vec<_> = enabled_adapters
+        .into_iter()
+        .chain(disabled_adapters)
+        .collect();
+    match command {
+        CacheCommand::Stats => {
+            let mut per_db: HashMap<String, (usize, u64)> = HashMap::new();
+            for entry in cache.entries()? {
+                let stats = per_db.entry(entry.db_name).or_default();
+                stats.0 += 1;
+                stats.1 += entry.size;
+            }
+            let mut per_db: Vec<_> = per_db.into_iter().collect();
+            per_db.sort_by_key(|(_, (_, size))| std::cmp::Reverse(*size));
+            let on_disk: u64 = std::fs::read_dir(path)?
+                .filter_map(|f| f.ok()?.metadata().ok())
+                .map(|m| m.len())
+                .sum();
+            println!(
+                "cache: {} ({} on disk)",
+                path.display(),
+                print_bytes(on_disk as f64)
+            );
+            for (db_name, (count, size)) in &per_db {
+                println!(
+                    "{:>12} {:>8} entries  {}",
+                    print_bytes(*size as f64),
+                    count,
+                    db_name
+                );
+            }
+            println!(
+                "{:>12} {:>8} entries  total",
+                print_bytes(per_db.iter().map(|(_, (_, size))| *size).sum::<u64>() as f64),
+                per_db.iter().map(|(_, (count, _))| count).sum::<usize>()
+            );
+        }
+        CacheCommand::Ls { adapter } => {
+            let mut entries = cache.entries()?;
+            entries.sort_by_key(|e| std::cmp::Reverse(e.last_access));
+            for entry in entries {
+                let name = db_adapter_name(&entry.db_name);
+                if adapter.as_deref().map(|a| a != name).unwrap_or(false) {
+                    continue;
+                }
+                let recurses = all_adapters
+                    .iter()
+                    .find(|a| a.metadata().name == name)
+                    .map(|a| a.metadata().recurses)
+                    .unwrap_or(false);
+                let last_access =
+                    chrono::NaiveDateTime::from_timestamp_opt((entry.last_access / 1000) as i64, 0)
+                        .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string())
+                        .unwrap_or_default();
+                println!(
+                    "{:>12}  {}  {:<12}  {}",
+                    print_bytes(entry.size as f64),
+                    last_access,
+                    entry.db_name,
+                    cache_key_path(&entry.key, recurses)
+                        .map(|p| p.display().to_string())
+                        .unwrap_or_else(|| "?".to_string())
+                );
+            }
+        }
+        CacheCommand::Clear {
+            adapter: Some(adapter),
+        } => {
+            let meta = all_adapters
+                .iter()
+                .map(|a| a.metadata())
+                .find(|m| m.name == adapter)
+                .ok_or_else(|| anyhow::format_err!("unknown adapter {}", adapter))?;
+            // outputs of older adapter versions are stored separately
+            let mut deleted = 0;
+            for version in 1..=meta.version {
+                deleted += cache.clear_db(&format!("{}.v{}", meta.name, version))?;
+            }
+            println!("deleted {} cache entries of adapter {}", deleted, adapter);
+        }
+        CacheCommand::Export { file } => {
+            let mut out = std::io::BufWriter::new(std::fs::File::create(&file)?);
+            let count = export_cache(cache.as_ref(), &mut out)?;
+            out.flush()?;
+            println!("exported {} cache entries to {}", count, file.display());
+        }
+        CacheCommand::Import { file } => {
+            let mut inp = std::io::BufReader::new(std::fs::File::open(&file)?);
+            let count = import_cache(cache.as_mut(), &mut inp)?;
+            println!("imported {} cache entries from {}", count, file.display());
+        }
+    }
+    Ok(())
+}
+
+/// print the completions for rga's flags and the flags of the rg in PATH
+fn print_completions(shell: &str) -> Result<()> {
+    let shell: structopt::clap::Shell = shell.parse().map_err(|e: String| anyhow::anyhow!(e))?;
+    let rg_help = match Command::new("rg").arg("--help").output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+        Err(e) => {
+            log::warn!(
+                "could not run rg --help, only completing the rga flags: {}",
+                e
+            );
+            String::new()
+        }
+    };
+    completions_app(&rg_help).gen_completions_to("rga", shell, &mut std::io::stdout());
+    Ok(())
+}
+
+/// --rga-verbose: how the files were handled, as messages like those of rg with --json
+fn print_detections(detections: &[Detection], json: bool) -> Result<()> {
+    if json {
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        for detection in detections {
+            let message = serde_json::json!({"type": "rga_detection", "data": detection});
+            writeln!(stdout, "{}", message)?;
+        }
+    } else {
+        for detection in detections {
+            eprintln!("rga: {}", detection);
+        }
+    }
+    Ok(())
+}
+
+/// print the adapter chosen for the file, for debugging why a file is not searched as expected
+fn print_adapter(config: &RgaConfig, path: &Path) -> Result<()> {
+    let (mimetype, adapter) = choose_adapter(config, path)?;
+    match &mimetype {
+        Some(mimetype) => println!("mime type: {}", mimetype),
+        None => println!("mime type: not detected (only with --rga-accurate)"),
+    }
+    match adapter {
+        Some((adapter, reason)) => {
+            println!(
+                "adapter: {} (matched by {})",
+                adapter.metadata().name,
+                reason
+            );
+            // e.g. foo.tar.zst: decompress, then tar for foo.tar
+            if let Some(inner) = adapter.passthrough_name(path).filter(|inner| inner != path) {
+                let adapters = enabled_adapters(config)?;
+                let chain = adapter_chain(adapter_matcher(&adapters, false)?, &inner);
+                for (path, adapter, reason) in &chain {
+                    println!(
+                        "then: {} for {} (matched by {})",
+                        adapter.metadata().name,
+                        path.display(),
+                        reason
+                    );
+                }
+                if chain.is_empty() {
+                    println!("then: searched as text ({})", inner.display());
+                }
+            }
+            // the order can be changed with --rga-adapter-priority
+            for (other, reason) in matching_adapters(config, path)?.iter().skip(1) {
+                println!(
+                    "also matched: {} (matched by {})",
+                    other.metadata().name,
+                    reason
+                );
+            }
+        }
+        None => {
+            if config.strings.fallback {
+                println!("adapter: none, searched as text (extracting strings if it is binary)");
+            } else if config.accurate {
+                println!("adapter: none, searched as text");
+            } else {
+                println!("adapter: none, rg searches the file itself");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let cleanup = match rga::signals::install() {
+        Ok(cleanup) => cleanup,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = rga_main() {
+        eprintln!("Error: {:?}", e);
+        // like rg, 1 means that nothing matched
+        rga::signals::exit(2);
+    }
+    drop(cleanup);
+}
+
+/// start rg, registered so that the signals rga gets are forwarded to it (see rga::signals)
+fn spawn_rg(cmd: &mut Command) -> Result<Child> {
+    let child = cmd
+        .spawn()
+        .map_err(|e| map_exe_error(e, "rg", "Please make sure you have ripgrep installed."))?;
+    subprocess_started(child.id());
+    Ok(child)
+}
+
+fn wait_rg(child: &mut Child) -> Result<ExitStatus> {
+    let status = child.wait()?;
+    subprocess_exited(child.id());
+    Ok(status)
+}
+
+fn rga_main() -> Result<()> {
     // set debugging as early as possible
     if std::env::args().position(|e| e == "--debug").is_some() {
         std::env::set_var("RUST_LOG", "debug");
@@ -67,7 +498,7 @@ fn main() -> anyhow::Result<()> {
 
     env_logger::init();
 
-    let (config, mut passthrough_args) = split_args(false)?;
+    let (mut config, mut passthrough_args) = split_args(false)?;
 
     if config.print_config_schema {
         println!("{}", serde_json::to_string_pretty(&schema_for!(RgaConfig))?);
@@ -76,7 +507,53 @@ fn main() -> anyhow::Result<()> {
     if config.list_adapters {
         return list_adapters(config);
     }
-    if let Some(path) = config.fzf_path {
+    if let Some(shell) = &config.completions {
+        return print_completions(shell);
+    }
+    if let Some(file) = &config.print_adapter {
+        return print_adapter(&config, Path::new(file));
+    }
+    if config.doctor {
+        // the same programs are found as when searching
+        add_exe_to_path()?;
+        let problems = doctor(&config)?;
+        if problems > 0 {
+            anyhow::bail!("found {} problems", problems);
+        }
+        return Ok(());
+    }
+    if config.cache.path == CachePath::default() {
+        let search_root = if is_cache_command(&passthrough_args) {
+            match &passthrough_args[1..] {
+                [show, file, ..] if show == "show" => PathBuf::from(file),
+                _ => PathBuf::from("."),
+            }
+        } else {
+            search_root(&passthrough_args)
+        };
+        if let Some(dir) = find_project_cache(&search_root) {
+            log::debug!("using project cache {}", dir.display());
+            set_cache_path(&mut config, &dir)?;
+        }
+    }
+    if is_cache_command(&passthrough_args) {
+        return cache_command(config, &passthrough_args[1..]);
+    }
+    if is_index_command(&passthrough_args) {
+        return index_command(&config, &passthrough_args[1..]);
+    }
+    if is_serve_command(&passthrough_args) {
+        let command = ServeCommand::from_iter(
+            std::iter::once(OsString::from("rga serve"))
+                .chain(passthrough_args[1..].iter().cloned()),
+        );
+        add_exe_to_path()?;
+        let res = rga::http::serve(&command.dir, &command.listen, &config);
+        pandoc_server::stop_server();
+        persistent::stop_all();
+        return res;
+    }
+    if let Some(path) = config.fzf_path.take() {
         if path == "_" {
             // fzf found no result, ignore everything and return
             println!("[no file found]");
@@ -85,60 +562,482 @@ fn main() -> anyhow::Result<()> {
         passthrough_args.push(std::ffi::OsString::from(&path[1..]));
     }
 
-    if passthrough_args.len() == 0 {
+    if config.list_subfiles {
+        return print_subfiles(&config, &passthrough_args);
+    }
+
+    if config.interactive {
+        if let Some(location) = interactive(&config, &passthrough_args)? {
+            println!("{}", location);
+        }
+        return Ok(());
+    }
+
+    if config.bench {
+        let roots: Vec<PathBuf> = passthrough_args.iter().map(PathBuf::from).collect();
+        let roots = if roots.is_empty() {
+            vec![PathBuf::from(".")]
+        } else {
+            roots
+        };
+        add_exe_to_path()?;
+        let timings = bench(&roots, &config);
+        pandoc_server::stop_server();
+        persistent::stop_all();
+        return print_report(&timings?, &mut std::io::stdout().lock());
+    }
+
+    if config.report {
+        let roots: Vec<PathBuf> = passthrough_args.iter().map(PathBuf::from).collect();
+        let roots = if roots.is_empty() {
+            vec![PathBuf::from(".")]
+        } else {
+            roots
+        };
+        add_exe_to_path()?;
+        let res = report(&roots, &config)
+            .and_then(|files| report::print_report(&files, &config, &mut std::io::stdout().lock()));
+        pandoc_server::stop_server();
+        persistent::stop_all();
+        return res;
+    }
+
+    if config.watch {
+        let roots: Vec<PathBuf> = passthrough_args.iter().map(PathBuf::from).collect();
+        let roots = if roots.is_empty() {
+            vec![PathBuf::from(".")]
+        } else {
+            roots
+        };
+        add_exe_to_path()?;
+        let res = watch(&roots, &config);
+        pandoc_server::stop_server();
+        persistent::stop_all();
+        return res;
+    }
+
+    if config.daemon {
+        add_exe_to_path()?;
+        let res = serve(&config);
+        pandoc_server::stop_server();
+        persistent::stop_all();
+        return res;
+    }
+
+    if config.warm_cache {
+        if config.cache.disabled {
+            anyhow::bail!("--rga-warm-cache can't be used with --rga-no-cache");
+        }
+        if config.cache.readonly {
+            anyhow::bail!("--rga-warm-cache can't be used with --rga-cache-readonly");
+        }
+        // search for something that never matches, so rg runs rga-preproc on every file and reads
+        // all of its output (which writes it to the cache) without printing anything
+        let search_nothing = ["--text", "--quiet", "--regexp", "\\x00rga warm cache\\x00"];
+        passthrough_args.splice(0..0, search_nothing.iter().map(OsString::from));
+        if passthrough_args.len() == search_nothing.len() {
+            passthrough_args.push(".".into());
+        }
+    }
+
+    if passthrough_args.len() == 0 && !config.print_rg_args {
         // rg would show help. Show own help instead.
         RgaConfig::clap().print_help()?;
         println!("");
         return Ok(());
     }
 
-    let adapters = get_adapters_filtered(config.custom_adapters.clone(), &config.adapters)?;
-
-    let pre_glob = if !config.accurate {
-        let extensions = adapters
+    if let Some(remote) = &config.remote {
+        if config.location_map.is_some() {
+            anyhow::bail!("--rga-location-map can't be used with --rga-remote");
+        }
+        if RgArgs::parse(&passthrough_args)
+            .paths
             .iter()
-            .flat_map(|a| &a.metadata().fast_matchers)
-            .flat_map(|m| match m {
-                FastFileMatcher::FileExtension(ext) => vec![ext.clone(), ext.to_ascii_uppercase()],
-            })
-            .collect::<Vec<_>>()
-            .join(",");
-        format!("*.{{{}}}", extensions)
-    } else {
-        "*".to_owned()
+            .any(|a| a == "-" || is_url(&a.to_string_lossy()))
+        {
+            anyhow::bail!("--rga-remote can't search stdin or URLs");
+        }
+        let code = search_remote(&config, remote, &passthrough_args)?;
+        rga::signals::exit(code);
+    }
+
+    // rg can't read URLs and files in archives (e.g. backup.zip/docs/report.pdf) and doesn't run rga-preproc
+    // on stdin ("-"), these are preprocessed here and the output is searched by rg from stdin
+    // only the paths, a pattern like https://internal or foo/bar (with a file foo) is searched for
+    let mut args = RgArgs::parse(&passthrough_args);
+    let is_stream = |a: &OsString| {
+        let a = a.to_string_lossy();
+        a == "-" || is_url(&a) || split_subfile_path(&a).is_some()
     };
+    let streams: Vec<String> = args
+        .paths
+        .iter()
+        .filter(|a| is_stream(a))
+        .map(|a| a.to_string_lossy().into_owned())
+        .collect();
+    if !streams.is_empty() {
+        args.paths.retain(|a| !is_stream(a));
+        passthrough_args = args.to_args();
+    }
+    // with only streams, rg would search the current directory
+    let search_local = streams.is_empty() || !args.paths.is_empty();
+
+    if config.git.history {
+        let heads = git_history_paths(&passthrough_args);
+        passthrough_args.extend(heads);
+    }
+
+    if config.use_daemon
+        && !config.warm_cache
+        && !config.print_rg_args
+        && config.location_map.is_none()
+        && streams.is_empty()
+    {
+        if let Some(result) = search_with_daemon(&config, &passthrough_args)? {
+            print!("{}", result.output);
+            eprint!("{}", result.errors);
+            std::io::stdout().flush()?;
+            rga::signals::exit(result.exit_code);
+        }
+        log::debug!("no daemon running, searching without it");
+    }
+
+    let pre_glob = pre_globs(&config)?;
+
+    // the locations of the matches are taken from the output of rg --json, which is printed as text
+    // if it was not asked for
+    let text_config = config.clone();
+    let user_json = passthrough_args.iter().any(|a| a == "--json");
+    if config.location_map.is_some() && !user_json {
+        passthrough_args.insert(0, "--json".into());
+    }
+    if passthrough_args.iter().any(|a| a == "--json") {
+        set_json_prefixes(&mut config)?;
+    }
+    let output = Arc::new(Mutex::new(JsonOutput::new(
+        Some(text_config).filter(|_| !user_json),
+        config.location_map.as_deref().map(Path::new),
+    )?));
 
     add_exe_to_path()?;
 
-    let rg_args = vec![
-        "--no-line-number",
-        // smart case by default because within weird files
-        // we probably can't really trust casing anyways
-        "--smart-case",
-    ];
+    let rg_args = RG_ARGS.to_vec();
 
     let exe = std::env::current_exe().expect("Could not get executable location");
     let preproc_exe = exe.with_file_name("rga-preproc");
 
+    if config.print_rg_args {
+        let preproc_exe = preproc_exe.to_string_lossy();
+        let args = rg_args
+            .iter()
+            .copied()
+            .chain(vec!["--pre", preproc_exe.as_ref()])
+            .chain(pre_glob.iter().flat_map(|g| vec!["--pre-glob", g.as_str()]))
+            .map(shell_quote)
+            .collect::<Vec<_>>();
+        println!("{}", args.join(" "));
+        eprintln!(
+            "# rga-preproc reads its settings from the environment, to use the same as rga:\n# export RGA_CONFIG={}",
+            shell_quote(&std::env::var("RGA_CONFIG").unwrap_or_default())
+        );
+        return Ok(());
+    }
+
+    // every rga-preproc process appends to this file, summed up after rg exits
+    let stats_file = if config.cache.stats {
+        let file = tempfile::NamedTempFile::new()?;
+        std::env::set_var(STATS_FILE_ENV, file.path());
+        Some(file)
+    } else {
+        None
+    };
+    // and the files it could not preprocess to this one, listed at the end
+    let failures_file = tempfile::NamedTempFile::new()?;
+    std::env::set_var(FAILURES_FILE_ENV, failures_file.path());
+    // and with --rga-verbose, how it handled the files
+    let detections_file = if config.verbose {
+        let file = tempfile::NamedTempFile::new()?;
+        std::env::set_var(DETECTIONS_FILE_ENV, file.path());
+        Some(file)
+    } else {
+        None
+    };
+
     let before = Instant::now();
+    let mut status = SearchStatus::default();
+    if !search_local {
+        log::debug!("only searching stdin and URLs");
+    } else if config.batch {
+        batch_search(
+            &config,
+            &rg_args,
+            &pre_glob,
+            passthrough_args.clone(),
+            &output,
+            &mut status,
+        )?;
+    } else {
+        let mut cmd = Command::new("rg");
+        cmd.args(rg_args)
+            .arg("--pre")
+            .arg(preproc_exe)
+            .args(pre_glob.iter().flat_map(|g| vec!["--pre-glob", g.as_str()]))
+            .args(&passthrough_args);
+        if passthrough_args.iter().any(|a| a == "--json") {
+            cmd.stdout(Stdio::piped());
+        }
+        log::debug!("rg command to run: {:?}", cmd);
+        let mut child = spawn_rg(&mut cmd)?;
+        let forward = forward_json(&mut child, &output);
+        status.add(wait_rg(&mut child)?);
+        if let Some(forward) = forward {
+            forward.join().expect("json output thread panicked")?;
+        }
+    }
+    if !streams.is_empty() {
+        search_streams(
+            &config,
+            &rg_args,
+            &passthrough_args,
+            &streams,
+            &output,
+            &mut status,
+        )?;
+    }
+    output.lock().unwrap().finish()?;
+
+    log::debug!("running rg took {}", print_dur(before));
+    if let Some(stats_file) = stats_file {
+        eprintln!("{}", CacheStats::read(stats_file.path())?);
+    }
+    if let Some(detections_file) = &detections_file {
+        // the files preprocessed by this process (--rga-batch)
+        rga::detection::flush()?;
+        print_detections(&read_detections(detections_file.path())?, user_json)?;
+    }
+    for fallback in fallback::degraded(&enabled_adapters(&config)?) {
+        eprintln!(
+            "rga: {} is not installed, so for the adapter {} {}",
+            fallback.program, fallback.adapter, fallback.description
+        );
+    }
+    let failures = read_failures(failures_file.path())?;
+    print_summary(&failures, &mut std::io::stderr())?;
+    if !failures.is_empty() && !config.ignore_adapter_errors {
+        status.errored = true;
+    }
+    if config.warm_cache {
+        println!("cache warmed in {}", print_dur(before));
+    }
+    drop(failures_file);
+    let code = status.code(is_quiet(&passthrough_args));
+    if code != 0 {
+        std::io::stdout().flush()?;
+        rga::signals::exit(code);
+    }
+    Ok(())
+}
+
+/// --rga-batch: rg searches the files without an adapter directly, then the output of the others
+/// (preprocessed in this process) from stdin
+fn batch_search(
+    config: &RgaConfig,
+    rg_args: &[&str],
+    pre_glob: &[String],
+    passthrough_args: Vec<OsString>,
+    output: &Arc<Mutex<JsonOutput>>,
+    status: &mut SearchStatus,
+) -> Result<()> {
+    let args = RgArgs::parse(&passthrough_args);
+    let roots = args.roots();
+    let walk = args.walk_options();
+    if pre_glob != ["*"] {
+        let mut cmd = Command::new("rg");
+        cmd.args(rg_args)
+            .args(&args.options)
+            .args(
+                pre_glob
+                    .iter()
+                    .flat_map(|g| vec!["--glob".to_string(), format!("!{}", g)]),
+            )
+            .args(args.positional_args(&roots));
+        if args.has("--json") {
+            cmd.stdout(Stdio::piped());
+        }
+        log::debug!("rg command for files without adapter: {:?}", cmd);
+        let mut child = spawn_rg(&mut cmd)?;
+        let forward = forward_json(&mut child, output);
+        status.add(wait_rg(&mut child)?);
+        if let Some(forward) = forward {
+            forward.join().expect("json output thread panicked")?;
+        }
+    }
+
+    let files = batch_files(&roots, config, &walk)?;
+    log::debug!("preprocessing {} files", files.len());
     let mut cmd = Command::new("rg");
     cmd.args(rg_args)
-        .arg("--pre")
-        .arg(preproc_exe)
-        .arg("--pre-glob")
-        .arg(pre_glob)
-        .args(passthrough_args);
-    log::debug!("rg command to run: {:?}", cmd);
-    let mut child = cmd
-        .spawn()
-        .map_err(|e| map_exe_error(e, "rg", "Please make sure you have ripgrep installed."))?;
+        .args(args.to_args_with_paths(&["-"]))
+        .stdin(Stdio::piped());
+    if args.has("--json") {
+        cmd.stdout(Stdio::piped());
+    }
+    log::debug!("rg command for preprocessed files: {:?}", cmd);
+    let mut child = spawn_rg(&mut cmd)?;
+    let forward = forward_json(&mut child, output);
+    let mut stdin = child.stdin.take().expect("is piped");
+    let res = preprocess_batch(files, config, &mut stdin);
+    pandoc_server::stop_server();
+    persistent::stop_all();
+    drop(stdin);
+    status.add(wait_rg(&mut child)?);
+    if let Some(forward) = forward {
+        forward.join().expect("json output thread panicked")?;
+    }
+    res
+}
 
-    child.wait()?;
+/// stdin ("-"), the remote files and the files in archives are preprocessed in this process, rg searches their output from stdin
+fn search_streams(
+    config: &RgaConfig,
+    rg_args: &[&str],
+    args: &[OsString],
+    streams: &[String],
+    output: &Arc<Mutex<JsonOutput>>,
+    status: &mut SearchStatus,
+) -> Result<()> {
+    let args = RgArgs::parse(args);
+    let mut cmd = Command::new("rg");
+    // the local paths were searched already
+    cmd.args(rg_args)
+        .args(args.to_args_with_paths(&["-"]))
+        .stdin(Stdio::piped());
+    if args.has("--json") {
+        cmd.stdout(Stdio::piped());
+    }
+    log::debug!("rg command for stdin and URLs: {:?}", cmd);
+    let mut child = spawn_rg(&mut cmd)?;
+    let forward = forward_json(&mut child, output);
+    let mut stdin = child.stdin.take().expect("is piped");
+    for stream in streams {
+        let oup = if stream == "-" {
+            preprocess_stdin(config)
+        } else if is_url(stream) {
+            preprocess_url(stream, config)
+        } else {
+            preprocess_subfile(stream, config)
+        };
+        let res = oup.and_then(|mut oup| Ok(std::io::copy(&mut oup, &mut stdin)?));
+        match res {
+            Ok(_) => {}
+            Err(e)
+                if e.downcast_ref::<std::io::Error>()
+                    .map_or(false, |e| e.kind() == std::io::ErrorKind::BrokenPipe) =>
+            {
+                log::debug!("rg does not need more output");
+                break;
+            }
+            Err(e) => {
+                eprintln!("rga: {:#}", e);
+                Failure::new(stream, &e).record();
+            }
+        }
+    }
+    drop(stdin);
+    status.add(wait_rg(&mut child)?);
+    if let Some(forward) = forward {
+        forward.join().expect("json output thread panicked")?;
+    }
+    Ok(())
+}
 
-    log::debug!("running rg took {}", print_dur(before));
+/// with --json, rg's output is piped through this to move the prefixes of files in archives
+/// and pages out of the matched lines and to write the --rga-location-map (see rg_json)
+fn forward_json(
+    child: &mut Child,
+    output: &Arc<Mutex<JsonOutput>>,
+) -> Option<std::thread::JoinHandle<Result<()>>> {
+    let rg_stdout = child.stdout.take()?;
+    let output = output.clone();
+    Some(std::thread::spawn(move || {
+        let stdout = std::io::stdout();
+        let mut oup = stdout.lock();
+        for line in BufReader::new(rg_stdout).lines() {
+            let res = output.lock().unwrap().write(&line?, &mut oup);
+            match res {
+                Err(e)
+                    if e.downcast_ref::<std::io::Error>()
+                        .map_or(false, |e| e.kind() == std::io::ErrorKind::BrokenPipe) =>
+                {
+                    break
+                }
+                res => res?,
+            }
+        }
+        Ok(())
+    }))
+}
+
+/// --rga-list-subfiles
+fn print_subfiles(config: &RgaConfig, args: &[OsString]) -> Result<()> {
+    let separator = if args.iter().any(|a| a == "-0" || a == "--null") {
+        b'\0'
+    } else {
+        b'\n'
+    };
+    let roots: Vec<PathBuf> = args
+        .iter()
+        .filter(|a| !a.to_string_lossy().starts_with('-'))
+        .map(PathBuf::from)
+        .collect();
+    let roots = if roots.is_empty() {
+        vec![PathBuf::from(".")]
+    } else {
+        roots
+    };
+    let stdout = std::io::stdout();
+    let mut oup = stdout.lock();
+    for file in batch_files(&roots, config, &WalkOptions::default())? {
+        let res = list_subfiles(&file, config, &mut |path| {
+            oup.write_all(path.as_bytes())?;
+            oup.write_all(&[separator])?;
+            Ok(())
+        });
+        match res {
+            Err(e)
+                if e.downcast_ref::<std::io::Error>()
+                    .map_or(false, |e| e.kind() == std::io::ErrorKind::BrokenPipe) =>
+            {
+                return Ok(())
+            }
+            Err(e) => eprintln!("rga: {}: {:#}", file.display(), e),
+            Ok(()) => {}
+        }
+    }
     Ok(())
 }
 
+/// the .git/HEAD files of the searched git repositories, which the git adapter expands to the history of the repository.
+/// rg skips .git directories, so they need to be passed explicitly
+fn git_history_paths(args: &[std::ffi::OsString]) -> Vec<std::ffi::OsString> {
+    let paths = RgArgs::parse(args).paths;
+    if paths.is_empty() {
+        // rg searches the current directory, which it won't do anymore once we add a path
+        return if Path::new(".git").is_dir() {
+            vec![".".into(), Path::new(".git").join("HEAD").into()]
+        } else {
+            vec![]
+        };
+    }
+    paths
+        .iter()
+        .map(|p| Path::new(p).join(".git").join("HEAD"))
+        .filter(|head| head.is_file())
+        .map(|head| head.into_os_string())
+        .collect()
+}
+
 /// add the directory that contains `rga` to PATH, so rga-preproc can find pandoc etc (if we are on Windows where we include dependent binaries)
 fn add_exe_to_path() -> Result<()> {
     use std::env;
@@ -0,0 +1,126 @@
+use crate::print_bytes;
+use anyhow::{Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+/// file that every rga-preproc process appends its cache statistics to, set by rga with --rga-cache-stats
+pub const STATS_FILE_ENV: &str = "RGA_CACHE_STATS_FILE";
+
+/// cache statistics of one or more rga-preproc runs
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// compressed bytes written to the cache
+    pub bytes_written: u64,
+    /// time the adapters took when the outputs read from the cache were created
+    pub time_saved_ms: u64,
+}
+
+impl CacheStats {
+    pub fn add(&mut self, other: &CacheStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.bytes_written += other.bytes_written;
+        self.time_saved_ms += other.time_saved_ms;
+    }
+
+    /// log the event, and append it to the stats file if rga was run with --rga-cache-stats
+    pub fn record(&self) {
+        debug!("cache stats: {:?}", self);
+        if let Some(path) = std::env::var_os(STATS_FILE_ENV) {
+            if let Err(e) = self.append_to(Path::new(&path)) {
+                warn!("could not write cache stats: {:#}", e);
+            }
+        }
+    }
+
+    /// one JSON line per event. Lines this short are appended atomically, so concurrent processes don't interleave
+    fn append_to(&self, path: &Path) -> Result<()> {
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&line)?;
+        Ok(())
+    }
+
+    /// sum of all events in the stats file
+    pub fn read(path: &Path) -> Result<CacheStats> {
+        let mut total = CacheStats::default();
+        if !path.exists() {
+            return Ok(total);
+        }
+        for line in BufReader::new(std::fs::File::open(path)?).lines() {
+            let stats: CacheStats =
+                serde_json::from_str(&line?).context("could not parse cache stats")?;
+            total.add(&stats);
+        }
+        Ok(total)
+    }
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lookups = self.hits + self.misses;
+        write!(
+            f,
+            "cache: {} hits, {} misses ({:.0}% hit rate), {} written, saved {:.1}s of adapter time",
+            self.hits,
+            self.misses,
+            if lookups == 0 {
+                0.0
+            } else {
+                self.hits as f64 * 100.0 / lookups as f64
+            },
+            print_bytes(self.bytes_written as f64),
+            self.time_saved_ms as f64 / 1000.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sum() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("stats");
+        assert_eq!(CacheStats::read(&path)?, CacheStats::default());
+        CacheStats {
+            hits: 1,
+            time_saved_ms: 1500,
+            ..Default::default()
+        }
+        .append_to(&path)?;
+        CacheStats {
+            misses: 1,
+            bytes_written: 100,
+            ..Default::default()
+        }
+        .append_to(&path)?;
+        let total = CacheStats::read(&path)?;
+        assert_eq!(
+            total,
+            CacheStats {
+                hits: 1,
+                misses: 1,
+                bytes_written: 100,
+                time_saved_ms: 1500
+            }
+        );
+        assert_eq!(
+            total.to_string(),
+            "cache: 1 hits, 1 misses (50% hit rate), 100 B written, saved 1.5s of adapter time"
+        );
+        Ok(())
+    }
+}
@@ -1,55 +1,147 @@
+use crate::{adapters::ReadBox, config::CacheCompression};
 use anyhow::Result;
 use log::*;
 use std::io::{Read, Write};
 
+/// prefix of uncompressed cache entries, so they can't be confused with zstd or lz4 frames
+const UNCOMPRESSED_MAGIC: &[u8] = b"rga\0";
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const LZ4_MAGIC: &[u8] = &[0x04, 0x22, 0x4d, 0x18];
+
+enum Encoder {
+    Zstd(zstd::stream::write::Encoder<Vec<u8>>),
+    Lz4(lz4::Encoder<Vec<u8>>),
+    None(Vec<u8>),
+}
+
+impl Encoder {
+    fn new(compression: CacheCompression, level: i32) -> std::io::Result<Encoder> {
+        Ok(match compression {
+            CacheCompression::Zstd => {
+                Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), level)?)
+            }
+            CacheCompression::Lz4 => Encoder::Lz4(lz4::EncoderBuilder::new().build(Vec::new())?),
+            CacheCompression::None => Encoder::None(UNCOMPRESSED_MAGIC.to_vec()),
+        })
+    }
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        match self {
+            Encoder::Zstd(e) => e.write_all(buf),
+            Encoder::Lz4(e) => e.write_all(buf),
+            Encoder::None(v) => v.write_all(buf),
+        }
+    }
+    /// length of the compressed data so far
+    fn len(&self) -> usize {
+        match self {
+            Encoder::Zstd(e) => e.get_ref().len(),
+            Encoder::Lz4(e) => e.writer().len(),
+            Encoder::None(v) => v.len(),
+        }
+    }
+    fn finish(self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoder::Zstd(e) => e.finish(),
+            Encoder::Lz4(e) => {
+                let (res, r) = e.finish();
+                r.map(|()| res)
+            }
+            Encoder::None(v) => Ok(v),
+        }
+    }
+}
+
+/// reads a cache entry written by CachingReader, with any compression
+pub fn decompress<'a>(cached: Vec<u8>) -> Result<ReadBox<'a>> {
+    let inp = std::io::Cursor::new(cached);
+    Ok(if inp.get_ref().starts_with(UNCOMPRESSED_MAGIC) {
+        let mut inp = inp;
+        inp.set_position(UNCOMPRESSED_MAGIC.len() as u64);
+        Box::new(inp)
+    } else if inp.get_ref().starts_with(LZ4_MAGIC) {
+        Box::new(lz4::Decoder::new(inp)?)
+    } else if inp.get_ref().starts_with(ZSTD_MAGIC) {
+        Box::new(zstd::stream::read::Decoder::new(inp)?)
+    } else {
+        return Err(anyhow::format_err!("Integrity: unknown cache entry format"));
+    })
+}
+
+/// called once when the input of a CachingReader ends: with (uncompressed size, compressed chunks)
+/// or None if the output was too large to cache, or with the error if reading the input failed
+pub type OnFinish = Box<
+    dyn FnOnce(std::result::Result<(u64, Option<Vec<Vec<u8>>>), &std::io::Error>) -> Result<()>
+        + Send,
+>;
+
 /**
  * wrap a writer so that it is passthrough,
- * but also the written data is compressed and written into a buffer,
- * unless more than max_cache_size bytes is written, then the cache is dropped and it is pure passthrough.
+ * but also the written data is compressed and written into a buffer.
+ * Every time the compressed data gets longer than max_chunk_size bytes, a new chunk is started,
+ * unless there are already max_chunks chunks, then the cache is dropped and it is pure passthrough.
  */
 pub struct CachingReader<R: Read> {
-    max_cache_size: usize,
-    zstd_writer: Option<zstd::stream::write::Encoder<Vec<u8>>>,
+    max_chunk_size: usize,
+    max_chunks: usize,
+    compression: CacheCompression,
+    compression_level: i32,
+    writer: Option<Encoder>,
+    /// finished chunks
+    chunks: Vec<Vec<u8>>,
     inp: R,
     bytes_written: u64,
-    on_finish: Box<dyn FnOnce((u64, Option<Vec<u8>>)) -> Result<()> + Send>,
+    on_finish: OnFinish,
 }
 impl<R: Read> CachingReader<R> {
     pub fn new(
         inp: R,
-        max_cache_size: usize,
+        max_chunk_size: usize,
+        max_chunks: usize,
+        compression: CacheCompression,
         compression_level: i32,
-        on_finish: Box<dyn FnOnce((u64, Option<Vec<u8>>)) -> Result<()> + Send>,
+        on_finish: OnFinish,
     ) -> Result<CachingReader<R>> {
         Ok(CachingReader {
             inp,
-            max_cache_size,
-            zstd_writer: Some(zstd::stream::write::Encoder::new(
-                Vec::new(),
-                compression_level,
-            )?),
+            max_chunk_size,
+            max_chunks,
+            compression,
+            compression_level,
+            writer: Some(Encoder::new(compression, compression_level)?),
+            chunks: Vec::new(),
             bytes_written: 0,
             on_finish,
         })
     }
-    pub fn finish(&mut self) -> std::io::Result<(u64, Option<Vec<u8>>)> {
-        if let Some(writer) = self.zstd_writer.take() {
+    pub fn finish(&mut self) -> std::io::Result<(u64, Option<Vec<Vec<u8>>>)> {
+        if let Some(writer) = self.writer.take() {
             let res = writer.finish()?;
-            if res.len() <= self.max_cache_size {
-                return Ok((self.bytes_written, Some(res)));
+            if self.chunks.is_empty() && res.len() > self.max_chunk_size {
+                return Ok((self.bytes_written, None));
             }
+            self.chunks.push(res);
+            return Ok((
+                self.bytes_written,
+                Some(std::mem::replace(&mut self.chunks, Vec::new())),
+            ));
         }
         Ok((self.bytes_written, None))
     }
     fn write_to_compressed(&mut self, buf: &[u8]) -> std::io::Result<()> {
-        if let Some(writer) = self.zstd_writer.as_mut() {
-            let wrote = writer.write(buf)?;
-            let compressed_len = writer.get_ref().len();
-            trace!("wrote {} to zstd, len now {}", wrote, compressed_len);
-            if compressed_len > self.max_cache_size {
-                debug!("cache longer than max, dropping");
-                //writer.finish();
-                self.zstd_writer.take().unwrap().finish()?;
+        if let Some(writer) = self.writer.as_mut() {
+            writer.write_all(buf)?;
+            let compressed_len = writer.len();
+            trace!("wrote {} to cache, len now {}", buf.len(), compressed_len);
+            if compressed_len > self.max_chunk_size {
+                let chunk = self.writer.take().unwrap().finish()?;
+                if self.chunks.len() + 1 >= self.max_chunks {
+                    debug!("cache longer than max, dropping");
+                    self.chunks.clear();
+                } else {
+                    debug!("starting cache chunk {}", self.chunks.len() + 1);
+                    self.chunks.push(chunk);
+                    self.writer = Some(Encoder::new(self.compression, self.compression_level)?);
+                }
             }
         }
         Ok(())
@@ -62,7 +154,7 @@ impl<R: Read> Read for CachingReader<R> {
                 // move out of box, replace with noop lambda
                 let on_finish = std::mem::replace(&mut self.on_finish, Box::new(|_| Ok(())));
                 // EOF, finish!
-                (on_finish)(self.finish()?)
+                (on_finish)(Ok(self.finish()?))
                     .map(|()| 0)
                     .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
             }
@@ -71,7 +163,85 @@ impl<R: Read> Read for CachingReader<R> {
                 self.bytes_written += read_bytes as u64;
                 Ok(read_bytes)
             }
-            Err(e) => Err(e),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => Err(e),
+            Err(e) => {
+                let on_finish = std::mem::replace(&mut self.on_finish, Box::new(|_| Ok(())));
+                self.writer = None;
+                if let Err(cb_err) = (on_finish)(Err(&e)) {
+                    warn!("{:#}", cb_err);
+                }
+                Err(e)
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let text = "all work and no play makes jack a dull boy\n".repeat(1000);
+        for compression in &[
+            CacheCompression::Zstd,
+            CacheCompression::Lz4,
+            CacheCompression::None,
+        ] {
+            let (tx, rx) = std::sync::mpsc::channel();
+            let mut reader = CachingReader::new(
+                text.as_bytes(),
+                1000,
+                100,
+                *compression,
+                3,
+                Box::new(move |res| {
+                    if let Ok((_, chunks)) = res {
+                        tx.send(chunks)?;
+                    }
+                    Ok(())
+                }),
+            )?;
+            let mut passthrough = String::new();
+            reader.read_to_string(&mut passthrough)?;
+            assert_eq!(passthrough, text);
+
+            let chunks = rx.recv()?.expect("output was cached");
+            let mut cached = String::new();
+            for chunk in chunks {
+                decompress(chunk)?.read_to_string(&mut cached)?;
+            }
+            assert_eq!(cached, text, "{:?}", compression);
+        }
+        Ok(())
+    }
+
+    struct FailingReader;
+    impl Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "subprocess failed",
+            ))
+        }
+    }
+
+    #[test]
+    fn failure() -> Result<()> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut reader = CachingReader::new(
+            FailingReader,
+            1000,
+            100,
+            CacheCompression::Zstd,
+            3,
+            Box::new(move |res| {
+                tx.send(res.map(|_| ()).map_err(|e| e.to_string()))?;
+                Ok(())
+            }),
+        )?;
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+        assert_eq!(rx.recv()?, Err("subprocess failed".to_string()));
+        Ok(())
+    }
+}
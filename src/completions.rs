@@ -0,0 +1,110 @@
+//! --rga-completions: shell completions for the flags of rga and of the rg it passes the other flags to
+
+use crate::config::RgaConfig;
+use regex::Regex;
+use std::collections::HashSet;
+use structopt::clap::{App, Arg};
+use structopt::StructOpt;
+
+/// a flag from the help of rg
+#[derive(Debug, PartialEq)]
+struct RgFlag {
+    long: String,
+    short: Option<String>,
+    takes_value: bool,
+}
+
+/// the flags listed in the output of `rg --help`.
+/// ripgrep 13 lists them as `-A, --after-context <NUM>`, ripgrep 14 as `-A NUM, --after-context=NUM`
+fn parse_rg_flags(help: &str) -> Vec<RgFlag> {
+    lazy_static::lazy_static! {
+        static ref FLAG: Regex =
+            Regex::new(r"^ {0,4}(?:-(\S)(?: [A-Z]+)?, )?--([a-z0-9][a-z0-9-]*)(=| <)?").unwrap();
+    }
+    let mut seen = HashSet::new();
+    help.lines()
+        .filter_map(|line| FLAG.captures(line))
+        .filter(|caps| seen.insert(caps[2].to_string()))
+        .map(|caps| RgFlag {
+            long: caps[2].to_string(),
+            short: caps.get(1).map(|s| s.as_str().to_string()),
+            takes_value: caps.get(3).is_some(),
+        })
+        .collect()
+}
+
+fn leak(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+}
+
+/// the clap app of rga with the flags of rg added, to generate completions for both
+pub fn completions_app(rg_help: &str) -> App<'static, 'static> {
+    let mut app = RgaConfig::clap();
+    for flag in parse_rg_flags(rg_help) {
+        // rga handles these itself
+        if flag.long == "help" || flag.long == "version" {
+            continue;
+        }
+        // the names of rga's args are its field names, which can be the same as the flags of rg
+        let mut arg = Arg::with_name(leak(format!("rg-{}", flag.long)))
+            .long(leak(flag.long))
+            .takes_value(flag.takes_value)
+            .multiple(true);
+        if let Some(short) = flag.short {
+            if short != "h" && short != "V" {
+                arg = arg.short(leak(short));
+            }
+        }
+        app = app.arg(arg);
+    }
+    app
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rg_flags() {
+        let rg13 = "OPTIONS:
+    -A, --after-context <NUM>
+            Show NUM lines after each match.
+
+            --foo in a description is not a flag
+    --binary
+            Search binary files.
+    -h, --help
+            Prints help information.
+";
+        let rg14 = "INPUT OPTIONS:
+    -e PATTERN, --regexp=PATTERN
+        A pattern to search for.
+
+    --no-ignore
+        Don't respect ignore files.
+    -., --hidden
+        Search hidden files and directories.
+";
+        let flag = |long: &str, short: Option<&str>, takes_value| RgFlag {
+            long: long.to_string(),
+            short: short.map(|s| s.to_string()),
+            takes_value,
+        };
+        assert_eq!(
+            parse_rg_flags(rg13),
+            vec![
+                flag("after-context", Some("A"), true),
+                flag("binary", None, false),
+                flag("help", Some("h"), false),
+            ]
+        );
+        assert_eq!(
+            parse_rg_flags(rg14),
+            vec![
+                flag("regexp", Some("e"), true),
+                flag("no-ignore", None, false),
+                flag("hidden", Some("."), false),
+            ]
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use crate::expansion::ExpansionBudget;
+use crate::rg_args::RgArgs;
 use crate::{adapters::custom::CustomAdapterConfig, project_dirs};
 use anyhow::*;
 use derive_more::FromStr;
@@ -5,7 +7,14 @@ use log::*;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::ffi::OsString;
-use std::{fs::File, io::Write, iter::IntoIterator, path::PathBuf, str::FromStr};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::Write,
+    iter::IntoIterator,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use structopt::StructOpt;
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -41,208 +50,1701 @@ impl Default for MaxArchiveRecursion {
     }
 }
 
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, FromStr)]
+pub struct ArchiveJobs(pub usize);
+
+impl ToString for ArchiveJobs {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl Default for ArchiveJobs {
+    fn default() -> Self {
+        ArchiveJobs(1)
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, FromStr)]
+pub struct AdapterJobs(pub usize);
+
+impl ToString for AdapterJobs {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, FromStr)]
+pub struct AdapterTimeout(pub u64);
+
+impl ToString for AdapterTimeout {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, FromStr)]
+pub struct ProcessCpuLimit(pub u64);
+
+impl ToString for ProcessCpuLimit {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct ProcessSizeLimit(pub usize);
+
+impl ToString for ProcessSizeLimit {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl FromStr for ProcessSizeLimit {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(ProcessSizeLimit(parse_readable_bytes_str(s)?))
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryLimit(pub usize);
+
+impl ToString for MemoryLimit {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl Default for MemoryLimit {
+    fn default() -> Self {
+        MemoryLimit(256_000_000)
+    }
+}
+impl FromStr for MemoryLimit {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(MemoryLimit(parse_readable_bytes_str(s)?))
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct MaxExpandedSize(pub u64);
+
+impl ToString for MaxExpandedSize {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl Default for MaxExpandedSize {
+    fn default() -> Self {
+        MaxExpandedSize(10_000_000_000)
+    }
+}
+impl FromStr for MaxExpandedSize {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(MaxExpandedSize(parse_readable_bytes_str(s)? as u64))
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, FromStr)]
+pub struct MaxExpansionRatio(pub u64);
+
+impl ToString for MaxExpansionRatio {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl Default for MaxExpansionRatio {
+    fn default() -> Self {
+        MaxExpansionRatio(1000)
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Clone, PartialEq, FromStr)]
+pub struct PrefixFormat(pub String);
+
+impl ToString for PrefixFormat {
+    fn to_string(&self) -> String {
+        self.0.clone()
+    }
+}
+impl Default for PrefixFormat {
+    fn default() -> Self {
+        PrefixFormat("{archive}{entry}: ".to_string())
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Clone, PartialEq, FromStr)]
+pub struct PageFormat(pub String);
+
+impl ToString for PageFormat {
+    fn to_string(&self) -> String {
+        self.0.clone()
+    }
+}
+impl Default for PageFormat {
+    fn default() -> Self {
+        PageFormat("Page {page}:".to_string())
+    }
+}
+impl PageFormat {
+    pub fn prefix(&self, page: usize) -> String {
+        fill_template(&self.0, &[("page", &page.to_string())])
+    }
+}
+
 #[derive(JsonSchema, Debug, Serialize, Deserialize, Clone, PartialEq, FromStr)]
 pub struct CachePath(pub String);
 
-impl ToString for CachePath {
-    fn to_string(&self) -> String {
-        self.0.to_string()
-    }
+impl ToString for CachePath {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl Default for CachePath {
+    fn default() -> Self {
+        let pd = project_dirs().expect("could not get cache path");
+        let app_cache = pd.cache_dir();
+        CachePath(app_cache.to_str().expect("cache path not utf8").to_owned())
+    }
+}
+
+/// name of a directory that is used as the cache of the files in the directory it is in
+const PROJECT_CACHE_DIR: &str = ".rga-cache";
+
+/// the nearest project cache directory in or above the given path
+pub fn find_project_cache(start: &Path) -> Option<PathBuf> {
+    let start = std::fs::canonicalize(start).ok()?;
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CACHE_DIR))
+        .find(|dir| dir.is_dir())
+}
+
+/// the first path given to rg, to find the project cache and directory configs. rg searches the current directory without paths
+pub fn search_root(passthrough_args: &[OsString]) -> PathBuf {
+    RgArgs::parse(passthrough_args)
+        .roots()
+        .into_iter()
+        .next()
+        .expect("at least the current directory")
+}
+
+/// name of a config file that applies to the searches in the directory it is in
+const DIRECTORY_CONFIG: &str = ".rga.toml";
+
+/// the settings a directory config can change: how the files are searched and how the output looks. The others are
+/// ignored, since whoever made the searched files chooses them: they could run programs, send the extracted text
+/// elsewhere (the cache), turn off the sandbox, read password files or remove the limits
+const DIRECTORY_CONFIG_ALLOWED: &[&str] = &[
+    "accurate",
+    "adapters",
+    "adapter_priority",
+    "adapter_ignore",
+    "mime",
+    "entry_name_encoding",
+    "file_header",
+    "hyperlinks",
+    "control_chars",
+    "no_page_numbers",
+    "page_numbers",
+    "prefix_format",
+    "page_format",
+    "markdown",
+    "archive_metadata",
+    "ignore_adapter_errors",
+    "csv",
+    "encoding",
+    "git",
+    "netcdf",
+    "pcap",
+    "sqlite",
+    "strings",
+];
+
+/// the directory configs in and above the given path, outermost first
+fn find_directory_configs(start: &Path) -> Vec<PathBuf> {
+    let start = match std::fs::canonicalize(start) {
+        Ok(start) => start,
+        Err(_) => return vec![],
+    };
+    let mut configs: Vec<PathBuf> = start
+        .ancestors()
+        .map(|dir| dir.join(DIRECTORY_CONFIG))
+        .filter(|file| file.is_file())
+        .collect();
+    configs.reverse();
+    configs
+}
+
+fn read_directory_configs(start: &Path) -> Result<Vec<(String, Value)>> {
+    find_directory_configs(start)
+        .into_iter()
+        .map(|path| {
+            let mut config = parse_config_file(&path)?;
+            if let Value::Object(o) = &mut config {
+                let ignored: Vec<String> = o
+                    .keys()
+                    .filter(|key| !DIRECTORY_CONFIG_ALLOWED.contains(&key.as_str()))
+                    .cloned()
+                    .collect();
+                for key in ignored {
+                    warn!("ignoring {} in {}", key, path.display());
+                    o.remove(&key);
+                }
+            }
+            Ok((path.to_string_lossy().into_owned(), config))
+        })
+        .collect()
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheCompression {
+    Zstd,
+    Lz4,
+    None,
+}
+
+impl ToString for CacheCompression {
+    fn to_string(&self) -> String {
+        match self {
+            CacheCompression::Zstd => "zstd",
+            CacheCompression::Lz4 => "lz4",
+            CacheCompression::None => "none",
+        }
+        .to_string()
+    }
+}
+impl Default for CacheCompression {
+    fn default() -> Self {
+        CacheCompression::Zstd
+    }
+}
+impl FromStr for CacheCompression {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zstd" => Ok(CacheCompression::Zstd),
+            "lz4" => Ok(CacheCompression::Lz4),
+            "none" => Ok(CacheCompression::None),
+            _ => Err(format_err!(
+                "unknown cache compression {:?}, expected zstd, lz4 or none",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlChars {
+    Strip,
+    Escape,
+    Keep,
+}
+
+impl ToString for ControlChars {
+    fn to_string(&self) -> String {
+        match self {
+            ControlChars::Strip => "strip",
+            ControlChars::Escape => "escape",
+            ControlChars::Keep => "keep",
+        }
+        .to_string()
+    }
+}
+impl Default for ControlChars {
+    fn default() -> Self {
+        ControlChars::Strip
+    }
+}
+impl FromStr for ControlChars {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strip" => Ok(ControlChars::Strip),
+            "escape" => Ok(ControlChars::Escape),
+            "keep" => Ok(ControlChars::Keep),
+            _ => Err(format_err!(
+                "unknown control character handling {:?}, expected strip, escape or keep",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    Lmdb,
+    Sqlite,
+}
+
+impl ToString for CacheBackend {
+    fn to_string(&self) -> String {
+        match self {
+            CacheBackend::Lmdb => "lmdb",
+            CacheBackend::Sqlite => "sqlite",
+        }
+        .to_string()
+    }
+}
+impl Default for CacheBackend {
+    fn default() -> Self {
+        CacheBackend::Lmdb
+    }
+}
+impl FromStr for CacheBackend {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lmdb" => Ok(CacheBackend::Lmdb),
+            "sqlite" => Ok(CacheBackend::Sqlite),
+            _ => Err(format_err!(
+                "unknown cache backend {:?}, expected lmdb or sqlite",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct CacheMaxBlobLen(pub usize);
+
+impl ToString for CacheMaxBlobLen {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl Default for CacheMaxBlobLen {
+    fn default() -> Self {
+        CacheMaxBlobLen(2000000)
+    }
+}
+
+/// parse a byte count with an optional suffix (k M G)
+fn parse_readable_bytes_str(s: &str) -> Result<usize> {
+    let suffix = s.chars().last();
+    if let Some(suffix) = suffix {
+        match suffix {
+            'k' | 'M' | 'G' => usize::from_str(s.trim_end_matches(suffix))
+                .with_context(|| format!("Could not parse int"))
+                .map(|e| {
+                    e * match suffix {
+                        'k' => 1000,
+                        'M' => 1000_000,
+                        'G' => 1000_000_000,
+                        _ => panic!("impossible"),
+                    }
+                }),
+            _ => usize::from_str(s).with_context(|| format!("Could not parse int")),
+        }
+    } else {
+        Err(format_err!("empty byte input"))
+    }
+}
+
+impl FromStr for CacheMaxBlobLen {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(CacheMaxBlobLen(parse_readable_bytes_str(s)?))
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, FromStr)]
+pub struct CacheMaxChunks(pub usize);
+
+impl ToString for CacheMaxChunks {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl Default for CacheMaxChunks {
+    fn default() -> Self {
+        CacheMaxChunks(100)
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Default)]
+pub struct CacheMinFileSize(pub usize);
+
+impl ToString for CacheMinFileSize {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl FromStr for CacheMinFileSize {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(CacheMinFileSize(parse_readable_bytes_str(s)?))
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, FromStr)]
+pub struct CacheMinDuration(pub u64);
+
+impl ToString for CacheMinDuration {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl Default for CacheMinDuration {
+    fn default() -> Self {
+        CacheMinDuration(20)
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct CacheMaxSize(pub usize);
+
+impl ToString for CacheMaxSize {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl FromStr for CacheMaxSize {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(CacheMaxSize(parse_readable_bytes_str(s)?))
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct MaxFileSize(pub usize);
+
+impl ToString for MaxFileSize {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl FromStr for MaxFileSize {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(MaxFileSize(parse_readable_bytes_str(s)?))
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct SqliteMaxBlobLen(pub usize);
+
+impl ToString for SqliteMaxBlobLen {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl Default for SqliteMaxBlobLen {
+    fn default() -> Self {
+        SqliteMaxBlobLen(10_000_000)
+    }
+}
+
+impl FromStr for SqliteMaxBlobLen {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(SqliteMaxBlobLen(parse_readable_bytes_str(s)?))
+    }
+}
+
+/// # rga configuration
+///
+/// this is kind of a "polyglot" struct, since it serves three functions
+///
+/// 1. describing the command line arguments using structopt+clap and for man page / readme generation
+/// 2. describing the config file format (output as JSON schema via schemars)
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone)]
+#[structopt(
+    name = "ripgrep-all",
+    rename_all = "kebab-case",
+    about = env!("CARGO_PKG_DESCRIPTION"),
+    author = env!("CARGO_PKG_HOMEPAGE"),
+    // TODO: long_about does not seem to work to only show this on short help
+    after_help = "-h shows a concise overview, --help shows more detail and advanced options.\n\nAll other options not shown here are passed directly to rg, especially [PATTERN] and [PATH ...]",
+    usage = "rga [RGA OPTIONS] [RG OPTIONS] PATTERN [PATH ...]"
+)]
+pub struct RgaConfig {
+    /// Use more accurate but slower matching by mime type
+    ///
+    /// By default, rga will match files using file extensions.
+    /// Some programs, such as sqlite3, don't care about the file extension at all,
+    /// so users sometimes use any or no extension at all. With this flag, rga
+    /// will try to detect the mime type of input files using the magic bytes
+    /// (similar to the `file` utility), and use that to choose the adapter.
+    /// Detection is only done on the first 8KiB of the file, since we can't always seek on the input (in archives).
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-accurate")]
+    pub accurate: bool,
+
+    /// Run the adapters in the rga process on a thread pool, instead of letting rg start one rga-preproc process per file.
+    /// Much faster for many small documents. The adapted content is searched by rg from stdin, so rg's
+    /// file filters (--glob, --type, ...) and per-file output options (-l, -c) only apply to files without an adapter.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-batch", hidden_short_help = true)]
+    pub batch: bool,
+
+    /// Change which adapters to use and in which priority order (descending)
+    ///
+    /// "foo,bar" means use only adapters foo and bar.
+    /// "-bar,baz" means use all default adapters except for bar and baz.
+    /// "+bar,baz" means use all default adapters and also bar and baz.
+    /// "+bar,-baz" means use all default adapters and bar, but not baz.
+    ///
+    /// Heavyweight adapters are disabled by default and only used when they are added here (see --rga-list-adapters),
+    /// e.g. --rga-adapters=+strings for the strings fallback (like --rga-strings-fallback) and +githistory for the
+    /// history of git repositories (like --rga-git-history).
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-adapters",
+        require_equals = true,
+        require_delimiter = true
+    )]
+    pub adapters: Vec<String>,
+
+    /// Adapters to prefer when multiple adapters match a file, e.g. --rga-adapter-priority=pandoc,mydocx
+    ///
+    /// The listed adapters are moved to the front of the adapters (in the given order), without enabling any.
+    /// By default custom adapters come first, then the builtin ones. --rga-print-adapter shows the adapters matching a file
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-adapter-priority",
+        require_equals = true,
+        require_delimiter = true,
+        hidden_short_help = true
+    )]
+    pub adapter_priority: Vec<String>,
+
+    /// Only search files of these mime types, e.g. --rga-mime=application/pdf,image/*.
+    /// Types starting with - are excluded instead, e.g. --rga-mime=-application/zip
+    ///
+    /// Archives are searched for files of the included types unless they are excluded themselves.
+    /// The mime type is detected like with --rga-accurate, so every file is passed to rga
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-mime",
+        require_equals = true,
+        require_delimiter = true,
+        hidden_short_help = true
+    )]
+    pub mime: Vec<String>,
+
+    /// Encodings of the names of files in archives that are not UTF-8, tried in order, e.g. --rga-entry-name-encoding=shift_jis
+    ///
+    /// Any encoding label of the WHATWG encoding standard (e.g. windows-1252, gbk, euc-kr), cp437,
+    /// or auto to guess the encoding of each name. Single-byte encodings accept any name, so put them last.
+    /// After these, zip files use CP437. Bytes that can't be decoded are shown as \xNN
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-entry-name-encoding",
+        require_equals = true,
+        require_delimiter = true,
+        hidden_short_help = true
+    )]
+    pub entry_name_encoding: Vec<String>,
+
+    /// Print a line before the content of each file with its detected mime type, the adapter used,
+    /// the page count (for PDFs) and how deep it is nested in archives.
+    ///
+    /// The line starts with the usual prefix followed by "# ", e.g. "# mime: application/pdf, adapter: poppler, pages: 12, archive depth: 0".
+    /// Not printed in the records of rga-preproc --format json
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-file-header", hidden_short_help = true)]
+    pub file_header: bool,
+
+    /// List how every file was handled after the search: the adapter, what matched it (file extension, magic bytes,
+    /// mime type, ...) and the adapters the content went through (e.g. decompress, then tar), or why it was skipped.
+    ///
+    /// Printed to stderr, with --json as {"type":"rga_detection"} messages on stdout. The records of rga-preproc --format json
+    /// include them. (-v is --invert-match of rg)
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-verbose", hidden_short_help = true)]
+    pub verbose: bool,
+
+    /// Make the page numbers of documents clickable in terminals that support hyperlinks (OSC 8).
+    ///
+    /// The links open the file at the page (file:///path/doc.pdf#page=3). Only for files that are not in archives.
+    /// rg can output links for the file names itself with --hyperlink-format (ripgrep 14+)
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-hyperlinks", hidden_short_help = true)]
+    pub hyperlinks: bool,
+
+    /// What to do with terminal control sequences (ANSI escapes) and other control characters (e.g. NUL) in the output of adapters
+    ///
+    /// "strip" (the default) removes them, "escape" shows them as \x1b, "keep" passes them through.
+    /// Documents could otherwise change the terminal (e.g. set the title or hide lines) when matches are printed,
+    /// and a NUL byte makes rg treat the output as binary. Tabs, newlines and form feeds are kept.
+    /// The hyperlinks of --rga-hyperlinks are kept
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-control-chars",
+        possible_values = &["strip", "escape", "keep"],
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub control_chars: ControlChars,
+
+    /// Don't prefix the lines of paginated documents (e.g. PDFs) with "Page N:"
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-no-page-numbers",
+        overrides_with = "page_numbers",
+        hidden_short_help = true
+    )]
+    pub no_page_numbers: bool,
+
+    /// Prefix the lines of paginated documents with "Page N:" (the default), overriding the config file
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-page-numbers",
+        overrides_with = "no_page_numbers",
+        hidden_short_help = true
+    )]
+    pub page_numbers: bool,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub cache: CacheConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub sqlite: SqliteConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub netcdf: NetcdfConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub dicom: DicomConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub pcap: PcapConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub strings: StringsConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub encoding: EncodingConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub git: GitConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub csv: CsvConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub secrets: SecretsConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub zip: ZipConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub pdf: PdfConfig,
+
+    /// Output a metadata line before the contents of each archive entry
+    ///
+    /// The line contains the size, modification time, unix permissions and comment of the entry,
+    /// so archives can also be searched by entry attributes. Zip archive comments are output as well.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-archive-metadata", hidden_short_help = true)]
+    pub archive_metadata: bool,
+
+    /// Format of the line prefix of files in archives
+    ///
+    /// {archive} is replaced by the prefix of the enclosing archives, {entry} by the name of the file in the archive
+    /// (for nested archives, the format is applied once per level).
+    /// Example: "{archive}{entry}> ". Page numbers are formatted with --rga-page-format
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-prefix-format",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub prefix_format: PrefixFormat,
+
+    /// Format of the page number prefix of paginated documents, {page} is replaced by the page number.
+    /// Example: "[p{page}] "
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-page-format",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub page_format: PageFormat,
+
+    /// Maximum nestedness of archives to recurse into. Deeper files are replaced by a truncation marker
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-max-archive-recursion",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub max_archive_recursion: MaxArchiveRecursion,
+
+    /// Number of archive entries to process in parallel
+    ///
+    /// By default, the entries of an archive (zip, tar, ...) are processed one after another.
+    /// With a higher number, entries are read into memory and run through their adapters on that many threads,
+    /// which speeds up archives containing many PDFs or office documents. The output order stays the same:
+    /// entries are always output in the order they are stored in the archive.
+    /// rg already searches multiple files in parallel, so this mostly helps when searching a few large archives.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-archive-jobs",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub archive_jobs: ArchiveJobs,
+
+    /// Don't run adapters on files larger than this, output "[rga: truncated at 100 MB (--rga-max-filesize), ...]" instead.
+    /// Unlike rg's --max-filesize, this also applies to files in archives.
+    /// Allowed suffixes: k M G. Can be overridden per adapter with max_filesize in rga.toml
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-max-filesize",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub max_filesize: Option<MaxFileSize>,
+
+    /// Maximum size of an intermediate buffer in rga-preproc (e.g. the output of an adapter that has to be
+    /// produced completely before it can be read) before it is moved to a temporary file.
+    /// Allowed suffixes: k M G
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-memory-limit",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub memory_limit: MemoryLimit,
+
+    /// Stop extracting a file after this much was decompressed or extracted from it (counting every level of
+    /// nested archives), and output a truncation marker instead of the rest. Protects against
+    /// decompression bombs. Allowed suffixes: k M G
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-max-expanded-size",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub max_expanded_size: MaxExpandedSize,
+
+    /// Stop extracting a file when more than this many times its size was decompressed or extracted from it,
+    /// like --rga-max-expanded-size
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-max-expansion-ratio",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub max_expansion_ratio: MaxExpansionRatio,
+
+    /// Don't count files that can't be preprocessed (e.g. corrupt PDFs) as errors. rga-preproc then outputs
+    /// "[rga: error: ...]" instead of failing, and rga exits with 0 or 1 depending only on whether something matched.
+    /// By default, rga exits with 2 like rg if a file could not be searched
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-ignore-adapter-errors", hidden_short_help = true)]
+    pub ignore_adapter_errors: bool,
+
+    /// Maximum number of external programs (pandoc, ffmpeg, pdftotext, ...) that run at the same time,
+    /// across all rga processes of the user. rg runs one rga-preproc per cpu (or --threads), and each can start
+    /// several programs when searching archives, which can overload the machine. By default, there is no limit
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-jobs", require_equals = true, hidden_short_help = true)]
+    pub jobs: Option<AdapterJobs>,
+
+    /// Limit the cpu time of every external program of an adapter (pandoc, pdftotext, ...) to this many seconds.
+    /// A program exceeding it is stopped, its output ends with "[rga: truncated at the cpu time limit (...), ...]"
+    /// and a warning is shown. Unlike --rga-adapter-timeout, time spent waiting (e.g. for input) does not count.
+    /// Only applied on Unix
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-adapter-cpu-limit",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub adapter_cpu_limit: Option<ProcessCpuLimit>,
+
+    /// Limit the memory (address space) of every external program of an adapter. A program exceeding it usually fails
+    /// like on a corrupt file, and is listed in the summary of failed files. Allowed suffixes: k M G. Only applied on Unix
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-adapter-memory-limit",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub adapter_memory_limit: Option<ProcessSizeLimit>,
+
+    /// Limit the size of the files every external program of an adapter writes (e.g. the images pdftoppm renders
+    /// for OCR). A program exceeding it is stopped like with --rga-adapter-cpu-limit. Allowed suffixes: k M G.
+    /// Only applied on Unix
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-adapter-file-size-limit",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub adapter_file_size_limit: Option<ProcessSizeLimit>,
+
+    /// Run the programs of adapters (pandoc, pdftotext, custom adapters, ...) in a bubblewrap (bwrap) sandbox,
+    /// without network access and with only the system directories, the input file and a temporary directory visible.
+    /// Protects the home directory when searching untrusted documents. Also applies to 7z, dcmdump, ncdump,
+    /// mdbtools and persistent custom adapters. --rga-pandoc-server is not used with it (pandoc runs for every file).
+    /// Only supported on Linux
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-sandbox", hidden_short_help = true)]
+    pub sandbox: bool,
+
+    /// A path the programs can read in the sandbox of --rga-sandbox, e.g. the scripts of a custom adapter. Can be given multiple times
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-sandbox-path",
+        require_equals = true,
+        number_of_values = 1,
+        hidden_short_help = true
+    )]
+    pub sandbox_path: Vec<String>,
+
+    /// Options for specific adapters, by adapter name. Only in the config file, e.g. in rga.toml:
+    ///
+    /// [adapter_options.poppler]
+    /// extra_args = ["-layout"]
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(skip)]
+    pub adapter_options: BTreeMap<String, AdapterOptions>,
+
+    /// Don't use adapters for the files matching a glob, e.g. --rga-adapter-ignore='node_modules/**=*' or --rga-adapter-ignore='*.sqlite3=sqlite'.
+    ///
+    /// The part after = is a comma separated list of adapter names, or * for all adapters. Can be given multiple times.
+    /// Globs without a / are matched against the file name, others against the path. The files are still searched as they are
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-adapter-ignore",
+        require_equals = true,
+        number_of_values = 1,
+        hidden_short_help = true
+    )]
+    pub adapter_ignore: Vec<String>,
+
+    /// Add an argument to the command of an adapter, e.g. --rga-adapter-arg=poppler:-layout or --rga-adapter-arg=pandoc:--wrap=auto.
+    ///
+    /// Can be given multiple times. Added to the extra_args in adapter_options
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-adapter-arg",
+        require_equals = true,
+        number_of_values = 1,
+        hidden_short_help = true
+    )]
+    pub adapter_arg: Vec<String>,
+
+    /// Keep the structure of documents converted with pandoc (docx, odt, epub, fb2, ipynb) as markdown:
+    /// headings, lists and tables, instead of plain text. Makes the context of matches easier to read
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-markdown", hidden_short_help = true)]
+    pub markdown: bool,
+
+    /// Convert documents with one long-running `pandoc server` (pandoc 3.0 or newer) instead of starting pandoc
+    /// for every file. Starting pandoc takes longer than converting a small document, so this is much faster
+    /// for archives with many documents and with --rga-batch.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-pandoc-server", hidden_short_help = true)]
+    pub pandoc_server: bool,
+
+    /// Kill adapters that run external programs (pandoc, ffmpeg, ...) when they take longer than this many seconds.
+    /// The output then ends with "[rga: truncated at 30s (--rga-adapter-timeout), ...]" and the search continues with the other files.
+    /// Like failures, timeouts are cached (for the same timeout)
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-adapter-timeout",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub adapter_timeout: Option<AdapterTimeout>,
+
+    /// Disable all the limits that cut off the output of files: --rga-max-filesize (also per adapter),
+    /// --rga-max-archive-recursion, --rga-max-expanded-size, --rga-max-expansion-ratio, --rga-adapter-timeout,
+    /// the resource limits of adapters and --rga-sqlite-max-blob-len.
+    ///
+    /// Output cut off by a limit ends with a marker like "[rga: truncated at 100 MB (--rga-max-filesize), rerun with --rga-no-limits]".
+    /// Search that file again with this option to get the complete output, e.g. rga --rga-no-limits pattern big.zip
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-no-limits", hidden_short_help = true)]
+    pub no_limits: bool,
+
+    /// The Unix socket of the daemon (--rga-daemon). Default: daemon.sock in the default cache directory
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-daemon-socket",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub daemon_socket: Option<String>,
+
+    /// Let the daemon (rga --rga-daemon) search if it is running, instead of starting the adapters in new processes.
+    /// The search then uses the configuration of the daemon, only the rg arguments are sent to it
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-use-daemon", hidden_short_help = true)]
+    pub use_daemon: bool,
+
+    //////////////////////////////////////////
+    //////////////////////////// Config file only
+    //////////////////////////////////////////
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(skip)]
+    pub custom_adapters: Option<Vec<CustomAdapterConfig>>,
+
+    /// output one JSON record per line instead of prefixed lines (`rga-preproc --format json`)
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(skip)]
+    pub json_records: bool,
+
+    /// what was extracted from the file that is preprocessed, set by rga_preproc
+    #[serde(skip)]
+    #[structopt(skip)]
+    pub expansion: ExpansionBudget,
+    //////////////////////////////////////////
+    //////////////////////////// CMD line only
+    //////////////////////////////////////////
+    #[serde(skip)]
+    #[structopt(long = "--rga-config-file", require_equals = true)]
+    pub config_file_path: Option<String>,
+
+    /// same as passing path directly, except if argument is empty
+    /// kinda hacky, but if no file is found, fzf calls rga with empty string as path, which causes No such file or directory from rg. So filter those cases and return specially
+    #[serde(skip)]
+    #[structopt(long = "--rga-fzf-path", require_equals = true, hidden = true)]
+    pub fzf_path: Option<String>,
+
+    // these arguments are basically "subcommands" that stop the process, so don't serialize them
+    #[serde(skip)]
+    #[structopt(long = "--rga-list-adapters", help = "List all known adapters")]
+    pub list_adapters: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-print-adapter",
+        require_equals = true,
+        value_name = "FILE",
+        help = "Print which adapter would be used for the file and why, without running it"
+    )]
+    pub print_adapter: Option<String>,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-location-map",
+        require_equals = true,
+        value_name = "FILE",
+        help = "Write the location of every match (output line, file, path in the archive, page, byte offset) to the file as JSON lines, for editors"
+    )]
+    pub location_map: Option<String>,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-doctor",
+        help = "Check that the programs the adapters run are installed, that the custom adapters are valid and that the cache opens"
+    )]
+    pub doctor: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-print-rg-args",
+        help = "Print the arguments (--pre, --pre-glob, ...) rga passes to rg, to use rga's preprocessing when running rg directly"
+    )]
+    pub print_rg_args: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-completions",
+        require_equals = true,
+        value_name = "SHELL",
+        possible_values = &structopt::clap::Shell::variants(),
+        help = "Print shell completions for the flags of rga and of the installed rg"
+    )]
+    pub completions: Option<String>,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-list-subfiles",
+        help = "List the files in the archives in the given paths (e.g. docs.zip/dir/report.pdf) without searching them. Separated by NUL with -0"
+    )]
+    pub list_subfiles: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-interactive",
+        help = "Search while typing, with the matches grouped by file and a preview of the selected match. Other arguments are passed to rg (e.g. paths)"
+    )]
+    pub interactive: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-warm-cache",
+        help = "Run the adapters on all files in the given paths to fill the cache, without searching"
+    )]
+    pub warm_cache: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-watch",
+        help = "Fill the cache for the given paths, then keep running and update it whenever a file changes"
+    )]
+    pub watch: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-bench",
+        help = "Time the adapters on the files in the given paths with an empty and a filled cache"
+    )]
+    pub bench: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-report",
+        help = "Print which adapters would handle the files in the given paths and how long extracting them takes, without searching"
+    )]
+    pub report: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-daemon",
+        help = "Keep running and answer search and preprocess requests on a Unix socket (see --rga-daemon-socket)"
+    )]
+    pub daemon: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-remote",
+        require_equals = true,
+        value_name = "user@host:/path",
+        help = "Search the path on another host over ssh, extracting there and sending back only the matches"
+    )]
+    pub remote: Option<String>,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-print-config-schema",
+        help = "Print the JSON Schema of the configuration file"
+    )]
+    pub print_config_schema: bool,
+
+    #[serde(skip)]
+    #[structopt(long, help = "Show help for ripgrep itself")]
+    pub rg_help: bool,
+
+    #[serde(skip)]
+    #[structopt(long, help = "Show version of ripgrep itself")]
+    pub rg_version: bool,
+}
+
+impl RgaConfig {
+    /// the line prefix of an entry named `entry` in an archive with the line prefix `archive`
+    pub fn entry_prefix(&self, archive: &str, entry: &str) -> String {
+        if self.json_records {
+            // the subfile path of the records is taken from the default prefix
+            return format!("{}{}: ", archive, entry);
+        }
+        fill_template(
+            &self.prefix_format.0,
+            &[("archive", archive), ("entry", entry)],
+        )
+    }
+
+    /// the arguments from the config file that are added to the command of the adapter
+    pub fn adapter_extra_args(&self, adapter: &str) -> &[String] {
+        self.adapter_options
+            .get(adapter)
+            .map_or(&[], |options| &options.extra_args)
+    }
+
+    /// the maximum size of the files the adapter is run on (--rga-max-filesize or its adapter options)
+    pub fn max_filesize(&self, adapter: &str) -> Option<u64> {
+        self.adapter_options
+            .get(adapter)
+            .and_then(|options| options.max_filesize)
+            .or(self.max_filesize)
+            .map(|max| max.0 as u64)
+    }
+
+    /// --rga-no-limits
+    fn remove_limits(&mut self) {
+        self.max_filesize = None;
+        for options in self.adapter_options.values_mut() {
+            options.max_filesize = None;
+        }
+        self.max_archive_recursion = MaxArchiveRecursion(i32::MAX);
+        self.max_expanded_size = MaxExpandedSize(u64::MAX);
+        self.max_expansion_ratio = MaxExpansionRatio(u64::MAX);
+        self.adapter_timeout = None;
+        self.adapter_cpu_limit = None;
+        self.adapter_memory_limit = None;
+        self.adapter_file_size_limit = None;
+        self.sqlite.max_blob_len = SqliteMaxBlobLen(usize::MAX);
+    }
+
+    /// the format of page numbers in the output
+    pub fn effective_page_format(&self) -> PageFormat {
+        if self.json_records {
+            // the page of the records is parsed from the default prefix
+            PageFormat::default()
+        } else {
+            self.page_format.clone()
+        }
+    }
+}
+
+/// options of a single adapter, set in the config file
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct AdapterOptions {
+    /// Arguments added to the command of an adapter that runs an external program, before its own arguments.
+    /// E.g. ["-layout"] for poppler to keep the layout of columns and tables
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub extra_args: Vec<String>,
+    /// Overrides --rga-max-filesize for this adapter, in bytes
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub max_filesize: Option<MaxFileSize>,
+    /// Only use the adapter for the files matching these conditions. Other files are handled as if the adapter did not match them
+    /// (by the next matching adapter, or searched as they are)
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub conditions: Option<AdapterConditions>,
+}
+
+/// conditions on the files an adapter is used for, e.g. for the strings fallback:
+///
+/// [adapter_options.strings.conditions]
+/// max_size = 50000000
+/// executable = true
+/// paths = ["**/bin/*"]
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct AdapterConditions {
+    /// only files at least this large, in bytes
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub min_size: Option<u64>,
+    /// only files at most this large, in bytes
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub max_size: Option<u64>,
+    /// only files that are (true) or are not (false) executable. Files in archives are not executable
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub executable: Option<bool>,
+    /// only files with a path matching one of these globs (within archives: the path within the archive)
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub paths: Vec<String>,
+}
+
+/// replaces the {name}s in the template by their values. Replaced values are not searched for placeholders again
+fn fill_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let value = values.iter().find(|(name, _)| {
+            rest.strip_prefix(name)
+                .map_or(false, |after| after.starts_with('}'))
+        });
+        match value {
+            Some((name, value)) => {
+                out.push_str(value);
+                rest = &rest[name.len() + 1..];
+            }
+            None => out.push('{'),
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct CacheConfig {
+    /// Disable caching of results
+    ///
+    /// By default, rga caches the extracted text, if it is small enough,
+    /// to a database in ~/.cache/rga on Linux,
+    /// ~/Library/Caches/rga on macOS,
+    /// or C:\Users\username\AppData\Local\rga on Windows.
+    /// This way, repeated searches on the same set of files will be much faster.
+    /// If you pass this flag, all caching will be disabled.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-no-cache")]
+    pub disabled: bool,
+
+    /// Max compressed size of a cache entry
+    ///
+    /// Longest byte length (after compression) to store in one cache entry. Longer adapter outputs are split into
+    /// multiple entries (up to --rga-cache-max-chunks), even longer ones will not be cached and recomputed every time. Allowed suffixes: k M G
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-cache-max-blob-len",
+        hidden_short_help = true,
+        require_equals = true,
+        // parse(try_from_str = parse_readable_bytes_str)
+    )]
+    pub max_blob_len: CacheMaxBlobLen,
+
+    /// Max number of cache entries an adapter output is split into
+    ///
+    /// Adapter outputs longer than --rga-cache-max-blob-len (after compression) are stored in chunks of that size,
+    /// so large extractions (e.g. big PDFs or sqlite dumps) are cached as well. Outputs needing more chunks are not cached.
+    /// Set to 1 to only cache outputs that fit into a single entry.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-cache-max-chunks",
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub max_chunks: CacheMaxChunks,
+
+    /// Don't use the cache for files smaller than this
+    ///
+    /// Running the adapter on tiny files is usually faster than looking them up in the cache.
+    /// Allows suffixes like k, M, G. Default: 0 (all files are cached)
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-cache-min-file-size",
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub min_file_size: CacheMinFileSize,
+
+    /// Don't cache outputs that took less than this many milliseconds to compute
+    ///
+    /// Outputs of near-instant adapters (e.g. decompressing a small .gz file) are cheaper to recompute
+    /// than to store. Set to 0 to cache all outputs.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-cache-min-duration",
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub min_duration: CacheMinDuration,
+
+    /// Identify cached files by a hash of their contents
+    ///
+    /// Adapter outputs are always stored by a SHA-256 hash of the file contents, so identical files are only processed once.
+    /// By default, a file is only hashed the first time it is seen, later lookups use its path and modification time.
+    /// With this flag, the file is hashed on every lookup, so files restored with an old modification time don't get stale results.
+    /// This reads every file one additional time.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-cache-content-hash", hidden_short_help = true)]
+    pub content_hash: bool,
+
+    /// Database used for the cache
+    ///
+    /// "lmdb" (the default) stores the cache in a memory mapped key-value store (data.mdb).
+    /// "sqlite" stores it in a single SQLite file (cache.sqlite3), which is easier to back up and inspect,
+    /// and can be used if the LMDB store causes locking problems (e.g. on network file systems).
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-cache-backend",
+        possible_values = &["lmdb", "sqlite"],
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub backend: CacheBackend,
+
+    /// Max total size of the cache
+    ///
+    /// When the (compressed) size of all cached outputs exceeds this, the least recently used entries are evicted.
+    /// By default, the cache grows without bound. Allowed suffixes: k M G
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-cache-max-size",
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub max_size: Option<CacheMaxSize>,
+
+    /// Compression of adapter outputs in the cache db
+    ///
+    /// "zstd" (the default) gives the smallest cache, "lz4" or "none" make reading from the cache faster
+    /// on fast disks. Entries written with another compression can still be read.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-cache-compression",
+        possible_values = &["zstd", "lz4", "none"],
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub compression: CacheCompression,
+
+    /// ZSTD compression level to apply to adapter outputs before storing in cache db
+    ///
+    ///  Ranges from 1 - 22
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-cache-compression-level",
+        hidden_short_help = true,
+        require_equals = true,
+        help = ""
+    )]
+    pub compression_level: CacheCompressionLevel,
+
+    /// Directory of the cache
+    ///
+    /// If not given, the nearest `.rga-cache` directory in or above the searched directory is used,
+    /// so a project can keep its cache next to its data. Otherwise the cache is stored in the default location
+    /// described in --rga-no-cache.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-cache-path",
+        alias = "rga-cache-dir",
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub path: CachePath,
+
+    /// URL of a shared HTTP cache
+    ///
+    /// Cache entries that are not in the local cache are fetched from {url}/{adapter}.v{version}/{sha256 of key},
+    /// and new entries are uploaded there with PUT, so a team or CI fleet can share adapter outputs
+    /// (e.g. with an S3 bucket or any web server that accepts uploads).
+    /// If the server can't be reached, rga continues with the local cache only.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-cache-remote",
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub remote: Option<String>,
+
+    /// Bearer token sent to the --rga-cache-remote server
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-cache-remote-token",
+        env = "RGA_CACHE_REMOTE_TOKEN",
+        hide_env_values = true,
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub remote_token: Option<String>,
+
+    /// Encrypt cached outputs with this key
+    ///
+    /// The extracted text of files is stored encrypted (XChaCha20-Poly1305 with a key derived from this one with scrypt
+    /// and a random salt stored in the cache), so it doesn't leak from the cache directory. Paths and modification times
+    /// of the cached files are not encrypted, and `rga cache export` writes decrypted entries. Entries that can't be
+    /// decrypted with the key are recomputed.
+    /// Use a long random key.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-cache-encryption-key",
+        env = "RGA_CACHE_ENCRYPTION_KEY",
+        hide_env_values = true,
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub encryption_key: Option<String>,
+
+    /// Only read from the cache, never write to it
+    ///
+    /// For running against a prebuilt cache on a read-only filesystem (e.g. in a container image or on a network share).
+    /// Cache misses are computed in memory and not stored. The cache must not be modified while rga is running with this flag.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-cache-readonly", hidden_short_help = true)]
+    pub readonly: bool,
+
+    /// Print cache statistics after searching
+    ///
+    /// Shows the number of cache hits and misses, the bytes written to the cache
+    /// and the adapter time saved by the cache. The same numbers are logged per file with --debug.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-cache-stats", hidden_short_help = true)]
+    pub stats: bool,
+}
+
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct SqliteConfig {
+    /// Recurse into sqlite BLOB columns
+    ///
+    /// By default, BLOB cells are only shown as their size.
+    /// With this flag, their content is sniffed for known file types (gzip, zip, pdf, ...)
+    /// and run through the matching adapter, the same as a file within an archive.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-sqlite-recurse-blobs", hidden_short_help = true)]
+    pub recurse_blobs: bool,
+
+    /// Max size of a sqlite BLOB to recurse into
+    ///
+    /// Larger blobs are only shown as their size. Allowed suffixes: k M G
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-sqlite-max-blob-len",
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub max_blob_len: SqliteMaxBlobLen,
+
+    /// Only dump these sqlite tables
+    ///
+    /// Comma separated list of table names, glob patterns are allowed (e.g. "users,order_*").
+    /// By default, all tables are dumped.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-sqlite-include-tables",
+        require_equals = true,
+        require_delimiter = true,
+        hidden_short_help = true
+    )]
+    pub include_tables: Vec<String>,
+
+    /// Skip these sqlite tables
+    ///
+    /// Comma separated list of table names, glob patterns are allowed (e.g. "logs,*_fts*").
+    /// Useful to skip huge log or full text index tables.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-sqlite-exclude-tables",
+        require_equals = true,
+        require_delimiter = true,
+        hidden_short_help = true
+    )]
+    pub exclude_tables: Vec<String>,
 }
-impl Default for CachePath {
-    fn default() -> Self {
-        let pd = project_dirs().expect("could not get cache path");
-        let app_cache = pd.cache_dir();
-        CachePath(app_cache.to_str().expect("cache path not utf8").to_owned())
-    }
+
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct NetcdfConfig {
+    /// Also dump the values of small netCDF variables
+    ///
+    /// By default, only the header (dimensions, variables and attributes) is output.
+    /// With this option, the values of all variables with at most this many elements
+    /// (e.g. coordinate variables, station names) are output as well.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value = "0",
+        long = "--rga-netcdf-max-values",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub max_values: usize,
 }
 
-#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
-pub struct CacheMaxBlobLen(pub usize);
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct DicomConfig {
+    /// Redact identifying DICOM tags
+    ///
+    /// Replaces the values of patient tags (name, id, birth date, ...) as well as
+    /// institution, physician and operator names with "[redacted]", so search results can be shared.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-dicom-redact", hidden_short_help = true)]
+    pub redact: bool,
+}
 
-impl ToString for CacheMaxBlobLen {
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct PcapConfig {
+    /// Also output the payload of captured packets
+    ///
+    /// By default, only a one-line summary (time, addresses, protocol) is output per packet.
+    /// With this flag, the transport payload is appended to the summary, with non-printable bytes replaced by ".".
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-pcap-payload", hidden_short_help = true)]
+    pub payload: bool,
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, FromStr)]
+pub struct StringsMinLen(pub usize);
+
+impl ToString for StringsMinLen {
     fn to_string(&self) -> String {
         self.0.to_string()
     }
 }
-impl Default for CacheMaxBlobLen {
+impl Default for StringsMinLen {
     fn default() -> Self {
-        CacheMaxBlobLen(2000000)
-    }
-}
-
-impl FromStr for CacheMaxBlobLen {
-    type Err = anyhow::Error;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let suffix = s.chars().last();
-        if let Some(suffix) = suffix {
-            Ok(CacheMaxBlobLen(match suffix {
-                'k' | 'M' | 'G' => usize::from_str(s.trim_end_matches(suffix))
-                    .with_context(|| format!("Could not parse int"))
-                    .map(|e| {
-                        e * match suffix {
-                            'k' => 1000,
-                            'M' => 1000_000,
-                            'G' => 1000_000_000,
-                            _ => panic!("impossible"),
-                        }
-                    }),
-                _ => usize::from_str(s).with_context(|| format!("Could not parse int")),
-            }?))
-        } else {
-            Err(format_err!("empty byte input"))
-        }
+        StringsMinLen(4)
     }
 }
 
-/// # rga configuration
-///
-/// this is kind of a "polyglot" struct, since it serves three functions
-///
-/// 1. describing the command line arguments using structopt+clap and for man page / readme generation
-/// 2. describing the config file format (output as JSON schema via schemars)
-#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone)]
-#[structopt(
-    name = "ripgrep-all",
-    rename_all = "kebab-case",
-    about = env!("CARGO_PKG_DESCRIPTION"),
-    author = env!("CARGO_PKG_HOMEPAGE"),
-    // TODO: long_about does not seem to work to only show this on short help
-    after_help = "-h shows a concise overview, --help shows more detail and advanced options.\n\nAll other options not shown here are passed directly to rg, especially [PATTERN] and [PATH ...]",
-    usage = "rga [RGA OPTIONS] [RG OPTIONS] PATTERN [PATH ...]"
-)]
-pub struct RgaConfig {
-    /// Use more accurate but slower matching by mime type
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct StringsConfig {
+    /// Search printable strings in binary files no adapter handles
     ///
-    /// By default, rga will match files using file extensions.
-    /// Some programs, such as sqlite3, don't care about the file extension at all,
-    /// so users sometimes use any or no extension at all. With this flag, rga
-    /// will try to detect the mime type of input files using the magic bytes
-    /// (similar to the `file` utility), and use that to choose the adapter.
-    /// Detection is only done on the first 8KiB of the file, since we can't always seek on the input (in archives).
+    /// Like the `strings` tool, extracts runs of printable ASCII / UTF-8 and UTF-16 text
+    /// from binary files that are not matched by any adapter (e.g. firmware images or unknown formats).
+    /// Other files without a matching adapter are searched as is. This makes rga look at every file, so it is slower.
     #[serde(default, skip_serializing_if = "is_default")]
-    #[structopt(long = "--rga-accurate")]
-    pub accurate: bool,
+    #[structopt(long = "--rga-strings-fallback", hidden_short_help = true)]
+    pub fallback: bool,
 
-    /// Change which adapters to use and in which priority order (descending)
-    ///
-    /// "foo,bar" means use only adapters foo and bar.
-    /// "-bar,baz" means use all default adapters except for bar and baz.
-    /// "+bar,baz" means use all default adapters and also bar and baz.
+    /// Minimum length of strings extracted by --rga-strings-fallback
     #[serde(default, skip_serializing_if = "is_default")]
     #[structopt(
-        long = "--rga-adapters",
+        default_value,
+        long = "--rga-strings-min-len",
         require_equals = true,
-        require_delimiter = true
+        hidden_short_help = true
     )]
-    pub adapters: Vec<String>,
-
-    #[serde(default, skip_serializing_if = "is_default")]
-    #[structopt(flatten)]
-    pub cache: CacheConfig,
+    pub min_len: StringsMinLen,
+}
 
-    /// Maximum nestedness of archives to recurse into
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct EncodingConfig {
+    /// Text encoding of files without a matching adapter
+    ///
+    /// By default, the encoding of text files that are passed through (e.g. inside of archives)
+    /// is detected from the byte order mark or guessed from the content, and
+    /// UTF-16, Latin-1, Shift-JIS, GBK etc. are transcoded to UTF-8 before searching.
+    /// Set to an encoding label (e.g. "utf-16le", "sjis") to always use that encoding,
+    /// or to "none" to search the raw bytes.
     #[serde(default, skip_serializing_if = "is_default")]
     #[structopt(
-        default_value,
-        long = "--rga-max-archive-recursion",
+        long = "--rga-encoding",
         require_equals = true,
         hidden_short_help = true
     )]
-    pub max_archive_recursion: MaxArchiveRecursion,
+    pub label: Option<String>,
+}
 
-    //////////////////////////////////////////
-    //////////////////////////// Config file only
-    //////////////////////////////////////////
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct GitConfig {
+    /// Also search the history of git repositories
+    ///
+    /// For every searched directory that is the root of a git repository (has a .git directory),
+    /// all file versions reachable from any branch or tag are searched as well,
+    /// so you can find text that was deleted from the working tree.
+    /// Every version of a file is only searched once. Results are prefixed with the commit id and path.
     #[serde(default, skip_serializing_if = "is_default")]
-    #[structopt(skip)]
-    pub custom_adapters: Option<Vec<CustomAdapterConfig>>,
-    //////////////////////////////////////////
-    //////////////////////////// CMD line only
-    //////////////////////////////////////////
-    #[serde(skip)]
-    #[structopt(long = "--rga-config-file", require_equals = true)]
-    pub config_file_path: Option<String>,
-
-    /// same as passing path directly, except if argument is empty
-    /// kinda hacky, but if no file is found, fzf calls rga with empty string as path, which causes No such file or directory from rg. So filter those cases and return specially
-    #[serde(skip)]
-    #[structopt(long = "--rga-fzf-path", require_equals = true, hidden = true)]
-    pub fzf_path: Option<String>,
+    #[structopt(long = "--rga-git-history", hidden_short_help = true)]
+    pub history: bool,
+}
 
-    // these arguments are basically "subcommands" that stop the process, so don't serialize them
-    #[serde(skip)]
-    #[structopt(long = "--rga-list-adapters", help = "List all known adapters")]
-    pub list_adapters: bool,
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct CsvConfig {
+    /// Output one line per cell instead of one per row (csv adapter)
+    ///
+    /// By default, the csv adapter outputs every row as `row 42: name=value, email=value`.
+    /// With this flag, every non-empty cell is output on its own line with its cell reference as in a spreadsheet
+    /// (the header is row 1), e.g. `B43 (email): value`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-csv-cells", hidden_short_help = true)]
+    pub cells: bool,
+}
 
-    #[serde(skip)]
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct SecretsConfig {
+    /// Password for all protected documents (encrypted zip entries, pdfs and office documents)
+    ///
+    /// Used if no other password is found for a file (see --rga-password-file and --rga-keyring).
+    /// Prefer the environment variable RGA_PASSWORD or --rga-ask-password,
+    /// since command line arguments are visible to other users of the system.
+    #[serde(default, skip_serializing_if = "is_default")]
     #[structopt(
-        long = "--rga-print-config-schema",
-        help = "Print the JSON Schema of the configuration file"
+        long = "--rga-password",
+        env = "RGA_PASSWORD",
+        hide_env_values = true,
+        require_equals = true,
+        hidden_short_help = true
     )]
-    pub print_config_schema: bool,
+    pub password: Option<String>,
 
-    #[serde(skip)]
-    #[structopt(long, help = "Show help for ripgrep itself")]
-    pub rg_help: bool,
+    /// File with passwords for protected documents, keyed by glob
+    ///
+    /// Every line contains a glob and a password separated by whitespace, e.g. `invoices/*.pdf hunter2`.
+    /// The glob is matched against the full path and the file name, the first matching line is used.
+    /// Empty lines and lines starting with # are ignored. Used for zip, pdf and office documents,
+    /// before the options of the format.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-password-file",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub password_file: Option<String>,
 
-    #[serde(skip)]
-    #[structopt(long, help = "Show version of ripgrep itself")]
-    pub rg_version: bool,
-}
+    /// Look up passwords of protected documents in the OS keyring
+    ///
+    /// The password is stored for the service "ripgrep-all" and the file name as user,
+    /// or the format (zip, pdf or office) for all files of a format,
+    /// e.g. `secret-tool store --label=rga service ripgrep-all username pdf` with the Secret Service.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-keyring", hidden_short_help = true)]
+    pub keyring: bool,
 
-#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
-pub struct CacheConfig {
-    /// Disable caching of results
+    /// Interactively ask for the password of protected documents before searching
     ///
-    /// By default, rga caches the extracted text, if it is small enough,
-    /// to a database in ~/.cache/rga on Linux,
-    /// ~/Library/Caches/rga on macOS,
-    /// or C:\Users\username\AppData\Local\rga on Windows.
-    /// This way, repeated searches on the same set of files will be much faster.
-    /// If you pass this flag, all caching will be disabled.
+    /// Only if rga is run from a terminal and no --rga-password is given.
     #[serde(default, skip_serializing_if = "is_default")]
-    #[structopt(long = "--rga-no-cache")]
-    pub disabled: bool,
+    #[structopt(long = "--rga-ask-password", hidden_short_help = true)]
+    pub ask_password: bool,
+}
 
-    /// Max compressed size to cache
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct ZipConfig {
+    /// Password for encrypted zip files
     ///
-    /// Longest byte length (after compression) to store in cache. Longer adapter outputs will not be cached and recomputed every time. Allowed suffixes: k M G
+    /// Used to decrypt password-protected entries (traditional PKWARE encryption) in zip files.
+    /// Prefer the environment variable RGA_ZIP_PASSWORD or --rga-zip-ask-password,
+    /// since command line arguments are visible to other users of the system.
     #[serde(default, skip_serializing_if = "is_default")]
     #[structopt(
-        default_value,
-        long = "--rga-cache-max-blob-len",
-        hidden_short_help = true,
+        long = "--rga-zip-password",
+        env = "RGA_ZIP_PASSWORD",
+        hide_env_values = true,
         require_equals = true,
-        // parse(try_from_str = parse_readable_bytes_str)
+        hidden_short_help = true
     )]
-    pub max_blob_len: CacheMaxBlobLen,
+    pub password: Option<String>,
 
-    /// ZSTD compression level to apply to adapter outputs before storing in cache db
+    /// Interactively ask for the password of encrypted zip files before searching
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-zip-ask-password", hidden_short_help = true)]
+    pub ask_password: bool,
+}
+
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct PdfConfig {
+    /// Password for encrypted PDF files
     ///
-    ///  Ranges from 1 - 22
+    /// Passed to pdftotext as both user and owner password.
+    /// Note that it is visible in the process list while pdftotext runs.
     #[serde(default, skip_serializing_if = "is_default")]
     #[structopt(
-        default_value,
-        long = "--rga-cache-compression-level",
-        hidden_short_help = true,
+        long = "--rga-pdf-password",
+        env = "RGA_PDF_PASSWORD",
+        hide_env_values = true,
         require_equals = true,
-        help = ""
+        hidden_short_help = true
     )]
-    pub compression_level: CacheCompressionLevel,
+    pub password: Option<String>,
 
-    /// ZSTD compression level to apply to adapter outputs before storing in cache db
+    /// File with passwords for encrypted PDF files, keyed by glob
     ///
-    ///  Ranges from 1 - 22
+    /// Every line contains a glob and a password separated by whitespace, e.g. `invoices/*.pdf hunter2`.
+    /// The glob is matched against the full path and the file name, the first matching line is used.
+    /// Empty lines and lines starting with # are ignored. Files not matching any glob use --rga-pdf-password.
     #[serde(default, skip_serializing_if = "is_default")]
     #[structopt(
-        default_value,
-        long = "--rga-cache-path",
-        hidden_short_help = true,
+        long = "--rga-pdf-password-file",
         require_equals = true,
-        help = ""
+        hidden_short_help = true
     )]
-    pub path: CachePath,
+    pub password_file: Option<String>,
 }
 
 static RGA_CONFIG: &str = "RGA_CONFIG";
@@ -261,6 +1763,50 @@ fn json_merge(a: &mut Value, b: &Value) {
     }
 }
 
+/// read a config file given with --rga-config-file or in the config dir, as TOML if it ends with .toml and JSON otherwise
+fn parse_config_file(config_filename: &Path) -> Result<Value> {
+    let config_filename_str = config_filename.to_string_lossy();
+    let config_file_contents = std::fs::read_to_string(config_filename)
+        .with_context(|| format!("Could not read config file {}", config_filename_str))?;
+    let config_json: Value = if config_filename.extension() == Some("toml".as_ref()) {
+        toml::from_str(&config_file_contents)
+            .with_context(|| format!("Could not parse config toml {}", config_filename_str))?
+    } else {
+        serde_json::from_str(&config_file_contents).context("Could not parse config json")?
+    };
+    {
+        // just for error messages. The file may only contain some of the settings
+        let mut full_config = serde_json::to_value(&RgaConfig::default())?;
+        json_merge(&mut full_config, &config_json);
+        serde_json::from_value::<RgaConfig>(full_config).with_context(|| {
+            format!(
+                "Error in config file {}: {}",
+                config_filename_str, config_file_contents
+            )
+        })?;
+    }
+    Ok(config_json)
+}
+
+/// the rga.toml files that are merged over config.jsonc, in order: the one in the config dir, then the one in the current directory
+fn read_toml_config_files() -> Result<Vec<(String, Value)>> {
+    let mut candidates = vec![project_dirs()?.config_dir().join("rga.toml")];
+    if let Ok(cwd) = std::env::current_dir() {
+        candidates.push(cwd.join("rga.toml"));
+    }
+    candidates.dedup();
+    candidates
+        .into_iter()
+        .filter(|path| path.is_file())
+        .map(|path| {
+            Ok((
+                path.to_string_lossy().into_owned(),
+                parse_config_file(&path)?,
+            ))
+        })
+        .collect()
+}
+
 fn read_config_file(path_override: Option<String>) -> Result<(String, Value)> {
     let proj = project_dirs()?;
     let config_dir = proj.config_dir();
@@ -270,19 +1816,7 @@ fn read_config_file(path_override: Option<String>) -> Result<(String, Value)> {
         .unwrap_or(config_dir.join("config.jsonc"));
     let config_filename_str = config_filename.to_string_lossy().into_owned();
     if config_filename.exists() {
-        let config_file_contents = std::fs::read_to_string(config_filename)
-            .with_context(|| format!("Could not read config file json {}", config_filename_str))?;
-        {
-            // just for error messages
-            serde_json::from_str::<RgaConfig>(&config_file_contents).with_context(|| {
-                format!(
-                    "Error in config file {}: {}",
-                    config_filename_str, config_file_contents
-                )
-            })?;
-        }
-        let config_json: serde_json::Value =
-            serde_json::from_str(&config_file_contents).context("Could not parse config json")?;
+        let config_json = parse_config_file(&config_filename)?;
         Ok((config_filename_str, config_json))
     } else if let Some(p) = path_override.as_ref() {
         Err(anyhow::anyhow!("Config file not found: {}", p))?
@@ -309,6 +1843,20 @@ fn read_config_file(path_override: Option<String>) -> Result<(String, Value)> {
         Ok((config_filename_str, config_json))
     }
 }
+/// prompt for the passwords on the terminal if requested and not given otherwise
+fn ask_passwords(config: &mut Value) -> Result<()> {
+    for (section, prompt) in &[
+        ("secrets", "rga: password: "),
+        ("zip", "rga: zip password: "),
+    ] {
+        if config[section]["ask_password"] == true && config[section]["password"].is_null() {
+            if let Some(password) = crate::secrets::prompt(prompt)? {
+                config[section]["password"] = Value::String(password);
+            }
+        }
+    }
+    Ok(())
+}
 fn read_config_env() -> Result<Value> {
     let val = std::env::var(RGA_CONFIG).ok();
     if let Some(val) = val {
@@ -317,7 +1865,38 @@ fn read_config_env() -> Result<Value> {
         serde_json::to_value(&RgaConfig::default()).context("could not create default config")
     }
 }
+/// append the values of --rga-adapter-arg=<adapter>:<arg> to the extra_args of the adapters
+fn add_adapter_args(config: &mut Value, adapter_args: &[String]) -> Result<()> {
+    for adapter_arg in adapter_args {
+        let colon = adapter_arg.find(':').ok_or_else(|| {
+            format_err!(
+                "--rga-adapter-arg must be <adapter>:<arg>, got {:?}",
+                adapter_arg
+            )
+        })?;
+        let (adapter, arg) = (&adapter_arg[..colon], &adapter_arg[colon + 1..]);
+        let extra_args = &mut config["adapter_options"][adapter]["extra_args"];
+        if extra_args.is_null() {
+            *extra_args = Value::Array(vec![]);
+        }
+        extra_args
+            .as_array_mut()
+            .ok_or_else(|| format_err!("extra_args of {} is not a list", adapter))?
+            .push(Value::String(arg.to_string()));
+    }
+    Ok(())
+}
+
 pub fn parse_args<I>(args: I, is_rga_preproc: bool) -> Result<RgaConfig>
+where
+    I: IntoIterator,
+    I::Item: Into<OsString> + Clone,
+{
+    parse_args_in(args, is_rga_preproc, None)
+}
+
+/// parse_args, also applying the .rga.toml files in and above the search root
+fn parse_args_in<I>(args: I, is_rga_preproc: bool, search_root: Option<&Path>) -> Result<RgaConfig>
 where
     I: IntoIterator,
     I::Item: Into<OsString> + Clone,
@@ -325,32 +1904,55 @@ where
     // TODO: don't read config file in rga-preproc for performance (called for every file)
 
     let arg_matches: RgaConfig = RgaConfig::from_iter(args);
-    let args_config = serde_json::to_value(&arg_matches)?;
+    let mut args_config = serde_json::to_value(&arg_matches)?;
+    if arg_matches.page_numbers {
+        // false is not serialized, but has to override the config file
+        args_config["no_page_numbers"] = Value::Bool(false);
+    }
 
     let merged_config = {
         if is_rga_preproc {
             // only read from env and args
             let mut merged_config = read_config_env()?;
             json_merge(&mut merged_config, &args_config);
+            add_adapter_args(&mut merged_config, &arg_matches.adapter_arg)?;
             log::debug!("Config: {}", serde_json::to_string(&merged_config)?);
             merged_config
         } else {
-            // read from config file, env and args
+            // read from config files, env and args
+            let mut toml_configs = if arg_matches.config_file_path.is_none() {
+                read_toml_config_files()?
+            } else {
+                // an explicitly given config file is the only one
+                vec![]
+            };
+            if let Some(search_root) = search_root {
+                toml_configs.extend(read_directory_configs(search_root)?);
+            }
             let (config_filename, config_file_config) =
                 read_config_file(arg_matches.config_file_path)?;
             let env_var_config = read_config_env()?;
             let mut merged_config = config_file_config.clone();
+            for (_, toml_config) in &toml_configs {
+                json_merge(&mut merged_config, toml_config);
+            }
             json_merge(&mut merged_config, &env_var_config);
             json_merge(&mut merged_config, &args_config);
+            add_adapter_args(&mut merged_config, &arg_matches.adapter_arg)?;
             log::debug!(
-                "Configs:\n{}: {}\n{}: {}\nArgs: {}\nMerged: {}",
+                "Configs:\n{}: {}\n{}{}: {}\nArgs: {}\nMerged: {}",
                 config_filename,
                 serde_json::to_string_pretty(&config_file_config)?,
+                toml_configs
+                    .iter()
+                    .map(|(name, config)| format!("{}: {}\n", name, config))
+                    .collect::<String>(),
                 RGA_CONFIG,
                 serde_json::to_string_pretty(&env_var_config)?,
                 serde_json::to_string_pretty(&args_config)?,
                 serde_json::to_string_pretty(&merged_config)?
             );
+            ask_passwords(&mut merged_config)?;
             // pass to child processes
             std::env::set_var(RGA_CONFIG, &merged_config.to_string());
             merged_config
@@ -368,17 +1970,62 @@ where
                 serde_json::to_string_pretty(&merged_config).expect("no tostring")
             )
         })?;
+    for label in &res.entry_name_encoding {
+        crate::entry_names::parse_encoding(label)?;
+    }
+    if res.no_limits {
+        res.remove_limits();
+    }
+    // optional adapters that are modes of the search rather than matched by file type
+    if crate::adapters::adapter_listed(&res.adapters, "strings") {
+        res.strings.fallback = true;
+    }
+    if crate::adapters::adapter_listed(&res.adapters, "githistory") {
+        res.git.history = true;
+    }
     {
         // readd values with [serde(skip)]
         res.fzf_path = arg_matches.fzf_path;
         res.list_adapters = arg_matches.list_adapters;
+        res.print_adapter = arg_matches.print_adapter;
+        res.location_map = arg_matches.location_map;
+        res.doctor = arg_matches.doctor;
+        res.print_rg_args = arg_matches.print_rg_args;
+        res.completions = arg_matches.completions;
+        res.list_subfiles = arg_matches.list_subfiles;
+        res.interactive = arg_matches.interactive;
+        res.bench = arg_matches.bench;
+        res.report = arg_matches.report;
         res.print_config_schema = arg_matches.print_config_schema;
         res.rg_help = arg_matches.rg_help;
         res.rg_version = arg_matches.rg_version;
+        res.warm_cache = arg_matches.warm_cache;
+        res.watch = arg_matches.watch;
+        res.daemon = arg_matches.daemon;
+        res.remote = arg_matches.remote;
     }
     Ok(res)
 }
 
+/// use a different cache directory, also in the rga-preproc processes started afterwards
+pub fn set_cache_path(config: &mut RgaConfig, path: &Path) -> Result<()> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| format_err!("cache path not utf8"))?
+        .to_owned();
+    update_config_env(|env| env["cache"]["path"] = Value::String(path.clone()))?;
+    config.cache.path = CachePath(path);
+    Ok(())
+}
+
+/// change the config passed to child processes (rga-preproc)
+pub fn update_config_env(update: impl FnOnce(&mut Value)) -> Result<()> {
+    let mut env_config = read_config_env()?;
+    update(&mut env_config);
+    std::env::set_var(RGA_CONFIG, &env_config.to_string());
+    Ok(())
+}
+
 /// Split arguments into the ones we care about and the ones rg cares about
 pub fn split_args(is_rga_preproc: bool) -> Result<(RgaConfig, Vec<OsString>)> {
     let mut app = RgaConfig::clap();
@@ -406,7 +2053,18 @@ pub fn split_args(is_rga_preproc: bool) -> Result<(RgaConfig, Vec<OsString>)> {
             }
         });
     debug!("rga (our) args: {:?}", our_args);
-    let matches = parse_args(our_args, is_rga_preproc).context("Could not parse config")?;
+    // rga-preproc is given the file, rga the arguments of rg
+    let root = if is_rga_preproc {
+        passthrough_args
+            .last()
+            .map(PathBuf::from)
+            .filter(|p| p.exists())
+            .unwrap_or_else(|| PathBuf::from("."))
+    } else {
+        search_root(&passthrough_args)
+    };
+    let matches =
+        parse_args_in(our_args, is_rga_preproc, Some(&root)).context("Could not parse config")?;
     if matches.rg_help {
         passthrough_args.insert(0, "--help".into());
     }
@@ -416,3 +2074,38 @@ pub fn split_args(is_rga_preproc: bool) -> Result<(RgaConfig, Vec<OsString>)> {
     debug!("rga (passthrough) args: {:?}", passthrough_args);
     Ok((matches, passthrough_args))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn directory_config_allowlist() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(
+            dir.path().join(DIRECTORY_CONFIG),
+            r#"
+accurate = true
+sandbox = false
+adapter_arg = ["pandoc=--lua-filter=evil.lua"]
+
+[cache]
+path = "/tmp/elsewhere"
+remote = "https://evil.example/cache"
+remote_token = "token"
+
+[secrets]
+password_file = "/home/me/.passwords"
+
+[csv]
+cells = true
+"#,
+        )?;
+        let configs = read_directory_configs(dir.path())?;
+        assert_eq!(configs.len(), 1);
+        let mut keys: Vec<&String> = configs[0].1.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["accurate", "csv"]);
+        Ok(())
+    }
+}
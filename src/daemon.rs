@@ -0,0 +1,390 @@
+//! rga --rga-daemon: keeps running and answers requests on a Unix socket, so editor plugins and repeated searches
+//! (with --rga-use-daemon) don't start rga, the adapters and their programs for every search. Persistent custom
+//! adapters and the pandoc server (--rga-pandoc-server) keep running between the requests.
+//!
+//! The requests and responses are JSON-RPC 2.0, framed like those of persistent adapters (see adapters::persistent).
+//! A connection can send any number of requests, one after another. The methods:
+//!
+//! - `preprocess`, params `{"path": "docs/report.pdf", "cwd": "/home/me"}`: result `{"text": "..."}`,
+//!   the text rga searches in the file
+//! - `search`, params `{"pattern": "needle", "rg_args": ["-i"], "paths": ["docs"], "cwd": "/home/me"}`:
+//!   result `{"output": "...", "errors": "...", "exit_code": 0}`, the output of rg for the arguments.
+//!   Searches like --rga-batch, the paths of files with an adapter are absolute. The pattern (optional with -e or -f
+//!   in rg_args) and the paths are given to rg after `--`, so they are never taken as options
+//! - `shutdown`: stops the daemon after answering
+
+use crate::adapters::persistent::{read_message, write_message};
+use crate::adapters::spawning::map_exe_error;
+use crate::batch::{batch_files, pre_globs, preprocess_batch, RG_ARGS};
+use crate::config::{CachePath, RgaConfig};
+use crate::exit_code::{is_quiet, SearchStatus};
+use crate::extract::{Extractor, ExtractorBuilder};
+use crate::rg_args::RgArgs;
+use crate::rg_json::{rewrite_message, set_json_prefixes};
+use anyhow::*;
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::ffi::OsString;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_REQUEST: i64 = -32600;
+const FAILED: i64 = 1;
+
+/// --rga-daemon-socket, or daemon.sock in the default cache directory (also with a project cache)
+pub fn socket_path(config: &RgaConfig) -> PathBuf {
+    match &config.daemon_socket {
+        Some(path) => PathBuf::from(path),
+        None => Path::new(&CachePath::default().0).join("daemon.sock"),
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct PreprocessParams {
+    path: PathBuf,
+    cwd: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SearchParams {
+    pattern: Option<String>,
+    #[serde(default)]
+    rg_args: Vec<String>,
+    #[serde(default)]
+    paths: Vec<String>,
+    cwd: PathBuf,
+}
+
+impl SearchParams {
+    fn rg_args(self) -> Result<RgArgs> {
+        RgArgs::from_parts(
+            self.rg_args.into_iter().map(OsString::from).collect(),
+            self.pattern.map(OsString::from),
+            self.paths.into_iter().map(OsString::from).collect(),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SearchResult {
+    pub output: String,
+    pub errors: String,
+    /// like rg: 0 if something was found, 1 if not, 2 on errors
+    pub exit_code: i32,
+}
+
+/// runs rg with its output captured. With files, their preprocessed output is searched from stdin
+fn run_rg(mut cmd: Command, files: Option<(Vec<PathBuf>, &RgaConfig)>) -> Result<Output> {
+    cmd.stdin(if files.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    })
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped());
+    debug!("daemon running {:?}", cmd);
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| map_exe_error(e, "rg", "Please make sure you have ripgrep installed."))?;
+    // written on another thread, otherwise rg could block on writing its output while we write its input
+    let writer = files.map(|(files, config)| {
+        let config = config.clone();
+        let mut stdin = child.stdin.take().expect("is piped");
+        std::thread::spawn(move || preprocess_batch(files, &config, &mut stdin))
+    });
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        writer
+            .join()
+            .map_err(|_| format_err!("preprocessing thread panicked"))??;
+    }
+    Ok(output)
+}
+
+/// searches like --rga-batch, with the output of rg returned instead of printed
+pub fn search(config: &RgaConfig, args: &RgArgs, cwd: &Path) -> Result<SearchResult> {
+    let mut config = config.clone();
+    let json = args.has("--json");
+    if json {
+        set_json_prefixes(&mut config)?;
+    }
+    let walk = args.walk_options();
+    let pre_glob = pre_globs(&config)?;
+
+    let mut outputs = Vec::new();
+    if pre_glob != ["*"] {
+        let mut cmd = Command::new("rg");
+        cmd.current_dir(cwd)
+            .args(RG_ARGS)
+            .args(&args.options)
+            .args(
+                pre_glob
+                    .iter()
+                    .flat_map(|g| vec!["--glob".to_string(), format!("!{}", g)]),
+            )
+            .args(args.positional_args(&args.paths));
+        outputs.push(run_rg(cmd, None)?);
+    }
+    let roots: Vec<PathBuf> = args.roots().iter().map(|r| cwd.join(r)).collect();
+    let files = batch_files(&roots, &config, &walk)?;
+    debug!("daemon preprocessing {} files", files.len());
+    let mut cmd = Command::new("rg");
+    cmd.current_dir(cwd)
+        .args(RG_ARGS)
+        .args(args.to_args_with_paths(&["-"]));
+    outputs.push(run_rg(cmd, Some((files, &config)))?);
+
+    let mut result = SearchResult::default();
+    let mut status = SearchStatus::default();
+    for output in &outputs {
+        status.add(output.status);
+    }
+    result.exit_code = status.code(is_quiet(&args.options));
+    for output in outputs {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if json {
+            for line in stdout.lines() {
+                result.output.push_str(&rewrite_message(line));
+                result.output.push('\n');
+            }
+        } else {
+            result.output.push_str(&stdout);
+        }
+        result
+            .errors
+            .push_str(&String::from_utf8_lossy(&output.stderr));
+    }
+    Ok(result)
+}
+
+fn preprocess(extractor: &Extractor, params: Value) -> Result<Value> {
+    let params: PreprocessParams = serde_json::from_value(params)?;
+    let path = match params.cwd {
+        Some(cwd) => cwd.join(params.path),
+        None => params.path,
+    };
+    Ok(json!({ "text": extractor.extract_to_string(&path)? }))
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+fn respond(request: Request, extractor: &Extractor, stop: &AtomicBool) -> Value {
+    let result = match request.method.as_str() {
+        "preprocess" => preprocess(extractor, request.params),
+        "search" => serde_json::from_value(request.params)
+            .map_err(Error::from)
+            .and_then(|p: SearchParams| {
+                let cwd = p.cwd.clone();
+                search(extractor.config(), &p.rg_args()?, &cwd)
+            })
+            .and_then(|r| serde_json::to_value(r).map_err(Error::from)),
+        "shutdown" => {
+            stop.store(true, Ordering::SeqCst);
+            Ok(Value::Null)
+        }
+        other => {
+            return error_response(
+                request.id,
+                METHOD_NOT_FOUND,
+                format!("unknown method {}", other),
+            )
+        }
+    };
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": request.id, "result": result}),
+        Err(e) => error_response(request.id, FAILED, format!("{:#}", e)),
+    }
+}
+
+/// answers the requests of a connection, until it is closed or the daemon should stop
+fn handle_connection(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+    config: &RgaConfig,
+    stop: &AtomicBool,
+) -> Result<()> {
+    let extractor = ExtractorBuilder::from_config(config.clone()).build()?;
+    while let Ok(body) = read_message(reader) {
+        let response = match serde_json::from_slice::<Request>(&body) {
+            Ok(request) => respond(request, &extractor, stop),
+            Err(e) => error_response(Value::Null, INVALID_REQUEST, format!("{}", e)),
+        };
+        write_message(writer, &serde_json::to_vec(&response)?)?;
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// --rga-daemon: answer requests on the socket until a shutdown request
+#[cfg(unix)]
+pub fn serve(config: &RgaConfig) -> Result<()> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::Arc;
+
+    let path = socket_path(config);
+    if UnixStream::connect(&path).is_ok() {
+        bail!("a daemon is already listening on {}", path.display());
+    }
+    if path.exists() {
+        // left behind by a daemon that was killed
+        std::fs::remove_file(&path)?;
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("could not listen on {}", path.display()))?;
+    eprintln!("rga daemon listening on {}", path.display());
+    let stop = Arc::new(AtomicBool::new(false));
+    for stream in listener.incoming() {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("{}", e);
+                continue;
+            }
+        };
+        let config = config.clone();
+        let stop = stop.clone();
+        let path = path.clone();
+        std::thread::spawn(move || {
+            let res = stream
+                .try_clone()
+                .map_err(Error::from)
+                .and_then(|mut writer| {
+                    handle_connection(
+                        &mut std::io::BufReader::new(stream),
+                        &mut writer,
+                        &config,
+                        &stop,
+                    )
+                });
+            if let Err(e) = res {
+                debug!("daemon connection failed: {:#}", e);
+            }
+            if stop.load(Ordering::SeqCst) {
+                // wake up the listener, so it sees that it should stop
+                UnixStream::connect(&path).ok();
+            }
+        });
+    }
+    std::fs::remove_file(&path).ok();
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn serve(_config: &RgaConfig) -> Result<()> {
+    bail!("--rga-daemon needs Unix sockets, which this platform does not have")
+}
+
+#[cfg(unix)]
+fn call(stream: std::os::unix::net::UnixStream, method: &str, params: Value) -> Result<Value> {
+    let request = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+    let mut writer = stream.try_clone()?;
+    write_message(&mut writer, &serde_json::to_vec(&request)?)?;
+    let response: Value =
+        serde_json::from_slice(&read_message(&mut std::io::BufReader::new(stream))?)?;
+    if let Some(error) = response.get("error") {
+        bail!("daemon: {}", error["message"].as_str().unwrap_or_default());
+    }
+    Ok(response["result"].clone())
+}
+
+/// sends one request to the daemon listening on the socket
+#[cfg(unix)]
+pub fn request(socket: &Path, method: &str, params: Value) -> Result<Value> {
+    let stream = std::os::unix::net::UnixStream::connect(socket)
+        .with_context(|| format!("no daemon listening on {}", socket.display()))?;
+    call(stream, method, params)
+}
+
+/// --rga-use-daemon: the search by the daemon, None if it is not running
+#[cfg(unix)]
+pub fn search_with_daemon(config: &RgaConfig, args: &[OsString]) -> Result<Option<SearchResult>> {
+    let socket = socket_path(config);
+    let stream = match std::os::unix::net::UnixStream::connect(&socket) {
+        Ok(stream) => stream,
+        Err(e) => {
+            debug!("no daemon on {}: {}", socket.display(), e);
+            return Ok(None);
+        }
+    };
+    let args = RgArgs::parse(args);
+    let lossy = |args: &[OsString]| -> Vec<String> {
+        args.iter()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    };
+    let params = SearchParams {
+        pattern: args
+            .pattern
+            .as_ref()
+            .map(|p| p.to_string_lossy().into_owned()),
+        rg_args: lossy(&args.options),
+        paths: lossy(&args.paths),
+        cwd: std::env::current_dir()?,
+    };
+    let params = serde_json::to_value(params)?;
+    Ok(Some(serde_json::from_value(call(
+        stream, "search", params,
+    )?)?))
+}
+
+#[cfg(not(unix))]
+pub fn search_with_daemon(_config: &RgaConfig, _args: &[OsString]) -> Result<Option<SearchResult>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn daemon() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut config = RgaConfig::default();
+        config.cache.disabled = true;
+        config.daemon_socket = Some(dir.path().join("rga.sock").to_string_lossy().into_owned());
+        let socket = socket_path(&config);
+        let server = {
+            let config = config.clone();
+            std::thread::spawn(move || serve(&config))
+        };
+        while !socket.exists() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let gz = test_data_dir().join("hello.gz");
+        let result = request(&socket, "preprocess", json!({ "path": gz }))?;
+        assert_eq!(result["text"], "hello\n\n");
+        let err = request(&socket, "nope", Value::Null).unwrap_err();
+        assert!(format!("{}", err).contains("unknown method nope"));
+        assert!(serve(&config).is_err(), "already running");
+
+        request(&socket, "shutdown", Value::Null)?;
+        server.join().expect("daemon panicked")?;
+        assert!(!socket.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+//! --rga-verbose: why each file is handled the way it is. The adapter and the matcher that chose it, the mime type,
+//! and the name the content is passed on under (e.g. foo.tar for the decompressed foo.tar.zst), so the chain of adapters
+//! can be followed. Files that are skipped (e.g. because of --rga-max-filesize) are listed with the reason.
+//!
+//! rga prints them after the search (every rga-preproc process appends its detections to a file, like the failures).
+//! rga-preproc prints them to stderr, and with --format json they are records of the output.
+//! Files in archives are only listed when the archive is adapted, not when its output is read from the cache.
+
+use crate::matching::FileMatcher;
+use anyhow::{Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// file that every rga-preproc process appends its detections to, set by rga
+pub const DETECTIONS_FILE_ENV: &str = "RGA_DETECTIONS_FILE";
+
+lazy_static::lazy_static! {
+    /// the detections of this process, written by flush
+    static ref DETECTIONS: Mutex<Vec<Detection>> = Mutex::new(Vec::new());
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MatchedBy {
+    /// "extension", "glob", "magic", "content", "mime" or "fallback" (--rga-strings-fallback)
+    pub kind: String,
+    /// the extension, glob, bytes, regex or mime type that matched
+    pub pattern: String,
+    /// e.g. "file extension .zst"
+    pub description: String,
+}
+
+impl From<&FileMatcher> for MatchedBy {
+    fn from(matcher: &FileMatcher) -> MatchedBy {
+        if matcher.pattern() == "strings-fallback" {
+            return MatchedBy {
+                kind: "fallback".to_string(),
+                pattern: String::new(),
+                description: "--rga-strings-fallback (binary file without adapter)".to_string(),
+            };
+        }
+        MatchedBy {
+            kind: matcher.kind().to_string(),
+            pattern: matcher.pattern().to_string(),
+            description: matcher.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Detection {
+    /// the path of the file (within archives: the path within the archive)
+    pub path: String,
+    /// how deep the file is nested in archives and compressed files. Files follow the file they are in
+    pub depth: i32,
+    /// with --rga-accurate or --rga-mime
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mimetype: Option<String>,
+    /// None if the file is searched as it is
+    pub adapter: Option<String>,
+    pub matched_by: Option<MatchedBy>,
+    /// the name the adapter passes the content on under (e.g. by decompress), which the next adapter is chosen by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub then: Option<String>,
+    /// why the file is not searched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+}
+
+impl Detection {
+    pub fn new(path: &Path, depth: i32) -> Detection {
+        Detection {
+            path: path.to_string_lossy().into_owned(),
+            depth,
+            mimetype: None,
+            adapter: None,
+            matched_by: None,
+            then: None,
+            skipped: None,
+        }
+    }
+
+    pub fn skipped(path: &Path, depth: i32, reason: &str) -> Detection {
+        Detection {
+            skipped: Some(reason.to_string()),
+            ..Detection::new(path, depth)
+        }
+    }
+}
+
+/// e.g. "foo.tar.zst: decompress (matched by file extension .zst), then foo.tar", indented by the depth
+impl std::fmt::Display for Detection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}: ",
+            "  ".repeat(self.depth.max(0) as usize),
+            self.path
+        )?;
+        if let Some(reason) = &self.skipped {
+            return write!(f, "skipped ({})", reason);
+        }
+        match (&self.adapter, &self.matched_by) {
+            (Some(adapter), Some(matched_by)) => {
+                write!(f, "{} (matched by {})", adapter, matched_by.description)?
+            }
+            (Some(adapter), None) => write!(f, "{}", adapter)?,
+            (None, _) => write!(f, "searched as text")?,
+        }
+        if let Some(mimetype) = &self.mimetype {
+            write!(f, ", mime type {}", mimetype)?;
+        }
+        if let Some(then) = &self.then {
+            write!(f, ", then {}", then)?;
+        }
+        Ok(())
+    }
+}
+
+/// remember the detection until flush
+pub fn record(detection: Detection) {
+    debug!("{}", detection);
+    DETECTIONS.lock().unwrap().push(detection);
+}
+
+/// Append the detections of this process to the file of rga, or print them to stderr if there is none (rga-preproc
+/// run by itself). All at once, so the files in an archive are not mixed with the files of other processes
+pub fn flush() -> Result<()> {
+    let detections = std::mem::take(&mut *DETECTIONS.lock().unwrap());
+    if detections.is_empty() {
+        return Ok(());
+    }
+    match std::env::var_os(DETECTIONS_FILE_ENV) {
+        Some(path) => {
+            let mut lines = Vec::new();
+            for detection in &detections {
+                serde_json::to_writer(&mut lines, detection)?;
+                lines.push(b'\n');
+            }
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)?
+                .write_all(&lines)?;
+        }
+        None => {
+            for detection in &detections {
+                eprintln!("rga: {}", detection);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Calls flush when dropped, at the end of rga-preproc
+pub struct FlushGuard;
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        if let Err(e) = flush() {
+            warn!("could not write the detected file types: {:#}", e);
+        }
+    }
+}
+
+pub fn read_detections(path: &Path) -> Result<Vec<Detection>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    BufReader::new(std::fs::File::open(path)?)
+        .lines()
+        .map(|line| serde_json::from_str(&line?).context("could not parse detection"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::matching::FastFileMatcher;
+
+    #[test]
+    fn display() {
+        let matcher = FileMatcher::Fast(FastFileMatcher::FileExtension("zst".to_string()));
+        let detection = Detection {
+            adapter: Some("decompress".to_string()),
+            matched_by: Some(MatchedBy::from(&matcher)),
+            then: Some("foo.tar".to_string()),
+            ..Detection::new(Path::new("foo.tar.zst"), 0)
+        };
+        assert_eq!(
+            detection.to_string(),
+            "foo.tar.zst: decompress (matched by file extension .zst), then foo.tar"
+        );
+        assert_eq!(
+            Detection::new(Path::new("notes.txt"), 1).to_string(),
+            "  notes.txt: searched as text"
+        );
+        assert_eq!(
+            Detection::skipped(Path::new("big.pdf"), 0, "larger than 100MB").to_string(),
+            "big.pdf: skipped (larger than 100MB)"
+        );
+        let json = serde_json::to_value(&detection).unwrap();
+        assert_eq!(json["matched_by"]["kind"], "extension");
+        assert_eq!(json["matched_by"]["pattern"], "zst");
+    }
+}
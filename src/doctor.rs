@@ -0,0 +1,254 @@
+//! --rga-doctor: checks the programs the adapters run, the custom adapters and the cache
+
+use crate::adapters::custom::{builtin_spawning_adapters, CustomAdapterConfig};
+use crate::adapters::plugin::Plugin;
+use crate::adapters::*;
+use crate::config::RgaConfig;
+use crate::preproc_cache::open_cache;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// the programs run by the builtin adapters that are not defined like custom adapters:
+/// (adapter, program, argument to print its version, what to install)
+const PROGRAMS: &[(&str, &str, Option<&str>, &str)] = &[
+    ("split", "7z", Some("i"), "7-Zip (p7zip)"),
+    ("dmg", "7z", Some("i"), "7-Zip (p7zip)"),
+    ("cab", "7z", Some("i"), "7-Zip (p7zip)"),
+    ("squashfs", "7z", Some("i"), "7-Zip (p7zip)"),
+    ("mdb", "mdb-tables", None, "mdbtools"),
+    ("mdb", "mdb-export", None, "mdbtools"),
+    ("netcdf", "ncdump", None, "the netCDF tools"),
+    ("dicom", "dcmdump", Some("--version"), "dcmtk"),
+    ("ffmpeg", "ffmpeg", Some("-version"), "ffmpeg"),
+    ("ffmpeg", "ffprobe", Some("-version"), "ffmpeg"),
+];
+
+/// the extensions a program in PATH can have when it is given without one (PATHEXT on Windows)
+fn program_extensions() -> Vec<String> {
+    if !cfg!(windows) {
+        return vec![];
+    }
+    std::env::var("PATHEXT")
+        .unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string())
+        .split(';')
+        .filter(|ext| !ext.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// the path of the program: the program itself if it is a path, otherwise the first match in PATH
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    if program.contains(std::path::MAIN_SEPARATOR) || program.contains('/') {
+        return Some(PathBuf::from(program)).filter(|p| p.is_file());
+    }
+    let path = std::env::var_os("PATH")?;
+    let extensions = program_extensions();
+    std::env::split_paths(&path)
+        .flat_map(|dir| {
+            let mut candidates = vec![dir.join(program)];
+            candidates.extend(
+                extensions
+                    .iter()
+                    .map(|ext| dir.join(format!("{}{}", program, ext))),
+            );
+            candidates
+        })
+        .find(|p| p.is_file())
+}
+
+/// the first line the program prints with the version argument
+fn program_version(path: &Path, version_arg: &str) -> Option<String> {
+    let output = Command::new(path)
+        .arg(version_arg)
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    // some programs (e.g. pdftotext -v) print the version to stderr
+    let text = [output.stdout, output.stderr].concat();
+    String::from_utf8_lossy(&text)
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .map(String::from)
+}
+
+struct Report {
+    problems: usize,
+    /// programs used by multiple adapters (e.g. 7z) are only checked once
+    checked_programs: HashSet<String>,
+}
+
+impl Report {
+    fn ok(&self, what: &str, detail: &str) {
+        println!("  ok       {}: {}", what, detail);
+    }
+    fn problem(&mut self, what: &str, detail: &str) {
+        self.problems += 1;
+        println!("  PROBLEM  {}: {}", what, detail);
+    }
+    fn program(&mut self, program: &str, version_arg: Option<&str>, missing: &str) {
+        if !self.checked_programs.insert(program.to_string()) {
+            return;
+        }
+        match find_program(program) {
+            Some(path) => {
+                let version = version_arg
+                    .and_then(|arg| program_version(&path, arg))
+                    .unwrap_or_else(|| path.display().to_string());
+                self.ok(program, &version)
+            }
+            None => self.problem(program, &format!("not found in PATH. {}", missing)),
+        }
+    }
+    fn adapter_program(
+        &mut self,
+        adapter: &str,
+        program: &str,
+        version_arg: Option<&str>,
+        install: &str,
+    ) {
+        let mut missing = format!(
+            "Install {} for the adapter {}, or disable it with --rga-adapters=-{}",
+            install, adapter, adapter
+        );
+        if let Some(fallback) = fallback::active_fallback(adapter, program) {
+            missing.push_str(&format!(". Without it, {}", fallback.description));
+        }
+        self.program(program, version_arg, &missing)
+    }
+}
+
+/// print the result of the checks. Returns the number of problems found
+pub fn doctor(config: &RgaConfig) -> anyhow::Result<usize> {
+    let mut report = Report {
+        problems: 0,
+        checked_programs: HashSet::new(),
+    };
+
+    println!("programs:");
+    report.program(
+        "rg",
+        Some("--version"),
+        "Install ripgrep, rga runs it to search",
+    );
+    if config.sandbox {
+        report.program(
+            "bwrap",
+            Some("--version"),
+            "Install bubblewrap for --rga-sandbox",
+        );
+    }
+    let enabled: HashSet<String> = enabled_adapters(config)?
+        .iter()
+        .map(|a| a.metadata().name.clone())
+        .collect();
+    let custom_adapters = config.custom_adapters.clone().unwrap_or_default();
+    for adapter in custom_adapters
+        .iter()
+        .chain(builtin_spawning_adapters.iter())
+    {
+        if !enabled.contains(&adapter.name) {
+            continue;
+        }
+        if let Some(library) = &adapter.library {
+            match Plugin::get(Path::new(library)) {
+                Ok(_) => report.ok(library, "loads"),
+                Err(e) => report.problem(library, &format!("{:#}", e)),
+            }
+            continue;
+        }
+        report.adapter_program(
+            &adapter.name,
+            &adapter.binary,
+            adapter.version_arg.as_deref(),
+            &adapter.binary,
+        );
+        for command in adapter.pipe_to.iter().flatten() {
+            report.adapter_program(&adapter.name, &command.binary, None, &command.binary);
+        }
+    }
+    for (adapter, program, version_arg, install) in PROGRAMS {
+        if enabled.contains(*adapter) {
+            report.adapter_program(adapter, program, *version_arg, install);
+        }
+    }
+
+    if !custom_adapters.is_empty() {
+        println!("custom adapters:");
+        let (enabled, disabled) = get_all_adapters(None);
+        let builtin_names: HashSet<String> = enabled
+            .iter()
+            .chain(disabled.iter())
+            .map(|a| a.metadata().name.clone())
+            .collect();
+        let mut seen = HashSet::new();
+        for adapter in &custom_adapters {
+            let mut problems = adapter.problems();
+            if builtin_names.contains(&adapter.name) {
+                problems.push("has the same name as a builtin adapter".to_string());
+            }
+            if !seen.insert(&adapter.name) {
+                problems.push("is defined more than once".to_string());
+            }
+            if problems.is_empty() {
+                report.ok(&adapter.name, "valid");
+            }
+            for problem in problems {
+                report.problem(&adapter.name, &problem);
+            }
+        }
+    }
+
+    println!("cache:");
+    match open_cache(&config.cache) {
+        Ok(Some(_)) => report.ok(&config.cache.path.0, "opens"),
+        Ok(None) => report.ok(&config.cache.path.0, "disabled or does not exist yet"),
+        Err(e) => report.problem(
+            &config.cache.path.0,
+            &format!(
+                "{:#}. Delete it with `rga cache clear` or run without it with --rga-no-cache",
+                e
+            ),
+        ),
+    }
+    Ok(report.problems)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn find_programs() {
+        assert!(find_program("sh").is_some());
+        assert_eq!(find_program("/bin/sh"), Some(PathBuf::from("/bin/sh")));
+        assert_eq!(find_program("rga-no-such-program"), None);
+        assert_eq!(find_program("/no/such/program"), None);
+    }
+
+    #[test]
+    fn custom_adapter_problems() {
+        let adapter = CustomAdapterConfig {
+            name: "My Tool".to_string(),
+            binary: "mytool".to_string(),
+            args: vec!["{file}".to_string(), "{nope}".to_string()],
+            magic: Some(vec!["7f 4".to_string()]),
+            ..Default::default()
+        };
+        let problems = adapter.problems();
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].contains("must only include"));
+        assert!(problems[1].contains("needs_file"));
+        assert!(problems[2].contains("invalid magic"));
+        let valid = CustomAdapterConfig {
+            name: "my_tool".to_string(),
+            binary: "mytool".to_string(),
+            args: vec!["--from={ext}".to_string()],
+            extensions: vec!["my".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(valid.problems(), Vec::<String>::new());
+    }
+}
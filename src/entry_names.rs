@@ -0,0 +1,141 @@
+//! Names of files in archives that are not valid UTF-8, e.g. zip files created on Windows with names in the
+//! OEM code page. Names are decoded as UTF-8, then with the encodings of --rga-entry-name-encoding, then with the
+//! legacy encoding of the format (CP437 for zip files). Bytes that can't be decoded are escaped as \xNN,
+//! so every entry gets a distinct, readable name for its prefix and for choosing its adapter.
+
+use anyhow::*;
+use encoding_rs::Encoding;
+use std::path::Path;
+
+/// CP437 (the original IBM PC code page, the legacy encoding of zip file names), bytes 0x80 to 0xFF
+static CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameEncoding {
+    Cp437,
+    /// guessed from the name with chardetng
+    Auto,
+    Other(&'static Encoding),
+}
+
+/// "cp437", "auto" or an encoding label like "shift_jis" or "windows-1252"
+pub fn parse_encoding(label: &str) -> Result<NameEncoding> {
+    match label.trim().to_lowercase().as_str() {
+        "cp437" | "ibm437" | "437" => Ok(NameEncoding::Cp437),
+        "auto" => Ok(NameEncoding::Auto),
+        other => Encoding::for_label(other.as_bytes())
+            .map(NameEncoding::Other)
+            .ok_or_else(|| format_err!("unknown entry name encoding {:?}", label)),
+    }
+}
+
+fn decode_cp437(raw: &[u8]) -> String {
+    raw.iter()
+        .map(|&b| {
+            if b < 0x80 {
+                b as char
+            } else {
+                CP437_HIGH.chars().nth((b - 0x80) as usize).unwrap_or('?')
+            }
+        })
+        .collect()
+}
+
+/// None if the name is not valid in the encoding
+fn decode_with(raw: &[u8], encoding: NameEncoding) -> Option<String> {
+    let encoding = match encoding {
+        NameEncoding::Cp437 => return Some(decode_cp437(raw)),
+        NameEncoding::Auto => {
+            let mut detector = chardetng::EncodingDetector::new();
+            detector.feed(raw, true);
+            detector.guess(None, true)
+        }
+        NameEncoding::Other(encoding) => encoding,
+    };
+    encoding
+        .decode_without_bom_handling_and_without_replacement(raw)
+        .map(|name| name.into_owned())
+}
+
+/// valid UTF-8 as it is, other bytes as \xNN
+pub fn escape_invalid(raw: &[u8]) -> String {
+    let mut out = String::new();
+    let mut rest = raw;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                out.push_str(valid);
+                return out;
+            }
+            Err(e) => {
+                let (valid, invalid) = rest.split_at(e.valid_up_to());
+                out.push_str(std::str::from_utf8(valid).expect("checked"));
+                let invalid_len = e.error_len().unwrap_or(invalid.len());
+                for b in &invalid[..invalid_len] {
+                    out.push_str(&format!("\\x{:02X}", b));
+                }
+                rest = &invalid[invalid_len..];
+            }
+        }
+    }
+}
+
+/// the name of an entry in an archive. `legacy` is the encoding the format uses for names that are not UTF-8
+pub fn decode_entry_name(raw: &[u8], encodings: &[String], legacy: Option<NameEncoding>) -> String {
+    if let Ok(name) = std::str::from_utf8(raw) {
+        return name.to_string();
+    }
+    encodings
+        .iter()
+        // validated when parsing the config
+        .filter_map(|label| parse_encoding(label).ok())
+        .chain(legacy)
+        .find_map(|encoding| decode_with(raw, encoding))
+        .unwrap_or_else(|| escape_invalid(raw))
+}
+
+/// the name of a file extracted from an archive to a directory, which can be any bytes on unix
+pub fn decode_path(path: &Path, encodings: &[String]) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        decode_entry_name(path.as_os_str().as_bytes(), encodings, None)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = encodings;
+        path.to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names() -> Result<()> {
+        assert_eq!(CP437_HIGH.chars().count(), 128);
+        let none: &[String] = &[];
+        assert_eq!(
+            decode_entry_name("übung.txt".as_bytes(), none, None),
+            "übung.txt"
+        );
+        // "übung.txt" written by an old zip tool
+        assert_eq!(
+            decode_entry_name(b"\x81bung.txt", none, Some(NameEncoding::Cp437)),
+            "übung.txt"
+        );
+        // "資料.txt" in shift_jis
+        let sjis = b"\x8e\x91\x97\xbf.txt";
+        assert_eq!(
+            decode_entry_name(sjis, &["shift_jis".to_string()], Some(NameEncoding::Cp437)),
+            "資料.txt"
+        );
+        assert_eq!(decode_entry_name(b"a\xffb.pdf", none, None), "a\\xFFb.pdf");
+        assert_eq!(escape_invalid(b"\xe2\x82"), "\\xE2\\x82");
+
+        assert_eq!(parse_encoding("CP437")?, NameEncoding::Cp437);
+        assert!(parse_encoding("klingon").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+//! rga exits like rg: 0 if something matched, 1 if nothing matched, 2 if there was an error
+//! (also when something matched, except with --quiet). Files that could not be preprocessed are errors,
+//! unless --rga-ignore-adapter-errors is given.
+
+use std::ffi::OsStr;
+use std::process::ExitStatus;
+
+/// the results of the rg processes of one search
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct SearchStatus {
+    pub matched: bool,
+    pub errored: bool,
+}
+
+impl SearchStatus {
+    /// add the exit code of rg (or of another rga)
+    pub fn add_code(&mut self, code: i32) {
+        match code {
+            0 => self.matched = true,
+            1 => {}
+            _ => self.errored = true,
+        }
+    }
+
+    pub fn add(&mut self, status: ExitStatus) {
+        // killed by a signal
+        self.add_code(status.code().unwrap_or(2));
+    }
+
+    pub fn code(&self, quiet: bool) -> i32 {
+        if self.errored && !(quiet && self.matched) {
+            2
+        } else if self.matched {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+/// whether rg is given -q / --quiet
+pub fn is_quiet(args: &[impl AsRef<OsStr>]) -> bool {
+    args.iter().any(|a| {
+        let a = a.as_ref().to_string_lossy();
+        a == "--quiet"
+            || (a.starts_with('-')
+                && !a.starts_with("--")
+                && a[1..].chars().all(|c| c.is_ascii_alphabetic())
+                && a.contains('q'))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        let status = |codes: &[i32]| {
+            let mut status = SearchStatus::default();
+            for &code in codes {
+                status.add_code(code);
+            }
+            status
+        };
+        assert_eq!(status(&[]).code(false), 1);
+        assert_eq!(status(&[1, 0]).code(false), 0);
+        assert_eq!(status(&[1, 1]).code(false), 1);
+        assert_eq!(status(&[0, 2]).code(false), 2);
+        assert_eq!(status(&[0, 2]).code(true), 0);
+        assert_eq!(status(&[1, 2]).code(true), 2);
+
+        assert!(is_quiet(&["-q", "pattern"]));
+        assert!(is_quiet(&["-iq", "pattern"]));
+        assert!(is_quiet(&["--quiet"]));
+        assert!(!is_quiet(&["--max-count=1", "quiet"]));
+    }
+}
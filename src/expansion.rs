@@ -0,0 +1,214 @@
+//! Protection against decompression bombs (zip bombs, nested gz layers): the data extracted from a file
+//! (decompressed streams and the files in archives, at every level) is counted. When it's more than
+//! --rga-max-expanded-size, or more than --rga-max-expansion-ratio times the size of the file, the rest of the
+//! file is skipped and "[rga: truncated at 10 GB (--rga-max-expanded-size), ...]" is printed instead (see truncation).
+
+use crate::adapters::ReadBox;
+use crate::print_bytes;
+use crate::truncation::Truncation;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// the ratio is only checked after this much was extracted, since the start of a file is read before it is decompressed
+const MIN_RATIO_CHECK: u64 = 1_000_000;
+
+#[derive(Debug)]
+struct Budget {
+    max_expanded: u64,
+    max_ratio: u64,
+    /// the bytes read from the file
+    read: AtomicU64,
+    expanded: AtomicU64,
+    exceeded: AtomicBool,
+}
+
+impl Budget {
+    fn add_expanded(&self, n: u64) -> bool {
+        let expanded = self.expanded.fetch_add(n, Ordering::Relaxed) + n;
+        let read = self.read.load(Ordering::Relaxed);
+        let exceeded = expanded > self.max_expanded
+            || (expanded > MIN_RATIO_CHECK && expanded > read.saturating_mul(self.max_ratio));
+        if exceeded {
+            self.exceeded.store(true, Ordering::Relaxed);
+        }
+        exceeded
+    }
+
+    /// which of the limits was reached
+    fn truncation(&self) -> Truncation {
+        if self.expanded.load(Ordering::Relaxed) > self.max_expanded {
+            Truncation::new(
+                print_bytes(self.max_expanded as f64),
+                "--rga-max-expanded-size",
+            )
+        } else {
+            Truncation::new(
+                format!("{} times the file size", self.max_ratio),
+                "--rga-max-expansion-ratio",
+            )
+        }
+    }
+}
+
+/// shared by everything extracted from one file, passed on in the config. Without a budget (e.g. for the files
+/// rga-preproc does not get from rg), nothing is limited
+#[derive(Debug, Default, Clone)]
+pub struct ExpansionBudget(Option<Arc<Budget>>);
+
+impl ExpansionBudget {
+    /// a budget for the file, with its size if it is known. Otherwise the bytes read from it are counted
+    pub fn new(max_expanded: u64, max_ratio: u64, file_size: Option<u64>) -> ExpansionBudget {
+        ExpansionBudget(Some(Arc::new(Budget {
+            max_expanded,
+            max_ratio,
+            read: AtomicU64::new(file_size.unwrap_or(0)),
+            expanded: AtomicU64::new(0),
+            exceeded: AtomicBool::new(false),
+        })))
+    }
+
+    pub fn exceeded(&self) -> bool {
+        self.0
+            .as_ref()
+            .map_or(false, |budget| budget.exceeded.load(Ordering::Relaxed))
+    }
+
+    /// count the bytes read from the file (if its size is not known)
+    pub fn count_input<'a>(&self, inp: ReadBox<'a>) -> ReadBox<'a> {
+        match &self.0 {
+            Some(budget) => Box::new(Counted {
+                inner: inp,
+                budget: budget.clone(),
+                expanded: false,
+            }),
+            None => inp,
+        }
+    }
+
+    /// count the bytes extracted from the file, ending the input when the budget is exceeded
+    pub fn limit<'a>(&self, inp: ReadBox<'a>) -> ReadBox<'a> {
+        match &self.0 {
+            Some(budget) => Box::new(Counted {
+                inner: inp,
+                budget: budget.clone(),
+                expanded: true,
+            }),
+            None => inp,
+        }
+    }
+
+    /// end the output of the file with a truncation marker if the budget was exceeded.
+    /// Errors of adapters that got incomplete input because of that are dropped
+    pub fn mark<'a>(&self, out: ReadBox<'a>, line_prefix: &str) -> ReadBox<'a> {
+        Box::new(Marked {
+            inner: Some(out),
+            budget: self.clone(),
+            line_prefix: line_prefix.to_string(),
+            marker: Vec::new(),
+            last: b'\n',
+        })
+    }
+}
+
+struct Counted<'a> {
+    inner: ReadBox<'a>,
+    budget: Arc<Budget>,
+    expanded: bool,
+}
+
+impl Read for Counted<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.expanded && self.budget.exceeded.load(Ordering::Relaxed) {
+            return Ok(0);
+        }
+        let n = self.inner.read(buf)?;
+        if !self.expanded {
+            self.budget.read.fetch_add(n as u64, Ordering::Relaxed);
+        } else if self.budget.add_expanded(n as u64) {
+            return Ok(0);
+        }
+        Ok(n)
+    }
+}
+
+struct Marked<'a> {
+    inner: Option<ReadBox<'a>>,
+    budget: ExpansionBudget,
+    line_prefix: String,
+    marker: Vec<u8>,
+    /// the last byte of the output, so the marker starts on a new line
+    last: u8,
+}
+
+impl Read for Marked<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(inner) = &mut self.inner {
+            match inner.read(buf) {
+                Ok(0) => {}
+                Ok(n) => {
+                    self.last = buf[n - 1];
+                    return Ok(n);
+                }
+                Err(e) if !self.budget.exceeded() => return Err(e),
+                Err(e) => log::debug!("after reaching the decompression limit: {}", e),
+            }
+            self.inner = None;
+            if let Some(budget) = self.budget.0.as_ref().filter(|_| self.budget.exceeded()) {
+                if self.last != b'\n' {
+                    self.marker.push(b'\n');
+                }
+                self.marker
+                    .extend_from_slice(budget.truncation().marker(&self.line_prefix).as_bytes());
+            }
+        }
+        let n = (&self.marker[..]).read(buf)?;
+        self.marker.drain(..n);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limits() -> anyhow::Result<()> {
+        let read_all = |budget: &ExpansionBudget, data: Vec<u8>| -> anyhow::Result<String> {
+            let mut out = String::new();
+            budget
+                .mark(
+                    budget.limit(Box::new(std::io::Cursor::new(data))),
+                    "a.zip: ",
+                )
+                .read_to_string(&mut out)?;
+            Ok(out)
+        };
+
+        let budget = ExpansionBudget::new(100, 1000, Some(10));
+        assert_eq!(read_all(&budget, b"hello".to_vec())?, "hello");
+        assert!(!budget.exceeded());
+
+        let budget = ExpansionBudget::new(100, 1000, Some(10));
+        let out = read_all(&budget, vec![b'a'; 1000])?;
+        assert!(out.len() < 200);
+        assert!(out.ends_with(
+            "a.zip: [rga: truncated at 100 B (--rga-max-expanded-size), rerun with --rga-no-limits]\n"
+        ));
+        assert!(budget.exceeded());
+
+        // the ratio, with the size counted while reading the file
+        let budget = ExpansionBudget::new(u64::MAX, 10, None);
+        let mut compressed = Vec::new();
+        budget
+            .count_input(Box::new(&[0u8; 1000][..]))
+            .read_to_end(&mut compressed)?;
+        let out = read_all(&budget, vec![b'a'; 2 * MIN_RATIO_CHECK as usize])?;
+        assert!(out.ends_with("[rga: truncated at 10 times the file size (--rga-max-expansion-ratio), rerun with --rga-no-limits]\n"));
+
+        // without a budget, nothing is limited
+        let out = read_all(&ExpansionBudget::default(), vec![b'a'; 1000])?;
+        assert_eq!(out.len(), 1000);
+        Ok(())
+    }
+}
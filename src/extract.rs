@@ -0,0 +1,186 @@
+//! Extracting the text of files with the adapters and the cache of rga, for programs embedding rga
+//! (e.g. note-taking apps, indexers or search GUIs) instead of running the rga binary:
+//!
+//! ```no_run
+//! use ripgrep_all::extract::Extractor;
+//!
+//! let extractor = Extractor::builder()
+//!     .adapters(&["+gron"])
+//!     .accurate(true)
+//!     .build()?;
+//! let text = extractor.extract_to_string("report.pdf")?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! The output is the same as what rga searches: e.g. pages of PDFs are prefixed with "Page N: ",
+//! files in archives with their path. The cache is shared with rga, unless another one is set.
+
+use crate::adapters::custom::CustomAdapterConfig;
+use crate::adapters::*;
+use crate::config::{parse_args, CachePath, RgaConfig};
+use crate::preproc::{choose_adapter, rga_preproc};
+use anyhow::*;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// sets up an Extractor. The defaults are the same as those of rga, without the config file of the user
+#[derive(Default)]
+pub struct ExtractorBuilder {
+    config: RgaConfig,
+}
+
+impl ExtractorBuilder {
+    /// start from the config of rga, including the config file and environment variables of the user
+    pub fn from_user_config() -> Result<ExtractorBuilder> {
+        Ok(ExtractorBuilder {
+            config: parse_args(&["rga"], false)?,
+        })
+    }
+
+    /// start from a config, e.g. parsed from the command line with config::parse_args
+    pub fn from_config(config: RgaConfig) -> ExtractorBuilder {
+        ExtractorBuilder { config }
+    }
+
+    /// which adapters to use, like --rga-adapters (e.g. ["+gron"] or ["-pandoc"])
+    pub fn adapters<T: AsRef<str>>(mut self, adapters: &[T]) -> Self {
+        self.config.adapters = adapters.iter().map(|a| a.as_ref().to_string()).collect();
+        self
+    }
+
+    /// adapters running programs, like custom_adapters in the config file
+    pub fn custom_adapters(mut self, adapters: Vec<CustomAdapterConfig>) -> Self {
+        self.config.custom_adapters = Some(adapters);
+        self
+    }
+
+    /// detect the mime types of files, like --rga-accurate
+    pub fn accurate(mut self, accurate: bool) -> Self {
+        self.config.accurate = accurate;
+        self
+    }
+
+    /// how deep archives in archives are extracted, like --rga-max-archive-recursion
+    pub fn max_archive_recursion(mut self, depth: i32) -> Self {
+        self.config.max_archive_recursion.0 = depth;
+        self
+    }
+
+    /// the directory of the cache, e.g. to not share it with rga
+    pub fn cache_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.config.cache.path = CachePath(path.as_ref().to_string_lossy().into_owned());
+        self
+    }
+
+    /// disable the cache, like --rga-no-cache
+    pub fn no_cache(mut self) -> Self {
+        self.config.cache.disabled = true;
+        self
+    }
+
+    /// checks the adapters
+    pub fn build(self) -> Result<Extractor> {
+        enabled_adapters(&self.config)?;
+        Ok(Extractor {
+            config: self.config,
+        })
+    }
+}
+
+/// extracts the text of files
+pub struct Extractor {
+    config: RgaConfig,
+}
+
+impl Extractor {
+    pub fn builder() -> ExtractorBuilder {
+        ExtractorBuilder::default()
+    }
+
+    pub fn config(&self) -> &RgaConfig {
+        &self.config
+    }
+
+    /// the names of the adapters used, in order of priority
+    pub fn adapter_names(&self) -> Result<Vec<String>> {
+        Ok(enabled_adapters(&self.config)?
+            .iter()
+            .map(|a| a.metadata().name.clone())
+            .collect())
+    }
+
+    /// the name of the adapter for the file, None if it is read as it is
+    pub fn adapter_for(&self, path: impl AsRef<Path>) -> Result<Option<String>> {
+        Ok(choose_adapter(&self.config, path.as_ref())?
+            .1
+            .map(|(adapter, _)| adapter.metadata().name.clone()))
+    }
+
+    /// the text of the file, from the cache if it was extracted before. Files without an adapter are read as they are
+    pub fn extract(&self, path: impl AsRef<Path>) -> Result<ReadBox<'static>> {
+        let path = path.as_ref();
+        if choose_adapter(&self.config, path)?.1.is_none() {
+            return Ok(Box::new(File::open(path)?));
+        }
+        rga_preproc(AdaptInfo {
+            inp: Box::new(File::open(path)?),
+            // same as rga-preproc, so the cache entries are shared
+            filepath_hint: std::env::current_dir()?.join(path),
+            is_real_file: true,
+            line_prefix: "".to_string(),
+            archive_recursion_depth: 0,
+            postprocess: true,
+            config: self.config.clone(),
+        })
+        .with_context(|| format!("extracting {}", path.display()))
+    }
+
+    /// the text of data that is not a file on disk (e.g. a download or an attachment).
+    /// The adapter is chosen by `name` (and the content), the output is not cached
+    pub fn extract_reader<'a>(
+        &self,
+        name: impl Into<PathBuf>,
+        inp: impl Read + 'a,
+    ) -> Result<ReadBox<'a>> {
+        rga_preproc(AdaptInfo {
+            inp: Box::new(inp),
+            filepath_hint: name.into(),
+            is_real_file: false,
+            line_prefix: "".to_string(),
+            archive_recursion_depth: 0,
+            postprocess: true,
+            config: self.config.clone(),
+        })
+    }
+
+    /// the text of the file as a string. Invalid UTF-8 is replaced
+    pub fn extract_to_string(&self, path: impl AsRef<Path>) -> Result<String> {
+        let mut text = Vec::new();
+        self.extract(path)?.read_to_end(&mut text)?;
+        Ok(String::from_utf8_lossy(&text).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn extract() -> Result<()> {
+        let extractor = Extractor::builder().no_cache().build()?;
+        let gz = test_data_dir().join("hello.gz");
+        assert_eq!(extractor.adapter_for(&gz)?.as_deref(), Some("decompress"));
+        assert_eq!(extractor.extract_to_string(&gz)?, "hello\n\n");
+
+        let mut text = String::new();
+        extractor
+            .extract_reader("dir/hello.gz", File::open(&gz)?)?
+            .read_to_string(&mut text)?;
+        assert_eq!(text, "hello\n\n");
+
+        assert!(Extractor::builder().adapters(&["nope"]).build().is_err());
+        Ok(())
+    }
+}
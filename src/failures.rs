@@ -0,0 +1,199 @@
+//! Files that could not be preprocessed. A failing file in an archive does not stop the archive: its output is
+//! replaced by an "[rga: error: ...]" line and the other files are searched. rga prints a summary of the failed
+//! files after the search (every rga-preproc process appends its failures to a file, like the cache statistics).
+
+use crate::adapters::{AdaptInfo, ReadBox};
+use crate::preproc::rga_preproc;
+use anyhow::{Context, Error, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// file that every rga-preproc process appends its failures to, set by rga
+pub const FAILURES_FILE_ENV: &str = "RGA_FAILURES_FILE";
+
+/// how many files are listed per reason in the summary
+const LISTED_FILES: usize = 5;
+
+/// failures in this process, so outputs containing error lines are not cached
+static FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Failure {
+    pub path: String,
+    /// the innermost error, which is the same for files that failed for the same reason
+    pub reason: String,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(path: &str, error: &Error) -> Failure {
+        Failure {
+            path: path.to_string(),
+            reason: error.root_cause().to_string(),
+            message: format!("{:#}", error),
+        }
+    }
+
+    /// log it, and append it to the failures file if rga set one
+    pub fn record(&self) {
+        FAILURES.fetch_add(1, Ordering::Relaxed);
+        warn!("{}: {}", self.path, self.message);
+        if let Some(path) = std::env::var_os(FAILURES_FILE_ENV) {
+            if let Err(e) = self.append_to(Path::new(&path)) {
+                warn!("could not write failure: {:#}", e);
+            }
+        }
+    }
+
+    fn append_to(&self, path: &Path) -> Result<()> {
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&line)?;
+        Ok(())
+    }
+
+    /// the line that replaces the (rest of the) output of the file
+    pub fn error_line(&self, line_prefix: &str) -> String {
+        format!(
+            "{}[rga: error: {}]\n",
+            line_prefix,
+            self.message.replace('\n', " ")
+        )
+    }
+}
+
+/// the number of failures in this process so far
+pub fn failure_count() -> usize {
+    FAILURES.load(Ordering::Relaxed)
+}
+
+pub fn read_failures(path: &Path) -> Result<Vec<Failure>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    BufReader::new(std::fs::File::open(path)?)
+        .lines()
+        .map(|line| serde_json::from_str(&line?).context("could not parse failure"))
+        .collect()
+}
+
+/// the failed files grouped by reason, the most common first
+pub fn print_summary(failures: &[Failure], oup: &mut dyn Write) -> Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    let mut by_reason: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for failure in failures {
+        by_reason
+            .entry(failure.reason.as_str())
+            .or_default()
+            .push(failure.path.as_str());
+    }
+    let mut by_reason: Vec<_> = by_reason.into_iter().collect();
+    by_reason.sort_by_key(|(_, paths)| std::cmp::Reverse(paths.len()));
+    let files = |n: usize| format!("{} file{}", n, if n == 1 { "" } else { "s" });
+    writeln!(
+        oup,
+        "rga: {} could not be preprocessed:",
+        files(failures.len())
+    )?;
+    for (reason, paths) in by_reason {
+        writeln!(oup, "  {}: {}", files(paths.len()), reason)?;
+        for path in paths.iter().take(LISTED_FILES) {
+            writeln!(oup, "      {}", path)?;
+        }
+        if paths.len() > LISTED_FILES {
+            writeln!(oup, "      ... and {} more", paths.len() - LISTED_FILES)?;
+        }
+    }
+    Ok(())
+}
+
+/// the output of a file in an archive, ending with an error line instead of failing if the adapter fails
+struct ErrorAsLine<'a> {
+    inner: Option<ReadBox<'a>>,
+    path: String,
+    line_prefix: String,
+    error_line: Cursor<Vec<u8>>,
+}
+
+impl Read for ErrorAsLine<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(inner) = &mut self.inner {
+            match inner.read(buf) {
+                Ok(n) => return Ok(n),
+                Err(e) => {
+                    let failure = Failure::new(&self.path, &Error::from(e));
+                    failure.record();
+                    self.error_line =
+                        Cursor::new(failure.error_line(&self.line_prefix).into_bytes());
+                    self.inner = None;
+                }
+            }
+        }
+        self.error_line.read(buf)
+    }
+}
+
+/// rga_preproc for a file in an archive: if it fails, the output is an error line so the other files are still searched
+pub fn preproc_or_error_line<'a>(ai: AdaptInfo<'a>) -> ReadBox<'a> {
+    let path = ai.filepath_hint.to_string_lossy().into_owned();
+    let line_prefix = ai.line_prefix.clone();
+    match rga_preproc(ai) {
+        Ok(out) => Box::new(ErrorAsLine {
+            inner: Some(out),
+            path,
+            line_prefix,
+            error_line: Cursor::new(vec![]),
+        }),
+        Err(e) => {
+            let failure = Failure::new(&path, &e);
+            failure.record();
+            Box::new(Cursor::new(failure.error_line(&line_prefix).into_bytes()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn summary() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("failures");
+        assert!(read_failures(&path)?.is_empty());
+        let broken = Error::msg("invalid xref table").context("poppler failed");
+        for file in &["a.pdf", "b.pdf"] {
+            Failure::new(file, &broken).append_to(&path)?;
+        }
+        Failure::new("c.docx", &Error::msg("pandoc not found")).append_to(&path)?;
+        let failures = read_failures(&path)?;
+        assert_eq!(
+            failures[0].error_line("docs.zip: "),
+            "docs.zip: [rga: error: poppler failed: invalid xref table]\n"
+        );
+
+        let mut out = Vec::new();
+        print_summary(&failures, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "rga: 3 files could not be preprocessed:
+  2 files: invalid xref table
+      a.pdf
+      b.pdf
+  1 file: pandoc not found
+      c.docx
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+//! rga-fzf: the preview of the selected file and the location to open it at
+
+use crate::adapters::postproc::split_page;
+use crate::adapters::*;
+use crate::config::RgaConfig;
+use crate::preproc::{choose_adapter, rga_preproc};
+use anyhow::*;
+use regex::Regex;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+const HIGHLIGHT: &str = "\x1b[1;31m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// the adapted text of a file, from the cache if rga already searched it.
+/// Files without an adapter are searched by rg as they are
+pub fn adapted_text(path: &Path, config: &RgaConfig) -> Result<String> {
+    if choose_adapter(config, path)?.1.is_none() {
+        return Ok(String::from_utf8_lossy(&std::fs::read(path)?).into_owned());
+    }
+    let mut oup = rga_preproc(AdaptInfo {
+        inp: Box::new(File::open(path)?),
+        // same as rga-preproc, so the cache entries are shared
+        filepath_hint: std::env::current_dir()?.join(path),
+        is_real_file: true,
+        line_prefix: "".to_string(),
+        archive_recursion_depth: 0,
+        postprocess: true,
+        config: config.clone(),
+    })?;
+    let mut text = Vec::new();
+    oup.read_to_end(&mut text)?;
+    Ok(String::from_utf8_lossy(&text).into_owned())
+}
+
+/// the regex for the query typed into fzf, which is passed to rg as the pattern.
+/// While typing, the query may not be a valid regex yet, then it's matched literally
+pub fn query_regex(query: &str) -> Regex {
+    Regex::new(query).unwrap_or_else(|_| Regex::new(&regex::escape(query)).expect("escaped"))
+}
+
+/// where the first match is in the adapted text
+#[derive(Debug, PartialEq)]
+pub struct MatchLocation {
+    /// 0-based line in the adapted text
+    pub line: usize,
+    /// page of paginated documents (e.g. PDFs)
+    pub page: Option<u32>,
+}
+
+pub fn first_match(text: &str, query: &Regex) -> Option<MatchLocation> {
+    if query.as_str().is_empty() {
+        return None;
+    }
+    text.lines()
+        .enumerate()
+        .find(|(_, line)| query.is_match(line))
+        .map(|(i, line)| MatchLocation {
+            line: i,
+            page: split_page(line).map(|(page, _)| page),
+        })
+}
+
+/// write the text starting so that the first match is in the middle of a preview of `height` lines,
+/// with the matches highlighted
+pub fn write_preview(text: &str, query: &Regex, height: usize, oup: &mut dyn Write) -> Result<()> {
+    let start = first_match(text, query).map_or(0, |m| m.line.saturating_sub(height / 2));
+    if start > 0 {
+        writeln!(oup, "{}[from line {}]{}", DIM, start + 1, RESET)?;
+    }
+    for line in text.lines().skip(start) {
+        if query.as_str().is_empty() {
+            writeln!(oup, "{}", line)?;
+            continue;
+        }
+        let highlighted = query.replace_all(line, |caps: &regex::Captures| {
+            format!("{}{}{}", HIGHLIGHT, &caps[0], RESET)
+        });
+        writeln!(oup, "{}", highlighted)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEXT: &str =
+        "Page 1: intro\nPage 1: \nPage 2: more\nPage 2: the answer is 42\nPage 3: end\n";
+
+    #[test]
+    fn locate() {
+        let m = first_match(TEXT, &query_regex("answer"));
+        assert_eq!(
+            m,
+            Some(MatchLocation {
+                line: 3,
+                page: Some(2)
+            })
+        );
+        assert_eq!(first_match(TEXT, &query_regex("missing")), None);
+        // not a valid regex yet
+        assert_eq!(first_match(TEXT, &query_regex("42 (")), None);
+        assert_eq!(first_match("a (b\n", &query_regex("(b")).unwrap().line, 0);
+    }
+
+    #[test]
+    fn preview() -> Result<()> {
+        let mut oup = Vec::new();
+        write_preview(TEXT, &query_regex("answer"), 4, &mut oup)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "\x1b[2m[from line 2]\x1b[0m\nPage 1: \nPage 2: more\nPage 2: the \x1b[1;31manswer\x1b[0m is 42\nPage 3: end\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,344 @@
+//! `rga serve <dir>`: an HTTP server answering with JSON, as the backend of document search web interfaces.
+//!
+//! - `GET /text?path=docs/report.pdf`: `{"path": ..., "adapter": "poppler", "text": ...}`, the text rga searches in the file
+//! - `GET /search?q=regex&path=docs`: `{"matches": [{"path": ..., "subpath": ..., "page": ..., "text": ..., "submatches": [...]}], "errors": ...}`,
+//!   searched like --rga-batch. path is optional, subpath (the path within an archive) and page only given if known
+//! - `GET /subfiles?path=docs/archive.zip`: `{"subfiles": [...]}`, the paths of the files in an archive (see --rga-list-subfiles)
+//!
+//! All paths are relative to the directory, files outside of it can't be read (also not through symlinks). Parameters
+//! starting with "-" are refused, so they can't be mistaken for options of rg. Requests from web pages of other sites
+//! are refused (by their Origin and Sec-Fetch-Site headers), and when listening on a loopback address, requests for
+//! other host names (DNS rebinding). Errors are returned as `{"error": ...}`.
+
+use crate::config::RgaConfig;
+use crate::daemon::search;
+use crate::extract::{Extractor, ExtractorBuilder};
+use crate::list::list_subfiles;
+use crate::rg_args::RgArgs;
+use anyhow::*;
+use log::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// an error response
+struct HttpError {
+    status: u16,
+    message: String,
+}
+
+impl From<Error> for HttpError {
+    fn from(e: Error) -> Self {
+        let not_found = e
+            .chain()
+            .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+            .any(|io| io.kind() == std::io::ErrorKind::NotFound);
+        let status = if not_found { 404 } else { 500 };
+        HttpError {
+            status,
+            message: format!("{:#}", e),
+        }
+    }
+}
+
+fn bad_request(message: &str) -> HttpError {
+    HttpError {
+        status: 400,
+        message: message.to_string(),
+    }
+}
+
+fn forbidden(message: &str) -> HttpError {
+    HttpError {
+        status: 403,
+        message: message.to_string(),
+    }
+}
+
+/// the path of the file in the directory, rejecting absolute paths, ".." and symlinks to files outside of it
+fn resolve(dir: &Path, path: &str) -> Result<PathBuf, HttpError> {
+    let file = dir.join(path);
+    if !Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(bad_request(
+            "path must be relative and within the served directory",
+        ));
+    }
+    let root = dir.canonicalize().map_err(Error::from)?;
+    if !file.canonicalize().map_err(Error::from)?.starts_with(&root) {
+        return Err(forbidden("path is not within the served directory"));
+    }
+    Ok(file)
+}
+
+/// whether the host name (without the port) is one of the loopback interface
+fn is_loopback(host: &str) -> bool {
+    let name = match host.rsplit_once(':') {
+        // [::1]:8080 or 127.0.0.1:8080, but not ::1
+        Some((name, port)) if !name.ends_with(':') && port.chars().all(|c| c.is_ascii_digit()) => {
+            name
+        }
+        _ => host,
+    };
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    name == "localhost"
+        || name
+            .parse::<std::net::IpAddr>()
+            .map_or(false, |ip| ip.is_loopback())
+}
+
+/// refuses the requests of web pages of other sites (which browsers send with the cookies and the network access of
+/// the user), and requests for other host names to a server on the loopback interface (DNS rebinding)
+fn check_request(
+    listen: &str,
+    host: Option<&str>,
+    origin: Option<&str>,
+    fetch_site: Option<&str>,
+) -> Result<(), HttpError> {
+    if let Some(site) = fetch_site {
+        if site != "same-origin" && site != "none" {
+            return Err(forbidden("requests from other sites are not allowed"));
+        }
+    }
+    let host = host.ok_or_else(|| bad_request("missing Host header"))?;
+    if is_loopback(listen) && !is_loopback(host) {
+        return Err(forbidden("unknown host"));
+    }
+    if let Some(origin) = origin {
+        if origin != format!("http://{}", host) {
+            return Err(forbidden("requests from other sites are not allowed"));
+        }
+    }
+    Ok(())
+}
+
+/// the path relative to the directory, for the paths in the responses
+fn relative(dir: &Path, path: &str) -> String {
+    Path::new(path)
+        .strip_prefix(dir)
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+struct Handler {
+    dir: PathBuf,
+    extractor: Extractor,
+}
+
+impl Handler {
+    fn text(&self, path: &str) -> Result<Value, HttpError> {
+        let file = resolve(&self.dir, path)?;
+        Ok(json!({
+            "path": path,
+            "adapter": self.extractor.adapter_for(&file)?,
+            "text": self.extractor.extract_to_string(&file)?,
+        }))
+    }
+
+    fn search(&self, query: &str, path: Option<&str>) -> Result<Value, HttpError> {
+        let mut paths = Vec::new();
+        if let Some(path) = path {
+            resolve(&self.dir, path)?;
+            paths.push(path.into());
+        }
+        // the pattern and the path are given after --, so they are not mistaken for options
+        let args = RgArgs::from_parts(vec!["--json".into()], Some(query.into()), paths)?;
+        let result = search(self.extractor.config(), &args, &self.dir)?;
+        let mut matches = Vec::new();
+        for line in result.output.lines() {
+            let message: Value = serde_json::from_str(line).map_err(Error::from)?;
+            if message["type"] != "match" {
+                continue;
+            }
+            let data = &message["data"];
+            let path = data["path"]["text"].as_str().unwrap_or_default();
+            let mut m = json!({
+                "path": relative(&self.dir, path),
+                "text": data["lines"]["text"],
+                "submatches": data["submatches"],
+            });
+            if let Some(subpath) = data["subpath"]["text"].as_str() {
+                m["subpath"] = json!(subpath);
+            }
+            if !data["page"].is_null() {
+                m["page"] = data["page"].clone();
+            }
+            matches.push(m);
+        }
+        Ok(json!({ "matches": matches, "errors": result.errors }))
+    }
+
+    fn subfiles(&self, path: &str) -> Result<Value, HttpError> {
+        let file = resolve(&self.dir, path)?;
+        let mut subfiles = Vec::new();
+        list_subfiles(&file, self.extractor.config(), &mut |subfile| {
+            subfiles.push(relative(&self.dir, subfile));
+            Ok(())
+        })?;
+        Ok(json!({ "subfiles": subfiles }))
+    }
+
+    fn handle(&self, url: &str) -> Result<Value, HttpError> {
+        let (route, query) = url.split_once('?').unwrap_or((url, ""));
+        let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+        if let Some((name, _)) = params.iter().find(|(_, value)| value.starts_with('-')) {
+            return Err(bad_request(&format!("{} must not start with -", name)));
+        }
+        let param = |name: &str| {
+            params
+                .get(name)
+                .map(String::as_str)
+                .ok_or_else(|| bad_request(&format!("missing parameter {}", name)))
+        };
+        match route {
+            "/text" => self.text(param("path")?),
+            "/search" => self.search(param("q")?, params.get("path").map(String::as_str)),
+            "/subfiles" => self.subfiles(param("path")?),
+            _ => Err(HttpError {
+                status: 404,
+                message: format!("unknown endpoint {}", route),
+            }),
+        }
+    }
+}
+
+fn respond(
+    request: tiny_http::Request,
+    dir: &Path,
+    listen: &str,
+    config: &RgaConfig,
+) -> Result<()> {
+    let header = |name: &str| {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.as_str())
+    };
+    let checked = check_request(
+        listen,
+        header("Host"),
+        header("Origin"),
+        header("Sec-Fetch-Site"),
+    );
+    let res = if request.method() != &tiny_http::Method::Get {
+        Err(HttpError {
+            status: 405,
+            message: "only GET requests are supported".to_string(),
+        })
+    } else if let Err(e) = checked {
+        Err(e)
+    } else {
+        ExtractorBuilder::from_config(config.clone())
+            .build()
+            .map_err(HttpError::from)
+            .and_then(|extractor| {
+                Handler {
+                    dir: dir.to_owned(),
+                    extractor,
+                }
+                .handle(request.url())
+            })
+    };
+    let (status, body) = match res {
+        Ok(body) => (200, body),
+        Err(e) => (e.status, json!({ "error": e.message })),
+    };
+    debug!("{} {} -> {}", request.method(), request.url(), status);
+    let header = tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .expect("valid header");
+    request.respond(
+        tiny_http::Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(header),
+    )?;
+    Ok(())
+}
+
+/// `rga serve`: answer requests for the files in dir until killed. Every request is handled on its own thread
+pub fn serve(dir: &Path, listen: &str, config: &RgaConfig) -> Result<()> {
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("could not serve {}", dir.display()))?;
+    let server = tiny_http::Server::http(listen)
+        .map_err(|e| format_err!("could not listen on {}: {}", listen, e))?;
+    eprintln!("serving {} on http://{}/", dir.display(), listen);
+    for request in server.incoming_requests() {
+        let dir = dir.clone();
+        let listen = listen.to_string();
+        let config = config.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = respond(request, &dir, &listen, &config) {
+                debug!("could not respond: {:#}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn routes() {
+        let mut config = RgaConfig::default();
+        config.cache.disabled = true;
+        let handler = Handler {
+            dir: test_data_dir(),
+            extractor: ExtractorBuilder::from_config(config).build().unwrap(),
+        };
+        let text = handler.handle("/text?path=hello.gz").ok().unwrap();
+        assert_eq!(text["adapter"], "decompress");
+        assert_eq!(text["text"], "hello\n\n");
+
+        let status = |url: &str| handler.handle(url).err().map(|e| e.status);
+        assert_eq!(status("/text"), Some(400));
+        assert_eq!(status("/text?path=..%2Fsecret"), Some(400));
+        assert_eq!(status("/text?path=%2Fetc%2Fpasswd"), Some(400));
+        assert_eq!(status("/text?path=nope.gz"), Some(404));
+        assert_eq!(status("/nope"), Some(404));
+        assert_eq!(status("/search?q=hello&path=--pre%3Dsh"), Some(400));
+        assert_eq!(status("/search?q=--pre%3Dsh"), Some(400));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlink_outside() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::os::unix::fs::symlink(test_data_dir(), dir.path().join("link"))?;
+        let root = dir.path().canonicalize()?;
+        assert_eq!(
+            resolve(&root, "link/hello.gz").err().map(|e| e.status),
+            Some(403)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cross_site() {
+        let listen = "127.0.0.1:8080";
+        let check = |host, origin, site| check_request(listen, host, origin, site).is_ok();
+        assert!(check(Some("127.0.0.1:8080"), None, None));
+        assert!(check(
+            Some("localhost:8080"),
+            Some("http://localhost:8080"),
+            Some("same-origin")
+        ));
+        assert!(!check(
+            Some("127.0.0.1:8080"),
+            Some("https://evil.example"),
+            None
+        ));
+        assert!(!check(Some("127.0.0.1:8080"), None, Some("cross-site")));
+        assert!(!check(Some("evil.example:8080"), None, None));
+        assert!(!check(None, None, None));
+        assert!(check_request("0.0.0.0:8080", Some("docs.local:8080"), None, None).is_ok());
+        assert!(is_loopback("[::1]:8080") && is_loopback("::1") && !is_loopback("10.0.0.1:80"));
+    }
+}
@@ -0,0 +1,244 @@
+//! `rga index build` and `rga index search`: a full-text index (tantivy) of the text the adapters extract,
+//! for ranked search in large collections of documents that are searched often. Searching without the index
+//! (plain rga) stays the default, the index has to be updated with `rga index build` when files change.
+//!
+//! The index is stored in the cache directory. Files are indexed with their absolute path,
+//! files that did not change since they were indexed are skipped.
+
+use crate::config::RgaConfig;
+use crate::extract::{Extractor, ExtractorBuilder};
+use anyhow::*;
+use log::*;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tantivy::collector::{DocSetCollector, TopDocs};
+use tantivy::query::{AllQuery, QueryParser};
+use tantivy::schema::{Field, Schema, INDEXED, STORED, STRING, TEXT};
+use tantivy::{doc, Index, SnippetGenerator, Term};
+
+/// memory used by tantivy for adding documents
+const WRITER_MEMORY: usize = 50_000_000;
+
+pub fn index_path(config: &RgaConfig) -> PathBuf {
+    Path::new(&config.cache.path.0).join("index")
+}
+
+struct Fields {
+    path: Field,
+    modified: Field,
+    body: Field,
+}
+
+fn open_index(dir: &Path) -> Result<(Index, Fields)> {
+    let mut builder = Schema::builder();
+    let fields = Fields {
+        path: builder.add_text_field("path", STRING | STORED),
+        modified: builder.add_u64_field("modified", INDEXED | STORED),
+        body: builder.add_text_field("body", TEXT | STORED),
+    };
+    std::fs::create_dir_all(dir)?;
+    let directory = tantivy::directory::MmapDirectory::open(dir)?;
+    let index = Index::open_or_create(directory, builder.build())
+        .with_context(|| format!("could not open index {}", dir.display()))?;
+    Ok((index, fields))
+}
+
+/// modification time in seconds, the files are indexed again when it changes
+fn modified_secs(path: &Path) -> Result<u64> {
+    Ok(std::fs::metadata(path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0))
+}
+
+/// the text of the file, None if it's binary without an adapter
+fn file_text(extractor: &Extractor, path: &Path) -> Result<Option<String>> {
+    let text = extractor.extract_to_string(path)?;
+    Ok(Some(text).filter(|t| !t.contains('\0')))
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct IndexStats {
+    pub added: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub failed: usize,
+}
+
+/// add the files below the roots to the index, update the changed ones and remove the ones that don't exist anymore.
+/// Respects .gitignore, .ignore and hidden files like rg
+pub fn build_index(index_dir: &Path, roots: &[PathBuf], config: &RgaConfig) -> Result<IndexStats> {
+    let roots = roots
+        .iter()
+        .map(|r| {
+            r.canonicalize()
+                .with_context(|| format!("could not index {}", r.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let (first, rest) = roots
+        .split_first()
+        .ok_or_else(|| format_err!("no paths to index"))?;
+    let extractor = ExtractorBuilder::from_config(config.clone()).build()?;
+    let (index, fields) = open_index(index_dir)?;
+
+    let searcher = index.reader()?.searcher();
+    let mut indexed = HashMap::new();
+    for address in searcher.search(&AllQuery, &DocSetCollector)? {
+        let doc = searcher.doc(address)?;
+        if let (Some(path), Some(modified)) = (
+            doc.get_first(fields.path).and_then(|v| v.text()),
+            doc.get_first(fields.modified).and_then(|v| v.u64_value()),
+        ) {
+            indexed.insert(PathBuf::from(path), modified);
+        }
+    }
+
+    let mut builder = ignore::WalkBuilder::new(first);
+    for root in rest {
+        builder.add(root);
+    }
+    let mut files = Vec::new();
+    let mut stats = IndexStats::default();
+    for entry in builder.build() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("{}", e);
+                continue;
+            }
+        };
+        if !entry.file_type().map_or(false, |t| t.is_file()) {
+            continue;
+        }
+        let path = entry.into_path();
+        let modified = modified_secs(&path)?;
+        if indexed.remove(&path) == Some(modified) {
+            stats.unchanged += 1;
+        } else {
+            files.push((path, modified));
+        }
+    }
+
+    let mut writer = index.writer(WRITER_MEMORY)?;
+    // what's left was deleted
+    for path in indexed.keys() {
+        if roots.iter().any(|root| path.starts_with(root)) {
+            writer.delete_term(Term::from_field_text(fields.path, &path.to_string_lossy()));
+            stats.removed += 1;
+        }
+    }
+    debug!("indexing {} files", files.len());
+    let (sender, receiver) = crossbeam_channel::bounded(rayon::current_num_threads());
+    let worker = std::thread::spawn(move || {
+        files
+            .into_par_iter()
+            .for_each_with(sender, |sender, (path, modified)| {
+                let text = file_text(&extractor, &path);
+                sender.send((path, modified, text)).ok();
+            })
+    });
+    for (path, modified, text) in receiver.iter() {
+        let path_text = path.to_string_lossy();
+        writer.delete_term(Term::from_field_text(fields.path, &path_text));
+        match text {
+            Ok(Some(text)) => {
+                writer.add_document(doc!(
+                    fields.path => path_text.as_ref(),
+                    fields.modified => modified,
+                    fields.body => text,
+                ));
+                stats.added += 1;
+            }
+            Ok(None) => debug!("{}: binary, not indexed", path.display()),
+            Err(e) => {
+                eprintln!("rga: {}: {:#}", path.display(), e);
+                stats.failed += 1;
+            }
+        }
+    }
+    worker
+        .join()
+        .map_err(|_| format_err!("index worker thread panicked"))?;
+    writer.commit()?;
+    Ok(stats)
+}
+
+#[derive(Debug)]
+pub struct Hit {
+    pub path: String,
+    pub score: f32,
+    /// the part of the text with the most matches
+    pub snippet: String,
+}
+
+/// the best matches for the query (see the tantivy query syntax: words, "phrases", AND, OR, -excluded)
+pub fn search_index(index_dir: &Path, query: &str, limit: usize) -> Result<Vec<Hit>> {
+    if !index_dir.exists() {
+        bail!(
+            "no index at {}, create it with rga index build",
+            index_dir.display()
+        );
+    }
+    let (index, fields) = open_index(index_dir)?;
+    let searcher = index.reader()?.searcher();
+    let query = QueryParser::for_index(&index, vec![fields.body]).parse_query(query)?;
+    let snippets = SnippetGenerator::create(&searcher, &*query, fields.body)?;
+    let mut hits = Vec::new();
+    for (score, address) in searcher.search(&query, &TopDocs::with_limit(limit))? {
+        let doc = searcher.doc(address)?;
+        let path = doc
+            .get_first(fields.path)
+            .and_then(|v| v.text())
+            .unwrap_or_default()
+            .to_string();
+        let snippet = snippets.snippet_from_doc(&doc);
+        hits.push(Hit {
+            path,
+            score,
+            snippet: snippet.fragments().replace('\n', " "),
+        });
+    }
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn index() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let docs = dir.path().join("docs");
+        std::fs::create_dir(&docs)?;
+        std::fs::copy(test_data_dir().join("hello.gz"), docs.join("hello.gz"))?;
+        std::fs::write(docs.join("other.txt"), "something else")?;
+        let mut config = RgaConfig::default();
+        config.cache.disabled = true;
+        let index_dir = dir.path().join("index");
+
+        let stats = build_index(&index_dir, &[docs.clone()], &config)?;
+        assert_eq!((stats.added, stats.unchanged), (2, 0));
+        let hits = search_index(&index_dir, "hello", 10)?;
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].path.ends_with("hello.gz"));
+        assert!(hits[0].snippet.contains("hello"));
+
+        std::fs::remove_file(docs.join("other.txt"))?;
+        let stats = build_index(&index_dir, &[docs], &config)?;
+        assert_eq!(
+            stats,
+            IndexStats {
+                added: 0,
+                unchanged: 1,
+                removed: 1,
+                failed: 0
+            }
+        );
+        assert!(search_index(&index_dir, "something", 10)?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,462 @@
+//! --rga-interactive: a terminal UI that searches while typing, with the matches grouped by file
+//! (and by file within archives) and a preview of the adapted text around the selected match.
+//!
+//! The searches are run by `rga --json` in the background, so they are the same as on the command line.
+
+use crate::config::RgaConfig;
+use crate::fzf::adapted_text;
+use crate::rg_json::{set_json_prefixes, split_prefix};
+use anyhow::*;
+use crossbeam_channel::Receiver;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, ClearType},
+};
+use serde_json::Value;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// stop reading the results of a search after this many matches
+const MAX_MATCHES: usize = 2000;
+/// wait this long after the last key press before searching, so not every keystroke starts rg
+const DEBOUNCE: Duration = Duration::from_millis(150);
+
+#[derive(Debug, PartialEq)]
+pub struct Match {
+    /// line in the adapted text of the file given to rg
+    pub line_number: u64,
+    pub page: Option<u64>,
+    pub text: String,
+}
+
+/// the matches in one file, or in one file within an archive
+#[derive(Debug, PartialEq)]
+pub struct MatchGroup {
+    pub path: String,
+    pub subpath: Option<String>,
+    pub matches: Vec<Match>,
+}
+
+impl MatchGroup {
+    /// the path of the file, within the archive for files in archives (as in --rga-list-subfiles)
+    pub fn location(&self) -> String {
+        match &self.subpath {
+            Some(subpath) => format!("{}/{}", self.path, subpath),
+            None => self.path.clone(),
+        }
+    }
+}
+
+/// add a message of `rga --json` to the groups. rg outputs the matches of a file together,
+/// and the matches within a file in order, so matches of the same subfile are consecutive
+pub fn add_message(groups: &mut Vec<MatchGroup>, message: &Value) {
+    if message["type"] != "match" {
+        return;
+    }
+    let data = &message["data"];
+    let path = match data["path"]["text"].as_str() {
+        Some(path) => path,
+        // not utf8
+        None => return,
+    };
+    let subpath = data["subpath"]["text"].as_str().map(String::from);
+    let m = Match {
+        line_number: data["line_number"].as_u64().unwrap_or(0),
+        page: data["page"].as_u64(),
+        text: data["lines"]["text"]
+            .as_str()
+            .unwrap_or_default()
+            .trim_end()
+            .to_string(),
+    };
+    match groups.last_mut() {
+        Some(group) if group.path == path && group.subpath == subpath => group.matches.push(m),
+        _ => groups.push(MatchGroup {
+            path: path.to_string(),
+            subpath,
+            matches: vec![m],
+        }),
+    }
+}
+
+/// a row of the result list: the header of a group or a match in it
+#[derive(Debug, PartialEq)]
+enum Row {
+    Group(usize),
+    Match(usize, usize),
+}
+
+fn rows(groups: &[MatchGroup]) -> Vec<Row> {
+    let mut rows = vec![];
+    for (g, group) in groups.iter().enumerate() {
+        rows.push(Row::Group(g));
+        rows.extend((0..group.matches.len()).map(|m| Row::Match(g, m)));
+    }
+    rows
+}
+
+/// the terminal can show tabs and control characters in other places than they are in the text
+fn printable(text: &str, width: usize) -> String {
+    text.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .take(width)
+        .collect()
+}
+
+/// a running `rga --json`
+struct Search {
+    child: Child,
+    messages: Receiver<Value>,
+}
+
+impl Search {
+    fn start(query: &str, args: &[OsString]) -> Result<Search> {
+        let mut child = Command::new(std::env::current_exe()?)
+            .arg("--json")
+            .arg("--line-number")
+            .args(args)
+            .arg(format!("--regexp={}", query))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            // would mess up the screen
+            .stderr(Stdio::null())
+            .spawn()
+            .context("could not start rga")?;
+        let stdout = child.stdout.take().expect("is piped");
+        let (sender, messages) = crossbeam_channel::unbounded();
+        std::thread::spawn(move || {
+            let mut matches = 0;
+            for line in BufReader::new(stdout).lines() {
+                let message: Value = match line.ok().and_then(|l| serde_json::from_str(&l).ok()) {
+                    Some(message) => message,
+                    None => break,
+                };
+                if message["type"] == "match" {
+                    matches += 1;
+                }
+                // the receiver is gone when the query changed
+                if sender.send(message).is_err() || matches >= MAX_MATCHES {
+                    break;
+                }
+            }
+        });
+        Ok(Search { child, messages })
+    }
+}
+
+impl Drop for Search {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// restores the terminal, also when returning early because of an error
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter(oup: &mut impl Write) -> Result<TerminalGuard> {
+        terminal::enable_raw_mode()?;
+        execute!(oup, terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        execute!(
+            std::io::stdout(),
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        )
+        .ok();
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+struct App<'a> {
+    config: &'a RgaConfig,
+    query: String,
+    groups: Vec<MatchGroup>,
+    /// index of the selected match, counting the matches of all groups
+    selected: usize,
+    search: Option<Search>,
+    /// the lines of the adapted text of the file in the preview
+    preview: Option<(String, Vec<String>)>,
+}
+
+impl<'a> App<'a> {
+    fn match_count(&self) -> usize {
+        self.groups.iter().map(|g| g.matches.len()).sum()
+    }
+
+    fn selected_match(&self) -> Option<(&MatchGroup, &Match)> {
+        let mut index = self.selected;
+        for group in &self.groups {
+            if index < group.matches.len() {
+                return Some((group, &group.matches[index]));
+            }
+            index -= group.matches.len();
+        }
+        None
+    }
+
+    /// the adapted text of the file with the selected match, from the cache since rg just searched it
+    fn preview_lines(&mut self, path: &str) -> &[String] {
+        if self.preview.as_ref().map_or(true, |(p, _)| p != path) {
+            let lines = match adapted_text(Path::new(path), self.config) {
+                Ok(text) => text.lines().map(String::from).collect(),
+                Err(e) => vec![format!("[rga: could not preview: {:#}]", e)],
+            };
+            self.preview = Some((path.to_string(), lines));
+        }
+        &self.preview.as_ref().expect("set above").1
+    }
+
+    fn draw(&mut self, oup: &mut impl Write) -> Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        queue!(oup, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+        queue!(oup, Print(printable(&format!("> {}", self.query), width)))?;
+        let status = format!(
+            "{} matches in {} files{}",
+            self.match_count(),
+            self.groups.len(),
+            if self.search.is_some() { "" } else { " (done)" }
+        );
+        queue!(
+            oup,
+            cursor::MoveTo(0, 1),
+            SetAttribute(Attribute::Dim),
+            Print(printable(&status, width)),
+            SetAttribute(Attribute::Reset)
+        )?;
+
+        // the results in the upper half, the preview in the lower half
+        let list_height = height.saturating_sub(2) / 2;
+        let rows = rows(&self.groups);
+        let selected_row = rows
+            .iter()
+            .position(|row| match row {
+                Row::Match(g, m) => {
+                    self.groups[..*g]
+                        .iter()
+                        .map(|g| g.matches.len())
+                        .sum::<usize>()
+                        + m
+                        == self.selected
+                }
+                Row::Group(_) => false,
+            })
+            .unwrap_or(0);
+        let scroll = (selected_row + 1).saturating_sub(list_height);
+        for (i, row) in rows.iter().skip(scroll).take(list_height).enumerate() {
+            queue!(oup, cursor::MoveTo(0, (i + 2) as u16))?;
+            match row {
+                Row::Group(g) => queue!(
+                    oup,
+                    SetAttribute(Attribute::Bold),
+                    Print(printable(&self.groups[*g].location(), width)),
+                    SetAttribute(Attribute::Reset)
+                )?,
+                Row::Match(g, m) => {
+                    let found = &self.groups[*g].matches[*m];
+                    let page = found.page.map(|p| format!("p.{} ", p)).unwrap_or_default();
+                    let text = format!("  {}{}: {}", page, found.line_number, found.text);
+                    if i + scroll == selected_row {
+                        queue!(oup, SetAttribute(Attribute::Reverse))?;
+                    }
+                    queue!(
+                        oup,
+                        Print(printable(&text, width)),
+                        SetAttribute(Attribute::Reset)
+                    )?;
+                }
+            }
+        }
+
+        let preview_top = list_height + 2;
+        let preview_height = height.saturating_sub(preview_top + 1);
+        queue!(
+            oup,
+            cursor::MoveTo(0, preview_top as u16),
+            SetAttribute(Attribute::Dim),
+            Print("─".repeat(width)),
+            SetAttribute(Attribute::Reset)
+        )?;
+        let selected = self
+            .selected_match()
+            .map(|(group, m)| (group.path.clone(), m.line_number as usize));
+        if let Some((path, line_number)) = selected {
+            let lines = self.preview_lines(&path);
+            let line = line_number.saturating_sub(1);
+            let start = line.saturating_sub(preview_height / 2);
+            for (i, text) in lines.iter().skip(start).take(preview_height).enumerate() {
+                let (_, _, prefix_len) = split_prefix(text);
+                queue!(oup, cursor::MoveTo(0, (preview_top + 1 + i) as u16))?;
+                if start + i == line {
+                    queue!(oup, SetAttribute(Attribute::Bold))?;
+                }
+                queue!(
+                    oup,
+                    Print(printable(&text[prefix_len..], width)),
+                    SetAttribute(Attribute::Reset)
+                )?;
+            }
+        }
+        oup.flush()?;
+        Ok(())
+    }
+}
+
+/// run the interactive search in the given paths (and with the given rg options).
+/// Returns the location of the match selected with enter
+pub fn interactive(config: &RgaConfig, args: &[OsString]) -> Result<Option<String>> {
+    // the previews use the same prefixes as the searches, so the adapted texts are in the cache
+    let mut config = config.clone();
+    set_json_prefixes(&mut config)?;
+    let mut app = App {
+        config: &config,
+        query: String::new(),
+        groups: vec![],
+        selected: 0,
+        search: None,
+        preview: None,
+    };
+    let stdout = std::io::stdout();
+    let mut oup = stdout.lock();
+    let _guard = TerminalGuard::enter(&mut oup)?;
+    let mut query_changed: Option<Instant> = None;
+    let mut dirty = true;
+    loop {
+        if let Some(search) = &app.search {
+            let mut done = false;
+            loop {
+                match search.messages.try_recv() {
+                    Ok(message) => {
+                        add_message(&mut app.groups, &message);
+                        dirty = true;
+                    }
+                    Err(crossbeam_channel::TryRecvError::Empty) => break,
+                    Err(crossbeam_channel::TryRecvError::Disconnected) => {
+                        done = true;
+                        break;
+                    }
+                }
+            }
+            if done {
+                app.search = None;
+                dirty = true;
+            }
+        }
+        if query_changed.map_or(false, |t| t.elapsed() >= DEBOUNCE) {
+            query_changed = None;
+            app.groups.clear();
+            app.selected = 0;
+            // stops the previous search
+            app.search = None;
+            if !app.query.is_empty() {
+                app.search = Some(Search::start(&app.query, args)?);
+            }
+            dirty = true;
+        }
+        if dirty {
+            app.draw(&mut oup)?;
+            dirty = false;
+        }
+        if !event::poll(Duration::from_millis(50))? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Esc => return Ok(None),
+                KeyCode::Char('c') if ctrl => return Ok(None),
+                KeyCode::Enter => {
+                    return Ok(app.selected_match().map(|(group, _)| group.location()))
+                }
+                KeyCode::Up => app.selected = app.selected.saturating_sub(1),
+                KeyCode::Down => {
+                    if app.selected + 1 < app.match_count() {
+                        app.selected += 1;
+                    }
+                }
+                KeyCode::Backspace => {
+                    app.query.pop();
+                    query_changed = Some(Instant::now());
+                }
+                KeyCode::Char(c) if !ctrl => {
+                    app.query.push(c);
+                    query_changed = Some(Instant::now());
+                }
+                _ => {}
+            }
+        }
+        dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn message(path: &str, subpath: Option<&str>, line_number: u64, text: &str) -> Value {
+        let mut message = json!({
+            "type": "match",
+            "data": {
+                "path": {"text": path},
+                "lines": {"text": text},
+                "line_number": line_number,
+            }
+        });
+        if let Some(subpath) = subpath {
+            message["data"]["subpath"] = json!({ "text": subpath });
+            message["data"]["page"] = json!(2);
+        }
+        message
+    }
+
+    #[test]
+    fn groups() {
+        let mut groups = vec![];
+        add_message(&mut groups, &json!({"type": "begin", "data": {}}));
+        add_message(&mut groups, &message("a.txt", None, 3, "first\n"));
+        add_message(&mut groups, &message("a.txt", None, 7, "second\n"));
+        add_message(&mut groups, &message("b.zip", Some("c.pdf"), 1, "third\n"));
+        add_message(&mut groups, &message("b.zip", Some("d.pdf"), 9, "fourth\n"));
+        assert_eq!(groups.len(), 3);
+        assert_eq!(
+            groups[0].matches,
+            vec![
+                Match {
+                    line_number: 3,
+                    page: None,
+                    text: "first".to_string()
+                },
+                Match {
+                    line_number: 7,
+                    page: None,
+                    text: "second".to_string()
+                }
+            ]
+        );
+        assert_eq!(groups[1].location(), "b.zip/c.pdf");
+        assert_eq!(groups[2].matches[0].page, Some(2));
+        assert_eq!(
+            rows(&groups)[..4],
+            [
+                Row::Group(0),
+                Row::Match(0, 0),
+                Row::Match(0, 1),
+                Row::Group(1)
+            ]
+        );
+    }
+}
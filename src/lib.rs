@@ -1,22 +1,54 @@
+//! The preprocessing of rga (ripgrep-all): adapters extracting the text of documents, archives, databases etc.,
+//! and the cache of their outputs. To extract text in other programs, use [extract::Extractor].
+
 #![warn(clippy::all)]
 #![feature(negative_impls)]
 
 pub mod adapted_iter;
 pub mod adapters;
+pub mod batch;
+pub mod bench;
+pub mod cache_stats;
 mod caching_writer;
+pub mod completions;
 pub mod config;
+pub mod daemon;
+pub mod detection;
+pub mod doctor;
+pub mod entry_names;
+pub mod exit_code;
+pub mod expansion;
+pub mod extract;
+pub mod failures;
+pub mod fzf;
+pub mod http;
+pub mod index;
+pub mod interactive;
+pub mod list;
 pub mod matching;
+pub mod paths;
 pub mod pipe;
 pub mod preproc;
 pub mod preproc_cache;
+pub mod pretty;
 pub mod recurse;
+pub mod remote;
+pub mod report;
+pub mod rg_args;
+pub mod rg_json;
+pub mod secrets;
+pub mod signals;
+pub mod spill;
+pub mod ssh;
 #[cfg(test)]
 pub mod test_utils;
+pub mod truncation;
+pub mod watch;
 use anyhow::Context;
 use anyhow::Result;
 pub use caching_writer::CachingReader;
 use directories_next::ProjectDirs;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 pub fn project_dirs() -> Result<ProjectDirs> {
     directories_next::ProjectDirs::from("", "", "ripgrep-all")
@@ -51,7 +83,11 @@ fn meh(float: f32, precision: usize) -> usize {
 }
 
 pub fn print_dur(start: Instant) -> String {
-    let mut dur = Instant::now().duration_since(start).as_secs_f32();
+    print_duration(Instant::now().duration_since(start))
+}
+
+pub fn print_duration(dur: Duration) -> String {
+    let mut dur = dur.as_secs_f32();
     let mut suffix = "";
     if dur < 0.1 {
         suffix = "m";
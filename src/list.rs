@@ -0,0 +1,283 @@
+//! --rga-list-subfiles: the paths of the files in archives (and other containers) without running the adapters
+//! on their contents.
+//!
+//! These paths also address a single file in an archive, e.g. `rga-preproc backup.zip/docs/report.pdf` or
+//! `rga pattern archive.tar.gz//inner/path` (// marks the end of the path of the archive). Only that file is
+//! extracted and adapted, not the rest of the archive.
+
+use crate::adapters::postproc::postproc_prefix;
+use crate::adapters::*;
+use crate::config::RgaConfig;
+use crate::matching::{adapter_matcher, FileMatcher, FileMeta};
+use crate::preproc::rga_preproc;
+use crate::spill::SpillBuffer;
+use anyhow::*;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+type Matcher<'m> = &'m dyn Fn(FileMeta) -> Option<(Rc<dyn FileAdapter>, FileMatcher)>;
+
+/// name of the notes (e.g. entry metadata) some archive adapters output in between the files
+const NOTE_NAME: &str = "rga-note.txt";
+
+/**
+ * Call `emit` with the virtual path of every file within the given file, recursively.
+ *
+ * The virtual path is the path of the file followed by the names of the entries, separated by "/"
+ * (e.g. `docs.zip/dir/inner.zip/report.pdf`). Only adapters that recurse (archives) are run.
+ */
+pub fn list_subfiles(
+    path: &Path,
+    config: &RgaConfig,
+    emit: &mut dyn FnMut(&str) -> Result<()>,
+) -> Result<()> {
+    let mut config = config.clone();
+    // the entry names are taken from the default line prefixes (see RgaConfig::entry_prefix)
+    config.json_records = true;
+    let adapters = enabled_adapters(&config)?;
+    let matcher = adapter_matcher(&adapters, false)?;
+    let ai = AdaptInfo {
+        inp: Box::new(File::open(path)?),
+        filepath_hint: std::env::current_dir()?.join(path),
+        is_real_file: true,
+        line_prefix: "".to_string(),
+        archive_recursion_depth: 0,
+        postprocess: false,
+        config,
+    };
+    list_entries(ai, &matcher, &path.to_string_lossy(), emit)
+}
+
+/// The archive (a file on disk) and the path of the file within it, for a path like `backup.zip/docs/report.pdf`
+/// or `backup.zip//docs/report.pdf`. None if the path is not within a file (e.g. it exists)
+pub fn split_subfile_path(path: &str) -> Option<(PathBuf, String)> {
+    if let Some((archive, inner)) = path.split_once("//") {
+        // not //server/share
+        if !archive.is_empty() && Path::new(archive).is_file() {
+            return Some((
+                PathBuf::from(archive),
+                inner.trim_start_matches('/').to_string(),
+            ));
+        }
+    }
+    if Path::new(path).exists() {
+        return None;
+    }
+    // the longest existing part of the path
+    let mut end = path.len();
+    while let Some(i) = path[..end].rfind('/') {
+        if i > 0 && Path::new(&path[..i]).is_file() {
+            return Some((PathBuf::from(&path[..i]), path[i + 1..].to_string()));
+        }
+        end = i;
+    }
+    None
+}
+
+/**
+ * The content of the file at the path `inner` within the given file (e.g. `docs/report.pdf` within `backup.zip`,
+ * as listed by list_subfiles). Only the archives on the way to it are extracted. The content is buffered, in a
+ * temporary file if it is larger than --rga-memory-limit
+ */
+pub fn open_subfile(path: &Path, inner: &str, config: &RgaConfig) -> Result<ReadBox<'static>> {
+    let mut config = config.clone();
+    config.json_records = true;
+    let adapters = enabled_adapters(&config)?;
+    let matcher = adapter_matcher(&adapters, false)?;
+    let mut buf = SpillBuffer::new(config.memory_limit.0);
+    let ai = AdaptInfo {
+        inp: Box::new(File::open(path)?),
+        filepath_hint: std::env::current_dir()?.join(path),
+        is_real_file: true,
+        line_prefix: "".to_string(),
+        archive_recursion_depth: 0,
+        postprocess: false,
+        config,
+    };
+    let found = find_entry(ai, &matcher, inner, &mut |mut entry| {
+        std::io::copy(&mut entry.inp, &mut buf)?;
+        Ok(())
+    })?;
+    if !found {
+        return Err(format_err!("{} does not contain {}", path.display(), inner));
+    }
+    Ok(buf.into_reader()?)
+}
+
+/// the adapted content of a file in an archive, with the prefix of its path like the files rg searches
+pub fn preprocess_subfile(virtual_path: &str, config: &RgaConfig) -> Result<ReadBox<'static>> {
+    let (archive, inner) = split_subfile_path(virtual_path)
+        .ok_or_else(|| format_err!("{} is not a file in an archive", virtual_path))?;
+    let oup = rga_preproc(AdaptInfo {
+        inp: open_subfile(&archive, &inner, config)?,
+        filepath_hint: PathBuf::from(virtual_path),
+        is_real_file: false,
+        line_prefix: "".to_string(),
+        archive_recursion_depth: 0,
+        postprocess: true,
+        config: config.clone(),
+    })
+    .with_context(|| format!("preprocessing {}", virtual_path))?;
+    Ok(Box::new(postproc_prefix(
+        &config.entry_prefix("", virtual_path),
+        oup,
+    )?))
+}
+
+/// the adapter that extracts the entries of the file, if it has entries
+fn archive_adapter(ai: &AdaptInfo, matcher: Matcher) -> Option<(Rc<dyn FileAdapter>, FileMatcher)> {
+    if ai.archive_recursion_depth >= ai.config.max_archive_recursion.0 {
+        return None;
+    }
+    let filename = ai.filepath_hint.file_name()?.to_string_lossy().into_owned();
+    matcher(FileMeta {
+        mimetype: None,
+        lossy_filename: filename,
+        lossy_path: ai.filepath_hint.to_string_lossy().into_owned(),
+        content: None,
+    })
+    .filter(|(adapter, _)| adapter.metadata().recurses)
+}
+
+/// the name of an entry of an archive with the line prefix `outer_prefix`. Empty if the entry is the content of the
+/// archive (e.g. the decompressed file of a .gz, which has the same prefix)
+fn entry_name(entry: &AdaptInfo, outer_prefix: &str) -> String {
+    entry
+        .line_prefix
+        .strip_prefix(outer_prefix)
+        .and_then(|name| name.strip_suffix(": "))
+        .unwrap_or("")
+        .to_string()
+}
+
+/// call `found` with the entry at the path `target` within the file, returns whether there is one
+fn find_entry(
+    ai: AdaptInfo,
+    matcher: Matcher,
+    target: &str,
+    found: &mut dyn FnMut(AdaptInfo) -> Result<()>,
+) -> Result<bool> {
+    let (adapter, detection_reason) = match archive_adapter(&ai, matcher) {
+        Some(adapter) => adapter,
+        None => return Ok(false),
+    };
+    let outer_prefix = ai.line_prefix.clone();
+    let mut entries = adapter.adapt(ai, &detection_reason)?;
+    while let Some(entry) = entries.next() {
+        if entry.filepath_hint.as_os_str() == NOTE_NAME {
+            continue;
+        }
+        let name = entry_name(&entry, &outer_prefix);
+        if name == target {
+            found(entry)?;
+            return Ok(true);
+        }
+        let rest = if name.is_empty() {
+            Some(target)
+        } else {
+            target
+                .strip_prefix(name.as_str())
+                .and_then(|rest| rest.strip_prefix('/'))
+        };
+        // entries of other archives are skipped without extracting them
+        if let Some(rest) = rest {
+            if find_entry(entry, matcher, rest, found)? {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+fn list_entries(
+    ai: AdaptInfo,
+    matcher: Matcher,
+    virtual_path: &str,
+    emit: &mut dyn FnMut(&str) -> Result<()>,
+) -> Result<()> {
+    let (adapter, detection_reason) = match archive_adapter(&ai, matcher) {
+        Some(adapter) => adapter,
+        None => return Ok(()),
+    };
+    let outer_prefix = ai.line_prefix.clone();
+    let mut entries = adapter.adapt(ai, &detection_reason)?;
+    let mut last_path = String::new();
+    while let Some(entry) = entries.next() {
+        if entry.filepath_hint.as_os_str() == NOTE_NAME {
+            continue;
+        }
+        let name = entry_name(&entry, &outer_prefix);
+        if name.is_empty() {
+            list_entries(entry, matcher, virtual_path, emit)?;
+            continue;
+        }
+        let entry_path = format!("{}/{}", virtual_path, name);
+        // archives with multiple outputs for an entry (e.g. several blobs of a row) have the same prefix for them
+        if entry_path != last_path {
+            emit(&entry_path)?;
+        }
+        list_entries(entry, matcher, &entry_path, emit)?;
+        last_path = entry_path;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::test_data_dir;
+    use std::io::Read;
+
+    #[test]
+    fn zip() -> Result<()> {
+        let path = test_data_dir().join("../test.zip");
+        let mut paths = vec![];
+        list_subfiles(&path, &RgaConfig::default(), &mut |p| {
+            paths.push(p.to_string());
+            Ok(())
+        })?;
+        let root = path.to_string_lossy();
+        let paths: Vec<&str> = paths
+            .iter()
+            .map(|p| p.strip_prefix(root.as_ref()).unwrap())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/test/inner.zip",
+                "/test/inner.zip/short.pdf",
+                "/test/inner.zip/wasteland.docx",
+                "/test/subdir/short.pdf",
+                "/test/subdir/wasteland.pdf"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn subfile() -> Result<()> {
+        let path = test_data_dir().join("../test.zip");
+        let root = path.to_string_lossy();
+        assert_eq!(split_subfile_path(&root), None);
+        let (archive, inner) =
+            split_subfile_path(&format!("{}/test/inner.zip/short.pdf", root)).unwrap();
+        assert_eq!(
+            (archive.as_path(), inner.as_str()),
+            (path.as_path(), "test/inner.zip/short.pdf")
+        );
+        let (archive, inner) =
+            split_subfile_path(&format!("{}//test/subdir/short.pdf", root)).unwrap();
+        assert_eq!(
+            (archive.as_path(), inner.as_str()),
+            (path.as_path(), "test/subdir/short.pdf")
+        );
+
+        let mut pdf = Vec::new();
+        open_subfile(&path, "test/inner.zip/short.pdf", &RgaConfig::default())?
+            .read_to_end(&mut pdf)?;
+        assert!(pdf.starts_with(b"%PDF"));
+        assert!(open_subfile(&path, "test/missing.pdf", &RgaConfig::default()).is_err());
+        Ok(())
+    }
+}
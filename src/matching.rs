@@ -2,16 +2,20 @@
  * Module for matching adapters to files based on file name or mime type
  */
 use crate::adapters::*;
+use crate::config::AdapterConditions;
 
 use anyhow::*;
+use log::*;
 
 use regex::{Regex, RegexSet};
 
+use std::collections::HashMap;
 use std::iter::Iterator;
+use std::path::{Path, PathBuf};
 
 use std::rc::Rc;
 
-// match only based on file path
+// match based on file path or the start of the file, without detecting the mime type
 #[derive(Clone, Debug)]
 pub enum FastFileMatcher {
     // MimeType(Regex),
@@ -20,7 +24,17 @@ pub enum FastFileMatcher {
      *
      */
     FileExtension(String),
-    // todo: maybe add others, e.g. regex on whole filename or even paths
+    // (not a block comment like above, since the example glob would end it)
+    /// glob matched against the whole path (within archives: the path within the archive),
+    /// e.g. "**/objects/[0-9a-f][0-9a-f]/*". Also passed to rg as a --pre-glob
+    PathGlob(String),
+    /// the bytes the file starts with, as hex, e.g. "7f 45 4c 46". Bytes at an offset
+    /// (within the first 64KiB) are given as "offset: hex", e.g. "32769: 43 44 30 30 31".
+    /// Matched against the start of the file already read, so files without an extension are matched too
+    Magic(String),
+    /// bytes regex matched against the start of the file (the first 64KiB), e.g. "^%!PS-Adobe"
+    ContentRegex(String),
+    // todo: maybe add others, e.g. regex on whole filename
     // todo: maybe allow matching a directory (e.g. /var/lib/postgres)
 }
 
@@ -40,12 +54,159 @@ impl From<FastFileMatcher> for FileMatcher {
     }
 }
 
-pub struct FileMeta {
+impl FileMatcher {
+    /// the type of the matcher, e.g. "extension" or "mime"
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FileMatcher::Fast(FastFileMatcher::FileExtension(_)) => "extension",
+            FileMatcher::Fast(FastFileMatcher::PathGlob(_)) => "glob",
+            FileMatcher::Fast(FastFileMatcher::Magic(_)) => "magic",
+            FileMatcher::Fast(FastFileMatcher::ContentRegex(_)) => "content",
+            FileMatcher::MimeType(_) => "mime",
+        }
+    }
+
+    /// the extension, glob, bytes, regex or mime type that matched
+    pub fn pattern(&self) -> &str {
+        match self {
+            FileMatcher::Fast(FastFileMatcher::FileExtension(s))
+            | FileMatcher::Fast(FastFileMatcher::PathGlob(s))
+            | FileMatcher::Fast(FastFileMatcher::Magic(s))
+            | FileMatcher::Fast(FastFileMatcher::ContentRegex(s))
+            | FileMatcher::MimeType(s) => s,
+        }
+    }
+}
+
+/// why an adapter was chosen, e.g. "file extension .zst"
+impl std::fmt::Display for FileMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileMatcher::Fast(FastFileMatcher::FileExtension(ext)) => {
+                write!(f, "file extension .{}", ext)
+            }
+            FileMatcher::Fast(FastFileMatcher::PathGlob(glob)) => write!(f, "path glob {}", glob),
+            FileMatcher::Fast(FastFileMatcher::Magic(hex)) => write!(f, "magic bytes {}", hex),
+            FileMatcher::Fast(FastFileMatcher::ContentRegex(re)) => {
+                write!(f, "content regex {}", re)
+            }
+            FileMatcher::MimeType(mime) => write!(f, "mime type {}", mime),
+        }
+    }
+}
+
+pub struct FileMeta<'a> {
     // filename is not actually a utf8 string, but since we can't do regex on OsStr and can't get a &[u8] from OsStr either,
     // and since we probably only want to do only matching on ascii stuff anyways, this is the filename as a string with non-valid bytes removed
     pub lossy_filename: String,
+    // same as lossy_filename, but the whole path. used for PathGlob matchers
+    pub lossy_path: String,
     // only given when slow matching is enabled
-    pub mimetype: Option<&'static str>,
+    pub mimetype: Option<String>,
+    // the start of the file, for Magic and ContentRegex matchers. None when only the path is known
+    pub content: Option<&'a [u8]>,
+}
+
+/// a --rga-adapter-ignore rule: don't use the adapters for the files matching the glob
+struct AdapterIgnoreRule {
+    glob: glob::Pattern,
+    /// globs without a "/" are matched against the file name, like in .gitignore
+    match_file_name: bool,
+    /// None for all adapters ("*")
+    adapters: Option<Vec<String>>,
+}
+
+/// the --rga-adapter-ignore rules, e.g. "node_modules/**=*" or "*.sqlite3=sqlite"
+#[derive(Default)]
+pub struct AdapterIgnore {
+    rules: Vec<AdapterIgnoreRule>,
+}
+
+impl AdapterIgnore {
+    pub fn new(rules: &[String]) -> Result<AdapterIgnore> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let eq = rule.rfind('=').ok_or_else(|| {
+                    format_err!(
+                        "--rga-adapter-ignore must be <glob>=<adapters>, got {:?}",
+                        rule
+                    )
+                })?;
+                let (glob, adapters) = (&rule[..eq], &rule[eq + 1..]);
+                let match_file_name = !glob.contains('/');
+                let glob = if match_file_name || glob.starts_with('/') || glob.starts_with("**") {
+                    glob.to_string()
+                } else {
+                    format!("**/{}", glob)
+                };
+                Ok(AdapterIgnoreRule {
+                    glob: glob::Pattern::new(&glob).with_context(|| {
+                        format!("invalid glob in --rga-adapter-ignore {:?}", rule)
+                    })?,
+                    match_file_name,
+                    adapters: if adapters == "*" {
+                        None
+                    } else {
+                        Some(adapters.split(',').map(String::from).collect())
+                    },
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(AdapterIgnore { rules })
+    }
+
+    /// whether the adapter must not be used for the file
+    pub fn is_ignored(&self, adapter: &str, lossy_filename: &str, lossy_path: &str) -> bool {
+        self.rules.iter().any(|rule| {
+            rule.adapters
+                .as_ref()
+                .map_or(true, |adapters| adapters.iter().any(|a| a == adapter))
+                && if rule.match_file_name {
+                    rule.glob.matches(lossy_filename)
+                } else {
+                    rule.glob.matches(lossy_path)
+                }
+        })
+    }
+}
+
+/// the --rga-mime filter
+#[derive(Default)]
+pub struct MimeFilter {
+    include: Vec<glob::Pattern>,
+    exclude: Vec<glob::Pattern>,
+}
+
+impl MimeFilter {
+    /// mime types (or globs like "image/*"), the ones starting with - are excluded
+    pub fn new(types: &[String]) -> Result<MimeFilter> {
+        let mut filter = MimeFilter::default();
+        for t in types {
+            let (list, t) = match t.strip_prefix('-') {
+                Some(t) => (&mut filter.exclude, t),
+                None => (&mut filter.include, t.as_str()),
+            };
+            list.push(
+                glob::Pattern::new(t)
+                    .with_context(|| format!("invalid mime type in --rga-mime {:?}", t))?,
+            );
+        }
+        Ok(filter)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// whether files of the mime type are searched.
+    /// Archives (`recurses`) are searched for included files unless they are excluded
+    pub fn allows(&self, mimetype: &str, recurses: bool) -> bool {
+        if self.exclude.iter().any(|p| p.matches(mimetype)) {
+            return false;
+        }
+        recurses || self.include.is_empty() || self.include.iter().any(|p| p.matches(mimetype))
+    }
 }
 
 pub fn extension_to_regex(extension: &str) -> Regex {
@@ -53,14 +214,230 @@ pub fn extension_to_regex(extension: &str) -> Regex {
         .expect("we know this regex compiles")
 }
 
+/// the conditions of an adapter in its adapter_options, checked after the adapter matched a file
+pub struct FileConditions {
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    executable: Option<bool>,
+    paths: Vec<glob::Pattern>,
+}
+
+impl FileConditions {
+    pub fn new(conditions: &AdapterConditions) -> Result<FileConditions> {
+        Ok(FileConditions {
+            min_size: conditions.min_size,
+            max_size: conditions.max_size,
+            executable: conditions.executable,
+            paths: conditions
+                .paths
+                .iter()
+                .map(|p| {
+                    glob::Pattern::new(p)
+                        .with_context(|| format!("invalid glob {:?} in conditions", p))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// `file` is the file on disk, None for files in archives.
+    /// Those always pass the size conditions, since their size is only known after reading them
+    pub fn allows(&self, lossy_path: &str, file: Option<&Path>) -> bool {
+        if !self.paths.is_empty() && !self.paths.iter().any(|p| p.matches(lossy_path)) {
+            return false;
+        }
+        if self.min_size.is_none() && self.max_size.is_none() && self.executable.is_none() {
+            return true;
+        }
+        let metadata = file.and_then(|f| std::fs::metadata(f).ok().map(|m| (f, m)));
+        if let Some((_, metadata)) = &metadata {
+            let len = metadata.len();
+            if self.min_size.map_or(false, |min| len < min)
+                || self.max_size.map_or(false, |max| len > max)
+            {
+                return false;
+            }
+        }
+        match self.executable {
+            Some(executable) => {
+                metadata.map_or(false, |(f, m)| m.is_file() && is_executable(f, &m)) == executable
+            }
+            None => true,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(_path: &Path, metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path, _metadata: &std::fs::Metadata) -> bool {
+    path.extension().map_or(false, |ext| {
+        ["exe", "com", "bat", "cmd"]
+            .iter()
+            .any(|e| ext.eq_ignore_ascii_case(e))
+    })
+}
+
+/// how much of the start of a file is read at most to choose its adapter (see FileMeta::content)
+pub const MAX_SNIFF_LEN: usize = 1 << 16;
+
+/// tree_magic detects some formats by bytes far into the file,
+/// e.g. ISO 9660 images by "CD001" at 32769, 34817 or 36865
+const ACCURATE_SNIFF_LEN: usize = 36865 + 5;
+
+/// the bytes of a FastFileMatcher::Magic
+#[derive(Debug, PartialEq)]
+pub struct Magic {
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+}
+
+impl Magic {
+    pub fn parse(magic: &str) -> Result<Magic> {
+        let (offset, hex) = match magic.find(':') {
+            Some(i) => (
+                magic[..i].trim().parse().map_err(|_| {
+                    format_err!("magic offset must be a number, e.g. \"257: 75 73\"")
+                })?,
+                &magic[i + 1..],
+            ),
+            None => (0, magic),
+        };
+        let hex: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+        if hex.is_empty() || hex.len() % 2 != 0 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format_err!("magic bytes must be hex, e.g. \"7f 45 4c 46\""));
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("checked hex"))
+            .collect();
+        let magic = Magic { offset, bytes };
+        if magic.end() > MAX_SNIFF_LEN {
+            return Err(format_err!(
+                "magic bytes must be within the first {} bytes",
+                MAX_SNIFF_LEN
+            ));
+        }
+        Ok(magic)
+    }
+
+    /// how much of the file has to be read to match it
+    pub fn end(&self) -> usize {
+        self.offset + self.bytes.len()
+    }
+
+    pub fn matches(&self, content: &[u8]) -> bool {
+        content
+            .get(self.offset..)
+            .map_or(false, |c| c.starts_with(&self.bytes))
+    }
+}
+
+enum ContentMatcher {
+    Magic(Magic),
+    Regex(regex::bytes::Regex),
+}
+
+impl ContentMatcher {
+    fn is_match(&self, content: &[u8]) -> bool {
+        match self {
+            ContentMatcher::Magic(magic) => magic.matches(content),
+            ContentMatcher::Regex(re) => re.is_match(content),
+        }
+    }
+}
+
+/// how much of the start of a file has to be read so the adapters can be matched by it.
+/// Files may be read in smaller parts (e.g. when decompressing), then more is read before choosing the adapter
+pub fn sniff_len(adapters: &[Rc<dyn FileAdapter>], slow: bool) -> Result<usize> {
+    let mut len = if slow { ACCURATE_SNIFF_LEN } else { 0 };
+    for adapter in adapters {
+        let metadata = adapter.metadata();
+        for matcher in metadata.get_matchers(slow) {
+            match matcher.as_ref() {
+                FileMatcher::Fast(FastFileMatcher::Magic(magic)) => {
+                    len = len.max(Magic::parse(magic)?.end())
+                }
+                FileMatcher::Fast(FastFileMatcher::ContentRegex(_)) => len = MAX_SNIFF_LEN,
+                _ => {}
+            }
+        }
+        if !slow {
+            for (_, magic) in BUILTIN_SIGNATURES
+                .iter()
+                .filter(|(name, _)| *name == metadata.name)
+            {
+                len = len.max(Magic::parse(magic)?.end());
+            }
+        }
+    }
+    Ok(len)
+}
+
+/// signatures of common formats and the builtin adapter handling them: (adapter, magic).
+/// Checked against the start of the file (which is read anyways) when no adapter matched the file name,
+/// so misnamed files and files without an extension (e.g. in archives) are handled without --rga-accurate.
+/// Unlike custom magic matchers, these don't make rg pass all files to rga
+const BUILTIN_SIGNATURES: &[(&str, &str)] = &[
+    // %PDF-
+    ("poppler", "25 50 44 46 2d"),
+    ("zip", "50 4b 03 04"),
+    // SQLite format 3\0
+    ("sqlite", "53 51 4c 69 74 65 20 66 6f 72 6d 61 74 20 33 00"),
+    // gzip
+    ("decompress", "1f 8b"),
+    // BZh
+    ("decompress", "42 5a 68"),
+    // xz
+    ("decompress", "fd 37 7a 58 5a 00"),
+    // zstd
+    ("decompress", "28 b5 2f fd"),
+    // ustar
+    ("tar", "257: 75 73 74 61 72"),
+    // hsqs (little endian squashfs, e.g. snaps)
+    ("squashfs", "68 73 71 73"),
+];
+
+/// whether an adapter is matched by the content of files, so rg has to pass all files to rga
+pub fn has_content_matchers(adapters: &[Rc<dyn FileAdapter>]) -> bool {
+    adapters.iter().any(|adapter| {
+        adapter.metadata().fast_matchers.iter().any(|m| {
+            matches!(
+                m,
+                FastFileMatcher::Magic(_) | FastFileMatcher::ContentRegex(_)
+            )
+        })
+    })
+}
+
+/// the adapter for a file: the first of the matching adapters in the order of `adapters`
 pub fn adapter_matcher(
     adapters: &Vec<Rc<dyn FileAdapter>>,
     slow: bool,
 ) -> Result<impl Fn(FileMeta) -> Option<(Rc<dyn FileAdapter>, FileMatcher)>> {
+    let matches = adapter_matches(adapters, slow)?;
+    Ok(move |meta: FileMeta| matches(meta).into_iter().next())
+}
+
+/// all adapters matching a file, in the order of `adapters` (see --rga-adapter-priority)
+pub fn adapter_matches(
+    adapters: &Vec<Rc<dyn FileAdapter>>,
+    slow: bool,
+) -> Result<impl Fn(FileMeta) -> Vec<(Rc<dyn FileAdapter>, FileMatcher)>> {
     // need order later
     let adapter_names: Vec<String> = adapters.iter().map(|e| e.metadata().name.clone()).collect();
-    let mut fname_regexes = vec![];
+    // extensions are looked up by the lowercased suffixes of the filename instead of compiling a regex for each of them,
+    // since a matcher is built for every file (and every file in archives)
+    let mut extensions: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut fname_matchers = vec![];
+    let mut path_globs = vec![];
     let mut mime_regexes = vec![];
+    let mut content_matchers = vec![];
+    // mime types detected with --rga-accurate are more precise than the builtin signatures
+    let mut signatures = vec![];
     for adapter in adapters.into_iter() {
         let metadata = adapter.metadata();
         use FileMatcher::*;
@@ -69,66 +446,158 @@ pub fn adapter_matcher(
                 MimeType(re) => {
                     mime_regexes.push((re.clone(), adapter.clone(), MimeType(re.clone())))
                 }
-                Fast(FastFileMatcher::FileExtension(re)) => fname_regexes.push((
-                    extension_to_regex(re),
+                Fast(FastFileMatcher::FileExtension(ext)) => {
+                    extensions
+                        .entry(ext.to_lowercase())
+                        .or_default()
+                        .push(fname_matchers.len());
+                    fname_matchers.push((
+                        adapter.clone(),
+                        Fast(FastFileMatcher::FileExtension(ext.clone())),
+                    ))
+                }
+                Fast(FastFileMatcher::PathGlob(glob)) => path_globs.push((
+                    glob::Pattern::new(glob).with_context(|| {
+                        format!("invalid glob {} in adapter {}", glob, metadata.name)
+                    })?,
+                    adapter.clone(),
+                    Fast(FastFileMatcher::PathGlob(glob.clone())),
+                )),
+                Fast(FastFileMatcher::Magic(hex)) => content_matchers.push((
+                    ContentMatcher::Magic(Magic::parse(hex).with_context(|| {
+                        format!("invalid magic {} in adapter {}", hex, metadata.name)
+                    })?),
                     adapter.clone(),
-                    Fast(FastFileMatcher::FileExtension(re.clone())),
+                    Fast(FastFileMatcher::Magic(hex.clone())),
+                )),
+                Fast(FastFileMatcher::ContentRegex(re)) => content_matchers.push((
+                    ContentMatcher::Regex(regex::bytes::Regex::new(re).with_context(|| {
+                        format!("invalid content regex {} in adapter {}", re, metadata.name)
+                    })?),
+                    adapter.clone(),
+                    Fast(FastFileMatcher::ContentRegex(re.clone())),
                 )),
             };
         }
+        if !slow {
+            for (_, hex) in BUILTIN_SIGNATURES
+                .iter()
+                .filter(|(name, _)| *name == metadata.name)
+            {
+                signatures.push((
+                    Magic::parse(hex)?,
+                    adapter.clone(),
+                    FileMatcher::Fast(FastFileMatcher::Magic(hex.to_string())),
+                ));
+            }
+        }
     }
-    let fname_regex_set = RegexSet::new(fname_regexes.iter().map(|p| p.0.as_str()))?;
-    let mime_regex_set = RegexSet::new(mime_regexes.iter().map(|p| p.0.as_str()))?;
+    // mime types are only matched in accurate mode
+    let mime_regex_set = if slow {
+        RegexSet::new(mime_regexes.iter().map(|p| p.0.as_str()))?
+    } else {
+        RegexSet::empty()
+    };
     Ok(move |meta: FileMeta| {
-        let fname_matches: Vec<_> = fname_regex_set
-            .matches(&meta.lossy_filename)
-            .into_iter()
+        let lowercase_filename = meta.lossy_filename.to_lowercase();
+        let mut fname_matches: Vec<usize> = lowercase_filename
+            .match_indices('.')
+            .filter_map(|(i, _)| extensions.get(&lowercase_filename[i + 1..]))
+            .flatten()
+            .copied()
             .collect();
-        let mime_matches: Vec<_> = if slow {
+        fname_matches.sort_unstable();
+        let fname_matches = fname_matches
+            .into_iter()
+            .map(|e| (fname_matchers[e].0.clone(), fname_matchers[e].1.clone()));
+        let glob_matches = path_globs
+            .iter()
+            .filter(|(glob, _, _)| glob.matches(&meta.lossy_path))
+            .map(|(_, adapter, matcher)| (adapter.clone(), matcher.clone()));
+        let content_matches = content_matchers
+            .iter()
+            .filter(|(m, _, _)| meta.content.map_or(false, |content| m.is_match(content)))
+            .map(|(_, adapter, matcher)| (adapter.clone(), matcher.clone()));
+        let mime_matches = if slow {
             mime_regex_set
-                .matches(&meta.mimetype.expect("No mimetype?"))
+                .matches(meta.mimetype.as_deref().expect("No mimetype?"))
                 .into_iter()
+                .map(|e| (mime_regexes[e].1.clone(), mime_regexes[e].2.clone()))
                 .collect()
         } else {
             vec![]
         };
-        if fname_matches.len() + mime_matches.len() > 1 {
+        let mut v: Vec<_> = fname_matches
+            .chain(glob_matches)
+            .chain(content_matches)
+            .chain(mime_matches)
+            .collect();
+        if v.is_empty() {
+            if let Some((_, adapter, matcher)) = signatures
+                .iter()
+                .find(|(magic, _, _)| meta.content.map_or(false, |content| magic.matches(content)))
+            {
+                debug!(
+                    "{} matched by the builtin signature of {}",
+                    meta.lossy_filename,
+                    adapter.metadata().name
+                );
+                return vec![(adapter.clone(), matcher.clone())];
+            }
+        }
+        if v.len() > 1 {
             // get first according to original priority list...
             // todo: kinda ugly
-            let fa = fname_matches
-                .iter()
-                .map(|e| (fname_regexes[*e].1.clone(), fname_regexes[*e].2.clone()));
-            let fb = mime_matches
-                .iter()
-                .map(|e| (mime_regexes[*e].1.clone(), mime_regexes[*e].2.clone()));
-            let mut v = vec![];
-            v.extend(fa);
-            v.extend(fb);
             v.sort_by_key(|e| {
                 adapter_names
                     .iter()
                     .position(|r| r == &e.0.metadata().name)
                     .expect("impossib7")
             });
-            eprintln!(
-                "Warning: found multiple adapters for {}:",
-                meta.lossy_filename
+            // an adapter is listed twice when it was given priority with "+name", and may match multiple ways
+            v.dedup_by(|a, b| a.0.metadata().name == b.0.metadata().name);
+            debug!(
+                "found multiple adapters for {}: {}",
+                meta.lossy_filename,
+                v.iter()
+                    .map(|m| m.0.metadata().name.clone())
+                    .collect::<Vec<_>>()
+                    .join(", ")
             );
-            for mmatch in v.iter() {
-                eprintln!(" - {}", mmatch.0.metadata().name);
-            }
-            return Some(v[0].clone());
-        }
-        if mime_matches.is_empty() {
-            if fname_matches.is_empty() {
-                None
-            } else {
-                let (_, adapter, matcher) = &fname_regexes[fname_matches[0]];
-                Some((adapter.clone(), matcher.clone()))
-            }
-        } else {
-            let (_, adapter, matcher) = &mime_regexes[mime_matches[0]];
-            Some((adapter.clone(), matcher.clone()))
         }
+        v
     })
 }
+
+/// the adapters a file goes through, going by its name. Adapters that output the content of the file under another name
+/// (e.g. decompress: foo.tar.zst -> foo.tar) are followed by the adapter matching that name,
+/// e.g. [(foo.tar.zst, decompress, .zst), (foo.tar, tar, .tar)]. Empty if no adapter matches the file
+pub fn adapter_chain(
+    matcher: impl Fn(FileMeta) -> Option<(Rc<dyn FileAdapter>, FileMatcher)>,
+    path: &Path,
+) -> Vec<(PathBuf, Rc<dyn FileAdapter>, FileMatcher)> {
+    let mut chain = vec![];
+    let mut path = path.to_owned();
+    loop {
+        let lossy_filename = match path.file_name() {
+            Some(filename) => filename.to_string_lossy().to_string(),
+            None => break,
+        };
+        let (adapter, reason) = match matcher(FileMeta {
+            lossy_filename,
+            lossy_path: path.to_string_lossy().to_string(),
+            mimetype: None,
+            content: None,
+        }) {
+            Some(m) => m,
+            None => break,
+        };
+        let inner = adapter.passthrough_name(&path);
+        chain.push((path.clone(), adapter, reason));
+        match inner {
+            Some(inner) if inner != path => path = inner,
+            _ => break,
+        }
+    }
+    chain
+}
@@ -0,0 +1,160 @@
+//! Windows paths: extended-length paths (\\?\C:\...) for folder trees deeper than MAX_PATH, and UNC shares (\\server\share\...).
+//! Outside of Windows, paths are used as they are.
+
+use std::path::{Path, PathBuf};
+
+/// paths of directories longer than this (in UTF-16 units) need the \\?\ prefix for most programs (MAX_PATH - 12, room for an 8.3 file name)
+#[cfg_attr(not(windows), allow(dead_code))]
+const MAX_SHORT_PATH: usize = 248;
+
+/// `\\?\C:\dir` → `C:\dir`, `\\?\UNC\server\share\dir` → `\\server\share\dir`.
+/// None if it is not the extended-length path of a drive or share (e.g. \\?\Volume{...}, which has no other form)
+pub fn strip_verbatim(path: &str) -> Option<String> {
+    let rest = path.strip_prefix(r"\\?\")?;
+    if let Some(unc) = rest.strip_prefix(r"UNC\") {
+        return Some(format!(r"\\{}", unc));
+    }
+    let bytes = rest.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        return Some(rest.to_string());
+    }
+    None
+}
+
+/// The extended-length form of an absolute Windows path: `C:\dir` → `\\?\C:\dir`, `\\server\share\dir` → `\\?\UNC\server\share\dir`.
+/// The path is normalized (/ and . and .. are not resolved in extended-length paths). None for relative paths
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn to_verbatim(path: &str) -> Option<String> {
+    if path.starts_with(r"\\?\") {
+        return Some(path.to_string());
+    }
+    let path = path.replace('/', r"\");
+    let (mut verbatim, rest) = if let Some(unc) = path.strip_prefix(r"\\") {
+        // \\server\share is the root, .. does not go above it
+        let mut parts = unc.splitn(3, '\\');
+        let server = parts.next().filter(|s| !s.is_empty())?;
+        let share = parts.next().filter(|s| !s.is_empty())?;
+        (
+            format!(r"\\?\UNC\{}\{}", server, share),
+            parts.next().unwrap_or(""),
+        )
+    } else {
+        let bytes = path.as_bytes();
+        if bytes.len() < 3
+            || !bytes[0].is_ascii_alphabetic()
+            || bytes[1] != b':'
+            || bytes[2] != b'\\'
+        {
+            return None;
+        }
+        (format!(r"\\?\{}", &path[..2]), &path[3..])
+    };
+    let mut components = vec![];
+    for component in rest.split('\\') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    for component in components {
+        verbatim.push('\\');
+        verbatim.push_str(component);
+    }
+    // the root of a drive is C:\, not C:
+    if verbatim.ends_with(':') {
+        verbatim.push('\\');
+    }
+    Some(verbatim)
+}
+
+/// The usual form of the path, without the \\?\ prefix (e.g. from canonicalize or given by the user).
+/// So the cache keys and the output are the same as when the path is given without it.
+/// The file functions of std add the prefix themselves when needed
+#[cfg(windows)]
+pub fn simplify(path: &Path) -> PathBuf {
+    match path.to_str().and_then(strip_verbatim) {
+        Some(simple) => PathBuf::from(simple),
+        None => path.to_owned(),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn simplify(path: &Path) -> PathBuf {
+    path.to_owned()
+}
+
+/// The path to give to a program: in the extended-length form if it is too long to be opened without it,
+/// e.g. documents deep in a folder tree on a network share
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> PathBuf {
+    let absolute = if path.is_absolute() {
+        path.to_owned()
+    } else {
+        match std::env::current_dir() {
+            Ok(dir) => dir.join(path),
+            Err(_) => return path.to_owned(),
+        }
+    };
+    let s = match absolute.to_str() {
+        Some(s) => s,
+        None => return path.to_owned(),
+    };
+    if s.encode_utf16().count() < MAX_SHORT_PATH {
+        return path.to_owned();
+    }
+    to_verbatim(s).map_or_else(|| path.to_owned(), PathBuf::from)
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> PathBuf {
+    path.to_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn verbatim() {
+        assert_eq!(
+            strip_verbatim(r"\\?\C:\dir\a.pdf").as_deref(),
+            Some(r"C:\dir\a.pdf")
+        );
+        assert_eq!(
+            strip_verbatim(r"\\?\UNC\server\share\a.pdf").as_deref(),
+            Some(r"\\server\share\a.pdf")
+        );
+        assert_eq!(strip_verbatim(r"\\?\Volume{1234}\a.pdf"), None);
+        assert_eq!(strip_verbatim(r"C:\dir\a.pdf"), None);
+
+        assert_eq!(
+            to_verbatim(r"C:\dir\.\sub\..\a.pdf").as_deref(),
+            Some(r"\\?\C:\dir\a.pdf")
+        );
+        assert_eq!(
+            to_verbatim("C:/dir/a.pdf").as_deref(),
+            Some(r"\\?\C:\dir\a.pdf")
+        );
+        assert_eq!(to_verbatim(r"C:\").as_deref(), Some(r"\\?\C:\"));
+        assert_eq!(
+            to_verbatim(r"\\server\share\dir\..\..\a.pdf").as_deref(),
+            Some(r"\\?\UNC\server\share\a.pdf")
+        );
+        assert_eq!(to_verbatim(r"\\server"), None);
+        assert_eq!(to_verbatim(r"dir\a.pdf"), None);
+        assert_eq!(
+            to_verbatim(r"\\?\C:\a.pdf").as_deref(),
+            Some(r"\\?\C:\a.pdf")
+        );
+    }
+
+    #[test]
+    fn unchanged_if_short() {
+        let path = Path::new("a.pdf");
+        assert_eq!(simplify(path), path);
+        assert_eq!(long_path(path), path);
+    }
+}
@@ -1,18 +1,138 @@
 use crate::adapters::*;
-use crate::{matching::*, recurse::RecursingConcattyReader};
 use crate::{
-    preproc_cache::{LmdbCache, PreprocCache},
-    print_bytes, print_dur, CachingReader,
+    cache_stats::CacheStats,
+    caching_writer::decompress,
+    config::RgaConfig,
+    detection::{self, Detection, MatchedBy},
+    expansion::ExpansionBudget,
+    failures::failure_count,
+    preproc_cache::{
+        cached_failure, failure_entry, get_chunked, open_cache, set_chunked, set_link,
+        CachedOutput, PreprocCache,
+    },
+    print_bytes, print_dur, secrets,
+    spill::SpillBuffer,
+    truncation::Truncation,
+    CachingReader,
 };
+use crate::{matching::*, recurse::concat_parallel};
 use anyhow::*;
 use log::*;
 use path_clean::PathClean;
 use postproc::PostprocPrefix;
+use sha2::{Digest, Sha256};
 use std::convert::TryInto;
+use std::path::{Path, PathBuf};
 
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read};
 
-use std::{rc::Rc, time::Instant};
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Instant};
+
+type AdapterMatcher = Rc<dyn Fn(FileMeta) -> Vec<(Rc<dyn FileAdapter>, FileMatcher)>>;
+
+/// the adapters enabled in the config, and the matcher for them
+#[derive(Clone)]
+struct ConfiguredAdapters {
+    filtered: Rc<Vec<Rc<dyn FileAdapter>>>,
+    matcher: AdapterMatcher,
+    ignore: Rc<AdapterIgnore>,
+    mime_filter: Rc<MimeFilter>,
+    /// adapters match files by their content, so rg passes all files to rga
+    match_content: bool,
+    /// how much of the start of files is read before choosing the adapter
+    sniff_len: usize,
+    /// the conditions in the adapter_options, by adapter name
+    conditions: Rc<HashMap<String, FileConditions>>,
+}
+
+impl ConfiguredAdapters {
+    /// the adapter for the file and the matcher that chose it, and whether the adapter is not used
+    /// because of --rga-adapter-ignore
+    fn choose(
+        &self,
+        mimetype: Option<String>,
+        lossy_filename: String,
+        lossy_path: String,
+        content: &[u8],
+        real_file: Option<&Path>,
+    ) -> (Option<(Rc<dyn FileAdapter>, FileMatcher)>, bool) {
+        let ignore = &self.ignore;
+        let is_ignored = |adapter: &dyn FileAdapter| {
+            ignore.is_ignored(&adapter.metadata().name, &lossy_filename, &lossy_path)
+        };
+        let adapter = (self.matcher)(FileMeta {
+            mimetype,
+            lossy_filename: lossy_filename.clone(),
+            lossy_path: lossy_path.clone(),
+            content: Some(content),
+        })
+        .into_iter()
+        .find(|(adapter, _)| {
+            self.conditions_allow(&adapter.metadata().name, &lossy_path, real_file)
+        });
+        match adapter {
+            Some((adapter, _)) if is_ignored(adapter.as_ref()) => {
+                debug!(
+                    "not using adapter {} (--rga-adapter-ignore)",
+                    adapter.metadata().name
+                );
+                (None, true)
+            }
+            adapter => (adapter, false),
+        }
+    }
+
+    /// whether the file matches the conditions in the adapter_options of the adapter
+    fn conditions_allow(&self, adapter: &str, lossy_path: &str, real_file: Option<&Path>) -> bool {
+        self.conditions.get(adapter).map_or(true, |conditions| {
+            let allowed = conditions.allows(lossy_path, real_file);
+            if !allowed {
+                debug!("not using adapter {} (conditions)", adapter);
+            }
+            allowed
+        })
+    }
+}
+
+thread_local! {
+    /// the adapters for the adapter settings seen in this thread.
+    /// Files in archives are preprocessed with the same settings, so the adapters are only set up once
+    static ADAPTERS: RefCell<HashMap<String, ConfiguredAdapters>> = RefCell::new(HashMap::new());
+}
+
+fn configured_adapters(config: &RgaConfig) -> Result<ConfiguredAdapters> {
+    let key = serde_json::to_string(&(
+        &config.adapters,
+        &config.adapter_priority,
+        &config.custom_adapters,
+        config.accurate,
+        &config.adapter_ignore,
+        &config.mime,
+        &config.adapter_options,
+    ))?;
+    if let Some(adapters) = ADAPTERS.with(|a| a.borrow().get(&key).cloned()) {
+        return Ok(adapters);
+    }
+    let filtered = enabled_adapters(config)?;
+    let adapters = ConfiguredAdapters {
+        matcher: Rc::new(adapter_matches(&filtered, config.accurate)?),
+        match_content: has_content_matchers(&filtered),
+        sniff_len: sniff_len(&filtered, config.accurate)?,
+        conditions: Rc::new(
+            config
+                .adapter_options
+                .iter()
+                .filter_map(|(name, options)| Some((name, options.conditions.as_ref()?)))
+                .map(|(name, conditions)| Ok((name.clone(), FileConditions::new(conditions)?)))
+                .collect::<Result<_>>()?,
+        ),
+        filtered: Rc::new(filtered),
+        ignore: Rc::new(AdapterIgnore::new(&config.adapter_ignore)?),
+        mime_filter: Rc::new(MimeFilter::new(&config.mime)?),
+    };
+    ADAPTERS.with(|a| a.borrow_mut().insert(key, adapters.clone()));
+    Ok(adapters)
+}
 /**
  * preprocess a file as defined in `ai`.
  *
@@ -25,45 +145,205 @@ pub fn rga_preproc(ai: AdaptInfo) -> Result<ReadBox> {
         is_real_file,
         inp,
         line_prefix,
-        config,
+        mut config,
         archive_recursion_depth,
         postprocess,
     } = ai;
     debug!("path (hint) to preprocess: {:?}", filepath_hint);
-    let filtered_adapters =
-        get_adapters_filtered(config.custom_adapters.clone(), &config.adapters)?;
-    let adapters = adapter_matcher(&filtered_adapters, config.accurate)?;
+    let adapters = configured_adapters(&config)?;
+    let filtered_adapters = adapters.filtered.clone();
     let filename = filepath_hint
         .file_name()
         .ok_or_else(|| format_err!("Empty filename"))?;
     debug!("Archive recursion depth: {}", archive_recursion_depth);
     if archive_recursion_depth >= config.max_archive_recursion.0 {
-        let s = format!("{}[rga: max archive recursion reached]", line_prefix).into_bytes();
-        return Ok(Box::new(std::io::Cursor::new(s)));
+        let s = Truncation::new(
+            format!("archive depth {}", config.max_archive_recursion.0),
+            "--rga-max-archive-recursion",
+        )
+        .marker(&line_prefix)
+        .into_bytes();
+        let out: ReadBox = Box::new(std::io::Cursor::new(s));
+        if config.verbose {
+            let detection = Detection::skipped(
+                &filepath_hint,
+                archive_recursion_depth,
+                "--rga-max-archive-recursion reached",
+            );
+            return detected(
+                config.json_records,
+                postprocess,
+                &line_prefix,
+                detection,
+                out,
+            );
+        }
+        return Ok(out);
     }
+    // everything extracted from the file counts towards the limits of the file
+    let inp = if archive_recursion_depth == 0 {
+        let file_size = Some(&filepath_hint)
+            .filter(|_| is_real_file)
+            .and_then(|path| std::fs::metadata(path).ok())
+            .map(|meta| meta.len());
+        config.expansion = ExpansionBudget::new(
+            config.max_expanded_size.0,
+            config.max_expansion_ratio.0,
+            file_size,
+        );
+        if file_size.is_some() {
+            inp
+        } else {
+            config.expansion.count_input(inp)
+        }
+    } else if config.expansion.exceeded() {
+        debug!(
+            "decompression limit reached, skipping {}",
+            filepath_hint.display()
+        );
+        if config.verbose {
+            let detection = Detection::skipped(
+                &filepath_hint,
+                archive_recursion_depth,
+                "--rga-max-expanded-size or --rga-max-expansion-ratio reached",
+            );
+            return detected(
+                config.json_records,
+                postprocess,
+                &line_prefix,
+                detection,
+                Box::new(std::io::empty()),
+            );
+        }
+        return Ok(Box::new(std::io::empty()));
+    } else {
+        config.expansion.limit(inp)
+    };
+    let expansion = Some(config.expansion.clone()).filter(|_| archive_recursion_depth == 0);
+    let marker_prefix = line_prefix.clone();
 
     // todo: figure out when using a bufreader is a good idea and when it is not
     // seems to be good for File::open() reads, but not sure about within archives (tar, zip)
-    let mut inp = BufReader::with_capacity(1 << 16, inp);
+    let mut inp = BufReader::with_capacity(MAX_SNIFF_LEN, inp);
+    if inp.fill_buf()?.len() < adapters.sniff_len {
+        inp = sniff(inp, adapters.sniff_len)?;
+    }
 
+    // fill but do not consume! Adapters may be matched by the start of the file
+    let buf = inp.fill_buf()?;
     let mimetype = if config.accurate {
-        let buf = inp.fill_buf()?; // fill but do not consume!
-        let mimetype = tree_magic::from_u8(buf);
+        let mimetype = detect_mimetype(&filepath_hint, is_real_file, buf, &config);
         debug!("mimetype: {:?}", mimetype);
         Some(mimetype)
     } else {
         None
     };
-    let adapter = adapters(FileMeta {
-        mimetype,
-        lossy_filename: filename.to_string_lossy().to_string(),
-    });
+    let (adapter, ignored) = adapters.choose(
+        mimetype.clone(),
+        filename.to_string_lossy().to_string(),
+        filepath_hint.to_string_lossy().to_string(),
+        buf,
+        Some(filepath_hint.as_path()).filter(|_| is_real_file),
+    );
+    // --rga-mime needs the mime type even without --rga-accurate (not used to choose the adapter then)
+    let mimetype = match mimetype {
+        None if !adapters.mime_filter.is_empty() => {
+            Some(detect_mimetype(&filepath_hint, is_real_file, buf, &config))
+        }
+        mimetype => mimetype,
+    };
+    if let Some(mimetype) = mimetype
+        .as_deref()
+        .filter(|_| !adapters.mime_filter.is_empty())
+    {
+        let recurses = adapter
+            .as_ref()
+            .map_or(false, |(adapter, _)| adapter.metadata().recurses);
+        if !adapters.mime_filter.allows(mimetype, recurses) {
+            debug!("skipping {} because of --rga-mime", mimetype);
+            if config.verbose {
+                let detection = Detection {
+                    mimetype: Some(mimetype.to_string()),
+                    skipped: Some("not allowed by --rga-mime".to_string()),
+                    ..Detection::new(&filepath_hint, archive_recursion_depth)
+                };
+                return detected(
+                    config.json_records,
+                    postprocess,
+                    &line_prefix,
+                    detection,
+                    Box::new(std::io::empty()),
+                );
+            }
+            return Ok(Box::new(std::io::empty()));
+        }
+    }
+    // protected office documents are decrypted for the adapter chosen by the extension.
+    // The decrypted output is not cached
+    let (mut inp, is_real_file) = if adapter.is_some()
+        && secrets::is_encrypted_office(&filepath_hint, inp.fill_buf()?)
+    {
+        debug!("decrypting {}", filepath_hint.display());
+        let decrypted = secrets::decrypt_office(&config, &filepath_hint, is_real_file, &mut inp)?;
+        let decrypted: ReadBox = Box::new(std::io::Cursor::new(decrypted));
+        (BufReader::with_capacity(MAX_SNIFF_LEN, decrypted), false)
+    } else {
+        (inp, is_real_file)
+    };
+    // text files that are passed through are transcoded to utf-8 so rg can search them
+    let text_encoding = if adapter.is_none() {
+        postproc::detect_encoding(config.encoding.label.as_deref(), inp.fill_buf()?)?
+    } else {
+        None
+    };
+    // same binary detection heuristic as rg: a NUL byte in the first block
+    let strings_fallback = adapter.is_none()
+        && !ignored
+        && text_encoding.is_none()
+        && config.strings.fallback
+        && adapters.conditions_allow(
+            "strings",
+            &filepath_hint.to_string_lossy(),
+            Some(filepath_hint.as_path()).filter(|_| is_real_file),
+        )
+        && inp.fill_buf()?.contains(&0);
+    // detected before transcoding, so the header shows the type of the original file
+    let header_mimetype = if config.file_header && postprocess && !config.json_records {
+        Some(match mimetype {
+            Some(mimetype) => mimetype,
+            None => tree_magic::from_u8(inp.fill_buf()?).to_string(),
+        })
+    } else {
+        None
+    };
+    let inp: ReadBox = match text_encoding {
+        Some(encoding) => {
+            debug!("transcoding from {}", encoding.name());
+            Box::new(postproc::postproc_encoding(encoding, inp))
+        }
+        None => Box::new(inp),
+    };
+    // the size limit is for adapters, not for files that are searched as they are
+    let limit_size = adapter.is_some() || strings_fallback;
     let (adapter, detection_reason) = match adapter {
         Some((a, d)) => (a, d),
+        None if strings_fallback => (
+            Rc::new(strings::StringsAdapter::new()) as Rc<dyn FileAdapter>,
+            FileMatcher::Fast(FastFileMatcher::FileExtension(
+                "strings-fallback".to_string(),
+            )), // todo: separate enum value for this
+        ),
         None => {
             // allow passthrough if the file is in an archive or accurate matching is enabled
             // otherwise it should have been filtered out by rg pre-glob since rg can handle those better than us
-            let allow_cat = !is_real_file || config.accurate;
+            // (with the strings fallback or adapters matching the content, rg passes all files to us)
+            // files ignored with --rga-adapter-ignore are searched as they are
+            let allow_cat = ignored
+                || !is_real_file
+                || config.accurate
+                || config.strings.fallback
+                || adapters.match_content
+                || !adapters.mime_filter.is_empty();
             if allow_cat {
                 if postprocess {
                     (
@@ -71,7 +351,7 @@ pub fn rga_preproc(ai: AdaptInfo) -> Result<ReadBox> {
                         FileMatcher::Fast(FastFileMatcher::FileExtension("default".to_string())), // todo: separate enum value for this
                     )
                 } else {
-                    return Ok(Box::new(inp));
+                    return Ok(inp);
                 }
             } else {
                 return Err(format_err!(
@@ -81,12 +361,85 @@ pub fn rga_preproc(ai: AdaptInfo) -> Result<ReadBox> {
             }
         }
     };
+    let detection = if config.verbose {
+        // the prefix adapter only adds the line prefixes
+        let adapted = adapter.metadata().name != "postprocprefix";
+        Some(Detection {
+            mimetype: mimetype.clone(),
+            adapter: Some(adapter.metadata().name.clone()).filter(|_| adapted),
+            matched_by: Some(MatchedBy::from(&detection_reason)).filter(|_| adapted),
+            then: adapter
+                .passthrough_name(&filepath_hint)
+                .filter(|inner| adapted && *inner != filepath_hint)
+                .map(|inner| inner.to_string_lossy().into_owned()),
+            ..Detection::new(&filepath_hint, archive_recursion_depth)
+        })
+    } else {
+        None
+    };
+    let max_filesize = if limit_size {
+        config.max_filesize(&adapter.metadata().name)
+    } else {
+        None
+    };
+    let inp = match max_filesize {
+        Some(max) => match limit_input(inp, is_real_file, &filepath_hint, max, &config)? {
+            Some(inp) => inp,
+            None => {
+                debug!(
+                    "{} is larger than {}, not running adapter {}",
+                    filepath_hint.display(),
+                    print_bytes(max as f64),
+                    adapter.metadata().name
+                );
+                let s = Truncation::new(print_bytes(max as f64), "--rga-max-filesize")
+                    .marker(&line_prefix)
+                    .into_bytes();
+                let out: ReadBox = Box::new(std::io::Cursor::new(s));
+                if let Some(detection) = detection {
+                    let detection = Detection {
+                        skipped: Some(format!(
+                            "larger than {} (--rga-max-filesize)",
+                            print_bytes(max as f64)
+                        )),
+                        ..detection
+                    };
+                    return detected(
+                        config.json_records,
+                        postprocess,
+                        &line_prefix,
+                        detection,
+                        out,
+                    );
+                }
+                return Ok(out);
+            }
+        },
+        None => inp,
+    };
+    let header = header_mimetype.map(|mimetype| {
+        let pages = if is_real_file && mimetype == "application/pdf" {
+            pdf_page_count(&filepath_hint)
+        } else {
+            None
+        };
+        file_header(
+            &line_prefix,
+            &mimetype,
+            &adapter.metadata().name,
+            pages,
+            archive_recursion_depth,
+        )
+    });
     let path_hint_copy = filepath_hint.clone();
-    run_adapter(
+    let json_records = config.json_records;
+    // the output of nested files is part of the output of the file
+    let sanitize = Some(config.clone()).filter(|_| archive_recursion_depth == 0);
+    let out = run_adapter(
         AdaptInfo {
             filepath_hint,
             is_real_file,
-            inp: Box::new(inp),
+            inp,
             line_prefix,
             config,
             archive_recursion_depth,
@@ -96,7 +449,619 @@ pub fn rga_preproc(ai: AdaptInfo) -> Result<ReadBox> {
         detection_reason,
         &filtered_adapters,
     )
-    .with_context(|| format!("run_adapter({})", &path_hint_copy.to_string_lossy()))
+    .with_context(|| format!("run_adapter({})", &path_hint_copy.to_string_lossy()))?;
+    let out = match detection {
+        Some(detection) => detected(json_records, postprocess, &marker_prefix, detection, out)?,
+        None => out,
+    };
+    let out: ReadBox = match header {
+        Some(header) => Box::new(std::io::Cursor::new(header.into_bytes()).chain(out)),
+        None => out,
+    };
+    // not cached, so it can be changed without clearing the cache
+    let out = match sanitize {
+        Some(config) => postproc::postproc_control_chars(&config, out),
+        None => out,
+    };
+    Ok(match expansion {
+        Some(expansion) => expansion.mark(out, &marker_prefix),
+        None => out,
+    })
+}
+
+/// --rga-verbose: with --format json, the detection is a record before the output of the file.
+/// Otherwise it is printed later, see detection
+fn detected<'a>(
+    json_records: bool,
+    postprocess: bool,
+    line_prefix: &str,
+    detection: Detection,
+    out: ReadBox<'a>,
+) -> Result<ReadBox<'a>> {
+    // without postprocessing, the output is read by the adapter of the file it is in
+    if !(json_records && postprocess) {
+        detection::record(detection);
+        return Ok(out);
+    }
+    let record = postproc::TextRecord {
+        source_path: None,
+        subfile_path: postproc::subfile_path(line_prefix),
+        page: None,
+        line: 0,
+        text: String::new(),
+        detection: Some(detection),
+    };
+    let mut line = serde_json::to_vec(&record)?;
+    line.push(b'\n');
+    Ok(Box::new(std::io::Cursor::new(line).chain(out)))
+}
+
+/// buffer the start of the input until `len` bytes (or the whole input) are read.
+/// fill_buf only reads once, which may return less (e.g. when decompressing) than the matchers look at
+fn sniff<'a>(mut inp: BufReader<ReadBox<'a>>, len: usize) -> Result<BufReader<ReadBox<'a>>> {
+    let mut head = Vec::with_capacity(len);
+    (&mut inp).take(len as u64).read_to_end(&mut head)?;
+    let inp: ReadBox = Box::new(std::io::Cursor::new(head).chain(inp));
+    Ok(BufReader::with_capacity(MAX_SNIFF_LEN, inp))
+}
+
+/// the input if it is at most `max` bytes, None if it is larger (see --rga-max-filesize).
+/// The size of files in archives is only known after reading them, so they are buffered
+/// (in a temporary file above --rga-memory-limit)
+fn limit_input<'a>(
+    inp: ReadBox<'a>,
+    is_real_file: bool,
+    filepath_hint: &Path,
+    max: u64,
+    config: &RgaConfig,
+) -> Result<Option<ReadBox<'a>>> {
+    if is_real_file {
+        let len = std::fs::metadata(filepath_hint)?.len();
+        return Ok(if len > max { None } else { Some(inp) });
+    }
+    let mut buf = SpillBuffer::new(config.memory_limit.0);
+    if std::io::copy(&mut inp.take(max + 1), &mut buf)? > max {
+        return Ok(None);
+    }
+    let inp: ReadBox<'a> = buf.into_reader()?;
+    Ok(Some(inp))
+}
+
+/// the line printed before the content of each file with --rga-file-header
+fn file_header(
+    line_prefix: &str,
+    mimetype: &str,
+    adapter: &str,
+    pages: Option<usize>,
+    archive_recursion_depth: i32,
+) -> String {
+    let pages = match pages {
+        Some(pages) => format!(", pages: {}", pages),
+        None => String::new(),
+    };
+    format!(
+        "{}# mime: {}, adapter: {}{}, archive depth: {}\n",
+        line_prefix, mimetype, adapter, pages, archive_recursion_depth
+    )
+}
+
+/// the page count from pdfinfo (part of poppler), if it's installed
+fn pdf_page_count(path: &Path) -> Option<usize> {
+    let output = std::process::Command::new("pdfinfo")
+        .arg(path)
+        .stderr(spawning::stderr_if_debug())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("Pages:"))
+        .and_then(|pages| pages.trim().parse().ok())
+}
+
+/// (name, version, definition digest) of an adapter
+type AdapterVersion = (String, i32, Option<Vec<u8>>);
+/// cache key of adapters that recurse: (active adapters, path, mtime, cache_key_extra)
+type RecursingCacheKey = (
+    Vec<AdapterVersion>,
+    PathBuf,
+    std::time::SystemTime,
+    Option<Vec<u8>>,
+);
+/// cache key of other adapters: (adapter, path, mtime, cache_key_extra)
+type CacheKey = (
+    AdapterVersion,
+    PathBuf,
+    std::time::SystemTime,
+    Option<Vec<u8>>,
+);
+
+/// cache key of adapters that recurse with --rga-cache-content-hash: (active adapters, sha256 of the file, cache_key_extra)
+type RecursingContentCacheKey = (Vec<AdapterVersion>, Vec<u8>, Option<Vec<u8>>);
+/// cache key of other adapters with --rga-cache-content-hash: (adapter, sha256 of the file, cache_key_extra)
+type ContentCacheKey = (AdapterVersion, Vec<u8>, Option<Vec<u8>>);
+
+fn adapter_version(adapter: &dyn FileAdapter) -> AdapterVersion {
+    let meta = adapter.metadata();
+    (meta.name.clone(), meta.version, adapter.definition_digest())
+}
+
+/// sha256 of the contents of a file
+fn hash_file(path: &Path) -> Result<Vec<u8>> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// errors caused by the environment rather than the file (e.g. a missing executable), which are not cached
+fn is_environment_error(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(e.kind(), NotFound | PermissionDenied | Interrupted)
+}
+
+/// record that the adapter failed, so it's not run again on the same file
+fn cache_failure(cache: &mut dyn PreprocCache, db_name: &str, key: &[u8], message: &str) {
+    debug!("caching failure: {}", message);
+    if let Err(e) = cache.set(db_name, key, &failure_entry(message)) {
+        warn!("could not write to cache: {:#}", e);
+    }
+}
+
+/// the cache_key_extra of the adapter, plus options that can change the output of any adapter
+fn cache_key_extra(
+    adapter: &dyn FileAdapter,
+    path: &Path,
+    filtered_adapters: &Vec<Rc<dyn FileAdapter>>,
+    config: &RgaConfig,
+) -> Result<Option<Vec<u8>>> {
+    let mut extra = adapter.cache_key_extra(path, config)?;
+    // the output of e.g. decompress depends on the adapters chosen by the inner file name,
+    // so identical files named foo.tar.gz and foo.gz have different outputs
+    match adapter.passthrough_name(path) {
+        Some(inner) if inner != path => {
+            let chain = adapter_chain(adapter_matcher(filtered_adapters, false)?, &inner)
+                .iter()
+                .map(|(_, adapter, _)| adapter.metadata().name.clone())
+                .collect::<Vec<_>>()
+                .join(",");
+            extra
+                .get_or_insert_with(Vec::new)
+                .extend_from_slice(format!("chain={}", chain).as_bytes());
+        }
+        _ => {}
+    }
+    if config.json_records {
+        extra
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(b"json");
+        // the records of files in archives include their detections
+        if config.verbose {
+            extra
+                .get_or_insert_with(Vec::new)
+                .extend_from_slice(b"verbose");
+        }
+    }
+    if config.hyperlinks {
+        extra
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(b"hyperlinks");
+    }
+    if !config.adapter_options.is_empty() {
+        extra
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(serde_json::to_string(&config.adapter_options)?.as_bytes());
+    }
+    // files in archives can be ignored
+    if !config.adapter_ignore.is_empty() {
+        extra
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(config.adapter_ignore.join("\n").as_bytes());
+    }
+    // changes the output of pandoc, also for documents in archives
+    if config.markdown {
+        extra
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(b"markdown");
+    }
+    // the headers of files in archives are part of the output of the archive
+    if config.file_header {
+        extra
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(b"header");
+    }
+    // the prefixes of files in archives and of pages are part of the output
+    if config.prefix_format != Default::default() || config.page_format != Default::default() {
+        let formats = format!(
+            "prefix={} page={}",
+            config.prefix_format.0, config.page_format.0
+        );
+        extra
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(formats.as_bytes());
+    }
+    // the output of files in archives is cut off by the limits
+    if config.no_limits {
+        extra
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(b"no_limits");
+    }
+    // the output of files in archives is cut off by the decompression limits
+    if config.max_expanded_size != Default::default()
+        || config.max_expansion_ratio != Default::default()
+    {
+        let limits = format!(
+            "expansion={},{}",
+            config.max_expanded_size.0, config.max_expansion_ratio.0
+        );
+        extra
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(limits.as_bytes());
+    }
+    // files in archives larger than the limit are replaced by a marker
+    if let Some(max) = config.max_filesize {
+        extra
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(format!("max_filesize={}", max.0).as_bytes());
+    }
+    // the names of files in archives are part of the output
+    if !config.entry_name_encoding.is_empty() {
+        extra.get_or_insert_with(Vec::new).extend_from_slice(
+            format!("entry_names={}", config.entry_name_encoding.join(",")).as_bytes(),
+        );
+    }
+    // files in archives can be skipped by their mime type
+    if !config.mime.is_empty() {
+        extra
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(format!("mime={}", config.mime.join(",")).as_bytes());
+    }
+    // outputs of programs that were stopped by a resource limit end with a note
+    if config.adapter_cpu_limit.is_some()
+        || config.adapter_memory_limit.is_some()
+        || config.adapter_file_size_limit.is_some()
+    {
+        let limits = format!(
+            "limits={:?},{:?},{:?}",
+            config.adapter_cpu_limit.map(|l| l.0),
+            config.adapter_memory_limit.map(|l| l.0),
+            config.adapter_file_size_limit.map(|l| l.0)
+        );
+        extra
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(limits.as_bytes());
+    }
+    Ok(match config.adapter_timeout {
+        // outputs that timed out are only valid for the same timeout
+        Some(timeout) => {
+            let mut extra = extra.unwrap_or_default();
+            extra.extend_from_slice(format!("timeout={}", timeout.0).as_bytes());
+            Some(extra)
+        }
+        None => extra,
+    })
+}
+
+/// cache store of the mime types detected with --rga-accurate. Change the version when updating tree_magic
+const MIMETYPE_DB: &str = "mimetype.v1";
+
+/// tree_magic checks the data against every known type, which takes a significant part of a search with a warm cache.
+/// So the result is cached for real files, by path and mtime
+fn detect_mimetype(
+    filepath_hint: &Path,
+    is_real_file: bool,
+    buf: &[u8],
+    config: &RgaConfig,
+) -> String {
+    let key = if is_real_file {
+        std::fs::metadata(filepath_hint)
+            .and_then(|m| m.modified())
+            .ok()
+            .map(|modified| {
+                bincode::serialize(&(filepath_hint.to_owned().clean(), modified))
+                    .expect("could not serialize path")
+            })
+    } else {
+        None
+    };
+    let mut cache = match key {
+        Some(_) => open_cache(&config.cache).unwrap_or_else(|e| {
+            warn!("could not open cache, continuing without: {:#}", e);
+            None
+        }),
+        None => None,
+    };
+    if let (Some(cache), Some(key)) = (&cache, &key) {
+        match cache.get(MIMETYPE_DB, key) {
+            Ok(Some(mimetype)) => {
+                if let Ok(mimetype) = String::from_utf8(mimetype) {
+                    debug!("mimetype from cache");
+                    return mimetype;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("could not read from cache: {:#}", e),
+        }
+    }
+    let mimetype = tree_magic::from_u8(buf).to_string();
+    if let (Some(cache), Some(key)) = (&mut cache, &key) {
+        if !config.cache.readonly {
+            if let Err(e) = cache.set(MIMETYPE_DB, key, mimetype.as_bytes()) {
+                warn!("could not write to cache: {:#}", e);
+            }
+        }
+    }
+    mimetype
+}
+
+/// whether the output of the adapter is just the content of the file, and nothing else processes it further.
+/// Then it's faster to stream it directly than to store it in and read it from the cache
+fn is_passthrough(
+    adapter: &dyn FileAdapter,
+    filepath_hint: &Path,
+    filtered_adapters: &Vec<Rc<dyn FileAdapter>>,
+    config: &RgaConfig,
+) -> Result<bool> {
+    let inner = match adapter.passthrough_name(filepath_hint) {
+        Some(inner) => inner,
+        None => return Ok(false),
+    };
+    if inner == filepath_hint {
+        // e.g. text files, for which no adapter was found already
+        return Ok(true);
+    }
+    if config.accurate {
+        // the inner file may be matched by its mime type, which is not known yet
+        return Ok(false);
+    }
+    // e.g. foo.txt.gz.xz is decompressed twice and then searched as it is
+    let adapters = adapter_matcher(filtered_adapters, false)?;
+    Ok(adapter_chain(adapters, &inner)
+        .iter()
+        .all(|(path, adapter, _)| adapter.passthrough_name(path).is_some()))
+}
+
+/// computes the keys the output of an adapter for a file is cached under
+struct CacheKeys<'a> {
+    adapter: &'a dyn FileAdapter,
+    filtered_adapters: &'a [Rc<dyn FileAdapter>],
+    filepath_hint: &'a Path,
+    extra: Option<Vec<u8>>,
+}
+
+impl CacheKeys<'_> {
+    fn adapter_versions(&self) -> Vec<AdapterVersion> {
+        self.filtered_adapters
+            .iter()
+            .map(|a| adapter_version(a.as_ref()))
+            .collect()
+    }
+
+    /// outputs are stored by the hash of the file, so identical files are processed and stored only once
+    fn content_key(&self) -> Result<Vec<u8>> {
+        let hash = hash_file(self.filepath_hint)?;
+        Ok(if self.adapter.metadata().recurses {
+            let key: RecursingContentCacheKey = (self.adapter_versions(), hash, self.extra.clone());
+            debug!("Cache key (content hash, with recursion): {:?}", key);
+            bincode::serialize(&key).expect("could not serialize hash")
+        } else {
+            let key: ContentCacheKey = (adapter_version(self.adapter), hash, self.extra.clone());
+            debug!("Cache key (content hash, no recursion): {:?}", key);
+            bincode::serialize(&key).expect("could not serialize hash")
+        })
+    }
+
+    /// links to the content key, so files only need to be hashed the first time they are seen.
+    /// Not used with --rga-cache-content-hash, which hashes the file every time
+    fn path_key(&self) -> Result<Vec<u8>> {
+        let clean_path = self.filepath_hint.to_owned().clean();
+        let meta = std::fs::metadata(self.filepath_hint).with_context(|| {
+            format!(
+                "reading metadata for {}",
+                self.filepath_hint.to_string_lossy()
+            )
+        })?;
+        let modified = meta.modified().expect("weird OS that can't into mtime");
+
+        Ok(if self.adapter.metadata().recurses {
+            let key: RecursingCacheKey = (
+                self.adapter_versions(),
+                clean_path,
+                modified,
+                self.extra.clone(),
+            );
+            debug!("Cache key (with recursion): {:?}", key);
+            bincode::serialize(&key).expect("could not serialize path")
+        } else {
+            let key: CacheKey = (
+                adapter_version(self.adapter),
+                clean_path,
+                modified,
+                self.extra.clone(),
+            );
+            debug!("Cache key (no recursion): {:?}", key);
+            bincode::serialize(&key).expect("could not serialize path")
+        })
+    }
+}
+
+/// look up the output for a file, by its path key first if given.
+/// Returns the output and the content key, which is computed if the path key is not found
+fn lookup_cache(
+    cache: &mut dyn PreprocCache,
+    db_name: &str,
+    keys: &CacheKeys,
+    path_key: Option<&[u8]>,
+) -> Result<(Option<CachedOutput>, Option<Vec<u8>>)> {
+    let lookup = |cache: &dyn PreprocCache, key: &[u8]| {
+        get_chunked(cache, db_name, key).unwrap_or_else(|e| {
+            warn!("could not read from cache: {:#}", e);
+            None
+        })
+    };
+    if let Some(cached) = path_key.and_then(|k| lookup(&*cache, k)) {
+        return Ok((Some(cached), None));
+    }
+    let content_key = keys.content_key()?;
+    let cached = lookup(&*cache, &content_key);
+    if cached.is_some() {
+        debug!("cache HIT for identical file");
+        link(cache, db_name, path_key, &content_key);
+    }
+    Ok((cached, Some(content_key)))
+}
+
+/// the decompressed output from the chunks of a cache entry
+fn cached_reader<'a>(chunks: Vec<Vec<u8>>) -> Result<ReadBox<'a>> {
+    let mut inp: ReadBox = Box::new(std::io::empty());
+    for chunk in chunks {
+        inp = Box::new(inp.chain(decompress(chunk)?));
+    }
+    Ok(inp)
+}
+
+/// the path given to the adapters for stdin: without an extension, so the adapter is chosen by the content
+pub const STDIN_HINT: &str = "stdin";
+
+/// the output of the adapters for stdin, e.g. an archive or a document (`tar -c dir | rga pattern -`)
+pub fn preprocess_stdin(config: &RgaConfig) -> Result<ReadBox<'static>> {
+    rga_preproc(AdaptInfo {
+        inp: Box::new(std::io::stdin()),
+        filepath_hint: PathBuf::from(STDIN_HINT),
+        is_real_file: false,
+        line_prefix: "".to_string(),
+        archive_recursion_depth: 0,
+        postprocess: true,
+        config: config.clone(),
+    })
+    .context("preprocessing stdin")
+}
+
+/// the adapter that rga_preproc chooses for a file and the matcher that chose it,
+/// and the detected mime type with --rga-accurate
+pub fn choose_adapter(
+    config: &RgaConfig,
+    path: &Path,
+) -> Result<(Option<String>, Option<(Rc<dyn FileAdapter>, FileMatcher)>)> {
+    let adapters = configured_adapters(&config)?;
+    let mut buf = Vec::new();
+    std::fs::File::open(path)?
+        .take(MAX_SNIFF_LEN as u64)
+        .read_to_end(&mut buf)?;
+    let mimetype = if config.accurate {
+        Some(detect_mimetype(path, true, &buf, config))
+    } else {
+        None
+    };
+    let (adapter, _) = adapters.choose(
+        mimetype.clone(),
+        path.file_name()
+            .ok_or_else(|| format_err!("Empty filename"))?
+            .to_string_lossy()
+            .to_string(),
+        path.to_string_lossy().to_string(),
+        &buf,
+        Some(path),
+    );
+    Ok((mimetype, adapter))
+}
+
+/// all adapters matching a file, the first is the one choose_adapter chooses (unless it is ignored with --rga-adapter-ignore)
+pub fn matching_adapters(
+    config: &RgaConfig,
+    path: &Path,
+) -> Result<Vec<(Rc<dyn FileAdapter>, FileMatcher)>> {
+    let mut buf = Vec::new();
+    std::fs::File::open(path)?
+        .take(MAX_SNIFF_LEN as u64)
+        .read_to_end(&mut buf)?;
+    let mimetype = if config.accurate {
+        Some(detect_mimetype(path, true, &buf, config))
+    } else {
+        None
+    };
+    let matches = adapter_matches(&enabled_adapters(config)?, config.accurate)?;
+    Ok(matches(FileMeta {
+        mimetype,
+        lossy_filename: path
+            .file_name()
+            .ok_or_else(|| format_err!("Empty filename"))?
+            .to_string_lossy()
+            .to_string(),
+        lossy_path: path.to_string_lossy().to_string(),
+        content: Some(&buf),
+    }))
+}
+
+/// the cached output of the adapter rga would use for a file, without running the adapter.
+/// Returns the name of the adapter, and None as the output if it is not cached
+pub fn cached_output<'a>(config: &RgaConfig, path: &Path) -> Result<(String, Option<ReadBox<'a>>)> {
+    let filtered_adapters = configured_adapters(&config)?.filtered;
+    let (adapter, _) = choose_adapter(config, path)?
+        .1
+        .ok_or_else(|| format_err!("no adapter for {}", path.display()))?;
+    let meta = adapter.metadata();
+    let mut cache =
+        open_cache(&config.cache)?.ok_or_else(|| format_err!("the cache is disabled"))?;
+    let keys = CacheKeys {
+        adapter: adapter.as_ref(),
+        filtered_adapters: &filtered_adapters,
+        filepath_hint: path,
+        extra: cache_key_extra(adapter.as_ref(), path, &filtered_adapters, config)?,
+    };
+    let path_key = if config.cache.content_hash {
+        None
+    } else {
+        Some(keys.path_key()?)
+    };
+    let db_name = format!("{}.v{}", meta.name, meta.version);
+    let output = match lookup_cache(cache.as_mut(), &db_name, &keys, path_key.as_deref())?.0 {
+        Some(CachedOutput { chunks, .. }) => {
+            if let [chunk] = &chunks[..] {
+                if let Some(message) = cached_failure(chunk) {
+                    return Err(format_err!(
+                        "cached failure of adapter {}: {}",
+                        meta.name,
+                        message
+                    ));
+                }
+            }
+            Some(cached_reader(chunks)?)
+        }
+        None => None,
+    };
+    Ok((meta.name.clone(), output))
+}
+
+/// point the (path, mtime) key of a file to the key its output is stored under
+fn link(cache: &mut dyn PreprocCache, db_name: &str, from: Option<&[u8]>, to: &[u8]) {
+    if let Some(from) = from {
+        if let Err(e) = set_link(cache, db_name, from, to) {
+            warn!("could not write to cache: {:#}", e);
+        }
+    }
+}
+
+/// the path of the file a cache entry was created for.
+/// None for keys by content hash
+pub fn cache_key_path(key: &[u8], recurses: bool) -> Option<PathBuf> {
+    if recurses {
+        bincode::deserialize::<RecursingCacheKey>(key)
+            .ok()
+            .map(|k| k.1)
+    } else {
+        bincode::deserialize::<CacheKey>(key).ok().map(|k| k.1)
+    }
 }
 
 fn run_adapter<'a>(
@@ -119,60 +1084,86 @@ fn run_adapter<'a>(
         "Chose adapter '{}' because of matcher {:?}",
         &meta.name, &detection_reason
     );
-    eprintln!(
-        "{} adapter: {}",
-        filepath_hint.to_string_lossy(),
-        &meta.name
-    );
     let db_name = format!("{}.v{}", meta.name, meta.version);
+    let cache_compression = config.cache.compression;
     let cache_compression_level = config.cache.compression_level;
     let cache_max_blob_len = config.cache.max_blob_len;
+    let cache_max_chunks = config.cache.max_chunks;
+    let cache_readonly = config.cache.readonly;
+    let archive_jobs = config.archive_jobs.0;
+    let cache_min_duration = std::time::Duration::from_millis(config.cache.min_duration.0);
+
+    // tiny files are faster to process than to look up
+    let cheap_file = is_real_file
+        && std::fs::metadata(&filepath_hint)
+            .map(|m| m.len() < config.cache.min_file_size.0 as u64)
+            .unwrap_or(false);
+    if cheap_file {
+        debug!("file smaller than --rga-cache-min-file-size, not using cache");
+    }
+    let passthrough = is_real_file
+        && !cheap_file
+        && is_passthrough(adapter.as_ref(), &filepath_hint, filtered_adapters, &config)?;
+    if passthrough {
+        debug!("output is the (decompressed) file content, not using cache");
+    }
 
-    let cache = if is_real_file {
-        LmdbCache::open(&config.cache)?
+    // the cache is shared with other rga processes. If it can't be used, run without it
+    let cache = if is_real_file && !cheap_file && !passthrough {
+        open_cache(&config.cache).unwrap_or_else(|e| {
+            warn!("could not open cache, continuing without: {:#}", e);
+            None
+        })
     } else {
         None
     };
 
     if let Some(mut cache) = cache {
-        let cache_key: Vec<u8> = {
-            let clean_path = filepath_hint.to_owned().clean();
-            let meta = std::fs::metadata(&filepath_hint).with_context(|| {
-                format!("reading metadata for {}", filepath_hint.to_string_lossy())
-            })?;
-            let modified = meta.modified().expect("weird OS that can't into mtime");
-
-            if adapter.metadata().recurses {
-                let key = (
-                    filtered_adapters
-                        .iter()
-                        .map(|a| (a.metadata().name.clone(), a.metadata().version))
-                        .collect::<Vec<_>>(),
-                    clean_path,
-                    modified,
-                );
-                debug!("Cache key (with recursion): {:?}", key);
-                bincode::serialize(&key).expect("could not serialize path")
-            } else {
-                let key = (
-                    adapter.metadata().name.clone(),
-                    adapter.metadata().version,
-                    clean_path,
-                    modified,
-                );
-                debug!("Cache key (no recursion): {:?}", key);
-                bincode::serialize(&key).expect("could not serialize path")
-            }
+        let keys = CacheKeys {
+            adapter: adapter.as_ref(),
+            filtered_adapters,
+            filepath_hint: &filepath_hint,
+            extra: cache_key_extra(adapter.as_ref(), &filepath_hint, filtered_adapters, &config)?,
         };
-        // let dbg_ctx = format!("adapter {}", &adapter.metadata().name);
-        let cached = cache.get(&db_name, &cache_key)?;
+        let path_key = if config.cache.content_hash {
+            None
+        } else {
+            Some(keys.path_key()?)
+        };
+        let (cached, cache_key) =
+            lookup_cache(cache.as_mut(), &db_name, &keys, path_key.as_deref())?;
         match cached {
-            Some(cached) => Ok(Box::new(
-                zstd::stream::read::Decoder::new(std::io::Cursor::new(cached))
-                    .context("could not create zstd decoder")?,
-            )),
+            Some(CachedOutput { chunks, duration }) => {
+                if let [chunk] = &chunks[..] {
+                    if let Some(message) = cached_failure(chunk) {
+                        return Err(format_err!(
+                            "adapting {} via {} failed (cached, retried after a day or `rga cache clear --adapter {}`): {}",
+                            filepath_hint.to_string_lossy(),
+                            meta.name,
+                            meta.name,
+                            message
+                        ));
+                    }
+                }
+                CacheStats {
+                    hits: 1,
+                    time_saved_ms: duration.map_or(0, |d| d.as_millis() as u64),
+                    ..Default::default()
+                }
+                .record();
+                cached_reader(chunks)
+            }
             None => {
                 debug!("cache MISS, running adapter");
+                let cache_key = cache_key.expect("computed on miss");
+                CacheStats {
+                    misses: 1,
+                    ..Default::default()
+                }
+                .record();
+                let adapt_start = Instant::now();
+                let failures_before = failure_count();
+                let expansion = config.expansion.clone();
                 debug!("adapting with caching...");
                 let inp = adapter
                     .adapt(
@@ -193,20 +1184,94 @@ fn run_adapter<'a>(
                             filepath_hint.to_string_lossy(),
                             meta.name
                         )
-                    })?;
-                let inp = RecursingConcattyReader::concat(inp)?;
+                    })
+                    .and_then(|inp| concat_parallel(inp, archive_jobs));
+                let inp = match inp {
+                    Ok(inp) => inp,
+                    Err(e) => {
+                        let environment_error = e
+                            .chain()
+                            .filter_map(|e| e.downcast_ref::<std::io::Error>())
+                            .any(is_environment_error);
+                        if !cache_readonly && !environment_error {
+                            cache_failure(
+                                cache.as_mut(),
+                                &db_name,
+                                &cache_key,
+                                &format!("{:#}", e),
+                            );
+                            link(cache.as_mut(), &db_name, path_key.as_deref(), &cache_key);
+                        }
+                        return Err(e);
+                    }
+                };
+                if cache_readonly {
+                    return Ok(inp);
+                }
                 let inp = CachingReader::new(
                     inp,
                     cache_max_blob_len.0.try_into().unwrap(),
+                    cache_max_chunks.0,
+                    cache_compression,
                     cache_compression_level.0.try_into().unwrap(),
-                    Box::new(move |(uncompressed_size, compressed)| {
+                    Box::new(move |res| {
+                        let (uncompressed_size, compressed) = match res {
+                            Ok(res) => res,
+                            Err(e) => {
+                                if !is_environment_error(e) {
+                                    cache_failure(
+                                        cache.as_mut(),
+                                        &db_name,
+                                        &cache_key,
+                                        &e.to_string(),
+                                    );
+                                    link(cache.as_mut(), &db_name, path_key.as_deref(), &cache_key);
+                                }
+                                return Ok(());
+                            }
+                        };
                         debug!(
                             "uncompressed output: {}",
                             print_bytes(uncompressed_size as f64)
                         );
-                        if let Some(cached) = compressed {
-                            debug!("compressed output: {}", print_bytes(cached.len() as f64));
-                            cache.set(&db_name, &cache_key, &cached)?
+                        if failure_count() != failures_before {
+                            // so they are tried again, e.g. after installing a missing program
+                            debug!("files in it could not be preprocessed, not caching");
+                            return Ok(());
+                        }
+                        if expansion.exceeded() {
+                            // the output is cut off, and the marker is added after the cache
+                            debug!("decompression limit reached, not caching");
+                            return Ok(());
+                        }
+                        let duration = adapt_start.elapsed();
+                        if duration < cache_min_duration {
+                            debug!("adapter took less than --rga-cache-min-duration, not caching");
+                            return Ok(());
+                        }
+                        if let Some(chunks) = compressed {
+                            debug!(
+                                "compressed output: {} in {} chunks",
+                                print_bytes(chunks.iter().map(|c| c.len()).sum::<usize>() as f64),
+                                chunks.len()
+                            );
+                            match set_chunked(
+                                cache.as_mut(),
+                                &db_name,
+                                &cache_key,
+                                &chunks,
+                                duration,
+                            ) {
+                                Ok(()) => {
+                                    link(cache.as_mut(), &db_name, path_key.as_deref(), &cache_key);
+                                    CacheStats {
+                                        bytes_written: chunks.iter().map(|c| c.len() as u64).sum(),
+                                        ..Default::default()
+                                    }
+                                    .record()
+                                }
+                                Err(e) => warn!("could not write to cache: {:#}", e),
+                            }
                         }
                         Ok(())
                     }),
@@ -244,6 +1309,6 @@ fn run_adapter<'a>(
             adapter.metadata().name,
             print_dur(start)
         );
-        Ok(RecursingConcattyReader::concat(oread)?)
+        Ok(concat_parallel(oread, archive_jobs)?)
     }
 }
@@ -1,7 +1,23 @@
-use crate::{config::CacheConfig, print_bytes, print_dur};
+use crate::{
+    config::{CacheBackend, CacheConfig},
+    print_bytes, print_dur,
+};
 use anyhow::{format_err, Context, Result};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use lazy_static::lazy_static;
 use log::*;
-use std::{fmt::Display, path::Path, time::Instant};
+use rusqlite::OptionalExtension;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{Read, Write},
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 pub trait PreprocCache: Send + Sync {
     /*/// gets cache at specified key.
@@ -17,11 +33,249 @@ pub trait PreprocCache: Send + Sync {
 
     fn get(&self, db_name: &str, key: &[u8]) -> Result<Option<Vec<u8>>>;
     fn set(&mut self, db_name: &str, key: &[u8], value: &[u8]) -> Result<()>;
+    /// all entries of the cache
+    fn entries(&self) -> Result<Vec<CacheEntry>>;
+    /// delete all entries of a store (the outputs of one adapter version).
+    /// Returns the number of deleted entries
+    fn clear_db(&self, db_name: &str) -> Result<usize>;
+}
+
+/// opens the cache with the configured backend, None if caching is disabled
+pub fn open_cache(config: &CacheConfig) -> Result<Option<Box<dyn PreprocCache>>> {
+    let local = match config.backend {
+        CacheBackend::Lmdb => {
+            LmdbCache::open(config)?.map(|c| Box::new(c) as Box<dyn PreprocCache>)
+        }
+        CacheBackend::Sqlite => {
+            SqliteCache::open(config)?.map(|c| Box::new(c) as Box<dyn PreprocCache>)
+        }
+    };
+    let cache = match (local, &config.remote) {
+        (Some(local), Some(url)) => Some(Box::new(RemoteCache {
+            local: std::sync::Mutex::new(local),
+            url: url.trim_end_matches('/').to_string(),
+            token: config.remote_token.clone(),
+            failed: AtomicBool::new(false),
+        }) as Box<dyn PreprocCache>),
+        (local, _) => local,
+    };
+    // outermost, so the remote cache only gets encrypted values as well
+    Ok(match (cache, &config.encryption_key) {
+        (Some(inner), Some(key)) => Some(Box::new(EncryptedCache::new(inner, key)?)),
+        (cache, _) => cache,
+    })
+}
+
+/// value stored under the key of outputs split into multiple chunks, followed by the number of chunks (u32 LE).
+/// Can't be confused with a single zstd frame
+const CHUNKED_MAGIC: &[u8] = b"rga-chunked";
+
+fn chunk_key(key: &[u8], i: u32) -> Vec<u8> {
+    let mut chunk_key = key.to_vec();
+    chunk_key.extend_from_slice(b"/chunk");
+    chunk_key.extend_from_slice(&i.to_le_bytes());
+    chunk_key
+}
+
+/// prefix of the value stored under the key of an output, followed by the time the adapter took
+/// to produce it (milliseconds, u64 LE) and the single chunk or the chunked manifest
+const TIMED_MAGIC: &[u8] = b"rga-timed";
+
+/// an adapter output read from the cache
+#[derive(Debug, PartialEq)]
+pub struct CachedOutput {
+    pub chunks: Vec<Vec<u8>>,
+    /// how long the adapter took to produce the output. None for entries written by older versions of rga
+    pub duration: Option<Duration>,
+}
+
+/// stores an output that consists of one or more compressed chunks, and the time the adapter took to produce it.
+/// Single chunks are stored with the timing, multiple chunks as separate entries
+pub fn set_chunked(
+    cache: &mut dyn PreprocCache,
+    db_name: &str,
+    key: &[u8],
+    chunks: &[Vec<u8>],
+    duration: Duration,
+) -> Result<()> {
+    let mut value = TIMED_MAGIC.to_vec();
+    value.extend_from_slice(&(duration.as_millis() as u64).to_le_bytes());
+    if let [chunk] = chunks {
+        value.extend_from_slice(chunk);
+        return cache.set(db_name, key, &value);
+    }
+    // chunks first, so a reader never sees an incomplete output
+    for (i, chunk) in chunks.iter().enumerate() {
+        cache.set(db_name, &chunk_key(key, i as u32), chunk)?;
+    }
+    value.extend_from_slice(CHUNKED_MAGIC);
+    value.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+    cache.set(db_name, key, &value)
+}
+
+/// value of a key that refers to the output stored under another key, followed by that key
+const LINK_MAGIC: &[u8] = b"rga-link";
+
+/// make get_chunked of key return the output stored under target
+pub fn set_link(
+    cache: &mut dyn PreprocCache,
+    db_name: &str,
+    key: &[u8],
+    target: &[u8],
+) -> Result<()> {
+    cache.set(db_name, key, &[LINK_MAGIC, target].concat())
+}
+
+/// reads an output stored with set_chunked, following a link set with set_link.
+/// None if it (or any of its chunks, e.g. after eviction) is missing
+pub fn get_chunked(
+    cache: &dyn PreprocCache,
+    db_name: &str,
+    key: &[u8],
+) -> Result<Option<CachedOutput>> {
+    let mut key = key.to_vec();
+    let mut value = match cache.get(db_name, &key)? {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    if value.starts_with(LINK_MAGIC) {
+        key = value.split_off(LINK_MAGIC.len());
+        value = match cache.get(db_name, &key)? {
+            Some(value) => value,
+            None => {
+                debug!("cache link target missing");
+                return Ok(None);
+            }
+        };
+    }
+    let mut duration = None;
+    if value.starts_with(TIMED_MAGIC) && value.len() >= TIMED_MAGIC.len() + 8 {
+        let mut millis = [0u8; 8];
+        millis.copy_from_slice(&value[TIMED_MAGIC.len()..TIMED_MAGIC.len() + 8]);
+        duration = Some(Duration::from_millis(u64::from_le_bytes(millis)));
+        value.drain(..TIMED_MAGIC.len() + 8);
+    }
+    if !value.starts_with(CHUNKED_MAGIC) || value.len() != CHUNKED_MAGIC.len() + 4 {
+        return Ok(Some(CachedOutput {
+            chunks: vec![value],
+            duration,
+        }));
+    }
+    let mut count = [0u8; 4];
+    count.copy_from_slice(&value[CHUNKED_MAGIC.len()..]);
+    let mut chunks = Vec::new();
+    for i in 0..u32::from_le_bytes(count) {
+        match cache.get(db_name, &chunk_key(&key, i))? {
+            Some(chunk) => chunks.push(chunk),
+            None => {
+                debug!("cache chunk {} missing", i);
+                return Ok(None);
+            }
+        }
+    }
+    Ok(Some(CachedOutput { chunks, duration }))
+}
+
+/// value stored instead of the output if the adapter failed, followed by the time of the failure
+/// (milliseconds since the unix epoch, u64 LE) and the error message
+const FAILURE_MAGIC: &[u8] = b"rga-failed";
+/// failures are retried after this time, in case they were caused by something outside of the file
+const FAILURE_TTL_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// the cache value recording that an adapter failed with the given error
+pub fn failure_entry(message: &str) -> Vec<u8> {
+    let mut value = FAILURE_MAGIC.to_vec();
+    value.extend_from_slice(&now_millis().to_le_bytes());
+    value.extend_from_slice(message.as_bytes());
+    value
+}
+
+/// the error message if the cache value is an unexpired failure_entry
+pub fn cached_failure(value: &[u8]) -> Option<String> {
+    if !value.starts_with(FAILURE_MAGIC) || value.len() < FAILURE_MAGIC.len() + 8 {
+        return None;
+    }
+    let mut time = [0u8; 8];
+    time.copy_from_slice(&value[FAILURE_MAGIC.len()..FAILURE_MAGIC.len() + 8]);
+    if now_millis().saturating_sub(u64::from_le_bytes(time)) > FAILURE_TTL_MS {
+        debug!("cached failure expired");
+        return None;
+    }
+    Some(String::from_utf8_lossy(&value[FAILURE_MAGIC.len() + 8..]).into_owned())
+}
+
+/// start of a file written by export_cache, followed by the format version (u32 LE)
+const EXPORT_MAGIC: &[u8] = b"rga-cache-export";
+const EXPORT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct ExportEntry {
+    db_name: String,
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+/// write all entries of the cache to out, in a format independent of the cache backend.
+/// Returns the number of exported entries
+pub fn export_cache(cache: &dyn PreprocCache, out: &mut dyn Write) -> Result<usize> {
+    out.write_all(EXPORT_MAGIC)?;
+    out.write_all(&EXPORT_VERSION.to_le_bytes())?;
+    let mut count = 0;
+    for entry in cache.entries()? {
+        // the entry may have been evicted since listing the entries
+        if let Some(value) = cache.get(&entry.db_name, &entry.key)? {
+            bincode::serialize_into(
+                &mut *out,
+                &Some(ExportEntry {
+                    db_name: entry.db_name,
+                    key: entry.key,
+                    value,
+                }),
+            )?;
+            count += 1;
+        }
+    }
+    bincode::serialize_into(&mut *out, &None::<ExportEntry>)?;
+    Ok(count)
+}
+
+/// add all entries written by export_cache to the cache, replacing existing entries with the same key.
+/// Returns the number of imported entries
+pub fn import_cache(cache: &mut dyn PreprocCache, inp: &mut dyn Read) -> Result<usize> {
+    let mut header = vec![0u8; EXPORT_MAGIC.len() + 4];
+    inp.read_exact(&mut header)
+        .context("not an rga cache export")?;
+    if !header.starts_with(EXPORT_MAGIC) {
+        return Err(format_err!("not an rga cache export"));
+    }
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&header[EXPORT_MAGIC.len()..]);
+    let version = u32::from_le_bytes(version);
+    if version != EXPORT_VERSION {
+        return Err(format_err!(
+            "unsupported cache export version {} (expected {})",
+            version,
+            EXPORT_VERSION
+        ));
+    }
+    let mut count = 0;
+    while let Some(entry) = bincode::deserialize_from::<_, Option<ExportEntry>>(&mut *inp)
+        .context("could not read cache export")?
+    {
+        cache.set(&entry.db_name, &entry.key, &entry.value)?;
+        count += 1;
+    }
+    Ok(count)
 }
 
 /// opens a LMDB cache
-fn open_cache_db(path: &Path) -> Result<std::sync::Arc<std::sync::RwLock<rkv::Rkv>>> {
-    std::fs::create_dir_all(path)?;
+fn open_cache_db(
+    path: &Path,
+    readonly: bool,
+) -> Result<std::sync::Arc<std::sync::RwLock<rkv::Rkv>>> {
+    if !readonly {
+        std::fs::create_dir_all(path)?;
+    }
 
     rkv::Manager::singleton()
         .write()
@@ -38,13 +292,52 @@ fn open_cache_db(path: &Path) -> Result<std::sync::Arc<std::sync::RwLock<rkv::Rk
                 .set_map_size(2 * 1024 * 1024 * 1024)
                 .set_max_dbs(100)
                 .set_max_readers(128);
+            if readonly {
+                // the lock file can't be created on a read-only filesystem.
+                // Locking is not needed if nobody writes to the cache
+                builder
+                    .set_flags(rkv::EnvironmentFlags::READ_ONLY | rkv::EnvironmentFlags::NO_LOCK);
+            }
             rkv::Rkv::from_env(p, builder)
         })
         .map_err(|e| format_err!("could not get/create cache db: {}", e))
 }
 
+/// store recording the size and last access time of all cache entries, for LRU eviction
+const INDEX_DB_NAME: &str = "rga-cache-index";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct IndexEntry {
+    /// compressed size in bytes
+    size: u64,
+    /// milliseconds since the unix epoch
+    last_access: u64,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// a cache hit only updates the last access time of the entry if it is older than this,
+/// so concurrent readers mostly don't need write transactions
+#[cfg(not(test))]
+const TOUCH_INTERVAL_MS: u64 = 10 * 60 * 1000;
+#[cfg(test)]
+const TOUCH_INTERVAL_MS: u64 = 0;
+
 pub struct LmdbCache {
     db_arc: std::sync::Arc<std::sync::RwLock<rkv::Rkv>>,
+    max_size: Option<u64>,
+    /// the total size of the entries after the last eviction plus what was written since, so the index is only
+    /// read when it may be over max_size
+    total_size: Option<u64>,
+    readonly: bool,
+    /// store handles opened by this process. Opening a store with Options::create() takes
+    /// a write transaction (blocking every other rga process), so it is done at most once per store
+    stores: std::sync::Mutex<HashMap<String, rkv::SingleStore>>,
 }
 
 impl LmdbCache {
@@ -53,10 +346,165 @@ impl LmdbCache {
             return Ok(None);
         }
         let path = Path::new(&config.path.0);
+        if config.readonly && !path.join("data.mdb").exists() {
+            debug!("no cache at {}, running uncached", path.display());
+            return Ok(None);
+        }
         Ok(Some(LmdbCache {
-            db_arc: open_cache_db(&path)?,
+            db_arc: open_cache_db(&path, config.readonly)?,
+            max_size: config.max_size.map(|s| s.0 as u64),
+            total_size: None,
+            readonly: config.readonly,
+            stores: std::sync::Mutex::new(HashMap::new()),
         }))
     }
+
+    /// get a handle to a store. Returns None if the store does not exist and create is false
+    fn store(
+        &self,
+        db_env: &rkv::Rkv,
+        db_name: &str,
+        create: bool,
+    ) -> Result<Option<rkv::SingleStore>> {
+        let mut stores = self
+            .stores
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Could not open lock, some lock writer panicked"))?;
+        if let Some(store) = stores.get(db_name) {
+            return Ok(Some(*store));
+        }
+        // opening an existing store only needs a read transaction
+        let store = match db_env.open_single(db_name, rkv::store::Options::default()) {
+            Ok(store) => store,
+            Err(_) if create => db_env
+                .open_single(db_name, rkv::store::Options::create())
+                .map_err(RkvErrWrap)
+                .context("could not open cache db store")?,
+            Err(e) => {
+                debug!("cache store {} does not exist: {}", db_name, e);
+                return Ok(None);
+            }
+        };
+        stores.insert(db_name.to_string(), store);
+        Ok(Some(store))
+    }
+
+    fn index(&self, db_env: &rkv::Rkv) -> Result<rkv::SingleStore> {
+        Ok(self
+            .store(db_env, INDEX_DB_NAME, true)?
+            .expect("created if missing"))
+    }
+
+    /// record the size and access time of an entry in the index
+    fn touch(&self, db_env: &rkv::Rkv, db_name: &str, key: &[u8], size: u64) -> Result<()> {
+        let index = self.index(db_env)?;
+        let index_key = bincode::serialize(&(db_name, key))?;
+        let entry = bincode::serialize(&IndexEntry {
+            size,
+            last_access: now_millis(),
+        })?;
+        let mut writer = db_env
+            .write()
+            .map_err(RkvErrWrap)
+            .context("could not open write handle to cache")?;
+        index
+            .put(&mut writer, &index_key, &rkv::Value::Blob(&entry))
+            .map_err(RkvErrWrap)
+            .context("could not write cache index")?;
+        writer
+            .commit()
+            .map_err(RkvErrWrap)
+            .context("could not write cache index")?;
+        Ok(())
+    }
+
+    /// delete least recently used entries until the total size is at most max_size. Returns the remaining size
+    fn evict(&self, db_env: &rkv::Rkv, max_size: u64) -> Result<u64> {
+        let index = self.index(db_env)?;
+        let mut entries = read_index(db_env, index)?;
+        let mut total: u64 = entries.iter().map(|e| e.size).sum();
+        if total <= max_size {
+            return Ok(total);
+        }
+        entries.sort_by_key(|e| e.last_access);
+        let mut evict = Vec::new();
+        for entry in entries {
+            if total <= max_size {
+                break;
+            }
+            total -= entry.size;
+            evict.push((entry.db_name, entry.key));
+        }
+        // stores have to be opened before starting the write transaction
+        let mut stores = HashMap::new();
+        for (db_name, _) in &evict {
+            if !stores.contains_key(db_name) {
+                if let Some(db) = self.store(db_env, db_name, false)? {
+                    stores.insert(db_name.clone(), db);
+                }
+            }
+        }
+        let mut writer = db_env
+            .write()
+            .map_err(RkvErrWrap)
+            .context("could not open write handle to cache")?;
+        for (db_name, key) in &evict {
+            // the entry may already be gone, e.g. if the cache was partially deleted
+            if let Some(Err(e)) = stores.get(db_name).map(|db| db.delete(&mut writer, key)) {
+                debug!("could not delete cache entry: {}", e);
+            }
+            index
+                .delete(&mut writer, &bincode::serialize(&(db_name, key))?)
+                .map_err(RkvErrWrap)
+                .context("could not delete from cache index")?;
+        }
+        writer
+            .commit()
+            .map_err(RkvErrWrap)
+            .context("could not write cache")?;
+        debug!(
+            "evicted {} cache entries, {} remaining",
+            evict.len(),
+            print_bytes(total as f64)
+        );
+        Ok(total)
+    }
+
+    fn db_env(&self) -> Result<std::sync::RwLockReadGuard<rkv::Rkv>> {
+        self.db_arc
+            .read()
+            .map_err(|_| anyhow::anyhow!("Could not open lock, some lock writer panicked"))
+    }
+}
+
+/// a cached adapter output
+pub struct CacheEntry {
+    /// name of the store, "{adapter}.v{version}"
+    pub db_name: String,
+    pub key: Vec<u8>,
+    /// compressed size in bytes
+    pub size: u64,
+    /// milliseconds since the unix epoch
+    pub last_access: u64,
+}
+
+fn read_index(db_env: &rkv::Rkv, index: rkv::SingleStore) -> Result<Vec<CacheEntry>> {
+    let reader = db_env.read().map_err(RkvErrWrap)?;
+    let mut entries = Vec::new();
+    for item in index.iter_start(&reader).map_err(RkvErrWrap)? {
+        let (index_key, value) = item.map_err(RkvErrWrap)?;
+        if let Some(rkv::Value::Blob(value)) = value {
+            let (db_name, key): (String, Vec<u8>) = bincode::deserialize(index_key)?;
+            let IndexEntry { size, last_access } = bincode::deserialize(value)?;
+            entries.push(CacheEntry {
+                db_name,
+                key,
+                size,
+                last_access,
+            });
+        }
+    }
+    Ok(entries)
 }
 
 #[derive(Debug)]
@@ -75,12 +523,16 @@ impl PreprocCache for LmdbCache {
             .db_arc
             .read()
             .map_err(|_| anyhow::anyhow!("Could not open lock, some lock writer panicked"))?;
-        let db = db_env
-            .open_single(db_name, rkv::store::Options::create())
-            .map_err(RkvErrWrap)
-            .context("could not open cache db store")?;
+        let db = match self.store(&db_env, db_name, false)? {
+            Some(db) => db,
+            None => return Ok(None),
+        };
+        let index = self.store(&db_env, INDEX_DB_NAME, false)?;
 
-        let reader = db_env.read().expect("could not get reader");
+        let reader = db_env
+            .read()
+            .map_err(RkvErrWrap)
+            .context("could not open cache reader")?;
         let cached = db
             .get(&reader, &key)
             .map_err(RkvErrWrap)
@@ -92,14 +544,38 @@ impl PreprocCache for LmdbCache {
                     "cache HIT, reading {} (compressed) from cache",
                     print_bytes(cached.len() as f64)
                 );
+                let cached = Vec::from(cached);
+                let last_access = match index {
+                    Some(index) => match index
+                        .get(&reader, &bincode::serialize(&(db_name, key))?)
+                        .map_err(RkvErrWrap)?
+                    {
+                        Some(rkv::Value::Blob(entry)) => {
+                            Some(bincode::deserialize::<IndexEntry>(entry)?.last_access)
+                        }
+                        _ => None,
+                    },
+                    None => None,
+                };
+                drop(reader);
+                if !self.readonly
+                    && last_access
+                        .map_or(true, |t| now_millis().saturating_sub(t) > TOUCH_INTERVAL_MS)
+                {
+                    self.touch(&db_env, db_name, key, cached.len() as u64)?;
+                }
                 debug!("reading from cache took {}", print_dur(start));
-                Ok(Some(Vec::from(cached)))
+                Ok(Some(cached))
             }
             Some(_) => Err(format_err!("Integrity: value not blob"))?,
             None => Ok(None),
         }
     }
     fn set(&mut self, db_name: &str, key: &[u8], got: &[u8]) -> Result<()> {
+        if self.readonly {
+            debug!("cache is read-only, not writing");
+            return Ok(());
+        }
         let start = Instant::now();
         debug!("writing {} to cache", print_bytes(got.len() as f64));
         let db_env = self
@@ -107,11 +583,16 @@ impl PreprocCache for LmdbCache {
             .read()
             .map_err(|_| anyhow::anyhow!("Could not open lock, some lock writer panicked"))?;
 
-        let db = db_env
-            .open_single(db_name, rkv::store::Options::create())
-            .map_err(RkvErrWrap)
-            .context("could not open cache db store")?;
+        let db = self
+            .store(&db_env, db_name, true)?
+            .expect("created if missing");
+        let index = self.index(&db_env)?;
+        let index_entry = bincode::serialize(&IndexEntry {
+            size: got.len() as u64,
+            last_access: now_millis(),
+        })?;
 
+        // value and index entry in one short write transaction
         let mut writer = db_env
             .write()
             .map_err(RkvErrWrap)
@@ -120,11 +601,782 @@ impl PreprocCache for LmdbCache {
         db.put(&mut writer, &key, &rkv::Value::Blob(&got))
             .map_err(RkvErrWrap)
             .with_context(|| format_err!("could not write to cache"))?;
+        index
+            .put(
+                &mut writer,
+                &bincode::serialize(&(db_name, key))?,
+                &rkv::Value::Blob(&index_entry),
+            )
+            .map_err(RkvErrWrap)
+            .context("could not write cache index")?;
         writer
             .commit()
             .map_err(RkvErrWrap)
             .with_context(|| format!("could not write cache"))?;
+        if let Some(max_size) = self.max_size {
+            // the writes of other processes are only counted when the index is read again
+            self.total_size = Some(match self.total_size {
+                Some(total) if total + got.len() as u64 <= max_size => total + got.len() as u64,
+                _ => self.evict(&db_env, max_size)?,
+            });
+        }
+        debug!("writing to cache took {}", print_dur(start));
+        Ok(())
+    }
+
+    /// Entries written by versions of rga before the index was added are not included
+    fn entries(&self) -> Result<Vec<CacheEntry>> {
+        let db_env = self.db_env()?;
+        match self.store(&db_env, INDEX_DB_NAME, false)? {
+            Some(index) => read_index(&db_env, index),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn clear_db(&self, db_name: &str) -> Result<usize> {
+        if self.readonly {
+            return Err(format_err!("cache is read-only"));
+        }
+        let db_env = self.db_env()?;
+        let db = match self.store(&db_env, db_name, false)? {
+            Some(db) => db,
+            None => return Ok(0),
+        };
+        let index = self.index(&db_env)?;
+        let entries: Vec<CacheEntry> = read_index(&db_env, index)?
+            .into_iter()
+            .filter(|e| e.db_name == db_name)
+            .collect();
+        let mut writer = db_env
+            .write()
+            .map_err(RkvErrWrap)
+            .context("could not open write handle to cache")?;
+        db.clear(&mut writer)
+            .map_err(RkvErrWrap)
+            .context("could not clear cache store")?;
+        for entry in &entries {
+            index
+                .delete(&mut writer, &bincode::serialize(&(db_name, &entry.key))?)
+                .map_err(RkvErrWrap)
+                .context("could not delete from cache index")?;
+        }
+        writer
+            .commit()
+            .map_err(RkvErrWrap)
+            .context("could not write cache")?;
+        Ok(entries.len())
+    }
+}
+
+/// a cache in a single SQLite file
+pub struct SqliteCache {
+    conn: std::sync::Mutex<rusqlite::Connection>,
+    max_size: Option<u64>,
+    /// see LmdbCache::total_size
+    total_size: Option<u64>,
+    readonly: bool,
+}
+
+impl SqliteCache {
+    pub fn open(config: &CacheConfig) -> Result<Option<SqliteCache>> {
+        if config.disabled {
+            return Ok(None);
+        }
+        let path = Path::new(&config.path.0);
+        if config.readonly {
+            return SqliteCache::open_readonly(&path.join("cache.sqlite3"), config);
+        }
+        std::fs::create_dir_all(path)?;
+        let conn = rusqlite::Connection::open(path.join("cache.sqlite3"))
+            .context("could not open cache db")?;
+        // many rga-preproc processes access the cache at the same time
+        conn.busy_timeout(std::time::Duration::from_secs(30))?;
+        // not durable cuz it's a cache
+        conn.execute_batch(
+            "pragma journal_mode = wal;
+            pragma synchronous = off;
+            create table if not exists cache (
+                db_name text not null,
+                key blob not null,
+                value blob not null,
+                last_access integer not null,
+                primary key (db_name, key)
+            );
+            create index if not exists cache_last_access on cache (last_access);",
+        )
+        .context("could not create cache table")?;
+        Ok(Some(SqliteCache {
+            conn: std::sync::Mutex::new(conn),
+            max_size: config.max_size.map(|s| s.0 as u64),
+            total_size: None,
+            readonly: false,
+        }))
+    }
+
+    fn open_readonly(file: &Path, config: &CacheConfig) -> Result<Option<SqliteCache>> {
+        if !file.exists() {
+            debug!("no cache at {}, running uncached", file.display());
+            return Ok(None);
+        }
+        // immutable: no locking and no -shm file, which can't be created on a read-only filesystem
+        let uri = format!(
+            "file:{}?immutable=1",
+            file.to_string_lossy()
+                .replace('%', "%25")
+                .replace('?', "%3f")
+                .replace('#', "%23")
+        );
+        let conn = rusqlite::Connection::open_with_flags(
+            uri,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_URI,
+        )
+        .context("could not open cache db")?;
+        Ok(Some(SqliteCache {
+            conn: std::sync::Mutex::new(conn),
+            max_size: config.max_size.map(|s| s.0 as u64),
+            total_size: None,
+            readonly: true,
+        }))
+    }
+
+    fn conn(&self) -> Result<std::sync::MutexGuard<rusqlite::Connection>> {
+        self.conn
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Could not open lock, some lock writer panicked"))
+    }
+
+    /// delete least recently used entries until the total size is at most max_size. Returns the remaining size
+    fn evict(conn: &rusqlite::Connection, max_size: u64) -> Result<u64> {
+        let mut total: i64 = conn.query_row(
+            "select coalesce(sum(length(value)), 0) from cache",
+            rusqlite::NO_PARAMS,
+            |r| r.get(0),
+        )?;
+        if total as u64 <= max_size {
+            return Ok(total as u64);
+        }
+        let mut evict = Vec::new();
+        {
+            let mut stmt =
+                conn.prepare("select db_name, key, length(value) from cache order by last_access")?;
+            let mut rows = stmt.query(rusqlite::NO_PARAMS)?;
+            while let Some(row) = rows.next()? {
+                if total as u64 <= max_size {
+                    break;
+                }
+                let (db_name, key, size): (String, Vec<u8>, i64) =
+                    (row.get(0)?, row.get(1)?, row.get(2)?);
+                total -= size;
+                evict.push((db_name, key));
+            }
+        }
+        for (db_name, key) in &evict {
+            conn.execute(
+                "delete from cache where db_name = ? and key = ?",
+                rusqlite::params![db_name, key],
+            )?;
+        }
+        debug!(
+            "evicted {} cache entries, {} remaining",
+            evict.len(),
+            print_bytes(total as f64)
+        );
+        Ok(total as u64)
+    }
+}
+
+impl PreprocCache for SqliteCache {
+    fn get(&self, db_name: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let start = Instant::now();
+        let conn = self.conn()?;
+        let cached: Option<(Vec<u8>, i64)> = conn
+            .query_row(
+                "select value, last_access from cache where db_name = ? and key = ?",
+                rusqlite::params![db_name, key],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .optional()
+            .context("could not read from db")?;
+        Ok(match cached {
+            Some((cached, last_access)) => {
+                debug!(
+                    "cache HIT, reading {} (compressed) from cache",
+                    print_bytes(cached.len() as f64)
+                );
+                let now = now_millis();
+                if !self.readonly && now.saturating_sub(last_access as u64) > TOUCH_INTERVAL_MS {
+                    conn.execute(
+                        "update cache set last_access = ? where db_name = ? and key = ?",
+                        rusqlite::params![now as i64, db_name, key],
+                    )?;
+                }
+                debug!("reading from cache took {}", print_dur(start));
+                Some(cached)
+            }
+            None => None,
+        })
+    }
+
+    fn set(&mut self, db_name: &str, key: &[u8], got: &[u8]) -> Result<()> {
+        if self.readonly {
+            debug!("cache is read-only, not writing");
+            return Ok(());
+        }
+        let start = Instant::now();
+        debug!("writing {} to cache", print_bytes(got.len() as f64));
+        let conn = self.conn()?;
+        conn.execute(
+            "insert or replace into cache (db_name, key, value, last_access) values (?, ?, ?, ?)",
+            rusqlite::params![db_name, key, got, now_millis() as i64],
+        )
+        .context("could not write to cache")?;
+        if let Some(max_size) = self.max_size {
+            let total = match self.total_size {
+                Some(total) if total + got.len() as u64 <= max_size => total + got.len() as u64,
+                _ => SqliteCache::evict(&conn, max_size)?,
+            };
+            drop(conn);
+            self.total_size = Some(total);
+        }
         debug!("writing to cache took {}", print_dur(start));
         Ok(())
     }
+
+    fn entries(&self) -> Result<Vec<CacheEntry>> {
+        let conn = self.conn()?;
+        let mut stmt =
+            conn.prepare("select db_name, key, length(value), last_access from cache")?;
+        let entries = stmt
+            .query_map(rusqlite::NO_PARAMS, |r| {
+                Ok(CacheEntry {
+                    db_name: r.get(0)?,
+                    key: r.get(1)?,
+                    size: r.get::<_, i64>(2)? as u64,
+                    last_access: r.get::<_, i64>(3)? as u64,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+
+    fn clear_db(&self, db_name: &str) -> Result<usize> {
+        if self.readonly {
+            return Err(format_err!("cache is read-only"));
+        }
+        Ok(self.conn()?.execute(
+            "delete from cache where db_name = ?",
+            rusqlite::params![db_name],
+        )?)
+    }
+}
+
+/// a shared cache on an HTTP server in front of the local cache
+pub struct RemoteCache {
+    local: std::sync::Mutex<Box<dyn PreprocCache>>,
+    url: String,
+    token: Option<String>,
+    /// set after the first failed request, the remote cache is not used anymore after that
+    failed: AtomicBool,
+}
+
+impl RemoteCache {
+    fn request(&self, method: &str, db_name: &str, key: &[u8]) -> ureq::Request {
+        let hash: String = Sha256::digest(key)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let mut req = ureq::request(method, &format!("{}/{}/{}", self.url, db_name, hash));
+        req.timeout(Duration::from_secs(10));
+        if let Some(token) = &self.token {
+            req.set("Authorization", &format!("Bearer {}", token));
+        }
+        req
+    }
+
+    fn local(&self) -> Result<std::sync::MutexGuard<Box<dyn PreprocCache>>> {
+        self.local
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Could not open lock, some lock writer panicked"))
+    }
+
+    /// log the first failure and continue with the local cache only
+    fn fail(&self, e: anyhow::Error) {
+        if !self.failed.swap(true, Ordering::Relaxed) {
+            warn!(
+                "remote cache {} failed, only using the local cache: {:#}",
+                self.url, e
+            );
+        }
+    }
+
+    fn remote_get(&self, db_name: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let resp = self.request("GET", db_name, key).call();
+        if resp.status() == 404 {
+            return Ok(None);
+        }
+        if let Some(e) = resp.synthetic_error() {
+            return Err(format_err!("{}", e));
+        }
+        if !resp.ok() {
+            return Err(format_err!("GET returned {}", resp.status_line()));
+        }
+        let mut cached = Vec::new();
+        resp.into_reader().read_to_end(&mut cached)?;
+        Ok(Some(cached))
+    }
+
+    fn remote_set(&self, db_name: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        let resp = self.request("PUT", db_name, key).send_bytes(value);
+        if let Some(e) = resp.synthetic_error() {
+            return Err(format_err!("{}", e));
+        }
+        if !resp.ok() {
+            return Err(format_err!("PUT returned {}", resp.status_line()));
+        }
+        Ok(())
+    }
+}
+
+impl PreprocCache for RemoteCache {
+    fn get(&self, db_name: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(cached) = self.local()?.get(db_name, key)? {
+            return Ok(Some(cached));
+        }
+        if self.failed.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let start = Instant::now();
+        match self.remote_get(db_name, key) {
+            Ok(Some(cached)) => {
+                debug!(
+                    "remote cache HIT, read {} in {}",
+                    print_bytes(cached.len() as f64),
+                    print_dur(start)
+                );
+                self.local()?.set(db_name, key, &cached)?;
+                Ok(Some(cached))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => {
+                self.fail(e);
+                Ok(None)
+            }
+        }
+    }
+
+    fn set(&mut self, db_name: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.local()?.set(db_name, key, value)?;
+        if !self.failed.load(Ordering::Relaxed) {
+            if let Err(e) = self.remote_set(db_name, key, value) {
+                self.fail(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// only the entries of the local cache
+    fn entries(&self) -> Result<Vec<CacheEntry>> {
+        self.local()?.entries()
+    }
+
+    /// only clears the local cache
+    fn clear_db(&self, db_name: &str) -> Result<usize> {
+        self.local()?.clear_db(db_name)
+    }
+}
+
+/// prefix of encrypted values, followed by the nonce and the ciphertext
+const ENCRYPTED_MAGIC: &[u8] = b"rga-encrypted";
+const NONCE_LEN: usize = 24;
+
+/// the salt of the key derivation, stored (unencrypted) in the cache itself, so all rga processes using the cache
+/// (also on other machines, with --rga-cache-remote) derive the same key from the password
+const SALT_DB: &str = "rga-encryption";
+const SALT_KEY: &[u8] = b"salt";
+const SALT_LEN: usize = 16;
+
+/// scrypt cost parameter (log2 of N), the recommended value for interactive logins
+#[cfg(not(test))]
+const SCRYPT_LOG_N: u8 = 15;
+#[cfg(test)]
+const SCRYPT_LOG_N: u8 = 8;
+
+lazy_static! {
+    /// the keys derived from (password, salt). Deriving a key is slow on purpose, and the cache is opened for every file
+    static ref DERIVED_KEYS: std::sync::Mutex<HashMap<(String, Vec<u8>), chacha20poly1305::Key>> =
+        std::sync::Mutex::new(HashMap::new());
+}
+
+/// the encryption key for the password, derived with scrypt
+fn derive_key(password: &str, salt: &[u8]) -> Result<chacha20poly1305::Key> {
+    let mut keys = DERIVED_KEYS.lock().unwrap();
+    let id = (password.to_string(), salt.to_vec());
+    if let Some(key) = keys.get(&id) {
+        return Ok(key.clone());
+    }
+    let params = scrypt::Params::new(SCRYPT_LOG_N, 8, 1)
+        .map_err(|_| format_err!("invalid scrypt parameters"))?;
+    let mut key = chacha20poly1305::Key::default();
+    scrypt::scrypt(password.as_bytes(), salt, &params, &mut key)
+        .map_err(|_| format_err!("could not derive the cache encryption key"))?;
+    keys.insert(id, key.clone());
+    Ok(key)
+}
+
+/// encrypts the values of another cache with XChaCha20-Poly1305. Keys are not encrypted
+pub struct EncryptedCache {
+    inner: Box<dyn PreprocCache>,
+    cipher: XChaCha20Poly1305,
+}
+
+impl EncryptedCache {
+    /// the key is derived from the password and the salt stored in the cache, which is created if it's missing
+    pub fn new(mut inner: Box<dyn PreprocCache>, password: &str) -> Result<EncryptedCache> {
+        let salt = match inner.get(SALT_DB, SALT_KEY)? {
+            Some(salt) => salt,
+            None => {
+                let mut salt = vec![0u8; SALT_LEN];
+                getrandom::getrandom(&mut salt).context("could not generate salt")?;
+                inner.set(SALT_DB, SALT_KEY, &salt)?;
+                salt
+            }
+        };
+        Ok(EncryptedCache {
+            cipher: XChaCha20Poly1305::new(&derive_key(password, &salt)?),
+            inner,
+        })
+    }
+
+    fn encrypt(&self, value: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).context("could not generate nonce")?;
+        let ciphertext = self
+            .cipher
+            .encrypt(XNonce::from_slice(&nonce), value)
+            .map_err(|_| format_err!("could not encrypt cache entry"))?;
+        Ok([ENCRYPTED_MAGIC, &nonce, &ciphertext].concat())
+    }
+
+    /// None if the value is not encrypted or was encrypted with a different key
+    fn decrypt(&self, value: &[u8]) -> Option<Vec<u8>> {
+        if !value.starts_with(ENCRYPTED_MAGIC) || value.len() < ENCRYPTED_MAGIC.len() + NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = value[ENCRYPTED_MAGIC.len()..].split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+}
+
+impl PreprocCache for EncryptedCache {
+    fn get(&self, db_name: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(match self.inner.get(db_name, key)? {
+            Some(value) => {
+                let value = self.decrypt(&value);
+                if value.is_none() {
+                    // recomputed and overwritten
+                    debug!("could not decrypt cache entry, treating as miss");
+                }
+                value
+            }
+            None => None,
+        })
+    }
+
+    fn set(&mut self, db_name: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        let value = self.encrypt(value)?;
+        self.inner.set(db_name, key, &value)
+    }
+
+    fn entries(&self) -> Result<Vec<CacheEntry>> {
+        self.inner.entries()
+    }
+
+    fn clear_db(&self, db_name: &str) -> Result<usize> {
+        self.inner.clear_db(db_name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::{CacheMaxSize, CachePath};
+    use std::{thread::sleep, time::Duration};
+
+    fn open_test_cache(
+        dir: &Path,
+        backend: CacheBackend,
+        max_size: Option<CacheMaxSize>,
+    ) -> Result<Box<dyn PreprocCache>> {
+        Ok(open_cache(&CacheConfig {
+            path: CachePath(dir.to_string_lossy().into_owned()),
+            backend,
+            max_size,
+            ..Default::default()
+        })?
+        .expect("cache is enabled"))
+    }
+
+    #[test]
+    fn lru_eviction() -> Result<()> {
+        for backend in &[CacheBackend::Lmdb, CacheBackend::Sqlite] {
+            let dir = tempfile::tempdir()?;
+            let mut cache = open_test_cache(dir.path(), *backend, Some(CacheMaxSize(250)))?;
+            cache.set("test.v1", b"a", &[1; 100])?;
+            sleep(Duration::from_millis(5));
+            cache.set("test.v1", b"b", &[2; 100])?;
+            sleep(Duration::from_millis(5));
+            assert!(cache.get("test.v1", b"a")?.is_some());
+            sleep(Duration::from_millis(5));
+            cache.set("test.v1", b"c", &[3; 100])?;
+
+            // b was used least recently
+            assert_eq!(cache.get("test.v1", b"b")?, None);
+            assert_eq!(cache.get("test.v1", b"a")?, Some(vec![1; 100]));
+            assert_eq!(cache.get("test.v1", b"c")?, Some(vec![3; 100]));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn clear_db() -> Result<()> {
+        for backend in &[CacheBackend::Lmdb, CacheBackend::Sqlite] {
+            let dir = tempfile::tempdir()?;
+            let mut cache = open_test_cache(dir.path(), *backend, None)?;
+            cache.set("pdf.v1", b"a", b"pdf text")?;
+            cache.set("zip.v3", b"b", b"zip text")?;
+
+            assert_eq!(cache.clear_db("pdf.v1")?, 1);
+            assert_eq!(cache.clear_db("pdf.v2")?, 0);
+            assert_eq!(cache.get("pdf.v1", b"a")?, None);
+            let entries = cache.entries()?;
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].db_name, "zip.v3");
+            assert_eq!(entries[0].size, 8);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_access() -> Result<()> {
+        for backend in &[CacheBackend::Lmdb, CacheBackend::Sqlite] {
+            let dir = tempfile::tempdir()?;
+            let threads: Vec<_> = (0..8u8)
+                .map(|t| {
+                    let dir = dir.path().to_owned();
+                    let backend = *backend;
+                    std::thread::spawn(move || -> Result<()> {
+                        // separate cache handles, like separate rga-preproc processes
+                        let mut cache = open_test_cache(&dir, backend, None)?;
+                        for i in 0..20u8 {
+                            cache.set(&format!("test.v{}", i % 3), &[t, i], &[t; 100])?;
+                            assert_eq!(
+                                cache.get(&format!("test.v{}", i % 3), &[t, i])?,
+                                Some(vec![t; 100])
+                            );
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().expect("thread panicked")?;
+            }
+            let cache = open_test_cache(dir.path(), *backend, None)?;
+            assert_eq!(cache.entries()?.len(), 8 * 20);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn readonly() -> Result<()> {
+        for backend in &[CacheBackend::Lmdb, CacheBackend::Sqlite] {
+            let dir = tempfile::tempdir()?;
+            let config = CacheConfig {
+                path: CachePath(dir.path().to_string_lossy().into_owned()),
+                backend: *backend,
+                readonly: true,
+                ..Default::default()
+            };
+            // no prebuilt cache
+            assert!(open_cache(&config)?.is_none());
+            assert!(!dir.path().join("cache.sqlite3").exists());
+
+            open_test_cache(dir.path(), *backend, None)?.set("pdf.v1", b"a", b"pdf text")?;
+            let mut cache = open_cache(&config)?.expect("cache exists");
+            assert_eq!(cache.get("pdf.v1", b"a")?, Some(b"pdf text".to_vec()));
+            cache.set("pdf.v1", b"b", b"other text")?;
+            assert_eq!(cache.get("pdf.v1", b"b")?, None);
+            assert!(cache.clear_db("pdf.v1").is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn link() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut cache = open_test_cache(dir.path(), CacheBackend::Lmdb, None)?;
+        let chunks = vec![
+            zstd::encode_all(&b"first "[..], 3)?,
+            zstd::encode_all(&b"second"[..], 3)?,
+        ];
+        set_chunked(
+            cache.as_mut(),
+            "pdf.v1",
+            b"content",
+            &chunks,
+            Duration::from_millis(10),
+        )?;
+        set_link(cache.as_mut(), "pdf.v1", b"path a", b"content")?;
+        set_link(cache.as_mut(), "pdf.v1", b"path b", b"content")?;
+        for key in &[&b"path a"[..], b"path b"] {
+            assert_eq!(
+                get_chunked(cache.as_ref(), "pdf.v1", key)?.map(|o| o.chunks),
+                Some(chunks.clone())
+            );
+        }
+        cache.clear_db("pdf.v1")?;
+        set_link(cache.as_mut(), "pdf.v1", b"path a", b"content")?;
+        assert_eq!(get_chunked(cache.as_ref(), "pdf.v1", b"path a")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn failure() {
+        assert_eq!(
+            cached_failure(&failure_entry("pandoc: exit code 1")),
+            Some("pandoc: exit code 1".to_string())
+        );
+        let mut expired = FAILURE_MAGIC.to_vec();
+        expired.extend_from_slice(&0u64.to_le_bytes());
+        expired.extend_from_slice(b"old");
+        assert_eq!(cached_failure(&expired), None);
+        assert_eq!(cached_failure(b"rga\0some output"), None);
+    }
+
+    #[test]
+    fn export_import() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut cache = open_test_cache(dir.path(), CacheBackend::Lmdb, None)?;
+        cache.set("pdf.v1", b"a", b"pdf text")?;
+        cache.set("zip.v3", b"b", b"zip text")?;
+        let mut exported = Vec::new();
+        assert_eq!(export_cache(cache.as_ref(), &mut exported)?, 2);
+
+        let dir = tempfile::tempdir()?;
+        let mut cache = open_test_cache(dir.path(), CacheBackend::Sqlite, None)?;
+        assert_eq!(import_cache(cache.as_mut(), &mut &exported[..])?, 2);
+        assert_eq!(cache.get("pdf.v1", b"a")?, Some(b"pdf text".to_vec()));
+        assert_eq!(cache.get("zip.v3", b"b")?, Some(b"zip text".to_vec()));
+
+        assert!(import_cache(cache.as_mut(), &mut &b"garbage"[..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn encrypted() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = CacheConfig {
+            path: CachePath(dir.path().to_string_lossy().into_owned()),
+            encryption_key: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let mut cache = open_cache(&config)?.expect("cache is enabled");
+        cache.set("pdf.v1", b"a", b"confidential text")?;
+        assert_eq!(
+            cache.get("pdf.v1", b"a")?,
+            Some(b"confidential text".to_vec())
+        );
+
+        let plain = open_test_cache(dir.path(), CacheBackend::Lmdb, None)?;
+        assert_eq!(
+            plain.get(SALT_DB, SALT_KEY)?.map(|s| s.len()),
+            Some(SALT_LEN)
+        );
+        let stored = plain.get("pdf.v1", b"a")?.expect("entry exists");
+        assert!(stored.starts_with(ENCRYPTED_MAGIC));
+        assert!(!stored
+            .windows(b"confidential".len())
+            .any(|w| w == b"confidential"));
+
+        let wrong_key = open_cache(&CacheConfig {
+            encryption_key: Some("wrong".to_string()),
+            ..config
+        })?
+        .expect("cache is enabled");
+        assert_eq!(wrong_key.get("pdf.v1", b"a")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn unreachable_remote() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut cache = open_cache(&CacheConfig {
+            path: CachePath(dir.path().to_string_lossy().into_owned()),
+            remote: Some("http://127.0.0.1:9/rga/".to_string()),
+            ..Default::default()
+        })?
+        .expect("cache is enabled");
+        assert_eq!(cache.get("pdf.v1", b"a")?, None);
+        cache.set("pdf.v1", b"a", b"pdf text")?;
+        assert_eq!(cache.get("pdf.v1", b"a")?, Some(b"pdf text".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn chunked() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut cache = open_test_cache(dir.path(), CacheBackend::Lmdb, None)?;
+        let chunks = vec![
+            zstd::encode_all(&b"first "[..], 3)?,
+            zstd::encode_all(&b"second"[..], 3)?,
+        ];
+        set_chunked(
+            cache.as_mut(),
+            "pdf.v1",
+            b"a",
+            &chunks,
+            Duration::from_millis(1500),
+        )?;
+        assert_eq!(
+            get_chunked(cache.as_ref(), "pdf.v1", b"a")?,
+            Some(CachedOutput {
+                chunks: chunks.clone(),
+                duration: Some(Duration::from_millis(1500))
+            })
+        );
+        set_chunked(
+            cache.as_mut(),
+            "pdf.v1",
+            b"b",
+            &chunks[..1],
+            Duration::from_millis(20),
+        )?;
+        assert_eq!(
+            get_chunked(cache.as_ref(), "pdf.v1", b"b")?,
+            Some(CachedOutput {
+                chunks: chunks[..1].to_vec(),
+                duration: Some(Duration::from_millis(20))
+            })
+        );
+        // written by older versions
+        cache.set("pdf.v1", b"c", &chunks[0])?;
+        assert_eq!(
+            get_chunked(cache.as_ref(), "pdf.v1", b"c")?,
+            Some(CachedOutput {
+                chunks: chunks[..1].to_vec(),
+                duration: None
+            })
+        );
+
+        cache.clear_db("pdf.v1")?;
+        cache.set(
+            "pdf.v1",
+            b"a",
+            &[CHUNKED_MAGIC, &2u32.to_le_bytes()].concat(),
+        )?;
+        assert_eq!(get_chunked(cache.as_ref(), "pdf.v1", b"a")?, None);
+        Ok(())
+    }
 }
@@ -0,0 +1,89 @@
+//! `rga-preproc --pretty`: the adapted output of a file for reading (e.g. in preview panes),
+//! with a header for every file in archives and a separator for every page instead of the line prefixes.
+
+use crate::adapters::postproc::TextRecord;
+use anyhow::Result;
+use std::io::{BufRead, BufReader, Read, Write};
+
+const BOLD: &str = "\x1b[1m";
+const CYAN: &str = "\x1b[36m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+struct Styler {
+    color: bool,
+}
+
+impl Styler {
+    fn line(&self, oup: &mut dyn Write, style: &str, text: &str) -> std::io::Result<()> {
+        if self.color {
+            writeln!(oup, "{}{}{}", style, text, RESET)
+        } else {
+            writeln!(oup, "{}", text)
+        }
+    }
+}
+
+/// render the json records of rga-preproc (see --format json), which have no source path
+pub fn render_pretty(
+    source_path: &str,
+    inp: &mut dyn Read,
+    oup: &mut dyn Write,
+    color: bool,
+) -> Result<()> {
+    let styler = Styler { color };
+    styler.line(oup, BOLD, source_path)?;
+    styler.line(oup, DIM, &"─".repeat(source_path.chars().count().max(3)))?;
+    let mut subfile_path = None;
+    let mut page = None;
+    for (i, line) in BufReader::new(inp).split(b'\n').enumerate() {
+        let record = TextRecord::from_line(&line?, i as u64 + 1);
+        if let Some(detection) = &record.detection {
+            styler.line(oup, DIM, &format!("rga: {}", detection))?;
+            continue;
+        }
+        if record.subfile_path != subfile_path {
+            subfile_path = record.subfile_path;
+            page = None;
+            if let Some(path) = &subfile_path {
+                writeln!(oup)?;
+                styler.line(oup, CYAN, &format!("── {} ──", path))?;
+            }
+        }
+        if record.page != page {
+            page = record.page;
+            if let Some(page) = page {
+                styler.line(oup, DIM, &format!("── page {} ──", page))?;
+            }
+        }
+        writeln!(oup, "{}", record.text)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pretty() -> Result<()> {
+        let records = concat!(
+            r#"{"subfile_path":null,"page":null,"line":1,"text":"readme"}"#,
+            "\n",
+            r#"{"subfile_path":"a.pdf","page":1,"line":1,"text":"first"}"#,
+            "\n",
+            r#"{"subfile_path":"a.pdf","page":1,"line":2,"text":"second"}"#,
+            "\n",
+            r#"{"subfile_path":"a.pdf","page":2,"line":3,"text":"third"}"#,
+            "\n",
+            "[rga: truncated at archive depth 5 (--rga-max-archive-recursion), rerun with --rga-no-limits]\n"
+        );
+        let mut oup = Vec::new();
+        render_pretty("x.zip", &mut records.as_bytes(), &mut oup, false)?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "x.zip\n─────\nreadme\n\n── a.pdf ──\n── page 1 ──\nfirst\nsecond\n── page 2 ──\nthird\n[rga: truncated at archive depth 5 (--rga-max-archive-recursion), rerun with --rga-no-limits]\n"
+        );
+        Ok(())
+    }
+}
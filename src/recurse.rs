@@ -1,8 +1,17 @@
-use crate::preproc::rga_preproc;
-use crate::{adapted_iter::AdaptedFilesIterBox, adapters::*};
+use crate::failures::preproc_or_error_line;
+use crate::{
+    adapted_iter::AdaptedFilesIterBox,
+    adapters::*,
+    config::{ArchiveJobs, RgaConfig},
+    spill::SpillBuffer,
+};
 
 use anyhow::*;
-use std::io::Read;
+use crossbeam_channel::{Receiver, Sender};
+use log::*;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
 
 pub struct RecursingConcattyReader<'a> {
     inp: AdaptedFilesIterBox<'a>,
@@ -22,10 +31,7 @@ impl<'a> RecursingConcattyReader<'a> {
             // would love to make this safe, but how? something like OwnedRef<inp, cur>
             (*(inp as *mut AdaptedFilesIterBox<'a>)).next()
         };
-        self.cur = match ai {
-            Some(ai) => Some(rga_preproc(ai)?),
-            None => None,
-        };
+        self.cur = ai.map(preproc_or_error_line);
         Ok(())
     }
 }
@@ -46,3 +52,269 @@ impl<'a> Read for RecursingConcattyReader<'a> {
         }
     }
 }
+
+/// concatenate the outputs of the files, processing up to jobs files at the same time
+pub fn concat_parallel<'a>(
+    inp: AdaptedFilesIterBox<'a>,
+    jobs: usize,
+) -> Result<Box<dyn Read + 'a>> {
+    if jobs <= 1 {
+        return RecursingConcattyReader::concat(inp);
+    }
+    Ok(Box::new(ParallelConcattyReader::new(inp, jobs)))
+}
+
+/// entries larger than this (or --rga-memory-limit) are processed in the reading thread instead of being buffered for a worker
+const MAX_PARALLEL_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+
+/// an archive entry read into memory, processed by a worker thread
+struct Task {
+    index: usize,
+    filepath_hint: PathBuf,
+    archive_recursion_depth: i32,
+    data: Vec<u8>,
+    line_prefix: String,
+    postprocess: bool,
+    config: RgaConfig,
+}
+
+type TaskOutput = std::result::Result<Box<dyn Read + Send>, String>;
+type TaskResult = (usize, TaskOutput);
+
+fn run_task(task: Task) -> TaskResult {
+    let Task {
+        index,
+        filepath_hint,
+        archive_recursion_depth,
+        data,
+        line_prefix,
+        postprocess,
+        config,
+    } = task;
+    let memory_limit = config.memory_limit.0;
+    let mut oup = preproc_or_error_line(AdaptInfo {
+        filepath_hint,
+        is_real_file: false,
+        archive_recursion_depth,
+        inp: Box::new(Cursor::new(data)),
+        line_prefix,
+        postprocess,
+        config,
+    });
+    let mut buf = SpillBuffer::new(memory_limit);
+    let res = std::io::copy(&mut oup, &mut buf)
+        .map_err(Error::from)
+        .and_then(|_| Ok(buf.into_reader()?))
+        .map_err(|e| format!("{:#}", e));
+    (index, res)
+}
+
+/**
+ * Like RecursingConcattyReader, but runs the adapters of the files on worker threads.
+ * The outputs are returned in the order of the files.
+ *
+ * The first file and files larger than MAX_PARALLEL_ENTRY_SIZE are processed in this thread
+ * (so e.g. a single decompressed stream is never buffered), the others are read into memory and sent to a worker.
+ */
+pub struct ParallelConcattyReader<'a> {
+    inp: AdaptedFilesIterBox<'a>,
+    exhausted: bool,
+    jobs: usize,
+    tasks: Sender<Task>,
+    results: Receiver<TaskResult>,
+    /// index of the next file taken from inp
+    next_index: usize,
+    /// index of the file whose output is read next
+    next_output: usize,
+    finished: HashMap<usize, TaskOutput>,
+    /// file processed in this thread. While it is being read, no other files can be taken from inp
+    inline: Option<(usize, ReadBox<'a>)>,
+    inline_active: bool,
+    cur: Option<ReadBox<'a>>,
+}
+
+impl<'a> ParallelConcattyReader<'a> {
+    pub fn new(inp: AdaptedFilesIterBox<'a>, jobs: usize) -> ParallelConcattyReader<'a> {
+        let (tasks, task_receiver) = crossbeam_channel::unbounded::<Task>();
+        let (result_sender, results) = crossbeam_channel::unbounded();
+        for _ in 0..jobs {
+            let task_receiver = task_receiver.clone();
+            let result_sender = result_sender.clone();
+            // exits when the reader (and with it the task sender) is dropped
+            std::thread::spawn(move || {
+                for task in task_receiver.iter() {
+                    if result_sender.send(run_task(task)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        ParallelConcattyReader {
+            inp,
+            exhausted: false,
+            jobs,
+            tasks,
+            results,
+            next_index: 0,
+            next_output: 0,
+            finished: HashMap::new(),
+            inline: None,
+            inline_active: false,
+            cur: None,
+        }
+    }
+
+    /// take files from inp until enough are in flight or one has to be processed in this thread
+    fn fill(&mut self) -> Result<()> {
+        while !self.exhausted
+            && !self.inline_active
+            && self.inline.is_none()
+            && self.next_index - self.next_output < self.jobs * 2
+        {
+            let inp = &mut self.inp;
+            // same as in RecursingConcattyReader: the previous AdaptInfo is not used anymore once we get here
+            let ai = unsafe { (*(inp as *mut AdaptedFilesIterBox<'a>)).next() };
+            let ai = match ai {
+                Some(ai) => ai,
+                None => {
+                    self.exhausted = true;
+                    break;
+                }
+            };
+            let index = self.next_index;
+            self.next_index += 1;
+            if index == 0 {
+                self.inline = Some((index, preproc_or_error_line(ai)));
+                break;
+            }
+            let AdaptInfo {
+                filepath_hint,
+                is_real_file,
+                archive_recursion_depth,
+                mut inp,
+                line_prefix,
+                postprocess,
+                mut config,
+            } = ai;
+            let max_size = MAX_PARALLEL_ENTRY_SIZE.min(config.memory_limit.0 as u64);
+            let mut data = Vec::new();
+            (&mut inp).take(max_size + 1).read_to_end(&mut data)?;
+            if data.len() as u64 > max_size {
+                debug!(
+                    "{} is too large to process in parallel",
+                    filepath_hint.to_string_lossy()
+                );
+                self.inline = Some((
+                    index,
+                    preproc_or_error_line(AdaptInfo {
+                        filepath_hint,
+                        is_real_file,
+                        archive_recursion_depth,
+                        inp: Box::new(Cursor::new(data).chain(inp)),
+                        line_prefix,
+                        postprocess,
+                        config,
+                    }),
+                ));
+                break;
+            }
+            // don't start more threads for archives within the archive
+            config.archive_jobs = ArchiveJobs(1);
+            self.tasks
+                .send(Task {
+                    index,
+                    filepath_hint,
+                    archive_recursion_depth,
+                    data,
+                    line_prefix,
+                    postprocess,
+                    config,
+                })
+                .map_err(|_| format_err!("archive worker threads exited"))?;
+        }
+        Ok(())
+    }
+
+    /// make the output of the next file the current reader. Returns false if there are no more files
+    fn advance(&mut self) -> Result<bool> {
+        self.inline_active = false;
+        self.fill()?;
+        if self.next_output == self.next_index {
+            return Ok(false);
+        }
+        if let Some((index, _)) = &self.inline {
+            if *index == self.next_output {
+                self.cur = self.inline.take().map(|(_, r)| r);
+                self.inline_active = true;
+                self.next_output += 1;
+                return Ok(true);
+            }
+        }
+        let output = loop {
+            if let Some(output) = self.finished.remove(&self.next_output) {
+                break output;
+            }
+            let (index, output) = self
+                .results
+                .recv()
+                .map_err(|_| format_err!("archive worker threads exited"))?;
+            self.finished.insert(index, output);
+        };
+        self.next_output += 1;
+        self.cur = Some(output.map_err(|e| format_err!(e))?);
+        Ok(true)
+    }
+}
+
+impl<'a> Read for ParallelConcattyReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if let Some(cur) = &mut self.cur {
+                match cur.read(buf)? {
+                    0 => self.cur = None,
+                    n => return Ok(n),
+                }
+            }
+            let more = self
+                .advance()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            if !more {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::adapted_iter::MultiAdaptedFilesIter;
+    use crate::test_utils::simple_adapt_info;
+    use std::path::Path;
+
+    fn entries<'a>() -> AdaptedFilesIterBox<'a> {
+        let ais = (0..20)
+            .map(|i| {
+                let (mut ai, _) = simple_adapt_info(
+                    Path::new(&format!("file{}.txt", i)),
+                    Box::new(Cursor::new(format!("content {}\n", i).into_bytes())),
+                );
+                ai.is_real_file = false;
+                ai.line_prefix = format!("file{}.txt: ", i);
+                ai
+            })
+            .collect();
+        Box::new(MultiAdaptedFilesIter::new(ais))
+    }
+
+    #[test]
+    fn same_output_as_sequential() -> Result<()> {
+        let mut sequential = String::new();
+        RecursingConcattyReader::concat(entries())?.read_to_string(&mut sequential)?;
+        let mut parallel = String::new();
+        concat_parallel(entries(), 4)?.read_to_string(&mut parallel)?;
+        assert_eq!(parallel, sequential);
+        assert!(parallel.starts_with("file0.txt: content 0\nfile1.txt: content 1\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+//! Searching remote files: `rga <pattern> https://example.com/report.pdf` and `rga-preproc <url>`.
+//!
+//! http(s) URLs are downloaded while the adapters read them, so the file is never written to disk.
+//! When the connection breaks, the download is resumed with a range request if the server supports them.
+//! s3://bucket/key URLs are read over https from AWS (the region is taken from AWS_REGION), or from the
+//! endpoint in AWS_ENDPOINT_URL (e.g. minio). The requests are not signed, so only public objects can be read,
+//! other objects can be given as presigned https URLs.
+
+use crate::adapters::postproc::postproc_prefix;
+use crate::adapters::*;
+use crate::config::RgaConfig;
+use crate::preproc::rga_preproc;
+use anyhow::*;
+use log::*;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// how often a broken download is resumed
+const MAX_RETRIES: usize = 3;
+
+pub fn is_url(arg: &str) -> bool {
+    ["http://", "https://", "s3://"]
+        .iter()
+        .any(|scheme| arg.starts_with(scheme))
+}
+
+fn s3_to_http(url: &str, endpoint: Option<String>, region: Option<String>) -> Result<String> {
+    let rest = match url.strip_prefix("s3://") {
+        Some(rest) => rest,
+        None => return Ok(url.to_string()),
+    };
+    let (bucket, key) = rest
+        .split_once('/')
+        .filter(|(_, key)| !key.is_empty())
+        .ok_or_else(|| format_err!("{}: no object key", url))?;
+    Ok(match (endpoint, region) {
+        (Some(endpoint), _) => format!("{}/{}/{}", endpoint.trim_end_matches('/'), bucket, key),
+        (None, Some(region)) => format!("https://{}.s3.{}.amazonaws.com/{}", bucket, region, key),
+        (None, None) => format!("https://{}.s3.amazonaws.com/{}", bucket, key),
+    })
+}
+
+/// the http(s) URL of the file
+pub fn http_url(url: &str) -> Result<String> {
+    s3_to_http(
+        url,
+        std::env::var("AWS_ENDPOINT_URL").ok(),
+        std::env::var("AWS_REGION").ok(),
+    )
+}
+
+/// the file name in the URL, used to choose the adapter
+pub fn url_file_name(url: &str) -> PathBuf {
+    let path = url.split(&['?', '#'][..]).next().unwrap_or(url);
+    let path = path.split_once("://").map_or(path, |(_, rest)| rest);
+    match path.split_once('/') {
+        Some((_, path)) if !path.is_empty() => PathBuf::from(
+            path.trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or(path),
+        ),
+        // e.g. https://example.com
+        _ => PathBuf::from("index.html"),
+    }
+}
+
+fn get(url: &str, offset: u64) -> Result<ureq::Response> {
+    let mut request = ureq::get(url);
+    if offset > 0 {
+        request.set("Range", &format!("bytes={}-", offset));
+    }
+    let resp = request.call();
+    if let Some(e) = resp.synthetic_error() {
+        return Err(format_err!("{}: {}", url, e));
+    }
+    if !resp.ok() {
+        return Err(format_err!("{}: {}", url, resp.status_line()));
+    }
+    if offset > 0 && resp.status() != 206 {
+        return Err(format_err!("{}: could not resume at byte {}", url, offset));
+    }
+    Ok(resp)
+}
+
+/// the body of a GET request, resumed with range requests when the connection breaks
+pub struct RemoteReader {
+    url: String,
+    inner: Box<dyn Read + Send>,
+    offset: u64,
+    ranges: bool,
+    retries: usize,
+}
+
+impl Read for RemoteReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            match self.inner.read(buf) {
+                Ok(n) => {
+                    self.offset += n as u64;
+                    return Ok(n);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) if self.ranges && self.retries < MAX_RETRIES => {
+                    self.retries += 1;
+                    warn!("{}: {}, resuming at byte {}", self.url, e, self.offset);
+                    let resp = get(&self.url, self.offset)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                    self.inner = Box::new(resp.into_reader());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+pub fn open_url(url: &str) -> Result<RemoteReader> {
+    let url = http_url(url)?;
+    let resp = get(&url, 0)?;
+    let ranges = resp.header("Accept-Ranges") == Some("bytes");
+    debug!("downloading {} (resumable: {})", url, ranges);
+    Ok(RemoteReader {
+        inner: Box::new(resp.into_reader()),
+        url,
+        offset: 0,
+        ranges,
+        retries: 0,
+    })
+}
+
+/// the output of the adapters for the remote file, with every line prefixed by the URL
+/// (like the files searched with --rga-batch)
+pub fn preprocess_url(url: &str, config: &RgaConfig) -> Result<ReadBox<'static>> {
+    let oup = rga_preproc(AdaptInfo {
+        inp: Box::new(open_url(url)?),
+        filepath_hint: url_file_name(url),
+        is_real_file: false,
+        line_prefix: "".to_string(),
+        archive_recursion_depth: 0,
+        postprocess: true,
+        config: config.clone(),
+    })
+    .with_context(|| format!("preprocessing {}", url))?;
+    Ok(Box::new(postproc_prefix(
+        &config.entry_prefix("", url),
+        oup,
+    )?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn urls() -> Result<()> {
+        assert!(is_url("https://example.com/a.pdf"));
+        assert!(is_url("s3://bucket/a.pdf"));
+        assert!(!is_url("http.pdf"));
+
+        assert_eq!(
+            s3_to_http("s3://docs/dir/a.pdf", None, None)?,
+            "https://docs.s3.amazonaws.com/dir/a.pdf"
+        );
+        assert_eq!(
+            s3_to_http("s3://docs/a.pdf", None, Some("eu-west-1".to_string()))?,
+            "https://docs.s3.eu-west-1.amazonaws.com/a.pdf"
+        );
+        assert_eq!(
+            s3_to_http(
+                "s3://docs/a.pdf",
+                Some("http://localhost:9000/".to_string()),
+                None
+            )?,
+            "http://localhost:9000/docs/a.pdf"
+        );
+        assert!(s3_to_http("s3://docs", None, None).is_err());
+        assert_eq!(s3_to_http("https://x/a", None, None)?, "https://x/a");
+
+        assert_eq!(
+            url_file_name("https://example.com/dir/report.pdf?download=1"),
+            PathBuf::from("report.pdf")
+        );
+        assert_eq!(
+            url_file_name("s3://docs/a.tar.gz"),
+            PathBuf::from("a.tar.gz")
+        );
+        assert_eq!(
+            url_file_name("https://example.com"),
+            PathBuf::from("index.html")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+//! `rga --rga-report <dir>`: which adapter would handle the files of a directory, without searching them.
+//! For auditing the coverage of a new collection of documents before searching or indexing it.
+//!
+//! The extraction time is estimated by running each adapter on one of its files (the one with the median size)
+//! and extrapolating by size. Files that are already cached are not counted.
+
+use crate::adapters::*;
+use crate::config::RgaConfig;
+use crate::preproc::{cached_output, choose_adapter, rga_preproc};
+use crate::{print_bytes, print_duration};
+use anyhow::*;
+use log::*;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// how much of a file is read to tell whether it is binary, like rg
+const BINARY_SNIFF_LEN: u64 = 8192;
+
+/// what happens to a file when searching
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Handling {
+    Adapter(String),
+    /// no adapter, rg searches the file itself
+    Text,
+    /// binary without an adapter, rg skips it (unless --rga-strings-fallback is enabled)
+    Skipped,
+}
+
+impl std::fmt::Display for Handling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Handling::Adapter(name) => write!(f, "{}", name),
+            Handling::Text => write!(f, "text"),
+            Handling::Skipped => write!(f, "skipped (binary)"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: PathBuf,
+    /// the mime type with --rga-accurate, otherwise the extension
+    pub kind: String,
+    pub handling: Handling,
+    pub size: u64,
+    pub cached: bool,
+}
+
+fn is_binary(path: &Path) -> Result<bool> {
+    let mut buf = Vec::new();
+    File::open(path)?
+        .take(BINARY_SNIFF_LEN)
+        .read_to_end(&mut buf)?;
+    Ok(buf.contains(&0))
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy().to_lowercase()))
+        .unwrap_or_else(|| "(none)".to_string())
+}
+
+pub fn report_file(path: &Path, config: &RgaConfig) -> Result<FileReport> {
+    let (mimetype, adapter) = choose_adapter(config, path)?;
+    let handling = match adapter {
+        Some((adapter, _)) => Handling::Adapter(adapter.metadata().name.clone()),
+        None if config.strings.fallback || !is_binary(path)? => Handling::Text,
+        None => Handling::Skipped,
+    };
+    let cached = matches!(handling, Handling::Adapter(_))
+        && !config.cache.disabled
+        && cached_output(config, path).map_or(false, |(_, output)| output.is_some());
+    Ok(FileReport {
+        kind: mimetype.unwrap_or_else(|| extension(path)),
+        handling,
+        size: std::fs::metadata(path)?.len(),
+        cached,
+        path: path.to_owned(),
+    })
+}
+
+/// all files below the roots. Respects .gitignore, .ignore and hidden files like rg
+pub fn report(roots: &[PathBuf], config: &RgaConfig) -> Result<Vec<FileReport>> {
+    let (first, rest) = roots
+        .split_first()
+        .ok_or_else(|| format_err!("no paths to report on"))?;
+    let mut builder = ignore::WalkBuilder::new(first);
+    for root in rest {
+        builder.add(root);
+    }
+    let mut files = Vec::new();
+    for entry in builder.build() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("{}", e);
+                continue;
+            }
+        };
+        if !entry.file_type().map_or(false, |t| t.is_file()) {
+            continue;
+        }
+        match report_file(entry.path(), config) {
+            Ok(file) => files.push(file),
+            Err(e) => eprintln!("rga: {}: {:#}", entry.path().display(), e),
+        }
+    }
+    Ok(files)
+}
+
+/// the time it takes to extract the file, without the cache
+fn extraction_time(path: &Path, config: &RgaConfig) -> Result<Duration> {
+    let mut config = config.clone();
+    config.cache.disabled = true;
+    let start = Instant::now();
+    let mut oup = rga_preproc(AdaptInfo {
+        inp: Box::new(File::open(path)?),
+        filepath_hint: std::env::current_dir()?.join(path),
+        is_real_file: true,
+        line_prefix: "".to_string(),
+        archive_recursion_depth: 0,
+        postprocess: true,
+        config,
+    })?;
+    std::io::copy(&mut oup, &mut std::io::sink())?;
+    Ok(start.elapsed())
+}
+
+#[derive(Default)]
+struct Totals {
+    files: usize,
+    size: u64,
+    uncached: Vec<(u64, PathBuf)>,
+}
+
+/// per kind of file the adapters handling them, per adapter the estimated extraction time
+pub fn print_report(files: &[FileReport], config: &RgaConfig, oup: &mut dyn Write) -> Result<()> {
+    let mut per_kind: BTreeMap<&str, (usize, u64, BTreeMap<&Handling, usize>)> = BTreeMap::new();
+    let mut per_handling: BTreeMap<&Handling, Totals> = BTreeMap::new();
+    for file in files {
+        let (count, size, handlings) = per_kind.entry(file.kind.as_str()).or_default();
+        *count += 1;
+        *size += file.size;
+        *handlings.entry(&file.handling).or_default() += 1;
+        let totals = per_handling.entry(&file.handling).or_default();
+        totals.files += 1;
+        totals.size += file.size;
+        if !file.cached {
+            totals.uncached.push((file.size, file.path.clone()));
+        }
+    }
+    let kind = if config.accurate {
+        "mime type"
+    } else {
+        "extension"
+    };
+    writeln!(
+        oup,
+        "{:<24} {:>6} {:>10}  handled by",
+        kind, "files", "size"
+    )?;
+    for (kind, (count, size, handlings)) in &per_kind {
+        let handled_by = handlings
+            .iter()
+            .map(|(handling, n)| format!("{} {}", handling, n))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(
+            oup,
+            "{:<24} {:>6} {:>10}  {}",
+            kind,
+            count,
+            print_bytes(*size as f64),
+            handled_by
+        )?;
+    }
+
+    writeln!(
+        oup,
+        "\n{:<20} {:>6} {:>10} {:>10} {:>12}",
+        "handled by", "files", "size", "uncached", "est. time"
+    )?;
+    let mut total_time = Duration::default();
+    for (handling, totals) in per_handling.iter_mut() {
+        let uncached_size: u64 = totals.uncached.iter().map(|(size, _)| size).sum();
+        let estimate = match handling {
+            Handling::Adapter(_) if !totals.uncached.is_empty() => {
+                totals.uncached.sort();
+                let (sample_size, sample) = &totals.uncached[totals.uncached.len() / 2];
+                match extraction_time(sample, config) {
+                    // empty files can't be extrapolated by size
+                    Ok(time) if *sample_size == 0 => Some(time * totals.uncached.len() as u32),
+                    Ok(time) => Some(time.mul_f64(uncached_size as f64 / *sample_size as f64)),
+                    Err(e) => {
+                        debug!("{}: {:#}", sample.display(), e);
+                        None
+                    }
+                }
+            }
+            _ => Some(Duration::default()),
+        };
+        total_time += estimate.unwrap_or_default();
+        writeln!(
+            oup,
+            "{:<20} {:>6} {:>10} {:>10} {:>12}",
+            handling.to_string(),
+            totals.files,
+            print_bytes(totals.size as f64),
+            print_bytes(uncached_size as f64),
+            estimate.map_or_else(|| "failed".to_string(), print_duration)
+        )?;
+    }
+    writeln!(
+        oup,
+        "\n{} files, extracting the uncached ones takes about {} (one adapter at a time)",
+        files.len(),
+        print_duration(total_time)
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn coverage() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::copy(
+            test_data_dir().join("hello.gz"),
+            dir.path().join("hello.gz"),
+        )?;
+        std::fs::write(dir.path().join("notes.txt"), "hello")?;
+        std::fs::write(dir.path().join("blob.bin"), b"\x00\x01\x02")?;
+        let mut config = RgaConfig::default();
+        config.cache.disabled = true;
+
+        let mut files = report(&[dir.path().to_owned()], &config)?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let handlings: Vec<_> = files
+            .iter()
+            .map(|f| (f.kind.as_str(), &f.handling))
+            .collect();
+        assert_eq!(
+            handlings,
+            [
+                (".bin", &Handling::Skipped),
+                (".gz", &Handling::Adapter("decompress".to_string())),
+                (".txt", &Handling::Text),
+            ]
+        );
+
+        let mut out = Vec::new();
+        print_report(&files, &config, &mut out)?;
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("decompress"));
+        assert!(out.contains("3 files"));
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+//! The arguments rga passes through to rg, split into the options (with their values), the pattern and the paths like rg
+//! does it, so `rga -e TODO dir`, `rga -f patterns.txt dir` or a pattern that is also a file name or a URL are not
+//! mistaken for paths (and a path is not mistaken for an option).
+//!
+//! When rga runs rg itself with changed paths, the pattern and the paths are given after `--`, so they are never options.
+
+use crate::batch::WalkOptions;
+use anyhow::*;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// the short options of rg that take a value (as the next argument or the rest of the group, e.g. -A3)
+const SHORT_WITH_VALUE: &str = "ABCEMTdefgjmrt";
+
+/// the long options of rg that take a value (as the next argument or after =)
+const LONG_WITH_VALUE: &[&str] = &[
+    "--after-context",
+    "--before-context",
+    "--color",
+    "--colors",
+    "--context",
+    "--context-separator",
+    "--dfa-size-limit",
+    "--encoding",
+    "--engine",
+    "--field-context-separator",
+    "--field-match-separator",
+    "--file",
+    "--generate",
+    "--glob",
+    "--hostname-bin",
+    "--hyperlink-format",
+    "--iglob",
+    "--ignore-file",
+    "--max-columns",
+    "--max-count",
+    "--max-depth",
+    "--max-filesize",
+    "--maxdepth",
+    "--path-separator",
+    "--pre",
+    "--pre-glob",
+    "--regex-size-limit",
+    "--regexp",
+    "--replace",
+    "--sort",
+    "--sortr",
+    "--threads",
+    "--type",
+    "--type-add",
+    "--type-clear",
+    "--type-not",
+];
+
+/// the options that give the patterns, then all arguments are paths
+const PATTERN_OPTIONS: &[&str] = &["-e", "--regexp", "-f", "--file"];
+
+/// the options with which rg does not search, so it takes no pattern (e.g. rg --files dir)
+const NO_PATTERN_OPTIONS: &[&str] = &[
+    "--files",
+    "--type-list",
+    "-h",
+    "--help",
+    "-V",
+    "--version",
+    "--pcre2-version",
+    "--generate",
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RgArgs {
+    /// the options with their values, as given
+    pub options: Vec<OsString>,
+    /// None if the patterns are given with -e or -f, or rg doesn't search (e.g. --files)
+    pub pattern: Option<OsString>,
+    /// the files and directories to search, rg searches the current directory without them
+    pub paths: Vec<OsString>,
+    /// the names of the options, with the short options of a group (e.g. -iuu) separately
+    names: Vec<String>,
+}
+
+impl RgArgs {
+    pub fn parse(args: &[OsString]) -> RgArgs {
+        let mut res = RgArgs::default();
+        let mut positional = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            // arguments that are not unicode can only be patterns or paths
+            let s = match arg.to_str() {
+                Some(s) if s.starts_with('-') && s != "-" => s,
+                _ => {
+                    positional.push(arg.clone());
+                    continue;
+                }
+            };
+            if s == "--" {
+                positional.extend(args.by_ref().cloned());
+                break;
+            }
+            res.options.push(arg.clone());
+            let needs_value = if s.starts_with("--") {
+                let name = s.split('=').next().unwrap_or(s);
+                res.names.push(name.to_string());
+                !s.contains('=') && LONG_WITH_VALUE.contains(&name)
+            } else {
+                let mut needs_value = false;
+                for (i, c) in s.char_indices().skip(1) {
+                    res.names.push(format!("-{}", c));
+                    if SHORT_WITH_VALUE.contains(c) {
+                        // the rest of the group is the value
+                        needs_value = i + c.len_utf8() == s.len();
+                        break;
+                    }
+                }
+                needs_value
+            };
+            if needs_value {
+                if let Some(value) = args.next() {
+                    res.options.push(value.clone());
+                }
+            }
+        }
+        let mut positional = positional.into_iter();
+        if !res.names.iter().any(|n| {
+            PATTERN_OPTIONS.contains(&n.as_str()) || NO_PATTERN_OPTIONS.contains(&n.as_str())
+        }) {
+            res.pattern = positional.next();
+        }
+        res.paths = positional.collect();
+        res
+    }
+
+    /// from the parts given separately (e.g. in a request to the daemon), so the pattern and the paths can't be options
+    pub fn from_parts(
+        options: Vec<OsString>,
+        pattern: Option<OsString>,
+        paths: Vec<OsString>,
+    ) -> Result<RgArgs> {
+        let parsed = RgArgs::parse(&options);
+        if parsed.pattern.is_some() || !parsed.paths.is_empty() || parsed.options != options {
+            bail!("the options of rg must not contain patterns or paths");
+        }
+        Ok(RgArgs {
+            pattern,
+            paths,
+            ..parsed
+        })
+    }
+
+    /// whether the option (e.g. "--json" or "-u") is given
+    pub fn has(&self, name: &str) -> bool {
+        self.names.iter().any(|n| n == name)
+    }
+
+    /// how often the option is given, e.g. 2 for -uu
+    pub fn count(&self, name: &str) -> usize {
+        self.names.iter().filter(|n| *n == name).count()
+    }
+
+    /// the options of rg that change which files it walks, for the files rga walks itself (--rga-batch)
+    pub fn walk_options(&self) -> WalkOptions {
+        WalkOptions {
+            hidden: self.has("--hidden") || self.count("-u") >= 2,
+            no_ignore: self.has("--no-ignore") || self.count("-u") >= 1,
+        }
+    }
+
+    /// the paths, or the current directory if there are none (like rg)
+    pub fn roots(&self) -> Vec<PathBuf> {
+        if self.paths.is_empty() {
+            vec![PathBuf::from(".")]
+        } else {
+            self.paths.iter().map(PathBuf::from).collect()
+        }
+    }
+
+    /// the pattern and the given paths after --, to follow the options (and those added by rga)
+    pub fn positional_args<P: Into<OsString> + Clone>(&self, paths: &[P]) -> Vec<OsString> {
+        let mut args = vec![OsString::from("--")];
+        args.extend(self.pattern.clone());
+        args.extend(paths.iter().cloned().map(Into::into));
+        args
+    }
+
+    /// the arguments for rg: the options, then the pattern and the given paths after --
+    pub fn to_args_with_paths<P: Into<OsString> + Clone>(&self, paths: &[P]) -> Vec<OsString> {
+        let mut args = self.options.clone();
+        args.extend(self.positional_args(paths));
+        args
+    }
+
+    /// the arguments for rg, with the pattern and the paths after --
+    pub fn to_args(&self) -> Vec<OsString> {
+        self.to_args_with_paths(&self.paths)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::OsStr;
+
+    fn parse(args: &[&str]) -> RgArgs {
+        RgArgs::parse(&args.iter().map(OsString::from).collect::<Vec<_>>())
+    }
+
+    fn strs(args: &[OsString]) -> Vec<&str> {
+        args.iter().map(|a| a.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn split() {
+        let args = parse(&["-i", "https://internal", "docs", "-g", "*.pdf", "a.zip"]);
+        assert_eq!(strs(&args.options), ["-i", "-g", "*.pdf"]);
+        assert_eq!(
+            args.pattern.as_deref(),
+            Some(OsStr::new("https://internal"))
+        );
+        assert_eq!(strs(&args.paths), ["docs", "a.zip"]);
+
+        let args = parse(&["-f", "patterns.txt", "-ie", "TODO", "dir", "-A3"]);
+        assert_eq!(
+            strs(&args.options),
+            ["-f", "patterns.txt", "-ie", "TODO", "-A3"]
+        );
+        assert_eq!(args.pattern, None);
+        assert_eq!(strs(&args.paths), ["dir"]);
+
+        let args = parse(&["--glob=*.pdf", "--max-count", "2", "--", "-pattern", "-"]);
+        assert_eq!(strs(&args.options), ["--glob=*.pdf", "--max-count", "2"]);
+        assert_eq!(args.pattern.as_deref(), Some(OsStr::new("-pattern")));
+        assert_eq!(strs(&args.paths), ["-"]);
+        assert_eq!(
+            strs(&args.to_args()),
+            ["--glob=*.pdf", "--max-count", "2", "--", "-pattern", "-"]
+        );
+
+        let args = parse(&["--files", "dir"]);
+        assert_eq!(args.pattern, None);
+        assert_eq!(strs(&args.paths), ["dir"]);
+
+        let args = RgArgs::from_parts(
+            vec!["--json".into()],
+            Some("--pre=sh".into()),
+            vec!["-docs".into()],
+        )
+        .unwrap();
+        assert!(args.has("--json"));
+        assert_eq!(strs(&args.to_args()), ["--json", "--", "--pre=sh", "-docs"]);
+        assert!(RgArgs::from_parts(vec!["-i".into(), "docs".into()], None, vec![]).is_err());
+        assert!(RgArgs::from_parts(vec!["--".into(), "docs".into()], None, vec![]).is_err());
+
+        let walk = parse(&["-iuu", "x"]).walk_options();
+        assert!(walk.hidden && walk.no_ignore);
+        assert_eq!(parse(&["x"]).roots(), [PathBuf::from(".")]);
+    }
+}
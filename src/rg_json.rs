@@ -0,0 +1,347 @@
+//! Post-processing of the output of `rg --json`: the prefixes rga adds to the lines of files in archives and of pages
+//! are removed from the matched lines and added as separate fields instead.
+//!
+//! With --json, rga uses prefixes with control characters as separators (see `set_json_prefixes`),
+//! so they can be split off reliably.
+
+use crate::config::{PageFormat, PrefixFormat, RgaConfig};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// ends the name of a file in an archive
+const ENTRY_END: char = '\u{1f}';
+/// starts a page number, which is ended by ENTRY_END
+const PAGE_START: char = '\u{1e}';
+
+/// path given by rg for the searched content in --rga-batch mode
+const STDIN_PATH: &str = "<stdin>";
+
+/// use the prefixes that can be parsed by `rewrite_message`
+pub fn set_json_prefixes(config: &mut RgaConfig) -> Result<()> {
+    config.prefix_format = PrefixFormat(format!("{{archive}}{{entry}}{}", ENTRY_END));
+    config.page_format = PageFormat(format!("{}{{page}}{}", PAGE_START, ENTRY_END));
+    // the links would be in the matched lines
+    config.hyperlinks = false;
+    crate::config::update_config_env(|env| {
+        env["hyperlinks"] = Value::Bool(false);
+        env["prefix_format"] = Value::String(config.prefix_format.0.clone());
+        env["page_format"] = Value::String(config.page_format.0.clone());
+    })
+}
+
+/// the names of the files in archives and the page at the start of a line, and the length of that prefix
+pub fn split_prefix(line: &str) -> (Vec<&str>, Option<u64>, usize) {
+    let mut entries = vec![];
+    let mut page = None;
+    let mut rest = line;
+    while let Some(end) = rest.find(ENTRY_END) {
+        let segment = &rest[..end];
+        match segment.strip_prefix(PAGE_START) {
+            Some(number) => match number.parse() {
+                Ok(number) => page = Some(number),
+                Err(_) => break,
+            },
+            // the content of the file (or a name after the page, which is not a prefix of ours)
+            None if segment.contains(PAGE_START) || page.is_some() => break,
+            None => entries.push(segment),
+        }
+        rest = &rest[end + ENTRY_END.len_utf8()..];
+    }
+    (entries, page, line.len() - rest.len())
+}
+
+/// rewrite one line of the output of rg --json. The prefixes are removed from matched and context lines,
+/// the files in archives are added as "subpath" (joined with "/") and the page number as "page".
+/// Lines that can't be parsed are returned as they are
+pub fn rewrite_message(line: &str) -> String {
+    match serde_json::from_str::<Value>(line) {
+        Ok(mut message) => {
+            if rewrite_value(&mut message).is_some() {
+                message.to_string()
+            } else {
+                line.to_string()
+            }
+        }
+        Err(_) => line.to_string(),
+    }
+}
+
+/// like rewrite_message, returns the names of the files in archives (for nested archives one per archive),
+/// or None if the message has no prefix
+fn rewrite_value(message: &mut Value) -> Option<Vec<String>> {
+    if message["type"] != "match" && message["type"] != "context" {
+        return None;
+    }
+    let data = &mut message["data"];
+    // not utf8 if it's given as base64 in "bytes"
+    let text = data["lines"]["text"].as_str()?;
+    let (entries, page, prefix_len) = split_prefix(text);
+    if prefix_len == 0 {
+        return None;
+    }
+    let entries: Vec<String> = entries.into_iter().map(String::from).collect();
+    let text = text[prefix_len..].to_string();
+    data["lines"]["text"] = Value::String(text);
+    if let Some(submatches) = data["submatches"].as_array_mut() {
+        for submatch in submatches {
+            for key in &["start", "end"] {
+                if let Some(offset) = submatch[*key].as_u64() {
+                    submatch[*key] = json!(offset.saturating_sub(prefix_len as u64));
+                }
+            }
+        }
+    }
+    let mut entries = &entries[..];
+    // with --rga-batch, the first prefix is the name of the file
+    if data["path"]["text"] == STDIN_PATH && !entries.is_empty() {
+        data["path"] = json!({ "text": entries[0] });
+        entries = &entries[1..];
+    }
+    if !entries.is_empty() {
+        data["subpath"] = json!({ "text": entries.join("/") });
+    }
+    if let Some(page) = page {
+        data["page"] = json!(page);
+    }
+    Some(entries.to_vec())
+}
+
+/// where a match is, a line of the --rga-location-map file
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Location {
+    /// the line of the output of rga the match is printed on, starting at 1
+    pub line: u64,
+    pub path: String,
+    /// the path of the file in the archive, with "/" between the paths in nested archives
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subpath: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<u64>,
+    /// the line number in the file, if rg was given --line-number
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_number: Option<u64>,
+    /// the byte offset of the line in the text extracted from the file
+    pub offset: u64,
+    /// the byte ranges of the matches in the line
+    pub submatches: Vec<(u64, u64)>,
+}
+
+fn text_of(value: &Value) -> String {
+    match (value["text"].as_str(), value["bytes"].as_str()) {
+        (Some(text), _) => text.to_string(),
+        (None, Some(bytes)) => {
+            String::from_utf8_lossy(&base64::decode(bytes).unwrap_or_default()).into_owned()
+        }
+        (None, None) => String::new(),
+    }
+}
+
+/// the line rg prints for the match or context message without --json (like --no-heading --color=never),
+/// with the prefixes of the config
+fn text_line(data: &Value, entries: &[String], is_match: bool, config: &RgaConfig) -> String {
+    let separator = if is_match { ':' } else { '-' };
+    let mut line = text_of(&data["path"]);
+    line.push(separator);
+    if let Some(line_number) = data["line_number"].as_u64() {
+        line.push_str(&format!("{}{}", line_number, separator));
+    }
+    let mut prefix = String::new();
+    for entry in entries {
+        prefix = config.entry_prefix(&prefix, entry);
+    }
+    line.push_str(&prefix);
+    if let Some(page) = data["page"].as_u64() {
+        line.push_str(&config.page_format.prefix(page as usize));
+    }
+    line.push_str(text_of(&data["lines"]).trim_end_matches(&['\r', '\n'][..]));
+    line
+}
+
+/// the output of rg --json with the locations of the matches written to the --rga-location-map file.
+/// Shared by the rg processes of one search, so the lines are counted across them
+pub struct JsonOutput {
+    /// the config with the prefixes for printing the matches as text, None to print the JSON
+    text: Option<RgaConfig>,
+    map: Option<BufWriter<File>>,
+    lines: u64,
+}
+
+impl JsonOutput {
+    pub fn new(text: Option<RgaConfig>, map: Option<&Path>) -> Result<JsonOutput> {
+        let map = match map {
+            Some(path) => {
+                Some(BufWriter::new(File::create(path).with_context(|| {
+                    format!("could not create {}", path.display())
+                })?))
+            }
+            None => None,
+        };
+        Ok(JsonOutput {
+            text,
+            map,
+            lines: 0,
+        })
+    }
+
+    /// write a line of rg's output
+    pub fn write(&mut self, line: &str, oup: &mut dyn Write) -> Result<()> {
+        let mut message: Value = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(_) => {
+                writeln!(oup, "{}", line)?;
+                self.lines += 1;
+                return Ok(());
+            }
+        };
+        let entries = rewrite_value(&mut message).unwrap_or_default();
+        let is_match = message["type"] == "match";
+        let data = &message["data"];
+        match &self.text {
+            Some(config) if is_match || message["type"] == "context" => {
+                writeln!(oup, "{}", text_line(data, &entries, is_match, config))?
+            }
+            // begin, end and summary messages
+            Some(_) => return Ok(()),
+            None => writeln!(oup, "{}", message)?,
+        }
+        self.lines += 1;
+        if let (Some(map), true) = (&mut self.map, is_match) {
+            let location = Location {
+                line: self.lines,
+                path: text_of(&data["path"]),
+                subpath: data["subpath"]["text"].as_str().map(String::from),
+                page: data["page"].as_u64(),
+                line_number: data["line_number"].as_u64(),
+                offset: data["absolute_offset"].as_u64().unwrap_or(0),
+                submatches: data["submatches"]
+                    .as_array()
+                    .map(|submatches| {
+                        submatches
+                            .iter()
+                            .filter_map(|m| Some((m["start"].as_u64()?, m["end"].as_u64()?)))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            };
+            writeln!(map, "{}", serde_json::to_string(&location)?)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some(map) = &mut self.map {
+            map.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rewrite() {
+        let line = json!({
+            "type": "match",
+            "data": {
+                "path": {"text": "docs.zip"},
+                "lines": {"text": "dir/report.pdf\u{1f}\u{1e}3\u{1f}total: 5\n"},
+                "line_number": null,
+                "absolute_offset": 100,
+                "submatches": [{"match": {"text": "total"}, "start": 18, "end": 23}]
+            }
+        })
+        .to_string();
+        let rewritten: Value = serde_json::from_str(&rewrite_message(&line)).unwrap();
+        assert_eq!(
+            rewritten["data"],
+            json!({
+                "path": {"text": "docs.zip"},
+                "subpath": {"text": "dir/report.pdf"},
+                "page": 3,
+                "lines": {"text": "total: 5\n"},
+                "line_number": null,
+                "absolute_offset": 100,
+                "submatches": [{"match": {"text": "total"}, "start": 0, "end": 5}]
+            })
+        );
+
+        let batch = json!({
+            "type": "context",
+            "data": {
+                "path": {"text": "<stdin>"},
+                "lines": {"text": "a.docx\u{1f}text\n"},
+                "submatches": []
+            }
+        })
+        .to_string();
+        let rewritten: Value = serde_json::from_str(&rewrite_message(&batch)).unwrap();
+        assert_eq!(rewritten["data"]["path"], json!({"text": "a.docx"}));
+        assert_eq!(rewritten["data"]["subpath"], Value::Null);
+        assert_eq!(rewritten["data"]["lines"]["text"], "text\n");
+
+        let summary = r#"{"type":"summary","data":{}}"#;
+        assert_eq!(rewrite_message(summary), summary);
+    }
+
+    #[test]
+    fn location_map() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let map_path = dir.path().join("map.jsonl");
+        let mut config = RgaConfig::default();
+        config.prefix_format = PrefixFormat("{archive}{entry}: ".to_string());
+        config.page_format = PageFormat("Page {page}: ".to_string());
+        let mut output = JsonOutput::new(Some(config), Some(&map_path))?;
+        let mut oup = Vec::new();
+        let messages = [
+            json!({"type": "begin", "data": {"path": {"text": "docs.zip"}}}),
+            json!({"type": "context", "data": {
+                "path": {"text": "docs.zip"},
+                "lines": {"text": "dir/report.pdf\u{1f}\u{1e}3\u{1f}before\n"},
+                "line_number": null, "absolute_offset": 80, "submatches": []
+            }}),
+            json!({"type": "match", "data": {
+                "path": {"text": "docs.zip"},
+                "lines": {"text": "dir/report.pdf\u{1f}\u{1e}3\u{1f}total: 5\n"},
+                "line_number": null, "absolute_offset": 100,
+                "submatches": [{"match": {"text": "total"}, "start": 18, "end": 23}]
+            }}),
+        ];
+        for message in &messages {
+            output.write(&message.to_string(), &mut oup)?;
+        }
+        output.finish()?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "docs.zip-dir/report.pdf: Page 3: before\ndocs.zip:dir/report.pdf: Page 3: total: 5\n"
+        );
+        let location: Value = serde_json::from_str(&std::fs::read_to_string(&map_path)?)?;
+        assert_eq!(
+            location,
+            json!({
+                "line": 2,
+                "path": "docs.zip",
+                "subpath": "dir/report.pdf",
+                "page": 3,
+                "offset": 100,
+                "submatches": [[0, 5]]
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(split_prefix("plain text"), (vec![], None, 0));
+        assert_eq!(
+            split_prefix("a.zip\u{1f}b.tar\u{1f}x"),
+            (vec!["a.zip", "b.tar"], None, 12)
+        );
+        assert_eq!(split_prefix("\u{1e}2\u{1f}x"), (vec![], Some(2), 3));
+    }
+}
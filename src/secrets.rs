@@ -0,0 +1,276 @@
+//! Passwords of protected documents (encrypted zip entries, pdfs and office documents). All adapters look them up
+//! the same way, the first password found is used:
+//!
+//! 1. the --rga-password-file, every line is a glob and a password (`invoices/*.pdf hunter2`)
+//! 2. the options of the format (--rga-pdf-password-file, --rga-zip-password, --rga-pdf-password)
+//! 3. with --rga-keyring, the OS keyring (Secret Service, macOS Keychain, Windows Credential Manager):
+//!    service "ripgrep-all", user the file name or the format (zip, pdf, office)
+//! 4. --rga-password, RGA_PASSWORD, or the password entered for --rga-ask-password
+//!
+//! Only one password is tried per file. Outputs are cached per password (by its hash).
+
+use crate::adapters::spawning::map_exe_error;
+use crate::config::RgaConfig;
+use anyhow::*;
+use lazy_static::lazy_static;
+use log::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+
+pub const KEYRING_SERVICE: &str = "ripgrep-all";
+
+/// a protected office document is an ole compound file instead of a zip file
+const OLE_MAGIC: &[u8] = &[0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1];
+
+/// office open xml formats, which are encrypted into an ole compound file
+static OOXML_EXTENSIONS: &[&str] = &[
+    "docx", "docm", "dotx", "xlsx", "xlsm", "xltx", "pptx", "pptm", "potx",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SecretKind {
+    Zip,
+    Pdf,
+    Office,
+}
+
+impl SecretKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SecretKind::Zip => "zip",
+            SecretKind::Pdf => "pdf",
+            SecretKind::Office => "office",
+        }
+    }
+}
+
+lazy_static! {
+    /// keyring lookups by user, every rga-preproc process asks the keyring at most once per user
+    static ref KEYRING: Mutex<HashMap<String, Option<String>>> = Mutex::new(HashMap::new());
+}
+
+fn matches_glob(pattern: &glob::Pattern, filepath: &Path) -> bool {
+    pattern.matches_path(filepath)
+        || filepath
+            .file_name()
+            .map(|n| pattern.matches(&n.to_string_lossy()))
+            .unwrap_or(false)
+}
+
+/// the password of the first line of the password file whose glob matches the full path or the file name.
+/// Empty lines and lines starting with # are ignored
+pub fn password_from_file(password_file: &str, filepath: &Path) -> Result<Option<String>> {
+    let contents = std::fs::read_to_string(password_file)
+        .with_context(|| format!("could not read password file {}", password_file))?;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let glob = parts.next().unwrap_or_default();
+        let password = parts.next().unwrap_or_default().trim_start();
+        let pattern = glob::Pattern::new(glob)
+            .with_context(|| format!("invalid glob {} in {}", glob, password_file))?;
+        if matches_glob(&pattern, filepath) {
+            return Ok(Some(password.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+fn keyring_lookup(user: &str) -> Option<String> {
+    let mut cache = KEYRING.lock().unwrap();
+    cache
+        .entry(user.to_string())
+        .or_insert_with(
+            || match keyring::Keyring::new(KEYRING_SERVICE, user).get_password() {
+                Ok(password) => Some(password),
+                Err(keyring::KeyringError::NoPasswordFound) => None,
+                Err(e) => {
+                    warn!("could not read {} from the keyring: {}", user, e);
+                    None
+                }
+            },
+        )
+        .clone()
+}
+
+/// the password to use for the protected file, if any
+pub fn password_for(
+    config: &RgaConfig,
+    kind: SecretKind,
+    filepath: &Path,
+) -> Result<Option<String>> {
+    if let Some(password_file) = &config.secrets.password_file {
+        if let Some(password) = password_from_file(password_file, filepath)? {
+            return Ok(Some(password));
+        }
+    }
+    let format_password = match kind {
+        SecretKind::Zip => config.zip.password.clone(),
+        SecretKind::Pdf => match &config.pdf.password_file {
+            Some(password_file) => password_from_file(password_file, filepath)?,
+            None => None,
+        }
+        .or_else(|| config.pdf.password.clone()),
+        SecretKind::Office => None,
+    };
+    if format_password.is_some() {
+        return Ok(format_password);
+    }
+    if config.secrets.keyring {
+        let file_name = filepath.file_name().map(|n| n.to_string_lossy());
+        let password = file_name
+            .and_then(|name| keyring_lookup(&name))
+            .or_else(|| keyring_lookup(kind.name()));
+        if password.is_some() {
+            return Ok(password);
+        }
+    }
+    Ok(config.secrets.password.clone())
+}
+
+/// part of the cache key of outputs that depend on the password
+pub fn cache_key(config: &RgaConfig, kind: SecretKind, filepath: &Path) -> Result<Option<Vec<u8>>> {
+    Ok(password_for(config, kind, filepath)?.map(|p| Sha256::digest(p.as_bytes()).to_vec()))
+}
+
+/// how to give a password, for the messages of adapters that found a protected file
+pub fn password_hint(kind: SecretKind) -> String {
+    let format_option = match kind {
+        SecretKind::Zip => "--rga-zip-password, ",
+        SecretKind::Pdf => "--rga-pdf-password, ",
+        SecretKind::Office => "",
+    };
+    format!(
+        "Pass the password with --rga-password-file, {}--rga-keyring (service {}, user {} or the file name), --rga-password or --rga-ask-password",
+        format_option,
+        KEYRING_SERVICE,
+        kind.name()
+    )
+}
+
+/// whether a prompt can be shown, i.e. rga is run from a terminal
+pub fn has_terminal() -> bool {
+    #[cfg(unix)]
+    {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .is_ok()
+    }
+    #[cfg(not(unix))]
+    {
+        use crossterm::tty::IsTty;
+        std::io::stdin().is_tty()
+    }
+}
+
+/// ask for a password on the terminal, None if there is no terminal
+pub fn prompt(prompt: &str) -> Result<Option<String>> {
+    if !has_terminal() {
+        warn!("not asking for a password since rga is not run from a terminal");
+        return Ok(None);
+    }
+    let password =
+        rpassword::read_password_from_tty(Some(prompt)).context("could not read password")?;
+    Ok(Some(password))
+}
+
+/// whether the file is an encrypted office open xml document (docx, xlsx, pptx, ...)
+pub fn is_encrypted_office(filepath: &Path, start: &[u8]) -> bool {
+    let ooxml = filepath
+        .extension()
+        .map(|ext| OOXML_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str()))
+        .unwrap_or(false);
+    ooxml && start.starts_with(OLE_MAGIC)
+}
+
+/// decrypt a protected office document with msoffcrypto-tool, the output is the unprotected document
+pub fn decrypt_office(
+    config: &RgaConfig,
+    filepath_hint: &Path,
+    is_real_file: bool,
+    inp: &mut dyn Read,
+) -> Result<Vec<u8>> {
+    let password = password_for(config, SecretKind::Office, filepath_hint)?.ok_or_else(|| {
+        format_err!(
+            "{} is password protected. {}",
+            filepath_hint.display(),
+            password_hint(SecretKind::Office)
+        )
+    })?;
+    let (path, _tmp) = crate::adapters::spawning::input_as_file(filepath_hint, is_real_file, inp)?;
+    let out = tempfile::Builder::new().prefix("rga-office-").tempfile()?;
+    // msoffcrypto-tool only accepts the password as an argument
+    let output = Command::new("msoffcrypto-tool")
+        .arg("-p")
+        .arg(&password)
+        .arg(&path)
+        .arg(out.path())
+        .output()
+        .map_err(|e| {
+            map_exe_error(
+                e,
+                "msoffcrypto-tool",
+                "Please make sure you have msoffcrypto-tool installed (pip install msoffcrypto-tool).",
+            )
+        })?;
+    if !output.status.success() {
+        bail!(
+            "could not decrypt {}: {}",
+            filepath_hint.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(std::fs::read(out.path())?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lookup_order() -> Result<()> {
+        let mut password_file = tempfile::NamedTempFile::new()?;
+        std::io::Write::write_all(
+            &mut password_file,
+            b"# passwords\n\ninvoices/*.pdf  secret one\n*.zip zippy\n",
+        )?;
+        let mut config = RgaConfig::default();
+        config.secrets.password_file = Some(password_file.path().to_string_lossy().into_owned());
+        config.secrets.password = Some("default".to_string());
+        config.pdf.password = Some("pdf".to_string());
+
+        let password = |kind, path: &str| password_for(&config, kind, Path::new(path));
+        assert_eq!(
+            password(SecretKind::Pdf, "invoices/2020.pdf")?.as_deref(),
+            Some("secret one")
+        );
+        assert_eq!(
+            password(SecretKind::Zip, "/home/me/backup.zip")?.as_deref(),
+            Some("zippy")
+        );
+        assert_eq!(
+            password(SecretKind::Pdf, "other.pdf")?.as_deref(),
+            Some("pdf")
+        );
+        assert_eq!(
+            password(SecretKind::Office, "report.docx")?.as_deref(),
+            Some("default")
+        );
+
+        let mut encrypted = OLE_MAGIC.to_vec();
+        encrypted.extend_from_slice(&[0; 8]);
+        assert!(is_encrypted_office(Path::new("a.DOCX"), &encrypted));
+        assert!(!is_encrypted_office(Path::new("a.doc"), &encrypted));
+        assert!(!is_encrypted_office(Path::new("a.docx"), b"PK\x03\x04"));
+        Ok(())
+    }
+}
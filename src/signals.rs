@@ -0,0 +1,157 @@
+//! Cleanup when rga or rga-preproc exits or is interrupted (SIGINT, SIGTERM, SIGHUP): the signal is forwarded to the
+//! programs the adapters run (which are killed if they don't exit), and the temporary files are removed.
+//!
+//! All temporary files of the process and of the programs it runs are created in a private directory (TMPDIR is set to it),
+//! so they are also removed if they were created by a program that was killed, or by a library that does not clean up.
+
+#[cfg(unix)]
+use crate::adapters::spawning::kill_subprocesses;
+use anyhow::Result;
+use log::*;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+lazy_static::lazy_static! {
+    static ref TEMP_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+#[cfg(unix)]
+const TEMP_VARS: &[&str] = &["TMPDIR"];
+#[cfg(not(unix))]
+const TEMP_VARS: &[&str] = &["TMP", "TEMP"];
+
+/// Removes the temporary directory when dropped, see install
+pub struct Cleanup;
+
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        remove_temp_dir();
+    }
+}
+
+/// Create the private temporary directory and handle the signals.
+/// Call it before starting any threads, since it changes the environment
+pub fn install() -> Result<Cleanup> {
+    match tempfile::Builder::new().prefix("rga-").tempdir() {
+        Ok(dir) => {
+            let dir = dir.into_path();
+            debug!("temporary files are created in {}", dir.display());
+            for var in TEMP_VARS {
+                std::env::set_var(var, &dir);
+            }
+            *TEMP_DIR.lock().unwrap() = Some(dir);
+        }
+        // e.g. if the directory of the parent rga process was removed, the temporary files are created where they are
+        Err(e) => warn!("could not create a temporary directory: {}", e),
+    }
+    #[cfg(unix)]
+    handle_signals()?;
+    Ok(Cleanup)
+}
+
+fn remove_temp_dir() {
+    if let Some(dir) = TEMP_DIR.lock().unwrap().take() {
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            debug!("could not remove {}: {}", dir.display(), e);
+        }
+    }
+}
+
+/// Like std::process::exit, but removes the temporary files first (exit does not run destructors)
+pub fn exit(code: i32) -> ! {
+    remove_temp_dir();
+    std::process::exit(code)
+}
+
+/// Kill the programs of the adapters, remove the temporary files and exit
+#[cfg(unix)]
+pub fn kill_and_exit(code: i32) -> ! {
+    kill_subprocesses();
+    exit(code)
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use crate::adapters::spawning::{signal_subprocesses, subprocesses_running};
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::time::{Duration, Instant};
+
+    const SIGNALS: &[libc::c_int] = &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+    /// how long the programs get to exit after the signal was forwarded, before they are killed
+    const GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+    /// the write end of the pipe the signal handler writes the signal to
+    static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+    extern "C" fn on_signal(signal: libc::c_int) {
+        // only async-signal-safe calls here, the cleanup happens on the thread reading the pipe
+        let byte = signal as u8;
+        unsafe {
+            libc::write(
+                SIGNAL_PIPE.load(Ordering::Relaxed),
+                &byte as *const u8 as *const libc::c_void,
+                1,
+            );
+        }
+    }
+
+    fn next_signal(fd: libc::c_int) -> Option<libc::c_int> {
+        let mut byte = 0u8;
+        loop {
+            match unsafe { libc::read(fd, &mut byte as *mut u8 as *mut libc::c_void, 1) } {
+                1 => return Some(byte as libc::c_int),
+                n if n < 0
+                    && std::io::Error::last_os_error().kind()
+                        == std::io::ErrorKind::Interrupted => {}
+                _ => return None,
+            }
+        }
+    }
+
+    fn stop(signal: libc::c_int) {
+        debug!("received signal {}, stopping the adapters", signal);
+        signal_subprocesses(signal);
+        let start = Instant::now();
+        while subprocesses_running() && start.elapsed() < GRACE_PERIOD {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        kill_subprocesses();
+        remove_temp_dir();
+        // die from the signal, so the parent (e.g. the shell) sees how the process ended
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    pub(super) fn handle_signals() -> Result<()> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        // the programs the adapters run don't need the pipe
+        for fd in &fds {
+            unsafe { libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+        SIGNAL_PIPE.store(fds[1], Ordering::Relaxed);
+        let read_fd = fds[0];
+        std::thread::spawn(move || {
+            if let Some(signal) = next_signal(read_fd) {
+                stop(signal);
+            }
+        });
+        for signal in SIGNALS {
+            let previous = unsafe { libc::signal(*signal, on_signal as libc::sighandler_t) };
+            // e.g. SIGINT of programs started in the background by a non-interactive shell
+            if previous == libc::SIG_IGN {
+                unsafe { libc::signal(*signal, libc::SIG_IGN) };
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+use unix::handle_signals;
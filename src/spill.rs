@@ -0,0 +1,107 @@
+use log::*;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom, Write};
+
+/**
+ * A buffer that is kept in memory until it grows larger than the limit (--rga-memory-limit),
+ * then it is moved to an anonymous temporary file.
+ */
+pub struct SpillBuffer {
+    limit: usize,
+    mem: Vec<u8>,
+    file: Option<File>,
+    len: u64,
+    last_byte: Option<u8>,
+}
+
+impl SpillBuffer {
+    pub fn new(limit: usize) -> SpillBuffer {
+        SpillBuffer {
+            limit,
+            mem: Vec::new(),
+            file: None,
+            len: 0,
+            last_byte: None,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn last_byte(&self) -> Option<u8> {
+        self.last_byte
+    }
+
+    pub fn is_spilled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// read the buffer contents from the start
+    pub fn into_reader(self) -> std::io::Result<Box<dyn Read + Send>> {
+        match self.file {
+            Some(mut file) => {
+                file.seek(SeekFrom::Start(0))?;
+                Ok(Box::new(BufReader::new(file)))
+            }
+            None => Ok(Box::new(Cursor::new(self.mem))),
+        }
+    }
+}
+
+impl Write for SpillBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.file.is_none() && self.mem.len() + buf.len() > self.limit {
+            debug!(
+                "buffer exceeds the memory limit of {} bytes, moving it to a temporary file",
+                self.limit
+            );
+            let mut file = tempfile::tempfile()?;
+            file.write_all(&self.mem)?;
+            self.mem = Vec::new();
+            self.file = Some(file);
+        }
+        match &mut self.file {
+            Some(file) => file.write_all(buf)?,
+            None => self.mem.extend_from_slice(buf),
+        }
+        self.len += buf.len() as u64;
+        self.last_byte = buf.last().copied();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spills_over_limit() -> anyhow::Result<()> {
+        for (limit, spilled) in &[(100, false), (10, true)] {
+            let mut buf = SpillBuffer::new(*limit);
+            buf.write_all(b"hello ")?;
+            buf.write_all(b"world\n")?;
+            assert_eq!(buf.is_spilled(), *spilled);
+            assert_eq!(buf.len(), 12);
+            assert_eq!(buf.last_byte(), Some(b'\n'));
+            let mut out = String::new();
+            buf.into_reader()?.read_to_string(&mut out)?;
+            assert_eq!(out, "hello world\n");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+//! `rga --rga-remote=user@host:/path pattern`: search files on another host over ssh.
+//! The adapters run on the remote host, only rg's output (the matches) is sent back.
+//!
+//! If rga is installed on the remote host, it is used (with its own configuration). Otherwise this rga-preproc
+//! is uploaded to ~/.cache/rga-remote/<version>/ on the remote host and run by the remote rg, with the local configuration.
+//! That only works if the remote host has the same OS and architecture (and a compatible libc, a static musl build
+//! of rga-preproc avoids that), and ripgrep and the programs the adapters need (pandoc, pdftotext, ...) installed.
+//!
+//! The remote command is run with sh, ssh is configured as usual (~/.ssh/config, ssh-agent). The configuration is
+//! sent on stdin (not on the command line, where passwords would be visible in the process list of the remote host),
+//! without the settings of the local cache.
+
+use crate::adapters::spawning::map_exe_error;
+use crate::batch::{pre_globs, RG_ARGS};
+use crate::config::RgaConfig;
+use anyhow::*;
+use log::*;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// exit code of the remote command when rga-preproc has to be uploaded
+const NEEDS_UPLOAD: i32 = 199;
+
+/// ssh exits with this code when the connection failed
+const SSH_FAILED: i32 = 255;
+
+/// quote an argument for sh if needed
+pub fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Target {
+    /// user@host, or a host from ~/.ssh/config
+    pub host: String,
+    pub path: String,
+}
+
+pub fn parse_target(target: &str) -> Result<Target> {
+    let (host, path) = target
+        .split_once(':')
+        .filter(|(host, _)| !host.is_empty() && !host.starts_with('-'))
+        .ok_or_else(|| format_err!("--rga-remote must be user@host:/path, got {:?}", target))?;
+    Ok(Target {
+        host: host.to_string(),
+        // like scp, an empty path is the home directory
+        path: if path.is_empty() { "." } else { path }.to_string(),
+    })
+}
+
+/// where rga-preproc is uploaded to, one directory per version so it's uploaded again after updating rga
+fn remote_preproc() -> String {
+    format!(
+        "\"$HOME/.cache/rga-remote/{}/rga-preproc\"",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// the settings of the local cache that the remote host must not get: the cache path of this host does not exist there,
+/// and the keys of the cache are not needed to search
+const LOCAL_CACHE_SETTINGS: &[&str] = &["path", "remote", "remote_token", "encryption_key"];
+
+/// the sh script searching on the remote host. rga_args are given to an installed rga, rg_args (without --pre) to rg.
+/// The configuration for rga-preproc is the first line of stdin
+fn search_script(host: &str, rga_args: &[String], rg_args: &[String]) -> String {
+    let quote_all = |args: &[String]| {
+        args.iter()
+            .map(|a| shell_quote(a))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    format!(
+        "if command -v rga >/dev/null 2>&1; then exec rga {rga_args}; fi
+pre={pre}
+[ -x \"$pre\" ] || exit {needs_upload}
+command -v rg >/dev/null 2>&1 || {{ echo {no_rg} >&2; exit 2; }}
+IFS= read -r RGA_CONFIG
+export RGA_CONFIG
+exec rg --pre \"$pre\" {rg_args}",
+        rga_args = quote_all(rga_args),
+        pre = remote_preproc(),
+        needs_upload = NEEDS_UPLOAD,
+        no_rg = shell_quote(&format!("rga: ripgrep (rg) is not installed on {}", host)),
+        rg_args = quote_all(rg_args),
+    )
+}
+
+/// ssh runs the command with the login shell of the user, which might not be sh
+fn ssh(host: &str, script: &str) -> Command {
+    let mut cmd = Command::new("ssh");
+    cmd.arg("-T")
+        .arg("--")
+        .arg(host)
+        .arg(format!("sh -c {}", shell_quote(script)));
+    cmd
+}
+
+fn run(mut cmd: Command) -> Result<i32> {
+    debug!("running {:?}", cmd);
+    let status = cmd
+        .status()
+        .map_err(|e| map_exe_error(e, "ssh", "Please make sure you have ssh installed."))?;
+    // killed by a signal
+    Ok(status.code().unwrap_or(1))
+}
+
+/// whether the output of `uname -sm` on the remote host is the platform rga was built for
+fn same_platform(uname: &str) -> bool {
+    let normalize = |s: &str| match s.to_ascii_lowercase().as_str() {
+        "darwin" => "macos".to_string(),
+        "amd64" => "x86_64".to_string(),
+        "arm64" => "aarch64".to_string(),
+        other => other.to_string(),
+    };
+    let remote: Vec<String> = uname.split_whitespace().map(normalize).collect();
+    remote == [std::env::consts::OS, std::env::consts::ARCH]
+}
+
+/// copy the rga-preproc next to this executable to the remote host
+fn upload_preproc(host: &str) -> Result<()> {
+    let output = ssh(host, "uname -sm")
+        .stderr(Stdio::inherit())
+        .output()
+        .context("could not run ssh")?;
+    let uname = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() || !same_platform(&uname) {
+        bail!(
+            "rga is not installed on {} and rga-preproc for {} {} can't run there ({}), please install rga on {}",
+            host,
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+            uname.trim(),
+            host
+        );
+    }
+    let preproc_exe = std::env::current_exe()?.with_file_name("rga-preproc");
+    let preproc = File::open(&preproc_exe)
+        .with_context(|| format!("could not read {}", preproc_exe.display()))?;
+    eprintln!("rga: uploading rga-preproc to {}", host);
+    let mut cmd = ssh(
+        host,
+        &format!(
+            "pre={pre}; mkdir -p \"$(dirname \"$pre\")\" && cat > \"$pre.tmp\" && chmod +x \"$pre.tmp\" && mv \"$pre.tmp\" \"$pre\"",
+            pre = remote_preproc()
+        ),
+    );
+    cmd.stdin(preproc);
+    if run(cmd)? != 0 {
+        bail!("could not upload rga-preproc to {}", host);
+    }
+    Ok(())
+}
+
+/// search the remote path with the rg args, the output of rg is written to stdout. Returns the exit code of rg
+pub fn search_remote(config: &RgaConfig, target: &str, args: &[OsString]) -> Result<i32> {
+    let target = parse_target(target)?;
+    let args: Vec<String> = args
+        .iter()
+        .map(|a| a.to_string_lossy().into_owned())
+        .chain(std::iter::once(target.path.clone()))
+        .collect();
+    let rg_args: Vec<String> = RG_ARGS
+        .iter()
+        .map(|a| a.to_string())
+        .chain(
+            pre_globs(config)?
+                .into_iter()
+                .flat_map(|g| vec!["--pre-glob".to_string(), g]),
+        )
+        .chain(args.iter().cloned())
+        .collect();
+    let mut config_json = serde_json::to_value(config)?;
+    if let Some(cache) = config_json.get_mut("cache").and_then(|c| c.as_object_mut()) {
+        for key in LOCAL_CACHE_SETTINGS {
+            cache.remove(*key);
+        }
+    }
+    // on one line, serde_json escapes the line breaks in strings
+    let config_line = format!("{}\n", config_json);
+    let script = search_script(&target.host, &args, &rg_args);
+
+    let search = || -> Result<i32> {
+        let mut cmd = ssh(&target.host, &script);
+        cmd.stdin(Stdio::piped());
+        debug!("running {:?}", cmd);
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| map_exe_error(e, "ssh", "Please make sure you have ssh installed."))?;
+        let mut stdin = child.stdin.take().expect("is piped");
+        match stdin.write_all(config_line.as_bytes()) {
+            // an installed rga does not read it
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+            res => res?,
+        }
+        drop(stdin);
+        // killed by a signal
+        Ok(child.wait()?.code().unwrap_or(1))
+    };
+    let mut code = search()?;
+    if code == NEEDS_UPLOAD {
+        upload_preproc(&target.host)?;
+        code = search()?;
+    }
+    match code {
+        SSH_FAILED => bail!("could not connect to {}", target.host),
+        NEEDS_UPLOAD => bail!("could not run rga-preproc on {}", target.host),
+        code => Ok(code),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn targets() -> Result<()> {
+        assert_eq!(
+            parse_target("me@server:/data/docs")?,
+            Target {
+                host: "me@server".to_string(),
+                path: "/data/docs".to_string()
+            }
+        );
+        assert_eq!(parse_target("server:")?.path, ".");
+        assert!(parse_target("/data/docs").is_err());
+        assert!(parse_target("-oProxyCommand=x:/").is_err());
+
+        assert_eq!(shell_quote("--pre-glob"), "--pre-glob");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        let script = search_script("server", &["a b".to_string()], &[]);
+        assert!(script.contains("exec rga 'a b'"));
+        assert!(script.contains("read -r RGA_CONFIG"));
+
+        assert!(same_platform(&format!(
+            "{} {}\n",
+            match std::env::consts::OS {
+                "macos" => "Darwin",
+                os => os,
+            },
+            std::env::consts::ARCH
+        )));
+        assert!(!same_platform("Plan9 mips"));
+        Ok(())
+    }
+}
@@ -26,7 +26,10 @@ pub fn simple_adapt_info<'a>(filepath: &Path, inp: ReadBox<'a>) -> (AdaptInfo<'a
             postprocess: true,
         },
         FastFileMatcher::FileExtension(
-            filepath.extension().unwrap().to_string_lossy().into_owned(),
+            filepath
+                .extension()
+                .map(|e| e.to_string_lossy().into_owned())
+                .unwrap_or_default(),
         )
         .into(),
     )
@@ -0,0 +1,75 @@
+//! Markers for output that is incomplete because of a limit (--rga-max-filesize, --rga-max-archive-recursion,
+//! --rga-max-expanded-size, the adapter timeout and resource limits, the sqlite blob size), e.g.
+//! "[rga: truncated at 100 MB (--rga-max-filesize), rerun with --rga-no-limits]".
+//!
+//! The marker is on its own line (after the line prefix of the file), so searching for "rga: truncated at" lists the
+//! files with partial results, and parse reads it back. --rga-no-limits disables all the limits, to search one file completely.
+
+use std::fmt;
+
+pub const NO_LIMITS_FLAG: &str = "--rga-no-limits";
+
+const START: &str = "[rga: truncated at ";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Truncation {
+    /// the limit that was reached, e.g. "100 MB" or "archive depth 5"
+    pub at: String,
+    /// the option that sets the limit, e.g. "--rga-max-filesize"
+    pub flag: String,
+}
+
+impl Truncation {
+    pub fn new(at: impl Into<String>, flag: &str) -> Truncation {
+        Truncation {
+            at: at.into(),
+            flag: flag.to_string(),
+        }
+    }
+
+    /// the marker as a line of the output
+    pub fn marker(&self, line_prefix: &str) -> String {
+        format!("{}{}\n", line_prefix, self)
+    }
+
+    /// the truncation in a line of the output (with or without the line prefix)
+    pub fn parse(line: &str) -> Option<Truncation> {
+        let start = line.find(START)?;
+        let rest = line[start + START.len()..].trim_end();
+        let rest = rest.strip_suffix(&format!(", rerun with {}]", NO_LIMITS_FLAG))?;
+        let (at, flag) = rest.rsplit_once(" (")?;
+        Some(Truncation::new(at, flag.strip_suffix(')')?))
+    }
+}
+
+impl fmt::Display for Truncation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{} ({}), rerun with {}]",
+            START, self.at, self.flag, NO_LIMITS_FLAG
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let truncation = Truncation::new("100 MB", "--rga-max-filesize");
+        let marker = truncation.marker("docs.zip: big.pdf: ");
+        assert_eq!(
+            marker,
+            "docs.zip: big.pdf: [rga: truncated at 100 MB (--rga-max-filesize), rerun with --rga-no-limits]\n"
+        );
+        assert_eq!(Truncation::parse(&marker), Some(truncation));
+        assert_eq!(
+            Truncation::parse("[rga: truncated at archive depth 5 (--rga-max-archive-recursion), rerun with --rga-no-limits]"),
+            Some(Truncation::new("archive depth 5", "--rga-max-archive-recursion"))
+        );
+        assert_eq!(Truncation::parse("[rga: truncated at 5 MB]"), None);
+        assert_eq!(Truncation::parse("page 3: some text"), None);
+    }
+}
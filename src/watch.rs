@@ -0,0 +1,92 @@
+use crate::batch::{adapted_file_filter, batch_files, preprocess_batch, WalkOptions};
+use crate::config::RgaConfig;
+use anyhow::*;
+use log::*;
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+/// wait this long after the last change of a file before processing it, since documents are often saved in several steps
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// hidden files and directories (e.g. .git or a project cache) are skipped, like when walking the directory
+fn is_hidden(path: &Path) -> bool {
+    path.components().any(|c| match c {
+        Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+        _ => false,
+    })
+}
+
+/// run the adapters on the files, which writes their outputs to the cache
+fn warm(files: Vec<PathBuf>, config: &RgaConfig) -> Result<()> {
+    preprocess_batch(files, config, &mut std::io::sink())
+}
+
+/**
+ * Fill the cache for all files below the roots, then run the adapters again whenever a file is created or changed,
+ * so searches are always served from the cache. Runs until interrupted.
+ */
+pub fn watch(roots: &[PathBuf], config: &RgaConfig) -> Result<()> {
+    if config.cache.disabled {
+        bail!("--rga-watch can't be used with --rga-no-cache");
+    }
+    if config.cache.readonly {
+        bail!("--rga-watch can't be used with --rga-cache-readonly");
+    }
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut watcher = notify::watcher(sender, DEBOUNCE)?;
+    // watch first, so files changed while the cache is filled are not missed
+    for root in roots {
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .with_context(|| format!("could not watch {}", root.display()))?;
+    }
+    let files = batch_files(roots, config, &WalkOptions::default())?;
+    eprintln!("rga: filling the cache for {} files", files.len());
+    warm(files, config)?;
+    eprintln!("rga: watching for changes");
+
+    let handled = adapted_file_filter(config)?;
+    // event paths are absolute. Only what's below the root counts for skipping hidden files
+    let cwd = std::env::current_dir()?;
+    let absolute_roots: Vec<PathBuf> = roots
+        .iter()
+        .flat_map(|r| vec![Some(cwd.join(r)), r.canonicalize().ok()])
+        .flatten()
+        .collect();
+    for event in receiver.iter() {
+        let path = match event {
+            DebouncedEvent::Create(path)
+            | DebouncedEvent::Write(path)
+            | DebouncedEvent::Rename(_, path) => path,
+            DebouncedEvent::Error(e, path) => {
+                warn!("watch error for {:?}: {}", path, e);
+                continue;
+            }
+            _ => continue,
+        };
+        let relative = absolute_roots
+            .iter()
+            .find_map(|r| path.strip_prefix(r).ok())
+            .unwrap_or(&path);
+        if !path.is_file() || is_hidden(relative) || !handled(&path) {
+            continue;
+        }
+        debug!("{} changed", path.display());
+        warm(vec![path], config)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hidden() {
+        assert!(is_hidden(Path::new("docs/.git/index")));
+        assert!(is_hidden(Path::new("/home/user/.rga-cache/data.mdb")));
+        assert!(!is_hidden(Path::new("./docs/report.pdf")));
+        assert!(!is_hidden(Path::new("../docs/report.pdf")));
+    }
+}